@@ -1,17 +1,39 @@
 extern crate getopts;
 extern crate riscv_emu;
 
+use riscv_emu::block::file::FileDisk;
+use riscv_emu::block::overlay::CowOverlay;
+use riscv_emu::block::{open_image, BlockBackend};
 use riscv_emu::bus::bus::Device;
+use riscv_emu::cache::CacheHierarchyConfig;
 use riscv_emu::console::TtyDummy;
 use riscv_emu::emulator::Emulator;
-use riscv_emu::machine::Machine;
+use riscv_emu::machine::{ExitStatus, Machine};
+use riscv_emu::net::hub::UdpHub;
+use riscv_emu::net::pcap::PcapLoopback;
+use riscv_emu::net::slirp::Slirp;
+use riscv_emu::net::NetBackend;
+use riscv_emu::peripherals::fe310_g002::gpio::GPIO_PINS;
+use riscv_emu::peripherals::fe310_g002::otp::OTP_SIZE;
+use riscv_emu::peripherals::goldfish_rtc::VirtualClock;
+use riscv_emu::peripherals::virtio::balloon::BALLOON_PAGE_SIZE;
+use riscv_emu::peripherals::virtio::mmio::{VIRTIO_MMIO_LEGACY, VIRTIO_MMIO_MODERN};
+use riscv_emu::peripherals::virtio::p9::Virtio9p;
+use riscv_emu::profiler::ProfilerMode;
+use riscv_emu::timing::{PipelineConfig, PipelineModel, TimingModel};
 
 use riscv_emu_desktop::tty::Tty;
 
 use getopts::Options;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::{env, process};
 
+/// Core cycles per second of the virtual clock.
+const VIRTUAL_CLOCK_FREQUENCY: u64 = 100_000_000;
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
@@ -21,7 +43,7 @@ fn main() {
     opts.optopt(
         "f",
         "filesystem",
-        "File system image file",
+        "File system image file (raw or qcow2), writes are kept in memory unless rw (FILE[,ro][,rw])",
         "./artifacts/xv6/fs.img",
     );
     opts.optopt(
@@ -37,6 +59,134 @@ fn main() {
         "SiFive_e",
     );
     opts.optflag("t", "testmode", "Testmode is enabled");
+    opts.optopt(
+        "p",
+        "profile",
+        "Write guest profile as folded stacks for flamegraph",
+        "./profile.folded",
+    );
+    opts.optopt(
+        "",
+        "profile-period",
+        "Sample call stacks every N > 0 instructions (exact PC count if omitted)",
+        "10000",
+    );
+    opts.optflag("s", "stats", "Print execution statistics at exit");
+    opts.optflag(
+        "c",
+        "cache",
+        "Simulate FU540-like L1I/L1D/L2 caches and print statistics at exit",
+    );
+    opts.optopt(
+        "",
+        "timing",
+        "Cycle timing model (e31|u54), one cycle per instruction if omitted",
+        "u54",
+    );
+    opts.optopt(
+        "n",
+        "net",
+        "Add a virtio network interface to Qemu_virt or connect the GEMGXL of SiFive_u (user|pcap:FILE|hub:LOCAL,PEER[,PEER])",
+        "user",
+    );
+    opts.optopt(
+        "",
+        "virtio-version",
+        "Virtio-mmio interface of Qemu_virt, legacy (1) if omitted (1|2)",
+        "2",
+    );
+    opts.optflag(
+        "",
+        "virtio-console",
+        "Add a virtio console sharing the terminal with the UART to Qemu_virt",
+    );
+    opts.optflagopt(
+        "",
+        "virtio-rng",
+        "Add a virtio entropy device to Qemu_virt, deterministic with a seed",
+        "SEED",
+    );
+    opts.optopt(
+        "",
+        "virtual-clock",
+        "Run the real-time clock of Qemu_virt from the executed cycles, starting at SECONDS since the UNIX epoch",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "virtio-balloon",
+        "Add a virtio memory balloon to Qemu_virt and ask the guest to give up MIB",
+        "MIB",
+    );
+    opts.optflag(
+        "",
+        "virtio-keyboard",
+        "Add a virtio keyboard to Qemu_virt typing the terminal input",
+    );
+    opts.optmulti(
+        "",
+        "share",
+        "Export a host directory to Qemu_virt over virtio-9p, mount tag hostshare if omitted (DIR[,tag=TAG][,ro])",
+        "DIR",
+    );
+    opts.optmulti(
+        "",
+        "disk",
+        "Add another disk to Qemu_virt, virtio-mmio by default, virtio-pci with pci or NVMe with nvme (FILE[,ro][,cow][,pci|,nvme])",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "sdcard",
+        "Add an SD card on the SPI bus of SiFive_e (SPI1) or SiFive_u (SPI2) (FILE[,ro][,cow])",
+        "./sdcard.img",
+    );
+    opts.optopt(
+        "",
+        "flash",
+        "Load a raw image into the SPI flash of SiFive_e|SiFive_u",
+        "./flash.img",
+    );
+    opts.optopt(
+        "",
+        "otp",
+        "Keep the OTP memory of SiFive_e in a file, created blank if missing",
+        "./otp.bin",
+    );
+    opts.optopt(
+        "",
+        "msel",
+        "Boot SiFive_e|SiFive_u through the mask ROM with the mode select pins set to MSEL (0-15), the kernel is then optional",
+        "MSEL",
+    );
+    opts.optopt(
+        "",
+        "gpio-script",
+        "Drive GPIO inputs of SiFive_e|SiFive_u from a file of \"CYCLE PIN 0|1\" lines",
+        "./gpio.txt",
+    );
+    opts.optopt(
+        "",
+        "gpio-log",
+        "Write the GPIO output changes as \"CYCLE PIN 0|1\" lines",
+        "./gpio.log",
+    );
+    opts.optflag(
+        "",
+        "snapshot",
+        "Keep all disk writes in memory, the image files are not modified",
+    );
+    opts.optflag(
+        "",
+        "no-reboot",
+        "Exit instead of resetting the machine when the guest reboots it",
+    );
+    opts.optopt(
+        "",
+        "screenshot",
+        "Save the framebuffer of Qemu_virt as a PNG image at exit",
+        "./screenshot.png",
+    );
     opts.optflag("h", "help", "Help message");
 
     if args.len() < 2 {
@@ -52,16 +202,153 @@ fn main() {
         print_usage(&program, &opts);
     }
 
-    let kernel_path = match matches.opt_str("k") {
-        Some(filepath) => filepath,
-        None => {
-            print_usage(&program, &opts);
-            process::exit(0);
-        }
+    let msel = match matches.opt_str("msel") {
+        Some(msel) => match msel.parse::<u32>() {
+            Ok(n) if n < 16 => Some(n),
+            _ => {
+                print_usage(&program, &opts);
+                process::exit(0);
+            }
+        },
+        None => None,
     };
+    let kernel_path = matches.opt_str("k");
+    if kernel_path.is_none() && msel.is_none() {
+        print_usage(&program, &opts);
+        process::exit(0);
+    }
+    let flash_path = matches.opt_str("flash");
     let fs_path = matches.opt_str("f");
     let dtb_path = matches.opt_str("d");
     let testmode = matches.opt_present("t");
+    let no_reboot = matches.opt_present("no-reboot");
+    let stats = matches.opt_present("s");
+    let cache = matches.opt_present("c");
+    let profile_path = matches.opt_str("p");
+    let profile_mode = match matches.opt_str("profile-period") {
+        Some(period) => match period.parse::<u64>() {
+            Ok(n) if n > 0 => ProfilerMode::Sampling(n),
+            _ => {
+                print_usage(&program, &opts);
+                process::exit(0);
+            }
+        },
+        None => ProfilerMode::Exact,
+    };
+    let timing: Option<Box<dyn TimingModel>> = match matches.opt_str("timing") {
+        Some(model) => match &*model {
+            "e31" => Some(Box::new(PipelineModel::new(PipelineConfig::e31()))),
+            "u54" => Some(Box::new(PipelineModel::new(PipelineConfig::u54()))),
+            _ => {
+                print_usage(&program, &opts);
+                process::exit(0);
+            }
+        },
+        None => None,
+    };
+    let net: Option<Box<dyn NetBackend>> = match matches.opt_str("n") {
+        Some(backend) => match create_net_backend(&backend) {
+            Ok(backend) => Some(backend),
+            Err(why) => panic!("Failed to set up network backend {}: {}", backend, why),
+        },
+        None => None,
+    };
+    let virtio_version = match matches.opt_str("virtio-version") {
+        Some(version) => match &*version {
+            "1" => Some(VIRTIO_MMIO_LEGACY),
+            "2" => Some(VIRTIO_MMIO_MODERN),
+            _ => {
+                print_usage(&program, &opts);
+                process::exit(0);
+            }
+        },
+        None => None,
+    };
+    let rng_seed = match matches.opt_str("virtio-rng") {
+        Some(seed) => match seed.parse::<u64>() {
+            Ok(seed) => Some(seed),
+            Err(_) => {
+                print_usage(&program, &opts);
+                process::exit(0);
+            }
+        },
+        None => None,
+    };
+    let virtual_clock = match matches.opt_str("virtual-clock") {
+        Some(seconds) => match seconds.parse::<u64>() {
+            Ok(seconds) => Some(seconds),
+            Err(_) => {
+                print_usage(&program, &opts);
+                process::exit(0);
+            }
+        },
+        None => None,
+    };
+    let balloon_pages = match matches.opt_str("virtio-balloon") {
+        Some(mib) => match mib.parse::<u64>() {
+            Ok(mib) => Some((mib * 1024 * 1024 / BALLOON_PAGE_SIZE) as u32),
+            Err(_) => {
+                print_usage(&program, &opts);
+                process::exit(0);
+            }
+        },
+        None => None,
+    };
+    let snapshot = matches.opt_present("snapshot");
+    let screenshot_path = matches.opt_str("screenshot");
+    let disk = fs_path.map(|spec| match open_disk(&spec, true, snapshot) {
+        Ok(disk) => disk,
+        Err(why) => panic!("Failed to open {}: {}", spec, why),
+    });
+    let mut disks = vec![];
+    for spec in matches.opt_strs("disk") {
+        // pci and nvme select the controller, the other options apply to the image.
+        let controller = spec
+            .split(',')
+            .skip(1)
+            .find(|word| *word == "pci" || *word == "nvme")
+            .unwrap_or("virtio")
+            .to_string();
+        let image: Vec<&str> = spec
+            .split(',')
+            .filter(|word| *word != "pci" && *word != "nvme")
+            .collect();
+        match open_disk(&image.join(","), false, snapshot) {
+            Ok(disk) => disks.push((disk, controller)),
+            Err(why) => panic!("Failed to open {}: {}", spec, why),
+        }
+    }
+    let sdcard = matches
+        .opt_str("sdcard")
+        .map(|spec| match open_disk(&spec, false, snapshot) {
+            Ok(disk) => disk,
+            Err(why) => panic!("Failed to open {}: {}", spec, why),
+        });
+    let otp = matches
+        .opt_str("otp")
+        .map(|filepath| match open_otp(&filepath, snapshot) {
+            Ok(otp) => otp,
+            Err(why) => panic!("Failed to open {}: {}", filepath, why),
+        });
+    let gpio_script = matches.opt_str("gpio-script").map(|filepath| {
+        match read_gpio_script(Path::new(&filepath)) {
+            Ok(script) => script,
+            Err(why) => panic!("Failed to read {}: {}", filepath, why),
+        }
+    });
+    let gpio_log = matches
+        .opt_str("gpio-log")
+        .map(|filepath| match File::create(&filepath) {
+            Ok(file) => file,
+            Err(why) => panic!("Falied to create {}: {}", filepath, why),
+        });
+    let mut shares = vec![];
+    for spec in matches.opt_strs("share") {
+        match create_shared_directory(&spec) {
+            Ok(share) => shares.push(share),
+            Err(why) => panic!("Failed to share {}: {}", spec, why),
+        }
+    }
     let machine = match matches.opt_str("m") {
         Some(machine_name) => match &*machine_name {
             "Qemu_virt" => Machine::QemuVirt,
@@ -79,6 +366,10 @@ fn main() {
         let tty = Box::new(Tty::new());
         emu = Emulator::new(machine, tty, testmode);
     }
+    // fail before running rather than losing the screenshot at exit.
+    if screenshot_path.is_some() && emu.get_framebuffer().is_none() {
+        panic!("The target machine has no framebuffer.");
+    }
 
     /*
     let data = vec![
@@ -89,19 +380,30 @@ fn main() {
     emu.run();
     */
 
+    // download the raw flash image before the program, which may be
+    // written into the flash.
+    if let Some(filepath) = flash_path {
+        let flash = PathBuf::from(filepath);
+        emu.set_data_from_file(Device::SpiFlash, flash.as_path());
+    }
+
     // download user program to main mermoy.
-    {
-        let kernel = PathBuf::from(kernel_path);
+    if let Some(filepath) = kernel_path {
+        let kernel = PathBuf::from(filepath);
         emu.load_program_from_file(kernel.as_path());
     }
 
-    // download disk image (Userland rootfs)
-    match fs_path {
-        Some(filepath) => {
-            let fs = PathBuf::from(filepath);
-            emu.set_data_from_file(Device::Disk, fs.as_path());
+    if let Some(msel) = msel {
+        if emu.set_mode_select(msel).is_err() {
+            panic!("The target machine has no boot ROM.");
+        }
+    }
+
+    // attach disk image (Userland rootfs)
+    if let Some(disk) = disk {
+        if emu.set_disk(disk).is_err() {
+            panic!("The target machine has no virtio disk.");
         }
-        None => {}
     }
 
     // download dtb image
@@ -113,12 +415,282 @@ fn main() {
         None => {}
     }
 
+    if let Some(version) = virtio_version {
+        if emu.set_virtio_version(version).is_err() {
+            panic!("The target machine has no virtio.");
+        }
+    }
+
+    if matches.opt_present("virtio-console") && emu.attach_virtio_console(vec![]).is_err() {
+        panic!("The target machine has no free virtio slot for the console.");
+    }
+
+    if matches.opt_present("virtio-rng") && emu.attach_virtio_rng(rng_seed).is_err() {
+        panic!("Failed to add the entropy device.");
+    }
+
+    if let Some(pages) = balloon_pages {
+        if emu.attach_virtio_balloon().is_err() {
+            panic!("The target machine has no free virtio slot for the balloon.");
+        }
+        emu.set_balloon_target(pages);
+    }
+
+    if matches.opt_present("virtio-keyboard") && emu.attach_virtio_keyboard(true).is_err() {
+        panic!("The target machine has no free virtio slot for the keyboard.");
+    }
+
+    for (disk, controller) in disks {
+        let result = match controller.as_str() {
+            "pci" => emu.attach_pci_disk(disk),
+            "nvme" => emu.attach_nvme(disk),
+            _ => emu.attach_disk(disk),
+        };
+        if result.is_err() {
+            match controller.as_str() {
+                "virtio" => panic!("The target machine has no free virtio slot for the disk."),
+                _ => panic!("The target machine has no free PCI slot for the disk."),
+            }
+        }
+    }
+
+    if let Some(disk) = sdcard {
+        if emu.attach_sd_card(disk).is_err() {
+            panic!("The target machine has no SPI bus for the SD card.");
+        }
+    }
+
+    if let Some(otp) = otp {
+        if emu.set_otp(otp).is_err() {
+            panic!("The target machine has no OTP memory.");
+        }
+    }
+
+    if let Some(seconds) = virtual_clock {
+        let clock = VirtualClock::new(seconds * 1_000_000_000, VIRTUAL_CLOCK_FREQUENCY);
+        if emu.set_time_source(Box::new(clock)).is_err() {
+            panic!("The target machine has no real-time clock.");
+        }
+    }
+
+    if gpio_script.is_some() || gpio_log.is_some() {
+        let gpio = match emu.get_gpio() {
+            Some(gpio) => gpio,
+            None => panic!("The target machine has no GPIO."),
+        };
+        for (cycle, pin, level) in gpio_script.unwrap_or_default() {
+            if gpio.schedule_input(cycle, pin, level).is_err() {
+                panic!("The target machine has no GPIO pin {}.", pin);
+            }
+        }
+        if let Some(file) = gpio_log {
+            gpio.set_event_log(Box::new(file));
+        }
+    }
+
+    for share in shares {
+        if emu.attach_virtio_device(Box::new(share)).is_err() {
+            panic!("The target machine has no free virtio slot for the shared directory.");
+        }
+    }
+
+    if let Some(backend) = net {
+        if emu.attach_network(backend).is_err() {
+            panic!("The target machine has no free slot for the network interface.");
+        }
+    }
+
+    if profile_path.is_some() {
+        emu.enable_profiler(profile_mode);
+    }
+    if stats {
+        emu.enable_stats();
+    }
+    if cache {
+        emu.enable_cache(CacheHierarchyConfig::fu540());
+    }
+    emu.set_timing_model(timing);
+
     // run emulator.
-    let result = match emu.run() {
-        Ok(ret) => ret,
-        Err(ret) => ret,
+    let exit_code = match testmode {
+        true => {
+            let (result, code) = match emu.run() {
+                Ok(ret) => (ret, 0),
+                Err(ret) => (ret, 1),
+            };
+            println!("Result: {}", result);
+            code
+        }
+        false => loop {
+            let status = emu.run_until_exit();
+            if status == ExitStatus::Reboot && !no_reboot {
+                continue;
+            }
+            println!("Exit: {:?}", status);
+            break match status {
+                ExitStatus::PowerOff(code) => code as i32,
+                ExitStatus::Reboot | ExitStatus::Halt => 0,
+                ExitStatus::Fatal(_) => 1,
+            };
+        },
+    };
+
+    if let Some(filepath) = screenshot_path {
+        let png = match emu.screenshot() {
+            Ok(png) => png,
+            Err(()) => panic!("The target machine has no framebuffer."),
+        };
+        if let Err(why) = File::create(&filepath).and_then(|mut file| file.write_all(&png)) {
+            panic!("Failed to write {}: {}", filepath, why);
+        }
+    }
+
+    if stats {
+        if let Err(why) = emu.write_stats_report(&mut io::stdout()) {
+            panic!("Failed to print statistics: {}", why);
+        }
+    }
+
+    if let Some(stats) = emu.get_balloon_stats() {
+        println!("Balloon: {:?}", stats);
+    }
+
+    if cache {
+        if let Err(why) = emu.write_cache_report(10, &mut io::stdout()) {
+            panic!("Failed to print cache statistics: {}", why);
+        }
+    }
+
+    // dump guest profile.
+    if let Some(filepath) = profile_path {
+        let mut file = match File::create(&filepath) {
+            Ok(file) => file,
+            Err(why) => panic!("Falied to create {}: {}", filepath, why),
+        };
+        if let Err(why) = emu.write_profile_folded(&mut file) {
+            panic!("Failed to write {}: {}", filepath, why);
+        }
+        if let Err(why) = emu.write_profile_hot_list(20, &mut io::stdout()) {
+            panic!("Failed to print profile: {}", why);
+        }
+    }
+
+    process::exit(exit_code);
+}
+
+fn create_net_backend(spec: &str) -> io::Result<Box<dyn NetBackend>> {
+    let mut words = spec.splitn(2, ':');
+    match (words.next(), words.next()) {
+        (Some("user"), None) => Ok(Box::new(Slirp::new())),
+        (Some("pcap"), Some(filepath)) => Ok(Box::new(PcapLoopback::new(File::create(filepath)?)?)),
+        (Some("hub"), Some(addrs)) => {
+            let mut addrs = addrs.split(',');
+            let mut hub = UdpHub::new(addrs.next().unwrap_or_default())?;
+            for peer in addrs {
+                hub.add_peer(peer)?;
+            }
+            Ok(Box::new(hub))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unknown backend",
+        )),
+    }
+}
+
+/// Open a disk image. With cow (the default if cow is set) or the snapshot
+/// option the writes are kept in memory, with rw they reach the image.
+fn open_disk(spec: &str, cow: bool, snapshot: bool) -> io::Result<Box<dyn BlockBackend>> {
+    let mut words = spec.split(',');
+    let path = words.next().unwrap_or_default();
+    let mut read_only = false;
+    let mut cow = cow;
+    for word in words {
+        match word {
+            "ro" => read_only = true,
+            "cow" => cow = true,
+            "rw" => cow = false,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "unknown option",
+                ))
+            }
+        }
+    }
+    let cow = cow || snapshot;
+    let disk = open_image(Path::new(path), read_only || cow)?;
+    match cow && !read_only {
+        true => Ok(Box::new(CowOverlay::new(disk))),
+        false => Ok(disk),
+    }
+}
+
+/// Open the file of the OTP memory, a blank one is created if it is missing.
+/// With the snapshot option the programmed fuses are kept in memory.
+fn open_otp(path: &str, snapshot: bool) -> io::Result<Box<dyn BlockBackend>> {
+    if !Path::new(path).exists() {
+        File::create(path)?.set_len(OTP_SIZE as u64)?;
+    }
+    let otp = Box::new(FileDisk::open(Path::new(path), snapshot)?);
+    match snapshot {
+        true => Ok(Box::new(CowOverlay::new(otp))),
+        false => Ok(otp),
+    }
+}
+
+fn create_shared_directory(spec: &str) -> io::Result<Virtio9p> {
+    let mut words = spec.split(',');
+    let dir = words.next().unwrap_or_default();
+    let mut tag = "hostshare";
+    let mut read_only = false;
+    for word in words {
+        match word {
+            "ro" => read_only = true,
+            _ if word.starts_with("tag=") => tag = &word[4..],
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "unknown option",
+                ))
+            }
+        }
+    }
+    Virtio9p::new(Path::new(dir), tag, read_only)
+}
+
+/// Read the GPIO inputs to drive, a "CYCLE PIN LEVEL" line for each change.
+/// Empty lines and the lines starting with # are skipped.
+fn read_gpio_script(path: &Path) -> io::Result<Vec<(u64, usize, bool)>> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid line: {}", line),
+        )
     };
-    println!("Result: {}", result);
+    let mut script = vec![];
+    for line in std::fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() != 3 {
+            return Err(invalid(line));
+        }
+        let cycle = words[0].parse::<u64>().map_err(|_| invalid(line))?;
+        let pin = match words[1].parse::<usize>() {
+            Ok(pin) if pin < GPIO_PINS => pin,
+            _ => return Err(invalid(line)),
+        };
+        let level = match words[2] {
+            "0" => false,
+            "1" => true,
+            _ => return Err(invalid(line)),
+        };
+        script.push((cycle, pin, level));
+    }
+    Ok(script)
 }
 
 fn print_usage(program: &str, opts: &Options) {
@@ -0,0 +1,88 @@
+// Disk image file accessed in place: guest writes reach the host file.
+
+use crate::block::{out_of_range, BlockBackend};
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Size of the zeros written at a time by discard.
+const DISCARD_CHUNK_SIZE: usize = 0x10_0000;
+
+pub struct FileDisk {
+    file: File,
+    size: u64,
+    read_only: bool,
+}
+
+impl FileDisk {
+    /// Open the image, without write access if read_only is set.
+    pub fn open(path: &Path, read_only: bool) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(FileDisk {
+            file,
+            size,
+            read_only,
+        })
+    }
+
+    fn check_range(&self, offset: u64, len: usize) -> io::Result<()> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(out_of_range()),
+        }
+    }
+}
+
+impl BlockBackend for FileDisk {
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        self.check_range(offset, data.len())?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(data)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "read-only disk",
+            ));
+        }
+        self.check_range(offset, data.len())?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)
+    }
+
+    /// The range reads back as zeros.
+    fn discard(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size => {}
+            _ => return Err(out_of_range()),
+        }
+        let zeros = vec![0; DISCARD_CHUNK_SIZE.min(len as usize)];
+        let mut pos = 0;
+        while pos < len {
+            let n = (len - pos).min(zeros.len() as u64) as usize;
+            self.write_at(offset + pos, &zeros[..n])?;
+            pos += n as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.read_only {
+            true => Ok(()),
+            false => self.file.sync_data(),
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
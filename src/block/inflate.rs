@@ -0,0 +1,246 @@
+// Raw DEFLATE decoder for compressed clusters of disk images
+// https://www.rfc-editor.org/rfc/rfc1951
+
+use std::io;
+
+const MAX_BITS: usize = 15;
+
+// Base lengths and extra bits of the length codes 257..285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// Base distances and extra bits of the distance codes 0..29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order of the code length code lengths of a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid deflate data")
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Read bits, least significant bit first.
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.bit_count < count {
+            let byte = *self.data.get(self.pos).ok_or_else(invalid_data)?;
+            self.pos += 1;
+            self.bit_buf |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buf & ((1u64 << count) - 1) as u32;
+        self.bit_buf >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    /// Skip to the byte boundary.
+    fn align(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+}
+
+/// Canonical Huffman code: the number of codes of each length and the
+/// symbols ordered by code.
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut count = [0; MAX_BITS + 1];
+        lengths.iter().for_each(|&len| count[len as usize] += 1);
+        let mut offset = [0; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offset[len + 1] = offset[len] + count[len];
+        }
+        let mut symbol = vec![0; lengths.len()];
+        for (s, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbol[offset[len as usize] as usize] = s as u16;
+                offset[len as usize] += 1;
+            }
+        }
+        Huffman { count, symbol }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        // codes are read bit by bit from the most significant bit.
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - count < first {
+                return Ok(self.symbol[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(invalid_data())
+    }
+}
+
+/// Decompress a raw DEFLATE stream, up to max_len bytes. Data after the
+/// final block is ignored.
+pub fn inflate(input: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader::new(input);
+    let mut output = Vec::with_capacity(max_len);
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => stored(&mut reader, &mut output)?,
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[..144].iter_mut().for_each(|l| *l = 8);
+                lengths[144..256].iter_mut().for_each(|l| *l = 9);
+                lengths[256..280].iter_mut().for_each(|l| *l = 7);
+                lengths[280..].iter_mut().for_each(|l| *l = 8);
+                let literal = Huffman::new(&lengths);
+                let distance = Huffman::new(&[5; 30]);
+                codes(&mut reader, &mut output, &literal, &distance, max_len)?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_tables(&mut reader)?;
+                codes(&mut reader, &mut output, &literal, &distance, max_len)?;
+            }
+            _ => return Err(invalid_data()),
+        }
+        if last || output.len() >= max_len {
+            output.truncate(max_len);
+            return Ok(output);
+        }
+    }
+}
+
+fn stored(reader: &mut BitReader, output: &mut Vec<u8>) -> io::Result<()> {
+    reader.align();
+    let header = reader
+        .data
+        .get(reader.pos..reader.pos + 4)
+        .ok_or_else(invalid_data)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(invalid_data());
+    }
+    reader.pos += 4;
+    let data = reader
+        .data
+        .get(reader.pos..reader.pos + len as usize)
+        .ok_or_else(invalid_data)?;
+    output.extend_from_slice(data);
+    reader.pos += len as usize;
+    Ok(())
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(invalid_data());
+    }
+
+    let mut lengths = [0; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_lengths) {
+        lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length = Huffman::new(&lengths);
+
+    let mut lengths = vec![0; literals + distances];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => match index {
+                0 => return Err(invalid_data()),
+                _ => (lengths[index - 1], 3 + reader.bits(2)? as usize),
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(invalid_data());
+        }
+        lengths[index..index + repeat]
+            .iter_mut()
+            .for_each(|l| *l = value);
+        index += repeat;
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+fn codes(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literal: &Huffman,
+    distance: &Huffman,
+    max_len: usize,
+) -> io::Result<()> {
+    while output.len() < max_len {
+        let symbol = literal.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let len =
+                    LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = distance.decode(reader)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err(invalid_data());
+                }
+                let dist = DISTANCE_BASE[index] as usize
+                    + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                if dist > output.len() {
+                    return Err(invalid_data());
+                }
+                // the source may overlap the copied bytes.
+                let start = output.len() - dist;
+                for i in 0..len {
+                    output.push(output[start + i]);
+                }
+            }
+            _ => return Err(invalid_data()),
+        }
+    }
+    Ok(())
+}
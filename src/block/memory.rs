@@ -0,0 +1,51 @@
+// Disk image held in memory, lost when the emulator exits.
+
+use crate::block::{out_of_range, BlockBackend};
+
+use std::io;
+
+pub struct MemoryDisk {
+    data: Vec<u8>,
+}
+
+impl MemoryDisk {
+    pub fn new(data: Vec<u8>) -> Self {
+        MemoryDisk { data }
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn get_range(&self, offset: u64, len: usize) -> io::Result<(usize, usize)> {
+        let start = offset as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.data.len() => Ok((start, end)),
+            _ => Err(out_of_range()),
+        }
+    }
+}
+
+impl BlockBackend for MemoryDisk {
+    fn get_size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        let (start, end) = self.get_range(offset, data.len())?;
+        data.copy_from_slice(&self.data[start..end]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let (start, end) = self.get_range(offset, data.len())?;
+        self.data[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn discard(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        let (start, end) = self.get_range(offset, len as usize)?;
+        self.data[start..end].iter_mut().for_each(|b| *b = 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+// Block Backends
+// Host side storage of the emulated disks, addressed in bytes.
+
+pub mod file;
+pub mod inflate;
+pub mod memory;
+pub mod overlay;
+pub mod qcow2;
+
+use crate::block::file::FileDisk;
+use crate::block::qcow2::{Qcow2Disk, QCOW_MAGIC};
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+pub trait BlockBackend {
+    /// Size of the disk in bytes.
+    fn get_size(&self) -> u64;
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()>;
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+    /// Make the written data persistent.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    /// The guest no longer needs the data, which reads back as anything.
+    fn discard(&mut self, _offset: u64, _len: u64) -> io::Result<()> {
+        Ok(())
+    }
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Error of an access beyond the end of the disk.
+pub fn out_of_range() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "out of the disk")
+}
+
+/// Open a raw or a qcow2 disk image, detected by the contents.
+pub fn open_image(path: &Path, read_only: bool) -> io::Result<Box<dyn BlockBackend>> {
+    let mut magic = [0; 4];
+    let is_qcow2 = File::open(path)?.read_exact(&mut magic).is_ok() && magic == QCOW_MAGIC;
+    match is_qcow2 {
+        true => Ok(Box::new(Qcow2Disk::open(path, read_only)?)),
+        false => Ok(Box::new(FileDisk::open(path, read_only)?)),
+    }
+}
@@ -0,0 +1,92 @@
+// Copy-on-write overlay: the guest sees its writes but the base image is
+// never modified. The written clusters are kept in memory.
+
+use crate::block::{out_of_range, BlockBackend};
+
+use std::collections::HashMap;
+use std::io;
+
+const CLUSTER_SIZE: u64 = 4096;
+
+pub struct CowOverlay {
+    base: Box<dyn BlockBackend>,
+    /// written clusters by cluster index.
+    clusters: HashMap<u64, Vec<u8>>,
+}
+
+impl CowOverlay {
+    pub fn new(base: Box<dyn BlockBackend>) -> Self {
+        CowOverlay {
+            base,
+            clusters: HashMap::new(),
+        }
+    }
+
+    /// Number of bytes which differ from the base image (in clusters).
+    pub fn get_overlay_size(&self) -> u64 {
+        self.clusters.len() as u64 * CLUSTER_SIZE
+    }
+
+    /// Forget the writes: the disk is the base image again.
+    pub fn revert(&mut self) {
+        self.clusters.clear();
+    }
+
+    fn check_range(&self, offset: u64, len: usize) -> io::Result<()> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.base.get_size() => Ok(()),
+            _ => Err(out_of_range()),
+        }
+    }
+
+    /// Split the range into (cluster, offset in cluster, offset in data, length).
+    fn split(offset: u64, len: usize) -> Vec<(u64, usize, usize, usize)> {
+        let mut pieces = vec![];
+        let mut pos = 0;
+        while pos < len {
+            let addr = offset + pos as u64;
+            let in_cluster = (addr % CLUSTER_SIZE) as usize;
+            let n = (CLUSTER_SIZE as usize - in_cluster).min(len - pos);
+            pieces.push((addr / CLUSTER_SIZE, in_cluster, pos, n));
+            pos += n;
+        }
+        pieces
+    }
+}
+
+impl BlockBackend for CowOverlay {
+    fn get_size(&self) -> u64 {
+        self.base.get_size()
+    }
+
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        self.check_range(offset, data.len())?;
+        for (cluster, in_cluster, pos, n) in Self::split(offset, data.len()) {
+            match self.clusters.get(&cluster) {
+                Some(copy) => data[pos..pos + n].copy_from_slice(&copy[in_cluster..in_cluster + n]),
+                None => self
+                    .base
+                    .read_at(offset + pos as u64, &mut data[pos..pos + n])?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.check_range(offset, data.len())?;
+        for (cluster, in_cluster, pos, n) in Self::split(offset, data.len()) {
+            if !self.clusters.contains_key(&cluster) {
+                // the last cluster of the image may be partial.
+                let start = cluster * CLUSTER_SIZE;
+                let len = CLUSTER_SIZE.min(self.base.get_size() - start);
+                let mut copy = vec![0; len as usize];
+                self.base.read_at(start, &mut copy)?;
+                self.clusters.insert(cluster, copy);
+            }
+            if let Some(copy) = self.clusters.get_mut(&cluster) {
+                copy[in_cluster..in_cluster + n].copy_from_slice(&data[pos..pos + n]);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,454 @@
+// QEMU Copy-On-Write disk image (version 2 and 3)
+// https://github.com/qemu/qemu/blob/master/docs/interop/qcow2.txt
+//
+// Guest offsets are translated through a two level table (L1 and L2) to the
+// clusters of the image file. Unallocated clusters read from the backing
+// file or as zeros. Writes allocate clusters at the end of the file, and
+// compressed clusters are rewritten uncompressed.
+
+use crate::block::inflate::inflate;
+use crate::block::{open_image, out_of_range, BlockBackend};
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const QCOW_MAGIC: [u8; 4] = [b'Q', b'F', b'I', 0xfb];
+
+const HEADER_V2_SIZE: usize = 72;
+const HEADER_V3_SIZE: usize = 104;
+const DEFAULT_CLUSTER_BITS: u32 = 16;
+/// 16-bit refcounts.
+const REFCOUNT_ORDER: u32 = 4;
+
+// Incompatible features
+const INCOMPAT_DIRTY: u64 = 1 << 0;
+
+// L1 and L2 table entries
+const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const FLAG_COPIED: u64 = 1 << 63;
+const FLAG_COMPRESSED: u64 = 1 << 62;
+const FLAG_ZERO: u64 = 1 << 0;
+
+/// Location of the data of a guest cluster.
+enum Cluster {
+    Unallocated,
+    Zero,
+    /// offset in the image file, and whether no other entry refers to it
+    /// (refcount 1), so it is written in place.
+    Normal(u64, bool),
+    /// offset and size of the compressed data.
+    Compressed(u64, usize),
+}
+
+fn invalid_image(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_be_bytes(bytes)
+}
+
+fn get_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_be_bytes(bytes)
+}
+
+pub struct Qcow2Disk {
+    file: File,
+    read_only: bool,
+    version: u32,
+    /// virtual disk size in bytes.
+    size: u64,
+    cluster_bits: u32,
+    l1_table_offset: u64,
+    l1_table: Vec<u64>,
+    /// L2 tables by offset in the image file.
+    l2_tables: HashMap<u64, Vec<u64>>,
+    refcount_table_offset: u64,
+    refcount_table: Vec<u64>,
+    /// offset of the next cluster to allocate (the end of the file).
+    next_cluster: u64,
+    backing: Option<Box<dyn BlockBackend>>,
+    /// last decompressed cluster (offset in the image file, data).
+    decompressed: Option<(u64, Vec<u8>)>,
+}
+
+impl Qcow2Disk {
+    /// Open the image and its backing file (always read-only).
+    /// Images with internal snapshots can only be opened read-only.
+    pub fn open(path: &Path, read_only: bool) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+        let mut header = vec![0; HEADER_V3_SIZE];
+        let len = file.read(&mut header)?;
+        if len < HEADER_V2_SIZE || header[0..4] != QCOW_MAGIC {
+            return Err(invalid_image("not a qcow2 image"));
+        }
+
+        let version = get_u32(&header, 4);
+        let backing_file_offset = get_u64(&header, 8);
+        let backing_file_size = get_u32(&header, 16);
+        let cluster_bits = get_u32(&header, 20);
+        let size = get_u64(&header, 24);
+        let crypt_method = get_u32(&header, 32);
+        let l1_size = get_u32(&header, 36);
+        let l1_table_offset = get_u64(&header, 40);
+        let refcount_table_offset = get_u64(&header, 48);
+        let refcount_table_clusters = get_u32(&header, 56);
+        let nb_snapshots = get_u32(&header, 60);
+        let (incompatible_features, refcount_order) = match version {
+            2 => (0, REFCOUNT_ORDER),
+            3 if len == HEADER_V3_SIZE => (get_u64(&header, 72), get_u32(&header, 96)),
+            _ => return Err(invalid_image("unsupported qcow2 version")),
+        };
+        if !(9..=21).contains(&cluster_bits) {
+            return Err(invalid_image("invalid cluster size"));
+        }
+        // every guest cluster has an entry in the L1 table.
+        let l2_entries = 1 << (cluster_bits - 3);
+        if (l1_size as u64) < size.div_ceil(1 << cluster_bits).div_ceil(l2_entries) {
+            return Err(invalid_image("L1 table too small"));
+        }
+        if crypt_method != 0 {
+            return Err(invalid_image("encrypted qcow2 images are not supported"));
+        }
+        // a dirty image may leak clusters, which is harmless.
+        if incompatible_features & !INCOMPAT_DIRTY != 0 {
+            return Err(invalid_image("unsupported qcow2 features"));
+        }
+        if !read_only && (refcount_order != REFCOUNT_ORDER || nb_snapshots != 0) {
+            return Err(invalid_image(
+                "qcow2 images with snapshots or non 16-bit refcounts are read-only",
+            ));
+        }
+
+        let mut disk = Qcow2Disk {
+            file,
+            read_only,
+            version,
+            size,
+            cluster_bits,
+            l1_table_offset,
+            l1_table: vec![],
+            l2_tables: HashMap::new(),
+            refcount_table_offset,
+            refcount_table: vec![],
+            next_cluster: 0,
+            backing: None,
+            decompressed: None,
+        };
+        disk.l1_table = disk.read_table(l1_table_offset, l1_size as u64)?;
+        let refcount_entries = refcount_table_clusters as u64 * disk.get_cluster_size() / 8;
+        disk.refcount_table = disk.read_table(refcount_table_offset, refcount_entries)?;
+        let file_size = disk.file.metadata()?.len();
+        disk.next_cluster = disk.align_to_cluster(file_size);
+
+        if backing_file_offset != 0 {
+            let mut name = vec![0; backing_file_size as usize];
+            disk.read_file(backing_file_offset, &mut name)?;
+            let name =
+                String::from_utf8(name).map_err(|_| invalid_image("invalid backing file name"))?;
+            // a relative path is relative to the image.
+            let backing_path = match path.parent() {
+                Some(dir) => dir.join(&name),
+                None => Path::new(&name).to_path_buf(),
+            };
+            disk.backing = Some(open_image(&backing_path, true)?);
+        }
+        Ok(disk)
+    }
+
+    /// Create an empty image of the size, on top of a backing file if any.
+    pub fn create(path: &Path, size: u64, backing_file: Option<&str>) -> io::Result<Self> {
+        let cluster_size = 1u64 << DEFAULT_CLUSTER_BITS;
+        let l2_entries = cluster_size / 8;
+        let l1_size = size.div_ceil(cluster_size).div_ceil(l2_entries);
+        let l1_clusters = (l1_size * 8).div_ceil(cluster_size).max(1);
+        // header, refcount table, refcount block and L1 table
+        let refcount_table_offset = cluster_size;
+        let refcount_block_offset = 2 * cluster_size;
+        let l1_table_offset = 3 * cluster_size;
+        let clusters = 3 + l1_clusters;
+
+        let mut header = vec![0; HEADER_V3_SIZE];
+        header[0..4].copy_from_slice(&QCOW_MAGIC);
+        header[4..8].copy_from_slice(&3u32.to_be_bytes());
+        header[20..24].copy_from_slice(&DEFAULT_CLUSTER_BITS.to_be_bytes());
+        header[24..32].copy_from_slice(&size.to_be_bytes());
+        header[36..40].copy_from_slice(&(l1_size as u32).to_be_bytes());
+        header[40..48].copy_from_slice(&l1_table_offset.to_be_bytes());
+        header[48..56].copy_from_slice(&refcount_table_offset.to_be_bytes());
+        header[56..60].copy_from_slice(&1u32.to_be_bytes());
+        header[96..100].copy_from_slice(&REFCOUNT_ORDER.to_be_bytes());
+        header[100..104].copy_from_slice(&(HEADER_V3_SIZE as u32).to_be_bytes());
+        // the end of the header extensions
+        header.extend_from_slice(&[0; 8]);
+        if let Some(name) = backing_file {
+            let offset = header.len() as u64;
+            header[8..16].copy_from_slice(&offset.to_be_bytes());
+            header[16..20].copy_from_slice(&(name.len() as u32).to_be_bytes());
+            header.extend_from_slice(name.as_bytes());
+        }
+        if header.len() as u64 > cluster_size {
+            return Err(invalid_image("backing file name too long"));
+        }
+
+        let mut file = File::create(path)?;
+        file.set_len(clusters * cluster_size)?;
+        file.write_all(&header)?;
+        file.seek(SeekFrom::Start(refcount_table_offset))?;
+        file.write_all(&refcount_block_offset.to_be_bytes())?;
+        file.seek(SeekFrom::Start(refcount_block_offset))?;
+        for _ in 0..clusters {
+            file.write_all(&1u16.to_be_bytes())?;
+        }
+        file.sync_all()?;
+        Qcow2Disk::open(path, false)
+    }
+
+    pub fn get_cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+
+    pub fn has_backing_file(&self) -> bool {
+        self.backing.is_some()
+    }
+
+    fn align_to_cluster(&self, offset: u64) -> u64 {
+        offset.div_ceil(self.get_cluster_size()) * self.get_cluster_size()
+    }
+
+    fn read_file(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(data)
+    }
+
+    fn write_file(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)
+    }
+
+    /// Table of big endian 64-bit entries.
+    fn read_table(&mut self, offset: u64, entries: u64) -> io::Result<Vec<u64>> {
+        let mut data = vec![0; entries as usize * 8];
+        self.read_file(offset, &mut data)?;
+        Ok(data.chunks_exact(8).map(|e| get_u64(e, 0)).collect())
+    }
+
+    fn get_l2_table(&mut self, offset: u64) -> io::Result<&mut Vec<u64>> {
+        if !self.l2_tables.contains_key(&offset) {
+            let table = self.read_table(offset, self.get_cluster_size() / 8)?;
+            self.l2_tables.insert(offset, table);
+        }
+        Ok(self.l2_tables.get_mut(&offset).unwrap())
+    }
+
+    /// Index of the L1 table and the L2 table of a guest cluster.
+    fn get_table_indexes(&self, cluster_index: u64) -> (usize, usize) {
+        let l2_entries = self.get_cluster_size() / 8;
+        (
+            (cluster_index / l2_entries) as usize,
+            (cluster_index % l2_entries) as usize,
+        )
+    }
+
+    fn get_cluster(&mut self, cluster_index: u64) -> io::Result<Cluster> {
+        let (l1_index, l2_index) = self.get_table_indexes(cluster_index);
+        let l2_offset = self.l1_table.get(l1_index).cloned().unwrap_or(0) & OFFSET_MASK;
+        if l2_offset == 0 {
+            return Ok(Cluster::Unallocated);
+        }
+        let entry = self.get_l2_table(l2_offset)?[l2_index];
+        if entry & FLAG_COMPRESSED != 0 {
+            // the offset is followed by the number of additional 512-byte sectors.
+            let offset_bits = 62 - (self.cluster_bits - 8);
+            let offset = entry & ((1 << offset_bits) - 1);
+            let sectors = ((entry & !FLAG_COPIED & !FLAG_COMPRESSED) >> offset_bits) + 1;
+            let size = sectors * 512 - (offset & 511);
+            return Ok(Cluster::Compressed(offset, size as usize));
+        }
+        // the zero flag is reserved in version 2.
+        if self.version >= 3 && entry & FLAG_ZERO != 0 {
+            return Ok(Cluster::Zero);
+        }
+        match entry & OFFSET_MASK {
+            0 => Ok(Cluster::Unallocated),
+            offset => Ok(Cluster::Normal(offset, entry & FLAG_COPIED != 0)),
+        }
+    }
+
+    /// Read within a guest cluster.
+    fn read_cluster(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        let cluster_size = self.get_cluster_size();
+        let in_cluster = offset % cluster_size;
+        match self.get_cluster(offset >> self.cluster_bits)? {
+            Cluster::Unallocated => {
+                data.iter_mut().for_each(|b| *b = 0);
+                if let Some(backing) = &mut self.backing {
+                    // the backing file may be smaller than the image.
+                    let len = backing.get_size().saturating_sub(offset);
+                    let len = (len as usize).min(data.len());
+                    backing.read_at(offset, &mut data[..len])?;
+                }
+                Ok(())
+            }
+            Cluster::Zero => {
+                data.iter_mut().for_each(|b| *b = 0);
+                Ok(())
+            }
+            Cluster::Normal(host, _) => self.read_file(host + in_cluster, data),
+            Cluster::Compressed(host, size) => {
+                if self.decompressed.as_ref().map(|d| d.0) != Some(host) {
+                    let mut compressed = Vec::with_capacity(size);
+                    // the last compressed cluster may end before the last sector.
+                    self.file.seek(SeekFrom::Start(host))?;
+                    (&mut self.file)
+                        .take(size as u64)
+                        .read_to_end(&mut compressed)?;
+                    let cluster = inflate(&compressed, cluster_size as usize)?;
+                    if cluster.len() != cluster_size as usize {
+                        return Err(invalid_image("truncated compressed cluster"));
+                    }
+                    self.decompressed = Some((host, cluster));
+                }
+                if let Some((_, cluster)) = &self.decompressed {
+                    let start = in_cluster as usize;
+                    data.copy_from_slice(&cluster[start..start + data.len()]);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write within a guest cluster.
+    fn write_cluster(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let cluster_size = self.get_cluster_size();
+        let cluster_index = offset >> self.cluster_bits;
+        if let Cluster::Normal(host, true) = self.get_cluster(cluster_index)? {
+            return self.write_file(host + offset % cluster_size, data);
+        }
+        // copy the current data of the cluster into a new one. A shared
+        // cluster keeps its refcount, which only leaks it.
+        let start = cluster_index << self.cluster_bits;
+        let len = cluster_size.min(self.size - start) as usize;
+        let mut cluster = vec![0; cluster_size as usize];
+        self.read_cluster(start, &mut cluster[..len])?;
+        let in_cluster = (offset - start) as usize;
+        cluster[in_cluster..in_cluster + data.len()].copy_from_slice(data);
+        let host = self.allocate_cluster()?;
+        self.write_file(host, &cluster)?;
+        self.set_l2_entry(cluster_index, host | FLAG_COPIED)
+    }
+
+    fn set_l2_entry(&mut self, cluster_index: u64, entry: u64) -> io::Result<()> {
+        let (l1_index, l2_index) = self.get_table_indexes(cluster_index);
+        let mut l2_offset = self.l1_table[l1_index] & OFFSET_MASK;
+        if l2_offset == 0 {
+            l2_offset = self.allocate_cluster()?;
+            let table = vec![0; self.get_cluster_size() as usize / 8];
+            self.write_file(l2_offset, &vec![0; self.get_cluster_size() as usize])?;
+            self.l2_tables.insert(l2_offset, table);
+            self.l1_table[l1_index] = l2_offset | FLAG_COPIED;
+            let l1_entry = self.l1_table_offset + 8 * l1_index as u64;
+            self.write_file(l1_entry, &(l2_offset | FLAG_COPIED).to_be_bytes())?;
+        }
+        self.get_l2_table(l2_offset)?[l2_index] = entry;
+        self.write_file(l2_offset + 8 * l2_index as u64, &entry.to_be_bytes())
+    }
+
+    /// Take a cluster at the end of the file.
+    fn allocate_cluster(&mut self) -> io::Result<u64> {
+        let offset = self.next_cluster;
+        self.next_cluster += self.get_cluster_size();
+        self.set_refcount(offset, 1)?;
+        Ok(offset)
+    }
+
+    fn set_refcount(&mut self, offset: u64, refcount: u16) -> io::Result<()> {
+        let cluster_size = self.get_cluster_size();
+        let block_entries = cluster_size / 2;
+        let cluster_index = offset >> self.cluster_bits;
+        let table_index = (cluster_index / block_entries) as usize;
+        if table_index >= self.refcount_table.len() {
+            return Err(io::Error::other("refcount table is full"));
+        }
+        let mut block = self.refcount_table[table_index] & OFFSET_MASK;
+        if block == 0 {
+            // the new refcount block counts itself if it is in its range.
+            block = self.next_cluster;
+            self.next_cluster += cluster_size;
+            self.write_file(block, &vec![0; cluster_size as usize])?;
+            self.refcount_table[table_index] = block;
+            let entry = self.refcount_table_offset + 8 * table_index as u64;
+            self.write_file(entry, &block.to_be_bytes())?;
+            self.set_refcount(block, 1)?;
+        }
+        let entry = block + 2 * (cluster_index % block_entries);
+        self.write_file(entry, &refcount.to_be_bytes())
+    }
+
+    fn check_range(&self, offset: u64, len: usize) -> io::Result<()> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(out_of_range()),
+        }
+    }
+
+    /// Split the range into (offset, offset in data, length) within clusters.
+    fn split(&self, offset: u64, len: usize) -> Vec<(u64, usize, usize)> {
+        let cluster_size = self.get_cluster_size();
+        let mut pieces = vec![];
+        let mut pos = 0;
+        while pos < len {
+            let addr = offset + pos as u64;
+            let n = ((cluster_size - addr % cluster_size) as usize).min(len - pos);
+            pieces.push((addr, pos, n));
+            pos += n;
+        }
+        pieces
+    }
+}
+
+impl BlockBackend for Qcow2Disk {
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        self.check_range(offset, data.len())?;
+        for (addr, pos, n) in self.split(offset, data.len()) {
+            self.read_cluster(addr, &mut data[pos..pos + n])?;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "read-only disk",
+            ));
+        }
+        self.check_range(offset, data.len())?;
+        for (addr, pos, n) in self.split(offset, data.len()) {
+            self.write_cluster(addr, &data[pos..pos + n])?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.read_only {
+            true => Ok(()),
+            false => self.file.sync_data(),
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
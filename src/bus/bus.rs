@@ -1,7 +1,18 @@
+use crate::block::BlockBackend;
 use crate::console::Console;
+use crate::machine::ExitStatus;
+use crate::net::NetBackend;
+use crate::peripherals::fe310_g002::gpio::Gpio;
+use crate::peripherals::fe310_g002::pwm::Pwm;
+use crate::peripherals::framebuffer::Framebuffer;
+use crate::peripherals::goldfish_rtc::TimeSource;
+use crate::peripherals::i2c::I2cDevice;
+use crate::peripherals::pci::PciDevice;
+use crate::peripherals::spi::SpiDevice;
+use crate::peripherals::virtio::VirtioDevice;
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Device {
     Dram = 0,
     SpiFlash = 1,
@@ -13,7 +24,93 @@ pub trait Bus {
     fn set_device_data(&mut self, device: Device, data: Vec<u8>);
     fn get_base_address(&mut self, device: Device) -> u64;
     fn get_console(&mut self) -> &mut Box<dyn Console>;
+    /// set the boot disk (Err if the machine has no disk).
+    fn set_disk(&mut self, _disk: Box<dyn BlockBackend>) -> Result<(), ()> {
+        Err(())
+    }
+    /// plug a device into a free virtio slot (Err if the machine has none).
+    fn attach_virtio_device(&mut self, _device: Box<dyn VirtioDevice>) -> Result<(), ()> {
+        Err(())
+    }
+    /// select the virtio-mmio interface version of all the slots.
+    fn set_virtio_version(&mut self, _version: u32) -> Result<(), ()> {
+        Err(())
+    }
+    /// plug a device into a free PCI slot (Err if the machine has no PCI bus).
+    fn attach_pci_device(&mut self, _device: Box<dyn PciDevice>) -> Result<(), ()> {
+        Err(())
+    }
+    /// plug a device on a chip select line of a SPI controller (Err if the
+    /// machine has no such line).
+    fn attach_spi_device(
+        &mut self,
+        _controller: usize,
+        _cs: usize,
+        _device: Box<dyn SpiDevice>,
+    ) -> Result<(), ()> {
+        Err(())
+    }
+    /// plug a device on the I2C bus (Err if the machine has no I2C bus or
+    /// the address is used).
+    fn attach_i2c_device(&mut self, _device: Box<dyn I2cDevice>) -> Result<(), ()> {
+        Err(())
+    }
+    /// connect the ethernet MAC to a network backend (Err if the machine has
+    /// no MAC or it is already connected).
+    fn attach_ethernet(&mut self, _backend: Box<dyn NetBackend>) -> Result<(), ()> {
+        Err(())
+    }
+    /// the GPIO controller (None if the machine has no such controller).
+    fn get_gpio(&mut self) -> Option<&mut Gpio> {
+        None
+    }
+    /// the PWM controller (None if the machine has no such controller).
+    fn get_pwm(&mut self, _index: usize) -> Option<&mut Pwm> {
+        None
+    }
+    /// the linear framebuffer (None if the machine has no display).
+    fn get_framebuffer(&mut self) -> Option<&mut Framebuffer> {
+        None
+    }
+    /// set the state of the boot mode select pins (Err if the machine has
+    /// none).
+    fn set_mode_select(&mut self, _msel: u32) -> Result<(), ()> {
+        Err(())
+    }
+    /// address of the reset vector of the mask ROM (None if the machine
+    /// boots without one).
+    fn get_reset_vector(&self) -> Option<u64> {
+        None
+    }
+    /// keep the one-time programmable memory in the backend (Err if the
+    /// machine has none).
+    fn set_otp(&mut self, _backend: Box<dyn BlockBackend>) -> Result<(), ()> {
+        Err(())
+    }
+    /// set the time source of the real-time clock (Err if the machine has
+    /// no clock keeping the time of the day).
+    fn set_time_source(&mut self, _source: Box<dyn TimeSource>) -> Result<(), ()> {
+        Err(())
+    }
+    /// name of the memory mapped I/O device at the address (None for memories).
+    fn get_mmio_device_name(&self, addr: u64) -> Option<&'static str>;
+    /// the address is in a RAM, which is read without side effects.
+    fn is_ram(&self, addr: u64) -> bool;
     fn tick(&mut self) -> Vec<bool>;
+    /// reset the devices out of the always-on domain, the memories are kept.
+    fn reset(&mut self);
+    /// a device requested a reset of the machine since the last call.
+    fn take_reset_request(&mut self) -> bool {
+        false
+    }
+    /// a device stopped or rebooted the machine since the last call.
+    fn take_exit_request(&mut self) -> Option<ExitStatus> {
+        None
+    }
+    /// the core is powered down, only the devices run.
+    fn is_sleeping(&mut self) -> bool {
+        false
+    }
     fn is_pending_software_interrupt(&mut self, core: usize) -> bool;
     fn is_pending_timer_interrupt(&mut self, core: usize) -> bool;
     fn read8(&mut self, addr: u64) -> Result<u8, ()>;
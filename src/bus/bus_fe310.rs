@@ -1,14 +1,25 @@
 // FE310 SoC
 
+use crate::block::BlockBackend;
 use crate::bus::bus::*;
 use crate::console::*;
+use crate::machine::ExitStatus;
+use crate::peripherals::fe310_g002::aon::Aon;
 use crate::peripherals::fe310_g002::fe310_uart::Fe310Uart;
 use crate::peripherals::fe310_g002::gpio::Gpio;
-use crate::peripherals::fe310_g002::prci::Prci;
+use crate::peripherals::fe310_g002::i2c::Fe310I2c;
+use crate::peripherals::fe310_g002::otp::Otp;
+use crate::peripherals::fe310_g002::prci::{Prci, PRCI_RTCCLK_FREQUENCY};
+use crate::peripherals::fe310_g002::pwm::Pwm;
+use crate::peripherals::fe310_g002::spi::Fe310Spi;
 use crate::peripherals::fu540_c000::clint::Clint;
 use crate::peripherals::fu540_c000::plic::Plic;
+use crate::peripherals::i2c::I2cDevice;
 use crate::peripherals::intc::Intc;
 use crate::peripherals::memory::Memory;
+use crate::peripherals::mrom::{BootTarget, ModeSelect, MSEL_RESET_VECTOR};
+use crate::peripherals::spi::nor_flash::{NorFlash, NOR_FLASH_SIZE};
+use crate::peripherals::spi::SpiDevice;
 use crate::peripherals::timer::Timer;
 
 const _DEBUG_ADDRESS_START: u64 = 0x0000_0000;
@@ -17,8 +28,11 @@ const _DEBUG_ADDRESS_END: u64 = 0x0000_0FFF;
 const DTB_ADDRESS_START: u64 = 0x0000_1020;
 const _DTB_ADDRESS_END: u64 = 0x0000_1FFF;
 
-const _MROM_ADDRESS_START: u64 = 0x0000_1000;
-const _MROM_ADDRESS_END: u64 = 0x0000_1FFF;
+const MSEL_ADDRESS_START: u64 = 0x0000_1000;
+const MSEL_ADDRESS_END: u64 = 0x0000_1FFF;
+
+const OTP_ADDRESS_START: u64 = 0x0002_0000;
+const OTP_ADDRESS_END: u64 = 0x0002_1FFF;
 
 const TIMER_ADDRESS_START: u64 = 0x0200_0000;
 const TIMER_ADDRESS_END: u64 = 0x0200_FFFF;
@@ -26,18 +40,50 @@ const TIMER_ADDRESS_END: u64 = 0x0200_FFFF;
 const INTC_ADDRESS_START: u64 = 0x0C00_0000;
 const INTC_ADDRESS_END: u64 = 0x0FFF_FFFF;
 
+const AON_ADDRESS_START: u64 = 0x1000_0000;
+const AON_ADDRESS_END: u64 = 0x1000_0FFF;
+
 const PRCI_ADDRESS_START: u64 = 0x1000_8000;
 const PRCI_ADDRESS_END: u64 = 0x1000_8FFF;
 
 const UART0_ADDRESS_START: u64 = 0x1001_3000;
 const UART0_ADDRESS_END: u64 = 0x1001_3FFF;
 
+const OTPCTRL_ADDRESS_START: u64 = 0x1001_0000;
+const OTPCTRL_ADDRESS_END: u64 = 0x1001_0FFF;
+
+const QSPI0_ADDRESS_START: u64 = 0x1001_4000;
+const QSPI0_ADDRESS_END: u64 = 0x1001_4FFF;
+
+// GPIO pins of the PWM comparators in the IOF1
+const PWM0_IOF1_PINS: [u32; 4] = [0, 1, 2, 3];
+const PWM1_IOF1_PINS: [u32; 4] = [20, 19, 21, 22];
+const PWM2_IOF1_PINS: [u32; 4] = [10, 11, 12, 13];
+
+const PWM0_ADDRESS_START: u64 = 0x1001_5000;
+const PWM0_ADDRESS_END: u64 = 0x1001_5FFF;
+
+const I2C0_ADDRESS_START: u64 = 0x1001_6000;
+const I2C0_ADDRESS_END: u64 = 0x1001_6FFF;
+
 const GPIO_ADDRESS_START: u64 = 0x1001_2000;
 const GPIO_ADDRESS_END: u64 = 0x1001_2FFF;
 
 const UART1_ADDRESS_START: u64 = 0x1002_3000;
 const UART1_ADDRESS_END: u64 = 0x1002_3FFF;
 
+const SPI1_ADDRESS_START: u64 = 0x1002_4000;
+const SPI1_ADDRESS_END: u64 = 0x1002_4FFF;
+
+const PWM1_ADDRESS_START: u64 = 0x1002_5000;
+const PWM1_ADDRESS_END: u64 = 0x1002_5FFF;
+
+const SPI2_ADDRESS_START: u64 = 0x1003_4000;
+const SPI2_ADDRESS_END: u64 = 0x1003_4FFF;
+
+const PWM2_ADDRESS_START: u64 = 0x1003_5000;
+const PWM2_ADDRESS_END: u64 = 0x1003_5FFF;
+
 const SPIFLASH_ADDRESS_START: u64 = 0x2000_0000;
 const SPIFLASH_ADDRESS_END: u64 = 0x3FFF_FFFF;
 
@@ -46,41 +92,110 @@ const DTIM_ADDRESS_START: u64 = 0x8000_0000;
 const DTIM_ADDRESS_END: u64 = 0x8000_3FFF;
 
 const DTIM_SIZE: usize = 0x4000;
-const FLASH_SIZE: usize = 1024 * 1024 * 512;
+
+/// Boot targets of the mode select values: 0x1 jumps to the SPI flash and
+/// 0xb to the OTP, the other values are reserved.
+const BOOT_TARGETS: [BootTarget; 16] = [
+    BootTarget::Wait,
+    BootTarget::Address(SPIFLASH_ADDRESS_START as u32),
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Address(OTP_ADDRESS_START as u32),
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Wait,
+];
 
 pub struct BusFe310 {
     clock: u64,
+    /// Core frequency the CLINT and the AON are clocked from
+    core_frequency: u64,
+    msel: ModeSelect,
     dtim: Memory,
-    flash: Memory,
     timer: Box<dyn Timer>,
     intc: Box<dyn Intc>,
+    aon: Aon,
     prci: Prci,
     uart0: Fe310Uart,
     uart1: Fe310Uart,
     gpio: Gpio,
+    /// SPI flash controller, with the flash mapped into the memory
+    qspi0: Fe310Spi,
+    spi1: Fe310Spi,
+    spi2: Fe310Spi,
+    pwm0: Pwm,
+    pwm1: Pwm,
+    pwm2: Pwm,
+    i2c0: Fe310I2c,
+    otp: Otp,
 }
 
 impl BusFe310 {
     pub fn new(console: Box<dyn Console>) -> Self {
+        let mut qspi0 = Fe310Spi::new(1, true);
+        qspi0
+            .attach_device(0, Box::new(NorFlash::new(NOR_FLASH_SIZE)))
+            .unwrap();
         Self {
             clock: 0,
+            core_frequency: 0,
+            msel: ModeSelect::new(MSEL_ADDRESS_START, BOOT_TARGETS),
             dtim: Memory::new(DTIM_SIZE),
-            flash: Memory::new(FLASH_SIZE),
             timer: Box::new(Clint::new()),
             intc: Box::new(Plic::new()),
+            aon: Aon::new(),
             uart0: Fe310Uart::new(console),
             uart1: Fe310Uart::new(Box::new(TtyDummy::new())),
             prci: Prci::new(),
             gpio: Gpio::new(),
+            qspi0,
+            spi1: Fe310Spi::new(4, false),
+            spi2: Fe310Spi::new(1, false),
+            pwm0: Pwm::new(8),
+            pwm1: Pwm::new(16),
+            pwm2: Pwm::new(16),
+            i2c0: Fe310I2c::new(),
+            otp: Otp::new(),
         }
     }
+
+    /// Drive the pins of the IOF1 with the PWM outputs. The serial
+    /// controllers of the IOF0 exchange their data directly and leave their
+    /// pins alone.
+    fn route_iof(&mut self) {
+        let mut pwm_pins = 0;
+        for (i, pin) in PWM0_IOF1_PINS.iter().enumerate() {
+            pwm_pins |= (self.pwm0.get_output(i) as u32) << pin;
+        }
+        for (i, pin) in PWM1_IOF1_PINS.iter().enumerate() {
+            pwm_pins |= (self.pwm1.get_output(i) as u32) << pin;
+        }
+        for (i, pin) in PWM2_IOF1_PINS.iter().enumerate() {
+            pwm_pins |= (self.pwm2.get_output(i) as u32) << pin;
+        }
+        let enable = [PWM0_IOF1_PINS, PWM1_IOF1_PINS, PWM2_IOF1_PINS]
+            .iter()
+            .flat_map(|pins| pins.iter())
+            .fold(0, |enable, pin| enable | 1 << pin);
+        self.gpio.set_iof_outputs(1, enable, pwm_pins);
+    }
 }
 
 impl Bus for BusFe310 {
     fn set_device_data(&mut self, device: Device, data: Vec<u8>) {
         match device {
             Device::SpiFlash => {
-                self.flash.initialize(data);
+                let mut flash = NorFlash::new(NOR_FLASH_SIZE);
+                flash.load(&data);
+                self.qspi0.attach_device(0, Box::new(flash)).unwrap();
             }
             _ => panic!("Unexpected device: {:?}", device),
         }
@@ -90,25 +205,180 @@ impl Bus for BusFe310 {
         self.uart0.get_console()
     }
 
+    fn attach_spi_device(
+        &mut self,
+        controller: usize,
+        cs: usize,
+        device: Box<dyn SpiDevice>,
+    ) -> Result<(), ()> {
+        match controller {
+            0 => self.qspi0.attach_device(cs, device),
+            1 => self.spi1.attach_device(cs, device),
+            2 => self.spi2.attach_device(cs, device),
+            _ => Err(()),
+        }
+    }
+
+    fn attach_i2c_device(&mut self, device: Box<dyn I2cDevice>) -> Result<(), ()> {
+        self.i2c0.attach_device(device)
+    }
+
+    fn get_gpio(&mut self) -> Option<&mut Gpio> {
+        Some(&mut self.gpio)
+    }
+
+    fn get_pwm(&mut self, index: usize) -> Option<&mut Pwm> {
+        match index {
+            0 => Some(&mut self.pwm0),
+            1 => Some(&mut self.pwm1),
+            2 => Some(&mut self.pwm2),
+            _ => None,
+        }
+    }
+
+    fn set_mode_select(&mut self, msel: u32) -> Result<(), ()> {
+        self.msel.set_mode_select(msel);
+        Ok(())
+    }
+
+    fn get_reset_vector(&self) -> Option<u64> {
+        Some(MSEL_ADDRESS_START + MSEL_RESET_VECTOR)
+    }
+
+    fn set_otp(&mut self, backend: Box<dyn BlockBackend>) -> Result<(), ()> {
+        self.otp.set_backend(backend);
+        Ok(())
+    }
+
+    fn get_mmio_device_name(&self, addr: u64) -> Option<&'static str> {
+        match addr {
+            TIMER_ADDRESS_START..=TIMER_ADDRESS_END => Some("clint"),
+            INTC_ADDRESS_START..=INTC_ADDRESS_END => Some("plic"),
+            AON_ADDRESS_START..=AON_ADDRESS_END => Some("aon"),
+            PRCI_ADDRESS_START..=PRCI_ADDRESS_END => Some("prci"),
+            GPIO_ADDRESS_START..=GPIO_ADDRESS_END => Some("gpio"),
+            UART0_ADDRESS_START..=UART0_ADDRESS_END => Some("uart0"),
+            UART1_ADDRESS_START..=UART1_ADDRESS_END => Some("uart1"),
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => Some("otp"),
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => Some("qspi0"),
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => Some("spi1"),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => Some("spi2"),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => Some("pwm0"),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => Some("pwm1"),
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => Some("pwm2"),
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => Some("i2c0"),
+            _ => None,
+        }
+    }
+
+    fn is_ram(&self, addr: u64) -> bool {
+        matches!(addr, DTIM_ADDRESS_START..=DTIM_ADDRESS_END)
+    }
+
     fn tick(&mut self) -> Vec<bool> {
         self.clock = self.clock.wrapping_add(1);
 
+        // the RTCCLK ticks of the CLINT and the AON follow the clock tree.
+        let core_frequency = self.prci.get_core_frequency();
+        if core_frequency != self.core_frequency {
+            self.core_frequency = core_frequency;
+            self.timer.set_frequency(core_frequency, PRCI_RTCCLK_FREQUENCY);
+            self.aon.set_core_frequency(core_frequency);
+        }
+
         self.timer.tick();
+        self.aon.tick();
         self.prci.tick();
-        self.gpio.tick();
         self.uart0.tick();
         self.uart1.tick();
+        self.qspi0.tick();
+        self.spi1.tick();
+        self.spi2.tick();
+        self.pwm0.tick();
+        self.pwm1.tick();
+        self.pwm2.tick();
+        self.i2c0.tick();
+        self.route_iof();
+        self.gpio.tick();
 
         let mut interrupts: Vec<usize> = Vec::new();
+        if self.aon.is_watchdog_irq() {
+            interrupts.push(1); // Interrupt ID for the watchdog
+        }
+        if self.aon.is_rtc_irq() {
+            interrupts.push(2); // Interrupt ID for the RTC
+        }
         if self.uart0.is_irq() {
             interrupts.push(3); // Interrupt ID for UART0
         }
         if self.uart1.is_irq() {
             interrupts.push(4); // Interrupt ID for UART1
         }
+        if self.qspi0.is_irq() {
+            interrupts.push(5); // Interrupt ID for QSPI0
+        }
+        if self.spi1.is_irq() {
+            interrupts.push(6); // Interrupt ID for SPI1
+        }
+        if self.spi2.is_irq() {
+            interrupts.push(7); // Interrupt ID for SPI2
+        }
+        let gpio_irqs = self.gpio.get_irqs();
+        for pin in 0..32 {
+            if (gpio_irqs >> pin) & 1 != 0 {
+                interrupts.push(8 + pin); // Interrupt IDs for GPIO
+            }
+        }
+        for i in 0..4 {
+            if self.pwm0.is_irq(i) {
+                interrupts.push(40 + i); // Interrupt IDs for PWM0
+            }
+            if self.pwm1.is_irq(i) {
+                interrupts.push(44 + i); // Interrupt IDs for PWM1
+            }
+            if self.pwm2.is_irq(i) {
+                interrupts.push(48 + i); // Interrupt IDs for PWM2
+            }
+        }
+        if self.i2c0.is_irq() {
+            interrupts.push(52); // Interrupt ID for I2C0
+        }
         self.intc.tick(0, interrupts)
     }
 
+    fn reset(&mut self) {
+        self.timer = Box::new(Clint::new());
+        self.intc = Box::new(Plic::new());
+        self.prci = Prci::new();
+        self.core_frequency = 0;
+        self.gpio.reset();
+        self.uart0.reset();
+        self.uart1.reset();
+        self.qspi0.reset();
+        self.spi1.reset();
+        self.spi2.reset();
+        self.pwm0 = Pwm::new(8);
+        self.pwm1 = Pwm::new(16);
+        self.pwm2 = Pwm::new(16);
+        self.i2c0.reset();
+        self.otp.reset();
+    }
+
+    fn take_reset_request(&mut self) -> bool {
+        self.aon.take_reset_request()
+    }
+
+    fn take_exit_request(&mut self) -> Option<ExitStatus> {
+        match self.aon.take_watchdog_reset() {
+            true => Some(ExitStatus::Reboot),
+            false => None,
+        }
+    }
+
+    fn is_sleeping(&mut self) -> bool {
+        self.aon.is_sleeping()
+    }
+
     fn is_pending_software_interrupt(&mut self, core: usize) -> bool {
         self.timer.is_pending_software_interrupt(core)
     }
@@ -127,15 +397,29 @@ impl Bus for BusFe310 {
 
     fn read8(&mut self, addr: u64) -> Result<u8, ()> {
         match addr {
+            MSEL_ADDRESS_START..=MSEL_ADDRESS_END => Ok(self.msel.read8(addr - MSEL_ADDRESS_START)),
+            OTP_ADDRESS_START..=OTP_ADDRESS_END => {
+                Ok(self.otp.read_memory8(addr - OTP_ADDRESS_START))
+            }
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => panic!("Unexpected size access."),
             INTC_ADDRESS_START..=INTC_ADDRESS_END => panic!("Unexpected size access."),
+            AON_ADDRESS_START..=AON_ADDRESS_END => panic!("Unexpected size access."),
             PRCI_ADDRESS_START..=PRCI_ADDRESS_END => panic!("Unexpected size access."),
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => panic!("Unexpected size access."),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => panic!("Unexpected size access."),
             UART1_ADDRESS_START..=UART1_ADDRESS_END => panic!("Unexpected size access."),
-            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
-                Ok(self.flash.read8(addr - SPIFLASH_ADDRESS_START))
-            }
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => panic!("Unexpected size access."),
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => panic!("Unexpected size access."),
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => panic!("Unexpected size access."),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => panic!("Unexpected size access."),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => panic!("Unexpected size access."),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => panic!("Unexpected size access."),
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => panic!("Unexpected size access."),
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => panic!("Unexpected size access."),
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => self
+                .qspi0
+                .read_flash(addr - SPIFLASH_ADDRESS_START, 1)
+                .map(|data| data as u8),
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => Ok(self.dtim.read8(addr - DTIM_ADDRESS_START)),
             _ => Err(()),
         }
@@ -143,15 +427,31 @@ impl Bus for BusFe310 {
 
     fn read16(&mut self, addr: u64) -> Result<u16, ()> {
         match addr {
+            MSEL_ADDRESS_START..=MSEL_ADDRESS_END => {
+                Ok(self.msel.read16(addr - MSEL_ADDRESS_START))
+            }
+            OTP_ADDRESS_START..=OTP_ADDRESS_END => {
+                Ok(self.otp.read_memory16(addr - OTP_ADDRESS_START))
+            }
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => panic!("Unexpected size access."),
             INTC_ADDRESS_START..=INTC_ADDRESS_END => panic!("Unexpected size access."),
+            AON_ADDRESS_START..=AON_ADDRESS_END => panic!("Unexpected size access."),
             PRCI_ADDRESS_START..=PRCI_ADDRESS_END => panic!("Unexpected size access."),
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => panic!("Unexpected size access."),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => panic!("Unexpected size access."),
             UART1_ADDRESS_START..=UART1_ADDRESS_END => panic!("Unexpected size access."),
-            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
-                Ok(self.flash.read16(addr - SPIFLASH_ADDRESS_START))
-            }
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => panic!("Unexpected size access."),
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => panic!("Unexpected size access."),
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => panic!("Unexpected size access."),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => panic!("Unexpected size access."),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => panic!("Unexpected size access."),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => panic!("Unexpected size access."),
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => panic!("Unexpected size access."),
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => panic!("Unexpected size access."),
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => self
+                .qspi0
+                .read_flash(addr - SPIFLASH_ADDRESS_START, 2)
+                .map(|data| data as u16),
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.read16(addr - DTIM_ADDRESS_START))
             }
@@ -161,10 +461,17 @@ impl Bus for BusFe310 {
 
     fn read32(&mut self, addr: u64) -> Result<u32, ()> {
         match addr {
+            MSEL_ADDRESS_START..=MSEL_ADDRESS_END => {
+                Ok(self.msel.read32(addr - MSEL_ADDRESS_START))
+            }
+            OTP_ADDRESS_START..=OTP_ADDRESS_END => {
+                Ok(self.otp.read_memory32(addr - OTP_ADDRESS_START))
+            }
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => {
                 Ok(self.timer.read(addr - TIMER_ADDRESS_START))
             }
             INTC_ADDRESS_START..=INTC_ADDRESS_END => Ok(self.intc.read(addr - INTC_ADDRESS_START)),
+            AON_ADDRESS_START..=AON_ADDRESS_END => Ok(self.aon.read(addr - AON_ADDRESS_START)),
             PRCI_ADDRESS_START..=PRCI_ADDRESS_END => Ok(self.prci.read(addr - PRCI_ADDRESS_START)),
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => Ok(self.gpio.read(addr - GPIO_ADDRESS_START)),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => {
@@ -173,9 +480,22 @@ impl Bus for BusFe310 {
             UART1_ADDRESS_START..=UART1_ADDRESS_END => {
                 Ok(self.uart1.read(addr - UART1_ADDRESS_START))
             }
-            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
-                Ok(self.flash.read32(addr - SPIFLASH_ADDRESS_START))
-            }
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => {
+                Ok(self.otp.read(addr - OTPCTRL_ADDRESS_START))
+            }
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => {
+                Ok(self.qspi0.read(addr - QSPI0_ADDRESS_START))
+            }
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => Ok(self.spi1.read(addr - SPI1_ADDRESS_START)),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => Ok(self.spi2.read(addr - SPI2_ADDRESS_START)),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => Ok(self.pwm0.read(addr - PWM0_ADDRESS_START)),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => Ok(self.pwm1.read(addr - PWM1_ADDRESS_START)),
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => Ok(self.pwm2.read(addr - PWM2_ADDRESS_START)),
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => Ok(self.i2c0.read(addr - I2C0_ADDRESS_START)),
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => self
+                .qspi0
+                .read_flash(addr - SPIFLASH_ADDRESS_START, 4)
+                .map(|data| data as u32),
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.read32(addr - DTIM_ADDRESS_START))
             }
@@ -185,6 +505,12 @@ impl Bus for BusFe310 {
 
     fn read64(&mut self, addr: u64) -> Result<u64, ()> {
         match addr {
+            MSEL_ADDRESS_START..=MSEL_ADDRESS_END => {
+                Ok(self.msel.read64(addr - MSEL_ADDRESS_START))
+            }
+            OTP_ADDRESS_START..=OTP_ADDRESS_END => {
+                Ok(self.otp.read_memory64(addr - OTP_ADDRESS_START))
+            }
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => {
                 let timer_addr = addr - TIMER_ADDRESS_START;
                 let data = self.timer.read(timer_addr) as u64
@@ -197,6 +523,12 @@ impl Bus for BusFe310 {
                     | ((self.intc.read(intc_addr.wrapping_add(4)) as u64) << 32);
                 Ok(data)
             }
+            AON_ADDRESS_START..=AON_ADDRESS_END => {
+                let aon_addr = addr - AON_ADDRESS_START;
+                let data = self.aon.read(aon_addr) as u64
+                    | ((self.aon.read(aon_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
             PRCI_ADDRESS_START..=PRCI_ADDRESS_END => {
                 let prci_addr = addr - PRCI_ADDRESS_START;
                 let data = self.prci.read(prci_addr) as u64
@@ -221,9 +553,57 @@ impl Bus for BusFe310 {
                     | ((self.uart1.read(uart1_addr.wrapping_add(4)) as u64) << 32);
                 Ok(data)
             }
-            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
-                Ok(self.flash.read64(addr - SPIFLASH_ADDRESS_START))
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => {
+                let otpctrl_addr = addr - OTPCTRL_ADDRESS_START;
+                let data = self.otp.read(otpctrl_addr) as u64
+                    | ((self.otp.read(otpctrl_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => {
+                let qspi0_addr = addr - QSPI0_ADDRESS_START;
+                let data = self.qspi0.read(qspi0_addr) as u64
+                    | ((self.qspi0.read(qspi0_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => {
+                let spi1_addr = addr - SPI1_ADDRESS_START;
+                let data = self.spi1.read(spi1_addr) as u64
+                    | ((self.spi1.read(spi1_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => {
+                let spi2_addr = addr - SPI2_ADDRESS_START;
+                let data = self.spi2.read(spi2_addr) as u64
+                    | ((self.spi2.read(spi2_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => {
+                let pwm0_addr = addr - PWM0_ADDRESS_START;
+                let data = self.pwm0.read(pwm0_addr) as u64
+                    | ((self.pwm0.read(pwm0_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => {
+                let pwm1_addr = addr - PWM1_ADDRESS_START;
+                let data = self.pwm1.read(pwm1_addr) as u64
+                    | ((self.pwm1.read(pwm1_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => {
+                let pwm2_addr = addr - PWM2_ADDRESS_START;
+                let data = self.pwm2.read(pwm2_addr) as u64
+                    | ((self.pwm2.read(pwm2_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => {
+                let i2c0_addr = addr - I2C0_ADDRESS_START;
+                let data = self.i2c0.read(i2c0_addr) as u64
+                    | ((self.i2c0.read(i2c0_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
             }
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => self
+                .qspi0
+                .read_flash(addr - SPIFLASH_ADDRESS_START, 8),
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.read64(addr - DTIM_ADDRESS_START))
             }
@@ -235,13 +615,22 @@ impl Bus for BusFe310 {
         match addr {
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => panic!("Unexpected size access."),
             INTC_ADDRESS_START..=INTC_ADDRESS_END => panic!("Unexpected size access."),
+            AON_ADDRESS_START..=AON_ADDRESS_END => panic!("Unexpected size access."),
             PRCI_ADDRESS_START..=PRCI_ADDRESS_END => panic!("Unexpected size access."),
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => panic!("Unexpected size access."),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => panic!("Unexpected size access."),
             UART1_ADDRESS_START..=UART1_ADDRESS_END => panic!("Unexpected size access."),
-            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
-                Ok(self.flash.write8(addr - SPIFLASH_ADDRESS_START, data))
-            }
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => panic!("Unexpected size access."),
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => panic!("Unexpected size access."),
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => panic!("Unexpected size access."),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => panic!("Unexpected size access."),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => panic!("Unexpected size access."),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => panic!("Unexpected size access."),
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => panic!("Unexpected size access."),
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => panic!("Unexpected size access."),
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => self
+                .qspi0
+                .write_flash(addr - SPIFLASH_ADDRESS_START, &data.to_le_bytes()),
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.write8(addr - DTIM_ADDRESS_START, data))
             }
@@ -253,13 +642,22 @@ impl Bus for BusFe310 {
         match addr {
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => panic!("Unexpected size access."),
             INTC_ADDRESS_START..=INTC_ADDRESS_END => panic!("Unexpected size access."),
+            AON_ADDRESS_START..=AON_ADDRESS_END => panic!("Unexpected size access."),
             PRCI_ADDRESS_START..=PRCI_ADDRESS_END => panic!("Unexpected size access."),
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => panic!("Unexpected size access."),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => panic!("Unexpected size access."),
             UART1_ADDRESS_START..=UART1_ADDRESS_END => panic!("Unexpected size access."),
-            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
-                Ok(self.flash.write16(addr - SPIFLASH_ADDRESS_START, data))
-            }
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => panic!("Unexpected size access."),
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => panic!("Unexpected size access."),
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => panic!("Unexpected size access."),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => panic!("Unexpected size access."),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => panic!("Unexpected size access."),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => panic!("Unexpected size access."),
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => panic!("Unexpected size access."),
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => panic!("Unexpected size access."),
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => self
+                .qspi0
+                .write_flash(addr - SPIFLASH_ADDRESS_START, &data.to_le_bytes()),
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.write16(addr - DTIM_ADDRESS_START, data))
             }
@@ -275,6 +673,10 @@ impl Bus for BusFe310 {
             INTC_ADDRESS_START..=INTC_ADDRESS_END => {
                 Ok(self.intc.write(addr - INTC_ADDRESS_START, data))
             }
+            AON_ADDRESS_START..=AON_ADDRESS_END => {
+                self.aon.write(addr - AON_ADDRESS_START, data);
+                Ok(())
+            }
             PRCI_ADDRESS_START..=PRCI_ADDRESS_END => {
                 Ok(self.prci.write(addr - PRCI_ADDRESS_START, data))
             }
@@ -287,9 +689,41 @@ impl Bus for BusFe310 {
             UART1_ADDRESS_START..=UART1_ADDRESS_END => {
                 Ok(self.uart1.write(addr - UART1_ADDRESS_START, data))
             }
-            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
-                Ok(self.flash.write32(addr - SPIFLASH_ADDRESS_START, data))
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => {
+                self.otp.write(addr - OTPCTRL_ADDRESS_START, data);
+                Ok(())
+            }
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => {
+                self.qspi0.write(addr - QSPI0_ADDRESS_START, data);
+                Ok(())
+            }
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => {
+                self.spi1.write(addr - SPI1_ADDRESS_START, data);
+                Ok(())
+            }
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => {
+                self.spi2.write(addr - SPI2_ADDRESS_START, data);
+                Ok(())
             }
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => {
+                self.pwm0.write(addr - PWM0_ADDRESS_START, data);
+                Ok(())
+            }
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => {
+                self.pwm1.write(addr - PWM1_ADDRESS_START, data);
+                Ok(())
+            }
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => {
+                self.pwm2.write(addr - PWM2_ADDRESS_START, data);
+                Ok(())
+            }
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => {
+                self.i2c0.write(addr - I2C0_ADDRESS_START, data);
+                Ok(())
+            }
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => self
+                .qspi0
+                .write_flash(addr - SPIFLASH_ADDRESS_START, &data.to_le_bytes()),
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.write32(addr - DTIM_ADDRESS_START, data))
             }
@@ -317,6 +751,15 @@ impl Bus for BusFe310 {
                 );
                 Ok(())
             }
+            AON_ADDRESS_START..=AON_ADDRESS_END => {
+                let aon_addr = addr - AON_ADDRESS_START;
+                self.aon.write(aon_addr, data as u32);
+                self.aon.write(
+                    aon_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
             PRCI_ADDRESS_START..=PRCI_ADDRESS_END => {
                 let prci_addr = addr - PRCI_ADDRESS_START;
                 self.prci.write(prci_addr, data as u32);
@@ -353,9 +796,81 @@ impl Bus for BusFe310 {
                 );
                 Ok(())
             }
-            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
-                Ok(self.flash.write64(addr - SPIFLASH_ADDRESS_START, data))
+            OTPCTRL_ADDRESS_START..=OTPCTRL_ADDRESS_END => {
+                let otpctrl_addr = addr - OTPCTRL_ADDRESS_START;
+                self.otp.write(otpctrl_addr, data as u32);
+                self.otp.write(
+                    otpctrl_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            QSPI0_ADDRESS_START..=QSPI0_ADDRESS_END => {
+                let qspi0_addr = addr - QSPI0_ADDRESS_START;
+                self.qspi0.write(qspi0_addr, data as u32);
+                self.qspi0.write(
+                    qspi0_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            SPI1_ADDRESS_START..=SPI1_ADDRESS_END => {
+                let spi1_addr = addr - SPI1_ADDRESS_START;
+                self.spi1.write(spi1_addr, data as u32);
+                self.spi1.write(
+                    spi1_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => {
+                let spi2_addr = addr - SPI2_ADDRESS_START;
+                self.spi2.write(spi2_addr, data as u32);
+                self.spi2.write(
+                    spi2_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => {
+                let pwm0_addr = addr - PWM0_ADDRESS_START;
+                self.pwm0.write(pwm0_addr, data as u32);
+                self.pwm0.write(
+                    pwm0_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => {
+                let pwm1_addr = addr - PWM1_ADDRESS_START;
+                self.pwm1.write(pwm1_addr, data as u32);
+                self.pwm1.write(
+                    pwm1_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            PWM2_ADDRESS_START..=PWM2_ADDRESS_END => {
+                let pwm2_addr = addr - PWM2_ADDRESS_START;
+                self.pwm2.write(pwm2_addr, data as u32);
+                self.pwm2.write(
+                    pwm2_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            I2C0_ADDRESS_START..=I2C0_ADDRESS_END => {
+                let i2c0_addr = addr - I2C0_ADDRESS_START;
+                self.i2c0.write(i2c0_addr, data as u32);
+                self.i2c0.write(
+                    i2c0_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
             }
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => self
+                .qspi0
+                .write_flash(addr - SPIFLASH_ADDRESS_START, &data.to_le_bytes()),
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.write64(addr - DTIM_ADDRESS_START, data))
             }
@@ -3,23 +3,38 @@
 
 use crate::bus::bus::*;
 use crate::console::*;
+use crate::net::NetBackend;
 use crate::peripherals::fe310_g002::fe310_uart::Fe310Uart;
 use crate::peripherals::fe310_g002::gpio::Gpio;
+use crate::peripherals::fe310_g002::i2c::Fe310I2c;
 use crate::peripherals::fe310_g002::prci::Prci;
+use crate::peripherals::fe310_g002::pwm::Pwm;
+use crate::peripherals::fe310_g002::spi::Fe310Spi;
+use crate::peripherals::i2c::I2cDevice;
 use crate::peripherals::fu540_c000::clint::Clint;
+use crate::peripherals::fu540_c000::ddr::DdrController;
+use crate::peripherals::fu540_c000::gemgxl::Gemgxl;
+use crate::peripherals::fu540_c000::l2cache::L2Cache;
 use crate::peripherals::fu540_c000::plic::Plic;
+use crate::peripherals::fu540_c000::zsbl::{ZSBL, ZSBL_ADDRESS};
 use crate::peripherals::intc::Intc;
 use crate::peripherals::memory::Memory;
+use crate::peripherals::mrom::{BootTarget, ModeSelect, MSEL_RESET_VECTOR};
+use crate::peripherals::spi::SpiDevice;
 use crate::peripherals::timer::Timer;
+use crate::peripherals::virtio::queue::GuestMemory;
 
 const _DEBUG_ADDRESS_START: u64 = 0x0000_0000;
 const _DEBUG_ADDRESS_END: u64 = 0x0000_0FFF;
 
+const MSEL_ADDRESS_START: u64 = 0x0000_1000;
+const MSEL_ADDRESS_END: u64 = 0x0000_1FFF;
+
 const DTB_ADDRESS_START: u64 = 0x0000_1020;
 const _DTB_ADDRESS_END: u64 = 0x0000_1FFF;
 
-const _MROM_ADDRESS_START: u64 = 0x0001_0000;
-const _MROM_ADDRESS_END: u64 = 0x0001_7FFF;
+const MROM_ADDRESS_START: u64 = 0x0001_0000;
+const MROM_ADDRESS_END: u64 = 0x0001_7FFF;
 
 const DTIM_ADDRESS_START: u64 = 0x0100_0000;
 const DTIM_ADDRESS_END: u64 = 0x0100_1FFF;
@@ -27,6 +42,12 @@ const DTIM_ADDRESS_END: u64 = 0x0100_1FFF;
 const TIMER_ADDRESS_START: u64 = 0x0200_0000;
 const TIMER_ADDRESS_END: u64 = 0x0200_FFFF;
 
+const L2CACHE_ADDRESS_START: u64 = 0x0201_0000;
+const L2CACHE_ADDRESS_END: u64 = 0x0201_0FFF;
+
+const L2LIM_ADDRESS_START: u64 = 0x0800_0000;
+const L2LIM_ADDRESS_END: u64 = 0x081F_FFFF;
+
 const INTC_ADDRESS_START: u64 = 0x0C00_0000;
 const INTC_ADDRESS_END: u64 = 0x0FFF_FFFF;
 
@@ -39,21 +60,76 @@ const UART0_ADDRESS_END: u64 = 0x1001_0FFF;
 const UART1_ADDRESS_START: u64 = 0x1001_1000;
 const UART1_ADDRESS_END: u64 = 0x1001_1FFF;
 
+const PWM0_ADDRESS_START: u64 = 0x1002_0000;
+const PWM0_ADDRESS_END: u64 = 0x1002_0FFF;
+
+const PWM1_ADDRESS_START: u64 = 0x1002_1000;
+const PWM1_ADDRESS_END: u64 = 0x1002_1FFF;
+
+const I2C_ADDRESS_START: u64 = 0x1003_0000;
+const I2C_ADDRESS_END: u64 = 0x1003_0FFF;
+
+const SPI2_ADDRESS_START: u64 = 0x1005_0000;
+const SPI2_ADDRESS_END: u64 = 0x1005_0FFF;
+
 const GPIO_ADDRESS_START: u64 = 0x1006_0000;
 const GPIO_ADDRESS_END: u64 = 0x1006_0FFF;
 
+const GEM_ADDRESS_START: u64 = 0x1009_0000;
+const GEM_ADDRESS_END: u64 = 0x1009_1FFF;
+
+const GEMMGMT_ADDRESS_START: u64 = 0x100A_0000;
+const GEMMGMT_ADDRESS_END: u64 = 0x100A_0FFF;
+
+const DDR_ADDRESS_START: u64 = 0x100B_0000;
+const DDR_ADDRESS_END: u64 = 0x100B_3FFF;
+
+const DDRBLOCKER_ADDRESS_START: u64 = 0x100B_8000;
+const DDRBLOCKER_ADDRESS_END: u64 = 0x100B_8FFF;
+
 const SPIFLASH_ADDRESS_START: u64 = 0x2000_0000;
 const SPIFLASH_ADDRESS_END: u64 = 0x3FFF_FFFF;
+/// The flash of QSPI1, the same memory as the one of QSPI0
+const SPIFLASH1_ADDRESS_START: u64 = 0x3000_0000;
 
 const DRAM_ADDRESS_START: u64 = 0x8000_0000;
 
+const MROM_SIZE: usize = 0x8000;
 const DTIM_SIZE: usize = 0x2000;
+const L2LIM_SIZE: usize = 0x20_0000;
 const FLASH_SIZE: usize = 1024 * 1024 * 512;
 const DRAM_SIZE: usize = 1024 * 1024 * 128;
 
+/// Boot targets of the mode select values: 0x1 and 0x2 jump to the SPI flash
+/// of QSPI0 and QSPI1, 0x3 and 0x4 (ChipLink) are not supported and the
+/// others run the ZSBL.
+const BOOT_TARGETS: [BootTarget; 16] = [
+    BootTarget::Wait,
+    BootTarget::Address(SPIFLASH_ADDRESS_START as u32),
+    BootTarget::Address(SPIFLASH1_ADDRESS_START as u32),
+    BootTarget::Wait,
+    BootTarget::Wait,
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+    BootTarget::Address(ZSBL_ADDRESS),
+];
+
 pub struct BusFu540 {
     clock: u64,
+    msel: ModeSelect,
+    /// Mask ROM with the ZSBL
+    mrom: Memory,
     dtim: Memory,
+    /// L2 cache ways used as memory
+    l2lim: Memory,
     flash: Memory,
     dram: Memory,
     timer: Box<dyn Timer>,
@@ -62,13 +138,28 @@ pub struct BusFu540 {
     uart0: Fe310Uart,
     uart1: Fe310Uart,
     gpio: Gpio,
+    l2cache: L2Cache,
+    pwm0: Pwm,
+    pwm1: Pwm,
+    i2c: Fe310I2c,
+    /// SPI controller of the SD card slot
+    spi2: Fe310Spi,
+    gem: Gemgxl,
+    ddr: DdrController,
 }
 
 impl BusFu540 {
     pub fn new(console: Box<dyn Console>) -> Self {
+        let mut mrom = Memory::new(MROM_SIZE);
+        for (i, word) in ZSBL.iter().enumerate() {
+            mrom.write32(i as u64 * 4, *word);
+        }
         Self {
             clock: 0,
+            msel: ModeSelect::new(MSEL_ADDRESS_START, BOOT_TARGETS),
+            mrom,
             dtim: Memory::new(DTIM_SIZE),
+            l2lim: Memory::new(L2LIM_SIZE),
             flash: Memory::new(FLASH_SIZE),
             dram: Memory::new(DRAM_SIZE),
             timer: Box::new(Clint::new()),
@@ -77,6 +168,13 @@ impl BusFu540 {
             uart1: Fe310Uart::new(Box::new(TtyDummy::new())),
             prci: Prci::new(),
             gpio: Gpio::new(),
+            l2cache: L2Cache::new(),
+            pwm0: Pwm::new(16),
+            pwm1: Pwm::new(16),
+            i2c: Fe310I2c::new(),
+            spi2: Fe310Spi::new(1, false),
+            gem: Gemgxl::new(),
+            ddr: DdrController::new(),
         }
     }
 }
@@ -95,6 +193,80 @@ impl Bus for BusFu540 {
         self.uart0.get_console()
     }
 
+    fn attach_spi_device(
+        &mut self,
+        controller: usize,
+        cs: usize,
+        device: Box<dyn SpiDevice>,
+    ) -> Result<(), ()> {
+        match controller {
+            2 => self.spi2.attach_device(cs, device),
+            _ => Err(()),
+        }
+    }
+
+    fn attach_i2c_device(&mut self, device: Box<dyn I2cDevice>) -> Result<(), ()> {
+        self.i2c.attach_device(device)
+    }
+
+    fn attach_ethernet(&mut self, backend: Box<dyn NetBackend>) -> Result<(), ()> {
+        if self.gem.has_backend() {
+            return Err(());
+        }
+        self.gem.set_backend(backend);
+        Ok(())
+    }
+
+    fn get_gpio(&mut self) -> Option<&mut Gpio> {
+        Some(&mut self.gpio)
+    }
+
+    fn get_pwm(&mut self, index: usize) -> Option<&mut Pwm> {
+        match index {
+            0 => Some(&mut self.pwm0),
+            1 => Some(&mut self.pwm1),
+            _ => None,
+        }
+    }
+
+    fn set_mode_select(&mut self, msel: u32) -> Result<(), ()> {
+        self.msel.set_mode_select(msel);
+        Ok(())
+    }
+
+    fn get_reset_vector(&self) -> Option<u64> {
+        Some(MSEL_ADDRESS_START + MSEL_RESET_VECTOR)
+    }
+
+    fn get_mmio_device_name(&self, addr: u64) -> Option<&'static str> {
+        match addr {
+            TIMER_ADDRESS_START..=TIMER_ADDRESS_END => Some("clint"),
+            INTC_ADDRESS_START..=INTC_ADDRESS_END => Some("plic"),
+            PRCI_ADDRESS_START..=PRCI_ADDRESS_END => Some("prci"),
+            GPIO_ADDRESS_START..=GPIO_ADDRESS_END => Some("gpio"),
+            UART0_ADDRESS_START..=UART0_ADDRESS_END => Some("uart0"),
+            UART1_ADDRESS_START..=UART1_ADDRESS_END => Some("uart1"),
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => Some("l2cache"),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => Some("pwm0"),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => Some("pwm1"),
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => Some("i2c"),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => Some("spi2"),
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => Some("gem"),
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => Some("gemgxl-mgmt"),
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => Some("ddr"),
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => Some("ddr-blocker"),
+            _ => None,
+        }
+    }
+
+    fn is_ram(&self, addr: u64) -> bool {
+        match addr {
+            DTIM_ADDRESS_START..=DTIM_ADDRESS_END => true,
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => true,
+            _ => addr.wrapping_sub(DRAM_ADDRESS_START) < DRAM_SIZE as u64,
+        }
+    }
+
     fn tick(&mut self) -> Vec<bool> {
         self.clock = self.clock.wrapping_add(1);
 
@@ -103,17 +275,67 @@ impl Bus for BusFu540 {
         self.gpio.tick();
         self.uart0.tick();
         self.uart1.tick();
+        self.pwm0.tick();
+        self.pwm1.tick();
+        self.i2c.tick();
+        self.spi2.tick();
+        self.gem
+            .tick(&mut GuestMemory::new(&mut self.dram, DRAM_ADDRESS_START));
 
         let mut interrupts: Vec<usize> = Vec::new();
+        for (i, irq) in self.l2cache.get_irqs().iter().enumerate() {
+            if *irq {
+                interrupts.push(1 + i); // Interrupt IDs for L2 cache
+            }
+        }
         if self.uart0.is_irq() {
             interrupts.push(3); // Interrupt ID for UART0
         }
         if self.uart1.is_irq() {
             interrupts.push(4); // Interrupt ID for UART1
         }
+        let gpio_irqs = self.gpio.get_irqs();
+        for pin in 0..16 {
+            if (gpio_irqs >> pin) & 1 != 0 {
+                interrupts.push(7 + pin); // Interrupt IDs for GPIO
+            }
+        }
+        if self.spi2.is_irq() {
+            interrupts.push(6); // Interrupt ID for QSPI2
+        }
+        for i in 0..4 {
+            if self.pwm0.is_irq(i) {
+                interrupts.push(42 + i); // Interrupt IDs for PWM0
+            }
+            if self.pwm1.is_irq(i) {
+                interrupts.push(46 + i); // Interrupt IDs for PWM1
+            }
+        }
+        if self.i2c.is_irq() {
+            interrupts.push(50); // Interrupt ID for I2C
+        }
+        if self.gem.is_irq() {
+            interrupts.push(53); // Interrupt ID for GEMGXL
+        }
         self.intc.tick(0, interrupts)
     }
 
+    fn reset(&mut self) {
+        self.timer = Box::new(Clint::new());
+        self.intc = Box::new(Plic::new());
+        self.prci = Prci::new();
+        self.gpio.reset();
+        self.uart0.reset();
+        self.uart1.reset();
+        self.l2cache = L2Cache::new();
+        self.pwm0 = Pwm::new(16);
+        self.pwm1 = Pwm::new(16);
+        self.i2c.reset();
+        self.spi2.reset();
+        self.gem.reset();
+        self.ddr = DdrController::new();
+    }
+
     fn is_pending_software_interrupt(&mut self, core: usize) -> bool {
         self.timer.is_pending_software_interrupt(core)
     }
@@ -142,9 +364,27 @@ impl Bus for BusFu540 {
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => panic!("Unexpected size access."),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => panic!("Unexpected size access."),
             UART1_ADDRESS_START..=UART1_ADDRESS_END => panic!("Unexpected size access."),
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => panic!("Unexpected size access."),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => panic!("Unexpected size access."),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => panic!("Unexpected size access."),
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => panic!("Unexpected size access."),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => panic!("Unexpected size access."),
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => panic!("Unexpected size access."),
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => panic!("Unexpected size access."),
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => panic!("Unexpected size access."),
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => panic!("Unexpected size access."),
             SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
                 Ok(self.flash.read8(addr - SPIFLASH_ADDRESS_START))
             }
+            MSEL_ADDRESS_START..=MSEL_ADDRESS_END => {
+                Ok(self.msel.read8(addr - MSEL_ADDRESS_START))
+            }
+            MROM_ADDRESS_START..=MROM_ADDRESS_END => {
+                Ok(self.mrom.read8(addr - MROM_ADDRESS_START))
+            }
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => {
+                Ok(self.l2lim.read8(addr - L2LIM_ADDRESS_START))
+            }
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => Ok(self.dtim.read8(addr - DTIM_ADDRESS_START)),
             _ => Err(()),
         }
@@ -161,9 +401,27 @@ impl Bus for BusFu540 {
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => panic!("Unexpected size access."),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => panic!("Unexpected size access."),
             UART1_ADDRESS_START..=UART1_ADDRESS_END => panic!("Unexpected size access."),
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => panic!("Unexpected size access."),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => panic!("Unexpected size access."),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => panic!("Unexpected size access."),
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => panic!("Unexpected size access."),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => panic!("Unexpected size access."),
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => panic!("Unexpected size access."),
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => panic!("Unexpected size access."),
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => panic!("Unexpected size access."),
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => panic!("Unexpected size access."),
             SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
                 Ok(self.flash.read16(addr - SPIFLASH_ADDRESS_START))
             }
+            MSEL_ADDRESS_START..=MSEL_ADDRESS_END => {
+                Ok(self.msel.read16(addr - MSEL_ADDRESS_START))
+            }
+            MROM_ADDRESS_START..=MROM_ADDRESS_END => {
+                Ok(self.mrom.read16(addr - MROM_ADDRESS_START))
+            }
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => {
+                Ok(self.l2lim.read16(addr - L2LIM_ADDRESS_START))
+            }
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.read16(addr - DTIM_ADDRESS_START))
             }
@@ -188,9 +446,33 @@ impl Bus for BusFu540 {
             UART1_ADDRESS_START..=UART1_ADDRESS_END => {
                 Ok(self.uart1.read(addr - UART1_ADDRESS_START))
             }
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => {
+                Ok(self.l2cache.read(addr - L2CACHE_ADDRESS_START))
+            }
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => Ok(self.pwm0.read(addr - PWM0_ADDRESS_START)),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => Ok(self.pwm1.read(addr - PWM1_ADDRESS_START)),
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => Ok(self.i2c.read(addr - I2C_ADDRESS_START)),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => Ok(self.spi2.read(addr - SPI2_ADDRESS_START)),
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => Ok(self.gem.read(addr - GEM_ADDRESS_START)),
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => {
+                Ok(self.gem.read_mgmt(addr - GEMMGMT_ADDRESS_START))
+            }
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => Ok(self.ddr.read(addr - DDR_ADDRESS_START)),
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => {
+                Ok(self.ddr.read_blocker(addr - DDRBLOCKER_ADDRESS_START))
+            }
             SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
                 Ok(self.flash.read32(addr - SPIFLASH_ADDRESS_START))
             }
+            MSEL_ADDRESS_START..=MSEL_ADDRESS_END => {
+                Ok(self.msel.read32(addr - MSEL_ADDRESS_START))
+            }
+            MROM_ADDRESS_START..=MROM_ADDRESS_END => {
+                Ok(self.mrom.read32(addr - MROM_ADDRESS_START))
+            }
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => {
+                Ok(self.l2lim.read32(addr - L2LIM_ADDRESS_START))
+            }
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.read32(addr - DTIM_ADDRESS_START))
             }
@@ -239,9 +521,72 @@ impl Bus for BusFu540 {
                     | ((self.uart1.read(uart1_addr.wrapping_add(4)) as u64) << 32);
                 Ok(data)
             }
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => {
+                let l2cache_addr = addr - L2CACHE_ADDRESS_START;
+                let data = self.l2cache.read(l2cache_addr) as u64
+                    | ((self.l2cache.read(l2cache_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => {
+                let pwm0_addr = addr - PWM0_ADDRESS_START;
+                let data = self.pwm0.read(pwm0_addr) as u64
+                    | ((self.pwm0.read(pwm0_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => {
+                let pwm1_addr = addr - PWM1_ADDRESS_START;
+                let data = self.pwm1.read(pwm1_addr) as u64
+                    | ((self.pwm1.read(pwm1_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => {
+                let i2c_addr = addr - I2C_ADDRESS_START;
+                let data = self.i2c.read(i2c_addr) as u64
+                    | ((self.i2c.read(i2c_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => {
+                let spi2_addr = addr - SPI2_ADDRESS_START;
+                let data = self.spi2.read(spi2_addr) as u64
+                    | ((self.spi2.read(spi2_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => {
+                let gem_addr = addr - GEM_ADDRESS_START;
+                let data = self.gem.read(gem_addr) as u64
+                    | ((self.gem.read(gem_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => {
+                let gemmgmt_addr = addr - GEMMGMT_ADDRESS_START;
+                let data = self.gem.read_mgmt(gemmgmt_addr) as u64
+                    | ((self.gem.read_mgmt(gemmgmt_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => {
+                let ddr_addr = addr - DDR_ADDRESS_START;
+                let data = self.ddr.read(ddr_addr) as u64
+                    | ((self.ddr.read(ddr_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => {
+                let ddrblocker_addr = addr - DDRBLOCKER_ADDRESS_START;
+                let data = self.ddr.read_blocker(ddrblocker_addr) as u64
+                    | ((self.ddr.read_blocker(ddrblocker_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
             SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
                 Ok(self.flash.read64(addr - SPIFLASH_ADDRESS_START))
             }
+            MSEL_ADDRESS_START..=MSEL_ADDRESS_END => {
+                Ok(self.msel.read64(addr - MSEL_ADDRESS_START))
+            }
+            MROM_ADDRESS_START..=MROM_ADDRESS_END => {
+                Ok(self.mrom.read64(addr - MROM_ADDRESS_START))
+            }
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => {
+                Ok(self.l2lim.read64(addr - L2LIM_ADDRESS_START))
+            }
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.read64(addr - DTIM_ADDRESS_START))
             }
@@ -260,9 +605,21 @@ impl Bus for BusFu540 {
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => panic!("Unexpected size access."),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => panic!("Unexpected size access."),
             UART1_ADDRESS_START..=UART1_ADDRESS_END => panic!("Unexpected size access."),
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => panic!("Unexpected size access."),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => panic!("Unexpected size access."),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => panic!("Unexpected size access."),
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => panic!("Unexpected size access."),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => panic!("Unexpected size access."),
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => panic!("Unexpected size access."),
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => panic!("Unexpected size access."),
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => panic!("Unexpected size access."),
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => panic!("Unexpected size access."),
             SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
                 Ok(self.flash.write8(addr - SPIFLASH_ADDRESS_START, data))
             }
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => {
+                Ok(self.l2lim.write8(addr - L2LIM_ADDRESS_START, data))
+            }
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.write8(addr - DTIM_ADDRESS_START, data))
             }
@@ -281,9 +638,21 @@ impl Bus for BusFu540 {
             GPIO_ADDRESS_START..=GPIO_ADDRESS_END => panic!("Unexpected size access."),
             UART0_ADDRESS_START..=UART0_ADDRESS_END => panic!("Unexpected size access."),
             UART1_ADDRESS_START..=UART1_ADDRESS_END => panic!("Unexpected size access."),
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => panic!("Unexpected size access."),
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => panic!("Unexpected size access."),
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => panic!("Unexpected size access."),
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => panic!("Unexpected size access."),
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => panic!("Unexpected size access."),
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => panic!("Unexpected size access."),
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => panic!("Unexpected size access."),
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => panic!("Unexpected size access."),
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => panic!("Unexpected size access."),
             SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
                 Ok(self.flash.write16(addr - SPIFLASH_ADDRESS_START, data))
             }
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => {
+                Ok(self.l2lim.write16(addr - L2LIM_ADDRESS_START, data))
+            }
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.write16(addr - DTIM_ADDRESS_START, data))
             }
@@ -314,9 +683,39 @@ impl Bus for BusFu540 {
             UART1_ADDRESS_START..=UART1_ADDRESS_END => {
                 Ok(self.uart1.write(addr - UART1_ADDRESS_START, data))
             }
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => {
+                Ok(self.l2cache.write(addr - L2CACHE_ADDRESS_START, data))
+            }
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => {
+                Ok(self.pwm0.write(addr - PWM0_ADDRESS_START, data))
+            }
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => {
+                Ok(self.pwm1.write(addr - PWM1_ADDRESS_START, data))
+            }
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => {
+                Ok(self.i2c.write(addr - I2C_ADDRESS_START, data))
+            }
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => {
+                Ok(self.spi2.write(addr - SPI2_ADDRESS_START, data))
+            }
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => {
+                Ok(self.gem.write(addr - GEM_ADDRESS_START, data))
+            }
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => {
+                Ok(self.gem.write_mgmt(addr - GEMMGMT_ADDRESS_START, data))
+            }
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => {
+                Ok(self.ddr.write(addr - DDR_ADDRESS_START, data))
+            }
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => Ok(self
+                .ddr
+                .write_blocker(addr - DDRBLOCKER_ADDRESS_START, data)),
             SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
                 Ok(self.flash.write32(addr - SPIFLASH_ADDRESS_START, data))
             }
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => {
+                Ok(self.l2lim.write32(addr - L2LIM_ADDRESS_START, data))
+            }
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.write32(addr - DTIM_ADDRESS_START, data))
             }
@@ -383,9 +782,93 @@ impl Bus for BusFu540 {
                 );
                 Ok(())
             }
+            L2CACHE_ADDRESS_START..=L2CACHE_ADDRESS_END => {
+                let l2cache_addr = addr - L2CACHE_ADDRESS_START;
+                self.l2cache.write(l2cache_addr, data as u32);
+                self.l2cache.write(
+                    l2cache_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            PWM0_ADDRESS_START..=PWM0_ADDRESS_END => {
+                let pwm0_addr = addr - PWM0_ADDRESS_START;
+                self.pwm0.write(pwm0_addr, data as u32);
+                self.pwm0.write(
+                    pwm0_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            PWM1_ADDRESS_START..=PWM1_ADDRESS_END => {
+                let pwm1_addr = addr - PWM1_ADDRESS_START;
+                self.pwm1.write(pwm1_addr, data as u32);
+                self.pwm1.write(
+                    pwm1_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            I2C_ADDRESS_START..=I2C_ADDRESS_END => {
+                let i2c_addr = addr - I2C_ADDRESS_START;
+                self.i2c.write(i2c_addr, data as u32);
+                self.i2c.write(
+                    i2c_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            SPI2_ADDRESS_START..=SPI2_ADDRESS_END => {
+                let spi2_addr = addr - SPI2_ADDRESS_START;
+                self.spi2.write(spi2_addr, data as u32);
+                self.spi2.write(
+                    spi2_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            GEM_ADDRESS_START..=GEM_ADDRESS_END => {
+                let gem_addr = addr - GEM_ADDRESS_START;
+                self.gem.write(gem_addr, data as u32);
+                self.gem.write(
+                    gem_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            GEMMGMT_ADDRESS_START..=GEMMGMT_ADDRESS_END => {
+                let gemmgmt_addr = addr - GEMMGMT_ADDRESS_START;
+                self.gem.write_mgmt(gemmgmt_addr, data as u32);
+                self.gem.write_mgmt(
+                    gemmgmt_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            DDR_ADDRESS_START..=DDR_ADDRESS_END => {
+                let ddr_addr = addr - DDR_ADDRESS_START;
+                self.ddr.write(ddr_addr, data as u32);
+                self.ddr.write(
+                    ddr_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            DDRBLOCKER_ADDRESS_START..=DDRBLOCKER_ADDRESS_END => {
+                let ddrblocker_addr = addr - DDRBLOCKER_ADDRESS_START;
+                self.ddr.write_blocker(ddrblocker_addr, data as u32);
+                self.ddr.write_blocker(
+                    ddrblocker_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
             SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => {
                 Ok(self.flash.write64(addr - SPIFLASH_ADDRESS_START, data))
             }
+            L2LIM_ADDRESS_START..=L2LIM_ADDRESS_END => {
+                Ok(self.l2lim.write64(addr - L2LIM_ADDRESS_START, data))
+            }
             DTIM_ADDRESS_START..=DTIM_ADDRESS_END => {
                 Ok(self.dtim.write64(addr - DTIM_ADDRESS_START, data))
             }
@@ -1,14 +1,24 @@
 // QEMU Virt Machine
 
+use crate::block::memory::MemoryDisk;
+use crate::block::BlockBackend;
 use crate::bus::bus::*;
 use crate::console::*;
+use crate::machine::ExitStatus;
+use crate::peripherals::framebuffer::{Framebuffer, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
 use crate::peripherals::fu540_c000::clint::Clint;
+use crate::peripherals::goldfish_rtc::{GoldfishRtc, HostClock, TimeSource};
 use crate::peripherals::fu540_c000::plic::Plic;
 use crate::peripherals::intc::Intc;
 use crate::peripherals::memory::Memory;
+use crate::peripherals::pci::host::PciHost;
+use crate::peripherals::pci::PciDevice;
+use crate::peripherals::sifive_test::SifiveTest;
 use crate::peripherals::timer::Timer;
 use crate::peripherals::uart::Uart;
-use crate::peripherals::virtio::Virtio;
+use crate::peripherals::virtio::block::VirtioBlock;
+use crate::peripherals::virtio::mmio::VirtioMmio;
+use crate::peripherals::virtio::VirtioDevice;
 
 const DTB_ADDRESS_START: u64 = 0x0000_1020;
 const DTB_ADDRESS_END: u64 = 0x0000_1FFF;
@@ -16,6 +26,12 @@ const DTB_ADDRESS_END: u64 = 0x0000_1FFF;
 const MROM_ADDRESS_START: u64 = 0x0000_1000;
 const MROM_ADDRESS_END: u64 = 0x0000_FFFF;
 
+const TEST_ADDRESS_START: u64 = 0x0010_0000;
+const TEST_ADDRESS_END: u64 = 0x0010_0FFF;
+
+const RTC_ADDRESS_START: u64 = 0x0010_1000;
+const RTC_ADDRESS_END: u64 = 0x0010_1FFF;
+
 const TIMER_ADDRESS_START: u64 = 0x0200_0000;
 const TIMER_ADDRESS_END: u64 = 0x0200_FFFF;
 
@@ -26,7 +42,21 @@ const UART_ADDRESS_START: u64 = 0x1000_0000;
 const UART_ADDRESS_END: u64 = 0x1000_0FFF;
 
 const VIRTIO_ADDRESS_START: u64 = 0x1000_1000;
-const VIRTIO_ADDRESS_END: u64 = 0x1000_1FFF;
+const VIRTIO_ADDRESS_END: u64 = 0x1000_8FFF;
+const VIRTIO_SLOT_SIZE: u64 = 0x1000;
+const VIRTIO_SLOT_COUNT: usize = 8;
+
+const FRAMEBUFFER_ADDRESS_START: u64 = 0x2400_0000;
+const FRAMEBUFFER_ADDRESS_END: u64 = 0x24FF_FFFF;
+
+const PCIE_ECAM_ADDRESS_START: u64 = 0x3000_0000;
+const PCIE_ECAM_ADDRESS_END: u64 = 0x3FFF_FFFF;
+
+const PCIE_MMIO_ADDRESS_START: u64 = 0x4000_0000;
+const PCIE_MMIO_ADDRESS_END: u64 = 0x7FFF_FFFF;
+
+/// PLIC interrupt ID of INTA, followed by INTB to INTD.
+const PCIE_IRQ_BASE: usize = 32;
 
 const DRAM_ADDRESS_START: u64 = 0x8000_0000;
 
@@ -39,10 +69,15 @@ pub struct BusQemuVirt {
     dtb: Vec<u8>,
     mrom: Memory,
     dram: Memory,
+    test: SifiveTest,
+    rtc: GoldfishRtc,
     timer: Box<dyn Timer>,
     intc: Box<dyn Intc>,
     uart: Uart,
-    virtio: Virtio,
+    /// virtio-mmio slots, the first one is the disk.
+    virtio: Vec<VirtioMmio>,
+    pci: PciHost,
+    framebuffer: Framebuffer,
 }
 
 impl BusQemuVirt {
@@ -52,12 +87,29 @@ impl BusQemuVirt {
             dtb: vec![0; DTB_SIZE],
             mrom: Memory::new(MROM_SIZE),
             dram: Memory::new(DRAM_SIZE),
+            test: SifiveTest::new(),
+            rtc: GoldfishRtc::new(Box::new(HostClock::new())),
             timer: Box::new(Clint::new()),
             intc: Box::new(Plic::new()),
             uart: Uart::new(console),
-            virtio: Virtio::new(DRAM_ADDRESS_START),
+            virtio: (0..VIRTIO_SLOT_COUNT)
+                .map(|_| VirtioMmio::new(DRAM_ADDRESS_START))
+                .collect(),
+            pci: PciHost::new(
+                PCIE_MMIO_ADDRESS_START,
+                PCIE_MMIO_ADDRESS_END - PCIE_MMIO_ADDRESS_START + 1,
+            ),
+            framebuffer: Framebuffer::new(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT),
         }
     }
+
+    fn get_virtio(&mut self, addr: u64) -> (&mut VirtioMmio, u64) {
+        let offset = addr - VIRTIO_ADDRESS_START;
+        (
+            &mut self.virtio[(offset / VIRTIO_SLOT_SIZE) as usize],
+            offset % VIRTIO_SLOT_SIZE,
+        )
+    }
 }
 
 impl Bus for BusQemuVirt {
@@ -67,7 +119,8 @@ impl Bus for BusQemuVirt {
                 self.dram.initialize(data);
             }
             Device::Disk => {
-                self.virtio.init(data);
+                let disk = VirtioBlock::new(Box::new(MemoryDisk::new(data)));
+                self.virtio[0].set_device(Box::new(disk));
             }
             Device::DTB => {
                 self.dtb.splice(..data.len(), data.iter().cloned());
@@ -80,24 +133,105 @@ impl Bus for BusQemuVirt {
         self.uart.get_console()
     }    
 
+    fn set_disk(&mut self, disk: Box<dyn BlockBackend>) -> Result<(), ()> {
+        self.virtio[0].set_device(Box::new(VirtioBlock::new(disk)));
+        Ok(())
+    }
+
+    fn attach_virtio_device(&mut self, device: Box<dyn VirtioDevice>) -> Result<(), ()> {
+        // the first slot is reserved for the disk.
+        match self.virtio.iter_mut().skip(1).find(|slot| slot.is_empty()) {
+            Some(slot) => {
+                slot.set_device(device);
+                Ok(())
+            }
+            None => Err(()),
+        }
+    }
+
+    fn set_virtio_version(&mut self, version: u32) -> Result<(), ()> {
+        for slot in self.virtio.iter_mut() {
+            slot.set_version(version);
+        }
+        Ok(())
+    }
+
+    fn attach_pci_device(&mut self, device: Box<dyn PciDevice>) -> Result<(), ()> {
+        self.pci.attach(device).map(|_| ())
+    }
+
+    fn get_framebuffer(&mut self) -> Option<&mut Framebuffer> {
+        Some(&mut self.framebuffer)
+    }
+
+    fn set_time_source(&mut self, source: Box<dyn TimeSource>) -> Result<(), ()> {
+        self.rtc.set_time_source(source);
+        Ok(())
+    }
+
+    fn get_mmio_device_name(&self, addr: u64) -> Option<&'static str> {
+        match addr {
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => Some("test"),
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => Some("rtc"),
+            TIMER_ADDRESS_START..=TIMER_ADDRESS_END => Some("clint"),
+            INTC_ADDRESS_START..=INTC_ADDRESS_END => Some("plic"),
+            UART_ADDRESS_START..=UART_ADDRESS_END => Some("uart"),
+            VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => Some("virtio"),
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => Some("framebuffer"),
+            PCIE_ECAM_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => Some("pci"),
+            _ => None,
+        }
+    }
+
+    fn is_ram(&self, addr: u64) -> bool {
+        addr.wrapping_sub(DRAM_ADDRESS_START) < DRAM_SIZE as u64
+    }
+
     fn tick(&mut self) -> Vec<bool> {
         self.clock = self.clock.wrapping_add(1);
 
-        self.virtio.tick(&mut self.dram);
+        for virtio in self.virtio.iter_mut() {
+            virtio.tick(&mut self.dram);
+        }
+        self.pci.tick(&mut self.dram);
         self.timer.tick();
         self.uart.tick();
+        self.rtc.tick();
 
         // https://github.com/mit-pdos/xv6-riscv/blob/riscv/kernel/memlayout.h
         let mut interrupts: Vec<usize> = Vec::new();
         if self.uart.is_irq() {
             interrupts.push(10); // Interrupt ID for UART0
         }
-        if self.virtio.is_irq() {
-            interrupts.push(1); // Interrupt ID for Virtio
+        if self.rtc.is_irq() {
+            interrupts.push(11); // Interrupt ID for RTC
+        }
+        for (i, virtio) in self.virtio.iter_mut().enumerate() {
+            if virtio.is_irq() {
+                interrupts.push(1 + i); // Interrupt ID for Virtio
+            }
+        }
+        for intx in self.pci.get_interrupts() {
+            interrupts.push(PCIE_IRQ_BASE + intx);
         }
         self.intc.tick(0, interrupts)
     }
 
+    fn reset(&mut self) {
+        self.timer = Box::new(Clint::new());
+        self.intc = Box::new(Plic::new());
+        self.uart.reset();
+        self.rtc.reset();
+        for virtio in self.virtio.iter_mut() {
+            virtio.reset();
+        }
+        self.pci.reset();
+    }
+
+    fn take_exit_request(&mut self) -> Option<ExitStatus> {
+        self.test.take_exit_request()
+    }
+
     fn is_pending_software_interrupt(&mut self, core: usize) -> bool {
         self.timer.is_pending_software_interrupt(core)
     }
@@ -123,14 +257,25 @@ impl Bus for BusQemuVirt {
                 Ok(self.dtb[(addr - DTB_ADDRESS_START) as usize])
             }
             MROM_ADDRESS_START..=MROM_ADDRESS_END => Ok(self.mrom.read8(addr - MROM_ADDRESS_START)),
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => panic!("Unexpected size access."),
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => panic!("Unexpected size access."),
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => panic!("Unexpected size access."),
             INTC_ADDRESS_START..=INTC_ADDRESS_END => panic!("Unexpected size access."),
             UART_ADDRESS_START..=UART_ADDRESS_END => Ok(self.uart.read(addr - UART_ADDRESS_START)),
             VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => {
-                let virtio_addr = (addr - VIRTIO_ADDRESS_START) & 0xffff_fffc;
-                let data = ((self.virtio.read(virtio_addr) >> 8 * (addr & 0x3)) & 0xff) as u8;
+                let (virtio, virtio_addr) = self.get_virtio(addr);
+                let data =
+                    ((virtio.read(virtio_addr & 0xffff_fffc) >> (8 * (addr & 0x3))) & 0xff) as u8;
                 Ok(data)
             }
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => self
+                .framebuffer
+                .read(addr - FRAMEBUFFER_ADDRESS_START, 1)
+                .map(|data| data as u8),
+            PCIE_ECAM_ADDRESS_START..=PCIE_ECAM_ADDRESS_END => {
+                Ok(self.pci.read_config(addr - PCIE_ECAM_ADDRESS_START, 1) as u8)
+            }
+            PCIE_MMIO_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => Ok(self.pci.read(addr, 1) as u8),
             _ => Err(()),
         }
     }
@@ -147,6 +292,8 @@ impl Bus for BusQemuVirt {
             MROM_ADDRESS_START..=MROM_ADDRESS_END => {
                 Ok(self.mrom.read16(addr - MROM_ADDRESS_START))
             }
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => panic!("Unexpected size access."),
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => panic!("Unexpected size access."),
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => panic!("Unexpected size access."),
             INTC_ADDRESS_START..=INTC_ADDRESS_END => panic!("Unexpected size access."),
             UART_ADDRESS_START..=UART_ADDRESS_END => {
@@ -156,6 +303,14 @@ impl Bus for BusQemuVirt {
                 Ok(data)
             }
             VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => panic!("Unexpected size access."),
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => self
+                .framebuffer
+                .read(addr - FRAMEBUFFER_ADDRESS_START, 2)
+                .map(|data| data as u16),
+            PCIE_ECAM_ADDRESS_START..=PCIE_ECAM_ADDRESS_END => {
+                Ok(self.pci.read_config(addr - PCIE_ECAM_ADDRESS_START, 2) as u16)
+            }
+            PCIE_MMIO_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => Ok(self.pci.read(addr, 2) as u16),
             _ => Err(()),
         }
     }
@@ -177,6 +332,8 @@ impl Bus for BusQemuVirt {
             MROM_ADDRESS_START..=MROM_ADDRESS_END => {
                 Ok(self.mrom.read32(addr - MROM_ADDRESS_START))
             }
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => Ok(self.test.read(addr - TEST_ADDRESS_START)),
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => Ok(self.rtc.read(addr - RTC_ADDRESS_START)),
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => {
                 Ok(self.timer.read(addr - TIMER_ADDRESS_START))
             }
@@ -190,8 +347,17 @@ impl Bus for BusQemuVirt {
                 Ok(data)
             }
             VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => {
-                Ok(self.virtio.read(addr - VIRTIO_ADDRESS_START))
+                let (virtio, virtio_addr) = self.get_virtio(addr);
+                Ok(virtio.read(virtio_addr))
+            }
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => self
+                .framebuffer
+                .read(addr - FRAMEBUFFER_ADDRESS_START, 4)
+                .map(|data| data as u32),
+            PCIE_ECAM_ADDRESS_START..=PCIE_ECAM_ADDRESS_END => {
+                Ok(self.pci.read_config(addr - PCIE_ECAM_ADDRESS_START, 4))
             }
+            PCIE_MMIO_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => Ok(self.pci.read(addr, 4) as u32),
             _ => Err(()),
         }
     }
@@ -221,6 +387,18 @@ impl Bus for BusQemuVirt {
             MROM_ADDRESS_START..=MROM_ADDRESS_END => {
                 Ok(self.mrom.read64(addr - MROM_ADDRESS_START))
             }
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => {
+                let test_addr = addr - TEST_ADDRESS_START;
+                let data = self.test.read(test_addr) as u64
+                    | ((self.test.read(test_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => {
+                let rtc_addr = addr - RTC_ADDRESS_START;
+                let data = self.rtc.read(rtc_addr) as u64
+                    | ((self.rtc.read(rtc_addr.wrapping_add(4)) as u64) << 32);
+                Ok(data)
+            }
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => {
                 let timer_addr = addr - TIMER_ADDRESS_START;
                 let data = self.timer.read(timer_addr) as u64
@@ -246,11 +424,21 @@ impl Bus for BusQemuVirt {
                 Ok(data)
             }
             VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => {
-                let virtio_addr = addr - VIRTIO_ADDRESS_START;
-                let data = self.virtio.read(virtio_addr) as u64
-                    | ((self.virtio.read(virtio_addr.wrapping_add(4)) as u64) << 32);
+                let (virtio, virtio_addr) = self.get_virtio(addr);
+                let data = virtio.read(virtio_addr) as u64
+                    | ((virtio.read(virtio_addr.wrapping_add(4)) as u64) << 32);
                 Ok(data)
             }
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => {
+                self.framebuffer.read(addr - FRAMEBUFFER_ADDRESS_START, 8)
+            }
+            PCIE_ECAM_ADDRESS_START..=PCIE_ECAM_ADDRESS_END => {
+                let offset = addr - PCIE_ECAM_ADDRESS_START;
+                let data = self.pci.read_config(offset, 4) as u64
+                    | ((self.pci.read_config(offset.wrapping_add(4), 4) as u64) << 32);
+                Ok(data)
+            }
+            PCIE_MMIO_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => Ok(self.pci.read(addr, 8)),
             _ => Err(()),
         }
     }
@@ -260,12 +448,30 @@ impl Bus for BusQemuVirt {
             return Ok(self.dram.write8(addr - DRAM_ADDRESS_START, data));
         }
         match addr {
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => panic!("Unexpected size access."),
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => panic!("Unexpected size access."),
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => panic!("Unexpected size access."),
             INTC_ADDRESS_START..=INTC_ADDRESS_END => panic!("Unexpected size access."),
             UART_ADDRESS_START..=UART_ADDRESS_END => {
                 Ok(self.uart.write(addr - UART_ADDRESS_START, data))
             }
-            VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => panic!("Unexpected size access."),
+            VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => {
+                let (virtio, virtio_addr) = self.get_virtio(addr);
+                virtio.write8(virtio_addr, data);
+                Ok(())
+            }
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => {
+                self.framebuffer
+                    .write(addr - FRAMEBUFFER_ADDRESS_START, data as u64, 1)
+            }
+            PCIE_ECAM_ADDRESS_START..=PCIE_ECAM_ADDRESS_END => {
+                self.pci.write_config(addr - PCIE_ECAM_ADDRESS_START, data as u32, 1);
+                Ok(())
+            }
+            PCIE_MMIO_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => {
+                self.pci.write(addr, data as u64, 1);
+                Ok(())
+            }
             _ => Err(()),
         }
     }
@@ -275,6 +481,8 @@ impl Bus for BusQemuVirt {
             return Ok(self.dram.write16(addr - DRAM_ADDRESS_START, data));
         }
         match addr {
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => panic!("Unexpected size access."),
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => panic!("Unexpected size access."),
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => panic!("Unexpected size access."),
             INTC_ADDRESS_START..=INTC_ADDRESS_END => panic!("Unexpected size access."),
             UART_ADDRESS_START..=UART_ADDRESS_END => {
@@ -285,6 +493,18 @@ impl Bus for BusQemuVirt {
                 Ok(())
             }
             VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => panic!("Unexpected size access."),
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => {
+                self.framebuffer
+                    .write(addr - FRAMEBUFFER_ADDRESS_START, data as u64, 2)
+            }
+            PCIE_ECAM_ADDRESS_START..=PCIE_ECAM_ADDRESS_END => {
+                self.pci.write_config(addr - PCIE_ECAM_ADDRESS_START, data as u32, 2);
+                Ok(())
+            }
+            PCIE_MMIO_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => {
+                self.pci.write(addr, data as u64, 2);
+                Ok(())
+            }
             _ => Err(()),
         }
     }
@@ -294,6 +514,12 @@ impl Bus for BusQemuVirt {
             return Ok(self.dram.write32(addr - DRAM_ADDRESS_START, data));
         }
         match addr {
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => {
+                Ok(self.test.write(addr - TEST_ADDRESS_START, data))
+            }
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => {
+                Ok(self.rtc.write(addr - RTC_ADDRESS_START, data))
+            }
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => {
                 Ok(self.timer.write(addr - TIMER_ADDRESS_START, data))
             }
@@ -312,7 +538,21 @@ impl Bus for BusQemuVirt {
                 Ok(())
             }
             VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => {
-                Ok(self.virtio.write(addr - VIRTIO_ADDRESS_START, data))
+                let (virtio, virtio_addr) = self.get_virtio(addr);
+                virtio.write(virtio_addr, data);
+                Ok(())
+            }
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => {
+                self.framebuffer
+                    .write(addr - FRAMEBUFFER_ADDRESS_START, data as u64, 4)
+            }
+            PCIE_ECAM_ADDRESS_START..=PCIE_ECAM_ADDRESS_END => {
+                self.pci.write_config(addr - PCIE_ECAM_ADDRESS_START, data, 4);
+                Ok(())
+            }
+            PCIE_MMIO_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => {
+                self.pci.write(addr, data as u64, 4);
+                Ok(())
             }
             _ => Err(()),
         }
@@ -323,6 +563,24 @@ impl Bus for BusQemuVirt {
             return Ok(self.dram.write64(addr - DRAM_ADDRESS_START, data));
         }
         match addr {
+            TEST_ADDRESS_START..=TEST_ADDRESS_END => {
+                let test_addr = addr - TEST_ADDRESS_START;
+                self.test.write(test_addr, data as u32);
+                self.test.write(
+                    test_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
+            RTC_ADDRESS_START..=RTC_ADDRESS_END => {
+                let rtc_addr = addr - RTC_ADDRESS_START;
+                self.rtc.write(rtc_addr, data as u32);
+                self.rtc.write(
+                    rtc_addr.wrapping_add(4),
+                    ((data >> 32) & 0xffffffff) as u32,
+                );
+                Ok(())
+            }
             TIMER_ADDRESS_START..=TIMER_ADDRESS_END => {
                 let timer_addr = addr - TIMER_ADDRESS_START;
                 self.timer.write(timer_addr, data as u32);
@@ -361,14 +619,29 @@ impl Bus for BusQemuVirt {
                 Ok(())
             }
             VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => {
-                let virtio_addr = addr - VIRTIO_ADDRESS_START;
-                self.virtio.write(virtio_addr, data as u32);
-                self.virtio.write(
+                let (virtio, virtio_addr) = self.get_virtio(addr);
+                virtio.write(virtio_addr, data as u32);
+                virtio.write(
                     virtio_addr.wrapping_add(4),
                     ((data >> 32) & 0xffffffff) as u32,
                 );
                 Ok(())
             }
+            FRAMEBUFFER_ADDRESS_START..=FRAMEBUFFER_ADDRESS_END => {
+                self.framebuffer
+                    .write(addr - FRAMEBUFFER_ADDRESS_START, data, 8)
+            }
+            PCIE_ECAM_ADDRESS_START..=PCIE_ECAM_ADDRESS_END => {
+                let offset = addr - PCIE_ECAM_ADDRESS_START;
+                self.pci.write_config(offset, data as u32, 4);
+                self.pci
+                    .write_config(offset.wrapping_add(4), (data >> 32) as u32, 4);
+                Ok(())
+            }
+            PCIE_MMIO_ADDRESS_START..=PCIE_MMIO_ADDRESS_END => {
+                self.pci.write(addr, data, 8);
+                Ok(())
+            }
             _ => Err(()),
         }
     }
@@ -0,0 +1,425 @@
+// Cache Hierarchy Simulator
+// Optional L1I/L1D/L2 model fed with the physical addresses of instruction
+// fetches, loads and stores. It only keeps tags, so it never changes the data
+// seen by the guest. Memory mapped I/O and page table walks bypass the caches.
+// The default configuration follows the U54 core complex of the FU540.
+// https://static.dev.sifive.com/FU540-C000-v1.0.pdf
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Replacement {
+    /// Evict the least recently used line.
+    Lru,
+    /// Evict the oldest filled line.
+    Fifo,
+    /// Evict a pseudo random line (deterministic between runs).
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WritePolicy {
+    /// Stores allocate a line and only write back dirty lines on eviction.
+    WriteBack,
+    /// Stores are forwarded to the next level and do not allocate on miss.
+    WriteThrough,
+}
+
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    /// total capacity in bytes.
+    pub size: u64,
+    /// number of ways per set.
+    pub ways: u64,
+    /// line size in bytes.
+    pub line_size: u64,
+    pub replacement: Replacement,
+    pub write_policy: WritePolicy,
+}
+
+impl CacheConfig {
+    pub fn new(size: u64, ways: u64, line_size: u64) -> Self {
+        CacheConfig {
+            size,
+            ways,
+            line_size,
+            replacement: Replacement::Lru,
+            write_policy: WritePolicy::WriteBack,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CacheHierarchyConfig {
+    /// instruction cache (None to bypass).
+    pub l1i: Option<CacheConfig>,
+    /// data cache (None to bypass).
+    pub l1d: Option<CacheConfig>,
+    /// unified second level cache (None to bypass).
+    pub l2: Option<CacheConfig>,
+    /// size in bytes of the PC regions accesses are attributed to.
+    pub region_size: u64,
+}
+
+impl CacheHierarchyConfig {
+    /// 32 KiB 8-way L1I and L1D, 2 MiB 16-way L2, 64 byte lines.
+    pub fn fu540() -> Self {
+        CacheHierarchyConfig {
+            l1i: Some(CacheConfig::new(32 * 1024, 8, 64)),
+            l1d: Some(CacheConfig::new(32 * 1024, 8, 64)),
+            l2: Some(CacheConfig::new(2 * 1024 * 1024, 16, 64)),
+            region_size: 4096,
+        }
+    }
+}
+
+impl Default for CacheHierarchyConfig {
+    fn default() -> Self {
+        Self::fu540()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub read_misses: u64,
+    pub writes: u64,
+    pub write_misses: u64,
+    /// dirty lines written to the next level.
+    pub writebacks: u64,
+}
+
+impl CacheStats {
+    pub fn get_accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn get_misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Line {
+    valid: bool,
+    dirty: bool,
+    tag: u64,
+    /// last use (LRU) or fill (FIFO) time.
+    stamp: u64,
+}
+
+pub struct Cache {
+    name: &'static str,
+    config: CacheConfig,
+    sets: Vec<Vec<Line>>,
+    line_shift: u32,
+    set_mask: u64,
+    clock: u64,
+    random: u64,
+    stats: CacheStats,
+    /// (accesses, misses) by PC region.
+    regions: HashMap<u64, (u64, u64)>,
+}
+
+/// Outcome of a single cache lookup.
+struct Access {
+    hit: bool,
+    /// line address to fetch from the next level.
+    fill: Option<u64>,
+    /// dirty line address to write to the next level.
+    writeback: Option<u64>,
+    /// write forwarded to the next level (write-through).
+    forward: bool,
+}
+
+impl Cache {
+    pub fn new(name: &'static str, config: CacheConfig) -> Self {
+        if !config.line_size.is_power_of_two() || config.ways == 0 {
+            panic!("{}: invalid cache geometry {:?}", name, config);
+        }
+        let sets = config.size / (config.ways * config.line_size);
+        if !sets.is_power_of_two() {
+            panic!(
+                "{}: number of sets must be a power of two: {:?}",
+                name, config
+            );
+        }
+        Cache {
+            name,
+            sets: vec![vec![Line::default(); config.ways as usize]; sets as usize],
+            line_shift: config.line_size.trailing_zeros(),
+            set_mask: sets - 1,
+            config,
+            clock: 0,
+            random: 0x2545_f491_4f6c_dd1d,
+            stats: CacheStats::default(),
+            regions: HashMap::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn get_config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    pub fn get_stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// (accesses, misses) by PC region base address.
+    pub fn get_regions(&self) -> &HashMap<u64, (u64, u64)> {
+        &self.regions
+    }
+
+    fn access(&mut self, addr: u64, is_write: bool, region: u64) -> Access {
+        self.clock += 1;
+        let line_addr = addr >> self.line_shift;
+        let set_index = (line_addr & self.set_mask) as usize;
+        let tag = line_addr >> self.set_mask.count_ones();
+        let write_back = self.config.write_policy == WritePolicy::WriteBack;
+
+        let mut result = Access {
+            hit: false,
+            fill: None,
+            writeback: None,
+            forward: is_write && !write_back,
+        };
+
+        let set = &mut self.sets[set_index];
+        if let Some(line) = set.iter_mut().find(|l| l.valid && l.tag == tag) {
+            result.hit = true;
+            if self.config.replacement == Replacement::Lru {
+                line.stamp = self.clock;
+            }
+            if is_write && write_back {
+                line.dirty = true;
+            }
+        } else if !is_write || write_back {
+            // allocate, preferring an invalid way.
+            let victim = match set.iter().position(|l| !l.valid) {
+                Some(way) => way,
+                None => match self.config.replacement {
+                    Replacement::Lru | Replacement::Fifo => {
+                        let mut way = 0;
+                        for (i, line) in set.iter().enumerate() {
+                            if line.stamp < set[way].stamp {
+                                way = i;
+                            }
+                        }
+                        way
+                    }
+                    Replacement::Random => {
+                        // xorshift64
+                        self.random ^= self.random << 13;
+                        self.random ^= self.random >> 7;
+                        self.random ^= self.random << 17;
+                        (self.random % self.config.ways) as usize
+                    }
+                },
+            };
+            let line = &mut set[victim];
+            if line.valid && line.dirty {
+                let victim_line = (line.tag << self.set_mask.count_ones()) | set_index as u64;
+                result.writeback = Some(victim_line << self.line_shift);
+                self.stats.writebacks += 1;
+            }
+            *line = Line {
+                valid: true,
+                dirty: is_write,
+                tag,
+                stamp: self.clock,
+            };
+            result.fill = Some(line_addr << self.line_shift);
+        }
+
+        match is_write {
+            true => {
+                self.stats.writes += 1;
+                if !result.hit {
+                    self.stats.write_misses += 1;
+                }
+            }
+            false => {
+                self.stats.reads += 1;
+                if !result.hit {
+                    self.stats.read_misses += 1;
+                }
+            }
+        }
+        let counts = self.regions.entry(region).or_insert((0, 0));
+        counts.0 += 1;
+        if !result.hit {
+            counts.1 += 1;
+        }
+        result
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheAccessType {
+    Fetch,
+    Load,
+    Store,
+}
+
+pub struct CacheHierarchy {
+    l1i: Option<Cache>,
+    l1d: Option<Cache>,
+    l2: Option<Cache>,
+    region_size: u64,
+    /// address of the instruction currently executed.
+    pc: u64,
+    /// accesses of the current instruction which missed L1.
+    l1_misses: u64,
+    /// accesses of the current instruction which missed L2.
+    l2_misses: u64,
+}
+
+impl CacheHierarchy {
+    pub fn new(config: CacheHierarchyConfig) -> Self {
+        CacheHierarchy {
+            l1i: config.l1i.map(|c| Cache::new("L1I", c)),
+            l1d: config.l1d.map(|c| Cache::new("L1D", c)),
+            l2: config.l2.map(|c| Cache::new("L2", c)),
+            region_size: config.region_size.max(1),
+            pc: 0,
+            l1_misses: 0,
+            l2_misses: 0,
+        }
+    }
+
+    /// Start accounting accesses to the instruction at the address.
+    pub fn set_pc(&mut self, pc: u64) {
+        self.pc = pc;
+        self.l1_misses = 0;
+        self.l2_misses = 0;
+    }
+
+    /// (L1 misses, L2 misses) of the current instruction.
+    pub fn get_instruction_misses(&self) -> (u64, u64) {
+        (self.l1_misses, self.l2_misses)
+    }
+
+    pub fn get_l1i(&self) -> Option<&Cache> {
+        self.l1i.as_ref()
+    }
+
+    pub fn get_l1d(&self) -> Option<&Cache> {
+        self.l1d.as_ref()
+    }
+
+    pub fn get_l2(&self) -> Option<&Cache> {
+        self.l2.as_ref()
+    }
+
+    /// Simulate an access to physical memory. Returns the first cache level
+    /// which hit (1 or 2) or 0 when the access went to memory.
+    pub fn access(&mut self, p_addr: u64, access_type: CacheAccessType) -> u8 {
+        let level = self.lookup(p_addr, access_type);
+        match level {
+            0 => {
+                self.l1_misses += 1;
+                self.l2_misses += 1;
+            }
+            2 => self.l1_misses += 1,
+            _ => {}
+        }
+        level
+    }
+
+    fn lookup(&mut self, p_addr: u64, access_type: CacheAccessType) -> u8 {
+        let region = self.pc - self.pc % self.region_size;
+        let is_write = access_type == CacheAccessType::Store;
+        let l1 = match access_type {
+            CacheAccessType::Fetch => &mut self.l1i,
+            _ => &mut self.l1d,
+        };
+        let (l1_hit, fill, writeback, forward) = match l1 {
+            Some(cache) => {
+                let result = cache.access(p_addr, is_write, region);
+                (result.hit, result.fill, result.writeback, result.forward)
+            }
+            None => (
+                false,
+                if is_write { None } else { Some(p_addr) },
+                None,
+                is_write,
+            ),
+        };
+
+        let mut level = match l1_hit {
+            true => 1,
+            false => 0,
+        };
+        let l2 = match &mut self.l2 {
+            Some(cache) => cache,
+            None => return level,
+        };
+        if let Some(addr) = writeback {
+            l2.access(addr, true, region);
+        }
+        if let Some(addr) = fill {
+            if l2.access(addr, false, region).hit {
+                level = 2;
+            }
+        }
+        if forward && l2.access(p_addr, true, region).hit && !l1_hit {
+            level = 2;
+        }
+        level
+    }
+
+    /// Print per level statistics and the PC regions with the most misses.
+    pub fn write_report(
+        &self,
+        max_regions: usize,
+        region_name: &dyn Fn(u64) -> String,
+        w: &mut dyn Write,
+    ) -> io::Result<()> {
+        writeln!(w, "==== Cache statistics ====")?;
+        let caches = [&self.l1i, &self.l1d, &self.l2];
+        for cache in caches.iter().filter_map(|c| c.as_ref()) {
+            let config = cache.get_config();
+            let stats = cache.get_stats();
+            writeln!(
+                w,
+                "{}: {} KiB, {}-way, {} B lines, {:?}, {:?}",
+                cache.get_name(),
+                config.size / 1024,
+                config.ways,
+                config.line_size,
+                config.replacement,
+                config.write_policy
+            )?;
+            writeln!(
+                w,
+                "  reads: {:>12} misses: {:>12}  writes: {:>12} misses: {:>12}  writebacks: {:>12}",
+                stats.reads, stats.read_misses, stats.writes, stats.write_misses, stats.writebacks
+            )?;
+            let miss_rate = match stats.get_accesses() {
+                0 => 0.0,
+                n => stats.get_misses() as f64 * 100.0 / n as f64,
+            };
+            writeln!(w, "  miss rate: {:.2}%", miss_rate)?;
+
+            let mut regions: Vec<(&u64, &(u64, u64))> = cache.get_regions().iter().collect();
+            regions.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(b.0)));
+            for (base, (accesses, misses)) in regions.iter().take(max_regions) {
+                let line = format!(
+                    "  {:016x} {:>12} accesses {:>12} misses  {}",
+                    base,
+                    accesses,
+                    misses,
+                    region_name(**base)
+                );
+                writeln!(w, "{}", line.trim_end())?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,7 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
 pub trait Console {
     fn putchar(&mut self, c: u8);
     fn getchar(&mut self) -> u8;
@@ -26,3 +30,76 @@ impl Console for TtyDummy {
         0
     }
 }
+
+/// Console which buffers the input and output, for hosts which drive the
+/// guest programmatically.
+pub struct TtyBuffer {
+    input: VecDeque<u8>,
+    output: VecDeque<u8>,
+}
+
+impl TtyBuffer {
+    pub fn new() -> Self {
+        TtyBuffer {
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+        }
+    }
+}
+
+impl Default for TtyBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console for TtyBuffer {
+    fn putchar(&mut self, c: u8) {
+        self.output.push_back(c);
+    }
+
+    fn getchar(&mut self) -> u8 {
+        self.input.pop_front().unwrap_or(0)
+    }
+
+    fn set_input(&mut self, c: u8) {
+        self.input.push_back(c);
+    }
+
+    fn get_output(&mut self) -> u8 {
+        self.output.pop_front().unwrap_or(0)
+    }
+}
+
+/// Handle to a console used by several devices (e.g. the UART and a virtio
+/// console port) or kept by the host to talk to a device.
+#[derive(Clone)]
+pub struct SharedConsole {
+    console: Rc<RefCell<Box<dyn Console>>>,
+}
+
+impl SharedConsole {
+    pub fn new(console: Box<dyn Console>) -> Self {
+        SharedConsole {
+            console: Rc::new(RefCell::new(console)),
+        }
+    }
+}
+
+impl Console for SharedConsole {
+    fn putchar(&mut self, c: u8) {
+        self.console.borrow_mut().putchar(c)
+    }
+
+    fn getchar(&mut self) -> u8 {
+        self.console.borrow_mut().getchar()
+    }
+
+    fn set_input(&mut self, c: u8) {
+        self.console.borrow_mut().set_input(c)
+    }
+
+    fn get_output(&mut self) -> u8 {
+        self.console.borrow_mut().get_output()
+    }
+}
@@ -5,7 +5,9 @@ use crate::cpu::cpu_instruction::{Opecode, OPECODES};
 use crate::cpu::cpu_instruction_comp::*;
 use crate::cpu::mmu::Mmu;
 use crate::cpu::trap::*;
-use crate::machine::Machine;
+use crate::machine::{ExitStatus, Machine};
+use crate::stats::CpuStats;
+use crate::timing::{RetiredInstruction, TimingModel};
 
 #[derive(Clone)]
 pub enum Xlen {
@@ -24,6 +26,8 @@ pub enum Privilege {
 pub struct Cpu {
     cycle: u64,
     pub pc: u64,
+    /// Address of the instruction retired by the last tick (None if nothing retired).
+    pub retired_pc: Option<u64>,
     pub wfi: bool,
     pub xlen: Xlen,
     pub privilege: Privilege,
@@ -31,6 +35,12 @@ pub struct Cpu {
     pub f: [f64; 32],
     pub csr: Csr,
     pub mmu: Mmu,
+    /// execution statistics (None when disabled).
+    pub stats: Option<CpuStats>,
+    /// cycle timing model (None for one cycle per instruction).
+    pub timing: Option<Box<dyn TimingModel>>,
+    /// the guest stopped or rebooted the machine (None while it runs).
+    pub exit_request: Option<ExitStatus>,
     testmode: bool,
 }
 
@@ -39,6 +49,7 @@ impl Cpu {
         let mut cpu = Cpu {
             cycle: 0,
             pc: 0,
+            retired_pc: None,
             wfi: false,
             xlen: Xlen::X64,
             privilege: Privilege::Machine,
@@ -46,6 +57,9 @@ impl Cpu {
             f: [0.0; 32],
             csr: Csr::new(),
             mmu: Mmu::new(Xlen::X64, machine_, console),
+            stats: None,
+            timing: None,
+            exit_request: None,
             testmode: testmode_,
         };
 
@@ -56,51 +70,120 @@ impl Cpu {
 
     pub fn reset(&mut self) {
         self.pc = 0;
+        self.retired_pc = None;
         self.cycle = 0;
         self.privilege = Privilege::Machine;
         self.wfi = false;
         self.xlen = Xlen::X64;
         self.x = [0; 32];
         self.f = [0.0; 32];
+        self.csr = Csr::new();
+        self.mmu.set_privilege(&self.privilege);
+        self.mmu.set_xlen(&self.xlen);
+        self.mmu.update_addressing_mode(0);
+        self.mmu.clear_address_reserve();
+        self.exit_request = None;
+        self.x[0xb] = self.mmu.get_bus().get_base_address(Device::DTB) as i64;
     }
 
     pub fn set_pc(&mut self, pc: u64) {
         self.pc = pc;
     }
 
+    pub fn get_cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// The guest stopped or rebooted the machine since the last call.
+    pub fn take_exit_request(&mut self) -> Option<ExitStatus> {
+        self.exit_request.take()
+    }
+
+    /// The hart waits for an interrupt while none is enabled, nothing can
+    /// wake it up.
+    pub fn is_halted(&mut self) -> bool {
+        self.wfi && self.csr.read_direct(CSR_MIE) == 0
+    }
+
     pub fn set_xlen(&mut self, xlen: Xlen) {
         self.xlen = xlen;
         self.mmu.set_xlen(&self.xlen);
+        self.csr.set_xlen(&self.xlen);
     }
 
     pub fn tick(&mut self) {
-        match self.check_interrupts() {
-            Some(interrupt) => self.interrupt_handler(interrupt),
-            None => {}
+        if let Some(stats) = &mut self.stats {
+            stats.privilege_ticks[self.privilege.clone() as usize] += 1;
+            if self.wfi {
+                stats.wfi_ticks += 1;
+            }
         }
 
+        // cycles taken by this tick (one per instruction without a timing model).
+        let mut cycles = 1;
+
+        if let Some(interrupt) = self.check_interrupts() {
+            self.interrupt_handler(interrupt);
+            cycles += self.trap_cycles();
+        }
+
+        self.retired_pc = None;
         if !self.wfi {
             let instruction_addr = self.pc;
             match self.tick_execute() {
-                Ok(()) => {}
-                Err(e) => self.catch_exception(e, instruction_addr),
+                Ok(instruction_cycles) => {
+                    self.retired_pc = Some(instruction_addr);
+                    cycles += instruction_cycles.saturating_sub(1);
+                }
+                Err(e) => {
+                    self.catch_exception(e, instruction_addr);
+                    cycles += self.trap_cycles();
+                }
             }
         }
 
-        // run peripherals.
+        // run peripherals once per cycle, an interrupt raised in any of them
+        // is kept even if it drops before the last one.
         let bus = self.mmu.get_bus();
-        let irqs = bus.tick();
+        let mut irqs = bus.tick();
+        for _ in 1..cycles {
+            for (irq, pending) in irqs.iter_mut().zip(bus.tick()) {
+                *irq |= pending;
+            }
+        }
 
         // handle interrupt.
         self.tick_interrupt(&irqs);
 
-        self.cycle = self.cycle.wrapping_add(1);
-        self.csr.write_direct(CSR_CYCLE, self.cycle);
-        self.csr.tick();
+        self.cycle = self.cycle.wrapping_add(cycles);
+        self.csr.tick_counters(cycles, self.retired_pc.is_some());
+        self.csr.count_event(
+            HPM_CLASS_MEMORY,
+            HPM_EVENT_ITLB_MISS,
+            self.mmu.take_itlb_misses(),
+        );
+        self.csr.count_event(
+            HPM_CLASS_MEMORY,
+            HPM_EVENT_DTLB_MISS,
+            self.mmu.take_dtlb_misses(),
+        );
+        self.csr.tick(cycles);
     }
 
-    fn tick_execute(&mut self) -> Result<(), Trap> {
+    /// Pipeline flush penalty of a taken trap.
+    fn trap_cycles(&mut self) -> u64 {
+        match &mut self.timing {
+            Some(timing) => timing.trap(),
+            None => 0,
+        }
+    }
+
+    /// Execute an instruction and return the number of cycles it took.
+    fn tick_execute(&mut self) -> Result<u64, Trap> {
         let instruction_addr = self.pc;
+        if let Some(cache) = &mut self.mmu.cache {
+            cache.set_pc(instruction_addr);
+        }
         let word = match self.fetch() {
             Ok(_word) => _word,
             Err(e) => return Err(e),
@@ -132,7 +215,20 @@ impl Cpu {
             debug_message += &format!("{}", dis);
             println!("{}", debug_message);
         }
-        match (instruction.operation)(self, instruction_addr, word) {
+        let operation = instruction.operation;
+
+        // count the instruction mix.
+        let mnemonic = instruction.mnemonic;
+        let length = self.pc.wrapping_sub(instruction_addr);
+        if let Some(stats) = &mut self.stats {
+            *stats.mnemonics.entry(mnemonic).or_insert(0) += 1;
+            match length {
+                0x2 => stats.compressed += 1,
+                _ => stats.full_width += 1,
+            }
+        }
+
+        match operation(self, instruction_addr, word) {
             Err(e) => return Err(e),
             _ => {}
         }
@@ -141,7 +237,24 @@ impl Cpu {
         // I don't care that x0 is always zero in each instruction implementation.
         self.x[0] = 0;
 
-        return Ok(());
+        self.csr
+            .count_event(HPM_CLASS_INSTRUCTION, instruction_commit_event(word), 1);
+
+        if let Some(timing) = &mut self.timing {
+            let (l1_misses, l2_misses) = match &self.mmu.cache {
+                Some(cache) => cache.get_instruction_misses(),
+                None => (0, 0),
+            };
+            return Ok(timing.retire(&RetiredInstruction {
+                pc: instruction_addr,
+                word,
+                length,
+                next_pc: self.pc,
+                l1_misses,
+                l2_misses,
+            }));
+        }
+        return Ok(1);
     }
 
     fn tick_interrupt(&mut self, irqs: &Vec<bool>) {
@@ -229,11 +342,24 @@ impl Cpu {
         }
 
         let trap_code = trap.exception as u8;
+        if let Some(stats) = &mut self.stats {
+            stats.exceptions[trap_code as usize] += 1;
+        }
+        self.csr
+            .count_event(HPM_CLASS_INSTRUCTION, HPM_EVENT_EXCEPTION_TAKEN, 1);
         let previous_privilege = self.privilege.clone();
         let next_privilege = self.get_next_privilege(trap_code, false);
         self.change_privilege(next_privilege);
         self.update_csr_trap_registers(addr, trap_code, trap.value, previous_privilege, false);
         self.pc = self.get_trap_next_pc();
+
+        // the trap handler is the faulting instruction: it traps forever.
+        if self.pc == addr && self.exit_request.is_none() {
+            self.exit_request = Some(ExitStatus::Fatal(format!(
+                "{:?} at {:x} traps to itself",
+                trap.exception, addr
+            )));
+        }
     }
 
     fn check_interrupts(&mut self) -> Option<Interrupt> {
@@ -296,6 +422,11 @@ impl Cpu {
         }
 
         let trap_code = interrupt as u8;
+        if let Some(stats) = &mut self.stats {
+            stats.interrupts[trap_code as usize] += 1;
+        }
+        self.csr
+            .count_event(HPM_CLASS_INSTRUCTION, HPM_EVENT_EXCEPTION_TAKEN, 1);
         let previous_privilege = self.privilege.clone();
         let next_privilege = self.get_next_privilege(trap_code, true);
 
@@ -553,3 +684,24 @@ impl Cpu {
         self.mmu.set_privilege(&self.privilege);
     }
 }
+
+/// Instruction commit event (mhpmevent class 0) of a retired instruction.
+fn instruction_commit_event(word: u32) -> u64 {
+    match word & 0x7f {
+        0x03 => HPM_EVENT_LOAD,
+        0x07 => HPM_EVENT_FP_LOAD,
+        0x23 => HPM_EVENT_STORE,
+        0x27 => HPM_EVENT_FP_STORE,
+        0x2f => HPM_EVENT_ATOMIC,
+        0x0f | 0x73 => HPM_EVENT_SYSTEM,
+        0x63 => HPM_EVENT_BRANCH,
+        0x6f => HPM_EVENT_JAL,
+        0x67 => HPM_EVENT_JALR,
+        0x33 | 0x3b if word >> 25 == 1 => match (word >> 12) & 0x7 {
+            0..=3 => HPM_EVENT_MULTIPLY,
+            _ => HPM_EVENT_DIVIDE,
+        },
+        0x13 | 0x1b | 0x33 | 0x3b | 0x17 | 0x37 => HPM_EVENT_INTEGER_ARITHMETIC,
+        _ => 0,
+    }
+}
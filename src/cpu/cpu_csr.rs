@@ -1,4 +1,4 @@
-use crate::cpu::cpu::Privilege;
+use crate::cpu::cpu::{Privilege, Xlen};
 use crate::cpu::trap::*;
 
 pub const CSR_USTATUS: u16 = 0x000;
@@ -18,15 +18,20 @@ pub const CSR_FCSR: u16 = 0x003;
 pub const CSR_CYCLE: u16 = 0xC00;
 pub const CSR_TIME: u16 = 0xC01;
 pub const CSR_INSTRET: u16 = 0xC02;
+pub const CSR_HPMCOUNTER3: u16 = 0xC03;
+pub const CSR_HPMCOUNTER31: u16 = 0xC1F;
 pub const CSR_CYCLEH: u16 = 0xC80;
 pub const CSR_TIMEH: u16 = 0xC81;
 pub const CSR_INSTRETH: u16 = 0xC82;
+pub const CSR_HPMCOUNTER3H: u16 = 0xC83;
+pub const CSR_HPMCOUNTER31H: u16 = 0xC9F;
 
 pub const CSR_SSTATUS: u16 = 0x100;
 pub const CSR_SEDELEG: u16 = 0x102;
 pub const CSR_SIDELEG: u16 = 0x103;
 pub const CSR_SIE: u16 = 0x104;
 pub const CSR_STVEC: u16 = 0x105;
+pub const CSR_SCOUNTEREN: u16 = 0x106;
 
 pub const CSR_SSCRATCH: u16 = 0x140;
 pub const CSR_SEPC: u16 = 0x141;
@@ -36,11 +41,17 @@ pub const CSR_SIP: u16 = 0x144;
 
 pub const CSR_SPTBR: u16 = 0x180;
 
+#[deprecated(note = "removed from the privileged specification, use CSR_CYCLE")]
 pub const CSR_SCYCLE: u16 = 0xD00;
+#[deprecated(note = "removed from the privileged specification, use CSR_TIME")]
 pub const CSR_STIME: u16 = 0xD01;
+#[deprecated(note = "removed from the privileged specification, use CSR_INSTRET")]
 pub const CSR_SINSTRET: u16 = 0xD02;
+#[deprecated(note = "removed from the privileged specification, use CSR_CYCLEH")]
 pub const CSR_SCYCLEH: u16 = 0xD80;
+#[deprecated(note = "removed from the privileged specification, use CSR_TIMEH")]
 pub const CSR_STIMEH: u16 = 0xD81;
+#[deprecated(note = "removed from the privileged specification, use CSR_INSTRETH")]
 pub const CSR_SINSTRETH: u16 = 0xD82;
 
 pub const CSR_HSTATUS: u16 = 0x200;
@@ -54,11 +65,17 @@ pub const CSR_HEPC: u16 = 0x241;
 pub const CSR_HCAUSE: u16 = 0x242;
 pub const CSR_HTVAL: u16 = 0x243;
 
+#[deprecated(note = "removed from the privileged specification, use CSR_CYCLE")]
 pub const CSR_HCYCLE: u16 = 0xE00;
+#[deprecated(note = "removed from the privileged specification, use CSR_TIME")]
 pub const CSR_HTIME: u16 = 0xE01;
+#[deprecated(note = "removed from the privileged specification, use CSR_INSTRET")]
 pub const CSR_HINSTRET: u16 = 0xE02;
+#[deprecated(note = "removed from the privileged specification, use CSR_CYCLEH")]
 pub const CSR_HCYCLEH: u16 = 0xE80;
+#[deprecated(note = "removed from the privileged specification, use CSR_TIMEH")]
 pub const CSR_HTIMEH: u16 = 0xE81;
+#[deprecated(note = "removed from the privileged specification, use CSR_INSTRETH")]
 pub const CSR_HINSTRETH: u16 = 0xE82;
 
 pub const CSR_MVENDORID: u16 = 0xF11;
@@ -72,6 +89,11 @@ pub const CSR_MEDELEG: u16 = 0x302;
 pub const CSR_MIDELEG: u16 = 0x303;
 pub const CSR_MIE: u16 = 0x304;
 pub const CSR_MTVEC: u16 = 0x305;
+pub const CSR_MCOUNTEREN: u16 = 0x306;
+
+pub const CSR_MCOUNTINHIBIT: u16 = 0x320;
+pub const CSR_MHPMEVENT3: u16 = 0x323;
+pub const CSR_MHPMEVENT31: u16 = 0x33F;
 
 pub const CSR_MSCRATCH: u16 = 0x340;
 pub const CSR_MEPC: u16 = 0x341;
@@ -86,15 +108,25 @@ pub const CSR_MIBOUND: u16 = 0x383;
 pub const CSR_MDBASE: u16 = 0x384;
 pub const CSR_MDBOUND: u16 = 0x385;
 
-pub const CSR_MCYCLE: u16 = 0xF00;
+pub const CSR_MCYCLE: u16 = 0xB00;
+pub const CSR_MINSTRET: u16 = 0xB02;
+pub const CSR_MHPMCOUNTER3: u16 = 0xB03;
+pub const CSR_MHPMCOUNTER31: u16 = 0xB1F;
+pub const CSR_MCYCLEH: u16 = 0xB80;
+pub const CSR_MINSTRETH: u16 = 0xB82;
+pub const CSR_MHPMCOUNTER3H: u16 = 0xB83;
+pub const CSR_MHPMCOUNTER31H: u16 = 0xB9F;
+
+#[deprecated(note = "removed from the privileged specification, the time is memory mapped")]
 pub const CSR_MTIME: u16 = 0xF01;
-pub const CSR_MINSTRET: u16 = 0xF02;
-pub const CSR_MCYCLEH: u16 = 0xF80;
+#[deprecated(note = "removed from the privileged specification, the time is memory mapped")]
 pub const CSR_MTIMEH: u16 = 0xF81;
-pub const CSR_MINSTRETH: u16 = 0xF82;
 
+#[deprecated(note = "replaced by CSR_MCOUNTEREN")]
 pub const CSR_MUCONTEREN: u16 = 0x310;
+#[deprecated(note = "replaced by CSR_MCOUNTEREN and CSR_SCOUNTEREN")]
 pub const CSR_MSCONTEREN: u16 = 0x311;
+#[deprecated(note = "removed from the privileged specification")]
 pub const CSR_MHCONTEREN: u16 = 0x312;
 
 // register bit files
@@ -141,15 +173,60 @@ pub const CSR_IE_SEIE: u64 = 0x00000200;
 pub const CSR_IE_HEIE: u64 = 0x00000400;
 pub const CSR_IE_MEIE: u64 = 0x00000800;
 
+// Hardware performance monitor events, SiFive mhpmevent encoding.
+// The event class is selected by bits [7:0] and events of the class are
+// selected by a mask in bits [63:8].
+// https://static.dev.sifive.com/FU540-C000-v1.0.pdf
+pub const HPM_CLASS_INSTRUCTION: u64 = 0;
+pub const HPM_CLASS_MEMORY: u64 = 2;
+
+// instruction commit events.
+/// exceptions and interrupts taken.
+pub const HPM_EVENT_EXCEPTION_TAKEN: u64 = 1 << 8;
+pub const HPM_EVENT_LOAD: u64 = 1 << 9;
+pub const HPM_EVENT_STORE: u64 = 1 << 10;
+pub const HPM_EVENT_ATOMIC: u64 = 1 << 11;
+pub const HPM_EVENT_SYSTEM: u64 = 1 << 12;
+pub const HPM_EVENT_INTEGER_ARITHMETIC: u64 = 1 << 13;
+pub const HPM_EVENT_BRANCH: u64 = 1 << 14;
+pub const HPM_EVENT_JAL: u64 = 1 << 15;
+pub const HPM_EVENT_JALR: u64 = 1 << 16;
+pub const HPM_EVENT_MULTIPLY: u64 = 1 << 17;
+pub const HPM_EVENT_DIVIDE: u64 = 1 << 18;
+pub const HPM_EVENT_FP_LOAD: u64 = 1 << 19;
+pub const HPM_EVENT_FP_STORE: u64 = 1 << 20;
+
+// memory system events.
+pub const HPM_EVENT_ITLB_MISS: u64 = 1 << 11;
+pub const HPM_EVENT_DTLB_MISS: u64 = 1 << 12;
+
+const HPM_CLASS_MASK: u64 = 0xff;
+
+// counter bits of mcounteren, scounteren and mcountinhibit.
+const COUNTER_CY: u32 = 1 << 0;
+const COUNTER_TM: u32 = 1 << 1;
+const COUNTER_IR: u32 = 1 << 2;
+
 pub struct Csr {
     csr: [u64; 4096],
+    /// counters written by the current instruction, which must not increment.
+    written_counters: u32,
+    /// any mhpmevent selects an event.
+    hpm_enabled: bool,
+    /// RV32 writes the counters by halves.
+    xlen: Xlen,
 }
 
 impl Csr {
     pub fn new() -> Self {
-        let mut csr = Csr { csr: [0; 4096] };
-
-        // this is actived when release mode for passing 
+        let mut csr = Csr {
+            csr: [0; 4096],
+            written_counters: 0,
+            hpm_enabled: false,
+            xlen: Xlen::X64,
+        };
+
+        // this is actived when release mode for passing
         // "rv32mi-p-csr" test scenario of riscv-tests.
         if cfg!(not(debug_assertions)) {
             csr.csr[CSR_MISA as usize] = 0x800000008014312f;
@@ -157,8 +234,57 @@ impl Csr {
         csr
     }
 
-    pub fn tick(&mut self) {
-        self.csr[CSR_TIME as usize] = self.csr[CSR_TIME as usize].wrapping_add(1);
+    pub fn set_xlen(&mut self, xlen: &Xlen) {
+        self.xlen = xlen.clone();
+    }
+
+    /// Advance time by the cycles the bus was clocked for.
+    pub fn tick(&mut self, cycles: u64) {
+        self.csr[CSR_TIME as usize] = self.csr[CSR_TIME as usize].wrapping_add(cycles);
+    }
+
+    /// Advance mcycle and minstret at the end of a CPU tick.
+    pub fn tick_counters(&mut self, cycles: u64, retired: bool) {
+        let enabled = !(self.csr[CSR_MCOUNTINHIBIT as usize] as u32 | self.written_counters);
+        if enabled & COUNTER_CY != 0 {
+            self.csr[CSR_MCYCLE as usize] = self.csr[CSR_MCYCLE as usize].wrapping_add(cycles);
+        }
+        if retired && enabled & COUNTER_IR != 0 {
+            self.csr[CSR_MINSTRET as usize] = self.csr[CSR_MINSTRET as usize].wrapping_add(1);
+        }
+        self.written_counters = 0;
+    }
+
+    /// Increment the mhpmcounters whose mhpmevent selects the event.
+    pub fn count_event(&mut self, class: u64, event: u64, n: u64) {
+        if !self.hpm_enabled || n == 0 {
+            return;
+        }
+        let enabled = !(self.csr[CSR_MCOUNTINHIBIT as usize] as u32 | self.written_counters);
+        for i in 3..32 {
+            let selector = self.csr[(CSR_MHPMEVENT3 + i - 3) as usize];
+            if enabled & (1 << i) != 0
+                && selector & HPM_CLASS_MASK == class
+                && selector & event != 0
+            {
+                let counter = (CSR_MHPMCOUNTER3 + i - 3) as usize;
+                self.csr[counter] = self.csr[counter].wrapping_add(n);
+            }
+        }
+    }
+
+    /// Whether the counter CSR (cycle, time, instret, hpmcounterN) is accessible
+    /// from the privilege mode according to mcounteren and scounteren.
+    fn is_counter_accessible(&self, addr: u16, cur_privilege: &Privilege) -> bool {
+        let bit = 1 << (addr & 0x1f);
+        match cur_privilege {
+            Privilege::Machine => true,
+            Privilege::User => {
+                self.csr[CSR_MCOUNTEREN as usize] & bit != 0
+                    && self.csr[CSR_SCOUNTEREN as usize] & bit != 0
+            }
+            _ => self.csr[CSR_MCOUNTEREN as usize] & bit != 0,
+        }
     }
 
     pub fn read(
@@ -169,7 +295,13 @@ impl Csr {
     ) -> Result<u64, Trap> {
         let privilege = ((addr >> 8) & 0x3) as u8;
         let cur_level = cur_privilege.clone() as u8;
-        match privilege <= cur_level {
+        let accessible = match addr {
+            CSR_CYCLE..=CSR_HPMCOUNTER31 | CSR_CYCLEH..=CSR_HPMCOUNTER31H => {
+                self.is_counter_accessible(addr, cur_privilege)
+            }
+            _ => true,
+        };
+        match privilege <= cur_level && accessible {
             true => Ok(self.read_direct(addr)),
             _ => Err(Trap {
                 exception: Exception::IllegalInstruction,
@@ -220,18 +352,16 @@ impl Csr {
                 self.csr[CSR_MIE as usize] & mask
             }
 
-            // timer
-            CSR_MCYCLE | CSR_MTIME | CSR_MINSTRET | CSR_MCYCLEH | CSR_MTIMEH | CSR_MINSTRETH => {
-                panic!("TODO: CSR MTimer")
-            }
-            CSR_HCYCLE | CSR_HTIME | CSR_HINSTRET | CSR_HCYCLEH | CSR_HTIMEH | CSR_HINSTRETH => {
-                panic!("TODO: CSR HTimer")
+            // counters
+            CSR_CYCLE | CSR_INSTRET | CSR_HPMCOUNTER3..=CSR_HPMCOUNTER31 => {
+                self.csr[(addr - CSR_CYCLE + CSR_MCYCLE) as usize]
             }
-            CSR_SCYCLE | CSR_STIME | CSR_SINSTRET | CSR_SCYCLEH | CSR_STIMEH | CSR_SINSTRETH => {
-                panic!("TODO: CSR STimer")
+            CSR_CYCLEH | CSR_INSTRETH | CSR_HPMCOUNTER3H..=CSR_HPMCOUNTER31H => {
+                self.csr[(addr - CSR_CYCLEH + CSR_MCYCLE) as usize] >> 32
             }
-            CSR_INSTRET | CSR_CYCLEH | CSR_TIMEH | CSR_INSTRETH => {
-                panic!("TODO: CSR Timer: {:x}", addr);
+            CSR_TIMEH => self.csr[CSR_TIME as usize] >> 32,
+            CSR_MCYCLEH | CSR_MINSTRETH | CSR_MHPMCOUNTER3H..=CSR_MHPMCOUNTER31H => {
+                self.csr[(addr - CSR_MCYCLEH + CSR_MCYCLE) as usize] >> 32
             }
 
             _ => self.csr[addr as usize],
@@ -252,7 +382,9 @@ impl Csr {
     ) -> Result<bool, Trap> {
         let privilege = ((addr >> 8) & 0x3) as u8;
         let cur_level = cur_privilege.clone() as u8;
-        match privilege <= cur_level {
+        // the top two address bits set indicate a read-only CSR.
+        let read_only = (addr >> 10) & 0x3 == 0x3;
+        match privilege <= cur_level && !read_only {
             true => {
                 self.write_direct(addr, data);
                 Ok(match addr {
@@ -316,17 +448,29 @@ impl Csr {
                 self.csr[CSR_MIE as usize] = (self.csr[CSR_MIE as usize] & !mask) | (data & mask);
             }
 
-            // timer
-            CSR_MCYCLE | CSR_MTIME | CSR_MINSTRET | CSR_MCYCLEH | CSR_MTIMEH | CSR_MINSTRETH => {
-                panic!("TODO: CSR MTimer")
+            // counters
+            CSR_MCYCLE | CSR_MINSTRET | CSR_MHPMCOUNTER3..=CSR_MHPMCOUNTER31 => {
+                let counter = addr as usize;
+                self.csr[counter] = match self.xlen {
+                    // the high half is written through the *H CSR.
+                    Xlen::X32 => (self.csr[counter] & !0xffffffff) | (data & 0xffffffff),
+                    Xlen::X64 => data,
+                };
+                self.written_counters |= 1 << (addr & 0x1f);
             }
-            CSR_HCYCLE | CSR_HTIME | CSR_HINSTRET | CSR_HCYCLEH | CSR_HTIMEH | CSR_HINSTRETH => {
-                panic!("TODO: CSR HTimer")
+            CSR_MCYCLEH | CSR_MINSTRETH | CSR_MHPMCOUNTER3H..=CSR_MHPMCOUNTER31H => {
+                let counter = (addr - CSR_MCYCLEH + CSR_MCYCLE) as usize;
+                self.csr[counter] = (self.csr[counter] & 0xffffffff) | (data << 32);
+                self.written_counters |= 1 << (addr & 0x1f);
             }
-            CSR_SCYCLE | CSR_STIME | CSR_SINSTRET | CSR_SCYCLEH | CSR_STIMEH | CSR_SINSTRETH => {
-                panic!("TODO: CSR STimer")
+            CSR_MCOUNTEREN | CSR_SCOUNTEREN => self.csr[addr as usize] = data & 0xffffffff,
+            // time is not inhibitable, so the TM bit is hardwired to zero.
+            CSR_MCOUNTINHIBIT => self.csr[addr as usize] = data & 0xffffffff & !(COUNTER_TM as u64),
+            CSR_MHPMEVENT3..=CSR_MHPMEVENT31 => {
+                self.csr[addr as usize] = data;
+                self.hpm_enabled =
+                    (CSR_MHPMEVENT3..=CSR_MHPMEVENT31).any(|event| self.csr[event as usize] != 0);
             }
-            CSR_INSTRET | CSR_CYCLEH | CSR_TIMEH | CSR_INSTRETH => panic!("TODO: CSR Timer"),
 
             _ => self.csr[addr as usize] = data,
         }
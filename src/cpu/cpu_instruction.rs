@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use crate::cpu::cpu::{Cpu, Privilege, Xlen};
 use crate::cpu::cpu_csr::*;
 use crate::cpu::trap::*;
+use crate::machine::ExitStatus;
 
 pub struct Opecode {
     pub operation: fn(cpu: &Cpu, addr: u64, word: u32) -> Result<&Instruction, ()>,
@@ -1737,6 +1738,11 @@ fn csrrs(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
         Ok(data) => data as i64,
         Err(e) => return Err(e),
     };
+    // CSRRS with rs1=x0 only reads the CSR (read-only CSRs must not trap).
+    if o.rs1 == 0 {
+        cpu.x[o.rd as usize] = signed(cpu, t);
+        return Ok(());
+    }
     let data = unsigned(cpu, t | cpu.x[o.rs1 as usize]);
     match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
         Ok(need_update_mmu_addressing_mode) => {
@@ -1757,6 +1763,11 @@ fn csrrsi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
         Ok(data) => data as i64,
         Err(e) => return Err(e),
     };
+    // CSRRSI with uimm=0 only reads the CSR (read-only CSRs must not trap).
+    if o.rs1 == 0 {
+        cpu.x[o.rd as usize] = signed(cpu, t);
+        return Ok(());
+    }
     let data = unsigned(cpu, t | o.rs1 as i64);
     match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
         Ok(need_update_mmu_addressing_mode) => {
@@ -1782,6 +1793,11 @@ fn csrrc(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
         Ok(data) => data as i64,
         Err(e) => return Err(e),
     };
+    // CSRRC with rs1=x0 only reads the CSR (read-only CSRs must not trap).
+    if o.rs1 == 0 {
+        cpu.x[o.rd as usize] = signed(cpu, t);
+        return Ok(());
+    }
     let data = (signed(cpu, t) & !cpu.x[o.rs1 as usize]) as u64;
     match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
         Ok(need_update_mmu_addressing_mode) => {
@@ -1802,6 +1818,11 @@ fn csrrci(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
         Ok(data) => data as i64,
         Err(e) => return Err(e),
     };
+    // CSRRCI with uimm=0 only reads the CSR (read-only CSRs must not trap).
+    if o.rs1 == 0 {
+        cpu.x[o.rd as usize] = signed(cpu, t);
+        return Ok(());
+    }
     let data = (signed(cpu, t) & !(o.rs1 as i64)) as u64;
     match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
         Ok(need_update_mmu_addressing_mode) => {
@@ -1818,8 +1839,45 @@ fn csrrci(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
 //==============================================================================
 // Environment Call and Breakpoints
 //==============================================================================
+// SBI system reset extension (SRST)
+// https://github.com/riscv-non-isa/riscv-sbi-doc
+const SBI_EXT_SRST: i64 = 0x5352_5354;
+const SBI_SRST_SYSTEM_RESET: i64 = 0;
+const SBI_SRST_TYPE_SHUTDOWN: u32 = 0;
+const SBI_SRST_TYPE_COLD_REBOOT: u32 = 1;
+const SBI_SRST_TYPE_WARM_REBOOT: u32 = 2;
+const SBI_SRST_REASON_SYSTEM_FAILURE: u32 = 1;
+const SBI_ERR_INVALID_PARAM: i64 = -3;
+
+/// The system reset call of the supervisor stops or reboots the machine
+/// without going through the firmware. An invalid call returns an error.
+fn sbi_system_reset(cpu: &mut Cpu) {
+    let reset_type = cpu.x[10] as u32;
+    let reason = cpu.x[11] as u32;
+    let status = match (reset_type, reason) {
+        (_, r) if r > SBI_SRST_REASON_SYSTEM_FAILURE && r < 0xf000_0000 => None,
+        (SBI_SRST_TYPE_SHUTDOWN, SBI_SRST_REASON_SYSTEM_FAILURE) => Some(ExitStatus::PowerOff(1)),
+        (SBI_SRST_TYPE_SHUTDOWN, _) => Some(ExitStatus::PowerOff(0)),
+        (SBI_SRST_TYPE_COLD_REBOOT, _) | (SBI_SRST_TYPE_WARM_REBOOT, _) => Some(ExitStatus::Reboot),
+        _ => None,
+    };
+    match status {
+        Some(status) => cpu.exit_request = Some(status),
+        None => {
+            cpu.x[10] = SBI_ERR_INVALID_PARAM;
+            cpu.x[11] = 0;
+        }
+    }
+}
+
 /// [ecall]
 fn ecall(cpu: &mut Cpu, addr: u64, _word: u32) -> Result<(), Trap> {
+    if let Privilege::Supervisor = cpu.privilege {
+        if cpu.x[17] == SBI_EXT_SRST && cpu.x[16] == SBI_SRST_SYSTEM_RESET {
+            sbi_system_reset(cpu);
+            return Ok(());
+        }
+    }
     Err(Trap {
         exception: match cpu.privilege {
             Privilege::User => Exception::EnvironmentCallFromUMode,
@@ -2,10 +2,12 @@ use crate::bus::bus::Bus;
 use crate::bus::bus_fe310::BusFe310;
 use crate::bus::bus_fu540::BusFu540;
 use crate::bus::bus_qemu_virt::BusQemuVirt;
+use crate::cache::{CacheAccessType, CacheHierarchy};
 use crate::console::Console;
 use crate::cpu::cpu::{Privilege, Xlen};
 use crate::cpu::trap::*;
 use crate::machine::Machine;
+use crate::stats::MemoryStats;
 use std::collections::HashMap;
 
 const PAGE_SIZE: u64 = 4096;
@@ -27,6 +29,14 @@ pub struct Mmu {
     addressing_mode: AddressingMode,
     privilege: Privilege,
     reserved_address: HashMap<u64, bool>,
+    /// memory system statistics (None when disabled).
+    pub stats: Option<MemoryStats>,
+    /// cache hierarchy model (None when disabled).
+    pub cache: Option<CacheHierarchy>,
+    /// page table walks for instruction fetch since the last take.
+    itlb_misses: u64,
+    /// page table walks for loads and stores since the last take.
+    dtlb_misses: u64,
 }
 
 struct Pte {
@@ -63,6 +73,10 @@ impl Mmu {
             addressing_mode: AddressingMode::Bare,
             privilege: Privilege::Machine,
             reserved_address: HashMap::new(),
+            stats: None,
+            cache: None,
+            itlb_misses: 0,
+            dtlb_misses: 0,
         }
     }
 
@@ -102,6 +116,11 @@ impl Mmu {
         };
     }
 
+    /// Drop the reservations of all the addresses.
+    pub fn clear_address_reserve(&mut self) {
+        self.reserved_address.clear();
+    }
+
     pub fn is_address_reserved(&mut self, addr: u64) -> bool {
         match self.reserved_address.get_mut(&addr) {
             Some(_v) => true,
@@ -240,6 +259,73 @@ impl Mmu {
         }
     }
 
+    /// Read a word for the profiler or a debugger without disturbing the
+    /// guest: the page table is walked without setting the A/D bits or
+    /// counting a TLB miss, the caches and the statistics are bypassed, and
+    /// only RAM is read (None otherwise).
+    pub fn debug_read32(&mut self, v_addr: u64) -> Option<u32> {
+        let p_addr = self.debug_translate(v_addr, 4)?;
+        self.bus.read32(p_addr).ok()
+    }
+
+    /// 64-bit version of `debug_read32`.
+    pub fn debug_read64(&mut self, v_addr: u64) -> Option<u64> {
+        let p_addr = self.debug_translate(v_addr, 8)?;
+        self.bus.read64(p_addr).ok()
+    }
+
+    fn debug_translate(&mut self, v_addr: u64, size: u64) -> Option<u64> {
+        let ev_addr = self.to_effective_address(v_addr);
+        if ev_addr & (PAGE_SIZE - 1) > PAGE_SIZE - size {
+            return None;
+        }
+        let p_addr = match (&self.addressing_mode, &self.privilege) {
+            (AddressingMode::Bare, _) | (_, Privilege::Machine) => ev_addr,
+            _ => self.debug_page_walk(ev_addr)?,
+        };
+        match self.bus.is_ram(p_addr) && self.bus.is_ram(p_addr + size - 1) {
+            true => Some(p_addr),
+            false => None,
+        }
+    }
+
+    /// Read only page table walk, for loads.
+    fn debug_page_walk(&mut self, v_addr: u64) -> Option<u64> {
+        let (levels, vpn_bits, pte_size) = match self.addressing_mode {
+            AddressingMode::Sv32 => (2, 10, 4),
+            AddressingMode::Sv39 => (3, 9, 8),
+            _ => return None,
+        };
+        let mut ppn = self.ppn;
+        for level in (0..levels).rev() {
+            let vpn = (v_addr >> (12 + level * vpn_bits)) & ((1 << vpn_bits) - 1);
+            let pte_addr = self.to_effective_address(ppn * PAGE_SIZE + vpn * pte_size);
+            if !self.bus.is_ram(pte_addr) {
+                return None;
+            }
+            let pte = match pte_size {
+                4 => self.bus.read32(pte_addr).ok()? as u64,
+                _ => self.bus.read64(pte_addr).ok()?,
+            };
+            let pte_d = self.parse_pte(pte);
+            if pte_d.v == 0 || (pte_d.r == 0 && pte_d.w == 1) {
+                return None;
+            }
+            if pte_d.r == 0 && pte_d.x == 0 {
+                ppn = pte_d.ppn;
+                continue;
+            }
+            // a superpage maps the lower VPNs through, it must be aligned.
+            let offset_mask = (1 << (12 + level * vpn_bits)) - 1;
+            let base = pte_d.ppn << 12;
+            if base & offset_mask != 0 {
+                return None;
+            }
+            return Some(base | (v_addr & offset_mask));
+        }
+        None
+    }
+
     pub fn write8(&mut self, v_addr: u64, val: u8) -> Result<(), Trap> {
         let ev_addr = self.to_effective_address(v_addr);
         match self.to_physical_address(ev_addr, MemoryAccessType::Write) {
@@ -410,10 +496,11 @@ impl Mmu {
         access_type: MemoryAccessType,
     ) -> Result<u64, ()> {
         //println!("AddressingMode = {:?}", self.addressing_mode);
-        match self.addressing_mode {
+        let result = match self.addressing_mode {
             AddressingMode::Bare => Ok(v_addr),
             AddressingMode::Sv32 => match self.privilege {
                 Privilege::User | Privilege::Supervisor => {
+                    self.count_page_walk(&access_type);
                     let vpns = [(v_addr >> 12) & 0x3ff, (v_addr >> 22) & 0x3ff];
                     self.page_waking(v_addr, 1, self.ppn, &vpns, &access_type)
                 }
@@ -421,6 +508,7 @@ impl Mmu {
             },
             AddressingMode::Sv39 => match self.privilege {
                 Privilege::User | Privilege::Supervisor => {
+                    self.count_page_walk(&access_type);
                     let vpns = [
                         (v_addr >> 12) & 0x1ff,
                         (v_addr >> 21) & 0x1ff,
@@ -439,6 +527,50 @@ impl Mmu {
             AddressingMode::Sv64 => {
                 panic!("AddressingMode SV64 is not implemented yet.");
             }
+        };
+
+        if let (Some(stats), Ok(p_addr)) = (&mut self.stats, &result) {
+            if let Some(device) = self.bus.get_mmio_device_name(*p_addr) {
+                stats.count_mmio(device, matches!(access_type, MemoryAccessType::Write));
+            }
+        }
+        if let (Some(cache), Ok(p_addr)) = (&mut self.cache, &result) {
+            if self.bus.get_mmio_device_name(*p_addr).is_none() {
+                cache.access(
+                    *p_addr,
+                    match access_type {
+                        MemoryAccessType::Fetch => CacheAccessType::Fetch,
+                        MemoryAccessType::Read => CacheAccessType::Load,
+                        MemoryAccessType::Write => CacheAccessType::Store,
+                    },
+                );
+            }
+        }
+        result
+    }
+
+    /// There is no TLB, so every translation walks the page table.
+    fn count_page_walk(&mut self, access_type: &MemoryAccessType) {
+        match access_type {
+            MemoryAccessType::Fetch => self.itlb_misses += 1,
+            _ => self.dtlb_misses += 1,
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.page_walks += 1;
+        }
+    }
+
+    pub fn take_itlb_misses(&mut self) -> u64 {
+        std::mem::replace(&mut self.itlb_misses, 0)
+    }
+
+    pub fn take_dtlb_misses(&mut self) -> u64 {
+        std::mem::replace(&mut self.dtlb_misses, 0)
+    }
+
+    fn count_pte_read(&mut self) {
+        if let Some(stats) = &mut self.stats {
+            stats.pte_reads += 1;
         }
     }
 
@@ -580,6 +712,7 @@ impl Mmu {
     }
 
     fn pte_read32(&mut self, addr: u64) -> u32 {
+        self.count_pte_read();
         let effective_addr = self.to_effective_address(addr);
         match self.bus.read32(effective_addr) {
             Ok(data) => data,
@@ -588,6 +721,7 @@ impl Mmu {
     }
 
     fn pte_read64(&mut self, addr: u64) -> u64 {
+        self.count_pte_read();
         let effective_addr = self.to_effective_address(addr);
         match self.bus.read64(effective_addr) {
             Ok(data) => data,
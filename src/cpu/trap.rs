@@ -4,7 +4,7 @@ pub struct Trap {
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub enum Exception {
     InstructionAddressMisaligned = 0,
     InstructionAccessFault = 1,
@@ -1,6 +1,11 @@
 const HEADER_MAGIC: u32 = 0x464c457f; // 0x7f 'E' 'L' 'F'
 const TOHOST: u64 = 0x0074736f686f742e; // .tohost
 
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+const SHN_LORESERVE: u16 = 0xff00;
+
 pub struct ElfHeader {
     pub e_indent: Ei,
     pub e_type: EType,
@@ -43,6 +48,12 @@ pub struct SectionHeader {
     pub sh_entsize: u64,
 }
 
+pub struct Symbol {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+}
+
 #[derive(Debug)]
 pub enum ShType {
     Null = 0x0,          // Section header table entry unused
@@ -466,6 +477,80 @@ impl ElfLoader {
         None
     }
 
+    /// get code symbols (functions and labels) from .symtab section.
+    pub fn get_symbols(
+        &self,
+        elf_header: &ElfHeader,
+        sec_headers: &Vec<SectionHeader>,
+    ) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        for symtab in sec_headers {
+            match symtab.sh_type {
+                ShType::Sysmtab => {}
+                _ => continue,
+            }
+            let strtab = match sec_headers.get(symtab.sh_link as usize) {
+                Some(sh) => sh,
+                None => continue,
+            };
+            let entsize = match elf_header.e_indent.ei_classs {
+                EiClass::Class32 => 16,
+                _ => 24,
+            };
+            for i in 0..(symtab.sh_size / entsize) {
+                let offset = (symtab.sh_offset + i * entsize) as usize;
+                /* Symbol entry
+                 * ---------------------------------------
+                 * ELF32: name, value, size, info, other, shndx
+                 * ELF64: name, info, other, shndx, value, size
+                 */
+                let (st_name, st_info, st_shndx, st_value, st_size) =
+                    match elf_header.e_indent.ei_classs {
+                        EiClass::Class32 => (
+                            self.read32(offset),
+                            self.read8(offset + 12),
+                            self.read16(offset + 14),
+                            self.read32(offset + 4) as u64,
+                            self.read32(offset + 8) as u64,
+                        ),
+                        _ => (
+                            self.read32(offset),
+                            self.read8(offset + 4),
+                            self.read16(offset + 6),
+                            self.read64(offset + 8),
+                            self.read64(offset + 16),
+                        ),
+                    };
+                let st_type = st_info & 0xf;
+                if (st_type != STT_FUNC && st_type != STT_NOTYPE)
+                    || st_shndx == SHN_UNDEF
+                    || st_shndx >= SHN_LORESERVE
+                {
+                    continue;
+                }
+                let name = self.read_string((strtab.sh_offset + st_name as u64) as usize);
+                // skip local assembler labels and mapping symbols.
+                if name.is_empty() || name.starts_with(".L") || name.starts_with('$') {
+                    continue;
+                }
+                symbols.push(Symbol {
+                    name,
+                    addr: st_value,
+                    size: st_size,
+                });
+            }
+        }
+        symbols
+    }
+
+    fn read_string(&self, offset: usize) -> String {
+        let mut end = offset;
+        while end < self.data.len() && self.data[end] != 0 {
+            end += 1;
+        }
+        String::from_utf8_lossy(&self.data[offset..end]).into_owned()
+    }
+
     pub fn read8(&self, offset: usize) -> u8 {
         self.data[offset]
     }
@@ -1,18 +1,59 @@
 use std::fs::File;
-use std::io::Read;
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
 use std::path::Path;
 
+use crate::block::BlockBackend;
 use crate::bus::bus::Device;
-use crate::console::Console;
+use crate::cache::{CacheHierarchy, CacheHierarchyConfig};
+use crate::console::{Console, SharedConsole, TtyDummy};
 use crate::cpu::cpu::{Cpu, Xlen};
 use crate::elf_loader::{EMachine, EiClass, ElfLoader, ShType};
-use crate::machine::Machine;
+use crate::machine::{ExitStatus, Machine};
+use crate::net::NetBackend;
+use crate::peripherals::fe310_g002::gpio::Gpio;
+use crate::peripherals::fe310_g002::pwm::Pwm;
+use crate::peripherals::framebuffer::Framebuffer;
+use crate::peripherals::goldfish_rtc::TimeSource;
+use crate::peripherals::i2c::I2cDevice;
+use crate::peripherals::pci::nvme::Nvme;
+use crate::peripherals::pci::PciDevice;
+use crate::peripherals::spi::sd_card::SdCard;
+use crate::peripherals::spi::SpiDevice;
+use crate::peripherals::virtio::balloon::{BalloonHandle, BalloonStats, VirtioBalloon};
+use crate::peripherals::virtio::block::VirtioBlock;
+use crate::peripherals::virtio::console::VirtioConsole;
+use crate::peripherals::virtio::input::{KeyboardHandle, VirtioInput};
+use crate::peripherals::virtio::net::VirtioNet;
+use crate::peripherals::virtio::pci::VirtioPci;
+use crate::peripherals::virtio::rng::{EntropySource, HostEntropy, SeededEntropy, VirtioRng};
+use crate::peripherals::virtio::VirtioDevice;
+use crate::profiler::{HotFunction, Profiler, ProfilerMode, SymbolTable};
+use crate::stats;
+use crate::stats::{CpuStats, MemoryStats};
+use crate::timing::TimingModel;
 
 pub struct Emulator {
     cpu: Cpu,
     machine: Machine,
     testmode: bool,
     tohost: u64,
+    symbols: SymbolTable,
+    profiler: Option<Profiler>,
+    balloon: Option<BalloonHandle>,
+    keyboard: Option<KeyboardHandle>,
+    /// address and XLEN the CPU starts with after a reset.
+    entry: u64,
+    xlen: Xlen,
+    /// why the machine stopped (None while it runs).
+    exit_status: Option<ExitStatus>,
+    /// the guest rebooted the machine since the last run.
+    rebooted: bool,
+    /// DRAM and DTB data and program segments, copied again on a reboot.
+    /// They are kept for the whole run, in addition to the memories.
+    images: Vec<(Device, Vec<u8>)>,
+    segments: Vec<(u64, Vec<u8>)>,
 }
 
 impl Emulator {
@@ -22,17 +63,52 @@ impl Emulator {
             machine: machine_,
             testmode: testmode_,
             tohost: 0,
+            symbols: SymbolTable::new(vec![]),
+            profiler: None,
+            balloon: None,
+            keyboard: None,
+            entry: 0,
+            xlen: Xlen::X64,
+            exit_status: None,
+            rebooted: false,
+            images: vec![],
+            segments: vec![],
         }
     }
 
+    /// Reset the machine: the CPU starts again from the entry of the program
+    /// and the devices out of the always-on domain are reset. The memories
+    /// are kept. A stopped machine runs again.
     pub fn reset(&mut self) {
-        self.cpu.reset()
+        self.exit_status = None;
+        self.cpu.reset();
+        self.cpu.set_xlen(self.xlen.clone());
+        self.cpu.set_pc(self.entry);
+        self.cpu.mmu.get_bus().reset();
     }
 
+    /// Set the address to start from, also after a reset.
     pub fn set_pc(&mut self, addr: u64) {
+        self.entry = addr;
         self.cpu.set_pc(addr)
     }
 
+    /// Boot through the mask ROM with the mode select pins in this state
+    /// instead of from the entry of a program, also after a reset (Err if
+    /// the machine has no boot ROM).
+    pub fn set_mode_select(&mut self, msel: u32) -> Result<(), ()> {
+        let bus = self.cpu.mmu.get_bus();
+        let reset_vector = bus.get_reset_vector().ok_or(())?;
+        bus.set_mode_select(msel)?;
+        self.xlen = match self.machine {
+            Machine::SiFiveE => Xlen::X32,
+            _ => Xlen::X64,
+        };
+        self.cpu.set_xlen(self.xlen.clone());
+        self.set_pc(reset_vector);
+        Ok(())
+    }
+
     pub fn get_console(&mut self) -> &mut Box<dyn Console> {
         self.cpu.mmu.get_bus().get_console()
     }
@@ -45,21 +121,234 @@ impl Emulator {
                     Err(why) => panic!("Failed to read {}: {}", filename.display(), why),
                     _ => {}
                 };
-                let bus = self.cpu.mmu.get_bus();
-                bus.set_device_data(device, data);
+                self.set_data_from_binary(device, data);
             }
             Err(why) => panic!("Falied to open {}: {}", filename.display(), why),
         };
     }
 
     pub fn set_data_from_binary(&mut self, device: Device, data: Vec<u8>) {
+        // the volatile memories lose their data on a reboot.
+        if let Device::Dram | Device::DTB = device {
+            self.images.push((device.clone(), data.clone()));
+        }
         let bus = self.cpu.mmu.get_bus();
         bus.set_device_data(device, data);
     }
 
-    pub fn set_dram_data(&mut self, data: Vec<u8>) {
+    /// Set the boot disk of the machine.
+    pub fn set_disk(&mut self, disk: Box<dyn BlockBackend>) -> Result<(), ()> {
+        self.cpu.mmu.get_bus().set_disk(disk)
+    }
+
+    /// Keep the one-time programmable memory of the machine in the backend,
+    /// which holds the fuses programmed by the earlier runs (Err if the
+    /// machine has no OTP).
+    pub fn set_otp(&mut self, backend: Box<dyn BlockBackend>) -> Result<(), ()> {
+        self.cpu.mmu.get_bus().set_otp(backend)
+    }
+
+    /// Set the time source of the real-time clock, the wall clock of the host
+    /// by default. Returns Err if the machine has no real-time clock.
+    pub fn set_time_source(&mut self, source: Box<dyn TimeSource>) -> Result<(), ()> {
+        self.cpu.mmu.get_bus().set_time_source(source)
+    }
+
+    /// Add a disk in a free virtio slot.
+    pub fn attach_disk(&mut self, disk: Box<dyn BlockBackend>) -> Result<(), ()> {
+        self.attach_virtio_device(Box::new(VirtioBlock::new(disk)))
+    }
+
+    /// Plug a device into a free virtio slot of the machine.
+    pub fn attach_virtio_device(&mut self, device: Box<dyn VirtioDevice>) -> Result<(), ()> {
+        self.cpu.mmu.get_bus().attach_virtio_device(device)
+    }
+
+    /// Plug a device into a free slot of the PCI bus of the machine.
+    pub fn attach_pci_device(&mut self, device: Box<dyn PciDevice>) -> Result<(), ()> {
+        self.cpu.mmu.get_bus().attach_pci_device(device)
+    }
+
+    /// Plug a virtio device into the PCI bus, with the virtio-pci transport.
+    pub fn attach_virtio_pci_device(&mut self, device: Box<dyn VirtioDevice>) -> Result<(), ()> {
+        let dram_base = self.cpu.mmu.get_bus().get_base_address(Device::Dram);
+        self.attach_pci_device(Box::new(VirtioPci::new(dram_base, device)))
+    }
+
+    /// Add a virtio-pci disk.
+    pub fn attach_pci_disk(&mut self, disk: Box<dyn BlockBackend>) -> Result<(), ()> {
+        self.attach_virtio_pci_device(Box::new(VirtioBlock::new(disk)))
+    }
+
+    /// Add an NVMe disk on the PCI bus.
+    pub fn attach_nvme(&mut self, disk: Box<dyn BlockBackend>) -> Result<(), ()> {
+        let dram_base = self.cpu.mmu.get_bus().get_base_address(Device::Dram);
+        self.attach_pci_device(Box::new(Nvme::new(dram_base, disk)))
+    }
+
+    /// Plug a device on a chip select line of a SPI controller of the
+    /// machine.
+    pub fn attach_spi_device(
+        &mut self,
+        controller: usize,
+        cs: usize,
+        device: Box<dyn SpiDevice>,
+    ) -> Result<(), ()> {
+        self.cpu
+            .mmu
+            .get_bus()
+            .attach_spi_device(controller, cs, device)
+    }
+
+    /// Add an SD card on the SPI controller the SiFive boards wire to the
+    /// card slot (SPI1 on FE310, SPI2 on FU540).
+    pub fn attach_sd_card(&mut self, disk: Box<dyn BlockBackend>) -> Result<(), ()> {
+        let controller = match self.machine {
+            Machine::SiFiveE => 1,
+            Machine::SiFiveU => 2,
+            Machine::QemuVirt => return Err(()),
+        };
+        self.attach_spi_device(controller, 0, Box::new(SdCard::new(disk)))
+    }
+
+    /// Plug a device on the I2C bus of the machine (Err if it has none or
+    /// the address is already used).
+    pub fn attach_i2c_device(&mut self, device: Box<dyn I2cDevice>) -> Result<(), ()> {
+        self.cpu.mmu.get_bus().attach_i2c_device(device)
+    }
+
+    /// The GPIO controller of the machine, to drive and observe its pins.
+    pub fn get_gpio(&mut self) -> Option<&mut Gpio> {
+        self.cpu.mmu.get_bus().get_gpio()
+    }
+
+    /// A PWM controller of the machine, None if it has no such controller.
+    pub fn get_pwm(&mut self, index: usize) -> Option<&mut Pwm> {
+        self.cpu.mmu.get_bus().get_pwm(index)
+    }
+
+    /// The linear framebuffer of the machine, None if it has no display.
+    pub fn get_framebuffer(&mut self) -> Option<&mut Framebuffer> {
+        self.cpu.mmu.get_bus().get_framebuffer()
+    }
+
+    /// Capture the framebuffer as a PNG image (Err if the machine has no
+    /// display).
+    pub fn screenshot(&mut self) -> Result<Vec<u8>, ()> {
+        match self.get_framebuffer() {
+            Some(framebuffer) => Ok(framebuffer.screenshot()),
+            None => Err(()),
+        }
+    }
+
+    /// Select the legacy (VIRTIO_MMIO_LEGACY) or the modern
+    /// (VIRTIO_MMIO_MODERN) virtio-mmio interface. Some drivers, such as
+    /// the Linux virtio-input driver, only work with the modern one.
+    pub fn set_virtio_version(&mut self, version: u32) -> Result<(), ()> {
+        self.cpu.mmu.get_bus().set_virtio_version(version)
+    }
+
+    /// Add a network interface connected to the backend: the GEMGXL
+    /// ethernet MAC of FU540, a virtio network interface otherwise.
+    pub fn attach_network(&mut self, backend: Box<dyn NetBackend>) -> Result<(), ()> {
+        match self.machine {
+            Machine::SiFiveU => self.cpu.mmu.get_bus().attach_ethernet(backend),
+            _ => self.attach_virtio_device(Box::new(VirtioNet::new(backend))),
+        }
+    }
+
+    /// Add a virtio console. Its console port shares the machine console with
+    /// the UART and the named ports are connected to the given consoles.
+    pub fn attach_virtio_console(
+        &mut self,
+        ports: Vec<(String, Box<dyn Console>)>,
+    ) -> Result<(), ()> {
         let bus = self.cpu.mmu.get_bus();
-        bus.set_device_data(Device::Dram, data);
+        let console = mem::replace(bus.get_console(), Box::new(TtyDummy::new()));
+        let shared = SharedConsole::new(console);
+        *bus.get_console() = Box::new(shared.clone());
+
+        let mut device = VirtioConsole::new(Box::new(shared));
+        for (name, console) in ports {
+            device.add_port(&name, console);
+        }
+        self.attach_virtio_device(Box::new(device))
+    }
+
+    /// Add a virtio entropy device. Random numbers are deterministic with a
+    /// seed and come from the host otherwise.
+    pub fn attach_virtio_rng(&mut self, seed: Option<u64>) -> Result<(), ()> {
+        let source: Box<dyn EntropySource> = match seed {
+            Some(seed) => Box::new(SeededEntropy::new(seed)),
+            None => match HostEntropy::new() {
+                Ok(source) => Box::new(source),
+                Err(_) => return Err(()),
+            },
+        };
+        self.attach_virtio_device(Box::new(VirtioRng::new(source)))
+    }
+
+    /// Add a virtio memory balloon.
+    pub fn attach_virtio_balloon(&mut self) -> Result<(), ()> {
+        let device = VirtioBalloon::new();
+        let handle = device.get_handle();
+        self.attach_virtio_device(Box::new(device))?;
+        self.balloon = Some(handle);
+        Ok(())
+    }
+
+    pub fn get_balloon(&self) -> Option<&BalloonHandle> {
+        self.balloon.as_ref()
+    }
+
+    /// Ask the guest to resize the balloon to the number of 4KiB pages.
+    pub fn set_balloon_target(&mut self, pages: u32) {
+        if let Some(balloon) = &self.balloon {
+            balloon.set_target(pages);
+        }
+    }
+
+    /// Ask the guest for new memory statistics.
+    pub fn request_balloon_stats(&mut self) {
+        if let Some(balloon) = &self.balloon {
+            balloon.request_stats();
+        }
+    }
+
+    /// The latest memory statistics reported by the guest.
+    pub fn get_balloon_stats(&self) -> Option<BalloonStats> {
+        match &self.balloon {
+            Some(balloon) => balloon.get_stats(),
+            None => None,
+        }
+    }
+
+    /// Add a virtio keyboard. If use_console is set, characters typed on the
+    /// machine console are sent as key strokes.
+    pub fn attach_virtio_keyboard(&mut self, use_console: bool) -> Result<(), ()> {
+        let console: Option<Box<dyn Console>> = match use_console {
+            true => {
+                let bus = self.cpu.mmu.get_bus();
+                let console = mem::replace(bus.get_console(), Box::new(TtyDummy::new()));
+                let shared = SharedConsole::new(console);
+                *bus.get_console() = Box::new(shared.clone());
+                Some(Box::new(shared))
+            }
+            false => None,
+        };
+        let device = VirtioInput::new_keyboard(console);
+        let handle = device.get_handle();
+        self.attach_virtio_device(Box::new(device))?;
+        self.keyboard = Some(handle);
+        Ok(())
+    }
+
+    pub fn get_keyboard(&self) -> Option<&KeyboardHandle> {
+        self.keyboard.as_ref()
+    }
+
+    pub fn set_dram_data(&mut self, data: Vec<u8>) {
+        self.set_data_from_binary(Device::Dram, data);
     }
 
     pub fn load_program_from_file(&mut self, filename: &Path) {
@@ -110,12 +399,13 @@ impl Emulator {
 
     fn load_program(&mut self, loader: ElfLoader) {
         let elf_header = loader.get_elf_header();
-        self.cpu.set_pc(elf_header.e_entry);
-        self.cpu.set_xlen(match elf_header.e_indent.ei_classs {
+        self.set_pc(elf_header.e_entry);
+        self.xlen = match elf_header.e_indent.ei_classs {
             EiClass::Class32 => Xlen::X32,
             EiClass::Class64 => Xlen::X64,
             _ => panic!("Unexpected class size: {:?}", elf_header.e_indent.ei_classs),
-        });
+        };
+        self.cpu.set_xlen(self.xlen.clone());
 
         let sec_headers = loader.get_section_header(&elf_header);
         let mut progbits_sec_headers = vec![];
@@ -154,15 +444,20 @@ impl Emulator {
                 }
             }
 
+            let mut segment = vec![];
             for j in 0..p_size {
                 let data = loader.read8((progbits_sec_headers[i].sh_offset + j) as usize);
                 match self.cpu.mmu.write8(p_addr + j as u64, data) {
                     Err(e) => panic!("{:?}", e.exception),
                     _ => {}
                 }
+                segment.push(data);
             }
+            self.segments.push((p_addr, segment));
         }
 
+        self.symbols = SymbolTable::new(loader.get_symbols(&elf_header, &sec_headers));
+
         if self.testmode {
             self.tohost = match loader.search_tohost(&progbits_sec_headers, &strtab_sec_headers) {
                 Some(addr) => addr,
@@ -171,9 +466,204 @@ impl Emulator {
         }
     }
 
+    /// Start profiling the guest. Any previously collected profile is discarded.
+    pub fn enable_profiler(&mut self, mode: ProfilerMode) {
+        self.profiler = Some(Profiler::new(mode));
+    }
+
+    pub fn disable_profiler(&mut self) {
+        self.profiler = None;
+    }
+
+    pub fn get_profiler(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    pub fn get_symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Write the collected profile as folded stacks for flamegraph tools.
+    pub fn write_profile_folded(&self, w: &mut dyn Write) -> io::Result<()> {
+        match &self.profiler {
+            Some(profiler) => profiler.write_folded(&self.symbols, w),
+            None => Ok(()),
+        }
+    }
+
+    /// Per-function hot list of the collected profile.
+    pub fn get_profile_hot_list(&self) -> Vec<HotFunction> {
+        match &self.profiler {
+            Some(profiler) => profiler.hot_list(&self.symbols),
+            None => vec![],
+        }
+    }
+
+    /// Print the hottest functions of the collected profile.
+    pub fn write_profile_hot_list(&self, max_entries: usize, w: &mut dyn Write) -> io::Result<()> {
+        match &self.profiler {
+            Some(profiler) => profiler.write_hot_list(&self.symbols, max_entries, w),
+            None => Ok(()),
+        }
+    }
+
+    /// Start collecting execution statistics. Any previous counters are discarded.
+    pub fn enable_stats(&mut self) {
+        self.cpu.stats = Some(CpuStats::new());
+        self.cpu.mmu.stats = Some(MemoryStats::new());
+    }
+
+    pub fn disable_stats(&mut self) {
+        self.cpu.stats = None;
+        self.cpu.mmu.stats = None;
+    }
+
+    pub fn get_cpu_stats(&self) -> Option<&CpuStats> {
+        self.cpu.stats.as_ref()
+    }
+
+    pub fn get_memory_stats(&self) -> Option<&MemoryStats> {
+        self.cpu.mmu.stats.as_ref()
+    }
+
+    /// Print the collected execution statistics.
+    pub fn write_stats_report(&self, w: &mut dyn Write) -> io::Result<()> {
+        match (&self.cpu.stats, &self.cpu.mmu.stats) {
+            (Some(cpu_stats), Some(memory_stats)) => {
+                stats::write_report(cpu_stats, memory_stats, w)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Start simulating the cache hierarchy. Any previous counters are discarded.
+    pub fn enable_cache(&mut self, config: CacheHierarchyConfig) {
+        self.cpu.mmu.cache = Some(CacheHierarchy::new(config));
+    }
+
+    pub fn disable_cache(&mut self) {
+        self.cpu.mmu.cache = None;
+    }
+
+    pub fn get_cache(&self) -> Option<&CacheHierarchy> {
+        self.cpu.mmu.cache.as_ref()
+    }
+
+    /// Print the cache statistics with the PC regions which miss the most.
+    pub fn write_cache_report(&self, max_regions: usize, w: &mut dyn Write) -> io::Result<()> {
+        match &self.cpu.mmu.cache {
+            Some(cache) => {
+                let region_name = |addr: u64| match self.symbols.lookup(addr) {
+                    Some(symbol) => symbol.name.clone(),
+                    None => String::new(),
+                };
+                cache.write_report(max_regions, &region_name, w)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Charge instructions the cycles computed by the timing model instead of
+    /// one cycle each. Peripherals are clocked once per cycle.
+    pub fn set_timing_model(&mut self, timing: Option<Box<dyn TimingModel>>) {
+        self.cpu.timing = timing;
+    }
+
+    /// Elapsed clock cycles.
+    pub fn get_cycle(&self) -> u64 {
+        self.cpu.get_cycle()
+    }
+
+    fn tick(&mut self) {
+        // a stopped machine runs again after a reset.
+        if self.exit_status.is_some() {
+            return;
+        }
+
+        let bus = self.cpu.mmu.get_bus();
+        if bus.is_sleeping() {
+            // the core is powered down, the devices keep running.
+            bus.tick();
+        } else {
+            self.cpu.tick();
+            if let Some(profiler) = &mut self.profiler {
+                profiler.tick(&mut self.cpu);
+            }
+        }
+
+        let bus = self.cpu.mmu.get_bus();
+        let request = match bus.take_exit_request() {
+            Some(status) => Some(status),
+            None => self.cpu.take_exit_request(),
+        };
+        let bus = self.cpu.mmu.get_bus();
+        let reset = bus.take_reset_request();
+        let sleeping = bus.is_sleeping();
+        match request {
+            Some(ExitStatus::Reboot) => {
+                self.reboot();
+                self.rebooted = true;
+            }
+            Some(status) => self.exit_status = Some(status),
+            None if reset => self.reboot(),
+            None if !sleeping && self.cpu.is_halted() => self.exit_status = Some(ExitStatus::Halt),
+            None => {}
+        }
+    }
+
+    /// Reset the machine and copy the data and the program loaded before it
+    /// started into the memories again, as the firmware of a rebooted board
+    /// finds them.
+    fn reboot(&mut self) {
+        self.reset();
+        let bus = self.cpu.mmu.get_bus();
+        for (device, data) in &self.images {
+            bus.set_device_data(device.clone(), data.clone());
+        }
+        // straight into the memories, the program was loaded there.
+        for (addr, segment) in &self.segments {
+            for (offset, data) in segment.iter().enumerate() {
+                let _ = bus.write8(addr + offset as u64, *data);
+            }
+        }
+    }
+
+    /// Run until the guest powers the machine off, reboots it, halts the
+    /// hart or can not make progress anymore. The machine is reset on a
+    /// reboot and runs again with the next call.
+    pub fn run_until_exit(&mut self) -> ExitStatus {
+        self.rebooted = false;
+        loop {
+            self.tick();
+            if self.rebooted {
+                self.rebooted = false;
+                return ExitStatus::Reboot;
+            }
+            if let Some(status) = &self.exit_status {
+                return status.clone();
+            }
+        }
+    }
+
+    /// Why the machine stopped, None while it runs.
+    pub fn get_exit_status(&self) -> Option<&ExitStatus> {
+        self.exit_status.as_ref()
+    }
+
+    /// Run until the test program reports its result in .tohost: Ok(1) when
+    /// it passed, Err with the code otherwise. A machine stopped by the guest
+    /// returns Ok(0) after a clean power-off, Err with the exit code or 1
+    /// otherwise.
     pub fn run(&mut self) -> Result<u32, u32> {
         loop {
-            self.cpu.tick();
+            self.tick();
+            if let Some(status) = &self.exit_status {
+                return match status {
+                    ExitStatus::PowerOff(0) => Ok(0),
+                    ExitStatus::PowerOff(code) => Err(*code),
+                    _ => Err(1),
+                };
+            }
             if self.testmode && self.tohost != 0 {
                 match self.cpu.mmu.read32_direct(self.tohost) {
                     Ok(data) => match data {
@@ -189,7 +679,7 @@ impl Emulator {
 
     pub fn run_steps(&mut self, steps: u32) {
         for _i in 0..steps {
-            self.cpu.tick();
+            self.tick();
         }
     }
 }
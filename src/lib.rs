@@ -1,10 +1,17 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod block;
 pub mod bus;
+pub mod cache;
 pub mod console;
 pub mod cpu;
 pub mod elf_loader;
 pub mod emulator;
 pub mod machine;
+pub mod net;
 pub mod peripherals;
+pub mod png;
+pub mod profiler;
+pub mod stats;
+pub mod timing;
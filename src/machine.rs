@@ -4,3 +4,16 @@ pub enum Machine {
     SiFiveU,
     QemuVirt,
 }
+
+/// Why the machine stopped running.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExitStatus {
+    /// The guest powered the machine off with this exit code.
+    PowerOff(u32),
+    /// The guest rebooted the machine, which was reset.
+    Reboot,
+    /// The hart waits for an interrupt while none is enabled.
+    Halt,
+    /// The guest can not make progress anymore.
+    Fatal(String),
+}
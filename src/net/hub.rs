@@ -0,0 +1,59 @@
+// UDP Hub Backend
+// Every frame is sent as a UDP datagram to all peers, and datagrams received
+// on the local socket are delivered to the guest. Two emulators on the same
+// host are connected by pointing each one at the local address of the other.
+
+use crate::net::NetBackend;
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+const MAX_FRAME_SIZE: usize = 65536;
+
+pub struct UdpHub {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+}
+
+impl UdpHub {
+    /// Bind the local address, e.g. "127.0.0.1:5555".
+    pub fn new<A: ToSocketAddrs>(local: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpHub {
+            socket,
+            peers: vec![],
+        })
+    }
+
+    pub fn add_peer<A: ToSocketAddrs>(&mut self, peer: A) -> io::Result<()> {
+        for addr in peer.to_socket_addrs()? {
+            self.peers.push(addr);
+        }
+        Ok(())
+    }
+
+    pub fn get_local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl NetBackend for UdpHub {
+    fn send(&mut self, frame: &[u8]) {
+        for peer in self.peers.iter() {
+            // like a real hub, frames to unreachable peers are lost.
+            let _ = self.socket.send_to(frame, peer);
+        }
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        let mut buf = vec![0; MAX_FRAME_SIZE];
+        match self.socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                buf.truncate(len);
+                Some(buf)
+            }
+            Err(_) => None,
+        }
+    }
+}
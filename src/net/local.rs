@@ -0,0 +1,68 @@
+// Local Packet Backend
+// The frames are exchanged with the host program through a handle: it
+// injects the frames received by the guest and takes the frames the guest
+// sent.
+
+use crate::net::NetBackend;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct LocalQueues {
+    /// frames to the guest
+    rx: VecDeque<Vec<u8>>,
+    /// frames from the guest
+    tx: VecDeque<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct LocalNet {
+    queues: Rc<RefCell<LocalQueues>>,
+}
+
+/// Host side of a LocalNet.
+#[derive(Clone)]
+pub struct LocalNetHandle {
+    queues: Rc<RefCell<LocalQueues>>,
+}
+
+impl LocalNet {
+    pub fn new() -> Self {
+        LocalNet::default()
+    }
+
+    pub fn get_handle(&self) -> LocalNetHandle {
+        LocalNetHandle {
+            queues: self.queues.clone(),
+        }
+    }
+}
+
+impl LocalNetHandle {
+    /// Queue a frame to be received by the guest.
+    pub fn inject(&self, frame: &[u8]) {
+        self.queues.borrow_mut().rx.push_back(frame.to_vec());
+    }
+
+    /// The frames sent by the guest since the last call.
+    pub fn take_frames(&self) -> Vec<Vec<u8>> {
+        self.queues.borrow_mut().tx.drain(..).collect()
+    }
+
+    /// Number of the injected frames the guest has not received yet.
+    pub fn get_pending(&self) -> usize {
+        self.queues.borrow().rx.len()
+    }
+}
+
+impl NetBackend for LocalNet {
+    fn send(&mut self, frame: &[u8]) {
+        self.queues.borrow_mut().tx.push_back(frame.to_vec());
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.queues.borrow_mut().rx.pop_front()
+    }
+}
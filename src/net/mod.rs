@@ -0,0 +1,15 @@
+// Network Backends
+// Host side of the emulated network interfaces. A backend exchanges raw
+// ethernet frames (without FCS) with the guest.
+
+pub mod hub;
+pub mod local;
+pub mod pcap;
+pub mod slirp;
+
+pub trait NetBackend {
+    /// A frame transmitted by the guest.
+    fn send(&mut self, frame: &[u8]);
+    /// The next frame to be received by the guest, if any.
+    fn recv(&mut self) -> Option<Vec<u8>>;
+}
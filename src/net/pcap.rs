@@ -0,0 +1,88 @@
+// Pcap Capture and Loopback Backend
+// https://wiki.wireshark.org/Development/LibpcapFileFormat
+
+use crate::net::NetBackend;
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 65535;
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Writes ethernet frames in the libpcap file format.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write the global header.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        writer.write_all(&PCAP_VERSION_MAJOR.to_le_bytes())?;
+        writer.write_all(&PCAP_VERSION_MINOR.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?; // thiszone
+        writer.write_all(&0u32.to_le_bytes())?; // sigfigs
+        writer.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+        Ok(PcapWriter { writer })
+    }
+
+    /// Write a packet record with the timestamp in microseconds.
+    pub fn write_packet(&mut self, timestamp: u64, frame: &[u8]) -> io::Result<()> {
+        let captured = frame.len().min(PCAP_SNAPLEN as usize);
+        self.writer
+            .write_all(&((timestamp / 1_000_000) as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&((timestamp % 1_000_000) as u32).to_le_bytes())?;
+        self.writer.write_all(&(captured as u32).to_le_bytes())?;
+        self.writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&frame[..captured])?;
+        self.writer.flush()
+    }
+
+    pub fn get_writer(&self) -> &W {
+        &self.writer
+    }
+}
+
+/// Returns every frame sent by the guest back to it and records them to a
+/// pcap capture.
+pub struct PcapLoopback<W: Write> {
+    capture: PcapWriter<W>,
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl<W: Write> PcapLoopback<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        Ok(PcapLoopback {
+            capture: PcapWriter::new(writer)?,
+            frames: VecDeque::new(),
+        })
+    }
+
+    pub fn get_capture(&self) -> &W {
+        self.capture.get_writer()
+    }
+}
+
+impl<W: Write> NetBackend for PcapLoopback<W> {
+    fn send(&mut self, frame: &[u8]) {
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_micros() as u64,
+            Err(_) => 0,
+        };
+        if let Err(why) = self.capture.write_packet(timestamp, frame) {
+            panic!("Failed to write pcap capture: {}", why);
+        }
+        self.frames.push_back(frame.to_vec());
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.frames.pop_front()
+    }
+}
@@ -0,0 +1,919 @@
+// User Mode Network Stack (slirp-like NAT)
+// The guest sees a virtual 10.0.2.0/24 network. Its UDP and TCP connections
+// are translated to host sockets, 10.0.2.2 being the host loopback, and
+// DHCP, DNS (forwarded to the host resolver), ARP and ping of the gateway are
+// answered locally.
+// https://wiki.qemu.org/Documentation/Networking#User_Networking_.28SLIRP.29
+// https://tools.ietf.org/html/rfc791 (IPv4)
+// https://tools.ietf.org/html/rfc768 (UDP)
+// https://tools.ietf.org/html/rfc793 (TCP)
+// https://tools.ietf.org/html/rfc2131 (DHCP)
+
+use crate::net::NetBackend;
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const GATEWAY_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+pub const DNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 3);
+pub const GUEST_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+pub const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+pub const GATEWAY_MAC: [u8; 6] = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+
+const CONFIG_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const CONFIG_DHCP_LEASE_TIME: u32 = 86400;
+const CONFIG_TCP_BUFFER_SIZE: usize = 0xffff;
+const CONFIG_TCP_MSS: usize = 1460;
+const CONFIG_TCP_RTO: Duration = Duration::from_secs(1);
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERNET_HEADER_SIZE: usize = 14;
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+
+const IP_PROTOCOL_ICMP: u8 = 1;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DHCP_MAGIC_COOKIE: u32 = 0x6382_5363;
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_OPTION_PAD: u8 = 0;
+const DHCP_OPTION_SUBNET_MASK: u8 = 1;
+const DHCP_OPTION_ROUTER: u8 = 3;
+const DHCP_OPTION_DNS: u8 = 6;
+const DHCP_OPTION_LEASE_TIME: u8 = 51;
+const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+const DHCP_OPTION_SERVER_ID: u8 = 54;
+const DHCP_OPTION_END: u8 = 255;
+
+const DNS_PORT: u16 = 53;
+
+struct UdpBinding {
+    /// guest side port.
+    port: u16,
+    /// whether the socket talks to the host resolver on behalf of DNS_ADDRESS.
+    dns: bool,
+    socket: UdpSocket,
+}
+
+#[derive(PartialEq)]
+enum TcpState {
+    Established,
+    /// the host closed the connection and our FIN was sent.
+    HostClosed,
+}
+
+/// A connection of the guest whose host socket is opened on a helper thread,
+/// so an unreachable host does not stall the emulator.
+struct TcpPending {
+    guest_port: u16,
+    remote_addr: Ipv4Addr,
+    remote_port: u16,
+    /// sequence number of the guest SYN.
+    seq: u32,
+    window: u32,
+    result: Receiver<io::Result<TcpStream>>,
+}
+
+struct TcpConnection {
+    guest_port: u16,
+    remote_addr: Ipv4Addr,
+    remote_port: u16,
+    stream: TcpStream,
+    state: TcpState,
+    /// the guest sent FIN.
+    guest_closed: bool,
+    /// next sequence number we send.
+    snd_nxt: u32,
+    /// oldest sequence number not acknowledged by the guest.
+    snd_una: u32,
+    /// next sequence number expected from the guest.
+    rcv_nxt: u32,
+    /// receive window advertised by the guest.
+    guest_window: u32,
+    /// data from the guest not yet accepted by the host socket.
+    to_host: Vec<u8>,
+    /// data sent to the guest from snd_una and not acknowledged yet.
+    to_guest: Vec<u8>,
+    /// the guest acknowledged our SYN.
+    syn_acked: bool,
+    /// when the oldest unacknowledged segment is sent again.
+    retransmit_at: Option<Instant>,
+    /// the window advertised to the guest was smaller than a segment.
+    window_closed: bool,
+}
+
+impl TcpConnection {
+    /// The receive window, the free space of the buffer to the host.
+    fn get_window(&self) -> usize {
+        CONFIG_TCP_BUFFER_SIZE - self.to_host.len()
+    }
+}
+
+pub struct Slirp {
+    guest_mac: [u8; 6],
+    dns_server: SocketAddr,
+    udp: Vec<UdpBinding>,
+    tcp: Vec<TcpConnection>,
+    tcp_pending: Vec<TcpPending>,
+    /// frames to the guest.
+    frames: VecDeque<Vec<u8>>,
+    ip_id: u16,
+    isn: u32,
+}
+
+impl Slirp {
+    pub fn new() -> Self {
+        Slirp {
+            guest_mac: BROADCAST_MAC,
+            dns_server: get_host_dns_server(),
+            udp: vec![],
+            tcp: vec![],
+            tcp_pending: vec![],
+            frames: VecDeque::new(),
+            ip_id: 0,
+            isn: 0x1000_0000,
+        }
+    }
+
+    /// Override the host resolver DNS queries are forwarded to.
+    pub fn set_dns_server(&mut self, addr: SocketAddr) {
+        self.dns_server = addr;
+        self.udp.retain(|b| !b.dns);
+    }
+
+    pub fn get_dns_server(&self) -> SocketAddr {
+        self.dns_server
+    }
+
+    fn handle_arp(&mut self, packet: &[u8]) {
+        /* ARP packet (ethernet, IPv4)
+         * ----------------
+         * u16 htype, u16 ptype, u8 hlen, u8 plen, u16 oper
+         * u8[6] sha, u8[4] spa, u8[6] tha, u8[4] tpa
+         */
+        if packet.len() < 28 || read16(packet, 6) != ARP_REQUEST {
+            return;
+        }
+        let target = Ipv4Addr::new(packet[24], packet[25], packet[26], packet[27]);
+        // everything on the virtual network except the guest is the gateway.
+        if !is_virtual_network(target) || target == GUEST_ADDRESS {
+            return;
+        }
+        let mut reply = packet[..28].to_vec();
+        reply[6..8].copy_from_slice(&ARP_REPLY.to_be_bytes());
+        reply[8..14].copy_from_slice(&GATEWAY_MAC);
+        reply[14..18].copy_from_slice(&target.octets());
+        reply[18..28].copy_from_slice(&packet[8..18]);
+        let frame = ethernet_frame(&self.guest_mac, &GATEWAY_MAC, ETHERTYPE_ARP, &reply);
+        self.frames.push_back(frame);
+    }
+
+    fn handle_ipv4(&mut self, packet: &[u8]) {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return;
+        }
+        let header_len = ((packet[0] & 0xf) as usize) * 4;
+        let total_len = (read16(packet, 2) as usize).min(packet.len());
+        if header_len < 20 || total_len < header_len {
+            return;
+        }
+        let src = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+        let dst = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+        let payload = &packet[header_len..total_len];
+        match packet[9] {
+            IP_PROTOCOL_ICMP => self.handle_icmp(src, dst, payload),
+            IP_PROTOCOL_UDP => self.handle_udp(src, dst, payload),
+            IP_PROTOCOL_TCP => self.handle_tcp(dst, payload),
+            _ => {}
+        }
+    }
+
+    fn handle_icmp(&mut self, src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) {
+        // only the virtual hosts answer to ping since raw sockets need privileges.
+        if payload.len() < 8 || payload[0] != ICMP_ECHO_REQUEST || !is_virtual_host(dst) {
+            return;
+        }
+        let mut reply = payload.to_vec();
+        reply[0] = ICMP_ECHO_REPLY;
+        reply[2] = 0;
+        reply[3] = 0;
+        let sum = checksum(&reply, 0);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        self.send_ipv4(dst, src, IP_PROTOCOL_ICMP, &reply);
+    }
+
+    fn handle_udp(&mut self, src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+        if segment.len() < 8 {
+            return;
+        }
+        let src_port = read16(segment, 0);
+        let dst_port = read16(segment, 2);
+        let len = (read16(segment, 4) as usize).clamp(8, segment.len());
+        let payload = &segment[8..len];
+
+        if dst_port == DHCP_SERVER_PORT {
+            self.handle_dhcp(payload);
+            return;
+        }
+        if src != GUEST_ADDRESS {
+            return;
+        }
+
+        let dns = dst == DNS_ADDRESS;
+        let target = match dns {
+            true if dst_port == DNS_PORT => self.dns_server,
+            true => return,
+            false => SocketAddr::V4(SocketAddrV4::new(to_host_address(dst), dst_port)),
+        };
+        let index = match self
+            .udp
+            .iter()
+            .position(|b| b.port == src_port && b.dns == dns)
+        {
+            Some(index) => index,
+            None => {
+                let local = match target {
+                    SocketAddr::V4(_) => "0.0.0.0:0",
+                    SocketAddr::V6(_) => "[::]:0",
+                };
+                let socket = match UdpSocket::bind(local) {
+                    Ok(socket) => socket,
+                    Err(_) => return,
+                };
+                if socket.set_nonblocking(true).is_err() {
+                    return;
+                }
+                self.udp.push(UdpBinding {
+                    port: src_port,
+                    dns,
+                    socket,
+                });
+                self.udp.len() - 1
+            }
+        };
+        let _ = self.udp[index].socket.send_to(payload, target);
+    }
+
+    fn handle_dhcp(&mut self, message: &[u8]) {
+        /* BOOTP message
+         * ----------------
+         * u8 op, u8 htype, u8 hlen, u8 hops, u32 xid, u16 secs, u16 flags
+         * u32 ciaddr, u32 yiaddr, u32 siaddr, u32 giaddr, u8[16] chaddr
+         * u8[64] sname, u8[128] file, u32 magic cookie, options
+         */
+        if message.len() < 240 || message[0] != 1 || read32(message, 236) != DHCP_MAGIC_COOKIE {
+            return;
+        }
+        let mut message_type = 0;
+        let mut i = 240;
+        while i < message.len() {
+            match message[i] {
+                DHCP_OPTION_PAD => i += 1,
+                DHCP_OPTION_END => break,
+                option => {
+                    if i + 1 >= message.len() {
+                        break;
+                    }
+                    let len = message[i + 1] as usize;
+                    if option == DHCP_OPTION_MESSAGE_TYPE && len == 1 && i + 2 < message.len() {
+                        message_type = message[i + 2];
+                    }
+                    i += 2 + len;
+                }
+            }
+        }
+        let reply_type = match message_type {
+            DHCP_DISCOVER => DHCP_OFFER,
+            DHCP_REQUEST => DHCP_ACK,
+            _ => return,
+        };
+
+        let mut reply = vec![0; 240];
+        reply[0] = 2; // BOOTREPLY
+        reply[1..3].copy_from_slice(&message[1..3]); // htype, hlen
+        reply[4..8].copy_from_slice(&message[4..8]); // xid
+        reply[10..12].copy_from_slice(&message[10..12]); // flags
+        reply[16..20].copy_from_slice(&GUEST_ADDRESS.octets()); // yiaddr
+        reply[20..24].copy_from_slice(&GATEWAY_ADDRESS.octets()); // siaddr
+        reply[28..44].copy_from_slice(&message[28..44]); // chaddr
+        reply[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE.to_be_bytes());
+        reply.extend_from_slice(&[DHCP_OPTION_MESSAGE_TYPE, 1, reply_type]);
+        reply.extend_from_slice(&[DHCP_OPTION_SERVER_ID, 4]);
+        reply.extend_from_slice(&GATEWAY_ADDRESS.octets());
+        reply.extend_from_slice(&[DHCP_OPTION_LEASE_TIME, 4]);
+        reply.extend_from_slice(&CONFIG_DHCP_LEASE_TIME.to_be_bytes());
+        reply.extend_from_slice(&[DHCP_OPTION_SUBNET_MASK, 4]);
+        reply.extend_from_slice(&NETMASK.octets());
+        reply.extend_from_slice(&[DHCP_OPTION_ROUTER, 4]);
+        reply.extend_from_slice(&GATEWAY_ADDRESS.octets());
+        reply.extend_from_slice(&[DHCP_OPTION_DNS, 4]);
+        reply.extend_from_slice(&DNS_ADDRESS.octets());
+        reply.push(DHCP_OPTION_END);
+
+        // the client has no address yet, so the reply is broadcast.
+        let segment = udp_segment(
+            GATEWAY_ADDRESS,
+            Ipv4Addr::BROADCAST,
+            DHCP_SERVER_PORT,
+            DHCP_CLIENT_PORT,
+            &reply,
+        );
+        self.send_ipv4(
+            GATEWAY_ADDRESS,
+            Ipv4Addr::BROADCAST,
+            IP_PROTOCOL_UDP,
+            &segment,
+        );
+    }
+
+    fn handle_tcp(&mut self, dst: Ipv4Addr, segment: &[u8]) {
+        if segment.len() < 20 {
+            return;
+        }
+        let src_port = read16(segment, 0);
+        let dst_port = read16(segment, 2);
+        let seq = read32(segment, 4);
+        let ack = read32(segment, 8);
+        let data_offset = ((segment[12] >> 4) as usize * 4).clamp(20, segment.len());
+        let flags = segment[13];
+        let window = read16(segment, 14) as u32;
+        let payload = &segment[data_offset..];
+
+        let index = self.tcp.iter().position(|c| {
+            c.guest_port == src_port && c.remote_addr == dst && c.remote_port == dst_port
+        });
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let pending = self.tcp_pending.iter().position(|p| {
+                    p.guest_port == src_port && p.remote_addr == dst && p.remote_port == dst_port
+                });
+                if let Some(pending) = pending {
+                    // a retransmitted SYN waits for the host connection, a
+                    // reset abandons it.
+                    if flags & TCP_RST != 0 {
+                        self.tcp_pending.remove(pending);
+                    }
+                    return;
+                }
+                if flags & (TCP_SYN | TCP_ACK | TCP_RST) == TCP_SYN {
+                    self.tcp_connect(dst, dst_port, src_port, seq, window);
+                } else if flags & TCP_RST == 0 && !(flags == TCP_ACK && payload.is_empty()) {
+                    // the bare ACK of a closed connection needs no answer.
+                    self.send_tcp_reset(dst, dst_port, src_port, ack, seq, flags, payload.len());
+                }
+                return;
+            }
+        };
+
+        if flags & TCP_RST != 0 {
+            let _ = self.tcp[index].stream.shutdown(Shutdown::Both);
+            self.tcp.remove(index);
+            return;
+        }
+
+        let mut reply = false;
+        {
+            let connection = &mut self.tcp[index];
+            if flags & TCP_ACK != 0 {
+                let acked = ack.wrapping_sub(connection.snd_una);
+                if acked != 0 && acked <= connection.snd_nxt.wrapping_sub(connection.snd_una) {
+                    let mut len = acked as usize;
+                    if !connection.syn_acked {
+                        // the SYN takes one sequence number.
+                        connection.syn_acked = true;
+                        len -= 1;
+                    }
+                    let len = len.min(connection.to_guest.len());
+                    connection.to_guest.drain(..len);
+                    connection.snd_una = ack;
+                    connection.retransmit_at = match connection.snd_una == connection.snd_nxt {
+                        true => None,
+                        false => Some(Instant::now() + CONFIG_TCP_RTO),
+                    };
+                }
+                connection.guest_window = window;
+            }
+            if seq == connection.rcv_nxt && !connection.guest_closed {
+                // take what fits in the buffer, the guest sends the rest again.
+                let len = payload.len().min(connection.get_window());
+                if !payload.is_empty() {
+                    connection.to_host.extend_from_slice(&payload[..len]);
+                    connection.rcv_nxt = connection.rcv_nxt.wrapping_add(len as u32);
+                    reply = true;
+                }
+                if flags & TCP_FIN != 0 && len == payload.len() {
+                    connection.rcv_nxt = connection.rcv_nxt.wrapping_add(1);
+                    connection.guest_closed = true;
+                    reply = true;
+                }
+            } else if !payload.is_empty() || flags & TCP_FIN != 0 {
+                // retransmission or out of order: tell the guest what we expect.
+                reply = true;
+            }
+        }
+        self.flush_to_host(index);
+        if reply {
+            self.send_tcp(index, TCP_ACK, &[]);
+        }
+
+        let connection = &self.tcp[index];
+        if connection.guest_closed
+            && connection.to_host.is_empty()
+            && connection.state == TcpState::HostClosed
+            && connection.snd_una == connection.snd_nxt
+        {
+            self.tcp.remove(index);
+        }
+    }
+
+    fn tcp_connect(
+        &mut self,
+        remote_addr: Ipv4Addr,
+        remote_port: u16,
+        guest_port: u16,
+        seq: u32,
+        window: u32,
+    ) {
+        let target = SocketAddr::V4(SocketAddrV4::new(to_host_address(remote_addr), remote_port));
+        let (sender, result) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(TcpStream::connect_timeout(&target, CONFIG_CONNECT_TIMEOUT));
+        });
+        self.tcp_pending.push(TcpPending {
+            guest_port,
+            remote_addr,
+            remote_port,
+            seq,
+            window,
+            result,
+        });
+    }
+
+    /// Answer the SYN of the guest once the host connection is open or failed.
+    fn poll_tcp_pending(&mut self) {
+        let mut i = 0;
+        while i < self.tcp_pending.len() {
+            let stream = match self.tcp_pending[i].result.try_recv() {
+                Ok(Ok(stream)) => stream_nonblocking(stream),
+                Ok(Err(_)) | Err(TryRecvError::Disconnected) => None,
+                Err(TryRecvError::Empty) => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let pending = self.tcp_pending.remove(i);
+            let stream = match stream {
+                Some(stream) => stream,
+                None => {
+                    self.send_tcp_reset(
+                        pending.remote_addr,
+                        pending.remote_port,
+                        pending.guest_port,
+                        0,
+                        pending.seq,
+                        TCP_SYN,
+                        0,
+                    );
+                    continue;
+                }
+            };
+            self.isn = self.isn.wrapping_add(0x0001_0000);
+            self.tcp.push(TcpConnection {
+                guest_port: pending.guest_port,
+                remote_addr: pending.remote_addr,
+                remote_port: pending.remote_port,
+                stream,
+                state: TcpState::Established,
+                guest_closed: false,
+                snd_nxt: self.isn.wrapping_add(1),
+                snd_una: self.isn,
+                rcv_nxt: pending.seq.wrapping_add(1),
+                guest_window: pending.window,
+                to_host: vec![],
+                to_guest: vec![],
+                syn_acked: false,
+                retransmit_at: Some(Instant::now() + CONFIG_TCP_RTO),
+                window_closed: false,
+            });
+            let index = self.tcp.len() - 1;
+            self.send_tcp_at(index, self.isn, TCP_SYN | TCP_ACK, &[]);
+        }
+    }
+
+    /// Send the oldest segment the guest has not acknowledged again.
+    fn retransmit_tcp(&mut self, index: usize) {
+        let connection = &mut self.tcp[index];
+        connection.retransmit_at = Some(Instant::now() + CONFIG_TCP_RTO);
+        let seq = connection.snd_una;
+        if !connection.syn_acked {
+            self.send_tcp_at(index, seq, TCP_SYN | TCP_ACK, &[]);
+        } else if !connection.to_guest.is_empty() {
+            let len = connection.to_guest.len().min(CONFIG_TCP_MSS);
+            let payload = connection.to_guest[..len].to_vec();
+            self.send_tcp_at(index, seq, TCP_PSH | TCP_ACK, &payload);
+        } else if connection.state == TcpState::HostClosed {
+            self.send_tcp_at(index, seq, TCP_FIN | TCP_ACK, &[]);
+        }
+    }
+
+    /// Write the data from the guest to the host socket as far as it accepts.
+    fn flush_to_host(&mut self, index: usize) {
+        let connection = &mut self.tcp[index];
+        while !connection.to_host.is_empty() {
+            match connection.stream.write(&connection.to_host) {
+                Ok(0) => break,
+                Ok(n) => {
+                    connection.to_host.drain(..n);
+                }
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        if connection.guest_closed && connection.to_host.is_empty() {
+            let _ = connection.stream.shutdown(Shutdown::Write);
+        }
+    }
+
+    fn poll_udp(&mut self) {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        for i in 0..self.udp.len() {
+            while let Ok((len, from)) = self.udp[i].socket.recv_from(&mut buf) {
+                let binding = &self.udp[i];
+                let (src, src_port) = match (binding.dns, from) {
+                    (true, _) => (DNS_ADDRESS, DNS_PORT),
+                    (false, SocketAddr::V4(addr)) => (to_guest_address(*addr.ip()), addr.port()),
+                    (false, SocketAddr::V6(_)) => continue,
+                };
+                let port = binding.port;
+                let segment = udp_segment(src, GUEST_ADDRESS, src_port, port, &buf[..len]);
+                self.send_ipv4(src, GUEST_ADDRESS, IP_PROTOCOL_UDP, &segment);
+            }
+        }
+    }
+
+    fn poll_tcp(&mut self) {
+        let mut buf = vec![0; CONFIG_TCP_MSS];
+        let mut i = 0;
+        while i < self.tcp.len() {
+            self.flush_to_host(i);
+            let connection = &self.tcp[i];
+            if connection.window_closed && connection.get_window() >= CONFIG_TCP_MSS {
+                // window update
+                self.send_tcp(i, TCP_ACK, &[]);
+            }
+            if matches!(self.tcp[i].retransmit_at, Some(at) if at <= Instant::now()) {
+                self.retransmit_tcp(i);
+            }
+            let mut reset = false;
+            while self.tcp[i].state == TcpState::Established {
+                let connection = &mut self.tcp[i];
+                let in_flight = connection.snd_nxt.wrapping_sub(connection.snd_una);
+                let window = connection.guest_window.saturating_sub(in_flight) as usize;
+                if window == 0 {
+                    break;
+                }
+                let len = window.min(CONFIG_TCP_MSS);
+                match connection.stream.read(&mut buf[..len]) {
+                    Ok(0) => {
+                        self.send_tcp(i, TCP_FIN | TCP_ACK, &[]);
+                        let connection = &mut self.tcp[i];
+                        connection.snd_nxt = connection.snd_nxt.wrapping_add(1);
+                        connection.state = TcpState::HostClosed;
+                        if connection.retransmit_at.is_none() {
+                            connection.retransmit_at = Some(Instant::now() + CONFIG_TCP_RTO);
+                        }
+                    }
+                    Ok(n) => {
+                        self.send_tcp(i, TCP_PSH | TCP_ACK, &buf[..n]);
+                        let connection = &mut self.tcp[i];
+                        connection.snd_nxt = connection.snd_nxt.wrapping_add(n as u32);
+                        connection.to_guest.extend_from_slice(&buf[..n]);
+                        if connection.retransmit_at.is_none() {
+                            connection.retransmit_at = Some(Instant::now() + CONFIG_TCP_RTO);
+                        }
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => {
+                        reset = true;
+                        break;
+                    }
+                }
+            }
+            if reset {
+                self.send_tcp(i, TCP_RST | TCP_ACK, &[]);
+                self.tcp.remove(i);
+                continue;
+            }
+            i += 1;
+        }
+    }
+
+    fn send_tcp(&mut self, index: usize, flags: u8, payload: &[u8]) {
+        let seq = self.tcp[index].snd_nxt;
+        self.send_tcp_at(index, seq, flags, payload);
+    }
+
+    fn send_tcp_at(&mut self, index: usize, seq: u32, flags: u8, payload: &[u8]) {
+        let connection = &mut self.tcp[index];
+        let window = connection.get_window();
+        connection.window_closed = window < CONFIG_TCP_MSS;
+        let (src, dst) = (connection.remote_addr, GUEST_ADDRESS);
+        let segment = tcp_segment(
+            src,
+            dst,
+            connection.remote_port,
+            connection.guest_port,
+            seq,
+            connection.rcv_nxt,
+            flags,
+            window.min(u16::MAX as usize) as u16,
+            payload,
+        );
+        self.send_ipv4(src, dst, IP_PROTOCOL_TCP, &segment);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send_tcp_reset(
+        &mut self,
+        src: Ipv4Addr,
+        src_port: u16,
+        dst_port: u16,
+        ack: u32,
+        seq: u32,
+        flags: u8,
+        len: usize,
+    ) {
+        // RFC 793: If the incoming segment has an ACK field, the reset takes
+        // its sequence number from the ACK field of the segment.
+        let segment = match flags & TCP_ACK {
+            0 => {
+                let mut seg_len = len as u32;
+                if flags & (TCP_SYN | TCP_FIN) != 0 {
+                    seg_len += 1;
+                }
+                let ack = seq.wrapping_add(seg_len);
+                tcp_segment(
+                    src,
+                    GUEST_ADDRESS,
+                    src_port,
+                    dst_port,
+                    0,
+                    ack,
+                    TCP_RST | TCP_ACK,
+                    0,
+                    &[],
+                )
+            }
+            _ => tcp_segment(
+                src,
+                GUEST_ADDRESS,
+                src_port,
+                dst_port,
+                ack,
+                0,
+                TCP_RST,
+                0,
+                &[],
+            ),
+        };
+        self.send_ipv4(src, GUEST_ADDRESS, IP_PROTOCOL_TCP, &segment);
+    }
+
+    fn send_ipv4(&mut self, src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) {
+        self.ip_id = self.ip_id.wrapping_add(1);
+        let packet = ipv4_packet(src, dst, protocol, self.ip_id, payload);
+        let dst_mac = match dst {
+            Ipv4Addr::BROADCAST => BROADCAST_MAC,
+            _ => self.guest_mac,
+        };
+        let frame = ethernet_frame(&dst_mac, &GATEWAY_MAC, ETHERTYPE_IPV4, &packet);
+        self.frames.push_back(frame);
+    }
+}
+
+impl Default for Slirp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetBackend for Slirp {
+    fn send(&mut self, frame: &[u8]) {
+        if frame.len() < ETHERNET_HEADER_SIZE {
+            return;
+        }
+        let mut src_mac = [0; 6];
+        src_mac.copy_from_slice(&frame[6..12]);
+        if src_mac[0] & 1 == 0 {
+            self.guest_mac = src_mac;
+        }
+        let payload = &frame[ETHERNET_HEADER_SIZE..];
+        match read16(frame, 12) {
+            ETHERTYPE_ARP => self.handle_arp(payload),
+            ETHERTYPE_IPV4 => self.handle_ipv4(payload),
+            _ => {}
+        }
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        if self.frames.is_empty() {
+            self.poll_udp();
+            self.poll_tcp_pending();
+            self.poll_tcp();
+        }
+        self.frames.pop_front()
+    }
+}
+
+/// The stream in the non-blocking mode polled by the NAT.
+fn stream_nonblocking(stream: TcpStream) -> Option<TcpStream> {
+    stream.set_nonblocking(true).ok()?;
+    let _ = stream.set_nodelay(true);
+    Some(stream)
+}
+
+/// The first IPv4 nameserver of resolv.conf, or the resolver on localhost.
+fn get_host_dns_server() -> SocketAddr {
+    let nameserver = fs::read_to_string("/etc/resolv.conf")
+        .ok()
+        .and_then(|conf| {
+            conf.lines()
+                .filter_map(|line| {
+                    let mut words = line.split_whitespace();
+                    match words.next() {
+                        Some("nameserver") => words.next()?.parse::<Ipv4Addr>().ok(),
+                        _ => None,
+                    }
+                })
+                .next()
+        })
+        .unwrap_or(Ipv4Addr::LOCALHOST);
+    SocketAddr::V4(SocketAddrV4::new(nameserver, DNS_PORT))
+}
+
+fn is_virtual_network(addr: Ipv4Addr) -> bool {
+    let [a, b, c, _] = addr.octets();
+    [a, b, c] == [10, 0, 2]
+}
+
+fn is_virtual_host(addr: Ipv4Addr) -> bool {
+    addr == GATEWAY_ADDRESS || addr == DNS_ADDRESS
+}
+
+/// The gateway stands for the host loopback interface.
+fn to_host_address(addr: Ipv4Addr) -> Ipv4Addr {
+    match addr {
+        GATEWAY_ADDRESS => Ipv4Addr::LOCALHOST,
+        _ => addr,
+    }
+}
+
+fn to_guest_address(addr: Ipv4Addr) -> Ipv4Addr {
+    match addr.is_loopback() {
+        true => GATEWAY_ADDRESS,
+        false => addr,
+    }
+}
+
+fn read16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn read32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Internet checksum (RFC 1071) continued from a partial sum.
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    for chunk in data.chunks(2) {
+        let word = match chunk.len() {
+            2 => u16::from_be_bytes([chunk[0], chunk[1]]),
+            _ => u16::from_be_bytes([chunk[0], 0]),
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Partial sum of the pseudo header of UDP and TCP.
+fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let [s0, s1, s2, s3] = src.octets();
+    let [d0, d1, d2, d3] = dst.octets();
+    u16::from_be_bytes([s0, s1]) as u32
+        + u16::from_be_bytes([s2, s3]) as u32
+        + u16::from_be_bytes([d0, d1]) as u32
+        + u16::from_be_bytes([d2, d3]) as u32
+        + protocol as u32
+        + len as u32
+}
+
+fn ethernet_frame(dst: &[u8; 6], src: &[u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETHERNET_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(dst);
+    frame.extend_from_slice(src);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; 20];
+    packet[0] = 0x45; // version 4, 5 words header
+    packet[2..4].copy_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6] = 0x40; // don't fragment
+    packet[8] = 64; // ttl
+    packet[9] = protocol;
+    packet[12..16].copy_from_slice(&src.octets());
+    packet[16..20].copy_from_slice(&dst.octets());
+    let sum = checksum(&packet, 0);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn udp_segment(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let len = 8 + payload.len();
+    let mut segment = Vec::with_capacity(len);
+    segment.extend_from_slice(&src_port.to_be_bytes());
+    segment.extend_from_slice(&dst_port.to_be_bytes());
+    segment.extend_from_slice(&(len as u16).to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(payload);
+    let sum = match checksum(&segment, pseudo_header_sum(src, dst, IP_PROTOCOL_UDP, len)) {
+        // zero means no checksum in UDP.
+        0 => 0xffff,
+        sum => sum,
+    };
+    segment[6..8].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
+
+#[allow(clippy::too_many_arguments)]
+fn tcp_segment(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let len = 20 + payload.len();
+    let mut segment = Vec::with_capacity(len);
+    segment.extend_from_slice(&src_port.to_be_bytes());
+    segment.extend_from_slice(&dst_port.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(5 << 4); // data offset
+    segment.push(flags);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]); // checksum, urgent pointer
+    segment.extend_from_slice(payload);
+    let sum = checksum(&segment, pseudo_header_sum(src, dst, IP_PROTOCOL_TCP, len));
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
@@ -0,0 +1,343 @@
+// AON (Always-On) domain: watchdog timer, real-time clock, power management
+// unit and backup registers. The domain keeps running while the core sleeps
+// and is not reset by the watchdog.
+// https://sifive.cdn.prismic.io/sifive%2F9ecbb623-7c7f-4acc-966f-9bb10ecdb62e_fe310-g002.pdf
+
+/// Value written to wdogkey and pmukey to unlock the next write.
+pub const AON_KEY: u32 = 0x0051_F15E;
+/// Value written to wdogfeed to restart the watchdog counter.
+pub const AON_WDOG_FEED: u32 = 0x0D09_F00D;
+
+/// Frequency of the low frequency clock
+pub const AON_LFCLK_FREQUENCY: u64 = 32_768;
+/// Core clock cycles per cycle of the low frequency clock, until the core
+/// frequency is set.
+pub const AON_LFCLK_DIVIDER: u64 = 488;
+
+// Registers
+const AON_WDOGCFG: u64 = 0x000;
+const AON_WDOGCOUNT: u64 = 0x008;
+const AON_WDOGS: u64 = 0x010;
+const AON_WDOGFEED: u64 = 0x018;
+const AON_WDOGKEY: u64 = 0x01C;
+const AON_WDOGCMP0: u64 = 0x020;
+const AON_RTCCFG: u64 = 0x040;
+const AON_RTCCOUNTLO: u64 = 0x048;
+const AON_RTCCOUNTHI: u64 = 0x04C;
+const AON_RTCS: u64 = 0x050;
+const AON_RTCCMP0: u64 = 0x060;
+const AON_LFROSCCFG: u64 = 0x070;
+const AON_LFCLKMUX: u64 = 0x07C;
+const AON_BACKUP: u64 = 0x080;
+const AON_BACKUP_END: u64 = 0x0BC;
+const AON_PMUWAKEUPI: u64 = 0x100;
+const AON_PMUWAKEUPI_END: u64 = 0x11C;
+const AON_PMUSLEEPI: u64 = 0x120;
+const AON_PMUSLEEPI_END: u64 = 0x13C;
+const AON_PMUIE: u64 = 0x140;
+const AON_PMUCAUSE: u64 = 0x144;
+const AON_PMUSLEEP: u64 = 0x148;
+const AON_PMUKEY: u64 = 0x14C;
+
+// wdogcfg and rtccfg bits
+const CFG_SCALE: u32 = 0xf;
+const WDOGCFG_RSTEN: u32 = 1 << 8;
+const WDOGCFG_ZEROCMP: u32 = 1 << 9;
+const CFG_ENALWAYS: u32 = 1 << 12;
+const WDOGCFG_COREAWAKE: u32 = 1 << 13;
+const CFG_IP0: u32 = 1 << 28;
+const WDOGCFG_MASK: u32 =
+    CFG_SCALE | WDOGCFG_RSTEN | WDOGCFG_ZEROCMP | CFG_ENALWAYS | WDOGCFG_COREAWAKE | CFG_IP0;
+const RTCCFG_MASK: u32 = CFG_SCALE | CFG_ENALWAYS;
+
+const WDOGCOUNT_MASK: u32 = 0x7fff_ffff;
+const RTCCOUNT_MASK: u64 = 0xffff_ffff_ffff;
+
+// pmuie bits
+const PMUIE_RTC: u32 = 1 << 1;
+const PMUIE_DWAKEUP: u32 = 1 << 2;
+
+// pmucause
+pub const AON_WAKEUPCAUSE_RESET: u32 = 0;
+pub const AON_WAKEUPCAUSE_RTC: u32 = 1;
+pub const AON_WAKEUPCAUSE_DWAKEUP: u32 = 2;
+pub const AON_RESETCAUSE_POWERON: u32 = 0;
+pub const AON_RESETCAUSE_EXTERNAL: u32 = 1;
+pub const AON_RESETCAUSE_WATCHDOG: u32 = 2;
+
+const PMU_WAKEUP_PROGRAM: [u32; 8] = [0x1f0, 0x0f8, 0x030, 0x030, 0x030, 0x030, 0x030, 0x030];
+const PMU_SLEEP_PROGRAM: [u32; 8] = [0x0f0, 0x1f0, 0x1d0, 0x1c0, 0x1c0, 0x1c0, 0x1c0, 0x1c0];
+
+const BACKUP_COUNT: usize = 16;
+
+pub struct Aon {
+    /// Frequency of the core clock which ticks the domain
+    core_frequency: u64,
+    /// low frequency clock cycles accumulated by the core cycles, the
+    /// domain runs at each core_frequency.
+    lfclk_phase: u64,
+    /// Watchdog configuration, with the interrupt pending bit
+    wdogcfg: u32,
+    /// Watchdog counter
+    wdogcount: u32,
+    /// Watchdog compare value
+    wdogcmp0: u32,
+    /// the next write to a watchdog register is allowed.
+    wdog_unlocked: bool,
+    /// RTC configuration
+    rtccfg: u32,
+    /// RTC counter (48 bits)
+    rtccount: u64,
+    /// RTC compare value
+    rtccmp0: u32,
+    lfrosccfg: u32,
+    lfclkmux: u32,
+    /// Backup registers, kept in the sleep
+    backup: [u32; BACKUP_COUNT],
+    /// PMU wake up program
+    pmuwakeupi: [u32; 8],
+    /// PMU sleep program
+    pmusleepi: [u32; 8],
+    /// PMU interrupt enables
+    pmuie: u32,
+    /// PMU wake up and reset causes
+    pmucause: u32,
+    /// the next write to a PMU register is allowed.
+    pmu_unlocked: bool,
+    /// the core is powered down by the PMU.
+    sleeping: bool,
+    /// the digital wake up pin is asserted.
+    dwakeup: bool,
+    /// the core must be reset (by the watchdog or a wake up).
+    reset_request: bool,
+    /// the pending reset comes from the watchdog.
+    watchdog_request: bool,
+}
+
+impl Default for Aon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Aon {
+    pub fn new() -> Self {
+        Aon {
+            core_frequency: AON_LFCLK_DIVIDER * AON_LFCLK_FREQUENCY,
+            lfclk_phase: 0,
+            wdogcfg: 0,
+            wdogcount: 0,
+            wdogcmp0: 0xffff,
+            wdog_unlocked: false,
+            rtccfg: 0,
+            rtccount: 0,
+            rtccmp0: 0xffff_ffff,
+            lfrosccfg: 0x0004_0000 | 4, // enabled, divided by 5
+            lfclkmux: 0,
+            backup: [0; BACKUP_COUNT],
+            pmuwakeupi: PMU_WAKEUP_PROGRAM,
+            pmusleepi: PMU_SLEEP_PROGRAM,
+            pmuie: 0,
+            pmucause: AON_WAKEUPCAUSE_RESET | AON_RESETCAUSE_POWERON << 8,
+            pmu_unlocked: false,
+            sleeping: false,
+            dwakeup: false,
+            reset_request: false,
+            watchdog_request: false,
+        }
+    }
+
+    /// Tick the low frequency clock from the core clock at the frequency
+    /// (Hz).
+    pub fn set_core_frequency(&mut self, core_frequency: u64) {
+        self.core_frequency = core_frequency.max(1);
+        self.lfclk_phase = 0;
+    }
+
+    pub fn tick(&mut self) {
+        self.lfclk_phase += AON_LFCLK_FREQUENCY;
+        if self.lfclk_phase < self.core_frequency {
+            return;
+        }
+        self.lfclk_phase -= self.core_frequency;
+
+        // watchdog
+        let awake = !self.sleeping && self.wdogcfg & WDOGCFG_COREAWAKE != 0;
+        if self.wdogcfg & CFG_ENALWAYS != 0 || awake {
+            self.wdogcount = self.wdogcount.wrapping_add(1) & WDOGCOUNT_MASK;
+        }
+        if self.get_wdogs() >= self.wdogcmp0 {
+            self.wdogcfg |= CFG_IP0;
+            if self.wdogcfg & WDOGCFG_ZEROCMP != 0 {
+                self.wdogcount = 0;
+            }
+            if self.wdogcfg & WDOGCFG_RSTEN != 0 {
+                self.watchdog_reset();
+                return;
+            }
+        }
+
+        // real-time clock
+        if self.rtccfg & CFG_ENALWAYS != 0 {
+            self.rtccount = self.rtccount.wrapping_add(1) & RTCCOUNT_MASK;
+        }
+
+        // the PMU wakes up the core, which starts from the reset.
+        if self.sleeping {
+            let cause = if self.pmuie & PMUIE_RTC != 0 && self.is_rtc_irq() {
+                AON_WAKEUPCAUSE_RTC
+            } else if self.pmuie & PMUIE_DWAKEUP != 0 && self.dwakeup {
+                AON_WAKEUPCAUSE_DWAKEUP
+            } else {
+                return;
+            };
+            self.sleeping = false;
+            self.pmucause = (self.pmucause & 0x300) | cause;
+            self.reset_request = true;
+        }
+    }
+
+    /// The watchdog resets the chip except the always-on domain, the
+    /// watchdog itself is disabled.
+    fn watchdog_reset(&mut self) {
+        self.wdogcfg = 0;
+        self.wdogcount = 0;
+        self.wdog_unlocked = false;
+        self.sleeping = false;
+        self.pmucause = AON_WAKEUPCAUSE_RESET | AON_RESETCAUSE_WATCHDOG << 8;
+        self.reset_request = true;
+        self.watchdog_request = true;
+    }
+
+    /// Scaled watchdog counter
+    fn get_wdogs(&self) -> u32 {
+        (self.wdogcount >> (self.wdogcfg & CFG_SCALE)) & 0xffff
+    }
+
+    /// Scaled RTC counter
+    fn get_rtcs(&self) -> u32 {
+        (self.rtccount >> (self.rtccfg & CFG_SCALE)) as u32
+    }
+
+    pub fn is_watchdog_irq(&self) -> bool {
+        self.wdogcfg & CFG_IP0 != 0
+    }
+
+    pub fn is_rtc_irq(&self) -> bool {
+        self.get_rtcs() >= self.rtccmp0
+    }
+
+    /// The core is powered down by the PMU.
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    /// Drive the digital wake up pin (dwakeup_n, true when pulled low).
+    pub fn set_dwakeup(&mut self, asserted: bool) {
+        self.dwakeup = asserted;
+    }
+
+    /// The core must be reset since the last call.
+    pub fn take_reset_request(&mut self) -> bool {
+        let request = self.reset_request;
+        self.reset_request = false;
+        self.watchdog_request = false;
+        request
+    }
+
+    /// The watchdog reset the chip since the last call, the reset request is
+    /// taken with it.
+    pub fn take_watchdog_reset(&mut self) -> bool {
+        let request = self.watchdog_request;
+        if request {
+            self.reset_request = false;
+            self.watchdog_request = false;
+        }
+        request
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr & 0xffc {
+            AON_WDOGCFG => self.wdogcfg,
+            AON_WDOGCOUNT => self.wdogcount,
+            AON_WDOGS => self.get_wdogs(),
+            AON_WDOGFEED => 0,
+            AON_WDOGKEY => self.wdog_unlocked as u32,
+            AON_WDOGCMP0 => self.wdogcmp0,
+            AON_RTCCFG => self.rtccfg | if self.is_rtc_irq() { CFG_IP0 } else { 0 },
+            AON_RTCCOUNTLO => self.rtccount as u32,
+            AON_RTCCOUNTHI => (self.rtccount >> 32) as u32,
+            AON_RTCS => self.get_rtcs(),
+            AON_RTCCMP0 => self.rtccmp0,
+            AON_LFROSCCFG => self.lfrosccfg | 0x8000_0000, /* OSC ready */
+            AON_LFCLKMUX => self.lfclkmux,
+            AON_BACKUP..=AON_BACKUP_END => self.backup[((addr & 0xffc) - AON_BACKUP) as usize / 4],
+            AON_PMUWAKEUPI..=AON_PMUWAKEUPI_END => {
+                self.pmuwakeupi[((addr & 0xffc) - AON_PMUWAKEUPI) as usize / 4]
+            }
+            AON_PMUSLEEPI..=AON_PMUSLEEPI_END => {
+                self.pmusleepi[((addr & 0xffc) - AON_PMUSLEEPI) as usize / 4]
+            }
+            AON_PMUIE => self.pmuie,
+            AON_PMUCAUSE => self.pmucause,
+            AON_PMUSLEEP => 0,
+            AON_PMUKEY => self.pmu_unlocked as u32,
+            n => panic!("Read reserved address: {:x}", n),
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        let addr = addr & 0xffc;
+        match addr {
+            AON_WDOGKEY => self.wdog_unlocked = data == AON_KEY,
+            // the watchdog registers are locked again by any write.
+            AON_WDOGCFG..=AON_WDOGCMP0 => {
+                let unlocked = self.wdog_unlocked;
+                self.wdog_unlocked = false;
+                if !unlocked {
+                    return;
+                }
+                match addr {
+                    AON_WDOGCFG => self.wdogcfg = data & WDOGCFG_MASK,
+                    AON_WDOGCOUNT => self.wdogcount = data & WDOGCOUNT_MASK,
+                    AON_WDOGFEED if data == AON_WDOG_FEED => self.wdogcount = 0,
+                    AON_WDOGCMP0 => self.wdogcmp0 = data & 0xffff,
+                    _ => {}
+                }
+            }
+            AON_RTCCFG => self.rtccfg = data & RTCCFG_MASK,
+            AON_RTCCOUNTLO => {
+                self.rtccount = (self.rtccount & !0xffff_ffff) | data as u64;
+            }
+            AON_RTCCOUNTHI => {
+                self.rtccount = (self.rtccount & 0xffff_ffff) | ((data & 0xffff) as u64) << 32;
+            }
+            AON_RTCS => {}
+            AON_RTCCMP0 => self.rtccmp0 = data,
+            AON_LFROSCCFG => self.lfrosccfg = data & 0x7fff_ffff,
+            AON_LFCLKMUX => self.lfclkmux = data & 0x1,
+            AON_BACKUP..=AON_BACKUP_END => self.backup[(addr - AON_BACKUP) as usize / 4] = data,
+            AON_PMUKEY => self.pmu_unlocked = data == AON_KEY,
+            // the PMU registers are locked again by any write.
+            AON_PMUWAKEUPI..=AON_PMUSLEEP => {
+                let unlocked = self.pmu_unlocked;
+                self.pmu_unlocked = false;
+                if !unlocked {
+                    return;
+                }
+                match addr {
+                    AON_PMUWAKEUPI..=AON_PMUWAKEUPI_END => {
+                        self.pmuwakeupi[(addr - AON_PMUWAKEUPI) as usize / 4] = data & 0x1ff
+                    }
+                    AON_PMUSLEEPI..=AON_PMUSLEEPI_END => {
+                        self.pmusleepi[(addr - AON_PMUSLEEPI) as usize / 4] = data & 0x1ff
+                    }
+                    AON_PMUIE => self.pmuie = data & (PMUIE_RTC | PMUIE_DWAKEUP),
+                    AON_PMUSLEEP => self.sleeping = true,
+                    _ => {}
+                }
+            }
+            n => panic!("Write reserved address: {:x}", n),
+        }
+    }
+}
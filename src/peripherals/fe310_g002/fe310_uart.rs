@@ -1,11 +1,23 @@
 // FE310 UART Device
 // https://static.dev.sifive.com/FE310-G000.pdf
+//
+// A frame takes div + 1 bus clock cycles (the core clock) per bit: a start
+// bit, 8 data bits and 1 or 2 stop bits. The console is polled for a
+// received character once per frame after a character, otherwise every
+// UART_RX_IDLE_CYCLES.
 
 use crate::console::Console;
 
 const UART_TXEN: u32 = 0x1;
+const UART_NSTOP: u32 = 0x2;
 const UART_RXEN: u32 = 0x1;
 
+const UART_TXDATA_FULL: u32 = 0x8000_0000;
+const UART_FIFO_DEPTH: usize = 8;
+
+/// Cycles between the polls of the console when nothing is received
+const UART_RX_IDLE_CYCLES: u64 = 0xffff;
+
 const UART_TXWM: u32 = 0x1;
 const UART_RXWM: u32 = 0x2;
 
@@ -30,8 +42,9 @@ pub struct Fe310Uart {
     t_fifo: Vec<u8>,
     /// Terminal for serial console.
     console: Box<dyn Console>,
-    /// current clock cycle.
-    cycle: u64,
+    /// Cycles until the transmitter and the receiver complete their frame
+    tx_busy: u64,
+    rx_busy: u64,
 }
 
 impl Fe310Uart {
@@ -47,42 +60,72 @@ impl Fe310Uart {
             r_fifo: Vec::new(),
             t_fifo: Vec::new(),
             console: console_,
-            cycle: 0,
+            tx_busy: 0,
+            rx_busy: 0,
         }
     }
 
+    /// Reset the registers and the FIFOs, the console is kept.
+    pub fn reset(&mut self) {
+        self.txdata = 0;
+        self.rxdata = 0x8000_0000;
+        self.txctrl = 0x01;
+        self.rxctrl = 0x01;
+        self.ie = 0;
+        self.ip = 0;
+        self.div = 0;
+        self.r_fifo.clear();
+        self.t_fifo.clear();
+        self.tx_busy = 0;
+        self.rx_busy = 0;
+    }
+
     pub fn get_console(&mut self) -> &mut Box<dyn Console> {
         &mut self.console
     }
 
-    pub fn tick(&mut self) {
-        self.cycle = self.cycle.wrapping_add(1);
-
-        // TODO: Correctly care for the clock frequency.
-        // The current settings have no reason.
+    /// Bus clock cycles of a frame.
+    fn get_frame_cycles(&self) -> u64 {
+        let bits = match self.txctrl & UART_NSTOP {
+            0 => 10,
+            _ => 11,
+        };
+        bits * (self.div as u64 + 1)
+    }
 
+    pub fn tick(&mut self) {
         // receiver
-        if (self.cycle % 0xffff) == 0 {
+        self.rx_busy = self.rx_busy.saturating_sub(1);
+        if self.rx_busy == 0 {
+            self.rx_busy = UART_RX_IDLE_CYCLES;
             if self.rxctrl & UART_RXEN > 0 {
                 match self.console.getchar() {
                     0 => {}
-                    c => self.r_fifo.push(c),
+                    c => {
+                        self.r_fifo.push(c);
+                        self.rx_busy = self.get_frame_cycles();
+                    }
                 }
             }
             self.update_recieve_interrupt_status();
         }
 
-        // transmitter
-        if (self.cycle % 0xf) == 0 && (self.txctrl & UART_TXEN > 0) && self.t_fifo.len() > 0 {
+        // transmitter, a character goes out when its frame starts.
+        self.tx_busy = self.tx_busy.saturating_sub(1);
+        if self.tx_busy == 0 && (self.txctrl & UART_TXEN > 0) && !self.t_fifo.is_empty() {
             self.console.putchar(self.t_fifo[0] as u8);
             self.t_fifo.remove(0);
+            self.tx_busy = self.get_frame_cycles();
             self.update_transmit_interrupt_status();
         }
     }
 
     pub fn read(&mut self, addr: u64) -> u32 {
         match addr & 0xff {
-            0x00 => self.txdata,
+            0x00 => match self.t_fifo.len() >= UART_FIFO_DEPTH {
+                true => UART_TXDATA_FULL,
+                false => 0,
+            },
             0x04 => {
                 match self.r_fifo.len() {
                     0 => self.rxdata = 0x8000_0000,
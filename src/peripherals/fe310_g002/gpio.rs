@@ -1,6 +1,29 @@
 // GPIO
 // https://static.dev.sifive.com/FE310-G000.pdf
 // https://bitbucket.org/nuttx/nuttx/src/master/arch/risc-v/src/fe310/fe310_gpio.c
+//
+// The host drives the pins which are not outputs, at once or at a given
+// cycle, and observes the changes of the output pins as timestamped events.
+// A pin with its IOF enabled is driven by the peripheral selected by iof_sel,
+// which the bus reports with set_iof_outputs().
+
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// Events kept, the oldest are dropped.
+const GPIO_EVENTS_MAX: usize = 4096;
+
+/// Number of pins.
+pub const GPIO_PINS: usize = 32;
+
+/// Change of the level of a pin, an output for the host or an input for the
+/// SoC.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GpioEvent {
+    pub cycle: u64,
+    pub pin: usize,
+    pub level: bool,
+}
 
 pub struct Gpio {
     /// Pin value
@@ -31,12 +54,27 @@ pub struct Gpio {
     low_ie: u32,
     /// Low interrupt pending
     low_ip: u32,
-    /// 
+    /// HW I/O function enable
     iof_en: u32,
-    /// 
+    /// HW I/O function select
     iof_sel: u32,
     /// Output XOR (invert)
     out_xor: u32,
+    /// current clock cycle.
+    cycle: u64,
+    /// Pins driven by the host and their levels
+    host_en: u32,
+    host_val: u32,
+    /// Inputs to apply, ordered by cycle
+    scheduled: VecDeque<GpioEvent>,
+    /// Pins driven by IOF0 and IOF1 and their levels
+    iof_oe: [u32; 2],
+    iof_val: [u32; 2],
+    /// Levels of the output pins, 0 for the others
+    outputs: u32,
+    events: VecDeque<GpioEvent>,
+    /// Also writes the output events as "cycle pin level" lines.
+    event_log: Option<Box<dyn Write>>,
 }
 
 impl Gpio {
@@ -59,15 +97,175 @@ impl Gpio {
             iof_en: 0,
             iof_sel: 0,
             out_xor: 0,
+            cycle: 0,
+            host_en: 0,
+            host_val: 0,
+            scheduled: VecDeque::new(),
+            iof_oe: [0; 2],
+            iof_val: [0; 2],
+            outputs: 0,
+            events: VecDeque::new(),
+            event_log: None,
+        }
+    }
+
+    /// Reset the registers, the host side (the levels it drives, the
+    /// scheduled inputs and the events) is kept.
+    pub fn reset(&mut self) {
+        self.input_val = 0;
+        self.input_en = 0;
+        self.output_en = 0;
+        self.output_val = 0;
+        self.pue = 0;
+        self.ds = 0;
+        self.rise_ie = 0;
+        self.rise_ip = 0;
+        self.fall_ie = 0;
+        self.fall_ip = 0;
+        self.high_ie = 0;
+        self.high_ip = 0;
+        self.low_ie = 0;
+        self.low_ip = 0;
+        self.iof_en = 0;
+        self.iof_sel = 0;
+        self.out_xor = 0;
+        self.update_outputs();
+    }
+
+    /// Drive a pin from the host, it is seen by the SoC from the next cycle
+    /// (Err if the pin does not exist).
+    pub fn set_input(&mut self, pin: usize, level: bool) -> Result<(), ()> {
+        if pin >= GPIO_PINS {
+            return Err(());
         }
+        self.host_en |= 1 << pin;
+        match level {
+            true => self.host_val |= 1 << pin,
+            false => self.host_val &= !(1 << pin),
+        }
+        Ok(())
+    }
+
+    /// Stop driving a pin from the host, it floats (or is pulled up).
+    pub fn release_input(&mut self, pin: usize) -> Result<(), ()> {
+        if pin >= GPIO_PINS {
+            return Err(());
+        }
+        self.host_en &= !(1 << pin);
+        self.host_val &= !(1 << pin);
+        Ok(())
+    }
+
+    /// Drive a pin from the host at a cycle of the devices clock.
+    pub fn schedule_input(&mut self, cycle: u64, pin: usize, level: bool) -> Result<(), ()> {
+        if pin >= GPIO_PINS {
+            return Err(());
+        }
+        let index = self
+            .scheduled
+            .iter()
+            .position(|event| event.cycle > cycle)
+            .unwrap_or(self.scheduled.len());
+        self.scheduled
+            .insert(index, GpioEvent { cycle, pin, level });
+        Ok(())
+    }
+
+    /// Levels driven by the pins of an I/O function (0 or 1).
+    pub fn set_iof_outputs(&mut self, iof: usize, enable: u32, value: u32) {
+        self.iof_oe[iof] = enable;
+        self.iof_val[iof] = value;
+    }
+
+    /// Write the output events to a log too.
+    pub fn set_event_log(&mut self, log: Box<dyn Write>) {
+        self.event_log = Some(log);
+    }
+
+    pub fn get_cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Level of an output pin, false if the SoC does not drive it.
+    pub fn get_output(&self, pin: usize) -> bool {
+        (self.outputs >> pin) & 1 != 0
+    }
+
+    /// The recorded output events, oldest first, since the last call.
+    pub fn take_events(&mut self) -> Vec<GpioEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Pins driven by the SoC, with their levels.
+    fn get_driven(&self) -> (u32, u32) {
+        let iof_oe = (self.iof_oe[0] & !self.iof_sel) | (self.iof_oe[1] & self.iof_sel);
+        let iof_val = (self.iof_val[0] & !self.iof_sel) | (self.iof_val[1] & self.iof_sel);
+        let enable = (self.output_en & !self.iof_en) | (iof_oe & self.iof_en);
+        let value = (self.output_val & !self.iof_en) | (iof_val & self.iof_en);
+        (enable, (value ^ self.out_xor) & enable)
+    }
+
+    fn update_outputs(&mut self) {
+        let (_, outputs) = self.get_driven();
+        let changed = outputs ^ self.outputs;
+        for pin in 0..32 {
+            if (changed >> pin) & 1 == 0 {
+                continue;
+            }
+            let event = GpioEvent {
+                cycle: self.cycle,
+                pin,
+                level: (outputs >> pin) & 1 != 0,
+            };
+            if let Some(log) = &mut self.event_log {
+                let line = format!("{} {} {}\n", event.cycle, event.pin, event.level as u8);
+                if log.write_all(line.as_bytes()).is_err() {
+                    self.event_log = None;
+                }
+            }
+            if self.events.len() == GPIO_EVENTS_MAX {
+                self.events.pop_front();
+            }
+            self.events.push_back(event);
+        }
+        self.outputs = outputs;
     }
 
     pub fn tick(&mut self) {
-        // do nothing.
+        self.cycle = self.cycle.wrapping_add(1);
+        while let Some(event) = self.scheduled.front() {
+            if event.cycle > self.cycle {
+                break;
+            }
+            let event = self.scheduled.pop_front().unwrap();
+            // the pins of the events are checked when they are scheduled.
+            let _ = self.set_input(event.pin, event.level);
+        }
+        self.update_outputs();
+
+        // an output pin reads its own level, a floating one the pull-up.
+        let (driven, outputs) = self.get_driven();
+        let external = (self.host_val & self.host_en) | (self.pue & !self.host_en);
+        let pins = outputs | (external & !driven);
+        let input_val = pins & self.input_en;
+
+        self.rise_ip |= input_val & !self.input_val;
+        self.fall_ip |= !input_val & self.input_val;
+        self.high_ip |= input_val;
+        self.low_ip |= !input_val;
+        self.input_val = input_val;
+    }
+
+    /// Pins whose interrupt is enabled and pending.
+    pub fn get_irqs(&self) -> u32 {
+        (self.rise_ip & self.rise_ie)
+            | (self.fall_ip & self.fall_ie)
+            | (self.high_ip & self.high_ie)
+            | (self.low_ip & self.low_ie)
     }
 
     pub fn is_irq(&mut self) -> bool {
-        self.rise_ip != 0 || self.fall_ip != 0 || self.high_ip != 0 || self.low_ip != 0
+        self.get_irqs() != 0
     }
 
     pub fn read(&mut self, addr: u64) -> u32 {
@@ -95,7 +293,7 @@ impl Gpio {
 
     pub fn write(&mut self, addr: u64, data: u32) {
         match addr & 0xff {
-            0x00 => self.input_val = data,
+            0x00 => {} // read only
             0x04 => self.input_en = data,
             0x08 => self.output_en = data,
             0x0c => self.output_val = data,
@@ -0,0 +1,184 @@
+// FE310-G002 I2C master, the OpenCores I2C controller with its registers at
+// 4-byte intervals
+// https://sifive.cdn.prismic.io/sifive/9ecbb623-7c7f-4acc-966f-9bb10ecdb62e_fe310-g002.pdf
+// https://opencores.org/websvn/filedetails?repname=i2c&path=%2Fi2c%2Ftrunk%2Fdoc%2Fi2c_specs.pdf
+//
+// The commands complete as soon as they are written to the command register:
+// the transfer is never in progress and the arbitration is never lost.
+
+use crate::peripherals::i2c::I2cDevice;
+
+// Control register
+const I2C_CTR_EN: u32 = 0x80;
+const I2C_CTR_IEN: u32 = 0x40;
+
+// Command register
+const I2C_CR_STA: u32 = 0x80;
+const I2C_CR_STO: u32 = 0x40;
+const I2C_CR_RD: u32 = 0x20;
+const I2C_CR_WR: u32 = 0x10;
+const I2C_CR_ACK: u32 = 0x08;
+const I2C_CR_IACK: u32 = 0x01;
+
+// Status register
+const I2C_SR_RXACK: u32 = 0x80;
+const I2C_SR_BUSY: u32 = 0x40;
+const I2C_SR_IF: u32 = 0x01;
+
+pub struct Fe310I2c {
+    /// Clock prescale
+    prer: u32,
+    /// Control register
+    ctr: u32,
+    /// Transmit register
+    txr: u32,
+    /// Receive register
+    rxr: u32,
+    /// Status register
+    sr: u32,
+    /// the next write transfers the address after a start condition.
+    address_phase: bool,
+    devices: Vec<Box<dyn I2cDevice>>,
+    /// Device which acknowledged its address in the transaction.
+    target: Option<usize>,
+}
+
+impl Default for Fe310I2c {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fe310I2c {
+    pub fn new() -> Self {
+        Fe310I2c {
+            prer: 0xffff,
+            ctr: 0,
+            txr: 0,
+            rxr: 0,
+            sr: 0,
+            address_phase: false,
+            devices: vec![],
+            target: None,
+        }
+    }
+
+    /// Reset the registers, the devices are kept.
+    pub fn reset(&mut self) {
+        if let Some(target) = self.target.take() {
+            self.devices[target].stop();
+        }
+        self.prer = 0xffff;
+        self.ctr = 0;
+        self.txr = 0;
+        self.rxr = 0;
+        self.sr = 0;
+        self.address_phase = false;
+    }
+
+    /// Plug a device on the bus (Err if the address is already used).
+    pub fn attach_device(&mut self, device: Box<dyn I2cDevice>) -> Result<(), ()> {
+        let address = device.get_address();
+        if self.devices.iter().any(|d| d.get_address() == address) {
+            return Err(());
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    pub fn tick(&mut self) {
+        // do nothing.
+    }
+
+    pub fn is_irq(&mut self) -> bool {
+        self.ctr & I2C_CTR_IEN != 0 && self.sr & I2C_SR_IF != 0
+    }
+
+    /// Send the byte of txr: the address after a start condition, or data
+    /// to the addressed device. Returns the acknowledge.
+    fn write_byte(&mut self) -> bool {
+        let data = self.txr as u8;
+        if self.address_phase {
+            self.address_phase = false;
+            let address = data >> 1;
+            let read = data & 1 != 0;
+            self.target = self
+                .devices
+                .iter()
+                .position(|device| device.get_address() == address);
+            let ack = match self.target {
+                Some(target) => self.devices[target].start(read),
+                None => false,
+            };
+            // a device that does not acknowledge its address is not selected.
+            if !ack {
+                self.target = None;
+            }
+            return ack;
+        }
+        match self.target {
+            Some(target) => self.devices[target].write(data),
+            None => false,
+        }
+    }
+
+    fn command(&mut self, data: u32) {
+        if data & I2C_CR_IACK != 0 {
+            self.sr &= !I2C_SR_IF;
+        }
+        if self.ctr & I2C_CTR_EN == 0 {
+            return;
+        }
+        if data & I2C_CR_STA != 0 {
+            // a repeated start ends the transaction with the last device.
+            if let Some(target) = self.target.take() {
+                self.devices[target].stop();
+            }
+            self.address_phase = true;
+            self.sr |= I2C_SR_BUSY;
+        }
+        if data & I2C_CR_WR != 0 {
+            match self.write_byte() {
+                true => self.sr &= !I2C_SR_RXACK,
+                false => self.sr |= I2C_SR_RXACK,
+            }
+            self.sr |= I2C_SR_IF;
+        } else if data & I2C_CR_RD != 0 {
+            let ack = data & I2C_CR_ACK == 0;
+            self.rxr = match self.target {
+                Some(target) => self.devices[target].read(ack) as u32,
+                None => 0xff,
+            };
+            self.sr |= I2C_SR_IF;
+        }
+        if data & I2C_CR_STO != 0 {
+            if let Some(target) = self.target.take() {
+                self.devices[target].stop();
+            }
+            self.address_phase = false;
+            self.sr &= !I2C_SR_BUSY;
+        }
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr & 0xff {
+            0x00 => self.prer & 0xff,
+            0x04 => self.prer >> 8,
+            0x08 => self.ctr,
+            0x0c => self.rxr,
+            0x10 => self.sr,
+            n => panic!("Read reserved address: {:x}", n),
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        match addr & 0xff {
+            0x00 => self.prer = (self.prer & 0xff00) | (data & 0xff),
+            0x04 => self.prer = (self.prer & 0x00ff) | (data & 0xff) << 8,
+            0x08 => self.ctr = data & (I2C_CTR_EN | I2C_CTR_IEN),
+            0x0c => self.txr = data & 0xff,
+            0x10 => self.command(data & 0xff),
+            n => panic!("Write reserved address: {:x}", n),
+        }
+    }
+}
@@ -1,3 +1,8 @@
 pub mod prci;
 pub mod gpio;
 pub mod fe310_uart;
+pub mod aon;
+pub mod spi;
+pub mod pwm;
+pub mod i2c;
+pub mod otp;
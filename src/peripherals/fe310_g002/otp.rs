@@ -0,0 +1,193 @@
+// One-time programmable memory of the FE310 and its controller
+// https://sifive.cdn.prismic.io/sifive%2F9ecbb623-7c7f-4acc-966f-9bb10ecdb62e_fe310-g002.pdf
+//
+// The fuses read through the memory mapped region, blank bits read 0. The
+// software programs them with the device signals of the controller: it
+// acquires the programmed-I/O lock, selects the device and enables the write
+// voltage, sets the address and the data, then raises the write enable and
+// pulses the clock. A bit programmed to 1 can never be cleared. The fuses
+// are kept in the backend, when one is set, as soon as they are programmed.
+
+use crate::block::BlockBackend;
+use crate::peripherals::memory::Memory;
+
+/// Size of the OTP memory in bytes
+pub const OTP_SIZE: usize = 0x2000;
+const OTP_WORDS: u32 = (OTP_SIZE / 4) as u32;
+
+// Registers
+const OTP_LOCK: u64 = 0x00;
+const OTP_CK: u64 = 0x04;
+const OTP_OE: u64 = 0x08;
+const OTP_SEL: u64 = 0x0C;
+const OTP_WE: u64 = 0x10;
+const OTP_MR: u64 = 0x14;
+const OTP_MRR: u64 = 0x18;
+const OTP_MPP: u64 = 0x1C;
+const OTP_VRREN: u64 = 0x20;
+const OTP_VPPEN: u64 = 0x24;
+const OTP_A: u64 = 0x28;
+const OTP_D: u64 = 0x2C;
+const OTP_Q: u64 = 0x30;
+const OTP_RSCTRL: u64 = 0x34;
+
+pub struct Otp {
+    fuses: Memory,
+    backend: Option<Box<dyn BlockBackend>>,
+    /// the programmed-I/O lock is held.
+    lock: bool,
+    ck: bool,
+    oe: bool,
+    sel: bool,
+    we: bool,
+    mr: u32,
+    mrr: u32,
+    mpp: u32,
+    vrren: bool,
+    vppen: bool,
+    /// Word address
+    a: u32,
+    d: u32,
+    q: u32,
+    rsctrl: u32,
+}
+
+impl Default for Otp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Otp {
+    pub fn new() -> Self {
+        Otp {
+            fuses: Memory::new(OTP_SIZE),
+            backend: None,
+            lock: false,
+            ck: false,
+            oe: false,
+            sel: false,
+            we: false,
+            mr: 0,
+            mrr: 0,
+            mpp: 0,
+            vrren: false,
+            vppen: false,
+            a: 0,
+            d: 0,
+            q: 0,
+            rsctrl: 0,
+        }
+    }
+
+    /// Reset the controller, the fuses are kept.
+    pub fn reset(&mut self) {
+        let fuses = std::mem::replace(&mut self.fuses, Memory::new(0));
+        *self = Otp {
+            fuses,
+            backend: self.backend.take(),
+            ..Otp::new()
+        };
+    }
+
+    /// Keep the fuses in the backend, they are loaded from it.
+    pub fn set_backend(&mut self, mut backend: Box<dyn BlockBackend>) {
+        let size = (backend.get_size() as usize).min(OTP_SIZE);
+        let mut data = vec![0; size];
+        if backend.read_at(0, &mut data).is_err() {
+            data.clear();
+        }
+        self.fuses = Memory::new(OTP_SIZE);
+        self.fuses.initialize(data);
+        self.backend = Some(backend);
+    }
+
+    /// Program the bits set in the data into the word.
+    fn program(&mut self, index: u32, data: u32) {
+        let offset = index as u64 * 4;
+        let word = self.fuses.read32(offset) | data;
+        self.fuses.write32(offset, word);
+        if let Some(backend) = &mut self.backend {
+            if offset + 4 <= backend.get_size() {
+                let _ = backend.write_at(offset, &word.to_le_bytes());
+                let _ = backend.flush();
+            }
+        }
+    }
+
+    /// Rising edge of the device clock: program or read the selected word.
+    fn clock(&mut self) {
+        if !self.sel {
+            return;
+        }
+        if self.we && self.vppen {
+            self.program(self.a, self.d);
+        } else if self.oe && self.vrren {
+            self.q = self.fuses.read32(self.a as u64 * 4);
+        }
+    }
+
+    pub fn read_memory8(&self, addr: u64) -> u8 {
+        self.fuses.read8(addr)
+    }
+
+    pub fn read_memory16(&self, addr: u64) -> u16 {
+        self.fuses.read16(addr)
+    }
+
+    pub fn read_memory32(&self, addr: u64) -> u32 {
+        self.fuses.read32(addr)
+    }
+
+    pub fn read_memory64(&self, addr: u64) -> u64 {
+        self.fuses.read64(addr)
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr {
+            OTP_LOCK => self.lock as u32,
+            OTP_CK => self.ck as u32,
+            OTP_OE => self.oe as u32,
+            OTP_SEL => self.sel as u32,
+            OTP_WE => self.we as u32,
+            OTP_MR => self.mr,
+            OTP_MRR => self.mrr,
+            OTP_MPP => self.mpp,
+            OTP_VRREN => self.vrren as u32,
+            OTP_VPPEN => self.vppen as u32,
+            OTP_A => self.a,
+            OTP_D => self.d,
+            OTP_Q => self.q,
+            OTP_RSCTRL => self.rsctrl,
+            _ => panic!("Read reserved address: {:x}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        match addr {
+            OTP_LOCK => self.lock = data & 1 != 0,
+            // the device signals are driven only while the lock is held.
+            OTP_CK..=OTP_D if !self.lock => {}
+            OTP_CK => {
+                let ck = data & 1 != 0;
+                if ck && !self.ck {
+                    self.clock();
+                }
+                self.ck = ck;
+            }
+            OTP_OE => self.oe = data & 1 != 0,
+            OTP_SEL => self.sel = data & 1 != 0,
+            OTP_WE => self.we = data & 1 != 0,
+            OTP_MR => self.mr = data & 0xf,
+            OTP_MRR => self.mrr = data & 0xf,
+            OTP_MPP => self.mpp = data & 0xf,
+            OTP_VRREN => self.vrren = data & 1 != 0,
+            OTP_VPPEN => self.vppen = data & 1 != 0,
+            OTP_A => self.a = data & (OTP_WORDS - 1),
+            OTP_D => self.d = data,
+            OTP_Q => {}
+            OTP_RSCTRL => self.rsctrl = data & 0xff,
+            _ => panic!("Write reserved address: {:x}", addr),
+        }
+    }
+}
@@ -1,6 +1,39 @@
 // PRCI (Power, Reset, Clock, Interrupt)
 // https://sifive.cdn.prismic.io/sifive%2F9ecbb623-7c7f-4acc-966f-9bb10ecdb62e_fe310-g002.pdf
 // https://bitbucket.org/nuttx/nuttx/src/master/arch/risc-v/src/fe310/fe310_clockconfig.c
+//
+// The clock tree sets the frequency of the core clock (hfclk), which is also
+// the bus clock (tlclk) of the peripherals. The oscillators are ready and the
+// PLL locked as soon as they are configured.
+
+/// Frequency of the internal ring oscillator before its divider
+pub const PRCI_HFROSC_FREQUENCY: u64 = 72_000_000;
+/// Frequency of the external crystal oscillator of the HiFive1
+pub const PRCI_HFXOSC_FREQUENCY: u64 = 16_000_000;
+/// Frequency of the real-time clock (RTCCLK), the low frequency clock
+pub const PRCI_RTCCLK_FREQUENCY: u64 = 32_768;
+
+// hfrosccfg
+const HFROSC_DIV: u32 = 0x3f;
+const HFROSC_TRIM_SHIFT: u32 = 16;
+const HFROSC_EN: u32 = 1 << 30;
+
+// hfxosccfg
+const HFXOSC_EN: u32 = 1 << 30;
+
+// pllcfg
+const PLL_R: u32 = 0x7;
+const PLL_F_SHIFT: u32 = 4;
+const PLL_F: u32 = 0x3f << PLL_F_SHIFT;
+const PLL_Q_SHIFT: u32 = 10;
+const PLL_Q: u32 = 0x3 << PLL_Q_SHIFT;
+const PLL_SEL: u32 = 1 << 16;
+const PLL_REFSEL: u32 = 1 << 17;
+const PLL_BYPASS: u32 = 1 << 18;
+
+// plloutdiv
+const PLLOUT_DIV: u32 = 0x3f;
+const PLLOUT_DIV_BY_1: u32 = 1 << 8;
 
 pub struct Prci {
     hfrosccfg: u32,
@@ -13,10 +46,12 @@ pub struct Prci {
 impl Prci {
     pub fn new() -> Self {
         Prci {
-            hfrosccfg: 0,
-            hfxosccfg: 0,
-            pllcfg: 0,
-            plloutdiv: 0,
+            // 72 MHz divided by 5
+            hfrosccfg: HFROSC_EN | 16 << HFROSC_TRIM_SHIFT | 4,
+            hfxosccfg: HFXOSC_EN,
+            // the PLL is bypassed and not selected.
+            pllcfg: 1 | 31 << PLL_F_SHIFT | 3 << PLL_Q_SHIFT | PLL_REFSEL | PLL_BYPASS,
+            plloutdiv: PLLOUT_DIV_BY_1,
             procmoncfg: 0,
         }
     }
@@ -25,6 +60,31 @@ impl Prci {
         // do nothing.
     }
 
+    /// Frequency of the core clock (hfclk) in Hz.
+    pub fn get_core_frequency(&self) -> u64 {
+        let hfrosc = PRCI_HFROSC_FREQUENCY / ((self.hfrosccfg & HFROSC_DIV) as u64 + 1);
+        if self.pllcfg & PLL_SEL == 0 {
+            return hfrosc;
+        }
+        let refclk = match self.pllcfg & PLL_REFSEL {
+            0 => hfrosc,
+            _ => PRCI_HFXOSC_FREQUENCY,
+        };
+        let pllout = match self.pllcfg & PLL_BYPASS {
+            0 => {
+                let r = (self.pllcfg & PLL_R) as u64 + 1;
+                let f = 2 * (((self.pllcfg & PLL_F) >> PLL_F_SHIFT) as u64 + 1);
+                let q = (self.pllcfg & PLL_Q) >> PLL_Q_SHIFT;
+                (refclk / r * f) >> q
+            }
+            _ => refclk,
+        };
+        match self.plloutdiv & PLLOUT_DIV_BY_1 {
+            0 => pllout / (2 * ((self.plloutdiv & PLLOUT_DIV) as u64 + 1)),
+            _ => pllout,
+        }
+    }
+
     pub fn read(&mut self, addr: u64) -> u32 {
         match addr & 0xff {
             0x00 => self.hfrosccfg | 0x8000_0000 /* OSC ready */,
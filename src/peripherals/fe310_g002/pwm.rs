@@ -0,0 +1,189 @@
+// PWM (Pulse Width Modulation)
+// https://static.dev.sifive.com/FE310-G000.pdf
+//
+// PWM0 has 8-bit comparators, PWM1 and PWM2 16-bit ones. The counter runs at
+// the core clock. The changes of the comparator outputs are recorded with
+// their cycle so the waveforms can be checked from the host.
+
+use std::collections::VecDeque;
+
+const PWM_COMPARATORS: usize = 4;
+/// Edges kept, the oldest are dropped.
+const PWM_EDGES_MAX: usize = 4096;
+
+// pwmcfg
+const PWM_CFG_SCALE: u32 = 0xf;
+const PWM_CFG_STICKY: u32 = 1 << 8;
+const PWM_CFG_ZEROCMP: u32 = 1 << 9;
+const PWM_CFG_DEGLITCH: u32 = 1 << 10;
+const PWM_CFG_ENALWAYS: u32 = 1 << 12;
+const PWM_CFG_ENONESHOT: u32 = 1 << 13;
+const PWM_CFG_CENTER_SHIFT: u32 = 16;
+const PWM_CFG_GANG_SHIFT: u32 = 24;
+const PWM_CFG_IP_SHIFT: u32 = 28;
+
+/// Change of the level of a comparator output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PwmEdge {
+    pub cycle: u64,
+    pub channel: usize,
+    pub level: bool,
+}
+
+pub struct Pwm {
+    /// Bits of the comparators
+    cmp_width: u32,
+    /// current clock cycle.
+    cycle: u64,
+    /// Configuration, without the interrupt pending bits
+    pwmcfg: u32,
+    /// Counter
+    pwmcount: u32,
+    /// Compare values
+    pwmcmp: [u32; PWM_COMPARATORS],
+    /// Interrupt pending bits, the comparator outputs
+    ip: u32,
+    /// Levels of the outputs, after the ganging
+    outputs: u32,
+    edges: VecDeque<PwmEdge>,
+}
+
+impl Pwm {
+    pub fn new(cmp_width: u32) -> Self {
+        Pwm {
+            cmp_width,
+            cycle: 0,
+            pwmcfg: 0,
+            pwmcount: 0,
+            pwmcmp: [0; PWM_COMPARATORS],
+            ip: 0,
+            outputs: 0,
+            edges: VecDeque::new(),
+        }
+    }
+
+    fn get_cmp_mask(&self) -> u32 {
+        (1 << self.cmp_width) - 1
+    }
+
+    fn get_count_mask(&self) -> u32 {
+        (1 << (self.cmp_width + 15)) - 1
+    }
+
+    /// Scaled counter compared to the compare values.
+    fn get_pwms(&self) -> u32 {
+        (self.pwmcount >> (self.pwmcfg & PWM_CFG_SCALE)) & self.get_cmp_mask()
+    }
+
+    pub fn tick(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+        if self.pwmcfg & (PWM_CFG_ENALWAYS | PWM_CFG_ENONESHOT) == 0 {
+            return;
+        }
+        // the counter goes back to zero after it matched pwmcmp0.
+        if self.pwmcfg & PWM_CFG_ZEROCMP != 0 && self.get_pwms() >= self.pwmcmp[0] {
+            self.pwmcount = 0;
+            self.pwmcfg &= !PWM_CFG_ENONESHOT;
+        } else {
+            self.pwmcount = (self.pwmcount + 1) & self.get_count_mask();
+        }
+
+        let pwms = self.get_pwms();
+        let msb = 1 << (self.cmp_width - 1);
+        let mut fired = 0;
+        for i in 0..PWM_COMPARATORS {
+            // centered comparators count down in the second half.
+            let value = match (self.pwmcfg >> (PWM_CFG_CENTER_SHIFT + i as u32)) & 1 {
+                1 if pwms & msb != 0 => pwms ^ self.get_cmp_mask(),
+                _ => pwms,
+            };
+            if value >= self.pwmcmp[i] {
+                fired |= 1 << i;
+            }
+        }
+        self.ip = match self.pwmcfg & PWM_CFG_STICKY {
+            0 => fired,
+            _ => self.ip | fired,
+        };
+        self.update_outputs();
+    }
+
+    /// A ganged comparator output is deasserted while the next one fires.
+    fn update_outputs(&mut self) {
+        let mut outputs = 0;
+        for i in 0..PWM_COMPARATORS {
+            let next = (i + 1) % PWM_COMPARATORS;
+            let gang = (self.pwmcfg >> (PWM_CFG_GANG_SHIFT + i as u32)) & 1 != 0;
+            let level = (self.ip >> i) & 1 != 0 && !(gang && (self.ip >> next) & 1 != 0);
+            outputs |= (level as u32) << i;
+        }
+        let changed = outputs ^ self.outputs;
+        for channel in 0..PWM_COMPARATORS {
+            if (changed >> channel) & 1 != 0 {
+                if self.edges.len() == PWM_EDGES_MAX {
+                    self.edges.pop_front();
+                }
+                self.edges.push_back(PwmEdge {
+                    cycle: self.cycle,
+                    channel,
+                    level: (outputs >> channel) & 1 != 0,
+                });
+            }
+        }
+        self.outputs = outputs;
+    }
+
+    /// Level of the output of a comparator.
+    pub fn get_output(&self, channel: usize) -> bool {
+        (self.outputs >> channel) & 1 != 0
+    }
+
+    /// The recorded edges, oldest first, since the last call.
+    pub fn take_edges(&mut self) -> Vec<PwmEdge> {
+        self.edges.drain(..).collect()
+    }
+
+    /// Interrupt of a comparator.
+    pub fn is_irq(&mut self, channel: usize) -> bool {
+        (self.ip >> channel) & 1 != 0
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr & 0xff {
+            0x00 => self.pwmcfg | self.ip << PWM_CFG_IP_SHIFT,
+            0x08 => self.pwmcount,
+            0x10 => self.get_pwms(),
+            0x20 => self.pwmcmp[0],
+            0x24 => self.pwmcmp[1],
+            0x28 => self.pwmcmp[2],
+            0x2c => self.pwmcmp[3],
+            n => panic!("Read reserved address: {:x}", n),
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        match addr & 0xff {
+            0x00 => {
+                self.pwmcfg = data
+                    & (PWM_CFG_SCALE
+                        | PWM_CFG_STICKY
+                        | PWM_CFG_ZEROCMP
+                        | PWM_CFG_DEGLITCH
+                        | PWM_CFG_ENALWAYS
+                        | PWM_CFG_ENONESHOT
+                        | 0xf << PWM_CFG_CENTER_SHIFT
+                        | 0xf << PWM_CFG_GANG_SHIFT);
+                // the pending bits are written by the software too.
+                self.ip = data >> PWM_CFG_IP_SHIFT;
+                self.update_outputs();
+            }
+            0x08 => self.pwmcount = data & self.get_count_mask(),
+            0x10 => {} // read only
+            0x20 => self.pwmcmp[0] = data & self.get_cmp_mask(),
+            0x24 => self.pwmcmp[1] = data & self.get_cmp_mask(),
+            0x28 => self.pwmcmp[2] = data & self.get_cmp_mask(),
+            0x2c => self.pwmcmp[3] = data & self.get_cmp_mask(),
+            n => panic!("Write reserved address: {:x}", n),
+        }
+    }
+}
@@ -0,0 +1,352 @@
+// FE310 SPI controller (QSPI0, SPI1 and SPI2)
+// https://static.dev.sifive.com/FE310-G000.pdf
+//
+// The frames are exchanged with the selected device as soon as they are
+// written to txdata, so the transmit FIFO is always empty. The frames are 8
+// bits long on any protocol. QSPI0 also maps the flash into the memory with
+// the instruction described by ffmt.
+
+use crate::peripherals::spi::SpiDevice;
+
+use std::collections::VecDeque;
+
+const SPI_FIFO_DEPTH: usize = 8;
+
+// csmode
+const SPI_CSMODE_AUTO: u32 = 0;
+const SPI_CSMODE_HOLD: u32 = 2;
+const SPI_CSMODE_OFF: u32 = 3;
+
+// fmt
+const SPI_FMT_PROTO: u32 = 0x3;
+const SPI_FMT_ENDIAN_LSB: u32 = 0x4;
+const SPI_FMT_DIR_TX: u32 = 0x8;
+
+const SPI_RXDATA_EMPTY: u32 = 0x8000_0000;
+
+const SPI_FCTRL_EN: u32 = 0x1;
+
+// Flash commands to load the images
+const SPI_FLASH_WRITE_ENABLE: u8 = 0x06;
+const SPI_FLASH_PAGE_PROGRAM: u8 = 0x02;
+const SPI_FLASH_PAGE_SIZE: usize = 256;
+
+// ie and ip
+const SPI_TXWM: u32 = 0x1;
+const SPI_RXWM: u32 = 0x2;
+
+pub struct Fe310Spi {
+    /// Serial clock divisor
+    sckdiv: u32,
+    /// Serial clock mode
+    sckmode: u32,
+    /// Chip select ID
+    csid: u32,
+    /// Chip select default (inactive levels)
+    csdef: u32,
+    /// Chip select mode
+    csmode: u32,
+    /// Delay control
+    delay0: u32,
+    delay1: u32,
+    /// Frame format
+    fmt: u32,
+    /// Receive FIFO
+    rx_fifo: VecDeque<u8>,
+    /// Transmit watermark
+    txmark: u32,
+    /// Receive watermark
+    rxmark: u32,
+    /// SPI flash interface control (QSPI0)
+    fctrl: u32,
+    /// SPI flash instruction format (QSPI0)
+    ffmt: u32,
+    /// Interrupt enable
+    ie: u32,
+    /// the controller has the flash interface.
+    flash: bool,
+    /// the hardware asserts the chip select of csid.
+    cs_active: bool,
+    /// Devices on the chip select lines, and their selection.
+    devices: Vec<Option<Box<dyn SpiDevice>>>,
+    selected: Vec<bool>,
+}
+
+/// Number of lines of the protocol (single, dual or quad).
+fn get_lanes(proto: u32) -> u32 {
+    match proto & 0x3 {
+        0 => 1,
+        1 => 2,
+        _ => 4,
+    }
+}
+
+impl Fe310Spi {
+    /// A controller with the chip select lines, and the flash interface
+    /// (QSPI0).
+    pub fn new(cs_width: usize, flash: bool) -> Self {
+        let mut devices = vec![];
+        devices.resize_with(cs_width, || None);
+        let mut spi = Fe310Spi {
+            sckdiv: 0,
+            sckmode: 0,
+            csid: 0,
+            csdef: 0,
+            csmode: 0,
+            delay0: 0,
+            delay1: 0,
+            fmt: 0,
+            rx_fifo: VecDeque::new(),
+            txmark: 0,
+            rxmark: 0,
+            fctrl: 0,
+            ffmt: 0,
+            ie: 0,
+            flash,
+            cs_active: false,
+            devices,
+            selected: vec![false; cs_width],
+        };
+        spi.reset();
+        spi
+    }
+
+    /// Reset the registers, the devices are kept and deselected.
+    pub fn reset(&mut self) {
+        let cs_width = self.devices.len();
+        self.sckdiv = 0x3;
+        self.sckmode = 0;
+        self.csid = 0;
+        self.csdef = (1 << cs_width) - 1;
+        self.csmode = SPI_CSMODE_AUTO;
+        self.delay0 = 0x0001_0001;
+        self.delay1 = 0x0000_0001;
+        self.fmt = 0x0008_0000; // 8 bits, single, MSB first
+        self.rx_fifo.clear();
+        self.txmark = match self.flash {
+            true => 1,
+            false => 0,
+        };
+        self.rxmark = 0;
+        self.fctrl = match self.flash {
+            true => SPI_FCTRL_EN,
+            false => 0,
+        };
+        // READ (0x03) with 3 address bytes
+        self.ffmt = 0x0003_0007;
+        self.ie = 0;
+        self.cs_active = false;
+        self.update_select();
+    }
+
+    /// Plug a device on a chip select line (Err if the line does not exist).
+    pub fn attach_device(&mut self, cs: usize, device: Box<dyn SpiDevice>) -> Result<(), ()> {
+        if cs >= self.devices.len() {
+            return Err(());
+        }
+        self.devices[cs] = Some(device);
+        self.selected[cs] = false;
+        self.update_select();
+        Ok(())
+    }
+
+    pub fn tick(&mut self) {
+        // do nothing.
+    }
+
+    fn get_ip(&self) -> u32 {
+        let mut ip = 0;
+        // the transmit FIFO is always empty.
+        if self.txmark > 0 {
+            ip |= SPI_TXWM;
+        }
+        if self.rx_fifo.len() > self.rxmark as usize {
+            ip |= SPI_RXWM;
+        }
+        ip
+    }
+
+    pub fn is_irq(&mut self) -> bool {
+        self.get_ip() & self.ie != 0
+    }
+
+    /// Drive the chip select lines, active low: the line of csid is asserted
+    /// by the hardware and the others stay at their default level.
+    fn update_select(&mut self) {
+        for cs in 0..self.devices.len() {
+            let level = (self.csdef >> cs) & 1 != 0;
+            let active = self.cs_active && cs == self.csid as usize;
+            let selected = level == active;
+            if selected != self.selected[cs] {
+                self.selected[cs] = selected;
+                if let Some(device) = self.devices[cs].as_mut() {
+                    device.select(selected);
+                }
+            }
+        }
+    }
+
+    fn set_cs_active(&mut self, active: bool) {
+        self.cs_active = active;
+        self.update_select();
+    }
+
+    /// Exchange a frame with the selected device, the data is all ones
+    /// without a device.
+    fn exchange(&mut self, data: u8) -> u8 {
+        let cs = self.csid as usize;
+        match self.devices.get_mut(cs) {
+            Some(Some(device)) if self.selected[cs] => device.transfer(data),
+            _ => 0xff,
+        }
+    }
+
+    fn transmit(&mut self, data: u8) {
+        let lsb_first = self.fmt & SPI_FMT_ENDIAN_LSB != 0;
+        let data = match lsb_first {
+            true => data.reverse_bits(),
+            false => data,
+        };
+        if self.csmode != SPI_CSMODE_OFF {
+            self.set_cs_active(true);
+        }
+        let received = self.exchange(data);
+        if self.csmode == SPI_CSMODE_AUTO {
+            self.set_cs_active(false);
+        }
+        let received = match lsb_first {
+            true => received.reverse_bits(),
+            false => received,
+        };
+        if self.fmt & SPI_FMT_DIR_TX == 0 && self.rx_fifo.len() < SPI_FIFO_DEPTH {
+            self.rx_fifo.push_back(received);
+        }
+    }
+
+    /// Read from the memory mapped flash with the instruction of ffmt (Err
+    /// if the flash interface is disabled).
+    pub fn read_flash(&mut self, offset: u64, size: usize) -> Result<u64, ()> {
+        if !self.flash || self.fctrl & SPI_FCTRL_EN == 0 {
+            return Err(());
+        }
+        let cmd_en = self.ffmt & 0x1 != 0;
+        let addr_len = (self.ffmt >> 1) & 0x7;
+        let pad_cnt = (self.ffmt >> 4) & 0xf;
+        let data_proto = (self.ffmt >> 12) & 0x3;
+        let cmd_code = (self.ffmt >> 16) as u8;
+        let pad_code = (self.ffmt >> 24) as u8;
+
+        self.set_cs_active(true);
+        if cmd_en {
+            self.exchange(cmd_code);
+        }
+        for i in (0..addr_len).rev() {
+            self.exchange((offset >> (i * 8)) as u8);
+        }
+        // the dummy cycles are counted on the lines of the data.
+        for _ in 0..(pad_cnt * get_lanes(data_proto)).div_ceil(8) {
+            self.exchange(pad_code);
+        }
+        let mut data = 0;
+        for i in 0..size {
+            data |= (self.exchange(0xff) as u64) << (i * 8);
+        }
+        self.set_cs_active(false);
+        Ok(data)
+    }
+
+    /// Program the memory mapped flash with the page program command, like
+    /// a programmer loading an image (Err if the flash interface is
+    /// disabled).
+    pub fn write_flash(&mut self, offset: u64, data: &[u8]) -> Result<(), ()> {
+        if !self.flash || self.fctrl & SPI_FCTRL_EN == 0 {
+            return Err(());
+        }
+        // a program wraps in its page.
+        let mut offset = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let len = data
+                .len()
+                .min(SPI_FLASH_PAGE_SIZE - (offset as usize % SPI_FLASH_PAGE_SIZE));
+            self.set_cs_active(true);
+            self.exchange(SPI_FLASH_WRITE_ENABLE);
+            self.set_cs_active(false);
+            self.set_cs_active(true);
+            self.exchange(SPI_FLASH_PAGE_PROGRAM);
+            for i in (0..3).rev() {
+                self.exchange((offset >> (i * 8)) as u8);
+            }
+            for byte in &data[..len] {
+                self.exchange(*byte);
+            }
+            self.set_cs_active(false);
+            offset += len as u64;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr & 0xfff {
+            0x00 => self.sckdiv,
+            0x04 => self.sckmode,
+            0x10 => self.csid,
+            0x14 => self.csdef,
+            0x18 => self.csmode,
+            0x28 => self.delay0,
+            0x2c => self.delay1,
+            0x40 => self.fmt,
+            0x48 => 0, // the transmit FIFO is never full.
+            0x4c => match self.rx_fifo.pop_front() {
+                Some(data) => data as u32,
+                None => SPI_RXDATA_EMPTY,
+            },
+            0x50 => self.txmark,
+            0x54 => self.rxmark,
+            0x60 if self.flash => self.fctrl,
+            0x64 if self.flash => self.ffmt,
+            0x70 => self.ie,
+            0x74 => self.get_ip(),
+            n => panic!("Read reserved address: {:x}", n),
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        match addr & 0xfff {
+            0x00 => self.sckdiv = data & 0xfff,
+            0x04 => self.sckmode = data & 0x3,
+            // the hardware chip select is released by a change of csid,
+            // csdef or csmode.
+            0x10 => {
+                self.csid = data;
+                self.set_cs_active(false);
+            }
+            0x14 => {
+                self.csdef = data & ((1 << self.devices.len()) - 1);
+                self.set_cs_active(false);
+            }
+            0x18 => {
+                self.csmode = data & 0x3;
+                if self.csmode != SPI_CSMODE_HOLD {
+                    self.set_cs_active(false);
+                }
+            }
+            0x28 => self.delay0 = data & 0x00ff_00ff,
+            0x2c => self.delay1 = data & 0x00ff_00ff,
+            0x40 => {
+                self.fmt =
+                    data & (0x000f_0000 | SPI_FMT_DIR_TX | SPI_FMT_ENDIAN_LSB | SPI_FMT_PROTO)
+            }
+            0x48 => self.transmit(data as u8),
+            0x4c => {} // read only
+            0x50 => self.txmark = data & 0x7,
+            0x54 => self.rxmark = data & 0x7,
+            0x60 if self.flash => self.fctrl = data & SPI_FCTRL_EN,
+            0x64 if self.flash => self.ffmt = data,
+            0x70 => self.ie = data & (SPI_TXWM | SPI_RXWM),
+            0x74 => {} // read only
+            n => panic!("Write reserved address: {:x}", n),
+        }
+    }
+}
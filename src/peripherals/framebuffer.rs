@@ -0,0 +1,101 @@
+// Linear framebuffer, described as "simple-framebuffer" in the device tree.
+// https://www.kernel.org/doc/Documentation/devicetree/bindings/display/simple-framebuffer.yaml
+//
+// The guest draws pixels in the a8r8g8b8 format (32 bits little endian,
+// the alpha is ignored) and the host renders them when it needs an image.
+
+use crate::peripherals::memory::Memory;
+use crate::png;
+
+pub const FRAMEBUFFER_WIDTH: usize = 640;
+pub const FRAMEBUFFER_HEIGHT: usize = 480;
+pub const FRAMEBUFFER_BYTES_PER_PIXEL: usize = 4;
+
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    memory: Memory,
+    /// the guest has drawn since the last rendering.
+    dirty: bool,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            memory: Memory::new(width * height * FRAMEBUFFER_BYTES_PER_PIXEL),
+            dirty: false,
+        }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    /// Bytes per line.
+    pub fn get_stride(&self) -> usize {
+        self.width * FRAMEBUFFER_BYTES_PER_PIXEL
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.memory.mem.len()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Access to the pixels with 1, 2, 4 or 8 bytes (Err out of the buffer).
+    pub fn read(&self, offset: u64, size: usize) -> Result<u64, ()> {
+        if offset + size as u64 > self.get_size() as u64 {
+            return Err(());
+        }
+        Ok(match size {
+            1 => self.memory.read8(offset) as u64,
+            2 => self.memory.read16(offset) as u64,
+            4 => self.memory.read32(offset) as u64,
+            _ => self.memory.read64(offset),
+        })
+    }
+
+    pub fn write(&mut self, offset: u64, data: u64, size: usize) -> Result<(), ()> {
+        if offset + size as u64 > self.get_size() as u64 {
+            return Err(());
+        }
+        match size {
+            1 => self.memory.write8(offset, data as u8),
+            2 => self.memory.write16(offset, data as u16),
+            4 => self.memory.write32(offset, data as u32),
+            _ => self.memory.write64(offset, data),
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Pixel at the position as 0x00rrggbb.
+    pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
+        let offset = y * self.get_stride() + x * FRAMEBUFFER_BYTES_PER_PIXEL;
+        self.memory.read32(offset as u64) & 0x00ff_ffff
+    }
+
+    /// Render the pixels to 8 bits RGB, row by row.
+    pub fn render(&mut self) -> Vec<u8> {
+        self.dirty = false;
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for pixel in self.memory.mem.chunks(FRAMEBUFFER_BYTES_PER_PIXEL) {
+            rgb.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+        rgb
+    }
+
+    /// Render the pixels as a PNG image.
+    pub fn screenshot(&mut self) -> Vec<u8> {
+        let rgb = self.render();
+        png::encode_rgb(self.width, self.height, &rgb)
+    }
+}
@@ -3,9 +3,16 @@
 
 use crate::peripherals::timer::Timer;
 
+/// Core cycles per mtime tick unless the frequencies are set
+const CLINT_DEFAULT_DIVIDER: u64 = 0xfffff;
+
 pub struct Clint {
-    /// current clock cycle.
-    cycle: u64,
+    /// Frequencies of the core clock and of RTCCLK
+    core_frequency: u64,
+    rtc_frequency: u64,
+    /// RTCCLK cycles accumulated by the core cycles, mtime advances at each
+    /// core_frequency.
+    rtc_phase: u64,
     // Machine-mode software interrupts are generated by writing to the memory-mapped control register msip.
     // Each msip register is a 32-bit wide WARL register where the upper 31 bits are tied to
     // 0. The least significant bit is reflected in the MSIP bit of the mip CSR. Other bits in the msip
@@ -23,7 +30,9 @@ pub struct Clint {
 impl Clint {
     pub fn new() -> Self {
         Clint {
-            cycle: 0,
+            core_frequency: CLINT_DEFAULT_DIVIDER,
+            rtc_frequency: 1,
+            rtc_phase: 0,
             msip: [0; 5],
             mtimecmp: [0; 5],
             mtime: 0,
@@ -41,15 +50,21 @@ impl Clint {
 
 impl Timer for Clint {
     fn tick(&mut self) {
-        self.cycle = self.cycle.wrapping_add(1);
-
-        // TODO: Correctly care for the clock frequency (1MHz clock @ RTCCLK).
-        // The current settings have no reason.
-        if self.cycle % 0xfffff == 0 {
+        // TODO: Correctly care for the clock frequency of the machines
+        // without a clock tree (1MHz clock @ RTCCLK).
+        self.rtc_phase += self.rtc_frequency;
+        if self.rtc_phase >= self.core_frequency {
+            self.rtc_phase -= self.core_frequency;
             self.mtime = self.mtime.wrapping_add(1);
         }
     }
 
+    fn set_frequency(&mut self, core_frequency: u64, rtc_frequency: u64) {
+        self.core_frequency = core_frequency.max(1);
+        self.rtc_frequency = rtc_frequency;
+        self.rtc_phase = 0;
+    }
+
     fn is_pending_software_interrupt(&mut self, core: usize) -> bool {
         self.msip[core] & 0x1 > 0
     }
@@ -0,0 +1,86 @@
+// DDR subsystem of the FU540: the Cadence DDR controller and PHY registers,
+// and the bus blocker in front of the memory
+// https://static.dev.sifive.com/FU540-C000-v1.0.pdf
+// https://github.com/sifive/freedom-u540-c000-bootloader/blob/master/ux00boot/ux00ddr.h
+//
+// The memory is always usable. The registers keep what is written, and the
+// initialization completes as soon as it is started.
+
+/// Controller registers, then the PHY registers
+const DDR_CTL_SIZE: u64 = 0x2000;
+const DDR_PHY_SIZE: u64 = 0x2000;
+const DDR_BLOCKER_SIZE: u64 = 0x1000;
+
+// DENALI_CTL_0
+const DDR_CTL_START_REGISTER: usize = 0;
+const DDR_CTL_START: u32 = 1 << 0;
+// DENALI_CTL_132
+const DDR_CTL_INT_STATUS_REGISTER: usize = 132;
+const DDR_CTL_MC_INIT_COMPLETE: u32 = 1 << 8;
+
+pub struct DdrController {
+    ctl: Vec<u32>,
+    phy: Vec<u32>,
+    blocker: Vec<u32>,
+}
+
+impl Default for DdrController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DdrController {
+    pub fn new() -> Self {
+        DdrController {
+            ctl: vec![0; (DDR_CTL_SIZE / 4) as usize],
+            phy: vec![0; (DDR_PHY_SIZE / 4) as usize],
+            blocker: vec![0; (DDR_BLOCKER_SIZE / 4) as usize],
+        }
+    }
+
+    /// The controller finished the initialization of the memory.
+    pub fn is_initialized(&self) -> bool {
+        self.ctl[DDR_CTL_INT_STATUS_REGISTER] & DDR_CTL_MC_INIT_COMPLETE != 0
+    }
+
+    /// Read a register of the controller or of the PHY.
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr {
+            0..=0x1FFF => self.ctl[(addr / 4) as usize],
+            0x2000..=0x3FFF => self.phy[((addr - DDR_CTL_SIZE) / 4) as usize],
+            _ => panic!("Read reserved address: {:x}", addr),
+        }
+    }
+
+    /// Write a register of the controller or of the PHY.
+    pub fn write(&mut self, addr: u64, data: u32) {
+        match addr {
+            0..=0x1FFF => {
+                let index = (addr / 4) as usize;
+                self.ctl[index] = data;
+                if index == DDR_CTL_START_REGISTER && data & DDR_CTL_START != 0 {
+                    self.ctl[DDR_CTL_INT_STATUS_REGISTER] |= DDR_CTL_MC_INIT_COMPLETE;
+                }
+            }
+            0x2000..=0x3FFF => self.phy[((addr - DDR_CTL_SIZE) / 4) as usize] = data,
+            _ => panic!("Write reserved address: {:x}", addr),
+        }
+    }
+
+    /// Read a register of the bus blocker.
+    pub fn read_blocker(&mut self, addr: u64) -> u32 {
+        match addr {
+            0..=0xFFF => self.blocker[(addr / 4) as usize],
+            _ => panic!("Read reserved address: {:x}", addr),
+        }
+    }
+
+    /// Write a register of the bus blocker.
+    pub fn write_blocker(&mut self, addr: u64, data: u32) {
+        match addr {
+            0..=0xFFF => self.blocker[(addr / 4) as usize] = data,
+            _ => panic!("Write reserved address: {:x}", addr),
+        }
+    }
+}
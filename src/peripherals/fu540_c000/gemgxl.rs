@@ -0,0 +1,477 @@
+// Cadence GEMGXL ethernet MAC of the FU540, with its PHY and the
+// gemgxl-mgmt clock select register
+// https://static.dev.sifive.com/FU540-C000-v1.0.pdf
+// https://www.xilinx.com/support/documentation/user_guides/ug1085-zynq-ultrascale-trm.pdf (GEM)
+//
+// One priority queue with 32-bit descriptors, the transmitter processes a
+// descriptor per cycle. The frames are exchanged with
+// a network backend without FCS, as if DRFCS were set. A frame received
+// without a free buffer is dropped.
+
+use crate::net::NetBackend;
+use crate::peripherals::virtio::queue::GuestMemory;
+
+/// Cycles between two polls of the backend for received frames.
+const GEM_POLL_INTERVAL: u64 = 0x400;
+const GEM_PHY_ADDRESS: u32 = 0;
+
+// Registers
+const GEM_NCR: u64 = 0x000;
+const GEM_NCFGR: u64 = 0x004;
+const GEM_NSR: u64 = 0x008;
+const GEM_DMACFG: u64 = 0x010;
+const GEM_TSR: u64 = 0x014;
+const GEM_RBQP: u64 = 0x018;
+const GEM_TBQP: u64 = 0x01C;
+const GEM_RSR: u64 = 0x020;
+const GEM_ISR: u64 = 0x024;
+const GEM_IER: u64 = 0x028;
+const GEM_IDR: u64 = 0x02C;
+const GEM_IMR: u64 = 0x030;
+const GEM_MAN: u64 = 0x034;
+const GEM_SA1B: u64 = 0x088;
+const GEM_SA1T: u64 = 0x08C;
+const GEM_MID: u64 = 0x0FC;
+const GEM_STATS: u64 = 0x100;
+const GEM_STATS_END: u64 = 0x1FC;
+const GEM_DCFG1: u64 = 0x280;
+const GEM_REGISTERS_SIZE: u64 = 0x2000;
+
+// NCR bits
+const NCR_RE: u32 = 1 << 2;
+const NCR_TE: u32 = 1 << 3;
+const NCR_TSTART: u32 = 1 << 9;
+const NCR_THALT: u32 = 1 << 10;
+
+// NCFGR bits
+const NCFGR_CAF: u32 = 1 << 4;
+const NCFGR_NBC: u32 = 1 << 5;
+const NCFGR_MTI: u32 = 1 << 6;
+const NCFGR_RBOF_SHIFT: u32 = 14;
+const NCFGR_RBOF_MASK: u32 = 0x3;
+
+// NSR bits
+const NSR_MDIO: u32 = 1 << 1;
+const NSR_IDLE: u32 = 1 << 2;
+
+// TSR bits
+const TSR_UBR: u32 = 1 << 0;
+const TSR_TGO: u32 = 1 << 3;
+const TSR_TXCOMP: u32 = 1 << 5;
+
+// RSR bits
+const RSR_BNA: u32 = 1 << 0;
+const RSR_REC: u32 = 1 << 1;
+
+// Interrupt bits of ISR, IER, IDR and IMR
+const ISR_MFD: u32 = 1 << 0;
+const ISR_RCOMP: u32 = 1 << 1;
+const ISR_RXUBR: u32 = 1 << 2;
+const ISR_TXUBR: u32 = 1 << 3;
+const ISR_TCOMP: u32 = 1 << 7;
+const ISR_MASK: u32 = 0x07ff_ffff;
+
+// MAN fields
+const MAN_RW_SHIFT: u32 = 28;
+const MAN_RW_WRITE: u32 = 1;
+const MAN_RW_READ: u32 = 2;
+const MAN_PHYA_SHIFT: u32 = 23;
+const MAN_REGA_SHIFT: u32 = 18;
+
+/// Module ID of a GEM (IDNUM 2), which the drivers check.
+const GEM_MID_VALUE: u32 = 0x0002_0118;
+/// IRQCOR (clear on read) and a 32-bit data bus.
+const GEM_DCFG1_VALUE: u32 = (1 << 23) | (1 << 25);
+
+// DMACFG receive buffer size, in units of 64 bytes
+const DMACFG_RXBS_SHIFT: u32 = 16;
+const DMACFG_RXBS_MASK: u32 = 0xff;
+
+// Receive descriptor bits
+const RX_DESC_USED: u32 = 1 << 0;
+const RX_DESC_WRAP: u32 = 1 << 1;
+const RX_DESC_ADDR_MASK: u32 = !0x3;
+const RX_DESC_SOF: u32 = 1 << 14;
+const RX_DESC_EOF: u32 = 1 << 15;
+const RX_DESC_SA1_MATCH: u32 = 1 << 27;
+const RX_DESC_BROADCAST: u32 = 1 << 31;
+
+// Transmit descriptor bits
+const TX_DESC_LEN_MASK: u32 = 0x3fff;
+const TX_DESC_LAST: u32 = 1 << 15;
+const TX_DESC_WRAP: u32 = 1 << 30;
+const TX_DESC_USED: u32 = 1 << 31;
+
+const DESCRIPTOR_SIZE: u64 = 8;
+
+// PHY registers (Microsemi VSC8541 of the HiFive Unleashed)
+const PHY_BMCR: usize = 0;
+const PHY_BMCR_RESET: u16 = 1 << 15;
+const PHY_BMCR_ANRESTART: u16 = 1 << 9;
+const PHY_RESET_VALUES: [(usize, u16); 9] = [
+    (PHY_BMCR, 0x1140),
+    (1, 0x796d),  // BMSR: link up, auto-negotiation complete
+    (2, 0x0007),  // PHYSID1
+    (3, 0x0772),  // PHYSID2
+    (4, 0x01e1),  // ADVERTISE
+    (5, 0xc5e1),  // LPA
+    (9, 0x0300),  // CTRL1000
+    (10, 0x3c00), // STAT1000
+    (15, 0x3000), // ESTATUS
+];
+
+pub struct Gemgxl {
+    cycle: u64,
+    /// Registers without a behavior, as written
+    registers: Vec<u32>,
+    ncr: u32,
+    ncfgr: u32,
+    dmacfg: u32,
+    tsr: u32,
+    rsr: u32,
+    isr: u32,
+    imr: u32,
+    man: u32,
+    /// Base of the receive and transmit descriptor lists
+    rbqp: u32,
+    tbqp: u32,
+    /// Descriptor the receiver and the transmitter use next
+    rx_desc: u32,
+    tx_desc: u32,
+    /// Frame being gathered from the transmit buffers, and its first
+    /// descriptor
+    tx_frame: Vec<u8>,
+    tx_first: u32,
+    /// Transmit clock select of the gemgxl-mgmt block
+    tx_clk_sel: u32,
+    phy: [u16; 32],
+    backend: Option<Box<dyn NetBackend>>,
+}
+
+impl Default for Gemgxl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gemgxl {
+    pub fn new() -> Self {
+        let mut gem = Gemgxl {
+            cycle: 0,
+            registers: vec![0; (GEM_REGISTERS_SIZE / 4) as usize],
+            ncr: 0,
+            ncfgr: 0,
+            dmacfg: 0,
+            tsr: 0,
+            rsr: 0,
+            isr: 0,
+            imr: 0,
+            man: 0,
+            rbqp: 0,
+            tbqp: 0,
+            rx_desc: 0,
+            tx_desc: 0,
+            tx_frame: vec![],
+            tx_first: 0,
+            tx_clk_sel: 0,
+            phy: [0; 32],
+            backend: None,
+        };
+        gem.reset();
+        gem
+    }
+
+    /// Reset the registers, the backend is kept.
+    pub fn reset(&mut self) {
+        for register in self.registers.iter_mut() {
+            *register = 0;
+        }
+        self.ncr = 0;
+        self.ncfgr = 0x0008_0000;
+        self.dmacfg = 0x0002_0784;
+        self.tsr = 0;
+        self.rsr = 0;
+        self.isr = 0;
+        self.imr = ISR_MASK;
+        self.man = 0;
+        self.rbqp = 0;
+        self.tbqp = 0;
+        self.rx_desc = 0;
+        self.tx_desc = 0;
+        self.tx_frame.clear();
+        self.tx_clk_sel = 0;
+        self.phy = [0; 32];
+        for (register, value) in PHY_RESET_VALUES.iter() {
+            self.phy[*register] = *value;
+        }
+    }
+
+    /// Connect the MAC to a network backend.
+    pub fn set_backend(&mut self, backend: Box<dyn NetBackend>) {
+        self.backend = Some(backend);
+    }
+
+    pub fn has_backend(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// The station address, from SA1B and SA1T.
+    pub fn get_mac_address(&self) -> [u8; 6] {
+        let bottom = self.registers[(GEM_SA1B / 4) as usize].to_le_bytes();
+        let top = self.registers[(GEM_SA1T / 4) as usize].to_le_bytes();
+        [bottom[0], bottom[1], bottom[2], bottom[3], top[0], top[1]]
+    }
+
+    pub fn tick(&mut self, mem: &mut GuestMemory) {
+        self.cycle = self.cycle.wrapping_add(1);
+        if self.tsr & TSR_TGO != 0 {
+            self.transmit(mem);
+        }
+        if self.cycle.is_multiple_of(GEM_POLL_INTERVAL) {
+            self.receive(mem);
+        }
+    }
+
+    pub fn is_irq(&mut self) -> bool {
+        self.isr & !self.imr != 0
+    }
+
+    /// Process the next transmit descriptor, a frame is sent with its last
+    /// buffer. The transmitter stops at a used descriptor.
+    fn transmit(&mut self, mem: &mut GuestMemory) {
+        let addr = self.tx_desc as u64;
+        let control = mem.read32(addr + 4);
+        if control & TX_DESC_USED != 0 {
+            self.tsr = (self.tsr & !TSR_TGO) | TSR_UBR;
+            self.isr |= ISR_TXUBR;
+            return;
+        }
+        if self.tx_frame.is_empty() {
+            self.tx_first = self.tx_desc;
+        }
+        let mut buffer = vec![0; (control & TX_DESC_LEN_MASK) as usize];
+        mem.read_bytes(mem.read32(addr) as u64, &mut buffer);
+        self.tx_frame.extend_from_slice(&buffer);
+        self.tx_desc = match control & TX_DESC_WRAP {
+            0 => self.tx_desc.wrapping_add(DESCRIPTOR_SIZE as u32),
+            _ => self.tbqp,
+        };
+        if control & TX_DESC_LAST != 0 {
+            if let Some(backend) = &mut self.backend {
+                backend.send(&self.tx_frame);
+            }
+            self.tx_frame.clear();
+            // the driver reclaims the buffers from the first descriptor of
+            // the frame.
+            let first = self.tx_first as u64 + 4;
+            mem.write32(first, mem.read32(first) | TX_DESC_USED);
+            self.tsr |= TSR_TXCOMP;
+            self.isr |= ISR_TCOMP;
+        }
+    }
+
+    /// The frame passes the address filter.
+    fn accepts(&self, frame: &[u8]) -> bool {
+        if frame.len() < 6 || self.ncfgr & NCFGR_CAF != 0 {
+            return true;
+        }
+        if frame[0..6] == [0xff; 6] {
+            return self.ncfgr & NCFGR_NBC == 0;
+        }
+        if frame[0] & 1 != 0 {
+            return self.ncfgr & NCFGR_MTI != 0;
+        }
+        frame[0..6] == self.get_mac_address()
+    }
+
+    /// Poll the backend and store its frames into the receive buffers.
+    fn receive(&mut self, mem: &mut GuestMemory) {
+        loop {
+            let frame = match &mut self.backend {
+                Some(backend) => match backend.recv() {
+                    Some(frame) => frame,
+                    None => return,
+                },
+                None => return,
+            };
+            if self.ncr & NCR_RE == 0 || !self.accepts(&frame) {
+                continue;
+            }
+            self.receive_frame(mem, &frame);
+        }
+    }
+
+    fn get_rx_buffer_size(&self) -> usize {
+        (((self.dmacfg >> DMACFG_RXBS_SHIFT) & DMACFG_RXBS_MASK) as usize * 64).max(64)
+    }
+
+    fn receive_frame(&mut self, mem: &mut GuestMemory, frame: &[u8]) {
+        let buffer_size = self.get_rx_buffer_size();
+        let offset = ((self.ncfgr >> NCFGR_RBOF_SHIFT) & NCFGR_RBOF_MASK) as usize;
+
+        // the descriptors of the whole frame have to be free.
+        let mut descriptors = vec![];
+        let mut desc = self.rx_desc;
+        let mut remaining = frame.len() + offset;
+        while remaining > 0 {
+            let address = mem.read32(desc as u64);
+            if address & RX_DESC_USED != 0 {
+                self.rsr |= RSR_BNA;
+                self.isr |= ISR_RXUBR;
+                return;
+            }
+            descriptors.push(desc);
+            remaining = remaining.saturating_sub(buffer_size);
+            desc = match address & RX_DESC_WRAP {
+                0 => desc.wrapping_add(DESCRIPTOR_SIZE as u32),
+                _ => self.rbqp,
+            };
+        }
+
+        let mut data = frame;
+        let count = descriptors.len();
+        for (i, desc) in descriptors.iter().enumerate() {
+            let address = mem.read32(*desc as u64);
+            let skip = if i == 0 { offset } else { 0 };
+            let len = data.len().min(buffer_size - skip);
+            mem.write_bytes(
+                (address & RX_DESC_ADDR_MASK) as u64 + skip as u64,
+                &data[..len],
+            );
+            data = &data[len..];
+
+            let mut status = 0;
+            if i == 0 {
+                status |= RX_DESC_SOF;
+            }
+            if i == count - 1 {
+                status |= RX_DESC_EOF | frame.len() as u32;
+                if frame.len() >= 6 && frame[0..6] == [0xff; 6] {
+                    status |= RX_DESC_BROADCAST;
+                } else if frame.len() >= 6 && frame[0..6] == self.get_mac_address() {
+                    status |= RX_DESC_SA1_MATCH;
+                }
+            }
+            mem.write32(*desc as u64 + 4, status);
+            mem.write32(*desc as u64, address | RX_DESC_USED);
+        }
+        self.rx_desc = desc;
+        self.rsr |= RSR_REC;
+        self.isr |= ISR_RCOMP;
+    }
+
+    /// Run a clause 22 frame of the PHY management interface.
+    fn manage(&mut self, data: u32) {
+        let phy = (data >> MAN_PHYA_SHIFT) & 0x1f;
+        let register = ((data >> MAN_REGA_SHIFT) & 0x1f) as usize;
+        self.man = data;
+        match (data >> MAN_RW_SHIFT) & 0x3 {
+            MAN_RW_READ => {
+                let value = match phy {
+                    GEM_PHY_ADDRESS => self.phy[register],
+                    _ => 0xffff,
+                };
+                self.man = (data & !0xffff) | value as u32;
+            }
+            MAN_RW_WRITE if phy == GEM_PHY_ADDRESS => {
+                let mut value = data as u16;
+                if register == PHY_BMCR {
+                    // reset and restart of the auto-negotiation complete
+                    // at once.
+                    if value & PHY_BMCR_RESET != 0 {
+                        value = PHY_RESET_VALUES[0].1;
+                    }
+                    value &= !PHY_BMCR_ANRESTART;
+                }
+                self.phy[register] = value;
+            }
+            _ => {}
+        }
+        self.isr |= ISR_MFD;
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr {
+            GEM_NCR => self.ncr,
+            GEM_NCFGR => self.ncfgr,
+            GEM_NSR => NSR_MDIO | NSR_IDLE,
+            GEM_DMACFG => self.dmacfg,
+            GEM_TSR => self.tsr,
+            GEM_RBQP => self.rx_desc,
+            GEM_TBQP => self.tx_desc,
+            GEM_RSR => self.rsr,
+            GEM_ISR => {
+                let isr = self.isr;
+                self.isr = 0;
+                isr
+            }
+            GEM_IER | GEM_IDR => 0,
+            GEM_IMR => self.imr,
+            GEM_MAN => self.man,
+            GEM_MID => GEM_MID_VALUE,
+            GEM_STATS..=GEM_STATS_END => 0,
+            GEM_DCFG1 => GEM_DCFG1_VALUE,
+            _ if addr < GEM_REGISTERS_SIZE => self.registers[(addr / 4) as usize],
+            _ => panic!("Read reserved address: {:x}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        match addr {
+            GEM_NCR => {
+                if data & NCR_TE == 0 {
+                    self.tx_desc = self.tbqp;
+                    self.tx_frame.clear();
+                    self.tsr &= !TSR_TGO;
+                }
+                if data & NCR_RE == 0 {
+                    self.rx_desc = self.rbqp;
+                }
+                if data & NCR_TSTART != 0 && data & NCR_TE != 0 {
+                    self.tsr |= TSR_TGO;
+                }
+                if data & NCR_THALT != 0 {
+                    self.tsr &= !TSR_TGO;
+                }
+                self.ncr = data & !(NCR_TSTART | NCR_THALT);
+            }
+            GEM_NCFGR => self.ncfgr = data,
+            GEM_NSR => {}
+            GEM_DMACFG => self.dmacfg = data,
+            GEM_TSR => self.tsr &= !(data & !TSR_TGO),
+            GEM_RBQP => {
+                self.rbqp = data & RX_DESC_ADDR_MASK;
+                self.rx_desc = self.rbqp;
+            }
+            GEM_TBQP => {
+                self.tbqp = data & RX_DESC_ADDR_MASK;
+                self.tx_desc = self.tbqp;
+            }
+            GEM_RSR => self.rsr &= !data,
+            GEM_ISR => self.isr &= !data,
+            GEM_IER => self.imr &= !data,
+            GEM_IDR => self.imr |= data & ISR_MASK,
+            GEM_IMR => {}
+            GEM_MAN => self.manage(data),
+            GEM_MID | GEM_STATS..=GEM_STATS_END | GEM_DCFG1 => {}
+            _ if addr < GEM_REGISTERS_SIZE => self.registers[(addr / 4) as usize] = data,
+            _ => panic!("Write reserved address: {:x}", addr),
+        }
+    }
+
+    /// Read a register of the gemgxl-mgmt block.
+    pub fn read_mgmt(&mut self, addr: u64) -> u32 {
+        match addr {
+            0 => self.tx_clk_sel,
+            _ => panic!("Read reserved address: {:x}", addr),
+        }
+    }
+
+    /// Write a register of the gemgxl-mgmt block.
+    pub fn write_mgmt(&mut self, addr: u64, data: u32) {
+        match addr {
+            0 => self.tx_clk_sel = data & 1,
+            _ => panic!("Write reserved address: {:x}", addr),
+        }
+    }
+}
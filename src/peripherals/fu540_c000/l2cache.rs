@@ -0,0 +1,150 @@
+// L2 cache controller of the FU540
+// https://static.dev.sifive.com/FU540-C000-v1.0.pdf
+//
+// The cache itself is not modeled: the flushes do nothing and the errors
+// only come from the ECC error injection.
+
+/// 4 banks, 16 ways, 512 sets per bank and 64-byte blocks.
+const L2_CONFIG_VALUE: u32 = 0x0609_1004;
+const L2_WAYS: u32 = 16;
+const L2_MASTERS: usize = 32;
+
+// Registers
+const L2_CONFIG: u64 = 0x000;
+const L2_WAYENABLE: u64 = 0x008;
+const L2_ECCINJECTERROR: u64 = 0x040;
+const L2_DIRECCFIX_LOW: u64 = 0x100;
+const L2_DIRECCFIX_HIGH: u64 = 0x104;
+const L2_DIRECCFIX_COUNT: u64 = 0x108;
+const L2_DIRECCFAIL_LOW: u64 = 0x120;
+const L2_DIRECCFAIL_HIGH: u64 = 0x124;
+const L2_DIRECCFAIL_COUNT: u64 = 0x128;
+const L2_DATECCFIX_LOW: u64 = 0x140;
+const L2_DATECCFIX_HIGH: u64 = 0x144;
+const L2_DATECCFIX_COUNT: u64 = 0x148;
+const L2_DATECCFAIL_LOW: u64 = 0x160;
+const L2_DATECCFAIL_HIGH: u64 = 0x164;
+const L2_DATECCFAIL_COUNT: u64 = 0x168;
+const L2_FLUSH64: u64 = 0x200;
+const L2_FLUSH64_HIGH: u64 = 0x204;
+const L2_FLUSH32: u64 = 0x240;
+const L2_WAYMASK: u64 = 0x800;
+const L2_WAYMASK_END: u64 = L2_WAYMASK + L2_MASTERS as u64 * 8 - 4;
+
+// ECCInjectError bits
+const ECC_INJECT_BIT_MASK: u32 = 0xff;
+const ECC_INJECT_DIRECTORY: u32 = 1 << 16;
+
+/// Address and count of the ECC errors of a kind.
+#[derive(Clone, Copy, Default)]
+struct EccErrors {
+    address: u64,
+    count: u32,
+}
+
+pub struct L2Cache {
+    /// Index of the last enabled way
+    way_enable: u32,
+    ecc_inject_error: u32,
+    dir_fix: EccErrors,
+    dir_fail: EccErrors,
+    dat_fix: EccErrors,
+    dat_fail: EccErrors,
+    /// Ways each master may allocate into, 32-bit words
+    way_masks: Vec<u32>,
+}
+
+impl Default for L2Cache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl L2Cache {
+    pub fn new() -> Self {
+        let mut way_masks = vec![0; L2_MASTERS * 2];
+        for mask in way_masks.iter_mut().step_by(2) {
+            *mask = (1 << L2_WAYS) - 1;
+        }
+        L2Cache {
+            way_enable: 0,
+            ecc_inject_error: 0,
+            dir_fix: EccErrors::default(),
+            dir_fail: EccErrors::default(),
+            dat_fix: EccErrors::default(),
+            dat_fail: EccErrors::default(),
+            way_masks,
+        }
+    }
+
+    pub fn get_way_enable(&self) -> u32 {
+        self.way_enable
+    }
+
+    /// Interrupts of the corrected directory errors, the corrected data
+    /// errors and the uncorrected data errors.
+    pub fn get_irqs(&self) -> [bool; 3] {
+        [
+            self.dir_fix.count != 0,
+            self.dat_fix.count != 0,
+            self.dat_fail.count != 0,
+        ]
+    }
+
+    /// Flip a bit of the directory or of the data: the ECC corrects it at
+    /// the next access.
+    fn inject_error(&mut self, data: u32) {
+        self.ecc_inject_error = data & (ECC_INJECT_BIT_MASK | ECC_INJECT_DIRECTORY);
+        let errors = match data & ECC_INJECT_DIRECTORY {
+            0 => &mut self.dat_fix,
+            _ => &mut self.dir_fix,
+        };
+        errors.address = (data & ECC_INJECT_BIT_MASK) as u64;
+        errors.count = errors.count.wrapping_add(1);
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr {
+            L2_CONFIG => L2_CONFIG_VALUE,
+            L2_WAYENABLE => self.way_enable,
+            L2_ECCINJECTERROR => self.ecc_inject_error,
+            L2_DIRECCFIX_LOW => self.dir_fix.address as u32,
+            L2_DIRECCFIX_HIGH => (self.dir_fix.address >> 32) as u32,
+            L2_DIRECCFAIL_LOW => self.dir_fail.address as u32,
+            L2_DIRECCFAIL_HIGH => (self.dir_fail.address >> 32) as u32,
+            L2_DATECCFIX_LOW => self.dat_fix.address as u32,
+            L2_DATECCFIX_HIGH => (self.dat_fix.address >> 32) as u32,
+            L2_DATECCFAIL_LOW => self.dat_fail.address as u32,
+            L2_DATECCFAIL_HIGH => (self.dat_fail.address >> 32) as u32,
+            // reading a count clears it and its interrupt.
+            L2_DIRECCFIX_COUNT => std::mem::take(&mut self.dir_fix.count),
+            L2_DIRECCFAIL_COUNT => std::mem::take(&mut self.dir_fail.count),
+            L2_DATECCFIX_COUNT => std::mem::take(&mut self.dat_fix.count),
+            L2_DATECCFAIL_COUNT => std::mem::take(&mut self.dat_fail.count),
+            L2_FLUSH64 | L2_FLUSH64_HIGH | L2_FLUSH32 => 0,
+            L2_WAYMASK..=L2_WAYMASK_END => self.way_masks[((addr - L2_WAYMASK) / 4) as usize],
+            _ => panic!("Read reserved address: {:x}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        match addr {
+            // the ways can only be enabled.
+            L2_WAYENABLE => self.way_enable = self.way_enable.max(data.min(L2_WAYS - 1)),
+            L2_ECCINJECTERROR => self.inject_error(data),
+            L2_FLUSH64 | L2_FLUSH64_HIGH | L2_FLUSH32 => {}
+            L2_WAYMASK..=L2_WAYMASK_END => {
+                let index = ((addr - L2_WAYMASK) / 4) as usize;
+                if index.is_multiple_of(2) {
+                    self.way_masks[index] = data & ((1 << L2_WAYS) - 1);
+                }
+            }
+            L2_CONFIG
+            | L2_DIRECCFIX_LOW..=L2_DIRECCFIX_COUNT
+            | L2_DIRECCFAIL_LOW..=L2_DIRECCFAIL_COUNT
+            | L2_DATECCFIX_LOW..=L2_DATECCFIX_COUNT
+            | L2_DATECCFAIL_LOW..=L2_DATECCFAIL_COUNT => {}
+            _ => panic!("Write reserved address: {:x}", addr),
+        }
+    }
+}
@@ -1,2 +1,6 @@
 pub mod clint;
-pub mod plic;
\ No newline at end of file
+pub mod ddr;
+pub mod gemgxl;
+pub mod l2cache;
+pub mod plic;
+pub mod zsbl;
@@ -27,12 +27,14 @@ const PLIC_SCLAIM_BASE: u64 = 0x201004;
 
 const PLIC_CORE_MAX: usize = 5;
 const PLIC_INT_MAX: usize = 0x1000 / 4;
+/// 32-bit words of the pending and enable bits.
+const PLIC_INT_WORDS: usize = PLIC_INT_MAX / 32;
 
 pub struct Plic {
     priority: [u32; PLIC_INT_MAX],
-    pending: u32, // RO
-    menable: [u32; PLIC_CORE_MAX],
-    senable: [u32; PLIC_CORE_MAX],
+    pending: [u32; PLIC_INT_WORDS], // RO
+    menable: [[u32; PLIC_INT_WORDS]; PLIC_CORE_MAX],
+    senable: [[u32; PLIC_INT_WORDS]; PLIC_CORE_MAX],
     mthreshold: [u32; PLIC_CORE_MAX],
     sthreshold: [u32; PLIC_CORE_MAX],
     mclaim: [u32; PLIC_CORE_MAX],
@@ -43,9 +45,9 @@ impl Plic {
     pub fn new() -> Self {
         Plic {
             priority: [0; PLIC_INT_MAX],
-            pending: 0,
-            menable: [0; PLIC_CORE_MAX],
-            senable: [0; PLIC_CORE_MAX],
+            pending: [0; PLIC_INT_WORDS],
+            menable: [[0; PLIC_INT_WORDS]; PLIC_CORE_MAX],
+            senable: [[0; PLIC_INT_WORDS]; PLIC_CORE_MAX],
             mthreshold: [0; PLIC_CORE_MAX],
             sthreshold: [0; PLIC_CORE_MAX],
             mclaim: [0; PLIC_CORE_MAX],
@@ -61,17 +63,19 @@ impl Intc for Plic {
         let mut irq_s = 0;
         let mut max_priority_s = 0;
         for id in interrupts {
-            if ((self.menable[core] >> id) & 0x1) > 0 {
-                if self.priority[id] > self.mthreshold[core] && self.priority[id] > max_priority_m {
-                    irq_m = id as u32;
-                    max_priority_m = self.priority[id];
-                }
+            if ((self.menable[core][id / 32] >> (id % 32)) & 0x1) > 0
+                && self.priority[id] > self.mthreshold[core]
+                && self.priority[id] > max_priority_m
+            {
+                irq_m = id as u32;
+                max_priority_m = self.priority[id];
             }
-            if ((self.senable[core] >> id) & 0x1) > 0 {
-                if self.priority[id] > self.sthreshold[core] && self.priority[id] > max_priority_s {
-                    irq_s = id as u32;
-                    max_priority_s = self.priority[id];
-                }
+            if ((self.senable[core][id / 32] >> (id % 32)) & 0x1) > 0
+                && self.priority[id] > self.sthreshold[core]
+                && self.priority[id] > max_priority_s
+            {
+                irq_s = id as u32;
+                max_priority_s = self.priority[id];
             }
         }
 
@@ -102,22 +106,25 @@ impl Intc for Plic {
             }
         }
         if e_addr < PLIC_MENABLE_BASE {
-            match e_addr {
-                0x1000 => return self.pending,
-                _ => panic!("Read to reserved area: {:x}", addr),
+            let word = ((e_addr - PLIC_PENDING_BASE) >> 2) as usize;
+            match self.pending.get(word) {
+                Some(pending) => return *pending,
+                None => panic!("Read to reserved area: {:x}", addr),
             }
         } else if e_addr < PLIC_MTHRESHOLD_BASE {
             if e_addr & 0x80 == 0 {
-                if e_addr <= PLIC_MENABLE_BASE + 0x100 * PLIC_CORE_MAX as u64 {
+                if e_addr < PLIC_MENABLE_BASE + 0x100 * PLIC_CORE_MAX as u64 {
                     let idx = ((e_addr - PLIC_MENABLE_BASE) / 0x100) as usize;
-                    return self.menable[idx];
+                    let word = ((e_addr & 0x7f) >> 2) as usize;
+                    return self.menable[idx][word];
                 } else {
                     panic!("Write to reserved area: {:x}", addr);
                 }
             } else {
-                if e_addr <= PLIC_SENABLE_BASE + 0x100 * PLIC_CORE_MAX as u64 {
+                if e_addr < PLIC_SENABLE_BASE + 0x100 * PLIC_CORE_MAX as u64 {
                     let idx = ((e_addr - PLIC_SENABLE_BASE) / 0x100) as usize;
-                    return self.senable[idx];
+                    let word = ((e_addr & 0x7f) >> 2) as usize;
+                    return self.senable[idx][word];
                 } else {
                     panic!("Write to reserved area: {:x}", addr);
                 }
@@ -169,22 +176,25 @@ impl Intc for Plic {
                 panic!("Write to reserved area: {:x}", addr);
             }
         } else if e_addr < PLIC_MENABLE_BASE {
-            match e_addr {
-                0x1000 => self.pending = data,
-                _ => panic!("Write to reserved area: {:x}", addr),
+            let word = ((e_addr - PLIC_PENDING_BASE) >> 2) as usize;
+            match self.pending.get_mut(word) {
+                Some(pending) => *pending = data,
+                None => panic!("Write to reserved area: {:x}", addr),
             }
         } else if e_addr < PLIC_MTHRESHOLD_BASE {
             if e_addr & 0x80 == 0 {
-                if e_addr <= PLIC_MENABLE_BASE + 0x100 * PLIC_CORE_MAX as u64 {
+                if e_addr < PLIC_MENABLE_BASE + 0x100 * PLIC_CORE_MAX as u64 {
                     let idx = ((e_addr - PLIC_MENABLE_BASE) / 0x100) as usize;
-                    self.menable[idx] = data;
+                    let word = ((e_addr & 0x7f) >> 2) as usize;
+                    self.menable[idx][word] = data;
                 } else {
                     panic!("Write to reserved area: {:x}", addr);
                 }
             } else {
-                if e_addr <= PLIC_SENABLE_BASE + 0x100 * PLIC_CORE_MAX as u64 {
+                if e_addr < PLIC_SENABLE_BASE + 0x100 * PLIC_CORE_MAX as u64 {
                     let idx = ((e_addr - PLIC_SENABLE_BASE) / 0x100) as usize;
-                    self.senable[idx] = data;
+                    let word = ((e_addr & 0x7f) >> 2) as usize;
+                    self.senable[idx][word] = data;
                 } else {
                     panic!("Write to reserved area: {:x}", addr);
                 }
@@ -0,0 +1,229 @@
+// Zeroth stage boot loader of the FU540, in the mask ROM at 0x1_0000
+// https://static.dev.sifive.com/FU540-C000-v1.0.pdf
+// https://github.com/sifive/freedom-u540-c000-bootloader
+//
+// The mode select pins pick the boot device: the SPI flash of QSPI0, the SPI
+// flash of QSPI1 (the same memory, mapped at 0x3000_0000) or the SD card on
+// QSPI2. The loader reads the GPT of the device with the DTIM as buffer,
+// copies the partition of the FSBL type GUID (at most 2 MiB) to the L2 LIM at
+// 0x0800_0000 and jumps there with a0 = mhartid and a1 = 0. It waits for an
+// interrupt forever if anything fails.
+
+/// Address of the ZSBL.
+pub const ZSBL_ADDRESS: u32 = 0x0001_0000;
+
+/// The ZSBL for RV64, assembled.
+pub const ZSBL: [u32; 195] = [
+    // start
+    0x000012b7, // lui t0, 0x1
+    0x0002a483, // lw s1, 0(t0)
+    0x00f4f493, // andi s1, s1, 15
+    0x00000297, // auipc t0, 0
+    0x2f028293, // addi t0, t0, 752
+    0x009282b3, // add t0, t0, s1
+    0x0002c903, // lbu s2, 0(t0)
+    0x12090c63, // beqz s2, wait
+    0x00300293, // li t0, 3
+    0x02590063, // beq s2, t0, sd
+    0x200009b7, // lui s3, 0x20000
+    0x00100293, // li t0, 1
+    0x00590463, // beq s2, t0, start+0x38
+    0x300009b7, // lui s3, 0x30000
+    0x00000a17, // auipc s4, 0
+    0x124a0a13, // addi s4, s4, 292
+    0x0180006f, // j gpt
+    // sd
+    0x10050437, // lui s0, 0x10050
+    0x1bc000ef, // jal sd_init
+    0x10051463, // bnez a0, wait
+    0x00000a17, // auipc s4, 0
+    0x230a0a13, // addi s4, s4, 560
+    // gpt
+    0x00100513, // li a0, 1
+    0x010005b7, // lui a1, 0x1000
+    0x000a00e7, // jalr s4
+    0x0e051863, // bnez a0, wait
+    0x010002b7, // lui t0, 0x1000
+    0x0002a303, // lw t1, 0(t0)
+    0x204943b7, // lui t2, 0x20494
+    0x6453839b, // addiw t2, t2, 1605
+    0x0c731e63, // bne t1, t2, wait
+    0x0042a303, // lw t1, 4(t0)
+    0x545243b7, // lui t2, 0x54524
+    0x1503839b, // addiw t2, t2, 336
+    0x0c731663, // bne t1, t2, wait
+    0x0482aa83, // lw s5, 72(t0)
+    0x0502ab03, // lw s6, 80(t0)
+    0x0542ab83, // lw s7, 84(t0)
+    0x00000c13, // li s8, 0
+    0x00000c93, // li s9, 0
+    // entry
+    0x0b6cfa63, // bgeu s9, s6, wait
+    0x009c5513, // srli a0, s8, 9
+    0x01550533, // add a0, a0, s5
+    0x010005b7, // lui a1, 0x1000
+    0x000a00e7, // jalr s4
+    0x0a051063, // bnez a0, wait
+    0x1ffc7293, // andi t0, s8, 511
+    0x01000337, // lui t1, 0x1000
+    0x006282b3, // add t0, t0, t1
+    0x00000317, // auipc t1, 0
+    0x22830313, // addi t1, t1, 552
+    0x0002a383, // lw t2, 0(t0)
+    0x00032e03, // lw t3, 0(t1)
+    0x03c39a63, // bne t2, t3, next
+    0x0042a383, // lw t2, 4(t0)
+    0x00432e03, // lw t3, 4(t1)
+    0x03c39463, // bne t2, t3, next
+    0x0082a383, // lw t2, 8(t0)
+    0x00832e03, // lw t3, 8(t1)
+    0x01c39e63, // bne t2, t3, next
+    0x00c2a383, // lw t2, 12(t0)
+    0x00c32e03, // lw t3, 12(t1)
+    0x01c39863, // bne t2, t3, next
+    0x0202ad03, // lw s10, 32(t0)
+    0x0282ad83, // lw s11, 40(t0)
+    0x0100006f, // j load
+    // next
+    0x017c0c33, // add s8, s8, s7
+    0x001c8c93, // addi s9, s9, 1
+    0xf91ff06f, // j entry
+    // load
+    0x080004b7, // lui s1, 0x8000
+    0x00001b37, // lui s6, 0x1
+    0x03ade463, // bltu s11, s10, boot
+    0x020b0263, // beqz s6, boot
+    0x000d0513, // mv a0, s10
+    0x00048593, // mv a1, s1
+    0x000a00e7, // jalr s4
+    0x02051263, // bnez a0, wait
+    0x001d0d13, // addi s10, s10, 1
+    0x20048493, // addi s1, s1, 512
+    0xfffb0b13, // addi s6, s6, -1
+    0xfddff06f, // j load+0x8
+    // boot
+    0xf1402573, // csrr a0, mhartid
+    0x00000593, // li a1, 0
+    0x080002b7, // lui t0, 0x8000
+    0x00028067, // jr t0
+    // wait
+    0x10500073, // wfi
+    0xffdff06f, // j wait
+    // read_flash
+    0x00951293, // slli t0, a0, 9
+    0x013282b3, // add t0, t0, s3
+    0x08000313, // li t1, 128
+    0x0002a383, // lw t2, 0(t0)
+    0x0075a023, // sw t2, 0(a1)
+    0x00428293, // addi t0, t0, 4
+    0x00458593, // addi a1, a1, 4
+    0xfff30313, // addi t1, t1, -1
+    0xfe0316e3, // bnez t1, read_flash+0xc
+    0x00000513, // li a0, 0
+    0x00008067, // ret
+    // xfer
+    0x0ff57513, // andi a0, a0, 255
+    0x04a42423, // sw a0, 72(s0)
+    0x04c42503, // lw a0, 76(s0)
+    0xfe054ee3, // bltz a0, xfer+0x8
+    0x0ff57513, // andi a0, a0, 255
+    0x000f8067, // jr t6
+    // sd_cmd
+    0x00050313, // mv t1, a0
+    0x00058393, // mv t2, a1
+    0x00060e13, // mv t3, a2
+    0x0ff00513, // li a0, 255
+    0xfd9fffef, // jal t6, xfer
+    0x04036513, // ori a0, t1, 64
+    0xfd1fffef, // jal t6, xfer
+    0x0183d513, // srli a0, t2, 24
+    0xfc9fffef, // jal t6, xfer
+    0x0103d513, // srli a0, t2, 16
+    0xfc1fffef, // jal t6, xfer
+    0x0083d513, // srli a0, t2, 8
+    0xfb9fffef, // jal t6, xfer
+    0x00038513, // mv a0, t2
+    0xfb1fffef, // jal t6, xfer
+    0x000e0513, // mv a0, t3
+    0xfa9fffef, // jal t6, xfer
+    0x01000e93, // li t4, 16
+    0x0ff00513, // li a0, 255
+    0xf9dfffef, // jal t6, xfer
+    0x08057293, // andi t0, a0, 128
+    0x00028663, // beqz t0, sd_cmd+0x60
+    0xfffe8e93, // addi t4, t4, -1
+    0xfe0e96e3, // bnez t4, sd_cmd+0x48
+    0x000f0067, // jr t5
+    // sd_init
+    0x00200293, // li t0, 2
+    0x00542c23, // sw t0, 24(s0)
+    0x00042823, // sw zero, 16(s0)
+    0x00000513, // li a0, 0
+    0x00000593, // li a1, 0
+    0x09500613, // li a2, 149
+    0xf85fff6f, // jal t5, sd_cmd
+    0x00100293, // li t0, 1
+    0x04551663, // bne a0, t0, sd_fail
+    0x00800513, // li a0, 8
+    0x1aa00593, // li a1, 426
+    0x08700613, // li a2, 135
+    0xf6dfff6f, // jal t5, sd_cmd
+    0x00100293, // li t0, 1
+    0x02551a63, // bne a0, t0, sd_fail
+    0x3e800693, // li a3, 1000
+    0x03700513, // li a0, 55
+    0x00000593, // li a1, 0
+    0x00100613, // li a2, 1
+    0xf51fff6f, // jal t5, sd_cmd
+    0x02900513, // li a0, 41
+    0x400005b7, // lui a1, 0x40000
+    0x00100613, // li a2, 1
+    0xf41fff6f, // jal t5, sd_cmd
+    0x00050a63, // beqz a0, sd_ok
+    0xfff68693, // addi a3, a3, -1
+    0xfc069ce3, // bnez a3, sd_init+0x40
+    // sd_fail
+    0x00100513, // li a0, 1
+    0x00008067, // ret
+    // sd_ok
+    0x00000513, // li a0, 0
+    0x00008067, // ret
+    // read_sd
+    0x00058693, // mv a3, a1
+    0x00050593, // mv a1, a0
+    0x01100513, // li a0, 17
+    0x00100613, // li a2, 1
+    0xf11fff6f, // jal t5, sd_cmd
+    0xfc051ee3, // bnez a0, sd_fail
+    0x3e800713, // li a4, 1000
+    0x0ff00513, // li a0, 255
+    0xee9fffef, // jal t6, xfer
+    0x0fe00293, // li t0, 254
+    0x00550863, // beq a0, t0, read_sd+0x38
+    0xfff70713, // addi a4, a4, -1
+    0xfe0716e3, // bnez a4, read_sd+0x1c
+    0xfbdff06f, // j sd_fail
+    0x20000713, // li a4, 512
+    0x0ff00513, // li a0, 255
+    0xec9fffef, // jal t6, xfer
+    0x00a68023, // sb a0, 0(a3)
+    0x00168693, // addi a3, a3, 1
+    0xfff70713, // addi a4, a4, -1
+    0xfe0716e3, // bnez a4, read_sd+0x3c
+    0x0ff00513, // li a0, 255
+    0xeb1fffef, // jal t6, xfer
+    0x0ff00513, // li a0, 255
+    0xea9fffef, // jal t6, xfer
+    0x00000513, // li a0, 0
+    0x00008067, // ret
+    // fsbl_guid
+    0x5b193300, // .byte 0x00, 0x33, 0x19, 0x5b
+    0x40cdfc78, // .byte 0x78, 0xfc, 0xcd, 0x40
+    0x6ce80280, // .byte 0x80, 0x02, 0xe8, 0x6c
+    0x470b5845, // .byte 0x45, 0x58, 0x0b, 0x47
+    // sources
+    0x00020100, // .byte 0x00, 0x01, 0x02, 0x00
+    0x01010100, // .byte 0x00, 0x01, 0x01, 0x01
+    0x03020202, // .byte 0x02, 0x02, 0x02, 0x03
+    0x01030100, // .byte 0x00, 0x01, 0x03, 0x01
+];
@@ -0,0 +1,191 @@
+// Goldfish real-time clock, described as "google,goldfish-rtc" in the device
+// tree.
+// https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT
+//
+// The time is in nanoseconds since the UNIX epoch. Reading TIME_LOW latches
+// TIME_HIGH, writing TIME_LOW sets the time with the latched TIME_HIGH. The
+// alarm is armed by writing ALARM_LOW after ALARM_HIGH and interrupts once
+// the time reaches it.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Registers
+const RTC_TIME_LOW: u64 = 0x00;
+const RTC_TIME_HIGH: u64 = 0x04;
+const RTC_ALARM_LOW: u64 = 0x08;
+const RTC_ALARM_HIGH: u64 = 0x0c;
+const RTC_IRQ_ENABLED: u64 = 0x10;
+const RTC_CLEAR_ALARM: u64 = 0x14;
+const RTC_ALARM_STATUS: u64 = 0x18;
+const RTC_CLEAR_INTERRUPT: u64 = 0x1c;
+
+/// Core cycles between two checks of the alarm.
+const RTC_ALARM_POLL_CYCLES: u64 = 0x1000;
+
+const NANOSECONDS: u128 = 1_000_000_000;
+
+/// Source of the time of the real-time clock.
+pub trait TimeSource {
+    /// Nanoseconds since the UNIX epoch.
+    fn get_time(&self) -> u64;
+    /// Called once per core cycle.
+    fn tick(&mut self) {}
+}
+
+/// Wall clock of the host.
+#[derive(Default)]
+pub struct HostClock {}
+
+impl HostClock {
+    pub fn new() -> Self {
+        HostClock {}
+    }
+}
+
+impl TimeSource for HostClock {
+    fn get_time(&self) -> u64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(time) => time.as_nanos() as u64,
+            Err(_) => 0,
+        }
+    }
+}
+
+/// Deterministic clock derived from the executed cycles, for reproducible
+/// runs.
+pub struct VirtualClock {
+    /// Time at the first cycle
+    start: u64,
+    /// Core cycles per second
+    frequency: u64,
+    cycles: u64,
+}
+
+impl VirtualClock {
+    /// A clock starting at `start` nanoseconds since the UNIX epoch, which
+    /// advances by one second every `frequency` cycles.
+    pub fn new(start: u64, frequency: u64) -> Self {
+        VirtualClock {
+            start,
+            frequency: frequency.max(1),
+            cycles: 0,
+        }
+    }
+}
+
+impl TimeSource for VirtualClock {
+    fn get_time(&self) -> u64 {
+        let elapsed = self.cycles as u128 * NANOSECONDS / self.frequency as u128;
+        self.start.wrapping_add(elapsed as u64)
+    }
+
+    fn tick(&mut self) {
+        self.cycles = self.cycles.wrapping_add(1);
+    }
+}
+
+pub struct GoldfishRtc {
+    source: Box<dyn TimeSource>,
+    /// Difference between the time of the guest and the source.
+    offset: u64,
+    /// TIME_HIGH latched by the last read of TIME_LOW, or written.
+    time_high: u32,
+    alarm: u64,
+    alarm_running: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    cycle: u64,
+}
+
+impl GoldfishRtc {
+    pub fn new(source: Box<dyn TimeSource>) -> Self {
+        GoldfishRtc {
+            source,
+            offset: 0,
+            time_high: 0,
+            alarm: 0,
+            alarm_running: false,
+            irq_enabled: false,
+            irq_pending: false,
+            cycle: 0,
+        }
+    }
+
+    /// Use another time source, the time set by the guest is forgotten.
+    pub fn set_time_source(&mut self, source: Box<dyn TimeSource>) {
+        self.source = source;
+        self.offset = 0;
+    }
+
+    /// Reset the registers, the clock keeps running with the time set by the
+    /// guest.
+    pub fn reset(&mut self) {
+        self.time_high = 0;
+        self.alarm = 0;
+        self.alarm_running = false;
+        self.irq_enabled = false;
+        self.irq_pending = false;
+    }
+
+    /// Nanoseconds since the UNIX epoch seen by the guest.
+    pub fn get_time(&self) -> u64 {
+        self.source.get_time().wrapping_add(self.offset)
+    }
+
+    pub fn tick(&mut self) {
+        self.source.tick();
+        self.cycle = self.cycle.wrapping_add(1);
+        if self.alarm_running && self.cycle.is_multiple_of(RTC_ALARM_POLL_CYCLES) {
+            self.check_alarm();
+        }
+    }
+
+    fn check_alarm(&mut self) {
+        if self.get_time() >= self.alarm {
+            self.alarm_running = false;
+            self.irq_pending = true;
+        }
+    }
+
+    pub fn is_irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr {
+            RTC_TIME_LOW => {
+                let time = self.get_time();
+                self.time_high = (time >> 32) as u32;
+                time as u32
+            }
+            RTC_TIME_HIGH => self.time_high,
+            RTC_ALARM_LOW => self.alarm as u32,
+            RTC_ALARM_HIGH => (self.alarm >> 32) as u32,
+            RTC_IRQ_ENABLED => self.irq_enabled as u32,
+            RTC_ALARM_STATUS => self.alarm_running as u32,
+            RTC_CLEAR_ALARM | RTC_CLEAR_INTERRUPT => 0,
+            _ => panic!("Read reserved address: {:x}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        match addr {
+            RTC_TIME_LOW => {
+                let time = (self.time_high as u64) << 32 | data as u64;
+                self.offset = time.wrapping_sub(self.source.get_time());
+            }
+            RTC_TIME_HIGH => self.time_high = data,
+            RTC_ALARM_LOW => {
+                self.alarm = (self.alarm & !0xffff_ffff) | data as u64;
+                self.alarm_running = true;
+                self.check_alarm();
+            }
+            RTC_ALARM_HIGH => self.alarm = (self.alarm & 0xffff_ffff) | (data as u64) << 32,
+            RTC_IRQ_ENABLED => self.irq_enabled = data & 1 != 0,
+            RTC_CLEAR_ALARM => self.alarm_running = false,
+            RTC_CLEAR_INTERRUPT => self.irq_pending = false,
+            RTC_ALARM_STATUS => {}
+            _ => panic!("Write reserved address: {:x}", addr),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// Serial EEPROM of the AT24Cxx family
+// https://ww1.microchip.com/downloads/en/DeviceDoc/AT24C01A-02-04-08-16-Data-Sheet-DS20006111.pdf
+//
+// A write transaction starts with the word address (2 bytes above 256
+// bytes) followed by the data, which wraps in the page. A read continues
+// from the current address. The writes complete immediately.
+
+use crate::peripherals::i2c::I2cDevice;
+
+pub struct Eeprom {
+    address: u8,
+    data: Vec<u8>,
+    page_size: usize,
+    /// Bytes of the word address.
+    address_bytes: usize,
+    /// Current word address.
+    pointer: usize,
+    /// Bytes of the word address received in the write transaction.
+    received: usize,
+}
+
+impl Eeprom {
+    /// An erased EEPROM of the size (a power of two) at the bus address.
+    pub fn new(address: u8, size: usize) -> Self {
+        let (address_bytes, page_size) = match size > 256 {
+            true => (2, 32),
+            false => (1, 8),
+        };
+        Eeprom {
+            address,
+            data: vec![0xff; size],
+            page_size,
+            address_bytes,
+            pointer: 0,
+            received: 0,
+        }
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl I2cDevice for Eeprom {
+    fn get_address(&self) -> u8 {
+        self.address
+    }
+
+    fn start(&mut self, _read: bool) -> bool {
+        self.received = 0;
+        true
+    }
+
+    fn write(&mut self, data: u8) -> bool {
+        let mask = self.data.len() - 1;
+        if self.received < self.address_bytes {
+            let high = match self.received {
+                0 => 0,
+                _ => self.pointer,
+            };
+            self.pointer = ((high << 8) | data as usize) & mask;
+            self.received += 1;
+            return true;
+        }
+        self.data[self.pointer] = data;
+        let page = self.pointer & !(self.page_size - 1);
+        self.pointer = page | ((self.pointer + 1) & (self.page_size - 1));
+        true
+    }
+
+    fn read(&mut self, _ack: bool) -> u8 {
+        let data = self.data[self.pointer];
+        self.pointer = (self.pointer + 1) & (self.data.len() - 1);
+        data
+    }
+
+    fn stop(&mut self) {}
+}
@@ -0,0 +1,21 @@
+// I2C (Inter-Integrated Circuit) slave devices
+// The masters of the SoCs address the devices by their 7-bit address after a
+// start condition, then exchange bytes with the one that acknowledged.
+
+pub mod eeprom;
+pub mod temperature;
+
+pub trait I2cDevice {
+    /// 7-bit address of the device on the bus.
+    fn get_address(&self) -> u8;
+    /// A start (or repeated start) condition addressed to the device, for a
+    /// read (true) or a write. Returns the acknowledge.
+    fn start(&mut self, read: bool) -> bool;
+    /// Byte written by the master, returns the acknowledge.
+    fn write(&mut self, data: u8) -> bool;
+    /// Byte read by the master, acknowledged by the master (true) when it
+    /// reads more.
+    fn read(&mut self, ack: bool) -> u8;
+    /// A stop condition ends the transaction.
+    fn stop(&mut self);
+}
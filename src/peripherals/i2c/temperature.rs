@@ -0,0 +1,129 @@
+// Temperature sensor compatible with the LM75
+// https://www.ti.com/lit/ds/symlink/lm75b.pdf
+//
+// The first byte of a write transaction selects the register. The
+// temperature is set by the host through a handle, in 0.5 degree steps.
+
+use crate::peripherals::i2c::I2cDevice;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+// Registers
+const LM75_TEMPERATURE: u8 = 0;
+const LM75_CONFIGURATION: u8 = 1;
+const LM75_HYSTERESIS: u8 = 2;
+const LM75_OVERTEMPERATURE: u8 = 3;
+
+/// Temperature seen by the sensor, in millidegrees Celsius.
+#[derive(Clone)]
+pub struct TemperatureHandle {
+    temperature: Rc<Cell<i32>>,
+}
+
+impl TemperatureHandle {
+    pub fn set_temperature(&self, millicelsius: i32) {
+        self.temperature.set(millicelsius);
+    }
+
+    pub fn get_temperature(&self) -> i32 {
+        self.temperature.get()
+    }
+}
+
+pub struct TemperatureSensor {
+    address: u8,
+    temperature: Rc<Cell<i32>>,
+    configuration: u8,
+    /// Hysteresis and overtemperature shutdown limits, like the temperature
+    /// (the degrees in the high byte, the half degree in the bit 7).
+    hysteresis: u16,
+    overtemperature: u16,
+    pointer: u8,
+    /// Bytes received in the write transaction.
+    received: usize,
+    /// Bytes read from the register in the read transaction.
+    sent: usize,
+}
+
+impl TemperatureSensor {
+    /// A sensor at the bus address (0x48 to 0x4f), at 25 degrees.
+    pub fn new(address: u8) -> Self {
+        TemperatureSensor {
+            address,
+            temperature: Rc::new(Cell::new(25000)),
+            configuration: 0,
+            hysteresis: 75 << 8,
+            overtemperature: 80 << 8,
+            pointer: LM75_TEMPERATURE,
+            received: 0,
+            sent: 0,
+        }
+    }
+
+    pub fn get_handle(&self) -> TemperatureHandle {
+        TemperatureHandle {
+            temperature: self.temperature.clone(),
+        }
+    }
+
+    fn get_register(&self) -> u16 {
+        match self.pointer {
+            LM75_TEMPERATURE => {
+                // 9 bits, two's complement.
+                let half_degrees = self.temperature.get().div_euclid(500).clamp(-256, 255);
+                ((half_degrees as i16) << 7) as u16
+            }
+            LM75_CONFIGURATION => (self.configuration as u16) << 8,
+            LM75_HYSTERESIS => self.hysteresis,
+            _ => self.overtemperature,
+        }
+    }
+}
+
+impl I2cDevice for TemperatureSensor {
+    fn get_address(&self) -> u8 {
+        self.address
+    }
+
+    fn start(&mut self, _read: bool) -> bool {
+        self.received = 0;
+        self.sent = 0;
+        true
+    }
+
+    fn write(&mut self, data: u8) -> bool {
+        self.received += 1;
+        match self.received {
+            1 => self.pointer = data & 0x3,
+            // the limits are written high byte first.
+            n => match self.pointer {
+                LM75_CONFIGURATION => self.configuration = data,
+                LM75_HYSTERESIS | LM75_OVERTEMPERATURE => {
+                    let limit = match self.pointer {
+                        LM75_HYSTERESIS => &mut self.hysteresis,
+                        _ => &mut self.overtemperature,
+                    };
+                    *limit = match n {
+                        2 => (data as u16) << 8 | (*limit & 0xff),
+                        _ => (*limit & 0xff00) | (data & 0x80) as u16,
+                    };
+                }
+                _ => {}
+            },
+        }
+        true
+    }
+
+    fn read(&mut self, _ack: bool) -> u8 {
+        let register = self.get_register();
+        let data = match self.sent % 2 {
+            0 => (register >> 8) as u8,
+            _ => register as u8,
+        };
+        self.sent += 1;
+        data
+    }
+
+    fn stop(&mut self) {}
+}
@@ -5,3 +5,10 @@ pub mod timer;
 pub mod uart;
 pub mod virtio;
 pub mod memory;
+pub mod mrom;
+pub mod sifive_test;
+pub mod framebuffer;
+pub mod goldfish_rtc;
+pub mod pci;
+pub mod spi;
+pub mod i2c;
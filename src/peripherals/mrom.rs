@@ -0,0 +1,93 @@
+// Mode select page of the SiFive machines
+// https://sifive.cdn.prismic.io/sifive/034760b5-ac6a-4b1c-911c-f4148bb2c4a5_fe310-g002-v1p5.pdf
+// https://static.dev.sifive.com/FU540-C000-v1.0.pdf
+//
+// The first word holds the mode select pins, the reset vector follows it. The
+// reset code jumps to the entry of the boot target table selected by the
+// pins, with a0 and a1 as the CPU reset left them.
+
+use crate::peripherals::memory::Memory;
+
+/// Offset of the reset vector in the page.
+pub const MSEL_RESET_VECTOR: u64 = 0x4;
+/// Offset of the boot target table in the page.
+const MSEL_TARGET_TABLE: u64 = 0x20;
+/// Offset of the loop waiting for an interrupt forever.
+const MSEL_WAIT: u64 = 0x60;
+
+const MSEL_PAGE_SIZE: usize = 0x1000;
+
+const RESET_CODE: [u32; 7] = [
+    0x00000297, // auipc t0, 0
+    0xffc2a303, // lw t1, -4(t0)
+    0x00f37313, // andi t1, t1, 15
+    0x00231313, // slli t1, t1, 2
+    0x00530333, // add t1, t1, t0
+    0x01c32303, // lw t1, 28(t1)
+    0x00030067, // jr t1
+];
+
+const WAIT_CODE: [u32; 2] = [
+    0x10500073, // wfi
+    0xffdff06f, // j -4
+];
+
+/// Boot target of a mode select value.
+#[derive(Clone, Copy)]
+pub enum BootTarget {
+    /// Jump to this address.
+    Address(u32),
+    /// Nothing to boot: wait for an interrupt forever.
+    Wait,
+}
+
+pub struct ModeSelect {
+    page: Memory,
+}
+
+impl ModeSelect {
+    /// Build the page mapped at `base` with the boot targets of the 16 mode
+    /// select values.
+    pub fn new(base: u64, targets: [BootTarget; 16]) -> Self {
+        let mut page = Memory::new(MSEL_PAGE_SIZE);
+        for (i, code) in RESET_CODE.iter().enumerate() {
+            page.write32(MSEL_RESET_VECTOR + i as u64 * 4, *code);
+        }
+        for (i, target) in targets.iter().enumerate() {
+            let address = match target {
+                BootTarget::Address(address) => *address,
+                BootTarget::Wait => (base + MSEL_WAIT) as u32,
+            };
+            page.write32(MSEL_TARGET_TABLE + i as u64 * 4, address);
+        }
+        for (i, code) in WAIT_CODE.iter().enumerate() {
+            page.write32(MSEL_WAIT + i as u64 * 4, *code);
+        }
+        ModeSelect { page }
+    }
+
+    /// Set the state of the mode select pins.
+    pub fn set_mode_select(&mut self, msel: u32) {
+        self.page.write32(0, msel & 0xf);
+    }
+
+    pub fn get_mode_select(&self) -> u32 {
+        self.page.read32(0)
+    }
+
+    pub fn read8(&self, addr: u64) -> u8 {
+        self.page.read8(addr)
+    }
+
+    pub fn read16(&self, addr: u64) -> u16 {
+        self.page.read16(addr)
+    }
+
+    pub fn read32(&self, addr: u64) -> u32 {
+        self.page.read32(addr)
+    }
+
+    pub fn read64(&self, addr: u64) -> u64 {
+        self.page.read64(addr)
+    }
+}
@@ -0,0 +1,344 @@
+// PCI Express host bridge with the Enhanced Configuration Access Mechanism
+// (ECAM), described as "pci-host-ecam-generic" in the device tree.
+// https://www.kernel.org/doc/Documentation/devicetree/bindings/pci/host-generic-pci.txt
+//
+// Only the bus 0 is populated, with single function devices. The first slot
+// is the host bridge itself.
+
+use crate::peripherals::memory::Memory;
+use crate::peripherals::pci::{PciDevice, PCI_CLASS_BRIDGE_HOST};
+
+pub const PCI_SLOT_COUNT: usize = 32;
+/// Number of the INTx lines (INTA to INTD).
+pub const PCI_INTX_COUNT: usize = 4;
+
+// Configuration header registers
+const PCI_ID: u64 = 0x00;
+const PCI_COMMAND_STATUS: u64 = 0x04;
+const PCI_CLASS_REVISION: u64 = 0x08;
+const PCI_BAR0: u64 = 0x10;
+const PCI_BAR5: u64 = 0x24;
+const PCI_SUBSYSTEM: u64 = 0x2c;
+const PCI_CAPABILITY_LIST: u64 = 0x34;
+const PCI_INTERRUPT: u64 = 0x3c;
+const PCI_CAPABILITIES: u64 = 0x40;
+const PCI_CONFIG_SIZE: u64 = 0x1000;
+
+// Command register bits
+const PCI_COMMAND_MEMORY: u16 = 0x2;
+const PCI_COMMAND_MASTER: u16 = 0x4;
+const PCI_COMMAND_INTX_DISABLE: u16 = 0x400;
+
+// Status register bits
+const PCI_STATUS_INTERRUPT: u16 = 0x8;
+const PCI_STATUS_CAP_LIST: u16 = 0x10;
+
+/// Interrupt pin of the devices.
+const PCI_INTERRUPT_PIN_INTA: u8 = 1;
+
+/// QEMU PCIe host bridge
+struct HostBridge {}
+
+impl PciDevice for HostBridge {
+    fn get_vendor_id(&self) -> u16 {
+        0x1b36 // Red Hat
+    }
+
+    fn get_device_id(&self) -> u16 {
+        0x0008
+    }
+
+    fn get_class_code(&self) -> u32 {
+        PCI_CLASS_BRIDGE_HOST
+    }
+
+    fn get_bar_sizes(&self) -> Vec<u64> {
+        vec![]
+    }
+
+    fn read_bar(&mut self, _bar: usize, _offset: u64, _size: usize) -> u64 {
+        0
+    }
+
+    fn write_bar(&mut self, _bar: usize, _offset: u64, _data: u64, _size: usize) {}
+
+    fn tick(&mut self, _dram: &mut Memory) {}
+
+    fn is_irq(&mut self) -> bool {
+        false
+    }
+}
+
+struct PciFunction {
+    device: Box<dyn PciDevice>,
+    command: u16,
+    /// BAR registers.
+    bars: [u32; 6],
+    /// BAR addresses assigned when the device is plugged.
+    initial_bars: [u32; 6],
+    bar_sizes: Vec<u64>,
+    interrupt_line: u8,
+    interrupt_pin: u8,
+    /// level of the INTx interrupt at the last tick.
+    irq: bool,
+    /// configuration space from PCI_CAPABILITIES.
+    capabilities: Vec<u8>,
+}
+
+impl PciFunction {
+    fn new(device: Box<dyn PciDevice>, interrupt_pin: u8) -> Self {
+        // capabilities are linked in order, aligned to 4 bytes.
+        let mut capabilities = vec![];
+        let list = device.get_capabilities();
+        for (i, (id, data)) in list.iter().enumerate() {
+            let len = (2 + data.len() + 3) & !3;
+            let next = match i + 1 < list.len() {
+                true => PCI_CAPABILITIES as usize + capabilities.len() + len,
+                false => 0,
+            };
+            capabilities.push(*id);
+            capabilities.push(next as u8);
+            capabilities.extend_from_slice(data);
+            capabilities.resize((capabilities.len() + 3) & !3, 0);
+        }
+        PciFunction {
+            bar_sizes: device.get_bar_sizes(),
+            device,
+            command: 0,
+            bars: [0; 6],
+            initial_bars: [0; 6],
+            interrupt_line: 0,
+            interrupt_pin,
+            irq: false,
+            capabilities,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.command = 0;
+        self.bars = self.initial_bars;
+        self.interrupt_line = 0;
+        self.irq = false;
+        self.device.reset();
+    }
+
+    fn get_bar_size(&self, index: usize) -> u64 {
+        self.bar_sizes.get(index).cloned().unwrap_or(0)
+    }
+
+    fn read_config(&self, reg: u64) -> u32 {
+        let device = &self.device;
+        match reg {
+            PCI_ID => device.get_vendor_id() as u32 | (device.get_device_id() as u32) << 16,
+            PCI_COMMAND_STATUS => {
+                let mut status = 0;
+                if !self.capabilities.is_empty() {
+                    status |= PCI_STATUS_CAP_LIST;
+                }
+                if self.irq {
+                    status |= PCI_STATUS_INTERRUPT;
+                }
+                self.command as u32 | (status as u32) << 16
+            }
+            PCI_CLASS_REVISION => device.get_revision() as u32 | device.get_class_code() << 8,
+            PCI_BAR0..=PCI_BAR5 => self.bars[((reg - PCI_BAR0) / 4) as usize],
+            PCI_SUBSYSTEM => {
+                device.get_subsystem_vendor_id() as u32 | (device.get_subsystem_id() as u32) << 16
+            }
+            PCI_CAPABILITY_LIST if !self.capabilities.is_empty() => PCI_CAPABILITIES as u32,
+            PCI_INTERRUPT => self.interrupt_line as u32 | (self.interrupt_pin as u32) << 8,
+            _ if reg >= PCI_CAPABILITIES => {
+                let offset = (reg - PCI_CAPABILITIES) as usize;
+                (0..4).fold(0, |data, i| {
+                    let byte = self.capabilities.get(offset + i).cloned().unwrap_or(0);
+                    data | (byte as u32) << (i * 8)
+                })
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_config(&mut self, reg: u64, data: u32) {
+        match reg {
+            PCI_COMMAND_STATUS => {
+                self.command = data as u16
+                    & (PCI_COMMAND_MEMORY | PCI_COMMAND_MASTER | PCI_COMMAND_INTX_DISABLE)
+            }
+            PCI_BAR0..=PCI_BAR5 => {
+                let index = ((reg - PCI_BAR0) / 4) as usize;
+                // writing all ones reads back the size.
+                let size = self.get_bar_size(index);
+                if size != 0 {
+                    self.bars[index] = data & !(size as u32 - 1);
+                }
+            }
+            PCI_INTERRUPT => self.interrupt_line = data as u8,
+            _ => {}
+        }
+    }
+
+    /// BAR and offset of an address if the memory decoding is enabled.
+    fn get_bar(&self, addr: u64) -> Option<(usize, u64)> {
+        if self.command & PCI_COMMAND_MEMORY == 0 {
+            return None;
+        }
+        (0..self.bar_sizes.len()).find_map(|index| {
+            let base = self.bars[index] as u64;
+            let size = self.get_bar_size(index);
+            match size != 0 && base <= addr && addr < base + size {
+                true => Some((index, addr - base)),
+                false => None,
+            }
+        })
+    }
+}
+
+pub struct PciHost {
+    /// functions by slot (device number).
+    functions: Vec<Option<PciFunction>>,
+    /// memory window for the BARs.
+    mmio_base: u64,
+    mmio_size: u64,
+    /// the next free address in the window to assign to BARs.
+    next_bar_address: u64,
+}
+
+impl PciHost {
+    pub fn new(mmio_base: u64, mmio_size: u64) -> Self {
+        let mut functions: Vec<Option<PciFunction>> = (0..PCI_SLOT_COUNT).map(|_| None).collect();
+        functions[0] = Some(PciFunction::new(Box::new(HostBridge {}), 0));
+        PciHost {
+            functions,
+            mmio_base,
+            mmio_size,
+            next_bar_address: mmio_base,
+        }
+    }
+
+    /// Plug a device into the next free slot. The BARs get initial addresses
+    /// in the memory window, which the guest may change. Returns the slot.
+    pub fn attach(&mut self, device: Box<dyn PciDevice>) -> Result<usize, ()> {
+        let slot = match self.functions.iter().position(|f| f.is_none()) {
+            Some(slot) => slot,
+            None => return Err(()),
+        };
+        let mut function = PciFunction::new(device, PCI_INTERRUPT_PIN_INTA);
+        let mut next = self.next_bar_address;
+        for index in 0..function.bar_sizes.len().min(6) {
+            let size = function.get_bar_size(index);
+            if size == 0 {
+                continue;
+            }
+            let base = next.div_ceil(size) * size;
+            if base + size > self.mmio_base + self.mmio_size {
+                return Err(());
+            }
+            function.bars[index] = base as u32;
+            next = base + size;
+        }
+        function.initial_bars = function.bars;
+        self.next_bar_address = next;
+        self.functions[slot] = Some(function);
+        Ok(slot)
+    }
+
+    /// Reset the functions, the BARs get their initial addresses again.
+    pub fn reset(&mut self) {
+        for function in self.functions.iter_mut().flatten() {
+            function.reset();
+        }
+    }
+
+    /// Function addressed by an ECAM offset, and the register offset.
+    fn get_function(&mut self, offset: u64) -> Option<(&mut PciFunction, u64)> {
+        let bus = offset >> 20;
+        let slot = ((offset >> 15) & 0x1f) as usize;
+        let function = (offset >> 12) & 0x7;
+        match (bus, function) {
+            (0, 0) => self.functions[slot]
+                .as_mut()
+                .map(|f| (f, offset % PCI_CONFIG_SIZE)),
+            _ => None,
+        }
+    }
+
+    /// Access to the configuration space at the offset in the ECAM region,
+    /// with 1, 2 or 4 bytes.
+    pub fn read_config(&mut self, offset: u64, size: usize) -> u32 {
+        match self.get_function(offset) {
+            Some((function, reg)) => {
+                let shift = (reg & 0x3) * 8;
+                let data = function.read_config(reg & !0x3) >> shift;
+                match size {
+                    1 => data & 0xff,
+                    2 => data & 0xffff,
+                    _ => data,
+                }
+            }
+            // no device responds.
+            None => 0xffff_ffff,
+        }
+    }
+
+    pub fn write_config(&mut self, offset: u64, data: u32, size: usize) {
+        if let Some((function, reg)) = self.get_function(offset) {
+            let aligned = reg & !0x3;
+            let shift = (reg & 0x3) * 8;
+            let mask = match size {
+                1 => 0xff << shift,
+                2 => 0xffff << shift,
+                _ => 0xffff_ffff,
+            };
+            let current = function.read_config(aligned);
+            function.write_config(aligned, (current & !mask) | ((data << shift) & mask));
+        }
+    }
+
+    /// Access to the BARs in the memory window.
+    pub fn read(&mut self, addr: u64, size: usize) -> u64 {
+        for function in self.functions.iter_mut().flatten() {
+            if let Some((bar, offset)) = function.get_bar(addr) {
+                return function.device.read_bar(bar, offset, size);
+            }
+        }
+        // no device responds.
+        match size {
+            8 => u64::MAX,
+            _ => (1 << (size * 8)) - 1,
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u64, size: usize) {
+        for function in self.functions.iter_mut().flatten() {
+            if let Some((bar, offset)) = function.get_bar(addr) {
+                function.device.write_bar(bar, offset, data, size);
+                return;
+            }
+        }
+    }
+
+    pub fn tick(&mut self, dram: &mut Memory) {
+        for function in self.functions.iter_mut().flatten() {
+            // DMA is allowed only by the bus master.
+            if function.command & PCI_COMMAND_MASTER != 0 {
+                function.device.tick(dram);
+            }
+            function.irq = function.device.is_irq();
+        }
+    }
+
+    /// Asserted INTx lines (0 for INTA). The pin of each slot is swizzled
+    /// like the interrupt-map of the device tree.
+    pub fn get_interrupts(&self) -> Vec<usize> {
+        self.functions
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, function)| match function {
+                Some(f) if f.irq && f.command & PCI_COMMAND_INTX_DISABLE == 0 => {
+                    Some((slot + f.interrupt_pin as usize - 1) % PCI_INTX_COUNT)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
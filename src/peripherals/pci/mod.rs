@@ -0,0 +1,50 @@
+// PCI (Peripheral Component Interconnect)
+// https://wiki.osdev.org/PCI
+
+pub mod host;
+pub mod nvme;
+
+use crate::peripherals::memory::Memory;
+
+// Class codes (base class, sub class and programming interface)
+pub const PCI_CLASS_STORAGE_NVME: u32 = 0x01_08_02;
+pub const PCI_CLASS_STORAGE_OTHER: u32 = 0x01_80_00;
+pub const PCI_CLASS_NETWORK_ETHERNET: u32 = 0x02_00_00;
+pub const PCI_CLASS_BRIDGE_HOST: u32 = 0x06_00_00;
+pub const PCI_CLASS_OTHER: u32 = 0xff_00_00;
+
+// Capability IDs
+pub const PCI_CAP_ID_VENDOR: u8 = 0x09;
+
+/// Function of a PCI device seen from the host bridge. The bridge handles the
+/// standard configuration header; the device provides its identity, memory
+/// BARs and capabilities.
+pub trait PciDevice {
+    fn get_vendor_id(&self) -> u16;
+    fn get_device_id(&self) -> u16;
+    fn get_revision(&self) -> u8 {
+        0
+    }
+    fn get_class_code(&self) -> u32;
+    fn get_subsystem_vendor_id(&self) -> u16 {
+        0
+    }
+    fn get_subsystem_id(&self) -> u16 {
+        0
+    }
+    /// Size in bytes of the 32-bit memory BARs, a power of two (0 if unused).
+    fn get_bar_sizes(&self) -> Vec<u64>;
+    /// Capabilities (ID, data following the ID and the next pointer).
+    fn get_capabilities(&self) -> Vec<(u8, Vec<u8>)> {
+        vec![]
+    }
+    /// Access to a BAR with 1, 2, 4 or 8 bytes.
+    fn read_bar(&mut self, bar: usize, offset: u64, size: usize) -> u64;
+    fn write_bar(&mut self, bar: usize, offset: u64, data: u64, size: usize);
+    /// Device activity, including DMA to the main memory.
+    fn tick(&mut self, dram: &mut Memory);
+    /// Level of the INTx interrupt.
+    fn is_irq(&mut self) -> bool;
+    /// Back to the power-on state of the registers, the backend is kept.
+    fn reset(&mut self) {}
+}
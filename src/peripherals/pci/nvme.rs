@@ -0,0 +1,798 @@
+// NVM Express controller
+// https://nvmexpress.org/wp-content/uploads/NVM-Express-1_4-2019.06.10-Ratified.pdf
+//
+// One namespace of 512-byte blocks on a block backend. Interrupts are
+// signaled with INTx, MSI and MSI-X are not supported.
+
+use crate::block::BlockBackend;
+use crate::peripherals::memory::Memory;
+use crate::peripherals::pci::{PciDevice, PCI_CLASS_STORAGE_NVME};
+use crate::peripherals::virtio::queue::GuestMemory;
+
+const NVME_VENDOR_ID: u16 = 0x1b36; // Red Hat
+const NVME_DEVICE_ID: u16 = 0x0010;
+const NVME_SUBSYSTEM_VENDOR_ID: u16 = 0x1af4;
+
+const NVME_BAR_SIZE: u64 = 0x4000;
+/// Memory page size (CC.MPS is 0).
+const NVME_PAGE_SIZE: u64 = 0x1000;
+const NVME_BLOCK_SIZE: u64 = 512;
+/// Maximum entries of a queue.
+const NVME_MAX_QUEUE_ENTRIES: u32 = 0x400;
+/// Number of the I/O submission and completion queues.
+const NVME_IO_QUEUES: usize = 64;
+/// Maximum data transfer size, in pages as a power of two.
+const NVME_MDTS: u8 = 7;
+/// Asynchronous event requests the controller keeps, 0's based.
+const NVME_AERL: u8 = 3;
+const NVME_COMMAND_SIZE: u64 = 64;
+const NVME_COMPLETION_SIZE: u64 = 16;
+const NVME_IDENTIFY_SIZE: usize = 0x1000;
+const NVME_NSID: u32 = 1;
+
+// Controller registers
+const NVME_REG_CAP: u64 = 0x00;
+const NVME_REG_VS: u64 = 0x08;
+const NVME_REG_INTMS: u64 = 0x0c;
+const NVME_REG_INTMC: u64 = 0x10;
+const NVME_REG_CC: u64 = 0x14;
+const NVME_REG_CSTS: u64 = 0x1c;
+const NVME_REG_AQA: u64 = 0x24;
+const NVME_REG_ASQ: u64 = 0x28;
+const NVME_REG_ACQ: u64 = 0x30;
+/// Submission queue tail and completion queue head doorbells of each queue
+/// (CAP.DSTRD is 0).
+const NVME_REG_DBS: u64 = 0x1000;
+
+/// NVMe 1.4
+const NVME_VERSION: u32 = 0x0001_0400;
+
+const NVME_CC_EN: u32 = 0x1;
+const NVME_CC_SHN: u32 = 0x3 << 14;
+const NVME_CSTS_RDY: u32 = 0x1;
+const NVME_CSTS_SHST_COMPLETE: u32 = 0x2 << 2;
+
+// Admin commands
+const NVME_ADMIN_DELETE_SQ: u8 = 0x00;
+const NVME_ADMIN_CREATE_SQ: u8 = 0x01;
+const NVME_ADMIN_GET_LOG_PAGE: u8 = 0x02;
+const NVME_ADMIN_DELETE_CQ: u8 = 0x04;
+const NVME_ADMIN_CREATE_CQ: u8 = 0x05;
+const NVME_ADMIN_IDENTIFY: u8 = 0x06;
+const NVME_ADMIN_ABORT: u8 = 0x08;
+const NVME_ADMIN_SET_FEATURES: u8 = 0x09;
+const NVME_ADMIN_GET_FEATURES: u8 = 0x0a;
+const NVME_ADMIN_ASYNC_EVENT: u8 = 0x0c;
+
+// NVM commands
+const NVME_CMD_FLUSH: u8 = 0x00;
+const NVME_CMD_WRITE: u8 = 0x01;
+const NVME_CMD_READ: u8 = 0x02;
+const NVME_CMD_WRITE_ZEROES: u8 = 0x08;
+const NVME_CMD_DSM: u8 = 0x09;
+
+// Identify CNS values
+const NVME_ID_CNS_NS: u32 = 0x00;
+const NVME_ID_CNS_CTRL: u32 = 0x01;
+const NVME_ID_CNS_NS_ACTIVE_LIST: u32 = 0x02;
+const NVME_ID_CNS_NS_DESC_LIST: u32 = 0x03;
+
+// Log pages
+const NVME_LOG_ERROR: u32 = 0x01;
+const NVME_LOG_SMART: u32 = 0x02;
+const NVME_LOG_FW_SLOT: u32 = 0x03;
+
+// Features
+const NVME_FEAT_NUM_QUEUES: u32 = 0x07;
+const NVME_FEAT_MAX: u32 = 0x20;
+
+// Optional NVM commands support (ONCS)
+const NVME_CTRL_ONCS_DSM: u16 = 1 << 2;
+const NVME_CTRL_ONCS_WRITE_ZEROES: u16 = 1 << 3;
+
+/// Deallocate attribute of dataset management.
+const NVME_DSMGMT_AD: u32 = 1 << 2;
+
+// Status (the status code type in the upper byte)
+const NVME_SC_SUCCESS: u16 = 0x000;
+const NVME_SC_INVALID_OPCODE: u16 = 0x001;
+const NVME_SC_INVALID_FIELD: u16 = 0x002;
+const NVME_SC_DATA_XFER_ERROR: u16 = 0x004;
+const NVME_SC_INTERNAL: u16 = 0x006;
+const NVME_SC_INVALID_NS: u16 = 0x00b;
+const NVME_SC_NS_WRITE_PROTECTED: u16 = 0x020;
+const NVME_SC_LBA_RANGE: u16 = 0x080;
+const NVME_SC_CQ_INVALID: u16 = 0x100;
+const NVME_SC_QID_INVALID: u16 = 0x101;
+const NVME_SC_QUEUE_SIZE: u16 = 0x102;
+const NVME_SC_ASYNC_LIMIT: u16 = 0x105;
+const NVME_SC_INVALID_LOG_PAGE: u16 = 0x109;
+const NVME_SC_INVALID_QUEUE_DELETION: u16 = 0x10c;
+const NVME_SC_WRITE_FAULT: u16 = 0x280;
+const NVME_SC_READ_ERROR: u16 = 0x281;
+/// Do not retry
+const NVME_SC_DNR: u16 = 0x4000;
+
+struct SubmissionQueue {
+    base: u64,
+    size: u32,
+    head: u32,
+    tail: u32,
+    cqid: usize,
+}
+
+struct CompletionQueue {
+    base: u64,
+    size: u32,
+    head: u32,
+    tail: u32,
+    /// phase tag of the entries posted in the current pass.
+    phase: bool,
+    irq_enabled: bool,
+}
+
+impl CompletionQueue {
+    fn is_full(&self) -> bool {
+        (self.tail + 1) % self.size == self.head
+    }
+}
+
+/// Submission queue entry.
+struct Command {
+    dw: [u32; 16],
+}
+
+impl Command {
+    fn get_opcode(&self) -> u8 {
+        self.dw[0] as u8
+    }
+
+    fn get_cid(&self) -> u16 {
+        (self.dw[0] >> 16) as u16
+    }
+
+    fn get_nsid(&self) -> u32 {
+        self.dw[1]
+    }
+
+    fn get_prp(&self) -> (u64, u64) {
+        (
+            self.dw[6] as u64 | (self.dw[7] as u64) << 32,
+            self.dw[8] as u64 | (self.dw[9] as u64) << 32,
+        )
+    }
+
+    /// Command dword 10 to 15.
+    fn cdw(&self, index: usize) -> u32 {
+        self.dw[index]
+    }
+}
+
+pub struct Nvme {
+    backend: Box<dyn BlockBackend>,
+    dram_base: u64,
+    serial: String,
+    /// Interrupt mask (INTMS/INTMC)
+    intms: u32,
+    /// Controller configuration (CC)
+    cc: u32,
+    /// Controller status (CSTS)
+    csts: u32,
+    /// Admin queue attributes (AQA)
+    aqa: u32,
+    /// Admin submission and completion queue base addresses (ASQ, ACQ)
+    asq: u64,
+    acq: u64,
+    /// queues by ID, the admin queues are the first ones.
+    sqs: Vec<Option<SubmissionQueue>>,
+    cqs: Vec<Option<CompletionQueue>>,
+    /// a submission queue doorbell was rung.
+    doorbell: bool,
+    /// outstanding asynchronous event requests, never completed.
+    async_events: u8,
+    features: [u32; NVME_FEAT_MAX as usize],
+}
+
+impl Nvme {
+    pub fn new(dram_base: u64, backend: Box<dyn BlockBackend>) -> Self {
+        Nvme {
+            backend,
+            dram_base,
+            serial: "riscv-emu".to_string(),
+            intms: 0,
+            cc: 0,
+            csts: 0,
+            aqa: 0,
+            asq: 0,
+            acq: 0,
+            sqs: (0..=NVME_IO_QUEUES).map(|_| None).collect(),
+            cqs: (0..=NVME_IO_QUEUES).map(|_| None).collect(),
+            doorbell: false,
+            async_events: 0,
+            features: [0; NVME_FEAT_MAX as usize],
+        }
+    }
+
+    /// Set the serial number (up to 20 bytes, e.g. /dev/disk/by-id on Linux).
+    pub fn set_serial(&mut self, serial: &str) {
+        self.serial = serial.to_string();
+    }
+
+    pub fn get_backend(&mut self) -> &mut Box<dyn BlockBackend> {
+        &mut self.backend
+    }
+
+    /// Capacity in blocks.
+    fn get_capacity(&self) -> u64 {
+        self.backend.get_size() / NVME_BLOCK_SIZE
+    }
+
+    fn get_cap(&self) -> u64 {
+        (NVME_MAX_QUEUE_ENTRIES as u64 - 1) // MQES
+            | 1 << 16 // CQR: contiguous queues required
+            | 0xf << 24 // TO: 7.5 seconds
+            | 1 << 37 // CSS: NVM command set
+    }
+
+    fn reset_controller(&mut self) {
+        self.intms = 0;
+        self.csts = 0;
+        self.sqs.iter_mut().for_each(|sq| *sq = None);
+        self.cqs.iter_mut().for_each(|cq| *cq = None);
+        self.doorbell = false;
+        self.async_events = 0;
+        self.features = [0; NVME_FEAT_MAX as usize];
+    }
+
+    fn write_cc(&mut self, data: u32) {
+        let enabled = self.cc & NVME_CC_EN != 0;
+        self.cc = data;
+        if data & NVME_CC_EN != 0 && !enabled {
+            self.sqs[0] = Some(SubmissionQueue {
+                base: self.asq,
+                size: (self.aqa & 0xfff) + 1,
+                head: 0,
+                tail: 0,
+                cqid: 0,
+            });
+            self.cqs[0] = Some(CompletionQueue {
+                base: self.acq,
+                size: ((self.aqa >> 16) & 0xfff) + 1,
+                head: 0,
+                tail: 0,
+                phase: true,
+                irq_enabled: true,
+            });
+            self.csts = NVME_CSTS_RDY;
+        } else if data & NVME_CC_EN == 0 && enabled {
+            self.reset_controller();
+        }
+        match data & NVME_CC_SHN {
+            0 => self.csts &= !NVME_CSTS_SHST_COMPLETE,
+            _ => {
+                let _ = self.backend.flush();
+                self.csts |= NVME_CSTS_SHST_COMPLETE;
+            }
+        }
+    }
+
+    fn read_register(&self, offset: u64) -> u32 {
+        match offset {
+            NVME_REG_CAP => self.get_cap() as u32,
+            0x04 => (self.get_cap() >> 32) as u32,
+            NVME_REG_VS => NVME_VERSION,
+            NVME_REG_INTMS | NVME_REG_INTMC => self.intms,
+            NVME_REG_CC => self.cc,
+            NVME_REG_CSTS => self.csts,
+            NVME_REG_AQA => self.aqa,
+            NVME_REG_ASQ => self.asq as u32,
+            0x2c => (self.asq >> 32) as u32,
+            NVME_REG_ACQ => self.acq as u32,
+            0x34 => (self.acq >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, offset: u64, data: u32) {
+        match offset {
+            NVME_REG_INTMS => self.intms |= data,
+            NVME_REG_INTMC => self.intms &= !data,
+            NVME_REG_CC => self.write_cc(data),
+            NVME_REG_AQA => self.aqa = data,
+            NVME_REG_ASQ => self.asq = (self.asq & !0xffff_ffff) | data as u64,
+            0x2c => self.asq = (self.asq & 0xffff_ffff) | (data as u64) << 32,
+            NVME_REG_ACQ => self.acq = (self.acq & !0xffff_ffff) | data as u64,
+            0x34 => self.acq = (self.acq & 0xffff_ffff) | (data as u64) << 32,
+            _ if offset >= NVME_REG_DBS => {
+                let qid = ((offset - NVME_REG_DBS) / 8) as usize;
+                let value = data & 0xffff;
+                match (offset - NVME_REG_DBS) % 8 {
+                    0 => {
+                        if let Some(Some(sq)) = self.sqs.get_mut(qid) {
+                            sq.tail = value % sq.size;
+                            self.doorbell = true;
+                        }
+                    }
+                    _ => {
+                        if let Some(Some(cq)) = self.cqs.get_mut(qid) {
+                            cq.head = value % cq.size;
+                            // entries were freed for the pending commands.
+                            self.doorbell = true;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Guest memory segments of a data buffer described by PRP entries.
+    /// An error if a segment is out of main memory or the PRP list has more
+    /// pages than the data needs (e.g. a list pointing to itself).
+    fn get_prp_segments(
+        mem: &GuestMemory,
+        command: &Command,
+        len: u64,
+    ) -> Result<Vec<(u64, u64)>, u16> {
+        let (prp1, prp2) = command.get_prp();
+        let first = (NVME_PAGE_SIZE - prp1 % NVME_PAGE_SIZE).min(len);
+        let mut segments = vec![(prp1, first)];
+        let mut remaining = len - first;
+        if remaining > 0 && remaining <= NVME_PAGE_SIZE {
+            segments.push((prp2, remaining));
+        } else if remaining > 0 {
+            // PRP list, the last entry of a list page points to the next one.
+            let max_pages = len / NVME_PAGE_SIZE + 1;
+            let mut pages = 1;
+            let mut entry = prp2;
+            while remaining > 0 {
+                let addr = mem.read64(entry);
+                if entry.wrapping_add(8) % NVME_PAGE_SIZE == 0 && remaining > NVME_PAGE_SIZE {
+                    pages += 1;
+                    if pages > max_pages {
+                        return Err(NVME_SC_DATA_XFER_ERROR);
+                    }
+                    entry = addr;
+                    continue;
+                }
+                let len = remaining.min(NVME_PAGE_SIZE);
+                segments.push((addr, len));
+                remaining -= len;
+                entry = entry.wrapping_add(8);
+            }
+        }
+        match segments
+            .iter()
+            .all(|(addr, len)| mem.contains(*addr, *len as usize))
+        {
+            true => Ok(segments),
+            false => Err(NVME_SC_DATA_XFER_ERROR),
+        }
+    }
+
+    fn read_data(mem: &GuestMemory, command: &Command, len: u64) -> Result<Vec<u8>, u16> {
+        let mut data = vec![0; len as usize];
+        let mut offset = 0;
+        for (addr, len) in Self::get_prp_segments(mem, command, len)? {
+            mem.read_bytes(addr, &mut data[offset..offset + len as usize]);
+            offset += len as usize;
+        }
+        Ok(data)
+    }
+
+    fn write_data(mem: &mut GuestMemory, command: &Command, data: &[u8]) -> Result<(), u16> {
+        let mut offset = 0;
+        for (addr, len) in Self::get_prp_segments(mem, command, data.len() as u64)? {
+            mem.write_bytes(addr, &data[offset..offset + len as usize]);
+            offset += len as usize;
+        }
+        Ok(())
+    }
+
+    /// Post a completion queue entry.
+    fn complete(&mut self, mem: &mut GuestMemory, sqid: usize, cid: u16, status: u16, result: u32) {
+        let (sq_head, cqid) = match &self.sqs[sqid] {
+            Some(sq) => (sq.head, sq.cqid),
+            None => return,
+        };
+        let cq = match &mut self.cqs[cqid] {
+            Some(cq) => cq,
+            None => return,
+        };
+        let addr = cq.base + cq.tail as u64 * NVME_COMPLETION_SIZE;
+        let status = match status {
+            NVME_SC_SUCCESS => status,
+            _ => status | NVME_SC_DNR,
+        };
+        mem.write32(addr, result);
+        mem.write32(addr + 4, 0);
+        mem.write32(addr + 8, sq_head | (sqid as u32) << 16);
+        mem.write32(
+            addr + 12,
+            cid as u32 | (cq.phase as u32) << 16 | (status as u32) << 17,
+        );
+        cq.tail += 1;
+        if cq.tail == cq.size {
+            cq.tail = 0;
+            cq.phase = !cq.phase;
+        }
+    }
+
+    /// Returns the status and the command specific result, None if the
+    /// command completes later.
+    fn execute_admin(&mut self, mem: &mut GuestMemory, command: &Command) -> Option<(u16, u32)> {
+        let status = match command.get_opcode() {
+            NVME_ADMIN_CREATE_CQ => self.create_cq(command),
+            NVME_ADMIN_CREATE_SQ => self.create_sq(command),
+            NVME_ADMIN_DELETE_CQ => {
+                let qid = (command.cdw(10) & 0xffff) as usize;
+                if qid == 0 || self.cqs.get(qid).is_none_or(|cq| cq.is_none()) {
+                    NVME_SC_QID_INVALID
+                } else if self.sqs.iter().flatten().any(|sq| sq.cqid == qid) {
+                    NVME_SC_INVALID_QUEUE_DELETION
+                } else {
+                    self.cqs[qid] = None;
+                    NVME_SC_SUCCESS
+                }
+            }
+            NVME_ADMIN_DELETE_SQ => {
+                let qid = (command.cdw(10) & 0xffff) as usize;
+                if qid == 0 || self.sqs.get(qid).is_none_or(|sq| sq.is_none()) {
+                    NVME_SC_QID_INVALID
+                } else {
+                    self.sqs[qid] = None;
+                    NVME_SC_SUCCESS
+                }
+            }
+            NVME_ADMIN_IDENTIFY => self.identify(mem, command),
+            NVME_ADMIN_GET_LOG_PAGE => {
+                let numd = ((command.cdw(10) >> 16) & 0xfff | (command.cdw(11) & 0xffff) << 12) + 1;
+                match command.cdw(10) & 0xff {
+                    // no errors, health information or firmware slots to report.
+                    NVME_LOG_ERROR | NVME_LOG_SMART | NVME_LOG_FW_SLOT => {
+                        match Self::write_data(mem, command, &vec![0; numd as usize * 4]) {
+                            Ok(()) => NVME_SC_SUCCESS,
+                            Err(status) => status,
+                        }
+                    }
+                    _ => NVME_SC_INVALID_LOG_PAGE,
+                }
+            }
+            NVME_ADMIN_SET_FEATURES | NVME_ADMIN_GET_FEATURES => {
+                let fid = command.cdw(10) & 0xff;
+                if fid == NVME_FEAT_NUM_QUEUES {
+                    let count = NVME_IO_QUEUES as u32 - 1;
+                    return Some((NVME_SC_SUCCESS, count | count << 16));
+                }
+                if fid >= NVME_FEAT_MAX {
+                    return Some((NVME_SC_INVALID_FIELD, 0));
+                }
+                if command.get_opcode() == NVME_ADMIN_SET_FEATURES {
+                    self.features[fid as usize] = command.cdw(11);
+                }
+                return Some((NVME_SC_SUCCESS, self.features[fid as usize]));
+            }
+            NVME_ADMIN_ASYNC_EVENT => match self.async_events > NVME_AERL {
+                true => NVME_SC_ASYNC_LIMIT,
+                false => {
+                    self.async_events += 1;
+                    return None;
+                }
+            },
+            // commands are never aborted.
+            NVME_ADMIN_ABORT => return Some((NVME_SC_SUCCESS, 1)),
+            _ => NVME_SC_INVALID_OPCODE,
+        };
+        Some((status, 0))
+    }
+
+    fn create_cq(&mut self, command: &Command) -> u16 {
+        let qid = (command.cdw(10) & 0xffff) as usize;
+        let size = (command.cdw(10) >> 16) + 1;
+        if qid == 0 || self.cqs.get(qid).is_none_or(|cq| cq.is_some()) {
+            return NVME_SC_QID_INVALID;
+        }
+        if !(2..=NVME_MAX_QUEUE_ENTRIES).contains(&size) {
+            return NVME_SC_QUEUE_SIZE;
+        }
+        // physically contiguous queues only.
+        if command.cdw(11) & 0x1 == 0 {
+            return NVME_SC_INVALID_FIELD;
+        }
+        self.cqs[qid] = Some(CompletionQueue {
+            base: command.get_prp().0,
+            size,
+            head: 0,
+            tail: 0,
+            phase: true,
+            irq_enabled: command.cdw(11) & 0x2 != 0,
+        });
+        NVME_SC_SUCCESS
+    }
+
+    fn create_sq(&mut self, command: &Command) -> u16 {
+        let qid = (command.cdw(10) & 0xffff) as usize;
+        let size = (command.cdw(10) >> 16) + 1;
+        let cqid = (command.cdw(11) >> 16) as usize;
+        if qid == 0 || self.sqs.get(qid).is_none_or(|sq| sq.is_some()) {
+            return NVME_SC_QID_INVALID;
+        }
+        if cqid == 0 || self.cqs.get(cqid).is_none_or(|cq| cq.is_none()) {
+            return NVME_SC_CQ_INVALID;
+        }
+        if !(2..=NVME_MAX_QUEUE_ENTRIES).contains(&size) {
+            return NVME_SC_QUEUE_SIZE;
+        }
+        if command.cdw(11) & 0x1 == 0 {
+            return NVME_SC_INVALID_FIELD;
+        }
+        self.sqs[qid] = Some(SubmissionQueue {
+            base: command.get_prp().0,
+            size,
+            head: 0,
+            tail: 0,
+            cqid,
+        });
+        NVME_SC_SUCCESS
+    }
+
+    fn identify(&mut self, mem: &mut GuestMemory, command: &Command) -> u16 {
+        let mut data = vec![0; NVME_IDENTIFY_SIZE];
+        let put = |data: &mut Vec<u8>, offset: usize, bytes: &[u8]| {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        // strings are padded with spaces.
+        let text = |s: &str, len: usize| {
+            let mut bytes = s.as_bytes().to_vec();
+            bytes.resize(len, b' ');
+            bytes.truncate(len);
+            bytes
+        };
+        match command.cdw(10) & 0xff {
+            NVME_ID_CNS_CTRL => {
+                put(&mut data, 0x00, &NVME_VENDOR_ID.to_le_bytes());
+                put(&mut data, 0x02, &NVME_SUBSYSTEM_VENDOR_ID.to_le_bytes());
+                put(&mut data, 0x04, &text(&self.serial, 20)); // SN
+                put(&mut data, 0x18, &text("riscv-emu NVMe Ctrl", 40)); // MN
+                put(&mut data, 0x40, &text("1.0", 8)); // FR
+                data[0x48] = 6; // RAB
+                data[0x4d] = NVME_MDTS;
+                put(&mut data, 0x50, &NVME_VERSION.to_le_bytes());
+                data[0x102] = 3; // ACL
+                data[0x103] = NVME_AERL;
+                data[0x200] = 0x66; // SQES: 64 bytes
+                data[0x201] = 0x44; // CQES: 16 bytes
+                put(&mut data, 0x204, &NVME_NSID.to_le_bytes()); // NN
+                let oncs = NVME_CTRL_ONCS_DSM | NVME_CTRL_ONCS_WRITE_ZEROES;
+                put(&mut data, 0x208, &oncs.to_le_bytes());
+                data[0x20c] = 1; // VWC: volatile write cache
+                let nqn = format!("nqn.2019-08.org.qemu:{}", self.serial);
+                put(&mut data, 0x300, nqn.as_bytes()); // SUBNQN
+            }
+            NVME_ID_CNS_NS => {
+                if command.get_nsid() != NVME_NSID {
+                    return NVME_SC_INVALID_NS;
+                }
+                let capacity = self.get_capacity().to_le_bytes();
+                put(&mut data, 0x00, &capacity); // NSZE
+                put(&mut data, 0x08, &capacity); // NCAP
+                put(&mut data, 0x10, &capacity); // NUSE
+                data[0x63] = self.backend.is_read_only() as u8; // NSATTR: write protected
+                data[0x82] = 9; // LBAF0.LBADS: 512 bytes
+            }
+            NVME_ID_CNS_NS_ACTIVE_LIST => {
+                // namespaces above the NSID.
+                if command.get_nsid() < NVME_NSID {
+                    put(&mut data, 0x00, &NVME_NSID.to_le_bytes());
+                }
+            }
+            // no namespace identification descriptors.
+            NVME_ID_CNS_NS_DESC_LIST if command.get_nsid() == NVME_NSID => {}
+            NVME_ID_CNS_NS_DESC_LIST => return NVME_SC_INVALID_NS,
+            _ => return NVME_SC_INVALID_FIELD,
+        }
+        match Self::write_data(mem, command, &data) {
+            Ok(()) => NVME_SC_SUCCESS,
+            Err(status) => status,
+        }
+    }
+
+    /// Byte offset and length of the blocks of a read or a write.
+    fn get_block_range(&self, command: &Command) -> Result<(u64, u64), u16> {
+        let slba = command.cdw(10) as u64 | (command.cdw(11) as u64) << 32;
+        let nlb = (command.cdw(12) & 0xffff) as u64 + 1;
+        match slba.checked_add(nlb) {
+            Some(end) if end <= self.get_capacity() => {
+                Ok((slba * NVME_BLOCK_SIZE, nlb * NVME_BLOCK_SIZE))
+            }
+            _ => Err(NVME_SC_LBA_RANGE),
+        }
+    }
+
+    fn execute_io(&mut self, mem: &mut GuestMemory, command: &Command) -> u16 {
+        let opcode = command.get_opcode();
+        let nsid = command.get_nsid();
+        // a flush of all the namespaces
+        if nsid != NVME_NSID && !(opcode == NVME_CMD_FLUSH && nsid == 0xffff_ffff) {
+            return NVME_SC_INVALID_NS;
+        }
+        let writes = [NVME_CMD_WRITE, NVME_CMD_WRITE_ZEROES, NVME_CMD_DSM];
+        if writes.contains(&opcode) && self.backend.is_read_only() {
+            return NVME_SC_NS_WRITE_PROTECTED;
+        }
+        match opcode {
+            NVME_CMD_FLUSH => match self.backend.flush() {
+                Ok(()) => NVME_SC_SUCCESS,
+                Err(_) => NVME_SC_WRITE_FAULT,
+            },
+            NVME_CMD_READ | NVME_CMD_WRITE => {
+                let (offset, len) = match self.get_block_range(command) {
+                    Ok(range) => range,
+                    Err(status) => return status,
+                };
+                if len > NVME_PAGE_SIZE << NVME_MDTS {
+                    return NVME_SC_INVALID_FIELD;
+                }
+                if opcode == NVME_CMD_READ {
+                    let mut data = vec![0; len as usize];
+                    if self.backend.read_at(offset, &mut data).is_err() {
+                        return NVME_SC_READ_ERROR;
+                    }
+                    match Self::write_data(mem, command, &data) {
+                        Ok(()) => NVME_SC_SUCCESS,
+                        Err(status) => status,
+                    }
+                } else {
+                    let data = match Self::read_data(mem, command, len) {
+                        Ok(data) => data,
+                        Err(status) => return status,
+                    };
+                    match self.backend.write_at(offset, &data) {
+                        Ok(()) => NVME_SC_SUCCESS,
+                        Err(_) => NVME_SC_WRITE_FAULT,
+                    }
+                }
+            }
+            NVME_CMD_WRITE_ZEROES => {
+                let (offset, len) = match self.get_block_range(command) {
+                    Ok(range) => range,
+                    Err(status) => return status,
+                };
+                match self.backend.write_at(offset, &vec![0; len as usize]) {
+                    Ok(()) => NVME_SC_SUCCESS,
+                    Err(_) => NVME_SC_WRITE_FAULT,
+                }
+            }
+            NVME_CMD_DSM => {
+                if command.cdw(11) & NVME_DSMGMT_AD == 0 {
+                    return NVME_SC_SUCCESS;
+                }
+                /* Range definition
+                 * ----------------
+                 * u32 context attributes
+                 * u32 length in logical blocks
+                 * u64 starting LBA
+                 */
+                let count = (command.cdw(10) & 0xff) as u64 + 1;
+                let ranges = match Self::read_data(mem, command, count * 16) {
+                    Ok(ranges) => ranges,
+                    Err(status) => return status,
+                };
+                for range in ranges.chunks_exact(16) {
+                    let nlb = u32::from_le_bytes([range[4], range[5], range[6], range[7]]) as u64;
+                    let mut slba = [0; 8];
+                    slba.copy_from_slice(&range[8..16]);
+                    let slba = u64::from_le_bytes(slba);
+                    match slba.checked_add(nlb) {
+                        Some(end) if end <= self.get_capacity() => {}
+                        _ => return NVME_SC_LBA_RANGE,
+                    }
+                    if self
+                        .backend
+                        .discard(slba * NVME_BLOCK_SIZE, nlb * NVME_BLOCK_SIZE)
+                        .is_err()
+                    {
+                        return NVME_SC_INTERNAL;
+                    }
+                }
+                NVME_SC_SUCCESS
+            }
+            _ => NVME_SC_INVALID_OPCODE,
+        }
+    }
+}
+
+impl PciDevice for Nvme {
+    fn get_vendor_id(&self) -> u16 {
+        NVME_VENDOR_ID
+    }
+
+    fn get_device_id(&self) -> u16 {
+        NVME_DEVICE_ID
+    }
+
+    fn get_revision(&self) -> u8 {
+        2
+    }
+
+    fn get_class_code(&self) -> u32 {
+        PCI_CLASS_STORAGE_NVME
+    }
+
+    fn get_subsystem_vendor_id(&self) -> u16 {
+        NVME_SUBSYSTEM_VENDOR_ID
+    }
+
+    fn get_bar_sizes(&self) -> Vec<u64> {
+        vec![NVME_BAR_SIZE]
+    }
+
+    fn read_bar(&mut self, _bar: usize, offset: u64, size: usize) -> u64 {
+        let data = self.read_register(offset & !0x3) as u64;
+        match size {
+            8 => data | (self.read_register((offset & !0x3) + 4) as u64) << 32,
+            4 => data,
+            _ => (data >> ((offset & 0x3) * 8)) & ((1 << (size * 8)) - 1),
+        }
+    }
+
+    fn write_bar(&mut self, _bar: usize, offset: u64, data: u64, size: usize) {
+        self.write_register(offset, data as u32);
+        if size == 8 {
+            self.write_register(offset + 4, (data >> 32) as u32);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.cc = 0;
+        self.aqa = 0;
+        self.asq = 0;
+        self.acq = 0;
+        self.reset_controller();
+    }
+
+    fn tick(&mut self, dram: &mut Memory) {
+        if !self.doorbell || self.csts & NVME_CSTS_RDY == 0 {
+            return;
+        }
+        self.doorbell = false;
+        let mut mem = GuestMemory::new(dram, self.dram_base);
+        for qid in 0..self.sqs.len() {
+            loop {
+                let addr = match &mut self.sqs[qid] {
+                    Some(sq) if sq.head != sq.tail => {
+                        // the command waits for a free completion entry.
+                        if self.cqs[sq.cqid].as_ref().is_none_or(|cq| cq.is_full()) {
+                            break;
+                        }
+                        let addr = sq.base + sq.head as u64 * NVME_COMMAND_SIZE;
+                        sq.head = (sq.head + 1) % sq.size;
+                        addr
+                    }
+                    _ => break,
+                };
+                let mut command = Command { dw: [0; 16] };
+                for (i, dw) in command.dw.iter_mut().enumerate() {
+                    *dw = mem.read32(addr + i as u64 * 4);
+                }
+                let completion = match qid {
+                    0 => self.execute_admin(&mut mem, &command),
+                    _ => Some((self.execute_io(&mut mem, &command), 0)),
+                };
+                if let Some((status, result)) = completion {
+                    self.complete(&mut mem, qid, command.get_cid(), status, result);
+                }
+            }
+        }
+    }
+
+    fn is_irq(&mut self) -> bool {
+        // all the completion queues share the vector 0.
+        self.intms & 0x1 == 0
+            && self
+                .cqs
+                .iter()
+                .flatten()
+                .any(|cq| cq.irq_enabled && cq.head != cq.tail)
+    }
+}
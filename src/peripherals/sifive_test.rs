@@ -0,0 +1,48 @@
+// SiFive test device of the QEMU virt machine, described as "sifive,test0"
+// and "syscon" in the device tree. The syscon-poweroff and syscon-reboot
+// drivers of Linux and the firmwares write a finisher code to stop or
+// reboot the machine.
+
+use crate::machine::ExitStatus;
+
+// Finisher codes, the exit code of a failure is in the upper 16 bits.
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+pub struct SifiveTest {
+    exit_request: Option<ExitStatus>,
+}
+
+impl Default for SifiveTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SifiveTest {
+    pub fn new() -> Self {
+        SifiveTest { exit_request: None }
+    }
+
+    /// The guest stopped or rebooted the machine since the last call.
+    pub fn take_exit_request(&mut self) -> Option<ExitStatus> {
+        self.exit_request.take()
+    }
+
+    pub fn read(&mut self, _addr: u64) -> u32 {
+        0
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        if addr != 0 {
+            return;
+        }
+        self.exit_request = match data & 0xffff {
+            FINISHER_FAIL => Some(ExitStatus::PowerOff(data >> 16)),
+            FINISHER_PASS => Some(ExitStatus::PowerOff(0)),
+            FINISHER_RESET => Some(ExitStatus::Reboot),
+            _ => return,
+        };
+    }
+}
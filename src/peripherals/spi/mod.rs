@@ -0,0 +1,14 @@
+// SPI (Serial Peripheral Interface) slave devices
+// The controllers of the SoCs shift the frames of their transfers through
+// the device selected by the chip select lines.
+
+pub mod nor_flash;
+pub mod sd_card;
+
+pub trait SpiDevice {
+    /// The chip select line of the device is asserted (true) or deasserted.
+    fn select(&mut self, selected: bool);
+    /// Exchange a frame: the byte from the controller is shifted in while the
+    /// returned byte is shifted out.
+    fn transfer(&mut self, data: u8) -> u8;
+}
@@ -0,0 +1,266 @@
+// Serial NOR flash with the common command set of the ISSI IS25LP family,
+// like the flash of the HiFive1 boards.
+// https://www.issi.com/WW/pdf/25LP-WP128F.pdf
+//
+// The commands complete immediately, the status never reports a write in
+// progress.
+
+use crate::peripherals::spi::SpiDevice;
+
+// Commands
+const NOR_CMD_WRITE_STATUS: u8 = 0x01;
+const NOR_CMD_PAGE_PROGRAM: u8 = 0x02;
+const NOR_CMD_READ: u8 = 0x03;
+const NOR_CMD_WRITE_DISABLE: u8 = 0x04;
+const NOR_CMD_READ_STATUS: u8 = 0x05;
+const NOR_CMD_WRITE_ENABLE: u8 = 0x06;
+const NOR_CMD_FAST_READ: u8 = 0x0b;
+const NOR_CMD_SECTOR_ERASE: u8 = 0x20;
+const NOR_CMD_QUAD_PAGE_PROGRAM: u8 = 0x32;
+const NOR_CMD_DUAL_OUTPUT_READ: u8 = 0x3b;
+const NOR_CMD_BLOCK_ERASE_32K: u8 = 0x52;
+const NOR_CMD_CHIP_ERASE: u8 = 0x60;
+const NOR_CMD_RESET_ENABLE: u8 = 0x66;
+const NOR_CMD_QUAD_OUTPUT_READ: u8 = 0x6b;
+const NOR_CMD_RESET: u8 = 0x99;
+const NOR_CMD_JEDEC_ID: u8 = 0x9f;
+const NOR_CMD_RELEASE_POWER_DOWN: u8 = 0xab;
+const NOR_CMD_POWER_DOWN: u8 = 0xb9;
+const NOR_CMD_DUAL_IO_READ: u8 = 0xbb;
+const NOR_CMD_CHIP_ERASE_ALT: u8 = 0xc7;
+const NOR_CMD_BLOCK_ERASE: u8 = 0xd8;
+const NOR_CMD_QUAD_IO_READ: u8 = 0xeb;
+
+/// Manufacturer (ISSI), memory type and capacity (16 MiB).
+pub const NOR_JEDEC_ID: [u8; 3] = [0x9d, 0x60, 0x18];
+pub const NOR_FLASH_SIZE: usize = 16 * 1024 * 1024;
+pub const NOR_PAGE_SIZE: usize = 256;
+
+// Status register
+const NOR_STATUS_WEL: u8 = 0x02;
+/// Block protection and quad enable bits, written by WRITE_STATUS.
+const NOR_STATUS_WRITABLE: u8 = 0xfc;
+
+const NOR_ADDRESS_BYTES: usize = 3;
+
+enum NorState {
+    /// Waiting for the command of the transaction.
+    Command,
+    /// Receiving the address of the command.
+    Address {
+        command: u8,
+        address: u32,
+        count: usize,
+    },
+    /// Mode and dummy bytes before the data of a read.
+    Dummy {
+        remaining: usize,
+    },
+    Read,
+    Program,
+    ReadStatus,
+    WriteStatus,
+    /// The rest of the transaction is ignored.
+    Ignore,
+}
+
+pub struct NorFlash {
+    data: Vec<u8>,
+    status: u8,
+    state: NorState,
+    /// Address of the read or the program in progress.
+    address: usize,
+    /// Output of the commands returning a fixed sequence (JEDEC ID).
+    output: Vec<u8>,
+}
+
+impl NorFlash {
+    /// An erased flash of the size (a power of two).
+    pub fn new(size: usize) -> Self {
+        NorFlash {
+            data: vec![0xff; size],
+            status: 0,
+            state: NorState::Ignore,
+            address: 0,
+            output: vec![],
+        }
+    }
+
+    /// Write the data from the start of the flash, like a programmer.
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn mask(&self, address: u32) -> usize {
+        address as usize & (self.data.len() - 1)
+    }
+
+    /// Mode and dummy bytes between the address and the data of a read.
+    fn get_dummy_bytes(command: u8) -> usize {
+        match command {
+            NOR_CMD_READ => 0,
+            // 6 cycles on 4 lines, the mode byte then 2 dummy bytes.
+            NOR_CMD_QUAD_IO_READ => 3,
+            _ => 1,
+        }
+    }
+
+    fn erase(&mut self, address: usize, size: usize) {
+        let start = address & !(size - 1);
+        for byte in self.data[start..start + size].iter_mut() {
+            *byte = 0xff;
+        }
+    }
+
+    fn start_command(&mut self, command: u8) -> NorState {
+        match command {
+            NOR_CMD_READ
+            | NOR_CMD_FAST_READ
+            | NOR_CMD_DUAL_OUTPUT_READ
+            | NOR_CMD_QUAD_OUTPUT_READ
+            | NOR_CMD_DUAL_IO_READ
+            | NOR_CMD_QUAD_IO_READ
+            | NOR_CMD_PAGE_PROGRAM
+            | NOR_CMD_QUAD_PAGE_PROGRAM
+            | NOR_CMD_SECTOR_ERASE
+            | NOR_CMD_BLOCK_ERASE_32K
+            | NOR_CMD_BLOCK_ERASE => NorState::Address {
+                command,
+                address: 0,
+                count: 0,
+            },
+            NOR_CMD_WRITE_ENABLE => {
+                self.status |= NOR_STATUS_WEL;
+                NorState::Ignore
+            }
+            NOR_CMD_WRITE_DISABLE | NOR_CMD_RESET_ENABLE | NOR_CMD_RESET => {
+                self.status &= !NOR_STATUS_WEL;
+                NorState::Ignore
+            }
+            NOR_CMD_READ_STATUS => NorState::ReadStatus,
+            NOR_CMD_WRITE_STATUS => match self.status & NOR_STATUS_WEL {
+                0 => NorState::Ignore,
+                _ => NorState::WriteStatus,
+            },
+            NOR_CMD_CHIP_ERASE | NOR_CMD_CHIP_ERASE_ALT => {
+                if self.status & NOR_STATUS_WEL != 0 {
+                    let size = self.data.len();
+                    self.erase(0, size);
+                    self.status &= !NOR_STATUS_WEL;
+                }
+                NorState::Ignore
+            }
+            NOR_CMD_JEDEC_ID => {
+                self.output = NOR_JEDEC_ID.to_vec();
+                NorState::Ignore
+            }
+            // power down is not modeled, the flash always answers.
+            NOR_CMD_POWER_DOWN | NOR_CMD_RELEASE_POWER_DOWN => NorState::Ignore,
+            _ => NorState::Ignore,
+        }
+    }
+
+    fn execute(&mut self, command: u8, address: usize) -> NorState {
+        self.address = address;
+        let write_enabled = self.status & NOR_STATUS_WEL != 0;
+        match command {
+            NOR_CMD_PAGE_PROGRAM | NOR_CMD_QUAD_PAGE_PROGRAM if write_enabled => NorState::Program,
+            NOR_CMD_SECTOR_ERASE | NOR_CMD_BLOCK_ERASE_32K | NOR_CMD_BLOCK_ERASE
+                if write_enabled =>
+            {
+                let size = match command {
+                    NOR_CMD_SECTOR_ERASE => 4 * 1024,
+                    NOR_CMD_BLOCK_ERASE_32K => 32 * 1024,
+                    _ => 64 * 1024,
+                };
+                self.erase(address, size.min(self.data.len()));
+                self.status &= !NOR_STATUS_WEL;
+                NorState::Ignore
+            }
+            NOR_CMD_PAGE_PROGRAM
+            | NOR_CMD_QUAD_PAGE_PROGRAM
+            | NOR_CMD_SECTOR_ERASE
+            | NOR_CMD_BLOCK_ERASE_32K
+            | NOR_CMD_BLOCK_ERASE => NorState::Ignore,
+            _ => match NorFlash::get_dummy_bytes(command) {
+                0 => NorState::Read,
+                remaining => NorState::Dummy { remaining },
+            },
+        }
+    }
+}
+
+impl SpiDevice for NorFlash {
+    fn select(&mut self, selected: bool) {
+        // a program ends with the transaction.
+        if let NorState::Program = self.state {
+            self.status &= !NOR_STATUS_WEL;
+        }
+        self.output.clear();
+        self.state = match selected {
+            true => NorState::Command,
+            false => NorState::Ignore,
+        };
+    }
+
+    fn transfer(&mut self, data: u8) -> u8 {
+        let state = std::mem::replace(&mut self.state, NorState::Ignore);
+        let (state, out) = match state {
+            NorState::Command => (self.start_command(data), 0xff),
+            NorState::Address {
+                command,
+                address,
+                count,
+            } => {
+                let address = address << 8 | data as u32;
+                match count + 1 {
+                    NOR_ADDRESS_BYTES => {
+                        let address = self.mask(address);
+                        (self.execute(command, address), 0xff)
+                    }
+                    count => (
+                        NorState::Address {
+                            command,
+                            address,
+                            count,
+                        },
+                        0xff,
+                    ),
+                }
+            }
+            NorState::Dummy { remaining } => match remaining - 1 {
+                0 => (NorState::Read, 0xff),
+                remaining => (NorState::Dummy { remaining }, 0xff),
+            },
+            NorState::Read => {
+                let out = self.data[self.address];
+                self.address = (self.address + 1) & (self.data.len() - 1);
+                (NorState::Read, out)
+            }
+            NorState::Program => {
+                // programming only clears bits, and wraps in the page.
+                self.data[self.address] &= data;
+                let page = self.address & !(NOR_PAGE_SIZE - 1);
+                self.address = page | ((self.address + 1) & (NOR_PAGE_SIZE - 1));
+                (NorState::Program, 0xff)
+            }
+            NorState::ReadStatus => (NorState::ReadStatus, self.status),
+            NorState::WriteStatus => {
+                self.status = (self.status & !NOR_STATUS_WRITABLE) | (data & NOR_STATUS_WRITABLE);
+                self.status &= !NOR_STATUS_WEL;
+                (NorState::Ignore, 0xff)
+            }
+            NorState::Ignore => match self.output.is_empty() {
+                true => (NorState::Ignore, 0xff),
+                false => (NorState::Ignore, self.output.remove(0)),
+            },
+        };
+        self.state = state;
+        out
+    }
+}
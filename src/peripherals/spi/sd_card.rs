@@ -0,0 +1,388 @@
+// SD card in the SPI mode, a high capacity card (SDHC) addressed in blocks.
+// https://www.sdcard.org/downloads/pls/ (Physical Layer Simplified
+// Specification, chapter 7 "SPI Mode")
+//
+// The card is ready as soon as ACMD41 is received and never reports busy.
+
+use crate::block::BlockBackend;
+use crate::peripherals::spi::SpiDevice;
+
+use std::collections::VecDeque;
+
+pub const SD_BLOCK_SIZE: usize = 512;
+
+// Commands
+const SD_CMD_GO_IDLE_STATE: u8 = 0;
+const SD_CMD_SEND_OP_COND: u8 = 1;
+const SD_CMD_SEND_IF_COND: u8 = 8;
+const SD_CMD_SEND_CSD: u8 = 9;
+const SD_CMD_SEND_CID: u8 = 10;
+const SD_CMD_STOP_TRANSMISSION: u8 = 12;
+const SD_CMD_SEND_STATUS: u8 = 13;
+const SD_CMD_SET_BLOCKLEN: u8 = 16;
+const SD_CMD_READ_SINGLE_BLOCK: u8 = 17;
+const SD_CMD_READ_MULTIPLE_BLOCK: u8 = 18;
+const SD_CMD_WRITE_BLOCK: u8 = 24;
+const SD_CMD_WRITE_MULTIPLE_BLOCK: u8 = 25;
+const SD_CMD_ERASE_WR_BLK_START: u8 = 32;
+const SD_CMD_ERASE_WR_BLK_END: u8 = 33;
+const SD_CMD_ERASE: u8 = 38;
+const SD_CMD_APP_CMD: u8 = 55;
+const SD_CMD_READ_OCR: u8 = 58;
+const SD_CMD_CRC_ON_OFF: u8 = 59;
+
+// Application commands (after APP_CMD)
+const SD_ACMD_SET_WR_BLK_ERASE_COUNT: u8 = 23;
+const SD_ACMD_SD_SEND_OP_COND: u8 = 41;
+const SD_ACMD_SEND_SCR: u8 = 51;
+
+// R1 response
+const SD_R1_IDLE: u8 = 0x01;
+const SD_R1_ILLEGAL_COMMAND: u8 = 0x04;
+const SD_R1_PARAMETER_ERROR: u8 = 0x40;
+
+// Tokens
+const SD_TOKEN_START_BLOCK: u8 = 0xfe;
+const SD_TOKEN_START_MULTIPLE: u8 = 0xfc;
+const SD_TOKEN_STOP_TRAN: u8 = 0xfd;
+const SD_TOKEN_DATA_ERROR: u8 = 0x01;
+const SD_DATA_ACCEPTED: u8 = 0x05;
+const SD_DATA_WRITE_ERROR: u8 = 0x0d;
+
+/// Power up done, card capacity status (SDHC) and 2.7-3.6V.
+const SD_OCR: u32 = 0xc0ff_8000;
+const SD_OCR_BUSY: u32 = 0x8000_0000;
+
+/// Version 1.0 of the specification, 1 and 4 bit bus widths.
+const SD_SCR: [u8; 8] = [0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+const SD_COMMAND_SIZE: usize = 6;
+
+enum SdState {
+    /// Receiving commands.
+    Command,
+    /// Waiting for the start token of the data of a write.
+    WriteToken { block: u64, multiple: bool },
+    /// Receiving the data block and its CRC.
+    WriteData { block: u64, multiple: bool },
+}
+
+pub struct SdCard {
+    disk: Box<dyn BlockBackend>,
+    state: SdState,
+    idle: bool,
+    /// The next command is an application command.
+    app_command: bool,
+    /// Command frame being received.
+    command: Vec<u8>,
+    /// Data block being received.
+    data: Vec<u8>,
+    /// Bytes to send to the controller.
+    output: VecDeque<u8>,
+    /// Next block of a multiple block read.
+    read_block: Option<u64>,
+    erase_start: u64,
+    erase_end: u64,
+}
+
+/// CRC7 of the commands and of the CSD and CID registers.
+fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        for bit in (0..8).rev() {
+            let input = (byte >> bit) & 1;
+            let msb = (crc >> 6) & 1;
+            crc = (crc << 1) & 0x7f;
+            if input ^ msb != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// CRC16 (CCITT) of the data blocks.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x1021,
+        })
+    })
+}
+
+impl SdCard {
+    pub fn new(disk: Box<dyn BlockBackend>) -> Self {
+        SdCard {
+            disk,
+            state: SdState::Command,
+            idle: true,
+            app_command: false,
+            command: vec![],
+            data: vec![],
+            output: VecDeque::new(),
+            read_block: None,
+            erase_start: 0,
+            erase_end: 0,
+        }
+    }
+
+    fn get_blocks(&self) -> u64 {
+        self.disk.get_size() / SD_BLOCK_SIZE as u64
+    }
+
+    fn r1(&self) -> u8 {
+        match self.idle {
+            true => SD_R1_IDLE,
+            false => 0,
+        }
+    }
+
+    /// Card specific data, version 2.0.
+    fn get_csd(&self) -> [u8; 16] {
+        // device size in units of 512 KiB, minus 1.
+        let size = (self.get_blocks() / 1024).max(1) - 1;
+        let mut csd = [
+            0x40,
+            0x0e,
+            0x00,
+            0x32,
+            0x5b,
+            0x59,
+            0x00,
+            (size >> 16) as u8 & 0x3f,
+            (size >> 8) as u8,
+            size as u8,
+            0x7f,
+            0x80,
+            0x0a,
+            0x40,
+            0x00,
+            0x00,
+        ];
+        csd[15] = crc7(&csd[..15]) << 1 | 1;
+        csd
+    }
+
+    /// Card identification.
+    fn get_cid(&self) -> [u8; 16] {
+        let mut cid = [
+            0x02, b'R', b'V', b'E', b'M', b'U', b'S', b'D', 0x10, 0x12, 0x34, 0x56, 0x78, 0x01,
+            0x4a, 0x00,
+        ];
+        cid[15] = crc7(&cid[..15]) << 1 | 1;
+        cid
+    }
+
+    /// Queue a data block, after the start token and followed by its CRC.
+    fn queue_data(&mut self, data: &[u8]) {
+        self.output.push_back(0xff);
+        self.output.push_back(SD_TOKEN_START_BLOCK);
+        self.output.extend(data.iter());
+        self.output.extend(crc16(data).to_be_bytes().iter());
+    }
+
+    /// Queue a block of the disk, or the error token.
+    fn queue_block(&mut self, block: u64) {
+        let mut data = vec![0; SD_BLOCK_SIZE];
+        match self.disk.read_at(block * SD_BLOCK_SIZE as u64, &mut data) {
+            Ok(()) => self.queue_data(&data),
+            Err(_) => {
+                self.output.push_back(0xff);
+                self.output.push_back(SD_TOKEN_DATA_ERROR);
+                self.read_block = None;
+            }
+        }
+    }
+
+    /// Run a command, the response follows a byte of delay.
+    fn execute(&mut self, index: u8, arg: u32) {
+        let app_command = self.app_command;
+        self.app_command = false;
+        self.output.clear();
+        self.output.push_back(0xff);
+
+        // a new command stops the multiple block read.
+        self.read_block = None;
+        let in_range = (arg as u64) < self.get_blocks();
+        match index {
+            SD_CMD_GO_IDLE_STATE => {
+                self.idle = true;
+                self.output.push_back(SD_R1_IDLE);
+            }
+            SD_CMD_SEND_OP_COND => {
+                self.idle = false;
+                self.output.push_back(0);
+            }
+            SD_ACMD_SD_SEND_OP_COND if app_command => {
+                self.idle = false;
+                self.output.push_back(0);
+            }
+            SD_CMD_SEND_IF_COND => {
+                // R7: the voltage is accepted and the check pattern echoed.
+                self.output.push_back(self.r1());
+                self.output
+                    .extend(&[0x00, 0x00, (arg >> 8) as u8 & 0xf, arg as u8]);
+            }
+            SD_CMD_READ_OCR => {
+                let ocr = match self.idle {
+                    true => SD_OCR & !SD_OCR_BUSY,
+                    false => SD_OCR,
+                };
+                self.output.push_back(self.r1());
+                self.output.extend(ocr.to_be_bytes().iter());
+            }
+            SD_CMD_SEND_CSD => {
+                self.output.push_back(self.r1());
+                let csd = self.get_csd();
+                self.queue_data(&csd);
+            }
+            SD_CMD_SEND_CID => {
+                self.output.push_back(self.r1());
+                let cid = self.get_cid();
+                self.queue_data(&cid);
+            }
+            SD_ACMD_SEND_SCR if app_command => {
+                self.output.push_back(self.r1());
+                self.queue_data(&SD_SCR);
+            }
+            SD_CMD_SEND_STATUS => {
+                // R2
+                self.output.push_back(self.r1());
+                self.output.push_back(0);
+            }
+            SD_CMD_READ_SINGLE_BLOCK | SD_CMD_READ_MULTIPLE_BLOCK if in_range => {
+                self.output.push_back(self.r1());
+                if index == SD_CMD_READ_MULTIPLE_BLOCK {
+                    self.read_block = Some(arg as u64 + 1);
+                }
+                self.queue_block(arg as u64);
+            }
+            SD_CMD_WRITE_BLOCK | SD_CMD_WRITE_MULTIPLE_BLOCK if in_range => {
+                self.output.push_back(self.r1());
+                self.state = SdState::WriteToken {
+                    block: arg as u64,
+                    multiple: index == SD_CMD_WRITE_MULTIPLE_BLOCK,
+                };
+            }
+            SD_CMD_READ_SINGLE_BLOCK
+            | SD_CMD_READ_MULTIPLE_BLOCK
+            | SD_CMD_WRITE_BLOCK
+            | SD_CMD_WRITE_MULTIPLE_BLOCK => {
+                self.output.push_back(self.r1() | SD_R1_PARAMETER_ERROR);
+            }
+            SD_CMD_ERASE_WR_BLK_START => {
+                self.erase_start = arg as u64;
+                self.output.push_back(self.r1());
+            }
+            SD_CMD_ERASE_WR_BLK_END => {
+                self.erase_end = arg as u64;
+                self.output.push_back(self.r1());
+            }
+            SD_CMD_ERASE => {
+                let start = self.erase_start * SD_BLOCK_SIZE as u64;
+                let len =
+                    (self.erase_end + 1).saturating_sub(self.erase_start) * SD_BLOCK_SIZE as u64;
+                let r1 = match self.disk.discard(start, len) {
+                    Ok(()) => self.r1(),
+                    Err(_) => self.r1() | SD_R1_PARAMETER_ERROR,
+                };
+                self.output.push_back(r1);
+            }
+            SD_CMD_APP_CMD => {
+                self.app_command = true;
+                self.output.push_back(self.r1());
+            }
+            SD_CMD_STOP_TRANSMISSION
+            | SD_CMD_SET_BLOCKLEN
+            | SD_CMD_CRC_ON_OFF
+            | SD_ACMD_SET_WR_BLK_ERASE_COUNT => {
+                // the blocks are always 512 bytes and the CRCs are not checked.
+                self.output.push_back(self.r1());
+            }
+            _ => self.output.push_back(self.r1() | SD_R1_ILLEGAL_COMMAND),
+        }
+    }
+
+    fn receive_command(&mut self, data: u8) {
+        // a frame starts with the bits 01.
+        if self.command.is_empty() && data & 0xc0 != 0x40 {
+            return;
+        }
+        self.command.push(data);
+        if self.command.len() == SD_COMMAND_SIZE {
+            let index = self.command[0] & 0x3f;
+            let arg = u32::from_be_bytes([
+                self.command[1],
+                self.command[2],
+                self.command[3],
+                self.command[4],
+            ]);
+            self.command.clear();
+            self.execute(index, arg);
+        }
+    }
+
+    fn receive_data(&mut self, data: u8, block: u64, multiple: bool) -> SdState {
+        self.data.push(data);
+        if self.data.len() < SD_BLOCK_SIZE + 2 {
+            return SdState::WriteData { block, multiple };
+        }
+        let response = match self
+            .disk
+            .write_at(block * SD_BLOCK_SIZE as u64, &self.data[..SD_BLOCK_SIZE])
+        {
+            Ok(()) => SD_DATA_ACCEPTED,
+            Err(_) => SD_DATA_WRITE_ERROR,
+        };
+        self.data.clear();
+        self.output.push_back(response);
+        match multiple && response == SD_DATA_ACCEPTED {
+            true => SdState::WriteToken {
+                block: block + 1,
+                multiple,
+            },
+            false => SdState::Command,
+        }
+    }
+}
+
+impl SpiDevice for SdCard {
+    fn select(&mut self, selected: bool) {
+        if !selected {
+            self.command.clear();
+        }
+    }
+
+    fn transfer(&mut self, data: u8) -> u8 {
+        if self.output.is_empty() {
+            if let Some(block) = self.read_block {
+                match block < self.get_blocks() {
+                    true => {
+                        self.read_block = Some(block + 1);
+                        self.queue_block(block);
+                    }
+                    false => self.read_block = None,
+                }
+            }
+        }
+        let out = self.output.pop_front().unwrap_or(0xff);
+
+        let state = std::mem::replace(&mut self.state, SdState::Command);
+        self.state = match state {
+            SdState::Command => {
+                self.receive_command(data);
+                // a write command changes the state.
+                std::mem::replace(&mut self.state, SdState::Command)
+            }
+            SdState::WriteToken { block, multiple } => match data {
+                SD_TOKEN_START_BLOCK if !multiple => SdState::WriteData { block, multiple },
+                SD_TOKEN_START_MULTIPLE if multiple => SdState::WriteData { block, multiple },
+                SD_TOKEN_STOP_TRAN if multiple => SdState::Command,
+                _ => SdState::WriteToken { block, multiple },
+            },
+            SdState::WriteData { block, multiple } => self.receive_data(data, block, multiple),
+        };
+        out
+    }
+}
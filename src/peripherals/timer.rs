@@ -1,5 +1,8 @@
 pub trait Timer {
     fn tick(&mut self);
+    /// Clock the timer at the rtc_frequency (Hz) of its time base, from the
+    /// ticks of the core_frequency (Hz).
+    fn set_frequency(&mut self, core_frequency: u64, rtc_frequency: u64);
     fn is_pending_software_interrupt(&mut self, core: usize) -> bool;
     fn is_pending_timer_interrupt(&mut self, core: usize) -> bool;
     fn read(&mut self, addr: u64) -> u32;
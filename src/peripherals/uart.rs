@@ -59,6 +59,20 @@ impl Uart {
         }
     }
 
+    /// Reset the registers, the console is kept.
+    pub fn reset(&mut self) {
+        self.rhr = 0;
+        self.thr = 0;
+        self.ier = 0;
+        self.isr = 0x0e;
+        self.fcr = 0;
+        self.lcr = 0;
+        self.mcr = 0;
+        self.lsr = 0x20;
+        self.msr = 0;
+        self.spr = 0;
+    }
+
     pub fn get_console(&mut self) -> &mut Box<dyn Console> {
         &mut self.console
     }
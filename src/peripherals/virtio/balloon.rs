@@ -0,0 +1,263 @@
+// Virtio Memory Balloon Device
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-2790005
+
+use crate::peripherals::virtio::queue::{DescriptorChain, GuestMemory, Queue};
+use crate::peripherals::virtio::{VirtioDevice, VIRTIO_ID_BALLOON};
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+// Feature bits
+const VIRTIO_BALLOON_F_STATS_VQ: u64 = 1 << 1;
+const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u64 = 1 << 2;
+
+// Statistics tags
+const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
+const VIRTIO_BALLOON_S_SWAP_OUT: u16 = 1;
+const VIRTIO_BALLOON_S_MAJFLT: u16 = 2;
+const VIRTIO_BALLOON_S_MINFLT: u16 = 3;
+const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+const VIRTIO_BALLOON_S_MEMTOT: u16 = 5;
+const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+const VIRTIO_BALLOON_S_CACHES: u16 = 7;
+
+const INFLATE_QUEUE: usize = 0;
+const DEFLATE_QUEUE: usize = 1;
+const STATS_QUEUE: usize = 2;
+
+/// The balloon works in 4KiB pages whatever the guest page size is.
+pub const BALLOON_PAGE_SIZE: u64 = 4096;
+
+/// Memory statistics reported by the guest (None if not reported).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BalloonStats {
+    /// bytes swapped in and out.
+    pub swap_in: Option<u64>,
+    pub swap_out: Option<u64>,
+    pub major_faults: Option<u64>,
+    pub minor_faults: Option<u64>,
+    /// memory sizes in bytes.
+    pub free_memory: Option<u64>,
+    pub total_memory: Option<u64>,
+    pub available_memory: Option<u64>,
+    pub disk_caches: Option<u64>,
+}
+
+struct BalloonState {
+    /// number of pages the host asks the guest to give up.
+    target_pages: u32,
+    /// number of pages the driver says it has given up.
+    actual_pages: u32,
+    /// guest page frame numbers in the balloon.
+    pages: BTreeSet<u32>,
+    stats: Option<BalloonStats>,
+    stats_requested: bool,
+    config_changed: bool,
+}
+
+/// Host side control of a balloon device attached to the machine.
+#[derive(Clone)]
+pub struct BalloonHandle {
+    state: Rc<RefCell<BalloonState>>,
+}
+
+impl BalloonHandle {
+    /// Ask the guest to inflate (or deflate) the balloon to the number of pages.
+    pub fn set_target(&self, pages: u32) {
+        let mut state = self.state.borrow_mut();
+        state.target_pages = pages;
+        state.config_changed = true;
+    }
+
+    pub fn get_target(&self) -> u32 {
+        self.state.borrow().target_pages
+    }
+
+    /// Size of the balloon reported by the driver.
+    pub fn get_actual(&self) -> u32 {
+        self.state.borrow().actual_pages
+    }
+
+    /// Pages the guest put into the balloon.
+    pub fn get_pages(&self) -> Vec<u32> {
+        self.state.borrow().pages.iter().cloned().collect()
+    }
+
+    /// Ask the guest for updated statistics.
+    pub fn request_stats(&self) {
+        self.state.borrow_mut().stats_requested = true;
+    }
+
+    /// The latest statistics reported by the guest.
+    pub fn get_stats(&self) -> Option<BalloonStats> {
+        self.state.borrow().stats.clone()
+    }
+}
+
+pub struct VirtioBalloon {
+    state: Rc<RefCell<BalloonState>>,
+    /// the statistics buffer, kept until new statistics are wanted.
+    stats_head: Option<u16>,
+}
+
+impl VirtioBalloon {
+    pub fn new() -> Self {
+        VirtioBalloon {
+            state: Rc::new(RefCell::new(BalloonState {
+                target_pages: 0,
+                actual_pages: 0,
+                pages: BTreeSet::new(),
+                stats: None,
+                stats_requested: false,
+                config_changed: false,
+            })),
+            stats_head: None,
+        }
+    }
+
+    pub fn get_handle(&self) -> BalloonHandle {
+        BalloonHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    fn get_pfns(chain: &DescriptorChain, mem: &GuestMemory) -> Vec<u32> {
+        chain
+            .read_all(mem)
+            .chunks_exact(4)
+            .map(|pfn| u32::from_le_bytes([pfn[0], pfn[1], pfn[2], pfn[3]]))
+            .collect()
+    }
+
+    fn update_stats(&mut self, data: &[u8]) {
+        /* virtio_balloon_stat
+         * ----------------
+         * u16 tag
+         * u64 val
+         */
+        let mut stats = BalloonStats::default();
+        for stat in data.chunks_exact(10) {
+            let tag = u16::from_le_bytes([stat[0], stat[1]]);
+            let mut val = [0; 8];
+            val.copy_from_slice(&stat[2..10]);
+            let val = Some(u64::from_le_bytes(val));
+            match tag {
+                VIRTIO_BALLOON_S_SWAP_IN => stats.swap_in = val,
+                VIRTIO_BALLOON_S_SWAP_OUT => stats.swap_out = val,
+                VIRTIO_BALLOON_S_MAJFLT => stats.major_faults = val,
+                VIRTIO_BALLOON_S_MINFLT => stats.minor_faults = val,
+                VIRTIO_BALLOON_S_MEMFREE => stats.free_memory = val,
+                VIRTIO_BALLOON_S_MEMTOT => stats.total_memory = val,
+                VIRTIO_BALLOON_S_AVAIL => stats.available_memory = val,
+                VIRTIO_BALLOON_S_CACHES => stats.disk_caches = val,
+                _ => {}
+            }
+        }
+        self.state.borrow_mut().stats = Some(stats);
+    }
+}
+
+impl Default for VirtioBalloon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtioDevice for VirtioBalloon {
+    fn get_device_id(&self) -> u32 {
+        VIRTIO_ID_BALLOON
+    }
+
+    fn get_features(&self) -> u64 {
+        VIRTIO_BALLOON_F_STATS_VQ | VIRTIO_BALLOON_F_DEFLATE_ON_OOM
+    }
+
+    fn get_queue_count(&self) -> usize {
+        3
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        /* virtio_balloon_config
+         * ----------------
+         * u32 num_pages
+         * u32 actual
+         */
+        let state = self.state.borrow();
+        match offset {
+            0..=3 => (state.target_pages >> (offset * 8)) as u8,
+            4..=7 => (state.actual_pages >> ((offset - 4) * 8)) as u8,
+            _ => 0,
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: u8) {
+        if let 4..=7 = offset {
+            let mut state = self.state.borrow_mut();
+            let shift = (offset - 4) * 8;
+            state.actual_pages = (state.actual_pages & !(0xff << shift)) | ((data as u32) << shift);
+        }
+    }
+
+    fn process_queue(&mut self, index: usize, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        let queue = &mut queues[index];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem) {
+            match index {
+                INFLATE_QUEUE => {
+                    let mut state = self.state.borrow_mut();
+                    state.pages.extend(Self::get_pfns(&chain, mem));
+                }
+                DEFLATE_QUEUE => {
+                    let mut state = self.state.borrow_mut();
+                    for pfn in Self::get_pfns(&chain, mem) {
+                        state.pages.remove(&pfn);
+                    }
+                }
+                STATS_QUEUE => {
+                    self.update_stats(&chain.read_all(mem));
+                    // returned when the host wants the next statistics.
+                    if let Some(head) = self.stats_head.replace(chain.head) {
+                        queue.push(mem, head, 0);
+                        used = true;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            queue.push(mem, chain.head, 0);
+            used = true;
+        }
+        used
+    }
+
+    fn poll(&mut self, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        let requested = self.state.borrow().stats_requested;
+        if !requested {
+            return false;
+        }
+        match self.stats_head.take() {
+            Some(head) => {
+                self.state.borrow_mut().stats_requested = false;
+                queues[STATS_QUEUE].push(mem, head, 0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn take_config_changed(&mut self) -> bool {
+        let mut state = self.state.borrow_mut();
+        let changed = state.config_changed;
+        state.config_changed = false;
+        changed
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.actual_pages = 0;
+        state.pages.clear();
+        state.stats_requested = false;
+        self.stats_head = None;
+    }
+}
@@ -0,0 +1,201 @@
+// Virtio Block Device
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-2390002
+// https://github.com/mit-pdos/xv6-riscv/blob/riscv/kernel/virtio_disk.c
+
+use crate::block::BlockBackend;
+use crate::peripherals::virtio::queue::{GuestMemory, Queue};
+use crate::peripherals::virtio::{VirtioDevice, VIRTIO_ID_BLOCK};
+
+const CONFIG_DISK_SECTOR_SIZE: u64 = 512;
+/// segments of a request (the queue size is enough with indirect descriptors).
+const CONFIG_SEG_MAX: u32 = 126;
+const CONFIG_MAX_DISCARD_SECTORS: u32 = 0x3f_ffff;
+
+// Feature bits
+const VIRTIO_BLK_F_SEG_MAX: u64 = 1 << 2;
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+const VIRTIO_BLK_F_DISCARD: u64 = 1 << 13;
+
+// Request types
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+
+/// Length of the device id string.
+const VIRTIO_BLK_ID_BYTES: usize = 20;
+
+// Request status
+const OK: u8 = 0;
+const IOERR: u8 = 1;
+const UNSUPP: u8 = 2;
+
+pub struct VirtioBlock {
+    backend: Box<dyn BlockBackend>,
+    /// serial number returned by GET_ID.
+    id: String,
+}
+
+impl VirtioBlock {
+    pub fn new(backend: Box<dyn BlockBackend>) -> Self {
+        VirtioBlock {
+            backend,
+            id: "riscv-emu".to_string(),
+        }
+    }
+
+    /// Set the serial number (up to 20 bytes, e.g. /dev/disk/by-id on Linux).
+    pub fn set_id(&mut self, id: &str) {
+        self.id = id.to_string();
+    }
+
+    pub fn get_backend(&mut self) -> &mut Box<dyn BlockBackend> {
+        &mut self.backend
+    }
+
+    /// Capacity in 512-byte sectors.
+    fn get_capacity(&self) -> u64 {
+        self.backend.get_size() / CONFIG_DISK_SECTOR_SIZE
+    }
+
+    fn transfer(&mut self, request: &[u8], chain_data: &mut Vec<u8>, writable_len: usize) -> u8 {
+        /* virtio_blk_req header
+         * ----------------
+         * u32 type
+         * u32 reserved
+         * u64 sector
+         */
+        if request.len() < 16 {
+            return IOERR;
+        }
+        let request_type = u32::from_le_bytes([request[0], request[1], request[2], request[3]]);
+        let mut sector = [0; 8];
+        sector.copy_from_slice(&request[8..16]);
+        let disk_addr = u64::from_le_bytes(sector).wrapping_mul(CONFIG_DISK_SECTOR_SIZE);
+        let data = &request[16..];
+        // the last writable byte is the status.
+        let len = writable_len.saturating_sub(1);
+
+        let result = match request_type {
+            VIRTIO_BLK_T_IN => {
+                chain_data.resize(len, 0);
+                self.backend.read_at(disk_addr, chain_data)
+            }
+            VIRTIO_BLK_T_OUT if self.backend.is_read_only() => return IOERR,
+            VIRTIO_BLK_T_OUT => self.backend.write_at(disk_addr, data),
+            VIRTIO_BLK_T_FLUSH => self.backend.flush(),
+            VIRTIO_BLK_T_GET_ID => {
+                // NUL terminated unless it is 20 bytes long.
+                let mut id = self.id.as_bytes().to_vec();
+                id.resize(VIRTIO_BLK_ID_BYTES, 0);
+                id.truncate(len);
+                chain_data.extend_from_slice(&id);
+                Ok(())
+            }
+            VIRTIO_BLK_T_DISCARD if self.backend.is_read_only() => return IOERR,
+            VIRTIO_BLK_T_DISCARD => {
+                /* virtio_blk_discard_write_zeroes
+                 * ----------------
+                 * u64 sector
+                 * u32 num_sectors
+                 * u32 flags
+                 */
+                data.chunks_exact(16).try_for_each(|segment| {
+                    let mut sector = [0; 8];
+                    sector.copy_from_slice(&segment[0..8]);
+                    let sector = u64::from_le_bytes(sector);
+                    let num_sectors =
+                        u32::from_le_bytes([segment[8], segment[9], segment[10], segment[11]]);
+                    self.backend.discard(
+                        sector.wrapping_mul(CONFIG_DISK_SECTOR_SIZE),
+                        num_sectors as u64 * CONFIG_DISK_SECTOR_SIZE,
+                    )
+                })
+            }
+            _ => return UNSUPP,
+        };
+        match result {
+            Ok(()) => OK,
+            Err(_) => IOERR,
+        }
+    }
+}
+
+impl VirtioDevice for VirtioBlock {
+    fn get_device_id(&self) -> u32 {
+        VIRTIO_ID_BLOCK
+    }
+
+    fn get_features(&self) -> u64 {
+        let features = VIRTIO_BLK_F_SEG_MAX
+            | VIRTIO_BLK_F_BLK_SIZE
+            | VIRTIO_BLK_F_FLUSH
+            | VIRTIO_BLK_F_DISCARD;
+        match self.backend.is_read_only() {
+            true => features | VIRTIO_BLK_F_RO,
+            false => features,
+        }
+    }
+
+    fn get_queue_count(&self) -> usize {
+        1
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        /* virtio_blk_config
+         * ----------------
+         * u64 capacity
+         * u32 size_max
+         * u32 seg_max
+         * u16 cylinders, u8 heads, u8 sectors
+         * u32 blk_size
+         * u8 physical_block_exp, u8 alignment_offset, u16 min_io_size, u32 opt_io_size
+         * u8 writeback
+         * u8 unused0
+         * u16 num_queues
+         * u32 max_discard_sectors
+         * u32 max_discard_seg
+         * u32 discard_sector_alignment
+         */
+        let mut config = self.get_capacity().to_le_bytes().to_vec();
+        config.extend_from_slice(&0u32.to_le_bytes());
+        config.extend_from_slice(&CONFIG_SEG_MAX.to_le_bytes());
+        config.extend_from_slice(&[0; 4]);
+        config.extend_from_slice(&(CONFIG_DISK_SECTOR_SIZE as u32).to_le_bytes());
+        config.extend_from_slice(&[0; 8]);
+        config.extend_from_slice(&[0, 0]);
+        config.extend_from_slice(&1u16.to_le_bytes());
+        config.extend_from_slice(&CONFIG_MAX_DISCARD_SECTORS.to_le_bytes());
+        config.extend_from_slice(&1u32.to_le_bytes());
+        config.extend_from_slice(&1u32.to_le_bytes());
+        config.get(offset as usize).cloned().unwrap_or(0)
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: u8) {}
+
+    fn process_queue(&mut self, index: usize, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        let queue = &mut queues[index];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem) {
+            // the readable part is the header followed by the data to write.
+            let request = chain.read_all(mem);
+            let writable_len = chain.get_writable_len();
+            let mut data = vec![];
+            let status = self.transfer(&request, &mut data, writable_len);
+            // on errors only the status is returned.
+            if status != OK {
+                data.clear();
+            }
+            data.push(status);
+            // the status is placed in the last writable byte.
+            let offset = writable_len.saturating_sub(data.len());
+            let written = chain.write_at(mem, offset, &data);
+            queue.push(mem, chain.head, written as u32);
+            used = true;
+        }
+        used
+    }
+}
@@ -0,0 +1,279 @@
+// Virtio Console Device
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-2900003
+
+use crate::console::Console;
+use crate::peripherals::virtio::queue::{GuestMemory, Queue};
+use crate::peripherals::virtio::{VirtioDevice, VIRTIO_ID_CONSOLE};
+
+use std::collections::VecDeque;
+
+// Feature bits
+const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1 << 1;
+const VIRTIO_CONSOLE_F_EMERG_WRITE: u64 = 1 << 2;
+
+// Control events
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_DEVICE_ADD: u16 = 1;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_CONSOLE_PORT: u16 = 4;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+
+const CONTROL_RX_QUEUE: usize = 2;
+const CONTROL_TX_QUEUE: usize = 3;
+
+/// Configuration space offsets.
+const CONFIG_MAX_NR_PORTS: u64 = 4;
+const CONFIG_EMERG_WR: u64 = 8;
+
+struct Port {
+    name: String,
+    console: Box<dyn Console>,
+    /// the guest opened the port.
+    guest_connected: bool,
+}
+
+pub struct VirtioConsole {
+    ports: Vec<Port>,
+    driver_features: u64,
+    /// control messages (struct virtio_console_control and data) to the driver.
+    control: VecDeque<Vec<u8>>,
+}
+
+impl VirtioConsole {
+    /// Port 0 is the console port.
+    pub fn new(console: Box<dyn Console>) -> Self {
+        VirtioConsole {
+            ports: vec![Port {
+                name: String::new(),
+                console,
+                guest_connected: false,
+            }],
+            driver_features: 0,
+            control: VecDeque::new(),
+        }
+    }
+
+    /// Add a named port (e.g. /dev/virtio-ports/NAME on Linux). Ports need
+    /// to be added before the device is attached.
+    pub fn add_port(&mut self, name: &str, console: Box<dyn Console>) {
+        self.ports.push(Port {
+            name: name.to_string(),
+            console,
+            guest_connected: false,
+        });
+    }
+
+    pub fn is_guest_connected(&self, port: usize) -> bool {
+        match self.ports.get(port) {
+            Some(port) => port.guest_connected,
+            None => false,
+        }
+    }
+
+    fn is_multiport(&self) -> bool {
+        self.driver_features & VIRTIO_CONSOLE_F_MULTIPORT != 0
+    }
+
+    /// receiveq of the port, transmitq is the next queue.
+    fn get_port_queue(port: usize) -> usize {
+        match port {
+            0 => 0,
+            _ => 2 * (port + 1),
+        }
+    }
+
+    /// port and direction (true for transmitq) of the queue.
+    fn get_queue_port(index: usize) -> Option<(usize, bool)> {
+        match index {
+            0 | 1 => Some((0, index == 1)),
+            CONTROL_RX_QUEUE | CONTROL_TX_QUEUE => None,
+            _ => Some((index / 2 - 1, index % 2 == 1)),
+        }
+    }
+
+    fn send_control(&mut self, id: usize, event: u16, value: u16, data: &[u8]) {
+        /* virtio_console_control
+         * ----------------
+         * u32 id
+         * u16 event
+         * u16 value
+         */
+        let mut message = (id as u32).to_le_bytes().to_vec();
+        message.extend_from_slice(&event.to_le_bytes());
+        message.extend_from_slice(&value.to_le_bytes());
+        message.extend_from_slice(data);
+        self.control.push_back(message);
+    }
+
+    fn handle_control(&mut self, message: &[u8]) {
+        if message.len() < 8 {
+            return;
+        }
+        let id = u32::from_le_bytes([message[0], message[1], message[2], message[3]]) as usize;
+        let event = u16::from_le_bytes([message[4], message[5]]);
+        let value = u16::from_le_bytes([message[6], message[7]]);
+        match event {
+            VIRTIO_CONSOLE_DEVICE_READY if value == 1 => {
+                for port in 0..self.ports.len() {
+                    self.send_control(port, VIRTIO_CONSOLE_DEVICE_ADD, 0, &[]);
+                }
+            }
+            VIRTIO_CONSOLE_PORT_READY if value == 1 && id < self.ports.len() => {
+                if id == 0 {
+                    self.send_control(id, VIRTIO_CONSOLE_CONSOLE_PORT, 1, &[]);
+                }
+                if !self.ports[id].name.is_empty() {
+                    let name = self.ports[id].name.clone().into_bytes();
+                    self.send_control(id, VIRTIO_CONSOLE_PORT_NAME, 1, &name);
+                }
+                // the host side is always connected.
+                self.send_control(id, VIRTIO_CONSOLE_PORT_OPEN, 1, &[]);
+            }
+            VIRTIO_CONSOLE_PORT_OPEN if id < self.ports.len() => {
+                self.ports[id].guest_connected = value == 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn transmit(&mut self, port: usize, queue: &mut Queue, mem: &mut GuestMemory) -> bool {
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem) {
+            let console = &mut self.ports[port].console;
+            for c in chain.read_all(mem) {
+                console.putchar(c);
+            }
+            queue.push(mem, chain.head, 0);
+            used = true;
+        }
+        used
+    }
+
+    fn receive(&mut self, port: usize, queue: &mut Queue, mem: &mut GuestMemory) -> bool {
+        // fetch input only if there is a buffer to put it in.
+        if !queue.has_available(mem) {
+            return false;
+        }
+        let console = &mut self.ports[port].console;
+        let c = console.getchar();
+        if c == 0 {
+            return false;
+        }
+        let chain = match queue.pop(mem) {
+            Some(chain) => chain,
+            None => return false,
+        };
+        let mut data = vec![c];
+        while data.len() < chain.get_writable_len() {
+            match console.getchar() {
+                0 => break,
+                c => data.push(c),
+            }
+        }
+        let written = chain.write_at(mem, 0, &data);
+        queue.push(mem, chain.head, written as u32);
+        true
+    }
+
+    fn process_control(&mut self, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        let mut used = false;
+        while let Some(chain) = queues[CONTROL_TX_QUEUE].pop(mem) {
+            let message = chain.read_all(mem);
+            self.handle_control(&message);
+            queues[CONTROL_TX_QUEUE].push(mem, chain.head, 0);
+            used = true;
+        }
+        while !self.control.is_empty() {
+            let chain = match queues[CONTROL_RX_QUEUE].pop(mem) {
+                Some(chain) => chain,
+                None => break,
+            };
+            let message = self.control.pop_front().unwrap_or_default();
+            let written = chain.write_at(mem, 0, &message);
+            queues[CONTROL_RX_QUEUE].push(mem, chain.head, written as u32);
+            used = true;
+        }
+        used
+    }
+}
+
+impl VirtioDevice for VirtioConsole {
+    fn get_device_id(&self) -> u32 {
+        VIRTIO_ID_CONSOLE
+    }
+
+    fn get_features(&self) -> u64 {
+        match self.ports.len() {
+            1 => VIRTIO_CONSOLE_F_EMERG_WRITE,
+            _ => VIRTIO_CONSOLE_F_EMERG_WRITE | VIRTIO_CONSOLE_F_MULTIPORT,
+        }
+    }
+
+    fn set_driver_features(&mut self, features: u64) {
+        self.driver_features = features;
+    }
+
+    fn get_queue_count(&self) -> usize {
+        match self.ports.len() {
+            1 => 2,
+            n => 2 * (n + 1),
+        }
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        /* virtio_console_config
+         * ----------------
+         * u16 cols
+         * u16 rows
+         * u32 max_nr_ports
+         * u32 emerg_wr
+         */
+        match offset {
+            CONFIG_MAX_NR_PORTS..=7 => {
+                let max_nr_ports = self.ports.len() as u32;
+                (max_nr_ports >> ((offset - CONFIG_MAX_NR_PORTS) * 8)) as u8
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: u8) {
+        // emergency write: output to the console port before the queues work.
+        if offset == CONFIG_EMERG_WR {
+            self.ports[0].console.putchar(data);
+        }
+    }
+
+    fn process_queue(&mut self, index: usize, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        match Self::get_queue_port(index) {
+            Some((port, true)) => self.transmit(port, &mut queues[index], mem),
+            Some((port, false)) => self.receive(port, &mut queues[index], mem),
+            None => self.process_control(queues, mem),
+        }
+    }
+
+    fn poll(&mut self, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        let ports = match self.is_multiport() {
+            true => self.ports.len(),
+            false => 1,
+        };
+        let mut used = false;
+        for port in 0..ports {
+            let index = Self::get_port_queue(port);
+            used |= self.receive(port, &mut queues[index], mem);
+        }
+        if self.is_multiport() {
+            used |= self.process_control(queues, mem);
+        }
+        used
+    }
+
+    fn reset(&mut self) {
+        self.driver_features = 0;
+        self.control.clear();
+        for port in self.ports.iter_mut() {
+            port.guest_connected = false;
+        }
+    }
+}
@@ -0,0 +1,286 @@
+// Virtio Input Device (keyboard)
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-3390008
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/input-event-codes.h
+
+use crate::console::Console;
+use crate::peripherals::virtio::queue::{GuestMemory, Queue};
+use crate::peripherals::virtio::{VirtioDevice, VIRTIO_ID_INPUT};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+// Configuration selectors
+const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
+const VIRTIO_INPUT_CFG_ID_DEVIDS: u8 = 0x03;
+const VIRTIO_INPUT_CFG_EV_BITS: u8 = 0x11;
+
+// Event types
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+
+const SYN_REPORT: u16 = 0;
+const BUS_VIRTUAL: u16 = 0x06;
+
+pub const KEY_ESC: u16 = 1;
+pub const KEY_BACKSPACE: u16 = 14;
+pub const KEY_TAB: u16 = 15;
+pub const KEY_ENTER: u16 = 28;
+pub const KEY_LEFTCTRL: u16 = 29;
+pub const KEY_LEFTSHIFT: u16 = 42;
+pub const KEY_SPACE: u16 = 57;
+/// keys up to this code are supported.
+const KEY_MAX_SUPPORTED: u16 = 127;
+
+const EVENT_QUEUE: usize = 0;
+const STATUS_QUEUE: usize = 1;
+
+const DEVICE_NAME: &str = "riscv-emu virtio keyboard";
+
+/// struct virtio_input_event
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub event_type: u16,
+    pub code: u16,
+    pub value: u32,
+}
+
+impl InputEvent {
+    pub fn new(event_type: u16, code: u16, value: u32) -> Self {
+        InputEvent {
+            event_type,
+            code,
+            value,
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut data = self.event_type.to_le_bytes().to_vec();
+        data.extend_from_slice(&self.code.to_le_bytes());
+        data.extend_from_slice(&self.value.to_le_bytes());
+        data
+    }
+}
+
+/// Host side of a keyboard attached to the machine.
+#[derive(Clone)]
+pub struct KeyboardHandle {
+    events: Rc<RefCell<VecDeque<InputEvent>>>,
+}
+
+impl KeyboardHandle {
+    /// Press (or release) the key of the Linux key code.
+    pub fn send_key(&self, code: u16, pressed: bool) {
+        let mut events = self.events.borrow_mut();
+        events.push_back(InputEvent::new(EV_KEY, code, pressed as u32));
+        events.push_back(InputEvent::new(EV_SYN, SYN_REPORT, 0));
+    }
+
+    /// Type the characters on a US keyboard layout. Characters without a
+    /// key are ignored.
+    pub fn type_text(&self, text: &str) {
+        for c in text.bytes() {
+            self.type_char(c);
+        }
+    }
+
+    fn type_char(&self, c: u8) {
+        let (code, modifier) = match get_key_code(c) {
+            Some(key) => key,
+            None => return,
+        };
+        if let Some(modifier) = modifier {
+            self.send_key(modifier, true);
+        }
+        self.send_key(code, true);
+        self.send_key(code, false);
+        if let Some(modifier) = modifier {
+            self.send_key(modifier, false);
+        }
+    }
+}
+
+pub struct VirtioInput {
+    events: Rc<RefCell<VecDeque<InputEvent>>>,
+    /// characters typed on the console are turned into key strokes.
+    console: Option<Box<dyn Console>>,
+    select: u8,
+    subsel: u8,
+}
+
+impl VirtioInput {
+    pub fn new_keyboard(console: Option<Box<dyn Console>>) -> Self {
+        VirtioInput {
+            events: Rc::new(RefCell::new(VecDeque::new())),
+            console,
+            select: 0,
+            subsel: 0,
+        }
+    }
+
+    pub fn get_handle(&self) -> KeyboardHandle {
+        KeyboardHandle {
+            events: self.events.clone(),
+        }
+    }
+
+    /// Data of the configuration selected by select and subsel.
+    fn get_config_data(&self) -> Vec<u8> {
+        match (self.select, self.subsel) {
+            (VIRTIO_INPUT_CFG_ID_NAME, 0) => DEVICE_NAME.as_bytes().to_vec(),
+            (VIRTIO_INPUT_CFG_ID_DEVIDS, 0) => {
+                // bustype, vendor, product, version
+                let mut ids = BUS_VIRTUAL.to_le_bytes().to_vec();
+                ids.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x01, 0x00]);
+                ids
+            }
+            // subsel is the event type.
+            (VIRTIO_INPUT_CFG_EV_BITS, subsel) if subsel as u16 == EV_KEY => {
+                // bitmap of the supported key codes (KEY_RESERVED excluded).
+                let mut bitmap = vec![0xff; (KEY_MAX_SUPPORTED as usize + 1) / 8];
+                bitmap[0] &= !1;
+                bitmap
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl VirtioDevice for VirtioInput {
+    fn get_device_id(&self) -> u32 {
+        VIRTIO_ID_INPUT
+    }
+
+    fn get_features(&self) -> u64 {
+        0
+    }
+
+    fn get_queue_count(&self) -> usize {
+        2
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        /* virtio_input_config
+         * ----------------
+         * u8 select
+         * u8 subsel
+         * u8 size
+         * u8[5] reserved
+         * u8[128] union
+         */
+        let data = self.get_config_data();
+        match offset {
+            0 => self.select,
+            1 => self.subsel,
+            2 => data.len() as u8,
+            8..=135 => data.get(offset as usize - 8).cloned().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: u8) {
+        match offset {
+            0 => self.select = data,
+            1 => self.subsel = data,
+            _ => {}
+        }
+    }
+
+    fn process_queue(&mut self, index: usize, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        match index {
+            EVENT_QUEUE => self.poll(queues, mem),
+            STATUS_QUEUE => {
+                // LED states and the like are accepted and ignored.
+                let queue = &mut queues[STATUS_QUEUE];
+                let mut used = false;
+                while let Some(chain) = queue.pop(mem) {
+                    queue.push(mem, chain.head, 0);
+                    used = true;
+                }
+                used
+            }
+            _ => false,
+        }
+    }
+
+    fn poll(&mut self, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        if let Some(console) = &mut self.console {
+            let handle = KeyboardHandle {
+                events: self.events.clone(),
+            };
+            loop {
+                match console.getchar() {
+                    0 => break,
+                    c => handle.type_char(c),
+                }
+            }
+        }
+
+        let queue = &mut queues[EVENT_QUEUE];
+        let mut events = self.events.borrow_mut();
+        let mut used = false;
+        while !events.is_empty() {
+            let chain = match queue.pop(mem) {
+                Some(chain) => chain,
+                None => break,
+            };
+            let event = events
+                .pop_front()
+                .unwrap_or(InputEvent::new(EV_SYN, SYN_REPORT, 0));
+            let written = chain.write_at(mem, 0, &event.to_bytes());
+            queue.push(mem, chain.head, written as u32);
+            used = true;
+        }
+        used
+    }
+
+    fn reset(&mut self) {
+        self.events.borrow_mut().clear();
+    }
+}
+
+/// Linux key code (and the modifier key) of an ASCII character.
+fn get_key_code(c: u8) -> Option<(u16, Option<u16>)> {
+    const LETTERS: &[u8; 26] = &[
+        30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17,
+        45, 21, 44,
+    ];
+    const DIGITS: &[u8; 10] = &[11, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    const SHIFTED_DIGITS: &[u8; 10] = b")!@#$%^&*(";
+    const SYMBOLS: &[(u8, u8, u16)] = &[
+        (b'-', b'_', 12),
+        (b'=', b'+', 13),
+        (b'[', b'{', 26),
+        (b']', b'}', 27),
+        (b';', b':', 39),
+        (b'\'', b'"', 40),
+        (b'`', b'~', 41),
+        (b'\\', b'|', 43),
+        (b',', b'<', 51),
+        (b'.', b'>', 52),
+        (b'/', b'?', 53),
+    ];
+    let shift = Some(KEY_LEFTSHIFT);
+    match c {
+        b'a'..=b'z' => Some((LETTERS[(c - b'a') as usize] as u16, None)),
+        b'A'..=b'Z' => Some((LETTERS[(c - b'A') as usize] as u16, shift)),
+        b'0'..=b'9' => Some((DIGITS[(c - b'0') as usize] as u16, None)),
+        b' ' => Some((KEY_SPACE, None)),
+        b'\n' | b'\r' => Some((KEY_ENTER, None)),
+        b'\t' => Some((KEY_TAB, None)),
+        0x08 | 0x7f => Some((KEY_BACKSPACE, None)),
+        0x1b => Some((KEY_ESC, None)),
+        // control characters are typed with the control key.
+        0x01..=0x1a => Some((LETTERS[(c - 1) as usize] as u16, Some(KEY_LEFTCTRL))),
+        _ => {
+            if let Some(i) = SHIFTED_DIGITS.iter().position(|&s| s == c) {
+                return Some((DIGITS[i] as u16, shift));
+            }
+            SYMBOLS.iter().find_map(|&(plain, shifted, code)| match c {
+                _ if c == plain => Some((code, None)),
+                _ if c == shifted => Some((code, shift)),
+                _ => None,
+            })
+        }
+    }
+}
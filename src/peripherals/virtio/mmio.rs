@@ -0,0 +1,405 @@
+// Virtio Over MMIO (legacy and modern interfaces)
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-1440002
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-1560004
+// https://github.com/mit-pdos/xv6-riscv/blob/riscv/kernel/virtio_disk.c
+// https://syuu1228.github.io/howto_implement_hypervisor/part12.html
+// https://syuu1228.github.io/howto_implement_hypervisor/part20.html
+
+use crate::peripherals::memory::Memory;
+use crate::peripherals::virtio::queue::{GuestMemory, Queue};
+use crate::peripherals::virtio::{
+    VirtioDevice, VIRTIO_F_EVENT_IDX, VIRTIO_F_INDIRECT_DESC, VIRTIO_F_VERSION_1,
+};
+
+const CONFIG_QUEUE_NUM_MAX: u32 = 0x1000; // Linux boot fails if the value is too small.
+const CONFIG_DMA_DELAY: u64 = 128;
+/// interval in cycles to poll devices for host side activity.
+const CONFIG_POLL_INTERVAL: u64 = 0x400;
+
+const VIRTIO_MAGIC_VALUE: u64 = 0x000;
+const VIRTIO_VERSION: u64 = 0x004;
+const VIRTIO_DEVICE_ID: u64 = 0x008;
+const VIRTIO_VENDOR_ID: u64 = 0x00c;
+const VIRTIO_DEVICE_FEATURES: u64 = 0x010;
+const VIRTIO_DEVICE_FEATURES_SEL: u64 = 0x014;
+const VIRTIO_DRIVER_FEATURES: u64 = 0x020;
+const VIRTIO_DRIVER_FEATURES_SEL: u64 = 0x024;
+const VIRTIO_GUEST_PAGE_SIZE: u64 = 0x028;
+const VIRTIO_QUEUE_SEL: u64 = 0x030;
+const VIRTIO_QUEUE_NUM_MAX: u64 = 0x034;
+const VIRTIO_QUEUE_NUM: u64 = 0x038;
+const VIRTIO_QUEUE_ALIGIN: u64 = 0x03c;
+const VIRTIO_QUEUE_PFN: u64 = 0x040;
+const VIRTIO_QUEUE_READY: u64 = 0x044;
+const VIRTIO_QUEUE_NOTIFY: u64 = 0x050;
+const VIRTIO_INTERRUPT_STATUS: u64 = 0x060;
+const VIRTIO_INTERRUPT_ACK: u64 = 0x64;
+const VIRTIO_DEVICE_STATUS: u64 = 0x070;
+const VIRTIO_QUEUE_DESC_LOW: u64 = 0x080;
+const VIRTIO_QUEUE_DESC_HIGH: u64 = 0x084;
+const VIRTIO_QUEUE_DRIVER_LOW: u64 = 0x090;
+const VIRTIO_QUEUE_DRIVER_HIGH: u64 = 0x094;
+const VIRTIO_QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const VIRTIO_QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+const VIRTIO_CONFIG_GENERATION: u64 = 0x0fc;
+const VIRTIO_CONFIG_SPACE: u64 = 0x100;
+
+/// Version of the legacy interface.
+pub const VIRTIO_MMIO_LEGACY: u32 = 1;
+/// Version of the modern (virtio 1.0) interface.
+pub const VIRTIO_MMIO_MODERN: u32 = 2;
+
+const VIRTIO_STATUS_FEATURES_OK: u32 = 0x8;
+
+const VIRTIO_INTERRUPT_QUEUE: u32 = 0x1;
+const VIRTIO_INTERRUPT_CONFIGURATION: u32 = 0x2;
+
+/// Per queue registers of the legacy interface.
+struct LegacyQueue {
+    /// Used Ring alignment in the virtual queue (WO)
+    align: u32,
+    /// Guest physical page number of the virtual queue (R/W)
+    pfn: u32,
+}
+
+pub struct VirtioMmio {
+    /// current clock cycle.
+    cycle: u64,
+    /// Main Memory Base Address
+    dram_base_addr: u64,
+    /// interface version: VIRTIO_MMIO_LEGACY or VIRTIO_MMIO_MODERN.
+    version: u32,
+    /// attached device (None for an empty slot).
+    device: Option<Box<dyn VirtioDevice>>,
+    queues: Vec<Queue>,
+    legacy_queues: Vec<LegacyQueue>,
+
+    /// Device (host) features word selection (WO)
+    device_features_sel: u32,
+    /// Flags representing device features understood and activated by the driver (WO)
+    driver_features: u64,
+    /// Activated (guest) features word selection (WO)
+    driver_features_sel: u32,
+    /// Guest page size (WO)
+    guest_page_size: u32,
+    /// Virtual queue index (WO)
+    queue_sel: u32,
+    /// Queue notifications (cycle, queue index) waiting for the DMA delay.
+    queue_notify: Vec<(u64, usize)>,
+    /// Interrupt status (RO)
+    interrupt_status: u32,
+    /// Device status (R/W)
+    device_status: u32,
+    /// Incremented when the configuration space changes (RO, modern only)
+    config_generation: u32,
+}
+
+impl VirtioMmio {
+    pub fn new(dram_base_addr_: u64) -> Self {
+        VirtioMmio {
+            cycle: 0,
+            dram_base_addr: dram_base_addr_,
+            version: VIRTIO_MMIO_LEGACY,
+            device: None,
+            queues: vec![],
+            legacy_queues: vec![],
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            guest_page_size: 0,
+            queue_sel: 0,
+            queue_notify: Vec::new(),
+            interrupt_status: 0,
+            device_status: 0,
+            config_generation: 0,
+        }
+    }
+
+    /// Select the legacy or the modern interface. Drivers see the change
+    /// from the next time they probe the device.
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+        self.reset();
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    fn is_modern(&self) -> bool {
+        self.version == VIRTIO_MMIO_MODERN
+    }
+
+    /// Features of the device and of the transport.
+    fn get_features(&self) -> u64 {
+        let features = match &self.device {
+            Some(device) => device.get_features() | VIRTIO_F_INDIRECT_DESC | VIRTIO_F_EVENT_IDX,
+            None => return 0,
+        };
+        match self.is_modern() {
+            true => features | VIRTIO_F_VERSION_1,
+            false => features,
+        }
+    }
+
+    /// Whether any queue has used buffers the driver wants to be notified of.
+    fn needs_notification(queues: &mut [Queue], mem: &GuestMemory) -> bool {
+        // every queue is checked to update its notification state.
+        let mut notify = false;
+        for queue in queues.iter_mut() {
+            notify |= queue.needs_notification(mem);
+        }
+        notify
+    }
+
+    /// Plug a device into the slot, replacing the previous one.
+    pub fn set_device(&mut self, device: Box<dyn VirtioDevice>) {
+        let queue_count = device.get_queue_count();
+        self.device = Some(device);
+        self.queues = (0..queue_count).map(|_| Queue::new()).collect();
+        self.legacy_queues = (0..queue_count)
+            .map(|_| LegacyQueue {
+                align: 0x1000, // TODO: check the reson.
+                pfn: 0,
+            })
+            .collect();
+        self.reset();
+    }
+
+    pub fn get_device(&mut self) -> Option<&mut Box<dyn VirtioDevice>> {
+        self.device.as_mut()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.device.is_none()
+    }
+
+    /// Reset the transport and the device, like writing 0 to the status.
+    pub fn reset(&mut self) {
+        self.device_features_sel = 0;
+        self.driver_features = 0;
+        self.driver_features_sel = 0;
+        self.queue_sel = 0;
+        self.queue_notify.clear();
+        self.interrupt_status = 0;
+        self.device_status = 0;
+        for queue in self.queues.iter_mut() {
+            queue.reset();
+        }
+        for queue in self.legacy_queues.iter_mut() {
+            queue.pfn = 0;
+        }
+        if let Some(device) = &mut self.device {
+            device.reset();
+        }
+    }
+
+    pub fn tick(&mut self, dram: &mut Memory) {
+        self.cycle = self.cycle.wrapping_add(1);
+        let device = match &mut self.device {
+            Some(device) => device,
+            None => return,
+        };
+        let mut mem = GuestMemory::new(dram, self.dram_base_addr);
+
+        // If an interrupt is generated immediately, it will not operate normally,
+        // so it is necessary to set a delay time.
+        while let Some(&(cycle, index)) = self.queue_notify.first() {
+            if self.cycle < cycle + CONFIG_DMA_DELAY {
+                break;
+            }
+            self.queue_notify.remove(0);
+            if device.process_queue(index, &mut self.queues, &mut mem)
+                && Self::needs_notification(&mut self.queues, &mem)
+            {
+                self.interrupt_status |= VIRTIO_INTERRUPT_QUEUE;
+            }
+        }
+
+        if self.cycle.is_multiple_of(CONFIG_POLL_INTERVAL) && self.queues.iter().any(|q| q.ready) {
+            if device.poll(&mut self.queues, &mut mem)
+                && Self::needs_notification(&mut self.queues, &mem)
+            {
+                self.interrupt_status |= VIRTIO_INTERRUPT_QUEUE;
+            }
+            if device.take_config_changed() {
+                self.config_generation = self.config_generation.wrapping_add(1);
+                self.interrupt_status |= VIRTIO_INTERRUPT_CONFIGURATION;
+            }
+        }
+    }
+
+    pub fn is_irq(&mut self) -> bool {
+        self.interrupt_status & 0x3 > 0
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        let device = match &self.device {
+            Some(device) => device,
+            // empty slots only identify themselves.
+            None => {
+                return match addr {
+                    VIRTIO_MAGIC_VALUE => 0x74726976,
+                    VIRTIO_VERSION => self.version,
+                    VIRTIO_VENDOR_ID => 0x554d4551,
+                    _ => 0,
+                }
+            }
+        };
+        let queue_sel = self.queue_sel as usize;
+        match addr {
+            VIRTIO_MAGIC_VALUE => 0x74726976, // "virt" string
+            VIRTIO_VERSION => self.version,
+            VIRTIO_DEVICE_ID => device.get_device_id(),
+            VIRTIO_VENDOR_ID => 0x554d4551, // from xv6-riscv source code.
+            VIRTIO_DEVICE_FEATURES => match self.device_features_sel {
+                0 => self.get_features() as u32,
+                1 => (self.get_features() >> 32) as u32,
+                _ => 0,
+            },
+            VIRTIO_QUEUE_NUM_MAX => match queue_sel < self.queues.len() {
+                true => CONFIG_QUEUE_NUM_MAX,
+                false => 0,
+            },
+            VIRTIO_QUEUE_PFN => match self.legacy_queues.get(queue_sel) {
+                Some(queue) if !self.is_modern() => queue.pfn,
+                _ => 0,
+            },
+            VIRTIO_QUEUE_READY => match self.queues.get(queue_sel) {
+                Some(queue) if self.is_modern() => queue.ready as u32,
+                _ => 0,
+            },
+            VIRTIO_INTERRUPT_STATUS => self.interrupt_status,
+            VIRTIO_DEVICE_STATUS => self.device_status,
+            VIRTIO_CONFIG_GENERATION => self.config_generation,
+            // write only registers of the modern interface.
+            VIRTIO_QUEUE_DESC_LOW..=VIRTIO_QUEUE_DEVICE_HIGH => 0,
+            // Device-specific configuration space starts at the offset 0x100 and is accessed with byte alignment.
+            // Its meaning and size depend on the device and the driver.
+            _ if addr >= VIRTIO_CONFIG_SPACE => {
+                let offset = addr - VIRTIO_CONFIG_SPACE;
+                (0..4).fold(0, |data, i| {
+                    data | (device.read_config(offset + i) as u32) << (i * 8)
+                })
+            }
+            _ => panic!("Read to reserved area: {:x}", addr),
+        }
+    }
+
+    /// Legacy drivers access the configuration space byte by byte.
+    pub fn write8(&mut self, addr: u64, data: u8) {
+        if addr < VIRTIO_CONFIG_SPACE {
+            panic!("Unexpected size access.");
+        }
+        if let Some(device) = &mut self.device {
+            device.write_config(addr - VIRTIO_CONFIG_SPACE, data);
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: u32) {
+        if self.device.is_none() {
+            return;
+        }
+        let queue_sel = self.queue_sel as usize;
+        match addr {
+            VIRTIO_DEVICE_FEATURES_SEL => self.device_features_sel = data,
+            VIRTIO_DRIVER_FEATURES => {
+                let shift = match self.driver_features_sel {
+                    0 => 0,
+                    1 => 32,
+                    _ => return,
+                };
+                self.driver_features =
+                    (self.driver_features & !(0xffffffff << shift)) | ((data as u64) << shift);
+                let features = self.driver_features & self.get_features();
+                for queue in self.queues.iter_mut() {
+                    queue.event_idx = features & VIRTIO_F_EVENT_IDX != 0;
+                }
+                if let Some(device) = &mut self.device {
+                    device.set_driver_features(features);
+                }
+            }
+            VIRTIO_DRIVER_FEATURES_SEL => self.driver_features_sel = data,
+            // the legacy queue layout is not used by the modern interface.
+            VIRTIO_GUEST_PAGE_SIZE | VIRTIO_QUEUE_ALIGIN | VIRTIO_QUEUE_PFN if self.is_modern() => {
+            }
+            VIRTIO_GUEST_PAGE_SIZE => self.guest_page_size = data,
+            VIRTIO_QUEUE_SEL => self.queue_sel = data,
+            VIRTIO_QUEUE_NUM => {
+                if let Some(queue) = self.queues.get_mut(queue_sel) {
+                    queue.num = data;
+                }
+            }
+            VIRTIO_QUEUE_ALIGIN => {
+                if let Some(queue) = self.legacy_queues.get_mut(queue_sel) {
+                    queue.align = data;
+                }
+            }
+            VIRTIO_QUEUE_PFN => {
+                if let (Some(queue), Some(legacy)) = (
+                    self.queues.get_mut(queue_sel),
+                    self.legacy_queues.get_mut(queue_sel),
+                ) {
+                    legacy.pfn = data;
+                    queue.set_legacy_layout(data, self.guest_page_size, legacy.align);
+                }
+            }
+            VIRTIO_QUEUE_READY if self.is_modern() => {
+                if let Some(queue) = self.queues.get_mut(queue_sel) {
+                    queue.ready = data & 0x1 != 0;
+                }
+            }
+            VIRTIO_QUEUE_DESC_LOW..=VIRTIO_QUEUE_DEVICE_HIGH if self.is_modern() => {
+                if let Some(queue) = self.queues.get_mut(queue_sel) {
+                    let (field, shift) = match addr {
+                        VIRTIO_QUEUE_DESC_LOW => (&mut queue.desc_addr, 0),
+                        VIRTIO_QUEUE_DESC_HIGH => (&mut queue.desc_addr, 32),
+                        VIRTIO_QUEUE_DRIVER_LOW => (&mut queue.avail_addr, 0),
+                        VIRTIO_QUEUE_DRIVER_HIGH => (&mut queue.avail_addr, 32),
+                        VIRTIO_QUEUE_DEVICE_LOW => (&mut queue.used_addr, 0),
+                        VIRTIO_QUEUE_DEVICE_HIGH => (&mut queue.used_addr, 32),
+                        _ => return,
+                    };
+                    *field = (*field & !(0xffffffff << shift)) | ((data as u64) << shift);
+                }
+            }
+            // the modern queue registers are not used by the legacy interface.
+            VIRTIO_QUEUE_READY | VIRTIO_QUEUE_DESC_LOW..=VIRTIO_QUEUE_DEVICE_HIGH => {}
+            VIRTIO_QUEUE_NOTIFY => {
+                if (data as usize) < self.queues.len() {
+                    self.queue_notify.push((self.cycle, data as usize));
+                }
+            }
+            VIRTIO_INTERRUPT_ACK => {
+                if data & VIRTIO_INTERRUPT_QUEUE > 0 {
+                    self.interrupt_status &= !VIRTIO_INTERRUPT_QUEUE;
+                }
+                if data & VIRTIO_INTERRUPT_CONFIGURATION > 0 {
+                    self.interrupt_status &= !VIRTIO_INTERRUPT_CONFIGURATION;
+                }
+            }
+            VIRTIO_DEVICE_STATUS => match data {
+                // Writing zero to the status register resets the device.
+                0 => self.reset(),
+                // modern drivers must accept VERSION_1, the features are
+                // rejected otherwise.
+                _ if self.is_modern()
+                    && data & VIRTIO_STATUS_FEATURES_OK != 0
+                    && self.driver_features & VIRTIO_F_VERSION_1 == 0 =>
+                {
+                    self.device_status = data & !VIRTIO_STATUS_FEATURES_OK
+                }
+                _ => self.device_status = data,
+            },
+            _ if addr >= VIRTIO_CONFIG_SPACE => {
+                if let Some(device) = &mut self.device {
+                    for i in 0..4 {
+                        device
+                            .write_config(addr - VIRTIO_CONFIG_SPACE + i, (data >> (i * 8)) as u8);
+                    }
+                }
+            }
+            _ => panic!("Write to reserved area: {:x}", addr),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// Virtio (Virtual I/O Device)
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html
+
+pub mod balloon;
+pub mod block;
+pub mod console;
+pub mod input;
+pub mod mmio;
+pub mod net;
+pub mod p9;
+pub mod pci;
+pub mod queue;
+pub mod rng;
+
+use crate::peripherals::virtio::queue::{GuestMemory, Queue};
+
+// Device IDs
+pub const VIRTIO_ID_NET: u32 = 1;
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+pub const VIRTIO_ID_CONSOLE: u32 = 3;
+pub const VIRTIO_ID_RNG: u32 = 4;
+pub const VIRTIO_ID_BALLOON: u32 = 5;
+pub const VIRTIO_ID_9P: u32 = 9;
+pub const VIRTIO_ID_INPUT: u32 = 18;
+
+// Feature bits handled by the transport
+pub const VIRTIO_F_INDIRECT_DESC: u64 = 1 << 28;
+pub const VIRTIO_F_EVENT_IDX: u64 = 1 << 29;
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Device type specific part of a virtio device. The transport owns the
+/// virtqueues and calls back into the device when they need processing.
+pub trait VirtioDevice {
+    fn get_device_id(&self) -> u32;
+    /// Feature bits offered to the driver, the transport adds its own.
+    fn get_features(&self) -> u64;
+    /// Feature bits accepted by the driver.
+    fn set_driver_features(&mut self, _features: u64) {}
+    fn get_queue_count(&self) -> usize;
+    /// Byte access to the device specific configuration space.
+    fn read_config(&self, offset: u64) -> u8;
+    fn write_config(&mut self, offset: u64, data: u8);
+    /// The driver notified the queue. Returns true if used buffers were added.
+    fn process_queue(&mut self, index: usize, queues: &mut [Queue], mem: &mut GuestMemory) -> bool;
+    /// Host side activity such as received packets, called periodically.
+    /// Returns true if used buffers were added.
+    fn poll(&mut self, _queues: &mut [Queue], _mem: &mut GuestMemory) -> bool {
+        false
+    }
+    /// Whether the configuration space changed since the last call, which is
+    /// notified to the driver.
+    fn take_config_changed(&mut self) -> bool {
+        false
+    }
+    /// The driver reset the device.
+    fn reset(&mut self) {}
+}
@@ -0,0 +1,151 @@
+// Virtio Network Device
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-1940001
+
+use crate::net::NetBackend;
+use crate::peripherals::virtio::queue::{GuestMemory, Queue};
+use crate::peripherals::virtio::{VirtioDevice, VIRTIO_F_VERSION_1, VIRTIO_ID_NET};
+
+// Feature bits
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
+/// struct virtio_net_hdr without num_buffers (legacy, no VIRTIO_NET_F_MRG_RXBUF).
+const NET_HEADER_SIZE: usize = 10;
+/// struct virtio_net_hdr with num_buffers, always used with VIRTIO_F_VERSION_1.
+const NET_HEADER_SIZE_V1: usize = 12;
+
+const RX_QUEUE: usize = 0;
+const TX_QUEUE: usize = 1;
+
+pub const DEFAULT_MAC_ADDRESS: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+pub struct VirtioNet {
+    mac: [u8; 6],
+    backend: Box<dyn NetBackend>,
+    /// a received frame waiting for a free receive buffer.
+    pending: Option<Vec<u8>>,
+    header_size: usize,
+}
+
+impl VirtioNet {
+    pub fn new(backend: Box<dyn NetBackend>) -> Self {
+        VirtioNet {
+            mac: DEFAULT_MAC_ADDRESS,
+            backend,
+            pending: None,
+            header_size: NET_HEADER_SIZE,
+        }
+    }
+
+    pub fn set_mac_address(&mut self, mac: [u8; 6]) {
+        self.mac = mac;
+    }
+
+    pub fn get_mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn transmit(&mut self, queue: &mut Queue, mem: &mut GuestMemory) -> bool {
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem) {
+            let packet = chain.read_all(mem);
+            if packet.len() > self.header_size {
+                self.backend.send(&packet[self.header_size..]);
+            }
+            queue.push(mem, chain.head, 0);
+            used = true;
+        }
+        used
+    }
+
+    fn receive(&mut self, queue: &mut Queue, mem: &mut GuestMemory) -> bool {
+        let mut used = false;
+        loop {
+            if self.pending.is_none() {
+                self.pending = self.backend.recv();
+            }
+            let frame = match &self.pending {
+                Some(frame) => frame,
+                None => break,
+            };
+            let chain = match queue.pop(mem) {
+                Some(chain) => chain,
+                // keep the frame until the driver adds buffers.
+                None => break,
+            };
+            // a zeroed header: no checksum offload and no segmentation.
+            let mut packet = vec![0; self.header_size];
+            if self.header_size == NET_HEADER_SIZE_V1 {
+                // num_buffers
+                packet[10] = 1;
+            }
+            packet.extend_from_slice(frame);
+            let written = chain.write_at(mem, 0, &packet);
+            queue.push(mem, chain.head, written as u32);
+            self.pending = None;
+            used = true;
+        }
+        used
+    }
+}
+
+impl VirtioDevice for VirtioNet {
+    fn get_device_id(&self) -> u32 {
+        VIRTIO_ID_NET
+    }
+
+    fn get_features(&self) -> u64 {
+        VIRTIO_NET_F_MAC | VIRTIO_NET_F_STATUS
+    }
+
+    fn set_driver_features(&mut self, features: u64) {
+        self.header_size = match features & VIRTIO_F_VERSION_1 {
+            0 => NET_HEADER_SIZE,
+            _ => NET_HEADER_SIZE_V1,
+        };
+    }
+
+    fn get_queue_count(&self) -> usize {
+        2
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        /* virtio_net_config
+         * ----------------
+         * u8[6] mac
+         * u16 status
+         */
+        let status = VIRTIO_NET_S_LINK_UP.to_le_bytes();
+        match offset {
+            0..=5 => self.mac[offset as usize],
+            6 | 7 => status[offset as usize - 6],
+            _ => 0,
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: u8) {
+        // legacy drivers may set the mac address.
+        if offset < 6 {
+            self.mac[offset as usize] = data;
+        }
+    }
+
+    fn process_queue(&mut self, index: usize, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        match index {
+            RX_QUEUE => self.receive(&mut queues[RX_QUEUE], mem),
+            TX_QUEUE => self.transmit(&mut queues[TX_QUEUE], mem),
+            _ => false,
+        }
+    }
+
+    fn poll(&mut self, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        self.receive(&mut queues[RX_QUEUE], mem)
+    }
+
+    fn reset(&mut self) {
+        self.pending = None;
+        self.header_size = NET_HEADER_SIZE;
+    }
+}
@@ -0,0 +1,900 @@
+// Virtio 9P Transport (9P2000.L file server)
+// https://github.com/chaos/diod/blob/master/protocol.md
+// https://github.com/torvalds/linux/blob/master/include/net/9p/9p.h
+
+use crate::peripherals::virtio::queue::{GuestMemory, Queue};
+use crate::peripherals::virtio::{VirtioDevice, VIRTIO_ID_9P};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, Metadata, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Feature bits
+const VIRTIO_9P_MOUNT_TAG: u64 = 1 << 0;
+
+// Message types (T-message, the R-message is the next number)
+const P9_RLERROR: u8 = 7;
+const P9_TSTATFS: u8 = 8;
+const P9_TLOPEN: u8 = 12;
+const P9_TLCREATE: u8 = 14;
+const P9_TRENAME: u8 = 20;
+const P9_TREADLINK: u8 = 22;
+const P9_TGETATTR: u8 = 24;
+const P9_TSETATTR: u8 = 26;
+const P9_TREADDIR: u8 = 40;
+const P9_TFSYNC: u8 = 50;
+const P9_TLOCK: u8 = 52;
+const P9_TGETLOCK: u8 = 54;
+const P9_TMKDIR: u8 = 72;
+const P9_TRENAMEAT: u8 = 74;
+const P9_TUNLINKAT: u8 = 76;
+const P9_TVERSION: u8 = 100;
+const P9_TATTACH: u8 = 104;
+const P9_TFLUSH: u8 = 108;
+const P9_TWALK: u8 = 110;
+const P9_TREAD: u8 = 116;
+const P9_TWRITE: u8 = 118;
+const P9_TCLUNK: u8 = 120;
+const P9_TREMOVE: u8 = 122;
+
+// Linux errno values returned in Rlerror
+const EPERM: u32 = 1;
+const ENOENT: u32 = 2;
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const EEXIST: u32 = 17;
+const ENOTDIR: u32 = 20;
+const EISDIR: u32 = 21;
+const EINVAL: u32 = 22;
+const EROFS: u32 = 30;
+const ENOTEMPTY: u32 = 39;
+const EOPNOTSUPP: u32 = 95;
+
+// Qid types
+const P9_QTDIR: u8 = 0x80;
+const P9_QTSYMLINK: u8 = 0x02;
+const P9_QTFILE: u8 = 0x00;
+
+// Directory entry types
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+
+// Open flags (Linux)
+const O_ACCMODE: u32 = 0o3;
+const O_WRONLY: u32 = 0o1;
+const O_RDWR: u32 = 0o2;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+
+// Setattr valid bits
+const P9_SETATTR_MODE: u32 = 0x001;
+const P9_SETATTR_SIZE: u32 = 0x008;
+const P9_SETATTR_MTIME: u32 = 0x020;
+const P9_SETATTR_MTIME_SET: u32 = 0x100;
+
+const P9_GETATTR_BASIC: u64 = 0x7ff;
+const AT_REMOVEDIR: u32 = 0x200;
+const P9_LOCK_SUCCESS: u8 = 0;
+const F_UNLCK: u8 = 2;
+const V9FS_MAGIC: u32 = 0x0102_1997;
+
+const P9_VERSION: &str = "9P2000.L";
+const P9_NOFID: u32 = 0xffff_ffff;
+/// The largest message size accepted from the driver.
+const MAX_MSIZE: u32 = 0x10000;
+/// size[4] type[1] tag[2] of a message.
+const HEADER_SIZE: u32 = 7;
+
+/// Qid: type[1] version[4] path[8], identifies a file on the server.
+#[derive(Clone, Copy, PartialEq)]
+struct Qid {
+    qid_type: u8,
+    version: u32,
+    path: u64,
+}
+
+/// File id: a file the driver walked to, and its handle once opened.
+struct Fid {
+    /// path relative to the exported directory.
+    path: PathBuf,
+    file: Option<File>,
+}
+
+/// Reads the fields of a T-message.
+struct MessageReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> MessageReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        MessageReader { data, pos: 0 }
+    }
+
+    fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], u32> {
+        match self.data.get(self.pos..self.pos + len) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => Err(EINVAL),
+        }
+    }
+
+    fn get_u8(&mut self) -> Result<u8, u32> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    fn get_u16(&mut self) -> Result<u16, u32> {
+        let bytes = self.get_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn get_u32(&mut self) -> Result<u32, u32> {
+        let bytes = self.get_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn get_u64(&mut self) -> Result<u64, u32> {
+        Ok(self.get_u32()? as u64 | (self.get_u32()? as u64) << 32)
+    }
+
+    /// string: len[2] followed by UTF-8 bytes.
+    fn get_str(&mut self) -> Result<String, u32> {
+        let len = self.get_u16()? as usize;
+        match String::from_utf8(self.get_bytes(len)?.to_vec()) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(EINVAL),
+        }
+    }
+}
+
+/// Builds the payload of an R-message.
+struct MessageWriter {
+    data: Vec<u8>,
+}
+
+impl MessageWriter {
+    fn new() -> Self {
+        MessageWriter { data: vec![] }
+    }
+
+    fn put_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn put_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_str(&mut self, value: &str) {
+        self.put_u16(value.len() as u16);
+        self.data.extend_from_slice(value.as_bytes());
+    }
+
+    fn put_qid(&mut self, qid: Qid) {
+        self.put_u8(qid.qid_type);
+        self.put_u32(qid.version);
+        self.put_u64(qid.path);
+    }
+}
+
+/// Exports a host directory to the guest, which mounts it with
+/// `mount -t 9p -o trans=virtio,version=9p2000.L TAG DIR`.
+pub struct Virtio9p {
+    /// canonical path of the exported directory.
+    root: PathBuf,
+    tag: String,
+    read_only: bool,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Virtio9p {
+    /// Export the directory with the mount tag. The guest can not modify
+    /// anything in a read-only export.
+    pub fn new(root: &Path, tag: &str, read_only: bool) -> io::Result<Self> {
+        let root = root.canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a directory",
+            ));
+        }
+        Ok(Virtio9p {
+            root,
+            tag: tag.to_string(),
+            read_only,
+            msize: MAX_MSIZE,
+            fids: HashMap::new(),
+        })
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn get_tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Handle a T-message and return the R-message.
+    pub fn handle_message(&mut self, request: &[u8]) -> Vec<u8> {
+        let mut reader = MessageReader::new(request);
+        let header = (reader.get_u32(), reader.get_u8(), reader.get_u16());
+        let (request_type, tag) = match header {
+            (Ok(_), Ok(request_type), Ok(tag)) => (request_type, tag),
+            _ => return vec![],
+        };
+        let (response_type, payload) = match self.dispatch(request_type, &mut reader) {
+            Ok(payload) => (request_type + 1, payload),
+            Err(ecode) => {
+                let mut writer = MessageWriter::new();
+                writer.put_u32(ecode);
+                (P9_RLERROR, writer)
+            }
+        };
+        let mut response = (HEADER_SIZE + payload.data.len() as u32)
+            .to_le_bytes()
+            .to_vec();
+        response.push(response_type);
+        response.extend_from_slice(&tag.to_le_bytes());
+        response.extend_from_slice(&payload.data);
+        response
+    }
+
+    fn dispatch(
+        &mut self,
+        request_type: u8,
+        reader: &mut MessageReader,
+    ) -> Result<MessageWriter, u32> {
+        let mut writer = MessageWriter::new();
+        match request_type {
+            P9_TVERSION => self.version(reader, &mut writer)?,
+            P9_TATTACH => self.attach(reader, &mut writer)?,
+            P9_TFLUSH => {}
+            P9_TWALK => self.walk(reader, &mut writer)?,
+            P9_TCLUNK => {
+                let fid = reader.get_u32()?;
+                self.fids.remove(&fid).ok_or(EBADF)?;
+            }
+            P9_TGETATTR => self.getattr(reader, &mut writer)?,
+            P9_TSTATFS => {
+                self.get_fid(reader.get_u32()?)?;
+                // the host file system is not queried, the numbers only
+                // need to be plausible.
+                writer.put_u32(V9FS_MAGIC);
+                writer.put_u32(4096); // bsize
+                writer.put_u64(0x100_0000); // blocks
+                writer.put_u64(0x80_0000); // bfree
+                writer.put_u64(0x80_0000); // bavail
+                writer.put_u64(0x10_0000); // files
+                writer.put_u64(0x8_0000); // ffree
+                writer.put_u64(0); // fsid
+                writer.put_u32(255); // namelen
+            }
+            P9_TLOPEN => self.lopen(reader, &mut writer)?,
+            P9_TLCREATE => self.lcreate(reader, &mut writer)?,
+            P9_TREAD => self.read(reader, &mut writer)?,
+            P9_TWRITE => self.write(reader, &mut writer)?,
+            P9_TREADDIR => self.readdir(reader, &mut writer)?,
+            P9_TREADLINK => {
+                let path = self.get_fid(reader.get_u32()?)?;
+                let host_path = self.get_host_path_checked(&path, false)?;
+                let target = fs::read_link(host_path).map_err(|e| get_errno(&e))?;
+                writer.put_str(&target.to_string_lossy());
+            }
+            P9_TSETATTR => self.setattr(reader)?,
+            P9_TFSYNC => {
+                let fid = self.fids.get(&reader.get_u32()?).ok_or(EBADF)?;
+                if let Some(file) = &fid.file {
+                    file.sync_all().map_err(|e| get_errno(&e))?;
+                }
+            }
+            P9_TMKDIR => {
+                let dir = self.get_fid(reader.get_u32()?)?;
+                let name = reader.get_str()?;
+                self.check_writable()?;
+                let path = self.get_child_path(&dir, &name)?;
+                let host_path = self.get_host_path_checked(&path, false)?;
+                fs::create_dir(host_path).map_err(|e| get_errno(&e))?;
+                writer.put_qid(self.get_qid(&path)?);
+            }
+            P9_TRENAME => {
+                let fid = reader.get_u32()?;
+                let dir = self.get_fid(reader.get_u32()?)?;
+                let name = reader.get_str()?;
+                let old_path = self.get_fid(fid)?;
+                let new_path = self.get_child_path(&dir, &name)?;
+                self.rename(&old_path, &new_path)?;
+            }
+            P9_TRENAMEAT => {
+                let old_dir = self.get_fid(reader.get_u32()?)?;
+                let old_name = reader.get_str()?;
+                let new_dir = self.get_fid(reader.get_u32()?)?;
+                let new_name = reader.get_str()?;
+                let old_path = self.get_child_path(&old_dir, &old_name)?;
+                let new_path = self.get_child_path(&new_dir, &new_name)?;
+                self.rename(&old_path, &new_path)?;
+            }
+            P9_TUNLINKAT => {
+                let dir = self.get_fid(reader.get_u32()?)?;
+                let name = reader.get_str()?;
+                let flags = reader.get_u32()?;
+                let path = self.get_child_path(&dir, &name)?;
+                self.remove(&path, flags & AT_REMOVEDIR != 0)?;
+            }
+            P9_TREMOVE => {
+                // the fid is clunked even if the removal fails.
+                let fid = self.fids.remove(&reader.get_u32()?).ok_or(EBADF)?;
+                let is_dir = self.get_metadata(&fid.path)?.is_dir();
+                self.remove(&fid.path, is_dir)?;
+            }
+            P9_TLOCK => {
+                // locks are not shared with the host, so they always succeed.
+                self.get_fid(reader.get_u32()?)?;
+                writer.put_u8(P9_LOCK_SUCCESS);
+            }
+            P9_TGETLOCK => {
+                self.get_fid(reader.get_u32()?)?;
+                let _lock_type = reader.get_u8()?;
+                let start = reader.get_u64()?;
+                let length = reader.get_u64()?;
+                let proc_id = reader.get_u32()?;
+                let client_id = reader.get_str()?;
+                writer.put_u8(F_UNLCK);
+                writer.put_u64(start);
+                writer.put_u64(length);
+                writer.put_u32(proc_id);
+                writer.put_str(&client_id);
+            }
+            // xattrs, links and device nodes are not supported.
+            _ => return Err(EOPNOTSUPP),
+        }
+        Ok(writer)
+    }
+
+    fn version(
+        &mut self,
+        reader: &mut MessageReader,
+        writer: &mut MessageWriter,
+    ) -> Result<(), u32> {
+        let msize = reader.get_u32()?;
+        let version = reader.get_str()?;
+        // a new session starts and all fids are released.
+        self.fids.clear();
+        self.msize = msize.min(MAX_MSIZE);
+        writer.put_u32(self.msize);
+        match version.as_str() {
+            P9_VERSION => writer.put_str(P9_VERSION),
+            _ => writer.put_str("unknown"),
+        }
+        Ok(())
+    }
+
+    fn attach(
+        &mut self,
+        reader: &mut MessageReader,
+        writer: &mut MessageWriter,
+    ) -> Result<(), u32> {
+        let fid = reader.get_u32()?;
+        let afid = reader.get_u32()?;
+        if afid != P9_NOFID {
+            // no authentication
+            return Err(EINVAL);
+        }
+        let path = PathBuf::new();
+        let qid = self.get_qid(&path)?;
+        self.fids.insert(fid, Fid { path, file: None });
+        writer.put_qid(qid);
+        Ok(())
+    }
+
+    fn walk(&mut self, reader: &mut MessageReader, writer: &mut MessageWriter) -> Result<(), u32> {
+        let fid = reader.get_u32()?;
+        let newfid = reader.get_u32()?;
+        let nwname = reader.get_u16()?;
+        let mut path = self.get_fid(fid)?;
+        let mut qids = vec![];
+        for i in 0..nwname {
+            let name = reader.get_str()?;
+            let next = match self.get_child_path(&path, &name) {
+                Ok(next) => next,
+                Err(ecode) if i == 0 => return Err(ecode),
+                Err(_) => break,
+            };
+            match self.get_qid(&next) {
+                Ok(qid) => qids.push(qid),
+                Err(ecode) if i == 0 => return Err(ecode),
+                Err(_) => break,
+            }
+            path = next;
+        }
+        // newfid is only set up if all the names are walked.
+        if qids.len() == nwname as usize {
+            self.fids.insert(newfid, Fid { path, file: None });
+        }
+        writer.put_u16(qids.len() as u16);
+        for qid in qids {
+            writer.put_qid(qid);
+        }
+        Ok(())
+    }
+
+    fn getattr(
+        &mut self,
+        reader: &mut MessageReader,
+        writer: &mut MessageWriter,
+    ) -> Result<(), u32> {
+        let path = self.get_fid(reader.get_u32()?)?;
+        let _request_mask = reader.get_u64()?;
+        let metadata = self.get_metadata(&path)?;
+        let (atime_sec, atime_nsec) = get_time(metadata.accessed());
+        let (mtime_sec, mtime_nsec) = get_time(metadata.modified());
+        let (btime_sec, btime_nsec) = get_time(metadata.created());
+        writer.put_u64(P9_GETATTR_BASIC);
+        writer.put_qid(self.get_qid(&path)?);
+        writer.put_u32(get_mode(&metadata));
+        // the guest sees every file as owned by root.
+        writer.put_u32(0); // uid
+        writer.put_u32(0); // gid
+        writer.put_u64(1); // nlink
+        writer.put_u64(0); // rdev
+        writer.put_u64(metadata.len());
+        writer.put_u64(4096); // blksize
+        writer.put_u64(metadata.len().div_ceil(512)); // blocks
+        writer.put_u64(atime_sec);
+        writer.put_u64(atime_nsec);
+        writer.put_u64(mtime_sec);
+        writer.put_u64(mtime_nsec);
+        // ctime
+        writer.put_u64(mtime_sec);
+        writer.put_u64(mtime_nsec);
+        writer.put_u64(btime_sec);
+        writer.put_u64(btime_nsec);
+        writer.put_u64(0); // gen
+        writer.put_u64(0); // data_version
+        Ok(())
+    }
+
+    fn setattr(&mut self, reader: &mut MessageReader) -> Result<(), u32> {
+        let path = self.get_fid(reader.get_u32()?)?;
+        let valid = reader.get_u32()?;
+        let mode = reader.get_u32()?;
+        let _uid = reader.get_u32()?;
+        let _gid = reader.get_u32()?;
+        let size = reader.get_u64()?;
+        let _atime = (reader.get_u64()?, reader.get_u64()?);
+        let mtime_sec = reader.get_u64()?;
+        let mtime_nsec = reader.get_u64()?;
+        // ownership and access times are not kept.
+        if valid & (P9_SETATTR_MODE | P9_SETATTR_SIZE | P9_SETATTR_MTIME) == 0 {
+            return Ok(());
+        }
+        self.check_writable()?;
+        let host_path = self.get_host_path_checked(&path, true)?;
+        if valid & P9_SETATTR_MODE != 0 {
+            set_mode(&host_path, mode).map_err(|e| get_errno(&e))?;
+        }
+        if valid & (P9_SETATTR_SIZE | P9_SETATTR_MTIME) != 0 {
+            let file = OpenOptions::new()
+                .write(true)
+                .open(&host_path)
+                .map_err(|e| get_errno(&e))?;
+            if valid & P9_SETATTR_SIZE != 0 {
+                file.set_len(size).map_err(|e| get_errno(&e))?;
+            }
+            if valid & P9_SETATTR_MTIME != 0 {
+                let mtime = match valid & P9_SETATTR_MTIME_SET {
+                    0 => SystemTime::now(),
+                    _ => UNIX_EPOCH + Duration::new(mtime_sec, mtime_nsec as u32),
+                };
+                file.set_modified(mtime).map_err(|e| get_errno(&e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn lopen(&mut self, reader: &mut MessageReader, writer: &mut MessageWriter) -> Result<(), u32> {
+        let fid = reader.get_u32()?;
+        let flags = reader.get_u32()?;
+        let path = self.get_fid(fid)?;
+        let metadata = self.get_metadata(&path)?;
+        let host_path = self.get_host_path_checked(&path, true)?;
+        let file = match metadata.is_dir() {
+            // directories are read with readdir.
+            true => None,
+            false => Some(self.open(&host_path, flags, false)?),
+        };
+        if let Some(fid) = self.fids.get_mut(&fid) {
+            fid.file = file;
+        }
+        writer.put_qid(self.get_qid(&path)?);
+        writer.put_u32(self.get_iounit());
+        Ok(())
+    }
+
+    fn lcreate(
+        &mut self,
+        reader: &mut MessageReader,
+        writer: &mut MessageWriter,
+    ) -> Result<(), u32> {
+        let fid = reader.get_u32()?;
+        let name = reader.get_str()?;
+        let flags = reader.get_u32()?;
+        let mode = reader.get_u32()?;
+        let _gid = reader.get_u32()?;
+        let dir = self.get_fid(fid)?;
+        self.check_writable()?;
+        let path = self.get_child_path(&dir, &name)?;
+        let host_path = self.get_host_path_checked(&path, false)?;
+        // a dangling symbolic link would be followed outside the export.
+        if let Ok(metadata) = fs::symlink_metadata(&host_path) {
+            if metadata.file_type().is_symlink() {
+                return Err(EEXIST);
+            }
+        }
+        let file = self.open(&host_path, flags, true)?;
+        // best effort, e.g. to keep the executable bit.
+        let _ = set_mode(&host_path, mode);
+        // the fid now represents the new file.
+        let qid = self.get_qid(&path)?;
+        self.fids.insert(
+            fid,
+            Fid {
+                path,
+                file: Some(file),
+            },
+        );
+        writer.put_qid(qid);
+        writer.put_u32(self.get_iounit());
+        Ok(())
+    }
+
+    fn read(&mut self, reader: &mut MessageReader, writer: &mut MessageWriter) -> Result<(), u32> {
+        let fid = reader.get_u32()?;
+        let offset = reader.get_u64()?;
+        let count = reader.get_u32()?.min(self.get_iounit());
+        let file = self.get_file(fid)?;
+        let mut data = vec![0; count as usize];
+        let mut len = 0;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| get_errno(&e))?;
+        while len < data.len() {
+            match file.read(&mut data[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) => return Err(get_errno(&e)),
+            }
+        }
+        writer.put_u32(len as u32);
+        writer.data.extend_from_slice(&data[..len]);
+        Ok(())
+    }
+
+    fn write(&mut self, reader: &mut MessageReader, writer: &mut MessageWriter) -> Result<(), u32> {
+        let fid = reader.get_u32()?;
+        let offset = reader.get_u64()?;
+        let count = reader.get_u32()?;
+        let data = reader.get_bytes(count as usize)?;
+        self.check_writable()?;
+        let file = self.get_file(fid)?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| get_errno(&e))?;
+        file.write_all(data).map_err(|e| get_errno(&e))?;
+        writer.put_u32(count);
+        Ok(())
+    }
+
+    fn readdir(
+        &mut self,
+        reader: &mut MessageReader,
+        writer: &mut MessageWriter,
+    ) -> Result<(), u32> {
+        let path = self.get_fid(reader.get_u32()?)?;
+        let offset = reader.get_u64()?;
+        let count = reader.get_u32()?.min(self.get_iounit()) as usize;
+        let host_path = self.get_host_path_checked(&path, true)?;
+        let mut names: Vec<String> = vec![".".to_string(), "..".to_string()];
+        let mut entries: Vec<String> = fs::read_dir(host_path)
+            .map_err(|e| get_errno(&e))?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        // the offset of an entry is its index, so the order must be stable.
+        entries.sort();
+        names.extend(entries);
+
+        /* dirent
+         * ----------------
+         * qid[13] offset[8] type[1] name[s]
+         */
+        let mut data = MessageWriter::new();
+        for (i, name) in names.iter().enumerate().skip(offset as usize) {
+            // an entry the guest cannot name (e.g. with a backslash) is
+            // skipped like one that vanished.
+            let entry_path = match name.as_str() {
+                "." => Ok(path.clone()),
+                _ => self.get_child_path(&path, name),
+            };
+            let qid = match entry_path.and_then(|entry_path| self.get_qid(&entry_path)) {
+                Ok(qid) => qid,
+                Err(_) => continue,
+            };
+            if data.data.len() + 24 + name.len() > count {
+                break;
+            }
+            data.put_qid(qid);
+            data.put_u64(i as u64 + 1);
+            data.put_u8(match qid.qid_type {
+                P9_QTDIR => DT_DIR,
+                P9_QTSYMLINK => DT_LNK,
+                _ => DT_REG,
+            });
+            data.put_str(name);
+        }
+        writer.put_u32(data.data.len() as u32);
+        writer.data.extend_from_slice(&data.data);
+        Ok(())
+    }
+
+    fn rename(&mut self, old_path: &Path, new_path: &Path) -> Result<(), u32> {
+        self.check_writable()?;
+        if old_path.as_os_str().is_empty() || new_path.as_os_str().is_empty() {
+            // the exported directory itself
+            return Err(EPERM);
+        }
+        let old_host_path = self.get_host_path_checked(old_path, false)?;
+        let new_host_path = self.get_host_path_checked(new_path, false)?;
+        fs::rename(old_host_path, new_host_path).map_err(|e| get_errno(&e))?;
+        // fids of the file (and its children) follow it.
+        for fid in self.fids.values_mut() {
+            if let Ok(rest) = fid.path.strip_prefix(old_path) {
+                fid.path = new_path.join(rest);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &Path, is_dir: bool) -> Result<(), u32> {
+        self.check_writable()?;
+        if path.as_os_str().is_empty() {
+            // the exported directory itself
+            return Err(EPERM);
+        }
+        let host_path = self.get_host_path_checked(path, false)?;
+        let result = match is_dir {
+            true => fs::remove_dir(host_path),
+            false => fs::remove_file(host_path),
+        };
+        result.map_err(|e| get_errno(&e))
+    }
+
+    fn open(&self, host_path: &Path, flags: u32, create: bool) -> Result<File, u32> {
+        let writable = match flags & O_ACCMODE {
+            O_WRONLY | O_RDWR => true,
+            _ => flags & O_TRUNC != 0,
+        };
+        if writable || create {
+            self.check_writable()?;
+        }
+        let mut options = OpenOptions::new();
+        options.read(flags & O_ACCMODE != O_WRONLY);
+        options.write(writable);
+        options.truncate(flags & O_TRUNC != 0);
+        match (create, flags & O_EXCL != 0) {
+            (true, true) => options.write(true).create_new(true),
+            (true, false) => options.write(true).create(true),
+            _ => &mut options,
+        };
+        options.open(host_path).map_err(|e| get_errno(&e))
+    }
+
+    fn check_writable(&self) -> Result<(), u32> {
+        match self.read_only {
+            true => Err(EROFS),
+            false => Ok(()),
+        }
+    }
+
+    fn get_iounit(&self) -> u32 {
+        // size[4] type[1] tag[2] count[4] precede the data.
+        self.msize.saturating_sub(HEADER_SIZE + 4)
+    }
+
+    /// Relative path of the fid.
+    fn get_fid(&self, fid: u32) -> Result<PathBuf, u32> {
+        match self.fids.get(&fid) {
+            Some(fid) => Ok(fid.path.clone()),
+            None => Err(EBADF),
+        }
+    }
+
+    fn get_file(&mut self, fid: u32) -> Result<&mut File, u32> {
+        match self.fids.get_mut(&fid) {
+            Some(Fid {
+                file: Some(file), ..
+            }) => Ok(file),
+            Some(_) => Err(EBADF),
+            None => Err(EBADF),
+        }
+    }
+
+    /// Relative path of a name in the directory. The path never leaves the
+    /// exported directory: ".." of the root is the root and names can not
+    /// contain separators.
+    fn get_child_path(&self, dir: &Path, name: &str) -> Result<PathBuf, u32> {
+        match name {
+            "" | "." => Err(EINVAL),
+            ".." => Ok(dir.parent().map(|p| p.to_path_buf()).unwrap_or_default()),
+            _ if name.contains('/') || name.contains('\\') || name.contains('\0') => Err(EINVAL),
+            _ => Ok(dir.join(name)),
+        }
+    }
+
+    /// Host path of the relative path, which is checked not to resolve
+    /// outside the exported directory through symbolic links. The last
+    /// component is followed only if follow is set.
+    fn get_host_path_checked(&self, path: &Path, follow: bool) -> Result<PathBuf, u32> {
+        let host_path = self.root.join(path);
+        let parent = match path.parent() {
+            Some(parent) => self.root.join(parent),
+            None => self.root.clone(),
+        };
+        let resolved = match follow {
+            // the file may not exist yet.
+            true => host_path.canonicalize().or_else(|_| parent.canonicalize()),
+            false => parent.canonicalize(),
+        };
+        match resolved {
+            Ok(resolved) if resolved.starts_with(&self.root) => Ok(host_path),
+            Ok(_) => Err(EACCES),
+            Err(e) => Err(get_errno(&e)),
+        }
+    }
+
+    /// Metadata of the file itself, not the target of a symbolic link.
+    fn get_metadata(&self, path: &Path) -> Result<Metadata, u32> {
+        let host_path = self.get_host_path_checked(path, false)?;
+        fs::symlink_metadata(host_path).map_err(|e| get_errno(&e))
+    }
+
+    fn get_qid(&self, path: &Path) -> Result<Qid, u32> {
+        let metadata = self.get_metadata(path)?;
+        let qid_type = match metadata.file_type() {
+            t if t.is_dir() => P9_QTDIR,
+            t if t.is_symlink() => P9_QTSYMLINK,
+            _ => P9_QTFILE,
+        };
+        // files are identified by their path in the export.
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        Ok(Qid {
+            qid_type,
+            version: 0,
+            path: hasher.finish(),
+        })
+    }
+}
+
+impl VirtioDevice for Virtio9p {
+    fn get_device_id(&self) -> u32 {
+        VIRTIO_ID_9P
+    }
+
+    fn get_features(&self) -> u64 {
+        VIRTIO_9P_MOUNT_TAG
+    }
+
+    fn get_queue_count(&self) -> usize {
+        1
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        /* virtio_9p_config
+         * ----------------
+         * u16 tag_len
+         * u8[] tag
+         */
+        let tag = self.tag.as_bytes();
+        match offset {
+            0 | 1 => ((tag.len() as u16) >> (offset * 8)) as u8,
+            _ => tag.get(offset as usize - 2).cloned().unwrap_or(0),
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: u8) {}
+
+    fn process_queue(&mut self, index: usize, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        let queue = &mut queues[index];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem) {
+            let response = self.handle_message(&chain.read_all(mem));
+            let written = chain.write_at(mem, 0, &response);
+            queue.push(mem, chain.head, written as u32);
+            used = true;
+        }
+        used
+    }
+
+    fn reset(&mut self) {
+        self.fids.clear();
+        self.msize = MAX_MSIZE;
+    }
+}
+
+/// errno of a host error for Rlerror.
+fn get_errno(error: &io::Error) -> u32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::InvalidInput => EINVAL,
+        io::ErrorKind::NotADirectory => ENOTDIR,
+        io::ErrorKind::IsADirectory => EISDIR,
+        io::ErrorKind::DirectoryNotEmpty => ENOTEMPTY,
+        io::ErrorKind::ReadOnlyFilesystem => EROFS,
+        _ => EIO,
+    }
+}
+
+/// Seconds and nanoseconds since the epoch.
+fn get_time(time: io::Result<SystemTime>) -> (u64, u64) {
+    match time.map(|time| time.duration_since(UNIX_EPOCH)) {
+        Ok(Ok(duration)) => (duration.as_secs(), duration.subsec_nanos() as u64),
+        _ => (0, 0),
+    }
+}
+
+#[cfg(unix)]
+fn get_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.mode()
+}
+
+/// Hosts without Unix permissions only tell whether a file is read-only.
+#[cfg(not(unix))]
+fn get_mode(metadata: &Metadata) -> u32 {
+    let (format, permissions) = match metadata.file_type() {
+        t if t.is_dir() => (0o040000, 0o755),
+        t if t.is_symlink() => (0o120000, 0o777),
+        _ => (0o100000, 0o755),
+    };
+    match metadata.permissions().readonly() {
+        true => format | (permissions & 0o555),
+        false => format | permissions,
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(host_path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(host_path, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(host_path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(host_path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(host_path, permissions)
+}
@@ -0,0 +1,382 @@
+// Virtio Over PCI Bus (modern interface)
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-1090002
+//
+// The device state lives in a VirtioMmio in the modern mode, the structures
+// of the PCI transport are translated to its registers.
+
+use crate::peripherals::memory::Memory;
+use crate::peripherals::pci::{
+    PciDevice, PCI_CAP_ID_VENDOR, PCI_CLASS_NETWORK_ETHERNET, PCI_CLASS_OTHER,
+    PCI_CLASS_STORAGE_OTHER,
+};
+use crate::peripherals::virtio::mmio::{VirtioMmio, VIRTIO_MMIO_MODERN};
+use crate::peripherals::virtio::{VirtioDevice, VIRTIO_ID_BLOCK, VIRTIO_ID_NET};
+
+const VIRTIO_PCI_VENDOR_ID: u16 = 0x1af4;
+/// Device IDs of the modern interface are 0x1040 plus the virtio device ID.
+const VIRTIO_PCI_DEVICE_ID_BASE: u16 = 0x1040;
+
+/// Size of the BAR 0, holding all the structures.
+const VIRTIO_PCI_BAR_SIZE: u64 = 0x4000;
+const VIRTIO_PCI_COMMON_CFG: u64 = 0x0000;
+const VIRTIO_PCI_ISR_CFG: u64 = 0x1000;
+const VIRTIO_PCI_DEVICE_CFG: u64 = 0x2000;
+const VIRTIO_PCI_NOTIFY_CFG: u64 = 0x3000;
+const VIRTIO_PCI_CFG_SIZE: u64 = 0x1000;
+/// queue_notify_off is the queue index.
+const VIRTIO_PCI_NOTIFY_OFF_MULTIPLIER: u32 = 4;
+
+// Types of the vendor specific capabilities
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+const VIRTIO_PCI_CAP_ISR_CFG: u8 = 3;
+const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
+
+// Common configuration structure
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0c;
+const COMMON_MSIX_CONFIG: u64 = 0x10;
+const COMMON_NUM_QUEUES: u64 = 0x12;
+const COMMON_DEVICE_STATUS: u64 = 0x14;
+const COMMON_CONFIG_GENERATION: u64 = 0x15;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_MSIX_VECTOR: u64 = 0x1a;
+const COMMON_QUEUE_ENABLE: u64 = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1e;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+const COMMON_QUEUE_DEVICE_HIGH: u64 = 0x34;
+const COMMON_CFG_SIZE: u64 = 0x38;
+
+// Registers of the MMIO interface
+const MMIO_DEVICE_FEATURES: u64 = 0x010;
+const MMIO_DEVICE_FEATURES_SEL: u64 = 0x014;
+const MMIO_DRIVER_FEATURES: u64 = 0x020;
+const MMIO_DRIVER_FEATURES_SEL: u64 = 0x024;
+const MMIO_QUEUE_SEL: u64 = 0x030;
+const MMIO_QUEUE_NUM_MAX: u64 = 0x034;
+const MMIO_QUEUE_NUM: u64 = 0x038;
+const MMIO_QUEUE_READY: u64 = 0x044;
+const MMIO_QUEUE_NOTIFY: u64 = 0x050;
+const MMIO_INTERRUPT_STATUS: u64 = 0x060;
+const MMIO_INTERRUPT_ACK: u64 = 0x064;
+const MMIO_DEVICE_STATUS: u64 = 0x070;
+const MMIO_QUEUE_DESC_LOW: u64 = 0x080;
+const MMIO_QUEUE_DRIVER_LOW: u64 = 0x090;
+const MMIO_QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const MMIO_CONFIG_GENERATION: u64 = 0x0fc;
+const MMIO_CONFIG_SPACE: u64 = 0x100;
+
+/// No MSI-X vector is used, interrupts are signaled with INTx.
+const VIRTIO_MSI_NO_VECTOR: u16 = 0xffff;
+
+/// Queue registers the driver can read back, which are write only in the
+/// MMIO interface.
+#[derive(Clone, Copy)]
+struct QueueConfig {
+    size: u16,
+    /// descriptor area, driver area and device area.
+    addrs: [u64; 3],
+}
+
+pub struct VirtioPci {
+    mmio: VirtioMmio,
+    /// virtio device ID.
+    device_id: u16,
+    /// the selectors and the driver features are write only in the MMIO
+    /// interface.
+    device_feature_select: u32,
+    driver_feature_select: u32,
+    driver_features: [u32; 2],
+    queue_select: u16,
+    queues: Vec<QueueConfig>,
+}
+
+impl VirtioPci {
+    pub fn new(dram_base_addr: u64, device: Box<dyn VirtioDevice>) -> Self {
+        let device_id = device.get_device_id() as u16;
+        let mut mmio = VirtioMmio::new(dram_base_addr);
+        mmio.set_version(VIRTIO_MMIO_MODERN);
+        mmio.set_device(device);
+        let mut pci = VirtioPci {
+            mmio,
+            device_id,
+            device_feature_select: 0,
+            driver_feature_select: 0,
+            driver_features: [0; 2],
+            queue_select: 0,
+            queues: vec![],
+        };
+        pci.reset_transport();
+        pci
+    }
+
+    pub fn get_device(&mut self) -> Option<&mut Box<dyn VirtioDevice>> {
+        self.mmio.get_device()
+    }
+
+    fn reset_transport(&mut self) {
+        let count = self.mmio.get_device().map_or(0, |d| d.get_queue_count());
+        let size = self.mmio.read(MMIO_QUEUE_NUM_MAX) as u16;
+        self.device_feature_select = 0;
+        self.driver_feature_select = 0;
+        self.driver_features = [0; 2];
+        self.queue_select = 0;
+        self.queues = vec![
+            QueueConfig {
+                size,
+                addrs: [0; 3]
+            };
+            count
+        ];
+    }
+
+    /// Select the queue in the MMIO interface before accessing its registers.
+    fn select_queue(&mut self) -> Option<usize> {
+        let index = self.queue_select as usize;
+        self.mmio.write(MMIO_QUEUE_SEL, index as u32);
+        match index < self.queues.len() {
+            true => Some(index),
+            false => None,
+        }
+    }
+
+    fn read_common(&mut self, offset: u64) -> u64 {
+        let queue = self.queues.get(self.queue_select as usize).cloned();
+        match offset {
+            COMMON_DEVICE_FEATURE_SELECT => self.device_feature_select as u64,
+            COMMON_DEVICE_FEATURE => self.mmio.read(MMIO_DEVICE_FEATURES) as u64,
+            COMMON_DRIVER_FEATURE_SELECT => self.driver_feature_select as u64,
+            COMMON_DRIVER_FEATURE => match self
+                .driver_features
+                .get(self.driver_feature_select as usize)
+            {
+                Some(features) => *features as u64,
+                None => 0,
+            },
+            COMMON_MSIX_CONFIG => VIRTIO_MSI_NO_VECTOR as u64,
+            COMMON_NUM_QUEUES => self.queues.len() as u64,
+            COMMON_DEVICE_STATUS => self.mmio.read(MMIO_DEVICE_STATUS) as u64,
+            COMMON_CONFIG_GENERATION => self.mmio.read(MMIO_CONFIG_GENERATION) as u64,
+            COMMON_QUEUE_SELECT => self.queue_select as u64,
+            COMMON_QUEUE_SIZE => queue.map_or(0, |q| q.size as u64),
+            COMMON_QUEUE_MSIX_VECTOR => VIRTIO_MSI_NO_VECTOR as u64,
+            COMMON_QUEUE_ENABLE => match self.select_queue() {
+                Some(_) => self.mmio.read(MMIO_QUEUE_READY) as u64,
+                None => 0,
+            },
+            COMMON_QUEUE_NOTIFY_OFF => self.queue_select as u64,
+            COMMON_QUEUE_DESC..=COMMON_QUEUE_DEVICE_HIGH => {
+                let addr = queue.map_or(0, |q| q.addrs[Self::get_area_index(offset)]);
+                match offset % 8 {
+                    0 => addr,
+                    _ => addr >> 32,
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /// Index of the descriptor, driver or device area addressed by a field.
+    fn get_area_index(offset: u64) -> usize {
+        match offset & !0x7 {
+            COMMON_QUEUE_DESC => 0,
+            COMMON_QUEUE_DRIVER => 1,
+            COMMON_QUEUE_DEVICE => 2,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_common(&mut self, offset: u64, data: u64) {
+        let data32 = data as u32;
+        match offset {
+            COMMON_DEVICE_FEATURE_SELECT => {
+                self.device_feature_select = data32;
+                self.mmio.write(MMIO_DEVICE_FEATURES_SEL, data32);
+            }
+            COMMON_DRIVER_FEATURE_SELECT => {
+                self.driver_feature_select = data32;
+                self.mmio.write(MMIO_DRIVER_FEATURES_SEL, data32);
+            }
+            COMMON_DRIVER_FEATURE => {
+                if let Some(features) = self
+                    .driver_features
+                    .get_mut(self.driver_feature_select as usize)
+                {
+                    *features = data32;
+                }
+                self.mmio.write(MMIO_DRIVER_FEATURES, data32);
+            }
+            COMMON_DEVICE_STATUS => {
+                self.mmio.write(MMIO_DEVICE_STATUS, data32 & 0xff);
+                // Writing zero to the status register resets the device.
+                if data32 & 0xff == 0 {
+                    self.reset_transport();
+                }
+            }
+            COMMON_QUEUE_SELECT => self.queue_select = data as u16,
+            COMMON_QUEUE_SIZE => {
+                if let Some(index) = self.select_queue() {
+                    self.queues[index].size = data as u16;
+                }
+            }
+            COMMON_QUEUE_ENABLE => {
+                if let Some(index) = self.select_queue() {
+                    let queue = self.queues[index];
+                    self.mmio.write(MMIO_QUEUE_NUM, queue.size as u32);
+                    let regs = [
+                        MMIO_QUEUE_DESC_LOW,
+                        MMIO_QUEUE_DRIVER_LOW,
+                        MMIO_QUEUE_DEVICE_LOW,
+                    ];
+                    for (reg, addr) in regs.iter().zip(queue.addrs.iter()) {
+                        self.mmio.write(*reg, *addr as u32);
+                        self.mmio.write(*reg + 4, (*addr >> 32) as u32);
+                    }
+                    self.mmio.write(MMIO_QUEUE_READY, data32 & 0x1);
+                }
+            }
+            COMMON_QUEUE_DESC..=COMMON_QUEUE_DEVICE_HIGH => {
+                if let Some(index) = self.select_queue() {
+                    let addr = &mut self.queues[index].addrs[Self::get_area_index(offset)];
+                    *addr = match offset % 8 {
+                        0 => (*addr & !0xffff_ffff) | data32 as u64,
+                        _ => (*addr & 0xffff_ffff) | (data32 as u64) << 32,
+                    };
+                }
+            }
+            // MSI-X is not supported, the vectors stay VIRTIO_MSI_NO_VECTOR.
+            _ => {}
+        }
+    }
+}
+
+impl PciDevice for VirtioPci {
+    fn get_vendor_id(&self) -> u16 {
+        VIRTIO_PCI_VENDOR_ID
+    }
+
+    fn get_device_id(&self) -> u16 {
+        VIRTIO_PCI_DEVICE_ID_BASE + self.get_subsystem_id()
+    }
+
+    fn get_revision(&self) -> u8 {
+        1
+    }
+
+    fn get_class_code(&self) -> u32 {
+        match self.get_subsystem_id() as u32 {
+            VIRTIO_ID_BLOCK => PCI_CLASS_STORAGE_OTHER,
+            VIRTIO_ID_NET => PCI_CLASS_NETWORK_ETHERNET,
+            _ => PCI_CLASS_OTHER,
+        }
+    }
+
+    fn get_subsystem_vendor_id(&self) -> u16 {
+        VIRTIO_PCI_VENDOR_ID
+    }
+
+    /// Virtio device ID.
+    fn get_subsystem_id(&self) -> u16 {
+        self.device_id
+    }
+
+    fn get_bar_sizes(&self) -> Vec<u64> {
+        vec![VIRTIO_PCI_BAR_SIZE]
+    }
+
+    fn get_capabilities(&self) -> Vec<(u8, Vec<u8>)> {
+        // struct virtio_pci_cap after the ID and the next pointer.
+        let cap = |cfg_type: u8, offset: u64, extra: &[u8]| {
+            let mut data = vec![16 + extra.len() as u8, cfg_type, 0, 0, 0, 0];
+            data.extend_from_slice(&(offset as u32).to_le_bytes());
+            data.extend_from_slice(&(VIRTIO_PCI_CFG_SIZE as u32).to_le_bytes());
+            data.extend_from_slice(extra);
+            (PCI_CAP_ID_VENDOR, data)
+        };
+        vec![
+            cap(VIRTIO_PCI_CAP_COMMON_CFG, VIRTIO_PCI_COMMON_CFG, &[]),
+            cap(
+                VIRTIO_PCI_CAP_NOTIFY_CFG,
+                VIRTIO_PCI_NOTIFY_CFG,
+                &VIRTIO_PCI_NOTIFY_OFF_MULTIPLIER.to_le_bytes(),
+            ),
+            cap(VIRTIO_PCI_CAP_ISR_CFG, VIRTIO_PCI_ISR_CFG, &[]),
+            cap(VIRTIO_PCI_CAP_DEVICE_CFG, VIRTIO_PCI_DEVICE_CFG, &[]),
+        ]
+    }
+
+    fn read_bar(&mut self, _bar: usize, offset: u64, size: usize) -> u64 {
+        let region = offset & !(VIRTIO_PCI_CFG_SIZE - 1);
+        let offset = offset & (VIRTIO_PCI_CFG_SIZE - 1);
+        let data = match region {
+            VIRTIO_PCI_COMMON_CFG if offset < COMMON_CFG_SIZE => {
+                // fields are accessed with their own size.
+                let data = self.read_common(offset);
+                match size {
+                    8 => data | self.read_common(offset + 4) << 32,
+                    _ => data,
+                }
+            }
+            VIRTIO_PCI_ISR_CFG if offset == 0 => {
+                // reading the ISR status acknowledges the interrupt.
+                let status = self.mmio.read(MMIO_INTERRUPT_STATUS);
+                self.mmio.write(MMIO_INTERRUPT_ACK, status);
+                status as u64
+            }
+            VIRTIO_PCI_DEVICE_CFG => {
+                let low = self.mmio.read(MMIO_CONFIG_SPACE + offset) as u64;
+                match size {
+                    8 => low | (self.mmio.read(MMIO_CONFIG_SPACE + offset + 4) as u64) << 32,
+                    _ => low,
+                }
+            }
+            _ => 0,
+        };
+        match size {
+            8 => data,
+            _ => data & ((1 << (size * 8)) - 1),
+        }
+    }
+
+    fn write_bar(&mut self, _bar: usize, offset: u64, data: u64, size: usize) {
+        let region = offset & !(VIRTIO_PCI_CFG_SIZE - 1);
+        let offset = offset & (VIRTIO_PCI_CFG_SIZE - 1);
+        match region {
+            VIRTIO_PCI_COMMON_CFG if offset < COMMON_CFG_SIZE => {
+                self.write_common(offset, data);
+                if size == 8 {
+                    self.write_common(offset + 4, data >> 32);
+                }
+            }
+            VIRTIO_PCI_DEVICE_CFG => {
+                for i in 0..size as u64 {
+                    self.mmio
+                        .write8(MMIO_CONFIG_SPACE + offset + i, (data >> (i * 8)) as u8);
+                }
+            }
+            VIRTIO_PCI_NOTIFY_CFG => {
+                let index = offset / VIRTIO_PCI_NOTIFY_OFF_MULTIPLIER as u64;
+                self.mmio.write(MMIO_QUEUE_NOTIFY, index as u32);
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, dram: &mut Memory) {
+        self.mmio.tick(dram);
+    }
+
+    fn is_irq(&mut self) -> bool {
+        self.mmio.is_irq()
+    }
+
+    fn reset(&mut self) {
+        self.mmio.reset();
+        self.reset_transport();
+    }
+}
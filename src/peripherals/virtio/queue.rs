@@ -0,0 +1,359 @@
+// Virtqueue (split virtqueue)
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-230005
+
+use crate::peripherals::memory::Memory;
+
+pub const DESCRIPTOR_SIZE: u64 = 16;
+
+// Descriptor flags
+pub const VRING_DESC_F_NEXT: u16 = 0x1;
+pub const VRING_DESC_F_WRITE: u16 = 0x2;
+pub const VRING_DESC_F_INDIRECT: u16 = 0x4;
+
+// Available ring flags
+pub const VRING_AVAIL_F_NO_INTERRUPT: u16 = 0x1;
+
+/// Main memory seen from devices, addressed by guest physical address.
+/// Accesses out of main memory read zeros and drop writes, so a bad address
+/// from the guest cannot crash the host.
+pub struct GuestMemory<'a> {
+    dram: &'a mut Memory,
+    base: u64,
+}
+
+impl<'a> GuestMemory<'a> {
+    pub fn new(dram: &'a mut Memory, base: u64) -> Self {
+        GuestMemory { dram, base }
+    }
+
+    fn offset(&self, addr: u64, len: usize) -> Result<usize, ()> {
+        let offset = addr.wrapping_sub(self.base) as usize;
+        match offset.checked_add(len) {
+            Some(end) if end <= self.dram.mem.len() => Ok(offset),
+            _ => Err(()),
+        }
+    }
+
+    /// Whether the range is in main memory.
+    pub fn contains(&self, addr: u64, len: usize) -> bool {
+        self.offset(addr, len).is_ok()
+    }
+
+    pub fn read16(&self, addr: u64) -> u16 {
+        match self.offset(addr, 2) {
+            Ok(offset) => self.dram.read16(offset as u64),
+            Err(()) => 0,
+        }
+    }
+
+    pub fn read32(&self, addr: u64) -> u32 {
+        match self.offset(addr, 4) {
+            Ok(offset) => self.dram.read32(offset as u64),
+            Err(()) => 0,
+        }
+    }
+
+    pub fn read64(&self, addr: u64) -> u64 {
+        match self.offset(addr, 8) {
+            Ok(offset) => self.dram.read64(offset as u64),
+            Err(()) => 0,
+        }
+    }
+
+    pub fn write16(&mut self, addr: u64, data: u16) {
+        if let Ok(offset) = self.offset(addr, 2) {
+            self.dram.write16(offset as u64, data)
+        }
+    }
+
+    pub fn write32(&mut self, addr: u64, data: u32) {
+        if let Ok(offset) = self.offset(addr, 4) {
+            self.dram.write32(offset as u64, data)
+        }
+    }
+
+    pub fn read_bytes(&self, addr: u64, data: &mut [u8]) {
+        match self.offset(addr, data.len()) {
+            Ok(offset) => data.copy_from_slice(&self.dram.mem[offset..offset + data.len()]),
+            Err(()) => data.fill(0),
+        }
+    }
+
+    pub fn write_bytes(&mut self, addr: u64, data: &[u8]) {
+        if let Ok(offset) = self.offset(addr, data.len()) {
+            self.dram.mem[offset..offset + data.len()].copy_from_slice(data);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+impl Descriptor {
+    pub fn is_write_only(&self) -> bool {
+        self.flags & VRING_DESC_F_WRITE != 0
+    }
+}
+
+/// Buffers of a request, in chain order.
+pub struct DescriptorChain {
+    /// index of the head descriptor, returned in the used ring.
+    pub head: u16,
+    pub descriptors: Vec<Descriptor>,
+}
+
+impl DescriptorChain {
+    /// Total length of the device readable buffers.
+    pub fn get_readable_len(&self) -> usize {
+        self.descriptors
+            .iter()
+            .filter(|d| !d.is_write_only())
+            .map(|d| d.len as usize)
+            .sum()
+    }
+
+    /// Total length of the device writable buffers.
+    pub fn get_writable_len(&self) -> usize {
+        self.descriptors
+            .iter()
+            .filter(|d| d.is_write_only())
+            .map(|d| d.len as usize)
+            .sum()
+    }
+
+    /// Gather the device readable buffers.
+    pub fn read_all(&self, mem: &GuestMemory) -> Vec<u8> {
+        let mut data = vec![0; self.get_readable_len()];
+        let mut pos = 0;
+        for d in self.descriptors.iter().filter(|d| !d.is_write_only()) {
+            mem.read_bytes(d.addr, &mut data[pos..pos + d.len as usize]);
+            pos += d.len as usize;
+        }
+        data
+    }
+
+    /// Scatter data into the device writable buffers starting at the offset.
+    /// Returns the number of bytes written.
+    pub fn write_at(&self, mem: &mut GuestMemory, offset: usize, data: &[u8]) -> usize {
+        let mut skip = offset;
+        let mut written = 0;
+        for d in self.descriptors.iter().filter(|d| d.is_write_only()) {
+            let len = d.len as usize;
+            if skip >= len {
+                skip -= len;
+                continue;
+            }
+            let n = (len - skip).min(data.len() - written);
+            mem.write_bytes(d.addr + skip as u64, &data[written..written + n]);
+            written += n;
+            skip = 0;
+            if written == data.len() {
+                break;
+            }
+        }
+        written
+    }
+}
+
+/// Driver configured state of a virtqueue.
+pub struct Queue {
+    /// queue size (number of descriptors).
+    pub num: u32,
+    pub ready: bool,
+    /// guest physical addresses of the descriptor table, available and used rings.
+    pub desc_addr: u64,
+    pub avail_addr: u64,
+    pub used_addr: u64,
+    /// VIRTIO_F_EVENT_IDX was negotiated: used_event and avail_event
+    /// suppress notifications instead of the ring flags.
+    pub event_idx: bool,
+    /// next index of the available ring to process.
+    last_avail_idx: u16,
+    /// next index of the used ring to fill.
+    used_idx: u16,
+    /// index of the used ring when the driver was last notified.
+    signalled_used_idx: u16,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Queue {
+            num: 0,
+            ready: false,
+            desc_addr: 0,
+            avail_addr: 0,
+            used_addr: 0,
+            event_idx: false,
+            last_avail_idx: 0,
+            used_idx: 0,
+            signalled_used_idx: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Queue::new();
+    }
+
+    /// Set the ring addresses from a legacy page frame number: the descriptor
+    /// table, the available ring and the used ring (aligned) are contiguous.
+    pub fn set_legacy_layout(&mut self, pfn: u32, page_size: u32, align: u32) {
+        let num = self.num as u64;
+        let align = (align as u64).max(1);
+        self.desc_addr = pfn as u64 * page_size as u64;
+        self.avail_addr = self.desc_addr + DESCRIPTOR_SIZE * num;
+        // flags, idx, ring[num], used_event
+        let avail_end = self.avail_addr + 6 + 2 * num;
+        self.used_addr = avail_end.div_ceil(align) * align;
+        self.ready = pfn != 0;
+    }
+
+    /// Whether the driver made buffers available which are not processed yet.
+    pub fn has_available(&self, mem: &GuestMemory) -> bool {
+        self.ready
+            && self.num != 0
+            && mem.read16(self.avail_addr.wrapping_add(2)) != self.last_avail_idx
+    }
+
+    /// Take the next request made available by the driver. A chain with a
+    /// descriptor out of main memory fails and is returned to the driver
+    /// with no data written.
+    pub fn pop(&mut self, mem: &mut GuestMemory) -> Option<DescriptorChain> {
+        while self.has_available(mem) {
+            let head = self.pop_head(mem);
+            match self.get_descriptors(mem, head) {
+                Ok(descriptors) => return Some(DescriptorChain { head, descriptors }),
+                Err(()) => self.push(mem, head, 0),
+            }
+        }
+        None
+    }
+
+    /// Index of the head descriptor of the next available chain.
+    fn pop_head(&mut self, mem: &mut GuestMemory) -> u16 {
+        /* Available Ring
+         * ----------------
+         * u16 flags
+         * u16 idx
+         * u16[QUEUE_NUM] ring
+         * u16 used_event
+         */
+        let slot = self.last_avail_idx as u64 % self.num as u64;
+        let head = mem.read16(self.avail_addr.wrapping_add(4 + slot * 2));
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+        if self.event_idx {
+            // the driver notifies again once it makes the next buffer available.
+            let avail_event = self.used_addr.wrapping_add(4 + 8 * self.num as u64);
+            mem.write16(avail_event, self.last_avail_idx);
+        }
+        head
+    }
+
+    /// Descriptors of the chain from the head.
+    fn get_descriptors(&self, mem: &GuestMemory, head: u16) -> Result<Vec<Descriptor>, ()> {
+        let mut descriptors = Vec::new();
+        let mut idx = head;
+        // a malformed chain must not hang the emulator.
+        for _ in 0..self.num {
+            let descriptor = self.get_descriptor(mem, self.desc_addr, self.num, idx)?;
+            if descriptor.flags & VRING_DESC_F_INDIRECT != 0 {
+                // the descriptor refers to a table holding the whole chain.
+                let count = descriptor.len / DESCRIPTOR_SIZE as u32;
+                let mut idx = 0;
+                for _ in 0..count {
+                    let descriptor = self.get_descriptor(mem, descriptor.addr, count, idx)?;
+                    descriptors.push(descriptor);
+                    if descriptor.flags & VRING_DESC_F_NEXT == 0 {
+                        break;
+                    }
+                    idx = descriptor.next;
+                }
+                break;
+            }
+            descriptors.push(descriptor);
+            if descriptor.flags & VRING_DESC_F_NEXT == 0 {
+                break;
+            }
+            idx = descriptor.next;
+        }
+        Ok(descriptors)
+    }
+
+    /// Return a processed request to the driver.
+    pub fn push(&mut self, mem: &mut GuestMemory, head: u16, len: u32) {
+        /* Used Ring
+         * ----------------
+         * u16 flags
+         * u16 idx
+         * UsedRingEntry[QUEUE_NUM] ring (u32 id, u32 len)
+         * u16 avail_event
+         */
+        let slot = self.used_idx as u64 % self.num as u64;
+        let entry = self.used_addr.wrapping_add(4 + slot * 8);
+        mem.write32(entry, head as u32);
+        mem.write32(entry.wrapping_add(4), len);
+        self.used_idx = self.used_idx.wrapping_add(1);
+        mem.write16(self.used_addr.wrapping_add(2), self.used_idx);
+    }
+
+    /// Whether the driver wants an interrupt for the buffers used since the
+    /// last time it was notified.
+    pub fn needs_notification(&mut self, mem: &GuestMemory) -> bool {
+        if !self.ready || self.num == 0 || self.used_idx == self.signalled_used_idx {
+            return false;
+        }
+        let old = self.signalled_used_idx;
+        let new = self.used_idx;
+        self.signalled_used_idx = new;
+        match self.event_idx {
+            true => {
+                // notify if used_event is in the newly used entries.
+                let used_event = mem.read16(self.avail_addr.wrapping_add(4 + 2 * self.num as u64));
+                new.wrapping_sub(used_event).wrapping_sub(1) < new.wrapping_sub(old)
+            }
+            false => mem.read16(self.avail_addr) & VRING_AVAIL_F_NO_INTERRUPT == 0,
+        }
+    }
+
+    /// Descriptor of the table (the descriptor table or an indirect table).
+    /// An error if the entry or its buffer is out of main memory.
+    fn get_descriptor(
+        &self,
+        mem: &GuestMemory,
+        table: u64,
+        size: u32,
+        idx: u16,
+    ) -> Result<Descriptor, ()> {
+        /* Descriptor entiry
+         * -----------------
+         * u64 addr
+         * u32 len
+         * u16 flags
+         * u16 next
+         */
+        let entry = table.wrapping_add(DESCRIPTOR_SIZE * (idx as u64 % size as u64));
+        if !mem.contains(entry, DESCRIPTOR_SIZE as usize) {
+            return Err(());
+        }
+        let descriptor = Descriptor {
+            addr: mem.read64(entry),
+            len: mem.read32(entry + 8),
+            flags: mem.read16(entry + 12),
+            next: mem.read16(entry + 14),
+        };
+        match mem.contains(descriptor.addr, descriptor.len as usize) {
+            true => Ok(descriptor),
+            false => Err(()),
+        }
+    }
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
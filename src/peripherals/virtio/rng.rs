@@ -0,0 +1,102 @@
+// Virtio Entropy Device
+// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-2760004
+
+use crate::peripherals::virtio::queue::{GuestMemory, Queue};
+use crate::peripherals::virtio::{VirtioDevice, VIRTIO_ID_RNG};
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+/// Source of the random bytes handed to the guest.
+pub trait EntropySource {
+    fn fill(&mut self, data: &mut [u8]);
+}
+
+/// Cryptographically secure random numbers of the host.
+pub struct HostEntropy {
+    urandom: File,
+}
+
+impl HostEntropy {
+    pub fn new() -> io::Result<Self> {
+        Ok(HostEntropy {
+            urandom: File::open("/dev/urandom")?,
+        })
+    }
+}
+
+impl EntropySource for HostEntropy {
+    fn fill(&mut self, data: &mut [u8]) {
+        if let Err(why) = self.urandom.read_exact(data) {
+            panic!("Failed to read /dev/urandom: {}", why);
+        }
+    }
+}
+
+/// Deterministic pseudo random numbers (SplitMix64) for reproducible runs.
+pub struct SeededEntropy {
+    state: u64,
+}
+
+impl SeededEntropy {
+    pub fn new(seed: u64) -> Self {
+        SeededEntropy { state: seed }
+    }
+}
+
+impl EntropySource for SeededEntropy {
+    fn fill(&mut self, data: &mut [u8]) {
+        for chunk in data.chunks_mut(8) {
+            self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+pub struct VirtioRng {
+    source: Box<dyn EntropySource>,
+}
+
+impl VirtioRng {
+    pub fn new(source: Box<dyn EntropySource>) -> Self {
+        VirtioRng { source }
+    }
+}
+
+impl VirtioDevice for VirtioRng {
+    fn get_device_id(&self) -> u32 {
+        VIRTIO_ID_RNG
+    }
+
+    fn get_features(&self) -> u64 {
+        0
+    }
+
+    fn get_queue_count(&self) -> usize {
+        1
+    }
+
+    fn read_config(&self, _offset: u64) -> u8 {
+        0
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: u8) {}
+
+    fn process_queue(&mut self, index: usize, queues: &mut [Queue], mem: &mut GuestMemory) -> bool {
+        let queue = &mut queues[index];
+        let mut used = false;
+        while let Some(chain) = queue.pop(mem) {
+            let mut data = vec![0; chain.get_writable_len()];
+            self.source.fill(&mut data);
+            let written = chain.write_at(mem, 0, &data);
+            queue.push(mem, chain.head, written as u32);
+            used = true;
+        }
+        used
+    }
+}
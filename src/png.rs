@@ -0,0 +1,89 @@
+// PNG encoder for the screenshots, without compression: the image data is
+// stored in uncompressed deflate blocks.
+// https://www.w3.org/TR/png/
+// https://www.rfc-editor.org/rfc/rfc1950 (zlib), rfc1951 (deflate)
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// IHDR
+const PNG_BIT_DEPTH: u8 = 8;
+const PNG_COLOR_TYPE_RGB: u8 = 2;
+
+/// Maximum length of a stored deflate block.
+const DEFLATE_STORED_MAX: usize = 0xffff;
+
+lazy_static! {
+    static ref CRC32_TABLE: Vec<u32> = (0..256)
+        .map(|n| (0..8).fold(n as u32, |c, _| match c & 1 {
+            1 => 0xedb8_8320 ^ (c >> 1),
+            _ => c >> 1,
+        }))
+        .collect();
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+/// Data in the zlib format with stored blocks.
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01]; // deflate, 32K window, no dictionary
+    let mut blocks = data.chunks(DEFLATE_STORED_MAX).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8); // BFINAL, BTYPE 00
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Encode an image of 8 bits RGB pixels, row by row, as a PNG file.
+pub fn encode_rgb(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(width * height * 3, rgb.len());
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // compression, filter and interlace methods are all 0.
+    header.extend_from_slice(&[PNG_BIT_DEPTH, PNG_COLOR_TYPE_RGB, 0, 0, 0]);
+
+    // each row starts with the filter type, 0 (none).
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    if width > 0 {
+        for row in rgb.chunks(width * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+    }
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_store(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
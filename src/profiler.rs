@@ -0,0 +1,283 @@
+// Guest Profiler
+// Exact mode counts every retired instruction by its PC. Sampling mode records
+// the guest call stack every N retired instructions by walking the frame pointer
+// chain (s0/fp) and the return address register (ra).
+// Results are symbolized with the ELF symbol table and emitted as folded stacks
+// (https://github.com/brendangregg/FlameGraph) or as a per-function hot list.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+use crate::cpu::cpu::{Cpu, Xlen};
+use crate::elf_loader::Symbol;
+
+const MAX_STACK_DEPTH: usize = 64;
+
+const REG_RA: usize = 1;
+const REG_FP: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ProfilerMode {
+    /// Count every retired instruction by its PC.
+    Exact,
+    /// Sample the call stack every N retired instructions (never if N is 0).
+    Sampling(u64),
+}
+
+pub struct HotFunction {
+    pub name: String,
+    /// Samples (or instructions) spent in the function itself.
+    pub self_count: u64,
+    /// Samples (or instructions) spent in the function and its callees.
+    pub total_count: u64,
+}
+
+/// Address sorted symbol table for symbolization.
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|s| s.addr);
+        SymbolTable { symbols }
+    }
+
+    pub fn lookup(&self, addr: u64) -> Option<&Symbol> {
+        let idx = match self.symbols.binary_search_by_key(&addr, |s| s.addr) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let symbol = &self.symbols[idx];
+        let is_last = idx == self.symbols.len() - 1;
+        // symbols without size (e.g. assembler labels) cover up to the next symbol,
+        // except the last one such as _end which marks the end of the image.
+        let covered = match symbol.size {
+            0 => !is_last || addr == symbol.addr,
+            size => addr < symbol.addr.wrapping_add(size),
+        };
+        match covered {
+            true => Some(symbol),
+            false => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn name(&self, addr: u64) -> String {
+        match self.lookup(addr) {
+            Some(symbol) => symbol.name.clone(),
+            None => format!("0x{:x}", addr),
+        }
+    }
+}
+
+pub struct Profiler {
+    mode: ProfilerMode,
+    /// retired instruction count since the profiler was enabled.
+    retired: u64,
+    /// retired instruction count by PC (exact mode).
+    pc_counts: HashMap<u64, u64>,
+    /// sampled call stacks, leaf first (sampling mode).
+    stacks: HashMap<Vec<u64>, u64>,
+}
+
+impl Profiler {
+    pub fn new(mode: ProfilerMode) -> Self {
+        Profiler {
+            mode,
+            retired: 0,
+            pc_counts: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    pub fn get_mode(&self) -> ProfilerMode {
+        self.mode
+    }
+
+    pub fn get_retired(&self) -> u64 {
+        self.retired
+    }
+
+    /// called once after every CPU tick. ECALL and EBREAK trap instead of
+    /// retiring, like for minstret, so the count matches the guest's instret.
+    pub fn tick(&mut self, cpu: &mut Cpu) {
+        let pc = match cpu.retired_pc {
+            Some(pc) => pc,
+            None => return,
+        };
+        self.retired = self.retired.wrapping_add(1);
+
+        match self.mode {
+            ProfilerMode::Exact => *self.pc_counts.entry(pc).or_insert(0) += 1,
+            ProfilerMode::Sampling(period) => {
+                if period != 0 && self.retired.is_multiple_of(period) {
+                    let stack = self.unwind(cpu, pc);
+                    *self.stacks.entry(stack).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    /// Walk the frame pointer chain. The standard RISC-V frame layout (with
+    /// -fno-omit-frame-pointer) keeps the return address at fp - XLEN/8 and the
+    /// previous frame pointer at fp - 2 * XLEN/8. The frames are read without
+    /// side effects on the guest, and the walk stops out of RAM.
+    fn unwind(&self, cpu: &mut Cpu, pc: u64) -> Vec<u64> {
+        let word = match cpu.xlen {
+            Xlen::X32 => 4,
+            Xlen::X64 => 8,
+        };
+        let mut stack = vec![pc];
+
+        // The leaf function may not have built its own frame yet, so ra is the
+        // only trace of its caller.
+        let ra = cpu.x[REG_RA] as u64;
+        if ra != 0 {
+            stack.push(ra.wrapping_sub(1));
+        }
+
+        let mut fp = cpu.x[REG_FP] as u64;
+        while stack.len() < MAX_STACK_DEPTH && fp != 0 && fp.is_multiple_of(word) {
+            let (ret, prev_fp) = match cpu.xlen {
+                Xlen::X32 => match (
+                    cpu.mmu.debug_read32(fp.wrapping_sub(4)),
+                    cpu.mmu.debug_read32(fp.wrapping_sub(8)),
+                ) {
+                    (Some(ret), Some(prev_fp)) => (ret as u64, prev_fp as u64),
+                    _ => break,
+                },
+                Xlen::X64 => match (
+                    cpu.mmu.debug_read64(fp.wrapping_sub(8)),
+                    cpu.mmu.debug_read64(fp.wrapping_sub(16)),
+                ) {
+                    (Some(ret), Some(prev_fp)) => (ret, prev_fp),
+                    _ => break,
+                },
+            };
+            if ret == 0 {
+                break;
+            }
+            // return addresses point after the call, so step back into it.
+            let caller = ret.wrapping_sub(1);
+            if stack.last() != Some(&caller) {
+                stack.push(caller);
+            }
+            // the stack grows downward, so older frames live at higher addresses.
+            if prev_fp <= fp {
+                break;
+            }
+            fp = prev_fp;
+        }
+        stack
+    }
+
+    /// Collapse the recorded samples to function level stacks (root first).
+    fn collapse(&self, symbols: &SymbolTable) -> HashMap<Vec<String>, u64> {
+        let mut folded: HashMap<Vec<String>, u64> = HashMap::new();
+        match self.mode {
+            ProfilerMode::Exact => {
+                for (pc, count) in self.pc_counts.iter() {
+                    *folded.entry(vec![symbols.name(*pc)]).or_insert(0) += count;
+                }
+            }
+            ProfilerMode::Sampling(_) => {
+                for (stack, count) in self.stacks.iter() {
+                    let mut names: Vec<String> = Vec::new();
+                    for (i, addr) in stack.iter().enumerate().rev() {
+                        // callers which can not be symbolized are most likely
+                        // garbage read from a register or a stack without frame.
+                        if i != 0 && !symbols.is_empty() && symbols.lookup(*addr).is_none() {
+                            continue;
+                        }
+                        let name = symbols.name(*addr);
+                        // drop the duplicate frame that ra or a not yet pushed
+                        // frame pointer introduces.
+                        if names.last() != Some(&name) {
+                            names.push(name);
+                        }
+                    }
+                    *folded.entry(names).or_insert(0) += count;
+                }
+            }
+        }
+        folded
+    }
+
+    /// Write folded stacks ("root;caller;leaf count" per line) consumable by
+    /// flamegraph.pl and inferno.
+    pub fn write_folded(&self, symbols: &SymbolTable, w: &mut dyn Write) -> io::Result<()> {
+        let mut lines: Vec<(String, u64)> = self
+            .collapse(symbols)
+            .into_iter()
+            .map(|(names, count)| (names.join(";"), count))
+            .collect();
+        lines.sort();
+        for (stack, count) in lines {
+            writeln!(w, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+
+    /// Functions sorted by self count, then by total count.
+    pub fn hot_list(&self, symbols: &SymbolTable) -> Vec<HotFunction> {
+        let mut functions: HashMap<String, HotFunction> = HashMap::new();
+        for (names, count) in self.collapse(symbols) {
+            let mut seen: Vec<&String> = Vec::new();
+            for name in names.iter() {
+                // recursive functions count only once per sample.
+                if seen.contains(&name) {
+                    continue;
+                }
+                seen.push(name);
+                let function = functions.entry(name.clone()).or_insert(HotFunction {
+                    name: name.clone(),
+                    self_count: 0,
+                    total_count: 0,
+                });
+                function.total_count += count;
+            }
+            if let Some(leaf) = names.last() {
+                functions.get_mut(leaf).unwrap().self_count += count;
+            }
+        }
+
+        let mut list: Vec<HotFunction> = functions.into_values().collect();
+        list.sort_by(|a, b| {
+            b.self_count
+                .cmp(&a.self_count)
+                .then(b.total_count.cmp(&a.total_count))
+                .then(a.name.cmp(&b.name))
+        });
+        list
+    }
+
+    /// Print the hot list as a table.
+    pub fn write_hot_list(
+        &self,
+        symbols: &SymbolTable,
+        max_entries: usize,
+        w: &mut dyn Write,
+    ) -> io::Result<()> {
+        let list = self.hot_list(symbols);
+        let samples: u64 = list.iter().map(|f| f.self_count).sum();
+        writeln!(w, "{:>8} {:>12} {:>12}  function", "self%", "self", "total")?;
+        for function in list.iter().take(max_entries) {
+            let ratio = match samples {
+                0 => 0.0,
+                n => function.self_count as f64 * 100.0 / n as f64,
+            };
+            writeln!(
+                w,
+                "{:>7.2}% {:>12} {:>12}  {}",
+                ratio, function.self_count, function.total_count, function.name
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+// Execution Statistics
+// Instruction mix, privilege-mode residency, trap counts and memory system
+// counters for ISA-extension trade-off studies.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+const EXCEPTION_NAMES: [&str; 16] = [
+    "instruction address misaligned",
+    "instruction access fault",
+    "illegal instruction",
+    "breakpoint",
+    "load address misaligned",
+    "load access fault",
+    "store address misaligned",
+    "store access fault",
+    "environment call from U-mode",
+    "environment call from S-mode",
+    "reserved",
+    "environment call from M-mode",
+    "instruction page fault",
+    "load page fault",
+    "reserved",
+    "store page fault",
+];
+
+const INTERRUPT_NAMES: [&str; 12] = [
+    "user software",
+    "supervisor software",
+    "reserved",
+    "machine software",
+    "user timer",
+    "supervisor timer",
+    "reserved",
+    "machine timer",
+    "user external",
+    "supervisor external",
+    "reserved",
+    "machine external",
+];
+
+const PRIVILEGE_NAMES: [&str; 4] = ["User", "Supervisor", "Hypervisor", "Machine"];
+
+/// Counters collected by the CPU.
+pub struct CpuStats {
+    /// execution count by mnemonic, including instructions which raised an exception.
+    pub mnemonics: HashMap<&'static str, u64>,
+    /// 16-bit (RVC) instructions.
+    pub compressed: u64,
+    /// 32-bit instructions.
+    pub full_width: u64,
+    /// ticks spent in each privilege mode (indexed by Privilege).
+    pub privilege_ticks: [u64; 4],
+    /// ticks spent stalled by WFI.
+    pub wfi_ticks: u64,
+    /// taken exceptions by cause.
+    pub exceptions: [u64; 16],
+    /// taken interrupts by cause.
+    pub interrupts: [u64; 16],
+}
+
+impl CpuStats {
+    pub fn new() -> Self {
+        CpuStats {
+            mnemonics: HashMap::new(),
+            compressed: 0,
+            full_width: 0,
+            privilege_ticks: [0; 4],
+            wfi_ticks: 0,
+            exceptions: [0; 16],
+            interrupts: [0; 16],
+        }
+    }
+
+    pub fn get_instructions(&self) -> u64 {
+        self.compressed + self.full_width
+    }
+}
+
+impl Default for CpuStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counters collected by the MMU.
+pub struct MemoryStats {
+    /// address translations which walked the page table. The MMU has no TLB,
+    /// so every translation in Sv32/Sv39 is a TLB miss.
+    pub page_walks: u64,
+    /// page table entries read while walking.
+    pub pte_reads: u64,
+    /// memory mapped I/O (reads, writes) by device name.
+    pub mmio: HashMap<&'static str, (u64, u64)>,
+}
+
+impl MemoryStats {
+    pub fn new() -> Self {
+        MemoryStats {
+            page_walks: 0,
+            pte_reads: 0,
+            mmio: HashMap::new(),
+        }
+    }
+
+    pub fn count_mmio(&mut self, device: &'static str, is_write: bool) {
+        let counts = self.mmio.entry(device).or_insert((0, 0));
+        match is_write {
+            true => counts.1 += 1,
+            false => counts.0 += 1,
+        }
+    }
+}
+
+impl Default for MemoryStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn percent(n: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        t => n as f64 * 100.0 / t as f64,
+    }
+}
+
+/// Print all counters as a human readable report.
+pub fn write_report(cpu: &CpuStats, memory: &MemoryStats, w: &mut dyn Write) -> io::Result<()> {
+    let instructions = cpu.get_instructions();
+    writeln!(w, "==== Execution statistics ====")?;
+    writeln!(w, "instructions: {}", instructions)?;
+    writeln!(
+        w,
+        "  compressed (16-bit): {} ({:.2}%)",
+        cpu.compressed,
+        percent(cpu.compressed, instructions)
+    )?;
+    writeln!(
+        w,
+        "  full-width (32-bit): {} ({:.2}%)",
+        cpu.full_width,
+        percent(cpu.full_width, instructions)
+    )?;
+
+    writeln!(w, "instruction mix:")?;
+    let mut mnemonics: Vec<(&&'static str, &u64)> = cpu.mnemonics.iter().collect();
+    mnemonics.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    for (mnemonic, count) in mnemonics {
+        writeln!(
+            w,
+            "  {:<12} {:>14} {:>7.2}%",
+            mnemonic,
+            count,
+            percent(*count, instructions)
+        )?;
+    }
+
+    let ticks: u64 = cpu.privilege_ticks.iter().sum();
+    writeln!(w, "privilege mode residency (ticks):")?;
+    for (i, name) in PRIVILEGE_NAMES.iter().enumerate() {
+        writeln!(
+            w,
+            "  {:<12} {:>14} {:>7.2}%",
+            name,
+            cpu.privilege_ticks[i],
+            percent(cpu.privilege_ticks[i], ticks)
+        )?;
+    }
+    writeln!(
+        w,
+        "  {:<12} {:>14} {:>7.2}%",
+        "(wfi)",
+        cpu.wfi_ticks,
+        percent(cpu.wfi_ticks, ticks)
+    )?;
+
+    writeln!(w, "exceptions:")?;
+    for (cause, count) in cpu.exceptions.iter().enumerate() {
+        if *count > 0 {
+            writeln!(
+                w,
+                "  {:>2} {:<32} {:>10}",
+                cause, EXCEPTION_NAMES[cause], count
+            )?;
+        }
+    }
+    writeln!(w, "interrupts:")?;
+    for (cause, count) in cpu.interrupts.iter().enumerate() {
+        if *count > 0 {
+            let name = INTERRUPT_NAMES.get(cause).unwrap_or(&"reserved");
+            writeln!(w, "  {:>2} {:<32} {:>10}", cause, name, count)?;
+        }
+    }
+
+    writeln!(w, "memory:")?;
+    writeln!(w, "  page walks (TLB misses): {}", memory.page_walks)?;
+    writeln!(w, "  PTE reads:               {}", memory.pte_reads)?;
+    writeln!(w, "MMIO accesses:")?;
+    let mut devices: Vec<(&&'static str, &(u64, u64))> = memory.mmio.iter().collect();
+    devices.sort_by(|a, b| a.0.cmp(b.0));
+    for (device, (reads, writes)) in devices {
+        writeln!(
+            w,
+            "  {:<12} reads: {:>12} writes: {:>12}",
+            device, reads, writes
+        )?;
+    }
+    Ok(())
+}
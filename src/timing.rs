@@ -0,0 +1,258 @@
+// Timing Model
+// By default every instruction takes one cycle. A timing model replaces that
+// with the number of cycles an instruction occupies the pipeline, and the bus
+// (CLINT, UART, ...) is clocked once per cycle so that mcycle and mtime keep
+// the relationship they have on hardware.
+// PipelineModel approximates the 5-stage single-issue in-order pipelines of the
+// SiFive E31 and U54 cores.
+// https://sifive.cdn.prismic.io/sifive/c89f6e5a-cf9e-44c3-a3db-04420702dcc1_sifive_e31_manual_v19.08.pdf
+// https://static.dev.sifive.com/FU540-C000-v1.0.pdf
+
+const OPCODE_LOAD: u32 = 0x03;
+const OPCODE_LOAD_FP: u32 = 0x07;
+const OPCODE_AUIPC: u32 = 0x17;
+const OPCODE_STORE: u32 = 0x23;
+const OPCODE_STORE_FP: u32 = 0x27;
+const OPCODE_AMO: u32 = 0x2f;
+const OPCODE_OP: u32 = 0x33;
+const OPCODE_LUI: u32 = 0x37;
+const OPCODE_OP_32: u32 = 0x3b;
+const OPCODE_BRANCH: u32 = 0x63;
+const OPCODE_JALR: u32 = 0x67;
+const OPCODE_JAL: u32 = 0x6f;
+
+const FUNCT7_MULDIV: u32 = 0x01;
+
+const REG_ZERO: u32 = 0;
+const REG_RA: u32 = 1;
+const REG_T0: u32 = 5;
+
+/// An instruction which completed execution.
+pub struct RetiredInstruction {
+    pub pc: u64,
+    /// the (decompressed) instruction word.
+    pub word: u32,
+    /// 2 for compressed instructions, 4 otherwise.
+    pub length: u64,
+    /// address of the next instruction.
+    pub next_pc: u64,
+    /// cache accesses of the instruction which missed L1 (fetch and data).
+    pub l1_misses: u64,
+    /// cache accesses of the instruction which missed L2 as well.
+    pub l2_misses: u64,
+}
+
+pub trait TimingModel {
+    /// Cycles between the retirement of the previous and this instruction.
+    /// A tick takes at least one cycle, so 0 counts as 1.
+    fn retire(&mut self, instruction: &RetiredInstruction) -> u64;
+    /// Cycles lost to flush the pipeline when a trap is taken.
+    fn trap(&mut self) -> u64;
+}
+
+#[derive(Clone, Debug)]
+pub struct PipelineConfig {
+    /// result latency of MUL* instructions.
+    pub mul_latency: u64,
+    /// result latency of DIV* and REM* instructions (worst case).
+    pub div_latency: u64,
+    /// result latency of loads; a dependent instruction right after the
+    /// load stalls for the remaining cycles.
+    pub load_latency: u64,
+    /// cycles lost by a mispredicted branch or jump.
+    pub mispredict_penalty: u64,
+    /// additional cycles of an access which misses L1 and hits L2.
+    pub l1_miss_penalty: u64,
+    /// additional cycles of an access which misses L2 and goes to memory.
+    pub l2_miss_penalty: u64,
+    /// entries of the branch history table (power of two).
+    pub bht_entries: usize,
+    /// entries of the return address stack.
+    pub ras_entries: usize,
+}
+
+impl PipelineConfig {
+    pub fn e31() -> Self {
+        PipelineConfig {
+            mul_latency: 2,
+            div_latency: 33,
+            load_latency: 2,
+            mispredict_penalty: 3,
+            l1_miss_penalty: 10,
+            l2_miss_penalty: 0,
+            bht_entries: 256,
+            ras_entries: 2,
+        }
+    }
+
+    pub fn u54() -> Self {
+        PipelineConfig {
+            mul_latency: 3,
+            div_latency: 65,
+            load_latency: 3,
+            mispredict_penalty: 4,
+            l1_miss_penalty: 20,
+            l2_miss_penalty: 100,
+            bht_entries: 512,
+            ras_entries: 6,
+        }
+    }
+}
+
+/// 5-stage in-order pipeline with a bimodal branch predictor and a return
+/// address stack. Jumps with a static target (JAL) and returns are predicted,
+/// other indirect jumps are always mispredicted.
+pub struct PipelineModel {
+    config: PipelineConfig,
+    /// 2-bit saturating counters indexed by PC.
+    bht: Vec<u8>,
+    ras: Vec<u64>,
+    /// destination register of the last long latency instruction and the
+    /// cycles until its result is available.
+    pending: Option<(u32, u64)>,
+    branches: u64,
+    mispredicts: u64,
+}
+
+impl PipelineModel {
+    pub fn new(config: PipelineConfig) -> Self {
+        let entries = config.bht_entries.max(1).next_power_of_two();
+        PipelineModel {
+            config,
+            // weakly not taken.
+            bht: vec![1; entries],
+            ras: Vec::new(),
+            pending: None,
+            branches: 0,
+            mispredicts: 0,
+        }
+    }
+
+    pub fn get_config(&self) -> &PipelineConfig {
+        &self.config
+    }
+
+    /// Conditional branches and indirect jumps seen by the predictor.
+    pub fn get_branches(&self) -> u64 {
+        self.branches
+    }
+
+    pub fn get_mispredicts(&self) -> u64 {
+        self.mispredicts
+    }
+
+    fn predict_branch(&mut self, pc: u64, taken: bool) -> bool {
+        let index = ((pc >> 1) as usize) & (self.bht.len() - 1);
+        let counter = self.bht[index];
+        let predicted = counter >= 2;
+        self.bht[index] = match taken {
+            true => (counter + 1).min(3),
+            false => counter.saturating_sub(1),
+        };
+        predicted == taken
+    }
+
+    fn predict_return(&mut self, target: u64) -> bool {
+        self.ras.pop() == Some(target)
+    }
+
+    fn push_return(&mut self, addr: u64) {
+        if self.config.ras_entries == 0 {
+            return;
+        }
+        if self.ras.len() == self.config.ras_entries {
+            self.ras.remove(0);
+        }
+        self.ras.push(addr);
+    }
+}
+
+fn is_link(reg: u32) -> bool {
+    reg == REG_RA || reg == REG_T0
+}
+
+impl TimingModel for PipelineModel {
+    fn retire(&mut self, instruction: &RetiredInstruction) -> u64 {
+        let word = instruction.word;
+        let opcode = word & 0x7f;
+        let rd = (word >> 7) & 0x1f;
+        let funct3 = (word >> 12) & 0x7;
+        let rs1 = (word >> 15) & 0x1f;
+        let rs2 = (word >> 20) & 0x1f;
+        let funct7 = word >> 25;
+
+        let mut cycles = 1;
+
+        // stall until the operands of this instruction are available.
+        if let Some((reg, remaining)) = self.pending.take() {
+            let uses_rs2 = matches!(
+                opcode,
+                OPCODE_OP
+                    | OPCODE_OP_32
+                    | OPCODE_BRANCH
+                    | OPCODE_AMO
+                    | OPCODE_STORE
+                    | OPCODE_STORE_FP
+            );
+            let uses_rs1 = !matches!(opcode, OPCODE_JAL | OPCODE_LUI | OPCODE_AUIPC);
+            if reg != REG_ZERO && ((uses_rs1 && rs1 == reg) || (uses_rs2 && rs2 == reg)) {
+                cycles += remaining;
+            } else if remaining > 1 {
+                self.pending = Some((reg, remaining - 1));
+            }
+        }
+
+        let latency = match opcode {
+            OPCODE_LOAD | OPCODE_LOAD_FP | OPCODE_AMO => self.config.load_latency,
+            OPCODE_OP | OPCODE_OP_32 if funct7 == FUNCT7_MULDIV => match funct3 {
+                0..=3 => self.config.mul_latency,
+                _ => self.config.div_latency,
+            },
+            _ => 1,
+        };
+        if latency > 1 {
+            self.pending = Some((rd, latency - 1));
+        }
+
+        let fallthrough = instruction.pc.wrapping_add(instruction.length);
+        let taken = instruction.next_pc != fallthrough;
+        let mispredicted = match opcode {
+            OPCODE_BRANCH => {
+                self.branches += 1;
+                !self.predict_branch(instruction.pc, taken)
+            }
+            OPCODE_JAL => {
+                if is_link(rd) {
+                    self.push_return(fallthrough);
+                }
+                false
+            }
+            OPCODE_JALR => {
+                self.branches += 1;
+                // RISC-V calling convention hints (Table 2.1 of the ISA manual).
+                let correct = match (is_link(rd), is_link(rs1)) {
+                    (false, true) => self.predict_return(instruction.next_pc),
+                    _ => false,
+                };
+                if is_link(rd) {
+                    self.push_return(fallthrough);
+                }
+                !correct
+            }
+            _ => false,
+        };
+        if mispredicted {
+            self.mispredicts += 1;
+            cycles += self.config.mispredict_penalty;
+        }
+
+        cycles += instruction.l1_misses * self.config.l1_miss_penalty;
+        cycles += instruction.l2_misses * self.config.l2_miss_penalty;
+        cycles
+    }
+
+    fn trap(&mut self) -> u64 {
+        self.pending = None;
+        self.config.mispredict_penalty
+    }
+}
@@ -0,0 +1,217 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::sifive;
+use riscv_emu::machine::Machine;
+use riscv_emu::peripherals::fe310_g002::aon::*;
+
+// Registers
+const WDOGCFG: u64 = 0x000;
+const WDOGCOUNT: u64 = 0x008;
+const WDOGS: u64 = 0x010;
+const WDOGFEED: u64 = 0x018;
+const WDOGKEY: u64 = 0x01C;
+const WDOGCMP0: u64 = 0x020;
+const RTCCFG: u64 = 0x040;
+const RTCCOUNTLO: u64 = 0x048;
+const RTCCOUNTHI: u64 = 0x04C;
+const RTCS: u64 = 0x050;
+const RTCCMP0: u64 = 0x060;
+const BACKUP0: u64 = 0x080;
+const PMUWAKEUPI0: u64 = 0x100;
+const PMUIE: u64 = 0x140;
+const PMUCAUSE: u64 = 0x144;
+const PMUSLEEP: u64 = 0x148;
+const PMUKEY: u64 = 0x14C;
+
+// wdogcfg and rtccfg bits
+const RSTEN: u32 = 1 << 8;
+const ZEROCMP: u32 = 1 << 9;
+const ENALWAYS: u32 = 1 << 12;
+const IP0: u32 = 1 << 28;
+
+fn tick_lfclk(aon: &mut Aon, cycles: u64) {
+    for _ in 0..cycles * AON_LFCLK_DIVIDER {
+        aon.tick();
+    }
+}
+
+fn write_wdog(aon: &mut Aon, addr: u64, data: u32) {
+    aon.write(WDOGKEY, AON_KEY);
+    aon.write(addr, data);
+}
+
+#[test]
+fn aon_watchdog_key() {
+    let mut aon = Aon::new();
+    assert_eq!(0, aon.read(WDOGKEY));
+
+    // locked writes are ignored.
+    aon.write(WDOGCMP0, 10);
+    assert_eq!(0xffff, aon.read(WDOGCMP0));
+
+    // the key unlocks a single write.
+    aon.write(WDOGKEY, AON_KEY);
+    assert_eq!(1, aon.read(WDOGKEY));
+    aon.write(WDOGCMP0, 10);
+    assert_eq!(0, aon.read(WDOGKEY));
+    assert_eq!(10, aon.read(WDOGCMP0));
+    aon.write(WDOGCMP0, 20);
+    assert_eq!(10, aon.read(WDOGCMP0));
+    aon.write(WDOGKEY, 0x1234);
+    assert_eq!(0, aon.read(WDOGKEY));
+}
+
+#[test]
+fn aon_watchdog_interrupt() {
+    let mut aon = Aon::new();
+    write_wdog(&mut aon, WDOGCMP0, 4);
+    write_wdog(&mut aon, WDOGCFG, ENALWAYS | 1); // scale 1
+    tick_lfclk(&mut aon, 5);
+    assert_eq!(5, aon.read(WDOGCOUNT));
+    assert_eq!(2, aon.read(WDOGS));
+    assert!(!aon.is_watchdog_irq());
+
+    // feeding restarts the counter.
+    write_wdog(&mut aon, WDOGFEED, 0x1234);
+    assert_eq!(5, aon.read(WDOGCOUNT));
+    write_wdog(&mut aon, WDOGFEED, AON_WDOG_FEED);
+    assert_eq!(0, aon.read(WDOGCOUNT));
+
+    tick_lfclk(&mut aon, 8);
+    assert!(aon.is_watchdog_irq());
+    assert_ne!(0, aon.read(WDOGCFG) & IP0);
+    // the pending bit is sticky until cleared.
+    write_wdog(&mut aon, WDOGCOUNT, 0);
+    assert!(aon.is_watchdog_irq());
+    write_wdog(&mut aon, WDOGCFG, ENALWAYS | 1);
+    assert!(!aon.is_watchdog_irq());
+
+    // the counter goes back to zero at the comparison.
+    write_wdog(&mut aon, WDOGCFG, ENALWAYS | ZEROCMP);
+    tick_lfclk(&mut aon, 4);
+    assert!(aon.is_watchdog_irq());
+    assert_eq!(0, aon.read(WDOGCOUNT));
+    assert!(!aon.take_reset_request());
+}
+
+#[test]
+fn aon_watchdog_reset() {
+    let mut aon = Aon::new();
+    aon.write(BACKUP0 + 4, 0xcafe);
+    write_wdog(&mut aon, WDOGCMP0, 2);
+    write_wdog(&mut aon, WDOGCFG, ENALWAYS | RSTEN);
+    tick_lfclk(&mut aon, 1);
+    assert!(!aon.take_reset_request());
+    tick_lfclk(&mut aon, 1);
+    assert!(aon.take_reset_request());
+    assert!(!aon.take_reset_request());
+    assert_eq!(AON_RESETCAUSE_WATCHDOG << 8, aon.read(PMUCAUSE));
+    // the watchdog is disabled, the backup registers are kept.
+    assert_eq!(0, aon.read(WDOGCFG));
+    assert_eq!(0xcafe, aon.read(BACKUP0 + 4));
+}
+
+#[test]
+fn aon_rtc() {
+    let mut aon = Aon::new();
+    aon.write(RTCCOUNTLO, 0xffff_fffe);
+    aon.write(RTCCOUNTHI, 0x1_0001);
+    assert_eq!(0x0001, aon.read(RTCCOUNTHI));
+    aon.write(RTCCFG, ENALWAYS | 8);
+    aon.write(RTCCMP0, 0x0200_0000);
+    assert!(!aon.is_rtc_irq());
+    tick_lfclk(&mut aon, 2);
+    assert_eq!(0, aon.read(RTCCOUNTLO));
+    assert_eq!(2, aon.read(RTCCOUNTHI));
+    assert_eq!(0x0200_0000, aon.read(RTCS));
+    assert!(aon.is_rtc_irq());
+    assert_ne!(0, aon.read(RTCCFG) & IP0);
+    // a later comparison clears the interrupt.
+    aon.write(RTCCMP0, 0x0200_0001);
+    assert!(!aon.is_rtc_irq());
+}
+
+#[test]
+fn aon_pmu_sleep() {
+    let mut aon = Aon::new();
+    assert_eq!(0x1f0, aon.read(PMUWAKEUPI0));
+
+    // locked writes are ignored.
+    aon.write(PMUSLEEP, 0);
+    assert!(!aon.is_sleeping());
+    aon.write(PMUKEY, AON_KEY);
+    assert_eq!(1, aon.read(PMUKEY));
+    aon.write(PMUIE, 0x2); // RTC
+    assert_eq!(0x2, aon.read(PMUIE));
+    aon.write(PMUKEY, AON_KEY);
+    aon.write(PMUWAKEUPI0, 0x0f0);
+    assert_eq!(0x0f0, aon.read(PMUWAKEUPI0));
+
+    aon.write(RTCCFG, ENALWAYS);
+    aon.write(RTCCMP0, 10);
+    aon.write(PMUKEY, AON_KEY);
+    aon.write(PMUSLEEP, 0);
+    assert!(aon.is_sleeping());
+    tick_lfclk(&mut aon, 9);
+    assert!(aon.is_sleeping());
+    tick_lfclk(&mut aon, 1);
+    assert!(!aon.is_sleeping());
+    assert!(aon.take_reset_request());
+    assert_eq!(AON_WAKEUPCAUSE_RTC, aon.read(PMUCAUSE));
+
+    // the digital wake up pin.
+    aon.write(PMUKEY, AON_KEY);
+    aon.write(PMUIE, 0x4);
+    aon.write(PMUKEY, AON_KEY);
+    aon.write(PMUSLEEP, 0);
+    tick_lfclk(&mut aon, 4);
+    assert!(aon.is_sleeping());
+    aon.set_dwakeup(true);
+    tick_lfclk(&mut aon, 1);
+    assert!(!aon.is_sleeping());
+    assert!(aon.take_reset_request());
+    assert_eq!(AON_WAKEUPCAUSE_DWAKEUP, aon.read(PMUCAUSE));
+}
+
+#[test]
+fn aon_fe310_watchdog_reboot() {
+    // Count the boots in a backup register and print it with the reset
+    // cause, then let the watchdog reset the machine.
+    let program = [
+        0x100002b7, // lui t0, 0x10000 (AON)
+        0x0802a303, // lw t1, 0x80(t0) (backup0)
+        0x00130313, // addi t1, t1, 1
+        0x0862a023, // sw t1, 0x80(t0)
+        0x10013eb7, // lui t4, 0x10013 (UART0)
+        0x04030f13, // addi t5, t1, 0x40
+        0x01eea023, // sw t5, 0(t4)
+        0x1442af03, // lw t5, 0x144(t0) (pmucause)
+        0x008f5f13, // srli t5, t5, 8
+        0x030f0f13, // addi t5, t5, 0x30
+        0x01eea023, // sw t5, 0(t4)
+        0x0051f3b7, // lui t2, 0x51f
+        0x15e38393, // addi t2, t2, 0x15e
+        0x0072ae23, // sw t2, 0x1c(t0) (wdogkey)
+        0x00200e13, // li t3, 2
+        0x03c2a023, // sw t3, 0x20(t0) (wdogcmp0)
+        0x0072ae23, // sw t2, 0x1c(t0)
+        0x00001e37, // lui t3, 0x1
+        0x100e0e13, // addi t3, t3, 0x100 (enalways, rsten)
+        0x01c2a023, // sw t3, 0(t0) (wdogcfg)
+        0x0000006f, // j .
+    ];
+
+    let mut emulator = sifive(Machine::SiFiveE, &program);
+    emulator.run_steps(AON_LFCLK_DIVIDER as u32 * 7);
+
+    let mut output = String::new();
+    loop {
+        match emulator.get_console().get_output() {
+            0 => break,
+            c => output.push(c as char),
+        }
+    }
+    assert_eq!("A0B2C2D2", output);
+}
@@ -0,0 +1,121 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::to_bytes;
+use riscv_emu::block::memory::MemoryDisk;
+use riscv_emu::bus::bus::Device;
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+
+const BLOCK_SIZE: usize = 512;
+
+/// On-disk bytes of the type GUID of the FSBL partition,
+/// 5B193300-FC78-40CD-8002-E86C45580B47.
+const FSBL_TYPE_GUID: [u8; 16] = [
+    0x00, 0x33, 0x19, 0x5b, 0x78, 0xfc, 0xcd, 0x40, 0x80, 0x02, 0xe8, 0x6c, 0x45, 0x58, 0x0b, 0x47,
+];
+
+/// Print 'F' on the UART0 of the FE310.
+fn fe310_program() -> Vec<u8> {
+    to_bytes(&[
+        0x100132b7, // lui t0, 0x10013 (UART0)
+        0x04600313, // li t1, 'F'
+        0x0062a023, // sw t1, 0(t0)
+        0x0000006f, // j .
+    ])
+}
+
+/// Print 'Z' + a0 (the hart ID) on the UART0 of the FU540.
+fn fu540_program() -> Vec<u8> {
+    to_bytes(&[
+        0x100102b7, // lui t0, 0x10010 (UART0)
+        0x05a50313, // addi t1, a0, 'Z'
+        0x0062a023, // sw t1, 0(t0)
+        0x0000006f, // j .
+    ])
+}
+
+/// A GPT disk with an unused entry, then the FSBL partition holding the
+/// program at LBA 34.
+fn gpt_image(fsbl: &[u8]) -> Vec<u8> {
+    let mut image = vec![0; 1024 * 1024];
+    let header = BLOCK_SIZE;
+    image[header..header + 8].copy_from_slice(b"EFI PART");
+    // partition entries LBA, number of entries and size of an entry
+    image[header + 72..header + 80].copy_from_slice(&2u64.to_le_bytes());
+    image[header + 80..header + 84].copy_from_slice(&4u32.to_le_bytes());
+    image[header + 84..header + 88].copy_from_slice(&128u32.to_le_bytes());
+    let entry = 2 * BLOCK_SIZE + 128;
+    image[entry..entry + 16].copy_from_slice(&FSBL_TYPE_GUID);
+    image[entry + 32..entry + 40].copy_from_slice(&34u64.to_le_bytes());
+    image[entry + 40..entry + 48].copy_from_slice(&35u64.to_le_bytes());
+    image[34 * BLOCK_SIZE..34 * BLOCK_SIZE + fsbl.len()].copy_from_slice(fsbl);
+    image
+}
+
+#[test]
+fn fe310_boot_from_flash() {
+    let mut emulator = Emulator::new(Machine::SiFiveE, Box::new(TtyBuffer::new()), false);
+    emulator.set_data_from_binary(Device::SpiFlash, fe310_program());
+    emulator.set_mode_select(0x1).unwrap();
+    emulator.run_steps(100);
+    assert_eq!(b'F', emulator.get_console().get_output());
+
+    // a reset goes through the mask ROM again.
+    emulator.reset();
+    emulator.run_steps(100);
+    assert_eq!(b'F', emulator.get_console().get_output());
+}
+
+#[test]
+fn fe310_boot_wait() {
+    // nothing to boot: the core waits for an interrupt.
+    let mut emulator = Emulator::new(Machine::SiFiveE, Box::new(TtyBuffer::new()), false);
+    emulator.set_data_from_binary(Device::SpiFlash, fe310_program());
+    emulator.set_mode_select(0x0).unwrap();
+    emulator.run_steps(100);
+    assert_eq!(0, emulator.get_console().get_output());
+}
+
+#[test]
+fn fu540_boot_from_flash() {
+    // MSEL 0x1 jumps to the flash, MSEL 0x6 runs the ZSBL which loads the
+    // FSBL from the GPT of the flash.
+    let mut emulator = Emulator::new(Machine::SiFiveU, Box::new(TtyBuffer::new()), false);
+    emulator.set_data_from_binary(Device::SpiFlash, fu540_program());
+    emulator.set_mode_select(0x1).unwrap();
+    emulator.run_steps(100);
+    assert_eq!(b'Z', emulator.get_console().get_output());
+
+    let mut emulator = Emulator::new(Machine::SiFiveU, Box::new(TtyBuffer::new()), false);
+    emulator.set_data_from_binary(Device::SpiFlash, gpt_image(&fu540_program()));
+    emulator.set_mode_select(0x6).unwrap();
+    emulator.run_steps(20000);
+    assert_eq!(b'Z', emulator.get_console().get_output());
+}
+
+#[test]
+fn fu540_boot_from_sd_card() {
+    // MSEL 0xb runs the ZSBL which loads the FSBL from the SD card.
+    let mut emulator = Emulator::new(Machine::SiFiveU, Box::new(TtyBuffer::new()), false);
+    emulator
+        .attach_sd_card(Box::new(MemoryDisk::new(gpt_image(&fu540_program()))))
+        .unwrap();
+    emulator.set_mode_select(0xb).unwrap();
+    emulator.run_steps(200000);
+    assert_eq!(b'Z', emulator.get_console().get_output());
+
+    // without a card the ZSBL gives up.
+    let mut emulator = Emulator::new(Machine::SiFiveU, Box::new(TtyBuffer::new()), false);
+    emulator.set_mode_select(0xb).unwrap();
+    emulator.run_steps(200000);
+    assert_eq!(0, emulator.get_console().get_output());
+}
+
+#[test]
+fn boot_rom_unsupported() {
+    let mut emulator = Emulator::new(Machine::QemuVirt, Box::new(TtyBuffer::new()), false);
+    assert!(emulator.set_mode_select(0x1).is_err());
+}
@@ -0,0 +1,68 @@
+extern crate riscv_emu;
+
+use std::path::PathBuf;
+
+use riscv_emu::cache::{CacheConfig, CacheHierarchyConfig, Replacement, WritePolicy};
+use riscv_emu::console::TtyDummy;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+
+fn run_with_cache(filename: &'static str, config: CacheHierarchyConfig) -> Emulator {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push("tests/bin");
+    root.push(filename);
+
+    let tty = Box::new(TtyDummy::new());
+    let mut emu = Emulator::new(Machine::SiFiveU, tty, true);
+    emu.load_program_from_file(root.as_path());
+    emu.enable_cache(config);
+    // the cache model must not change the result.
+    assert_eq!(Ok(1), emu.run());
+    emu
+}
+
+#[test]
+fn cache_fu540() {
+    let emu = run_with_cache("rv64ui-v-sd", CacheHierarchyConfig::fu540());
+    let cache = emu.get_cache().unwrap();
+
+    let l1i = cache.get_l1i().unwrap().get_stats();
+    let l1d = cache.get_l1d().unwrap().get_stats();
+    let l2 = cache.get_l2().unwrap().get_stats();
+    assert!(l1i.reads > 0 && l1i.read_misses > 0);
+    assert!(l1i.read_misses < l1i.reads);
+    assert!(l1d.writes > 0);
+    // every L1 miss fills from L2.
+    assert_eq!(l1i.get_misses() + l1d.get_misses(), l2.reads);
+
+    let region_accesses: u64 = cache
+        .get_l1i()
+        .unwrap()
+        .get_regions()
+        .values()
+        .map(|r| r.0)
+        .sum();
+    assert_eq!(l1i.get_accesses(), region_accesses);
+}
+
+#[test]
+fn cache_write_through() {
+    let mut l1d = CacheConfig::new(1024, 2, 16);
+    l1d.replacement = Replacement::Random;
+    l1d.write_policy = WritePolicy::WriteThrough;
+    let config = CacheHierarchyConfig {
+        l1i: None,
+        l1d: Some(l1d),
+        l2: Some(CacheConfig::new(8 * 1024, 4, 32)),
+        region_size: 256,
+    };
+    let emu = run_with_cache("rv32ui-p-sw", config);
+    let cache = emu.get_cache().unwrap();
+    assert!(cache.get_l1i().is_none());
+
+    let l1d = cache.get_l1d().unwrap().get_stats();
+    let l2 = cache.get_l2().unwrap().get_stats();
+    assert_eq!(0, l1d.writebacks);
+    // stores are always forwarded to L2.
+    assert_eq!(l1d.writes, l2.writes);
+}
@@ -0,0 +1,176 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::sifive;
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::machine::Machine;
+use riscv_emu::peripherals::fe310_g002::aon::*;
+use riscv_emu::peripherals::fe310_g002::fe310_uart::Fe310Uart;
+use riscv_emu::peripherals::fe310_g002::prci::*;
+use riscv_emu::peripherals::fu540_c000::clint::Clint;
+use riscv_emu::peripherals::timer::Timer;
+
+// PRCI registers
+const HFROSCCFG: u64 = 0x00;
+const PLLCFG: u64 = 0x08;
+const PLLOUTDIV: u64 = 0x0c;
+
+const PLL_SEL: u32 = 1 << 16;
+const PLL_REFSEL: u32 = 1 << 17;
+const PLL_BYPASS: u32 = 1 << 18;
+
+// UART registers
+const TXDATA: u64 = 0x00;
+const TXCTRL: u64 = 0x08;
+const DIV: u64 = 0x18;
+
+const MTIME: u64 = 0xbff8;
+
+#[test]
+fn prci_core_frequency() {
+    let mut prci = Prci::new();
+    // the ring oscillator divided by 5 at the reset.
+    assert_eq!(14_400_000, prci.get_core_frequency());
+    let hfrosccfg = prci.read(HFROSCCFG) & 0x7fff_ffc0;
+    prci.write(HFROSCCFG, hfrosccfg | 1);
+    assert_eq!(36_000_000, prci.get_core_frequency());
+
+    // the crystal through the bypassed PLL.
+    prci.write(PLLCFG, PLL_SEL | PLL_REFSEL | PLL_BYPASS);
+    assert_eq!(PRCI_HFXOSC_FREQUENCY, prci.get_core_frequency());
+
+    // 16 MHz / 2 * 64 / 2
+    let pll_256mhz = PLL_SEL | PLL_REFSEL | 1 | 31 << 4 | 1 << 10;
+    prci.write(PLLCFG, pll_256mhz);
+    assert_eq!(256_000_000, prci.get_core_frequency());
+    // divided by 2 * (1 + 1)
+    prci.write(PLLOUTDIV, 1);
+    assert_eq!(64_000_000, prci.get_core_frequency());
+
+    // the PLL from the ring oscillator.
+    prci.write(PLLOUTDIV, 1 << 8);
+    prci.write(PLLCFG, pll_256mhz & !PLL_REFSEL);
+    assert_eq!(576_000_000, prci.get_core_frequency());
+}
+
+#[test]
+fn clint_rtcclk() {
+    let mut clint = Clint::new();
+    for _ in 0..0xfffff {
+        clint.tick();
+    }
+    assert_eq!(1, clint.read(MTIME));
+
+    // 10 core cycles per RTCCLK cycle.
+    let mut clint = Clint::new();
+    clint.set_frequency(10 * PRCI_RTCCLK_FREQUENCY, PRCI_RTCCLK_FREQUENCY);
+    for _ in 0..105 {
+        clint.tick();
+    }
+    assert_eq!(10, clint.read(MTIME));
+
+    // a second at 16 MHz.
+    clint.set_frequency(PRCI_HFXOSC_FREQUENCY, PRCI_RTCCLK_FREQUENCY);
+    for _ in 0..PRCI_HFXOSC_FREQUENCY {
+        clint.tick();
+    }
+    assert_eq!(10 + PRCI_RTCCLK_FREQUENCY as u32, clint.read(MTIME));
+}
+
+#[test]
+fn aon_core_frequency() {
+    let mut aon = Aon::new();
+    aon.write(0x040, 1 << 12); // rtccfg, enalways
+    aon.set_core_frequency(PRCI_HFXOSC_FREQUENCY);
+    for _ in 0..PRCI_HFXOSC_FREQUENCY {
+        aon.tick();
+    }
+    assert_eq!(AON_LFCLK_FREQUENCY as u32, aon.read(0x048));
+}
+
+#[test]
+fn uart_baud_rate() {
+    let mut uart = Fe310Uart::new(Box::new(TtyBuffer::new()));
+    // a bit takes 10 cycles.
+    uart.write(DIV, 9);
+    for c in b"abc" {
+        uart.write(TXDATA, *c as u32);
+    }
+    uart.tick();
+    assert_eq!(b'a', uart.get_console().get_output());
+    for _ in 0..99 {
+        uart.tick();
+    }
+    assert_eq!(0, uart.get_console().get_output());
+    uart.tick();
+    assert_eq!(b'b', uart.get_console().get_output());
+
+    for _ in 0..100 {
+        uart.tick();
+    }
+    assert_eq!(b'c', uart.get_console().get_output());
+
+    // with 2 stop bits.
+    let mut uart = Fe310Uart::new(Box::new(TtyBuffer::new()));
+    uart.write(DIV, 9);
+    uart.write(TXCTRL, 0x3);
+    uart.write(TXDATA, b'a' as u32);
+    uart.write(TXDATA, b'b' as u32);
+    uart.tick();
+    assert_eq!(b'a', uart.get_console().get_output());
+    for _ in 0..109 {
+        uart.tick();
+    }
+    assert_eq!(0, uart.get_console().get_output());
+    uart.tick();
+    assert_eq!(b'b', uart.get_console().get_output());
+
+    // the transmit FIFO holds 8 characters.
+    for c in 0..8 {
+        assert_eq!(0, uart.read(TXDATA));
+        uart.write(TXDATA, b'0' as u32 + c);
+    }
+    assert_eq!(0x8000_0000, uart.read(TXDATA));
+    for _ in 0..110 {
+        uart.tick();
+    }
+    assert_eq!(0, uart.read(TXDATA));
+}
+
+/// Count the RTCCLK cycles of a loop of 5128 instructions run after the
+/// pllcfg value set by the two first instructions.
+fn fe310_loop_mtime(pllcfg: [u32; 2]) -> u8 {
+    let program = [
+        0x100082b7, // lui t0, 0x10008 (PRCI)
+        pllcfg[0],  // li t1, pllcfg
+        pllcfg[1],  // (second instruction of li)
+        0x0062a423, // sw t1, 8(t0) (pllcfg)
+        0x0200c3b7, // lui t2, 0x200c (CLINT)
+        0xff83ae03, // lw t3, -8(t2) (mtime)
+        0x00001eb7, // lui t4, 1
+        0xa04e8e93, // addi t4, t4, -1532
+        0xfffe8e93, // 1: addi t4, t4, -1
+        0xfe0e9ee3, // bnez t4, 1b
+        0xff83af03, // lw t5, -8(t2)
+        0x41cf0f33, // sub t5, t5, t3
+        0x030f0f13, // addi t5, t5, '0'
+        0x10013fb7, // lui t6, 0x10013 (UART0)
+        0x01efa023, // sw t5, 0(t6)
+        0x0000006f, // j .
+    ];
+
+    let mut emulator = sifive(Machine::SiFiveE, &program);
+    emulator.run_steps(6000);
+    emulator.get_console().get_output() - b'0'
+}
+
+#[test]
+fn fe310_clock_tree() {
+    // 14.4 MHz at the reset (the pllcfg reset value).
+    let ticks = fe310_loop_mtime([0x00061337, 0xdf130313]);
+    assert!(ticks == 11 || ticks == 12);
+    // 256 MHz from the PLL.
+    let ticks = fe310_loop_mtime([0x00030337, 0x5f130313]);
+    assert!(ticks <= 1);
+}
@@ -0,0 +1,92 @@
+// Fixtures shared by the integration tests. Each test crate uses a part of
+// them.
+#![allow(dead_code)]
+
+use riscv_emu::bus::bus::Device;
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::virtio::mmio::VirtioMmio;
+
+pub const DRAM_BASE: u64 = 0x8000_0000;
+pub const SPIFLASH_BASE: u64 = 0x2000_0000;
+pub const PAGE_SIZE: u64 = 0x1000;
+pub const QUEUE_NUM: u64 = 8;
+pub const BUFFER_ADDRESS: u64 = 0x10000;
+
+/// Little endian bytes of the instructions.
+pub fn to_bytes(program: &[u32]) -> Vec<u8> {
+    program
+        .iter()
+        .flat_map(|inst| inst.to_le_bytes().to_vec())
+        .collect()
+}
+
+/// Qemu_virt running the data from the start of the DRAM.
+pub fn qemu_virt_dram(data: Vec<u8>) -> Emulator {
+    let mut emulator = Emulator::new(Machine::QemuVirt, Box::new(TtyBuffer::new()), false);
+    emulator.set_dram_data(data);
+    emulator.set_pc(DRAM_BASE);
+    emulator
+}
+
+/// Qemu_virt running the program from the start of the DRAM.
+pub fn qemu_virt(program: &[u32]) -> Emulator {
+    qemu_virt_dram(to_bytes(program))
+}
+
+/// SiFive machine running the program from the start of the SPI flash.
+pub fn sifive(machine: Machine, program: &[u32]) -> Emulator {
+    let mut emulator = Emulator::new(machine, Box::new(TtyBuffer::new()), false);
+    emulator.set_data_from_binary(Device::SpiFlash, to_bytes(program));
+    emulator.set_pc(SPIFLASH_BASE);
+    emulator
+}
+
+/// Queues are placed every two pages from the second page of the memory.
+pub fn get_queue_offset(index: u64) -> u64 {
+    PAGE_SIZE + index * 2 * PAGE_SIZE
+}
+
+/// Set up the queue in the legacy layout. GuestPageSize must be PAGE_SIZE.
+pub fn setup_queue(virtio: &mut VirtioMmio, index: u64) {
+    virtio.write(0x030, index as u32); // QueueSel
+    virtio.write(0x038, QUEUE_NUM as u32); // QueueNum
+    let pfn = (DRAM_BASE + get_queue_offset(index)) / PAGE_SIZE;
+    virtio.write(0x040, pfn as u32); // QueuePFN
+}
+
+/// Make a single descriptor buffer of 64 bytes available, filled with the data.
+/// Returns the offset of the buffer in the memory.
+pub fn add_buffer(dram: &mut Memory, index: u64, data: &[u8], writable: bool) -> u64 {
+    let queue = get_queue_offset(index);
+    let avail = queue + 16 * QUEUE_NUM;
+    let idx = dram.read16(avail + 2);
+    let slot = idx as u64 % QUEUE_NUM;
+    let buffer = BUFFER_ADDRESS + (index * QUEUE_NUM + slot) * 64;
+    dram.mem[buffer as usize..buffer as usize + data.len()].copy_from_slice(data);
+
+    let desc = queue + 16 * slot;
+    dram.write64(desc, DRAM_BASE + buffer);
+    dram.write32(desc + 8, if writable { 64 } else { data.len() as u32 });
+    dram.write16(desc + 12, if writable { 2 } else { 0 });
+    dram.write16(desc + 14, 0);
+    dram.write16(avail + 4 + 2 * slot, slot as u16);
+    dram.write16(avail + 2, idx.wrapping_add(1));
+    buffer
+}
+
+/// Data of the used buffers of a queue filled by add_buffer().
+pub fn get_used(dram: &Memory, index: u64) -> Vec<Vec<u8>> {
+    let used = get_queue_offset(index) + PAGE_SIZE;
+    (0..dram.read16(used + 2) as u64)
+        .map(|i| {
+            let entry = used + 4 + 8 * (i % QUEUE_NUM);
+            let slot = dram.read32(entry) as u64;
+            let len = dram.read32(entry + 4) as usize;
+            let buffer = (BUFFER_ADDRESS + (index * QUEUE_NUM + slot) * 64) as usize;
+            dram.mem[buffer..buffer + len].to_vec()
+        })
+        .collect()
+}
@@ -0,0 +1,117 @@
+extern crate riscv_emu;
+
+use riscv_emu::cpu::cpu::{Privilege, Xlen};
+use riscv_emu::cpu::cpu_csr::*;
+
+#[test]
+fn counter_enable() {
+    let mut csr = Csr::new();
+    csr.tick_counters(5, true);
+
+    // machine mode can always read the counters.
+    assert_eq!(
+        Ok(5),
+        csr.read(CSR_CYCLE, 0, &Privilege::Machine).map_err(|_| ())
+    );
+    assert!(csr.read(CSR_CYCLE, 0, &Privilege::Supervisor).is_err());
+    assert!(csr.read(CSR_INSTRET, 0, &Privilege::User).is_err());
+
+    csr.write(CSR_MCOUNTEREN, 0x5, 0, &Privilege::Machine).ok();
+    assert_eq!(
+        Ok(5),
+        csr.read(CSR_CYCLE, 0, &Privilege::Supervisor)
+            .map_err(|_| ())
+    );
+    assert!(csr.read(CSR_CYCLE, 0, &Privilege::User).is_err());
+
+    csr.write(CSR_SCOUNTEREN, 0x4, 0, &Privilege::Supervisor)
+        .ok();
+    assert_eq!(
+        Ok(1),
+        csr.read(CSR_INSTRET, 0, &Privilege::User).map_err(|_| ())
+    );
+    assert!(csr.read(CSR_CYCLE, 0, &Privilege::User).is_err());
+
+    // user counters are read-only.
+    assert!(csr.write(CSR_CYCLE, 0, 0, &Privilege::Machine).is_err());
+}
+
+#[test]
+fn counter_inhibit_and_write() {
+    let mut csr = Csr::new();
+    csr.write(CSR_MCOUNTINHIBIT, 0x4, 0, &Privilege::Machine)
+        .ok();
+    csr.tick_counters(3, true);
+    assert_eq!(3, csr.read_direct(CSR_MCYCLE));
+    assert_eq!(0, csr.read_direct(CSR_MINSTRET));
+
+    // the value written by an instruction is not incremented by it.
+    csr.write(CSR_MCYCLE, 100, 0, &Privilege::Machine).ok();
+    csr.tick_counters(1, true);
+    assert_eq!(100, csr.read_direct(CSR_MCYCLE));
+    csr.tick_counters(1, true);
+    assert_eq!(101, csr.read_direct(CSR_MCYCLE));
+
+    csr.write(CSR_MCYCLEH, 0x1, 0, &Privilege::Machine).ok();
+    assert_eq!(0x1_0000_0065, csr.read_direct(CSR_MCYCLE));
+    assert_eq!(0x1, csr.read_direct(CSR_CYCLEH));
+}
+
+#[test]
+fn counter_write_halves_rv32() {
+    let mut csr = Csr::new();
+    csr.set_xlen(&Xlen::X32);
+    csr.write(CSR_MCYCLEH, 0x1, 0, &Privilege::Machine).ok();
+    csr.write(CSR_MCYCLE, 0xffff_fff0, 0, &Privilege::Machine)
+        .ok();
+    csr.write(CSR_MHPMCOUNTER3H, 0x2, 0, &Privilege::Machine)
+        .ok();
+    csr.write(CSR_MHPMCOUNTER3, 0x3, 0, &Privilege::Machine)
+        .ok();
+
+    // the low half is replaced, the high half is kept.
+    assert_eq!(0x1_ffff_fff0, csr.read_direct(CSR_MCYCLE));
+    assert_eq!(0x1, csr.read_direct(CSR_CYCLEH));
+    assert_eq!(0x2_0000_0003, csr.read_direct(CSR_MHPMCOUNTER3));
+
+    // the carry out of the low half goes to the high half (the writing
+    // instruction does not count).
+    csr.tick_counters(1, true);
+    csr.tick_counters(0x10, true);
+    assert_eq!(0x2_0000_0000, csr.read_direct(CSR_MCYCLE));
+}
+
+#[test]
+fn counter_hpm_events() {
+    let mut csr = Csr::new();
+    // mhpmcounter3 counts loads and stores, mhpmcounter4 counts DTLB misses.
+    csr.write(
+        CSR_MHPMEVENT3,
+        HPM_CLASS_INSTRUCTION | HPM_EVENT_LOAD | HPM_EVENT_STORE,
+        0,
+        &Privilege::Machine,
+    )
+    .ok();
+    csr.write(
+        CSR_MHPMEVENT3 + 1,
+        HPM_CLASS_MEMORY | HPM_EVENT_DTLB_MISS,
+        0,
+        &Privilege::Machine,
+    )
+    .ok();
+
+    csr.count_event(HPM_CLASS_INSTRUCTION, HPM_EVENT_LOAD, 1);
+    csr.count_event(HPM_CLASS_INSTRUCTION, HPM_EVENT_STORE, 1);
+    csr.count_event(HPM_CLASS_INSTRUCTION, HPM_EVENT_BRANCH, 1);
+    csr.count_event(HPM_CLASS_MEMORY, HPM_EVENT_DTLB_MISS, 2);
+    csr.count_event(HPM_CLASS_MEMORY, HPM_EVENT_ITLB_MISS, 1);
+    assert_eq!(2, csr.read_direct(CSR_MHPMCOUNTER3));
+    assert_eq!(2, csr.read_direct(CSR_MHPMCOUNTER3 + 1));
+    assert_eq!(2, csr.read_direct(CSR_HPMCOUNTER3));
+
+    // inhibited counters do not count.
+    csr.write(CSR_MCOUNTINHIBIT, 1 << 3, 0, &Privilege::Machine)
+        .ok();
+    csr.count_event(HPM_CLASS_INSTRUCTION, HPM_EVENT_LOAD, 1);
+    assert_eq!(2, csr.read_direct(CSR_MHPMCOUNTER3));
+}
@@ -0,0 +1,216 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{qemu_virt, sifive};
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::{ExitStatus, Machine};
+
+/// Run the program in the supervisor mode.
+fn supervisor(program: &[u32]) -> Vec<u32> {
+    let mut words = vec![
+        0x00000297, // auipc t0, 0
+        0x01c28293, // addi t0, t0, 28
+        0x34129073, // csrw mepc, t0
+        0x000012b7, // lui t0, 1
+        0x8002829b, // addiw t0, t0, -2048 (MPP = S)
+        0x30029073, // csrw mstatus, t0
+        0x30200073, // mret
+    ];
+    words.extend_from_slice(program);
+    words
+}
+
+fn get_output(emulator: &mut Emulator) -> String {
+    let mut output = String::new();
+    loop {
+        match emulator.get_console().get_output() {
+            0 => break,
+            c => output.push(c as char),
+        }
+    }
+    output
+}
+
+#[test]
+fn qemu_virt_test_device() {
+    let mut emulator = qemu_virt(&[
+        0x001002b7, // lui t0, 0x100 (test)
+        0x00005337, // lui t1, 5
+        0x55530313, // addi t1, t1, 0x555 (pass)
+        0x0062a023, // sw t1, 0(t0)
+        0x0000006f, // j .
+    ]);
+    assert_eq!(None, emulator.get_exit_status());
+    assert_eq!(ExitStatus::PowerOff(0), emulator.run_until_exit());
+    assert_eq!(Some(&ExitStatus::PowerOff(0)), emulator.get_exit_status());
+
+    // a failure with the exit code 3.
+    let mut emulator = qemu_virt(&[
+        0x001002b7, // lui t0, 0x100 (test)
+        0x00033337, // lui t1, 0x33
+        0x33330313, // addi t1, t1, 0x333 (fail)
+        0x0062a023, // sw t1, 0(t0)
+        0x0000006f, // j .
+    ]);
+    assert_eq!(ExitStatus::PowerOff(3), emulator.run_until_exit());
+
+    // run() stops too outside the test mode.
+    let mut emulator = qemu_virt(&[
+        0x001002b7, // lui t0, 0x100 (test)
+        0x00033337, // lui t1, 0x33
+        0x33330313, // addi t1, t1, 0x333 (fail)
+        0x0062a023, // sw t1, 0(t0)
+        0x0000006f, // j .
+    ]);
+    assert_eq!(Err(3), emulator.run());
+}
+
+#[test]
+fn qemu_virt_reboot() {
+    // Count the boots in the memory after the program and print the count
+    // plus a byte of the program, which is changed before rebooting. Reboot
+    // the first time and power off the second time.
+    let program = vec![
+        0x00001297, // auipc t0, 1
+        0x0002a303, // lw t1, 0(t0)
+        0x00130313, // addi t1, t1, 1
+        0x0062a023, // sw t1, 0(t0)
+        0x100003b7, // lui t2, 0x10000 (UART)
+        0x00000f17, // auipc t5, 0
+        0x048f4e03, // lbu t3, 72(t5) (data)
+        0x006e0e33, // add t3, t3, t1
+        0x01c38023, // sb t3, 0(t2)
+        0x0053ce03, // 1: lbu t3, 5(t2) (LSR)
+        0x020e7e13, // andi t3, t3, 0x20 (THR empty)
+        0xfe0e0ce3, // beqz t3, 1b
+        0x03500e13, // li t3, '5'
+        0x05cf0423, // sb t3, 72(t5) (data)
+        0x001002b7, // lui t0, 0x100 (test)
+        0x00007e37, // lui t3, 7
+        0x777e0e13, // addi t3, t3, 0x777 (reset)
+        0x00200e93, // li t4, 2
+        0x01d34663, // blt t1, t4, 2f
+        0x00005e37, // lui t3, 5
+        0x555e0e13, // addi t3, t3, 0x555 (pass)
+        0x01c2a023, // 2: sw t3, 0(t0)
+        0x0000006f, // j .
+        0x00000030, // data: '0'
+    ];
+    let mut emulator = qemu_virt(&program);
+    assert_eq!(ExitStatus::Reboot, emulator.run_until_exit());
+    assert_eq!(None, emulator.get_exit_status());
+    // the program is loaded again, the memory after it is kept.
+    assert_eq!(ExitStatus::PowerOff(0), emulator.run_until_exit());
+    assert_eq!("12", get_output(&mut emulator));
+
+    // a stopped machine runs again after a reset, which keeps the memory.
+    emulator.reset();
+    assert_eq!(None, emulator.get_exit_status());
+    assert_eq!(ExitStatus::PowerOff(0), emulator.run_until_exit());
+    assert_eq!("8", get_output(&mut emulator));
+}
+
+#[test]
+fn sbi_system_reset() {
+    // An invalid reset type returns SBI_ERR_INVALID_PARAM, then shut down
+    // because of a system failure.
+    let mut emulator = qemu_virt(&supervisor(&[
+        0x535258b7, // lui a7, 0x53525
+        0x35488893, // addi a7, a7, 0x354 (SRST)
+        0x00000813, // li a6, 0
+        0x00500513, // li a0, 5
+        0x00000593, // li a1, 0
+        0x00000073, // ecall
+        0x03350313, // addi t1, a0, '0' + 3
+        0x100003b7, // lui t2, 0x10000 (UART)
+        0x00638023, // sb t1, 0(t2)
+        0x0053c303, // 1: lbu t1, 5(t2) (LSR)
+        0x02037313, // andi t1, t1, 0x20 (THR empty)
+        0xfe030ce3, // beqz t1, 1b
+        0x00000513, // li a0, 0 (shutdown)
+        0x00100593, // li a1, 1 (system failure)
+        0x00000073, // ecall
+        0x0000006f, // j .
+    ]));
+    assert_eq!(ExitStatus::PowerOff(1), emulator.run_until_exit());
+    assert_eq!("0", get_output(&mut emulator));
+
+    let mut emulator = qemu_virt(&supervisor(&[
+        0x535258b7, // lui a7, 0x53525
+        0x35488893, // addi a7, a7, 0x354 (SRST)
+        0x00000813, // li a6, 0
+        0x00100513, // li a0, 1 (cold reboot)
+        0x00000593, // li a1, 0
+        0x00000073, // ecall
+        0x0000006f, // j .
+    ]));
+    assert_eq!(ExitStatus::Reboot, emulator.run_until_exit());
+}
+
+#[test]
+fn fe310_watchdog_reboot() {
+    // Print the boot count and the reset cause, then let the watchdog reset
+    // the machine.
+    let program = [
+        0x100002b7, // lui t0, 0x10000 (AON)
+        0x0802a303, // lw t1, 0x80(t0) (backup0)
+        0x00130313, // addi t1, t1, 1
+        0x0862a023, // sw t1, 0x80(t0)
+        0x10013eb7, // lui t4, 0x10013 (UART0)
+        0x04030f13, // addi t5, t1, 0x40
+        0x01eea023, // sw t5, 0(t4)
+        0x1442af03, // lw t5, 0x144(t0) (pmucause)
+        0x008f5f13, // srli t5, t5, 8
+        0x030f0f13, // addi t5, t5, 0x30
+        0x01eea023, // sw t5, 0(t4)
+        0x0051f3b7, // lui t2, 0x51f
+        0x15e38393, // addi t2, t2, 0x15e
+        0x0072ae23, // sw t2, 0x1c(t0) (wdogkey)
+        0x00200e13, // li t3, 2
+        0x03c2a023, // sw t3, 0x20(t0) (wdogcmp0)
+        0x0072ae23, // sw t2, 0x1c(t0)
+        0x00001e37, // lui t3, 0x1
+        0x100e0e13, // addi t3, t3, 0x100 (enalways, rsten)
+        0x01c2a023, // sw t3, 0(t0) (wdogcfg)
+        0x0000006f, // j .
+    ];
+
+    let mut emulator = sifive(Machine::SiFiveE, &program);
+    assert_eq!(ExitStatus::Reboot, emulator.run_until_exit());
+    assert_eq!("A0", get_output(&mut emulator));
+    assert_eq!(ExitStatus::Reboot, emulator.run_until_exit());
+    assert_eq!("B2", get_output(&mut emulator));
+}
+
+#[test]
+fn halt() {
+    // wait for an interrupt while none is enabled.
+    let mut emulator = qemu_virt(&[
+        0x10500073, // wfi
+    ]);
+    assert_eq!(ExitStatus::Halt, emulator.run_until_exit());
+
+    // nothing to boot: the mask ROM waits for an interrupt forever.
+    let mut emulator = Emulator::new(Machine::SiFiveE, Box::new(TtyBuffer::new()), false);
+    emulator.set_mode_select(0x0).unwrap();
+    assert_eq!(ExitStatus::Halt, emulator.run_until_exit());
+}
+
+#[test]
+fn fatal() {
+    // jump to 0 where nothing is mapped, the trap vector is 0 too.
+    let mut emulator = qemu_virt(&[
+        0x00000067, // jr zero
+    ]);
+    match emulator.run_until_exit() {
+        ExitStatus::Fatal(message) => assert!(message.contains("traps to itself")),
+        status => panic!("Unexpected status: {:?}", status),
+    }
+
+    // the machine does not run anymore.
+    let cycle = emulator.get_cycle();
+    emulator.run_steps(100);
+    assert_eq!(cycle, emulator.get_cycle());
+}
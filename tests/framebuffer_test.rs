@@ -0,0 +1,156 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::qemu_virt;
+use riscv_emu::console::TtyDummy;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::peripherals::framebuffer::Framebuffer;
+
+/// Chunks of a PNG file, checking the CRCs.
+fn read_chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+    assert_eq!(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a], &png[..8]);
+    let mut chunks = vec![];
+    let mut offset = 8;
+    while offset < png.len() {
+        let len = u32::from_be_bytes([
+            png[offset],
+            png[offset + 1],
+            png[offset + 2],
+            png[offset + 3],
+        ]) as usize;
+        let body = &png[offset + 4..offset + 8 + len];
+        let crc = &png[offset + 8 + len..offset + 12 + len];
+        assert_eq!(&crc32(body).to_be_bytes()[..], crc);
+        chunks.push((
+            String::from_utf8(body[..4].to_vec()).unwrap(),
+            body[4..].to_vec(),
+        ));
+        offset += 12 + len;
+    }
+    chunks
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => 0xedb8_8320 ^ (crc >> 1),
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+/// Data of zlib stored blocks.
+fn inflate_stored(data: &[u8]) -> Vec<u8> {
+    assert_eq!(0x78, data[0]);
+    assert_eq!(0, (data[0] as u16 * 256 + data[1] as u16) % 31);
+    let mut out = vec![];
+    let mut offset = 2;
+    loop {
+        let header = data[offset];
+        assert_eq!(0, header & 0x6); // stored
+        let len = u16::from_le_bytes([data[offset + 1], data[offset + 2]]);
+        let nlen = u16::from_le_bytes([data[offset + 3], data[offset + 4]]);
+        assert_eq!(!len, nlen);
+        offset += 5;
+        out.extend_from_slice(&data[offset..offset + len as usize]);
+        offset += len as usize;
+        if header & 1 != 0 {
+            break;
+        }
+    }
+    let (a, b) = out.iter().fold((1u32, 0u32), |(a, b), byte| {
+        (
+            (a + *byte as u32) % 65521,
+            (b + (a + *byte as u32) % 65521) % 65521,
+        )
+    });
+    assert_eq!(&(b << 16 | a).to_be_bytes()[..], &data[offset..offset + 4]);
+    out
+}
+
+#[test]
+fn framebuffer_render() {
+    let mut framebuffer = Framebuffer::new(4, 2);
+    assert_eq!(16, framebuffer.get_stride());
+    assert_eq!(32, framebuffer.get_size());
+    assert!(!framebuffer.is_dirty());
+
+    assert_eq!(Ok(()), framebuffer.write(4, 0xff12_3456, 4));
+    assert_eq!(Ok(()), framebuffer.write(16 + 12, 0x0000_00ff, 1));
+    assert!(framebuffer.is_dirty());
+    assert_eq!(Ok(0x1234), framebuffer.read(5, 2));
+    assert_eq!(Err(()), framebuffer.read(32, 1));
+    assert_eq!(Err(()), framebuffer.write(28, 0, 8));
+    assert_eq!(0x12_3456, framebuffer.get_pixel(1, 0));
+    assert_eq!(0x00_00ff, framebuffer.get_pixel(3, 1));
+
+    let rgb = framebuffer.render();
+    assert!(!framebuffer.is_dirty());
+    assert_eq!(24, rgb.len());
+    assert_eq!(&[0x12, 0x34, 0x56], &rgb[3..6]);
+    assert_eq!(&[0x00, 0x00, 0xff], &rgb[21..24]);
+}
+
+#[test]
+fn framebuffer_png() {
+    let mut framebuffer = Framebuffer::new(300, 200);
+    for y in 0..200 {
+        for x in 0..300 {
+            let pixel = x << 16 | y << 8 | 0x80;
+            let offset = (y * 300 + x) * 4;
+            assert_eq!(Ok(()), framebuffer.write(offset, pixel, 4));
+        }
+    }
+    let png = framebuffer.screenshot();
+
+    let chunks = read_chunks(&png);
+    let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+    assert_eq!(vec!["IHDR", "IDAT", "IEND"], kinds);
+    // 300x200, 8 bits RGB
+    assert_eq!(vec![0, 0, 1, 44, 0, 0, 0, 200, 8, 2, 0, 0, 0], chunks[0].1);
+    // more than a stored block
+    let raw = inflate_stored(&chunks[1].1);
+    assert_eq!((300 * 3 + 1) * 200, raw.len());
+    for y in 0..200 {
+        let row = &raw[y * 901..(y + 1) * 901];
+        assert_eq!(0, row[0]); // no filter
+        for x in 0..300 {
+            let expected = [x as u8, y as u8, 0x80];
+            assert_eq!(&expected, &row[1 + x * 3..4 + x * 3]);
+        }
+    }
+}
+
+#[test]
+fn framebuffer_qemu_virt() {
+    let mut emulator = qemu_virt(&[
+        0x240002b7, // lui t0, 0x24000
+        0x00ff0337, // lui t1, 0xff0
+        0x0062a223, // sw t1, 4(t0)
+        0x0000006f, // j .
+    ]);
+    emulator.run_steps(16);
+
+    let framebuffer = emulator.get_framebuffer().unwrap();
+    assert_eq!(640, framebuffer.get_width());
+    assert_eq!(480, framebuffer.get_height());
+    assert!(framebuffer.is_dirty());
+    assert_eq!(0xff_0000, framebuffer.get_pixel(1, 0));
+    assert_eq!(0, framebuffer.get_pixel(0, 0));
+
+    let chunks = read_chunks(&emulator.screenshot().unwrap());
+    let raw = inflate_stored(&chunks[1].1);
+    assert_eq!(&[0, 0, 0, 0, 0xff, 0, 0], &raw[..7]);
+
+    // SiFive boards have no display.
+    let mut emulator = Emulator::new(Machine::SiFiveE, Box::new(TtyDummy::new()), false);
+    assert!(emulator.get_framebuffer().is_none());
+    assert_eq!(Err(()), emulator.screenshot());
+}
@@ -0,0 +1,334 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::sifive;
+use riscv_emu::block::memory::MemoryDisk;
+use riscv_emu::machine::Machine;
+use riscv_emu::net::local::LocalNet;
+use riscv_emu::peripherals::fu540_c000::ddr::DdrController;
+use riscv_emu::peripherals::fu540_c000::gemgxl::Gemgxl;
+use riscv_emu::peripherals::fu540_c000::l2cache::L2Cache;
+use riscv_emu::peripherals::i2c::eeprom::Eeprom;
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::virtio::queue::GuestMemory;
+
+const DRAM_BASE: u64 = 0x8000_0000;
+
+// GEM registers
+const GEM_NCR: u64 = 0x000;
+const GEM_NCFGR: u64 = 0x004;
+const GEM_DMACFG: u64 = 0x010;
+const GEM_TSR: u64 = 0x014;
+const GEM_RBQP: u64 = 0x018;
+const GEM_TBQP: u64 = 0x01C;
+const GEM_RSR: u64 = 0x020;
+const GEM_ISR: u64 = 0x024;
+const GEM_IER: u64 = 0x028;
+const GEM_MAN: u64 = 0x034;
+const GEM_SA1B: u64 = 0x088;
+const GEM_SA1T: u64 = 0x08C;
+const GEM_MID: u64 = 0x0FC;
+
+const NCR_RE: u32 = 1 << 2;
+const NCR_TE: u32 = 1 << 3;
+const NCR_TSTART: u32 = 1 << 9;
+const TSR_UBR: u32 = 1 << 0;
+const TSR_TGO: u32 = 1 << 3;
+const TSR_TXCOMP: u32 = 1 << 5;
+const RSR_BNA: u32 = 1 << 0;
+const RSR_REC: u32 = 1 << 1;
+const ISR_MFD: u32 = 1 << 0;
+const ISR_RCOMP: u32 = 1 << 1;
+const ISR_RXUBR: u32 = 1 << 2;
+const ISR_TXUBR: u32 = 1 << 3;
+const ISR_TCOMP: u32 = 1 << 7;
+
+const TX_LAST: u32 = 1 << 15;
+const TX_WRAP: u32 = 1 << 30;
+const TX_USED: u32 = 1 << 31;
+const RX_USED: u32 = 1 << 0;
+const RX_WRAP: u32 = 1 << 1;
+const RX_SOF: u32 = 1 << 14;
+const RX_EOF: u32 = 1 << 15;
+const RX_SA1_MATCH: u32 = 1 << 27;
+
+// L2 cache controller registers
+const L2_CONFIG: u64 = 0x000;
+const L2_WAYENABLE: u64 = 0x008;
+const L2_ECCINJECTERROR: u64 = 0x040;
+const L2_DIRECCFIX_LOW: u64 = 0x100;
+const L2_DIRECCFIX_COUNT: u64 = 0x108;
+const L2_DATECCFIX_LOW: u64 = 0x140;
+const L2_DATECCFIX_COUNT: u64 = 0x148;
+const L2_WAYMASK: u64 = 0x800;
+
+fn tick(gem: &mut Gemgxl, dram: &mut Memory, cycles: usize) {
+    for _ in 0..cycles {
+        gem.tick(&mut GuestMemory::new(dram, DRAM_BASE));
+    }
+}
+
+fn write_descriptor(dram: &mut Memory, addr: u64, word0: u32, word1: u32) {
+    let mut mem = GuestMemory::new(dram, DRAM_BASE);
+    mem.write32(addr, word0);
+    mem.write32(addr + 4, word1);
+}
+
+fn read32(dram: &mut Memory, addr: u64) -> u32 {
+    GuestMemory::new(dram, DRAM_BASE).read32(addr)
+}
+
+fn frame(dest: [u8; 6], len: usize) -> Vec<u8> {
+    let mut frame = dest.to_vec();
+    frame.extend((6..len).map(|i| i as u8));
+    frame
+}
+
+#[test]
+fn gem_transmit() {
+    let mut dram = Memory::new(0x10000);
+    let backend = LocalNet::new();
+    let handle = backend.get_handle();
+    let mut gem = Gemgxl::new();
+    gem.set_backend(Box::new(backend));
+    assert_eq!(2, gem.read(GEM_MID) >> 16);
+
+    let first = frame([0xff; 6], 60);
+    let second = frame([0x02, 0, 0, 0, 0, 0x02], 30);
+    GuestMemory::new(&mut dram, DRAM_BASE).write_bytes(0x8000_1000, &first);
+    GuestMemory::new(&mut dram, DRAM_BASE).write_bytes(0x8000_1100, &second[..10]);
+    GuestMemory::new(&mut dram, DRAM_BASE).write_bytes(0x8000_1200, &second[10..]);
+    write_descriptor(&mut dram, 0x8000_0000, 0x8000_1000, 60 | TX_LAST);
+    write_descriptor(&mut dram, 0x8000_0008, 0x8000_1100, 10);
+    write_descriptor(&mut dram, 0x8000_0010, 0x8000_1200, 20 | TX_LAST | TX_WRAP);
+
+    gem.write(GEM_TBQP, 0x8000_0000);
+    gem.write(GEM_IER, ISR_TCOMP);
+    gem.write(GEM_NCR, NCR_TE | NCR_RE);
+    tick(&mut gem, &mut dram, 10);
+    // nothing is sent before the transmission is started.
+    assert!(handle.take_frames().is_empty());
+
+    gem.write(GEM_NCR, NCR_TE | NCR_RE | NCR_TSTART);
+    assert_ne!(0, gem.read(GEM_TSR) & TSR_TGO);
+    tick(&mut gem, &mut dram, 10);
+    assert_eq!(vec![first, second], handle.take_frames());
+    // the used bit is set in the first descriptor of each frame, and the
+    // transmitter stops at the used descriptor after the wrap.
+    assert_ne!(0, read32(&mut dram, 0x8000_0004) & TX_USED);
+    assert_ne!(0, read32(&mut dram, 0x8000_000c) & TX_USED);
+    assert_eq!(0, read32(&mut dram, 0x8000_0014) & TX_USED);
+    assert_eq!(TSR_UBR | TSR_TXCOMP, gem.read(GEM_TSR));
+    assert_eq!(0x8000_0000, gem.read(GEM_TBQP));
+    gem.write(GEM_TSR, TSR_UBR | TSR_TXCOMP);
+    assert_eq!(0, gem.read(GEM_TSR));
+
+    // only TCOMP is enabled, ISR is cleared on read.
+    assert!(gem.is_irq());
+    assert_eq!(ISR_TCOMP | ISR_TXUBR, gem.read(GEM_ISR));
+    assert!(!gem.is_irq());
+
+    // disabling the transmitter goes back to the first descriptor.
+    write_descriptor(&mut dram, 0x8000_0000, 0x8000_1000, 14 | TX_LAST);
+    gem.write(GEM_NCR, NCR_RE);
+    gem.write(GEM_NCR, NCR_TE | NCR_RE | NCR_TSTART);
+    tick(&mut gem, &mut dram, 2);
+    assert_eq!(vec![frame([0xff; 6], 14)], handle.take_frames());
+}
+
+#[test]
+fn gem_receive() {
+    let mut dram = Memory::new(0x10000);
+    let backend = LocalNet::new();
+    let handle = backend.get_handle();
+    let mut gem = Gemgxl::new();
+    gem.set_backend(Box::new(backend));
+
+    let mac = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    gem.write(GEM_SA1B, 0x0000_0002);
+    gem.write(GEM_SA1T, 0x0000_0100);
+    assert_eq!(mac, gem.get_mac_address());
+    // 64-byte buffers, the first one with a 2-byte offset.
+    gem.write(GEM_DMACFG, 1 << 16);
+    gem.write(GEM_NCFGR, 2 << 14);
+    write_descriptor(&mut dram, 0x8000_0100, 0x8000_2000, 0);
+    write_descriptor(&mut dram, 0x8000_0108, 0x8000_2080 | RX_WRAP, 0);
+    gem.write(GEM_RBQP, 0x8000_0100);
+
+    // frames are dropped while the receiver is disabled.
+    handle.inject(&frame(mac, 100));
+    tick(&mut gem, &mut dram, 0x400);
+    assert_eq!(0, handle.get_pending());
+    assert_eq!(0, gem.read(GEM_RSR));
+
+    gem.write(GEM_IER, ISR_RCOMP);
+    gem.write(GEM_NCR, NCR_RE);
+    let received = frame(mac, 100);
+    handle.inject(&received);
+    // another unicast address is filtered out.
+    handle.inject(&frame([0x02, 0, 0, 0, 0, 0x09], 60));
+    // no buffer is left for this one.
+    handle.inject(&frame([0xff; 6], 20));
+    tick(&mut gem, &mut dram, 0x400);
+    assert_eq!(0, handle.get_pending());
+
+    assert_eq!(0x8000_2000 | RX_USED, read32(&mut dram, 0x8000_0100));
+    assert_eq!(RX_SOF, read32(&mut dram, 0x8000_0104));
+    assert_eq!(
+        0x8000_2080 | RX_WRAP | RX_USED,
+        read32(&mut dram, 0x8000_0108)
+    );
+    assert_eq!(RX_EOF | RX_SA1_MATCH | 100, read32(&mut dram, 0x8000_010c));
+    let mut data = vec![0; 62];
+    GuestMemory::new(&mut dram, DRAM_BASE).read_bytes(0x8000_2002, &mut data);
+    assert_eq!(&received[..62], &data[..]);
+    let mut data = vec![0; 38];
+    GuestMemory::new(&mut dram, DRAM_BASE).read_bytes(0x8000_2080, &mut data);
+    assert_eq!(&received[62..], &data[..]);
+
+    assert_eq!(RSR_REC | RSR_BNA, gem.read(GEM_RSR));
+    assert!(gem.is_irq());
+    assert_eq!(ISR_RCOMP | ISR_RXUBR, gem.read(GEM_ISR));
+    gem.write(GEM_RSR, RSR_REC | RSR_BNA);
+    assert_eq!(0, gem.read(GEM_RSR));
+
+    // the driver gives the buffers back, the receiver continues from the
+    // wrap.
+    write_descriptor(&mut dram, 0x8000_0100, 0x8000_2000, 0);
+    handle.inject(&frame([0xff; 6], 20));
+    tick(&mut gem, &mut dram, 0x400);
+    assert_eq!(0x8000_2000 | RX_USED, read32(&mut dram, 0x8000_0100));
+    assert_eq!(
+        RX_SOF | RX_EOF | (1 << 31) | 20,
+        read32(&mut dram, 0x8000_0104)
+    );
+}
+
+#[test]
+fn gem_phy_management() {
+    let mut gem = Gemgxl::new();
+    let read = |gem: &mut Gemgxl, register: u32| {
+        gem.write(
+            GEM_MAN,
+            (1 << 30) | (2 << 28) | (register << 18) | (2 << 16),
+        );
+        gem.read(GEM_MAN) & 0xffff
+    };
+    assert_eq!(0x0007, read(&mut gem, 2));
+    assert_eq!(0x0772, read(&mut gem, 3));
+    // link up and auto-negotiation complete
+    assert_eq!(0x0024, read(&mut gem, 1) & 0x0024);
+    assert_eq!(ISR_MFD, gem.read(GEM_ISR));
+
+    // write the advertisement, then reset the PHY.
+    gem.write(
+        GEM_MAN,
+        (1 << 30) | (1 << 28) | (4 << 18) | (2 << 16) | 0x0061,
+    );
+    assert_eq!(0x0061, read(&mut gem, 4));
+    gem.write(GEM_MAN, (1 << 30) | (1 << 28) | (2 << 16) | 0x9200);
+    assert_eq!(0x1140, read(&mut gem, 0));
+
+    // no PHY at another address
+    gem.write(
+        GEM_MAN,
+        (1 << 30) | (2 << 28) | (5 << 23) | (2 << 18) | (2 << 16),
+    );
+    assert_eq!(0xffff, gem.read(GEM_MAN) & 0xffff);
+}
+
+#[test]
+fn l2cache_registers() {
+    let mut l2 = L2Cache::new();
+    assert_eq!(0x0609_1004, l2.read(L2_CONFIG));
+    assert_eq!(0, l2.read(L2_WAYENABLE));
+    l2.write(L2_WAYENABLE, 7);
+    assert_eq!(7, l2.read(L2_WAYENABLE));
+    // the ways can not be disabled.
+    l2.write(L2_WAYENABLE, 3);
+    assert_eq!(7, l2.read(L2_WAYENABLE));
+    l2.write(L2_WAYENABLE, 0xff);
+    assert_eq!(15, l2.get_way_enable());
+
+    assert_eq!(0xffff, l2.read(L2_WAYMASK + 8));
+    l2.write(L2_WAYMASK + 8, 0x00ff);
+    assert_eq!(0x00ff, l2.read(L2_WAYMASK + 8));
+    assert_eq!(0xffff, l2.read(L2_WAYMASK));
+}
+
+#[test]
+fn l2cache_ecc_injection() {
+    let mut l2 = L2Cache::new();
+    assert_eq!([false, false, false], l2.get_irqs());
+
+    // data error
+    l2.write(L2_ECCINJECTERROR, 5);
+    l2.write(L2_ECCINJECTERROR, 5);
+    assert_eq!([false, true, false], l2.get_irqs());
+    assert_eq!(5, l2.read(L2_DATECCFIX_LOW));
+    assert_eq!(2, l2.read(L2_DATECCFIX_COUNT));
+    // reading the count clears it and the interrupt.
+    assert_eq!(0, l2.read(L2_DATECCFIX_COUNT));
+    assert_eq!([false, false, false], l2.get_irqs());
+
+    // directory error
+    l2.write(L2_ECCINJECTERROR, (1 << 16) | 3);
+    assert_eq!([true, false, false], l2.get_irqs());
+    assert_eq!(3, l2.read(L2_DIRECCFIX_LOW));
+    assert_eq!(1, l2.read(L2_DIRECCFIX_COUNT));
+    assert_eq!([false, false, false], l2.get_irqs());
+}
+
+#[test]
+fn ddr_initialization() {
+    let mut ddr = DdrController::new();
+    ddr.write(0x1000, 0x1234_5678);
+    assert_eq!(0x1234_5678, ddr.read(0x1000));
+    ddr.write(0x2004, 0xabcd);
+    assert_eq!(0xabcd, ddr.read(0x2004));
+    ddr.write_blocker(0, 0x0200_000f);
+    assert_eq!(0x0200_000f, ddr.read_blocker(0));
+
+    // DRAM class in DENALI_CTL_0, then START
+    assert!(!ddr.is_initialized());
+    ddr.write(0, 0xa << 8);
+    assert_eq!(0, ddr.read(0x210));
+    ddr.write(0, (0xa << 8) | 1);
+    assert!(ddr.is_initialized());
+    assert_eq!(1 << 8, ddr.read(0x210) & (1 << 8));
+}
+
+#[test]
+fn fu540_peripherals() {
+    // Start the DDR controller, wait for the end of its initialization and
+    // print 'D'.
+    let program = [
+        0x100b02b7, // lui t0, 0x100b0 (DDR controller)
+        0x00100313, // li t1, 1
+        0x0062a023, // sw t1, 0(t0) (START)
+        0x2102a303, // 1: lw t1, 528(t0) (DENALI_CTL_132)
+        0x10037313, // andi t1, t1, 0x100
+        0xfe030ce3, // beqz t1, 1b
+        0x100103b7, // lui t2, 0x10010 (UART0)
+        0x04400e13, // li t3, 'D'
+        0x01c3a023, // sw t3, 0(t2)
+        0x0000006f, // 2: j 2b
+    ];
+
+    let mut emulator = sifive(Machine::SiFiveU, &program);
+    emulator.run_steps(100);
+    assert_eq!(b'D', emulator.get_console().get_output());
+
+    assert!(emulator
+        .attach_sd_card(Box::new(MemoryDisk::new(vec![0; 0x10000])))
+        .is_ok());
+    assert!(emulator
+        .attach_i2c_device(Box::new(Eeprom::new(0x50, 256)))
+        .is_ok());
+    assert!(emulator.get_pwm(1).is_some());
+    assert!(emulator.get_pwm(2).is_none());
+    assert!(emulator.attach_network(Box::new(LocalNet::new())).is_ok());
+    // the GEMGXL is already connected.
+    assert!(emulator.attach_network(Box::new(LocalNet::new())).is_err());
+}
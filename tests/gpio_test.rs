@@ -0,0 +1,269 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::sifive;
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::peripherals::fe310_g002::gpio::{Gpio, GpioEvent};
+
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+// Registers
+const INPUT_VAL: u64 = 0x00;
+const INPUT_EN: u64 = 0x04;
+const OUTPUT_EN: u64 = 0x08;
+const OUTPUT_VAL: u64 = 0x0c;
+const PUE: u64 = 0x10;
+const RISE_IE: u64 = 0x18;
+const RISE_IP: u64 = 0x1c;
+const FALL_IE: u64 = 0x20;
+const FALL_IP: u64 = 0x24;
+const HIGH_IE: u64 = 0x28;
+const HIGH_IP: u64 = 0x2c;
+const LOW_IP: u64 = 0x34;
+const IOF_EN: u64 = 0x38;
+const IOF_SEL: u64 = 0x3c;
+const OUT_XOR: u64 = 0x40;
+
+/// Output event log shared with the test.
+struct Log(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Log {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn event(cycle: u64, pin: usize, level: bool) -> GpioEvent {
+    GpioEvent { cycle, pin, level }
+}
+
+#[test]
+fn gpio_inputs() {
+    let mut gpio = Gpio::new();
+    gpio.write(INPUT_EN, 0x60);
+    gpio.write(PUE, 0x40);
+    gpio.write(RISE_IE, 0x20);
+    gpio.write(FALL_IE, 0x20);
+    gpio.tick();
+    // the pin 6 is pulled up, the pin 5 floats low.
+    assert_eq!(0x40, gpio.read(INPUT_VAL));
+    assert_eq!(0x20, gpio.read(LOW_IP) & 0x20);
+    assert_eq!(0, gpio.get_irqs());
+
+    // the input register follows the pin from the next cycle.
+    gpio.set_input(5, true).unwrap();
+    gpio.set_input(7, true).unwrap();
+    assert_eq!(0x40, gpio.read(INPUT_VAL));
+    gpio.tick();
+    assert_eq!(0x60, gpio.read(INPUT_VAL));
+    // the pin 6 rose in the first cycle, its interrupt is disabled.
+    assert_eq!(0x60, gpio.read(RISE_IP));
+    assert_eq!(0x20, gpio.get_irqs());
+    assert!(gpio.is_irq());
+    gpio.write(RISE_IP, 0x60);
+    assert_eq!(0, gpio.get_irqs());
+
+    gpio.set_input(6, false).unwrap();
+    gpio.set_input(5, false).unwrap();
+    gpio.tick();
+    assert_eq!(0, gpio.read(INPUT_VAL));
+    assert_eq!(0x60, gpio.read(FALL_IP));
+    assert_eq!(0x20, gpio.get_irqs());
+    gpio.write(FALL_IP, 0xffff_ffff);
+
+    // the level interrupt stays pending while the level holds.
+    gpio.write(HIGH_IE, 0x40);
+    gpio.release_input(6).unwrap();
+    gpio.tick();
+    assert_eq!(0x40, gpio.get_irqs());
+    gpio.write(HIGH_IP, 0x40);
+    gpio.tick();
+    assert_eq!(0x40, gpio.get_irqs());
+    // the input value is read only.
+    gpio.write(INPUT_VAL, 0);
+    assert_eq!(0x40, gpio.read(INPUT_VAL));
+}
+
+#[test]
+fn gpio_scheduled_inputs() {
+    let mut gpio = Gpio::new();
+    gpio.write(INPUT_EN, 0x3);
+    gpio.schedule_input(10, 1, true).unwrap();
+    gpio.schedule_input(5, 0, true).unwrap();
+    gpio.schedule_input(10, 1, false).unwrap();
+    gpio.schedule_input(12, 1, true).unwrap();
+
+    let mut levels = vec![];
+    for _ in 0..14 {
+        gpio.tick();
+        levels.push((gpio.get_cycle(), gpio.read(INPUT_VAL)));
+    }
+    assert_eq!((4, 0), levels[3]);
+    assert_eq!((5, 1), levels[4]);
+    // the later input at the same cycle wins.
+    assert_eq!((10, 1), levels[9]);
+    assert_eq!((11, 1), levels[10]);
+    assert_eq!((12, 3), levels[11]);
+}
+
+#[test]
+fn gpio_invalid_pin() {
+    let mut gpio = Gpio::new();
+    gpio.write(INPUT_EN, 0xffff_ffff);
+    assert!(gpio.set_input(32, true).is_err());
+    assert!(gpio.release_input(32).is_err());
+    assert!(gpio.schedule_input(1, 32, true).is_err());
+    gpio.tick();
+    gpio.tick();
+    assert_eq!(0, gpio.read(INPUT_VAL));
+}
+
+#[test]
+fn gpio_outputs() {
+    let mut gpio = Gpio::new();
+    gpio.write(OUTPUT_VAL, 0x9);
+    gpio.tick();
+    // the pins are not outputs yet.
+    assert!(gpio.take_events().is_empty());
+
+    gpio.write(OUTPUT_EN, 0xb);
+    gpio.tick();
+    gpio.write(OUT_XOR, 0x3);
+    gpio.tick();
+    assert_eq!(
+        vec![
+            event(2, 0, true),
+            event(2, 3, true),
+            event(3, 0, false),
+            event(3, 1, true),
+        ],
+        gpio.take_events()
+    );
+    assert!(!gpio.get_output(0));
+    assert!(gpio.get_output(1));
+    assert!(!gpio.get_output(2));
+
+    // an output reads its own level, not the level driven by the host.
+    gpio.write(INPUT_EN, 0xf);
+    gpio.set_input(0, true).unwrap();
+    gpio.set_input(2, true).unwrap();
+    gpio.tick();
+    assert_eq!(0xe, gpio.read(INPUT_VAL));
+}
+
+#[test]
+fn gpio_iof() {
+    let mut gpio = Gpio::new();
+    let log = Rc::new(RefCell::new(vec![]));
+    gpio.set_event_log(Box::new(Log(log.clone())));
+
+    gpio.write(OUTPUT_EN, 0x30);
+    gpio.write(OUTPUT_VAL, 0x30);
+    gpio.set_iof_outputs(0, 0x10, 0);
+    gpio.set_iof_outputs(1, 0x30, 0x20);
+    gpio.write(IOF_EN, 0x30);
+    gpio.tick();
+    // the pins follow the IOF0 (pin 4) and IOF1 (pin 5) after the select.
+    assert!(!gpio.get_output(4));
+    assert!(!gpio.get_output(5));
+    gpio.write(IOF_SEL, 0x20);
+    gpio.tick();
+    assert!(gpio.get_output(5));
+    gpio.write(IOF_EN, 0);
+    gpio.tick();
+    assert_eq!(
+        vec![event(2, 5, true), event(3, 4, true)],
+        gpio.take_events()
+    );
+    assert_eq!("2 5 1\n3 4 1\n", String::from_utf8_lossy(&log.borrow()));
+}
+
+#[test]
+fn gpio_fe310() {
+    // Enable the rise interrupt of the pin 9 and set the output pin 3, then
+    // wait for the interrupt and print 'G' in its handler.
+    let program = [
+        0x100122b7, // lui t0, 0x10012 (GPIO)
+        0x20000313, // li t1, 0x200
+        0x0062a223, // sw t1, 4(t0) (input_en)
+        0x0062ac23, // sw t1, 24(t0) (rise_ie)
+        0x00800313, // li t1, 8
+        0x0062a423, // sw t1, 8(t0) (output_en)
+        0x0062a623, // sw t1, 12(t0) (output_val)
+        0x0c0003b7, // lui t2, 0xc000 (PLIC)
+        0x00100313, // li t1, 1
+        0x0463a223, // sw t1, 68(t2) (priority 17)
+        0x0c002e37, // lui t3, 0xc002
+        0x00020337, // lui t1, 0x20
+        0x006e2023, // sw t1, 0(t3) (enable 17)
+        0x00000317, // auipc t1, 0
+        0x02430313, // addi t1, t1, 36
+        0x30531073, // csrw mtvec, t1
+        0x00001337, // lui t1, 1
+        0x80030313, // addi t1, t1, -2048
+        0x30431073, // csrw mie, t1
+        0x30046073, // csrsi mstatus, 8
+        0x10500073, // 1: wfi
+        0xffdff06f, // j 1b
+        0x0c200e37, // lui t3, 0xc200
+        0x004e2e83, // lw t4, 4(t3) (claim)
+        0x10013f37, // lui t5, 0x10013 (UART0)
+        0x04700f93, // li t6, 'G'
+        0x01ff2023, // sw t6, 0(t5)
+        0x20000313, // li t1, 0x200
+        0x0062ae23, // sw t1, 28(t0) (rise_ip)
+        0x01de2223, // sw t4, 4(t3) (complete)
+        0x30200073, // mret
+    ];
+
+    let mut emulator = sifive(Machine::SiFiveE, &program);
+    emulator
+        .get_gpio()
+        .unwrap()
+        .schedule_input(500, 9, true)
+        .unwrap();
+    emulator.run_steps(400);
+    assert_eq!(0, emulator.get_console().get_output());
+    let gpio = emulator.get_gpio().unwrap();
+    let events = gpio.take_events();
+    assert_eq!(1, events.len());
+    assert_eq!(3, events[0].pin);
+    assert!(events[0].level);
+
+    emulator.run_steps(200);
+    assert_eq!(b'G', emulator.get_console().get_output());
+
+    // the PWM0 comparator 1 drives the pin 1 in the IOF1.
+    let pwm = emulator.get_pwm(0).unwrap();
+    pwm.write(0x20, 10);
+    pwm.write(0x24, 5);
+    pwm.write(0x00, 0x1200);
+    let gpio = emulator.get_gpio().unwrap();
+    gpio.write(IOF_SEL, 0x2);
+    gpio.write(IOF_EN, 0x2);
+    emulator.run_steps(100);
+    let rising: Vec<u64> = emulator
+        .get_gpio()
+        .unwrap()
+        .take_events()
+        .iter()
+        .filter(|event| event.pin == 1 && event.level)
+        .map(|event| event.cycle)
+        .collect();
+    assert!(rising.len() > 2);
+    assert!(rising.windows(2).all(|w| w[1] - w[0] == 11));
+
+    let mut emulator = Emulator::new(Machine::QemuVirt, Box::new(TtyBuffer::new()), false);
+    assert!(emulator.get_gpio().is_none());
+}
@@ -0,0 +1,327 @@
+extern crate riscv_emu;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use common::{add_buffer, get_used, setup_queue, DRAM_BASE, PAGE_SIZE};
+use riscv_emu::net::hub::UdpHub;
+use riscv_emu::net::pcap::PcapLoopback;
+use riscv_emu::net::slirp::{Slirp, GATEWAY_ADDRESS, GUEST_ADDRESS};
+use riscv_emu::net::NetBackend;
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::virtio::mmio::VirtioMmio;
+use riscv_emu::peripherals::virtio::net::{VirtioNet, DEFAULT_MAC_ADDRESS};
+
+const GUEST_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+fn ethernet_frame(dst: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = dst.to_vec();
+    frame.extend_from_slice(&GUEST_MAC);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// IPv4 + UDP without checksums, which are optional for the receiver.
+fn udp_frame(dst: Ipv4Addr, src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+    let src = match dst {
+        Ipv4Addr::BROADCAST => Ipv4Addr::UNSPECIFIED,
+        _ => GUEST_ADDRESS,
+    };
+    let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0];
+    let total_len = (20 + 8 + payload.len()) as u16;
+    packet[2..4].copy_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    packet.extend_from_slice(&src_port.to_be_bytes());
+    packet.extend_from_slice(&dst_port.to_be_bytes());
+    packet.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(payload);
+    ethernet_frame([0xff; 6], 0x0800, &packet)
+}
+
+/// (source address, source port, destination port, payload) of a UDP frame.
+fn parse_udp_frame(frame: &[u8]) -> (Ipv4Addr, u16, u16, Vec<u8>) {
+    assert_eq!(&frame[12..14], &[0x08, 0x00]);
+    let ip = &frame[14..];
+    assert_eq!(ip[9], 17);
+    let header_len = ((ip[0] & 0xf) * 4) as usize;
+    let udp = &ip[header_len..];
+    let len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    (
+        Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]),
+        u16::from_be_bytes([udp[0], udp[1]]),
+        u16::from_be_bytes([udp[2], udp[3]]),
+        udp[8..len].to_vec(),
+    )
+}
+
+/// IPv4 + TCP from the guest to the gateway without checksums.
+fn tcp_frame(
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0];
+    let total_len = (20 + 20 + payload.len()) as u16;
+    packet[2..4].copy_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&GUEST_ADDRESS.octets());
+    packet.extend_from_slice(&GATEWAY_ADDRESS.octets());
+    packet.extend_from_slice(&src_port.to_be_bytes());
+    packet.extend_from_slice(&dst_port.to_be_bytes());
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(&ack.to_be_bytes());
+    packet.extend_from_slice(&[5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    packet.extend_from_slice(payload);
+    ethernet_frame([0x52, 0x55, 0x0a, 0x00, 0x02, 0x02], 0x0800, &packet)
+}
+
+/// (sequence number, acknowledgment number, flags, window, payload) of a TCP
+/// frame.
+fn parse_tcp_frame(frame: &[u8]) -> (u32, u32, u8, u16, Vec<u8>) {
+    assert_eq!(&frame[12..14], &[0x08, 0x00]);
+    let ip = &frame[14..];
+    assert_eq!(ip[9], 6);
+    let header_len = ((ip[0] & 0xf) * 4) as usize;
+    let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+    let tcp = &ip[header_len..total_len];
+    let data_offset = (tcp[12] >> 4) as usize * 4;
+    (
+        u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+        u32::from_be_bytes([tcp[8], tcp[9], tcp[10], tcp[11]]),
+        tcp[13],
+        u16::from_be_bytes([tcp[14], tcp[15]]),
+        tcp[data_offset..].to_vec(),
+    )
+}
+
+fn recv_with_retry(backend: &mut dyn NetBackend) -> Option<Vec<u8>> {
+    for _ in 0..200 {
+        if let Some(frame) = backend.recv() {
+            return Some(frame);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    None
+}
+
+#[test]
+fn slirp_dhcp() {
+    let mut slirp = Slirp::new();
+
+    // DHCPDISCOVER
+    let mut message = vec![0; 240];
+    message[0] = 1; // BOOTREQUEST
+    message[1] = 1; // ethernet
+    message[2] = 6;
+    message[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+    message[28..34].copy_from_slice(&GUEST_MAC);
+    message[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+    message.extend_from_slice(&[53, 1, 1, 255]);
+    slirp.send(&udp_frame(Ipv4Addr::BROADCAST, 68, 67, &message));
+
+    let frame = slirp.recv().expect("no DHCPOFFER");
+    let (src, src_port, dst_port, reply) = parse_udp_frame(&frame);
+    assert_eq!(GATEWAY_ADDRESS, src);
+    assert_eq!((67, 68), (src_port, dst_port));
+    assert_eq!(2, reply[0]); // BOOTREPLY
+    assert_eq!(&[0xde, 0xad, 0xbe, 0xef], &reply[4..8]);
+    assert_eq!(&GUEST_ADDRESS.octets(), &reply[16..20]);
+    // the message type option follows the magic cookie.
+    assert_eq!(&[53, 1, 2], &reply[240..243]);
+    assert!(slirp.recv().is_none());
+}
+
+#[test]
+fn slirp_arp() {
+    let mut slirp = Slirp::new();
+    let mut request = vec![0, 1, 8, 0, 6, 4, 0, 1];
+    request.extend_from_slice(&GUEST_MAC);
+    request.extend_from_slice(&GUEST_ADDRESS.octets());
+    request.extend_from_slice(&[0; 6]);
+    request.extend_from_slice(&GATEWAY_ADDRESS.octets());
+    slirp.send(&ethernet_frame([0xff; 6], 0x0806, &request));
+
+    let reply = slirp.recv().expect("no ARP reply");
+    assert_eq!(&GUEST_MAC, &reply[0..6]);
+    assert_eq!(&[0x08, 0x06], &reply[12..14]);
+    assert_eq!(&[0, 2], &reply[20..22]); // reply
+    assert_eq!(&GATEWAY_ADDRESS.octets(), &reply[28..32]);
+    assert_eq!(&GUEST_MAC, &reply[32..38]);
+}
+
+#[test]
+fn slirp_udp_nat() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    // the gateway is the host loopback.
+    let mut slirp = Slirp::new();
+    slirp.send(&udp_frame(GATEWAY_ADDRESS, 1234, port, b"ping"));
+
+    let mut buf = [0; 16];
+    let (len, from) = server.recv_from(&mut buf).unwrap();
+    assert_eq!(b"ping", &buf[..len]);
+    server.send_to(b"pong", from).unwrap();
+
+    let frame = recv_with_retry(&mut slirp).expect("no UDP reply");
+    let (src, src_port, dst_port, payload) = parse_udp_frame(&frame);
+    assert_eq!(GATEWAY_ADDRESS, src);
+    assert_eq!((port, 1234), (src_port, dst_port));
+    assert_eq!(b"pong", &payload[..]);
+}
+
+#[test]
+fn slirp_dns_forwarding() {
+    let resolver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    resolver
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+
+    let mut slirp = Slirp::new();
+    slirp.set_dns_server(resolver.local_addr().unwrap());
+    slirp.send(&udp_frame(Ipv4Addr::new(10, 0, 2, 3), 5353, 53, b"query"));
+
+    let mut buf = [0; 16];
+    let (len, from) = resolver.recv_from(&mut buf).unwrap();
+    assert_eq!(b"query", &buf[..len]);
+    resolver.send_to(b"answer", from).unwrap();
+
+    let frame = recv_with_retry(&mut slirp).expect("no DNS reply");
+    let (src, src_port, dst_port, payload) = parse_udp_frame(&frame);
+    assert_eq!(Ipv4Addr::new(10, 0, 2, 3), src);
+    assert_eq!((53, 5353), (src_port, dst_port));
+    assert_eq!(b"answer", &payload[..]);
+}
+
+#[test]
+fn slirp_tcp_nat() {
+    const FIN: u8 = 0x01;
+    const SYN: u8 = 0x02;
+    const PSH: u8 = 0x08;
+    const ACK: u8 = 0x10;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    // the host connects in the background and the NAT answers the SYN later.
+    let mut slirp = Slirp::new();
+    slirp.send(&tcp_frame(1234, port, 100, 0, SYN, &[]));
+    let frame = recv_with_retry(&mut slirp).expect("no SYN-ACK");
+    let (isn, ack, flags, window, _) = parse_tcp_frame(&frame);
+    assert_eq!((SYN | ACK, 101, 0xffff), (flags, ack, window));
+    let (mut stream, _) = listener.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+
+    slirp.send(&tcp_frame(1234, port, 101, isn + 1, PSH | ACK, b"ping"));
+    let (_, ack, flags, _, _) = parse_tcp_frame(&slirp.recv().expect("no ACK"));
+    assert_eq!((ACK, 105), (flags, ack));
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(b"ping", &buf);
+
+    // data the guest does not acknowledge is sent again.
+    stream.write_all(b"pong").unwrap();
+    let frame = recv_with_retry(&mut slirp).expect("no data");
+    assert_eq!((isn + 1, b"pong".to_vec()), {
+        let (seq, _, _, _, payload) = parse_tcp_frame(&frame);
+        (seq, payload)
+    });
+    let frame = recv_with_retry(&mut slirp).expect("no retransmission");
+    let (seq, _, flags, _, payload) = parse_tcp_frame(&frame);
+    assert_eq!(
+        (isn + 1, PSH | ACK, b"pong".to_vec()),
+        (seq, flags, payload)
+    );
+
+    // the host closes, and the FIN is acknowledged with the data.
+    drop(stream);
+    let frame = recv_with_retry(&mut slirp).expect("no FIN");
+    let (seq, _, flags, _, _) = parse_tcp_frame(&frame);
+    assert_eq!((isn + 5, FIN | ACK), (seq, flags));
+    slirp.send(&tcp_frame(1234, port, 105, isn + 6, ACK, &[]));
+    thread::sleep(Duration::from_millis(1500));
+    assert!(slirp.recv().is_none());
+}
+
+#[test]
+fn udp_hub() {
+    let mut hub0 = UdpHub::new("127.0.0.1:0").unwrap();
+    let mut hub1 = UdpHub::new("127.0.0.1:0").unwrap();
+    hub0.add_peer(hub1.get_local_addr().unwrap()).unwrap();
+    hub1.add_peer(hub0.get_local_addr().unwrap()).unwrap();
+
+    let frame = ethernet_frame([0xff; 6], 0x0800, b"hello");
+    hub0.send(&frame);
+    assert_eq!(Some(frame.clone()), recv_with_retry(&mut hub1));
+    hub1.send(&frame);
+    assert_eq!(Some(frame), recv_with_retry(&mut hub0));
+}
+
+#[test]
+fn virtio_net_loopback() {
+    let mut dram = Memory::new(0x20000);
+    let loopback = PcapLoopback::new(vec![]).unwrap();
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(VirtioNet::new(Box::new(loopback))));
+
+    assert_eq!(0x74726976, virtio.read(0x000)); // "virt"
+    assert_eq!(1, virtio.read(0x008)); // network card
+    assert_eq!(1 << 5, virtio.read(0x010) & (1 << 5)); // VIRTIO_NET_F_MAC
+    let mac = virtio.read(0x100).to_le_bytes();
+    assert_eq!(&DEFAULT_MAC_ADDRESS[..4], &mac[..]);
+
+    virtio.write(0x028, PAGE_SIZE as u32); // GuestPageSize
+    setup_queue(&mut virtio, 0); // receiveq
+    setup_queue(&mut virtio, 1); // transmitq
+
+    // a receive buffer and a packet (virtio_net_hdr and frame) to transmit.
+    let frame = ethernet_frame(DEFAULT_MAC_ADDRESS, 0x0800, b"loopback");
+    let mut packet = vec![0; 10];
+    packet.extend_from_slice(&frame);
+    add_buffer(&mut dram, 0, &[], true);
+    add_buffer(&mut dram, 1, &packet, false);
+    virtio.write(0x050, 0); // QueueNotify
+    virtio.write(0x050, 1);
+
+    for _ in 0..0x1000 {
+        virtio.tick(&mut dram);
+    }
+    assert!(virtio.is_irq());
+    assert_eq!(vec![Vec::<u8>::new()], get_used(&dram, 1));
+    assert_eq!(vec![packet], get_used(&dram, 0));
+
+    // acknowledge the interrupt.
+    let status = virtio.read(0x060);
+    virtio.write(0x064, status);
+    assert!(!virtio.is_irq());
+}
+
+#[test]
+fn pcap_capture() {
+    let mut loopback = PcapLoopback::new(vec![]).unwrap();
+    let frame = ethernet_frame([0xff; 6], 0x0806, &[0; 28]);
+    loopback.send(&frame);
+    assert_eq!(Some(frame.clone()), loopback.recv());
+    assert!(loopback.recv().is_none());
+
+    let capture = loopback.get_capture();
+    assert_eq!(24 + 16 + frame.len(), capture.len());
+    assert_eq!(&[0xd4, 0xc3, 0xb2, 0xa1], &capture[0..4]);
+    assert_eq!(&[1, 0, 0, 0], &capture[20..24]); // LINKTYPE_ETHERNET
+    assert_eq!(&(frame.len() as u32).to_le_bytes(), &capture[32..36]);
+    assert_eq!(&frame[..], &capture[40..]);
+}
@@ -0,0 +1,337 @@
+extern crate riscv_emu;
+
+use riscv_emu::block::memory::MemoryDisk;
+use riscv_emu::block::BlockBackend;
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::pci::host::PciHost;
+use riscv_emu::peripherals::pci::nvme::Nvme;
+
+use std::io;
+
+const DRAM_BASE: u64 = 0x8000_0000;
+const WINDOW_BASE: u64 = 0x4000_0000;
+const PAGE_SIZE: u64 = 0x1000;
+
+// Queues and buffers (offsets in the memory)
+const ADMIN_SQ: u64 = 0x1000;
+const ADMIN_CQ: u64 = 0x2000;
+const IO_SQ: u64 = 0x3000;
+const IO_CQ: u64 = 0x4000;
+const PRP_LIST: u64 = 0x5000;
+const DATA: u64 = 0x10000;
+
+const QUEUE_SIZE: u64 = 4;
+
+// Status (the status code type in the upper byte)
+const SUCCESS: u16 = 0x000;
+const INVALID_OPCODE: u16 = 0x001;
+const DATA_XFER_ERROR: u16 = 0x004;
+const INVALID_NS: u16 = 0x00b;
+const NS_WRITE_PROTECTED: u16 = 0x020;
+const LBA_RANGE: u16 = 0x080;
+const CQ_INVALID: u16 = 0x100;
+const QID_INVALID: u16 = 0x101;
+
+/// Disk of a slice, read-only.
+struct ReadOnlyDisk {
+    disk: MemoryDisk,
+}
+
+impl BlockBackend for ReadOnlyDisk {
+    fn get_size(&self) -> u64 {
+        self.disk.get_size()
+    }
+
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        self.disk.read_at(offset, data)
+    }
+
+    fn write_at(&mut self, _offset: u64, _data: &[u8]) -> io::Result<()> {
+        panic!("write to a read-only disk");
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+/// Controller on the PCI bus with the queue state of a driver.
+struct Driver {
+    host: PciHost,
+    dram: Memory,
+    bar: u64,
+    /// submission queue tails, completion queue heads and phases
+    sq_tail: [u64; 2],
+    cq_head: [u64; 2],
+    phase: [bool; 2],
+    cid: u16,
+}
+
+impl Driver {
+    fn new(disk: Box<dyn BlockBackend>) -> Self {
+        let mut host = PciHost::new(WINDOW_BASE, 0x4000_0000);
+        assert_eq!(Ok(1), host.attach(Box::new(Nvme::new(DRAM_BASE, disk))));
+        host.write_config(1 << 15 | 0x04, 0x6, 2); // memory space, bus master
+        let bar = host.read_config(1 << 15 | 0x10, 4) as u64;
+        Driver {
+            host,
+            dram: Memory::new(0x40000),
+            bar,
+            sq_tail: [0; 2],
+            cq_head: [0; 2],
+            phase: [true; 2],
+            cid: 0,
+        }
+    }
+
+    fn enable(&mut self) {
+        let bar = self.bar;
+        self.host
+            .write(bar + 0x24, (QUEUE_SIZE - 1) << 16 | (QUEUE_SIZE - 1), 4); // AQA
+        self.host.write(bar + 0x28, DRAM_BASE + ADMIN_SQ, 8); // ASQ
+        self.host.write(bar + 0x30, DRAM_BASE + ADMIN_CQ, 8); // ACQ
+        self.host.write(bar + 0x14, 0x0046_0001, 4); // CC: IOSQES, IOCQES, EN
+        assert_eq!(1, self.host.read(bar + 0x1c, 4) & 0x1); // CSTS.RDY
+    }
+
+    /// Submit a command and return the status and the result.
+    fn submit(&mut self, qid: usize, dw: [u32; 16]) -> (u16, u32) {
+        let (sq, cq) = match qid {
+            0 => (ADMIN_SQ, ADMIN_CQ),
+            _ => (IO_SQ, IO_CQ),
+        };
+        self.cid += 1;
+        let addr = sq + self.sq_tail[qid] * 64;
+        for (i, data) in dw.iter().enumerate() {
+            self.dram.write32(addr + i as u64 * 4, *data);
+        }
+        self.dram
+            .write32(addr, dw[0] & 0xffff | (self.cid as u32) << 16);
+        self.sq_tail[qid] = (self.sq_tail[qid] + 1) % QUEUE_SIZE;
+        let doorbell = self.bar + 0x1000 + qid as u64 * 8;
+        self.host.write(doorbell, self.sq_tail[qid], 4);
+        self.host.tick(&mut self.dram);
+        assert_eq!(vec![1], self.host.get_interrupts()); // INTB
+
+        let entry = cq + self.cq_head[qid] * 16;
+        let result = self.dram.read32(entry);
+        assert_eq!(
+            self.sq_tail[qid] as u32 | (qid as u32) << 16,
+            self.dram.read32(entry + 8)
+        );
+        let dw3 = self.dram.read32(entry + 12);
+        assert_eq!(self.cid as u32, dw3 & 0xffff);
+        assert_eq!(self.phase[qid], dw3 & 0x1_0000 != 0);
+        self.cq_head[qid] = (self.cq_head[qid] + 1) % QUEUE_SIZE;
+        if self.cq_head[qid] == 0 {
+            self.phase[qid] = !self.phase[qid];
+        }
+        self.host.write(doorbell + 4, self.cq_head[qid], 4);
+        self.host.tick(&mut self.dram);
+        assert!(self.host.get_interrupts().is_empty());
+        (((dw3 >> 17) & 0x3fff) as u16 & 0xfff, result)
+    }
+
+    fn create_io_queues(&mut self) {
+        let mut cmd = [0; 16];
+        cmd[0] = 0x05; // create I/O completion queue
+        cmd[6] = (DRAM_BASE + IO_CQ) as u32;
+        cmd[7] = ((DRAM_BASE + IO_CQ) >> 32) as u32;
+        cmd[10] = (QUEUE_SIZE as u32 - 1) << 16 | 1;
+        cmd[11] = 0x3; // physically contiguous, interrupts enabled
+        assert_eq!(SUCCESS, self.submit(0, cmd).0);
+        assert_eq!(QID_INVALID, self.submit(0, cmd).0);
+
+        let mut cmd = [0; 16];
+        cmd[0] = 0x01; // create I/O submission queue
+        cmd[6] = (DRAM_BASE + IO_SQ) as u32;
+        cmd[7] = ((DRAM_BASE + IO_SQ) >> 32) as u32;
+        cmd[10] = (QUEUE_SIZE as u32 - 1) << 16 | 1;
+        cmd[11] = 2 << 16 | 0x1; // completion queue 2
+        assert_eq!(CQ_INVALID, self.submit(0, cmd).0);
+        cmd[11] = 1 << 16 | 0x1;
+        assert_eq!(SUCCESS, self.submit(0, cmd).0);
+    }
+
+    /// Read or write blocks with the data at DATA, described by a PRP list.
+    fn transfer(&mut self, opcode: u32, nsid: u32, slba: u64, nlb: u32) -> u16 {
+        let pages = (nlb as u64 * 512).div_ceil(PAGE_SIZE);
+        for i in 1..pages {
+            self.dram
+                .write64(PRP_LIST + (i - 1) * 8, DRAM_BASE + DATA + i * PAGE_SIZE);
+        }
+        let prp2 = match pages {
+            0 | 1 => 0,
+            2 => DRAM_BASE + DATA + PAGE_SIZE,
+            _ => DRAM_BASE + PRP_LIST,
+        };
+        let mut cmd = [0; 16];
+        cmd[0] = opcode;
+        cmd[1] = nsid;
+        cmd[6] = (DRAM_BASE + DATA) as u32;
+        cmd[7] = ((DRAM_BASE + DATA) >> 32) as u32;
+        cmd[8] = prp2 as u32;
+        cmd[9] = (prp2 >> 32) as u32;
+        cmd[10] = slba as u32;
+        cmd[11] = (slba >> 32) as u32;
+        cmd[12] = nlb - 1;
+        self.submit(1, cmd).0
+    }
+}
+
+#[test]
+fn nvme_identify() {
+    let mut driver = Driver::new(Box::new(MemoryDisk::new(vec![0; 0x10_0000])));
+    assert_eq!(0x0010_1b36, driver.host.read_config(1 << 15, 4));
+    assert_eq!(0x01_08_02, driver.host.read_config(1 << 15 | 0x08, 4) >> 8);
+    let bar = driver.bar;
+    let cap = driver.host.read(bar, 8);
+    assert_eq!(0x3ff, cap & 0xffff); // MQES
+    assert_ne!(0, cap & 1 << 37); // NVM command set
+    assert_eq!(0x0001_0400, driver.host.read(bar + 0x08, 4));
+    driver.enable();
+
+    // controller
+    let mut cmd = [0; 16];
+    cmd[0] = 0x06;
+    cmd[6] = (DRAM_BASE + DATA) as u32;
+    cmd[7] = ((DRAM_BASE + DATA) >> 32) as u32;
+    cmd[10] = 1;
+    assert_eq!(SUCCESS, driver.submit(0, cmd).0);
+    let data = &driver.dram.mem[DATA as usize..];
+    assert_eq!(&b"riscv-emu           "[..], &data[0x04..0x18]);
+    assert_eq!(0x66, data[0x200]); // SQES
+    assert_eq!(1, data[0x204]); // NN
+
+    // namespace
+    cmd[1] = 1;
+    cmd[10] = 0;
+    assert_eq!(SUCCESS, driver.submit(0, cmd).0);
+    assert_eq!(0x800, driver.dram.read64(DATA)); // NSZE
+    assert_eq!(0, driver.dram.mem[DATA as usize + 0x63]); // writable
+    assert_eq!(9, driver.dram.mem[DATA as usize + 0x82]); // LBADS
+    cmd[1] = 2;
+    assert_eq!(INVALID_NS, driver.submit(0, cmd).0);
+
+    // active namespaces
+    cmd[1] = 0;
+    cmd[10] = 2;
+    assert_eq!(SUCCESS, driver.submit(0, cmd).0);
+    assert_eq!(1, driver.dram.read32(DATA));
+    assert_eq!(0, driver.dram.read32(DATA + 4));
+
+    // number of queues
+    let mut cmd = [0; 16];
+    cmd[0] = 0x09;
+    cmd[10] = 0x07;
+    cmd[11] = 0x0003_0003;
+    assert_eq!((SUCCESS, 0x003f_003f), driver.submit(0, cmd));
+
+    let mut cmd = [0; 16];
+    cmd[0] = 0x7f;
+    assert_eq!(INVALID_OPCODE, driver.submit(0, cmd).0);
+
+    // shutdown
+    driver.host.write(bar + 0x14, 0x0046_4001, 4);
+    assert_eq!(0x2 << 2, driver.host.read(bar + 0x1c, 4) & 0xc);
+    // disabling resets the controller.
+    driver.host.write(bar + 0x14, 0, 4);
+    assert_eq!(0, driver.host.read(bar + 0x1c, 4) & 0x1);
+}
+
+#[test]
+fn nvme_read_write() {
+    let disk: Vec<u8> = (0..0x10_0000).map(|i| (i / 512) as u8).collect();
+    let mut driver = Driver::new(Box::new(MemoryDisk::new(disk)));
+    driver.enable();
+    driver.create_io_queues();
+
+    // 16 KiB, through a PRP list
+    assert_eq!(SUCCESS, driver.transfer(0x02, 1, 4, 32));
+    for i in 0..32 {
+        let offset = DATA as usize + i * 512;
+        assert_eq!(
+            vec![4 + i as u8; 512],
+            driver.dram.mem[offset..offset + 512].to_vec()
+        );
+    }
+
+    // 8 KiB, the second page in PRP2
+    for i in 0..0x2000 {
+        driver.dram.mem[DATA as usize + i] = 0xa5;
+    }
+    assert_eq!(SUCCESS, driver.transfer(0x01, 1, 0x100, 16));
+    assert_eq!(SUCCESS, driver.transfer(0x00, 1, 0, 1)); // flush
+    for i in 0..0x3000 {
+        driver.dram.mem[DATA as usize + i] = 0;
+    }
+    assert_eq!(SUCCESS, driver.transfer(0x02, 1, 0xff, 18));
+    let data = &driver.dram.mem[DATA as usize..DATA as usize + 18 * 512];
+    assert_eq!(vec![0xff; 512], data[..512].to_vec());
+    assert_eq!(vec![0xa5; 0x2000], data[512..512 + 0x2000].to_vec());
+    assert_eq!(vec![0x10; 512], data[512 + 0x2000..].to_vec());
+
+    // write zeroes
+    assert_eq!(SUCCESS, driver.transfer(0x08, 1, 0x100, 1));
+    assert_eq!(SUCCESS, driver.transfer(0x02, 1, 0x100, 2));
+    assert_eq!(
+        vec![0; 512],
+        driver.dram.mem[DATA as usize..DATA as usize + 512].to_vec()
+    );
+    assert_eq!(0xa5, driver.dram.mem[DATA as usize + 512]);
+
+    // errors
+    assert_eq!(LBA_RANGE, driver.transfer(0x02, 1, 0x7ff, 2));
+    assert_eq!(INVALID_NS, driver.transfer(0x02, 2, 0, 1));
+
+    // the last entry of the PRP list page points to itself.
+    let prp2 = DRAM_BASE + PRP_LIST + PAGE_SIZE - 8;
+    driver.dram.write64(PRP_LIST + PAGE_SIZE - 8, prp2);
+    let mut cmd = [0; 16];
+    cmd[0] = 0x02;
+    cmd[1] = 1;
+    cmd[6] = (DRAM_BASE + DATA) as u32;
+    cmd[7] = ((DRAM_BASE + DATA) >> 32) as u32;
+    cmd[8] = prp2 as u32;
+    cmd[9] = (prp2 >> 32) as u32;
+    cmd[12] = 31;
+    assert_eq!(DATA_XFER_ERROR, driver.submit(1, cmd).0);
+    // the data is out of main memory.
+    cmd[6] = 0;
+    cmd[7] = 0;
+    cmd[12] = 0;
+    assert_eq!(DATA_XFER_ERROR, driver.submit(1, cmd).0);
+
+    // the I/O queues are deleted in order.
+    let mut cmd = [0; 16];
+    cmd[0] = 0x04;
+    cmd[10] = 1;
+    assert_eq!(0x10c, driver.submit(0, cmd).0);
+    cmd[0] = 0x00;
+    assert_eq!(SUCCESS, driver.submit(0, cmd).0);
+    cmd[0] = 0x04;
+    assert_eq!(SUCCESS, driver.submit(0, cmd).0);
+}
+
+#[test]
+fn nvme_read_only() {
+    let disk = MemoryDisk::new(vec![0x5a; 0x10000]);
+    let mut driver = Driver::new(Box::new(ReadOnlyDisk { disk }));
+    driver.enable();
+    driver.create_io_queues();
+
+    let mut cmd = [0; 16];
+    cmd[0] = 0x06;
+    cmd[1] = 1;
+    cmd[6] = (DRAM_BASE + DATA) as u32;
+    cmd[7] = ((DRAM_BASE + DATA) >> 32) as u32;
+    assert_eq!(SUCCESS, driver.submit(0, cmd).0);
+    assert_eq!(1, driver.dram.mem[DATA as usize + 0x63] & 0x1); // write protected
+
+    assert_eq!(NS_WRITE_PROTECTED, driver.transfer(0x01, 1, 0, 1));
+    assert_eq!(SUCCESS, driver.transfer(0x02, 1, 0, 1));
+    assert_eq!(
+        vec![0x5a; 512],
+        driver.dram.mem[DATA as usize..DATA as usize + 512].to_vec()
+    );
+}
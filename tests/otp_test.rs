@@ -0,0 +1,185 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{sifive, to_bytes};
+use riscv_emu::block::file::FileDisk;
+use riscv_emu::block::memory::MemoryDisk;
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::peripherals::fe310_g002::otp::{Otp, OTP_SIZE};
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+// OTP controller registers
+const OTP_LOCK: u64 = 0x00;
+const OTP_CK: u64 = 0x04;
+const OTP_OE: u64 = 0x08;
+const OTP_SEL: u64 = 0x0C;
+const OTP_WE: u64 = 0x10;
+const OTP_VRREN: u64 = 0x20;
+const OTP_VPPEN: u64 = 0x24;
+const OTP_A: u64 = 0x28;
+const OTP_D: u64 = 0x2C;
+const OTP_Q: u64 = 0x30;
+
+/// OTP file, removed when dropped.
+struct TestFile {
+    path: PathBuf,
+}
+
+impl TestFile {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("riscv_emu_otp_{}_{}", name, process::id()));
+        fs::write(&path, vec![0; OTP_SIZE]).unwrap();
+        TestFile { path }
+    }
+}
+
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The programming sequence of a word, the lock must be held.
+fn program(otp: &mut Otp, index: u32, data: u32) {
+    otp.write(OTP_SEL, 1);
+    otp.write(OTP_VPPEN, 1);
+    otp.write(OTP_A, index);
+    otp.write(OTP_D, data);
+    otp.write(OTP_WE, 1);
+    otp.write(OTP_CK, 1);
+    otp.write(OTP_CK, 0);
+    otp.write(OTP_WE, 0);
+    otp.write(OTP_VPPEN, 0);
+    otp.write(OTP_SEL, 0);
+}
+
+/// Read a word with the device signals, the lock must be held.
+fn read(otp: &mut Otp, index: u32) -> u32 {
+    otp.write(OTP_SEL, 1);
+    otp.write(OTP_VRREN, 1);
+    otp.write(OTP_OE, 1);
+    otp.write(OTP_A, index);
+    otp.write(OTP_CK, 1);
+    otp.write(OTP_CK, 0);
+    otp.write(OTP_OE, 0);
+    otp.write(OTP_VRREN, 0);
+    otp.write(OTP_SEL, 0);
+    otp.read(OTP_Q)
+}
+
+#[test]
+fn otp_programming() {
+    let mut otp = Otp::new();
+    assert_eq!(0, otp.read_memory32(0x10));
+
+    // the device signals are ignored without the lock.
+    program(&mut otp, 4, 0x1234_5678);
+    assert_eq!(0, otp.read_memory32(0x10));
+    assert_eq!(0, otp.read(OTP_SEL));
+
+    otp.write(OTP_LOCK, 1);
+    assert_eq!(1, otp.read(OTP_LOCK));
+    program(&mut otp, 4, 0x0000_00f0);
+    assert_eq!(0x0000_00f0, otp.read_memory32(0x10));
+    assert_eq!(0xf0, otp.read_memory8(0x10));
+
+    // the bits are programmed once: they can be set, never cleared.
+    program(&mut otp, 4, 0x0000_000f);
+    assert_eq!(0x0000_00ff, otp.read_memory32(0x10));
+    program(&mut otp, 4, 0);
+    assert_eq!(0x0000_00ff, otp.read_memory32(0x10));
+    assert_eq!(0x0000_00ff, read(&mut otp, 4));
+    assert_eq!(0, read(&mut otp, 5));
+
+    // a reset releases the lock and keeps the fuses.
+    otp.reset();
+    assert_eq!(0, otp.read(OTP_LOCK));
+    assert_eq!(0x0000_00ff, otp.read_memory32(0x10));
+}
+
+#[test]
+fn otp_persistent_file() {
+    let file = TestFile::new("persistent");
+    {
+        let mut otp = Otp::new();
+        otp.set_backend(Box::new(FileDisk::open(&file.path, false).unwrap()));
+        otp.write(OTP_LOCK, 1);
+        program(&mut otp, 0, 0xdead_beef);
+        program(&mut otp, (OTP_SIZE / 4 - 1) as u32, 0x8000_0001);
+    }
+    let data = fs::read(&file.path).unwrap();
+    assert_eq!(OTP_SIZE, data.len());
+    assert_eq!([0xef, 0xbe, 0xad, 0xde], data[0..4]);
+
+    // the next run reads the fuses back.
+    let mut otp = Otp::new();
+    otp.set_backend(Box::new(FileDisk::open(&file.path, false).unwrap()));
+    assert_eq!(0xdead_beef, otp.read_memory32(0));
+    assert_eq!(0x8000_0001, otp.read_memory32((OTP_SIZE - 4) as u64));
+}
+
+#[test]
+fn fe310_otp() {
+    // Program the word 0 of the OTP with 'O', read it through the memory
+    // mapped region and print it.
+    let program = [
+        0x100102b7, // lui t0, 0x10010 (OTP controller)
+        0x00100313, // li t1, 1
+        0x0062a023, // sw t1, 0(t0) (otp_lock)
+        0x0062a623, // sw t1, 12(t0) (otp_sel)
+        0x0262a223, // sw t1, 36(t0) (otp_vppen)
+        0x0202a423, // sw zero, 40(t0) (otp_a)
+        0x04f00393, // li t2, 'O'
+        0x0272a623, // sw t2, 44(t0) (otp_d)
+        0x0062a823, // sw t1, 16(t0) (otp_we)
+        0x0062a223, // sw t1, 4(t0) (otp_ck)
+        0x0002a223, // sw zero, 4(t0)
+        0x0002a823, // sw zero, 16(t0)
+        0x0202a223, // sw zero, 36(t0)
+        0x0002a623, // sw zero, 12(t0)
+        0x0002a023, // sw zero, 0(t0)
+        0x00020e37, // lui t3, 0x20 (OTP)
+        0x000e2e83, // lw t4, 0(t3)
+        0x10013f37, // lui t5, 0x10013 (UART0)
+        0x01df2023, // sw t4, 0(t5)
+        0x0000006f, // j .
+    ];
+
+    let file = TestFile::new("fe310");
+    let mut emulator = sifive(Machine::SiFiveE, &program);
+    emulator
+        .set_otp(Box::new(FileDisk::open(&file.path, false).unwrap()))
+        .unwrap();
+    emulator.run_steps(100);
+    assert_eq!(b'O', emulator.get_console().get_output());
+    assert_eq!(b'O', fs::read(&file.path).unwrap()[0]);
+
+    let mut emulator = Emulator::new(Machine::SiFiveU, Box::new(TtyBuffer::new()), false);
+    assert!(emulator
+        .set_otp(Box::new(MemoryDisk::new(vec![0; OTP_SIZE])))
+        .is_err());
+}
+
+#[test]
+fn fe310_boot_from_otp() {
+    // MSEL 0xb runs the program in the OTP.
+    let mut fuses = to_bytes(&[
+        0x100132b7, // lui t0, 0x10013 (UART0)
+        0x04f00313, // li t1, 'O'
+        0x0062a023, // sw t1, 0(t0)
+        0x0000006f, // j .
+    ]);
+    fuses.resize(OTP_SIZE, 0);
+
+    let mut emulator = Emulator::new(Machine::SiFiveE, Box::new(TtyBuffer::new()), false);
+    emulator.set_otp(Box::new(MemoryDisk::new(fuses))).unwrap();
+    emulator.set_mode_select(0xb).unwrap();
+    emulator.run_steps(100);
+    assert_eq!(b'O', emulator.get_console().get_output());
+}
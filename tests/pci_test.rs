@@ -0,0 +1,201 @@
+extern crate riscv_emu;
+
+use riscv_emu::block::memory::MemoryDisk;
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::pci::host::PciHost;
+use riscv_emu::peripherals::virtio::block::VirtioBlock;
+use riscv_emu::peripherals::virtio::pci::VirtioPci;
+
+const DRAM_BASE: u64 = 0x8000_0000;
+const WINDOW_BASE: u64 = 0x4000_0000;
+const WINDOW_SIZE: u64 = 0x4000_0000;
+
+// Virtqueue areas (offsets in the memory)
+const QUEUE_SIZE: u64 = 8;
+const DESC: u64 = 0x1000;
+const AVAIL: u64 = 0x2000;
+const USED: u64 = 0x3000;
+const HEADER: u64 = 0x10000;
+const DATA: u64 = 0x11000;
+const STATUS: u64 = 0x12000;
+
+// Command register bits
+const MEMORY: u32 = 0x2;
+const MASTER: u32 = 0x4;
+const INTX_DISABLE: u32 = 0x400;
+
+const STATUS_FEATURES_OK: u64 = 0x8;
+
+/// ECAM offset of a configuration register on the bus 0.
+fn config(slot: u64, reg: u64) -> u64 {
+    slot << 15 | reg
+}
+
+fn create_host(disk: Vec<u8>) -> PciHost {
+    let mut host = PciHost::new(WINDOW_BASE, WINDOW_SIZE);
+    let block = VirtioBlock::new(Box::new(MemoryDisk::new(disk)));
+    assert_eq!(
+        Ok(1),
+        host.attach(Box::new(VirtioPci::new(DRAM_BASE, Box::new(block))))
+    );
+    host
+}
+
+/// Find the virtio capability of the type, returns the offset of the
+/// structure in the BAR.
+fn find_virtio_cap(host: &mut PciHost, cfg_type: u32) -> Option<(u64, u32)> {
+    let mut cap = host.read_config(config(1, 0x34), 1) as u64;
+    while cap != 0 {
+        let header = host.read_config(config(1, cap), 4);
+        if header & 0xff == 0x09 && (header >> 24) == cfg_type {
+            let offset = host.read_config(config(1, cap + 8), 4) as u64;
+            let extra = host.read_config(config(1, cap + 16), 4);
+            return Some((offset, extra));
+        }
+        cap = ((header >> 8) & 0xff) as u64;
+    }
+    None
+}
+
+#[test]
+fn pci_enumeration() {
+    let mut host = create_host(vec![0; 0x1000]);
+
+    // the host bridge
+    assert_eq!(0x0008_1b36, host.read_config(config(0, 0x00), 4));
+    assert_eq!(0x06, host.read_config(config(0, 0x0b), 1));
+    // virtio-blk (modern)
+    assert_eq!(0x1af4, host.read_config(config(1, 0x00), 2));
+    assert_eq!(0x1042, host.read_config(config(1, 0x02), 2));
+    assert_eq!(0x0180_0001, host.read_config(config(1, 0x08), 4));
+    assert_eq!(0x0002_1af4, host.read_config(config(1, 0x2c), 4));
+    assert_eq!(0x0000_0100, host.read_config(config(1, 0x3c), 4) & 0xff00); // INTA
+    assert_ne!(0, host.read_config(config(1, 0x06), 2) & 0x10); // capability list
+
+    // empty slots and other functions
+    assert_eq!(0xffff_ffff, host.read_config(config(2, 0x00), 4));
+    assert_eq!(0xffff_ffff, host.read_config(1 << 12 | config(1, 0x00), 4));
+
+    assert_eq!(Some(0x0000), find_virtio_cap(&mut host, 1).map(|(o, _)| o));
+    // notify_off_multiplier
+    assert_eq!(Some((0x3000, 4)), find_virtio_cap(&mut host, 2));
+    assert_eq!(Some(0x1000), find_virtio_cap(&mut host, 3).map(|(o, _)| o));
+    assert_eq!(Some(0x2000), find_virtio_cap(&mut host, 4).map(|(o, _)| o));
+}
+
+#[test]
+fn pci_bar_sizing() {
+    let mut host = create_host(vec![0; 0x1000]);
+    let bar = host.read_config(config(1, 0x10), 4);
+    assert_eq!(WINDOW_BASE as u32, bar);
+    host.write_config(config(1, 0x10), 0xffff_ffff, 4);
+    assert_eq!(0xffff_c000, host.read_config(config(1, 0x10), 4));
+    assert_eq!(0, host.read_config(config(1, 0x14), 4)); // unused BAR
+
+    // the guest moves the BAR.
+    let bar = WINDOW_BASE + 0x10_0000;
+    host.write_config(config(1, 0x10), bar as u32, 4);
+    // no decoding until the memory space is enabled.
+    assert_eq!(0xffff, host.read(bar + 0x12, 2));
+    host.write_config(config(1, 0x04), MEMORY, 2);
+    assert_eq!(1, host.read(bar + 0x12, 2)); // num_queues
+    assert_eq!(0xffff, host.read(WINDOW_BASE + 0x12, 2));
+}
+
+#[test]
+fn pci_reset() {
+    let mut host = create_host(vec![0; 0x1000]);
+    let bar = WINDOW_BASE + 0x10_0000;
+    host.write_config(config(1, 0x10), bar as u32, 4);
+    host.write_config(config(1, 0x04), MEMORY | MASTER, 2);
+    host.write(bar + 0x14, 1, 1); // device_status: acknowledge
+    assert_eq!(1, host.read(bar + 0x14, 1));
+
+    // the BAR gets its initial address and the device forgets its state.
+    host.reset();
+    assert_eq!(0, host.read_config(config(1, 0x04), 2));
+    assert_eq!(WINDOW_BASE as u32, host.read_config(config(1, 0x10), 4));
+    host.write_config(config(1, 0x04), MEMORY, 2);
+    assert_eq!(0, host.read(WINDOW_BASE + 0x14, 1));
+}
+
+#[test]
+fn pci_virtio_block() {
+    let disk: Vec<u8> = (0..0x1000).map(|i| (i / 512) as u8).collect();
+    let mut host = create_host(disk);
+    let mut dram = Memory::new(0x20000);
+    let bar = host.read_config(config(1, 0x10), 4) as u64;
+    host.write_config(config(1, 0x04), MEMORY | MASTER, 2);
+
+    // capacity in the device configuration
+    assert_eq!(8, host.read(bar + 0x2000, 8));
+
+    // negotiate VERSION_1
+    host.write(bar + 0x14, 0x3, 1); // ACKNOWLEDGE | DRIVER
+    host.write(bar, 1, 4);
+    assert_eq!(1, host.read(bar + 0x04, 4) & 1); // VERSION_1
+    host.write(bar + 0x08, 1, 4);
+    host.write(bar + 0x0c, 1, 4);
+    assert_eq!(1, host.read(bar + 0x0c, 4));
+    host.write(bar + 0x14, 0x3 | STATUS_FEATURES_OK, 1);
+    assert_eq!(0x3 | STATUS_FEATURES_OK, host.read(bar + 0x14, 1));
+
+    // the queue 0
+    host.write(bar + 0x16, 0, 2);
+    assert_eq!(0x1000, host.read(bar + 0x18, 2));
+    host.write(bar + 0x18, QUEUE_SIZE, 2);
+    assert_eq!(QUEUE_SIZE, host.read(bar + 0x18, 2));
+    assert_eq!(0xffff, host.read(bar + 0x1a, 2)); // no MSI-X
+    host.write(bar + 0x20, DRAM_BASE + DESC, 8);
+    host.write(bar + 0x28, DRAM_BASE + AVAIL, 4);
+    host.write(bar + 0x2c, (DRAM_BASE + AVAIL) >> 32, 4);
+    host.write(bar + 0x30, DRAM_BASE + USED, 8);
+    assert_eq!(DRAM_BASE + AVAIL, host.read(bar + 0x28, 8));
+    host.write(bar + 0x1c, 1, 2);
+    assert_eq!(1, host.read(bar + 0x1c, 2));
+    host.write(bar + 0x14, 0xf, 1); // DRIVER_OK
+
+    // read the sector 3
+    dram.write32(HEADER, 0); // VIRTIO_BLK_T_IN
+    dram.write64(HEADER + 8, 3);
+    let buffers = [(HEADER, 16, 0x1), (DATA, 512, 0x1 | 0x2), (STATUS, 1, 0x2)];
+    for (i, (addr, len, flags)) in buffers.iter().enumerate() {
+        let desc = DESC + 16 * i as u64;
+        dram.write64(desc, DRAM_BASE + addr);
+        dram.write32(desc + 8, *len);
+        dram.write16(desc + 12, *flags);
+        dram.write16(desc + 14, i as u16 + 1);
+    }
+    dram.write16(AVAIL + 4, 0);
+    dram.write16(AVAIL + 2, 1);
+    let notify_off = host.read(bar + 0x1e, 2);
+    host.write(bar + 0x3000 + notify_off * 4, 0, 2);
+    for _ in 0..0x1000 {
+        host.tick(&mut dram);
+    }
+    assert_eq!(0, dram.mem[STATUS as usize]);
+    assert_eq!(
+        vec![3; 512],
+        dram.mem[DATA as usize..DATA as usize + 512].to_vec()
+    );
+    assert_eq!(1, dram.read16(USED + 2));
+
+    // INTA of the slot 1 is routed to INTB.
+    assert_eq!(vec![1], host.get_interrupts());
+    assert_ne!(0, host.read_config(config(1, 0x06), 2) & 0x8);
+    host.write_config(config(1, 0x04), MEMORY | MASTER | INTX_DISABLE, 2);
+    assert!(host.get_interrupts().is_empty());
+    host.write_config(config(1, 0x04), MEMORY | MASTER, 2);
+
+    // reading the ISR status acknowledges the interrupt.
+    assert_eq!(1, host.read(bar + 0x1000, 1));
+    assert_eq!(0, host.read(bar + 0x1000, 1));
+    host.tick(&mut dram);
+    assert!(host.get_interrupts().is_empty());
+
+    // reset
+    host.write(bar + 0x14, 0, 1);
+    assert_eq!(0, host.read(bar + 0x14, 1));
+    assert_eq!(0x1000, host.read(bar + 0x18, 2));
+    assert_eq!(0, host.read(bar + 0x1c, 2));
+}
@@ -0,0 +1,98 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{qemu_virt_dram, to_bytes};
+use std::path::PathBuf;
+
+use riscv_emu::console::TtyDummy;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::profiler::ProfilerMode;
+
+fn profile(filename: &'static str, mode: ProfilerMode) -> Emulator {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push("tests/bin");
+    root.push(filename);
+
+    let tty = Box::new(TtyDummy::new());
+    let mut emu = Emulator::new(Machine::SiFiveU, tty, true);
+    emu.load_program_from_file(root.as_path());
+    emu.enable_profiler(mode);
+    assert_eq!(Ok(1), emu.run());
+    emu
+}
+
+#[test]
+fn profiler_exact() {
+    let mut emu = profile("rv64ui-p-add", ProfilerMode::Exact);
+    let retired = emu.get_profiler().unwrap().get_retired();
+
+    // every retired instruction is accounted to exactly one function.
+    let hot_list = emu.get_profile_hot_list();
+    let total: u64 = hot_list.iter().map(|f| f.self_count).sum();
+    assert_eq!(retired, total);
+    assert!(hot_list.iter().any(|f| f.name == "reset_vector"));
+
+    let mut folded = Vec::new();
+    emu.write_profile_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    assert!(folded.lines().any(|l| l.starts_with("test_2 ")));
+}
+
+#[test]
+fn profiler_sampling() {
+    let mut emu = profile("rv32ui-p-add", ProfilerMode::Sampling(10));
+    let retired = emu.get_profiler().unwrap().get_retired();
+
+    let hot_list = emu.get_profile_hot_list();
+    let samples: u64 = hot_list.iter().map(|f| f.self_count).sum();
+    assert_eq!(retired / 10, samples);
+}
+
+fn sample_qemu_virt(program: &[u32], frame: &[u64]) -> Emulator {
+    let mut dram = to_bytes(program);
+    // the frame lies below 0x8000_1000.
+    dram.resize(0x1000 - frame.len() * 8, 0);
+    dram.extend(frame.iter().flat_map(|word| word.to_le_bytes().to_vec()));
+
+    let mut emu = qemu_virt_dram(dram);
+    emu.enable_stats();
+    emu.enable_profiler(ProfilerMode::Sampling(1));
+    emu.run_steps(100);
+    emu
+}
+
+#[test]
+fn profiler_unwind_frame() {
+    let emu = sample_qemu_virt(
+        &[
+            0x00001417, // auipc s0, 1
+            0x00128293, // 1: addi t0, t0, 1
+            0xffdff06f, // j 1b
+        ],
+        // previous frame pointer, return address
+        &[0, 0x8000_0104],
+    );
+    let mut folded = Vec::new();
+    emu.write_profile_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    assert!(folded
+        .lines()
+        .any(|l| l.starts_with("0x80000103;0x8000000")));
+}
+
+#[test]
+fn profiler_unwind_without_side_effects() {
+    // a frame pointer into the UART must not read (and pop) its registers.
+    let emu = sample_qemu_virt(
+        &[
+            0x10000437, // lui s0, 0x10000 (UART)
+            0x01040413, // addi s0, s0, 16
+            0x00128293, // 1: addi t0, t0, 1
+            0xffdff06f, // j 1b
+        ],
+        &[],
+    );
+    assert!(emu.get_memory_stats().unwrap().mmio.is_empty());
+}
@@ -0,0 +1,401 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::sifive;
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::peripherals::fe310_g002::i2c::Fe310I2c;
+use riscv_emu::peripherals::fe310_g002::pwm::{Pwm, PwmEdge};
+use riscv_emu::peripherals::i2c::eeprom::Eeprom;
+use riscv_emu::peripherals::i2c::temperature::TemperatureSensor;
+use riscv_emu::peripherals::i2c::I2cDevice;
+
+// PWM registers
+const PWMCFG: u64 = 0x00;
+const PWMCOUNT: u64 = 0x08;
+const PWMS: u64 = 0x10;
+const PWMCMP0: u64 = 0x20;
+const PWMCMP1: u64 = 0x24;
+const PWMCMP2: u64 = 0x28;
+const PWMCMP3: u64 = 0x2c;
+
+const PWMCFG_STICKY: u32 = 1 << 8;
+const PWMCFG_ZEROCMP: u32 = 1 << 9;
+const PWMCFG_ENALWAYS: u32 = 1 << 12;
+const PWMCFG_ENONESHOT: u32 = 1 << 13;
+const PWMCFG_CENTER0: u32 = 1 << 16;
+const PWMCFG_GANG0: u32 = 1 << 24;
+
+// I2C registers
+const I2C_CTR: u64 = 0x08;
+const I2C_TXR: u64 = 0x0c;
+const I2C_RXR: u64 = 0x0c;
+const I2C_CR: u64 = 0x10;
+const I2C_SR: u64 = 0x10;
+
+const CTR_EN: u32 = 0x80;
+const CTR_IEN: u32 = 0x40;
+const CR_STA: u32 = 0x80;
+const CR_STO: u32 = 0x40;
+const CR_RD: u32 = 0x20;
+const CR_WR: u32 = 0x10;
+const CR_ACK: u32 = 0x08;
+const CR_IACK: u32 = 0x01;
+const SR_RXACK: u32 = 0x80;
+const SR_BUSY: u32 = 0x40;
+const SR_IF: u32 = 0x01;
+
+fn edge(cycle: u64, channel: usize, level: bool) -> PwmEdge {
+    PwmEdge {
+        cycle,
+        channel,
+        level,
+    }
+}
+
+fn run(pwm: &mut Pwm, cycles: usize) {
+    for _ in 0..cycles {
+        pwm.tick();
+    }
+}
+
+#[test]
+fn pwm_waveform() {
+    let mut pwm = Pwm::new(8);
+    pwm.write(PWMCMP0, 10);
+    pwm.write(PWMCMP1, 4);
+    pwm.write(PWMCMP2, 0xff);
+    pwm.write(PWMCMP3, 0xff);
+    // the counter is stopped until it is enabled.
+    run(&mut pwm, 5);
+    assert_eq!(0, pwm.read(PWMCOUNT));
+    assert!(pwm.take_edges().is_empty());
+
+    pwm.write(PWMCFG, PWMCFG_ENALWAYS | PWMCFG_ZEROCMP);
+    run(&mut pwm, 22);
+    // the period is pwmcmp0 + 1 cycles.
+    assert_eq!(
+        vec![
+            edge(9, 1, true),
+            edge(15, 0, true),
+            edge(16, 0, false),
+            edge(16, 1, false),
+            edge(20, 1, true),
+            edge(26, 0, true),
+            edge(27, 0, false),
+            edge(27, 1, false),
+        ],
+        pwm.take_edges()
+    );
+    assert!(pwm.take_edges().is_empty());
+    assert_eq!(0, pwm.read(PWMCOUNT));
+    assert!(!pwm.get_output(1));
+
+    run(&mut pwm, 4);
+    assert!(pwm.get_output(1));
+    assert!(pwm.is_irq(1));
+    assert!(!pwm.is_irq(0));
+    assert_eq!(0x2 << 28, pwm.read(PWMCFG) & 0xf000_0000);
+}
+
+#[test]
+fn pwm_scale_and_oneshot() {
+    let mut pwm = Pwm::new(16);
+    pwm.write(PWMCMP0, 3);
+    pwm.write(PWMCMP1, 2);
+    pwm.write(PWMCMP2, 0xffff);
+    pwm.write(PWMCMP3, 0xffff);
+    // the comparators see the counter divided by 4.
+    pwm.write(PWMCFG, PWMCFG_ENONESHOT | PWMCFG_ZEROCMP | 2);
+    run(&mut pwm, 7);
+    assert_eq!(7, pwm.read(PWMCOUNT));
+    assert_eq!(1, pwm.read(PWMS));
+    assert!(!pwm.get_output(1));
+    pwm.tick();
+    assert_eq!(2, pwm.read(PWMS));
+    assert!(pwm.get_output(1));
+
+    // the counter stops after one period.
+    run(&mut pwm, 20);
+    assert_eq!(0, pwm.read(PWMCOUNT));
+    assert_eq!(0, pwm.read(PWMCFG) & PWMCFG_ENONESHOT);
+}
+
+#[test]
+fn pwm_sticky() {
+    let mut pwm = Pwm::new(8);
+    pwm.write(PWMCMP0, 10);
+    pwm.write(PWMCMP1, 4);
+    pwm.write(PWMCFG, PWMCFG_ENALWAYS | PWMCFG_ZEROCMP | PWMCFG_STICKY);
+    run(&mut pwm, 15);
+    // the counter restarted but the interrupt is kept.
+    assert_eq!(4, pwm.read(PWMCOUNT));
+    assert!(pwm.is_irq(0));
+    assert!(pwm.is_irq(1));
+
+    // the software clears the pending bits.
+    pwm.write(PWMCFG, PWMCFG_ENALWAYS | PWMCFG_ZEROCMP | PWMCFG_STICKY);
+    assert!(!pwm.is_irq(0));
+    assert!(!pwm.is_irq(1));
+    assert!(!pwm.get_output(1));
+}
+
+#[test]
+fn pwm_gang_and_center() {
+    let mut pwm = Pwm::new(8);
+    pwm.write(PWMCMP0, 3);
+    pwm.write(PWMCMP1, 6);
+    pwm.write(PWMCMP2, 0xff);
+    pwm.write(PWMCMP3, 0xff);
+    // the output 0 is high from pwmcmp0 until pwmcmp1.
+    pwm.write(PWMCFG, PWMCFG_ENALWAYS | PWMCFG_GANG0);
+    run(&mut pwm, 10);
+    assert_eq!(
+        vec![edge(3, 0, true), edge(6, 0, false), edge(6, 1, true)],
+        pwm.take_edges()
+    );
+
+    let mut pwm = Pwm::new(8);
+    pwm.write(PWMCMP0, 0x40);
+    pwm.write(PWMCFG, PWMCFG_ENALWAYS | PWMCFG_CENTER0);
+    pwm.write(PWMCOUNT, 0x3e);
+    pwm.tick();
+    assert!(!pwm.get_output(0));
+    pwm.tick();
+    assert!(pwm.get_output(0));
+    // the comparator counts down in the second half of the period.
+    pwm.write(PWMCOUNT, 0xbe);
+    pwm.tick();
+    assert!(pwm.get_output(0));
+    pwm.tick();
+    assert!(!pwm.get_output(0));
+}
+
+/// Issue a command, the interrupt flag is set when it completes.
+fn i2c_command(i2c: &mut Fe310I2c, data: Option<u8>, command: u32) {
+    if let Some(data) = data {
+        i2c.write(I2C_TXR, data as u32);
+    }
+    i2c.write(I2C_CR, command | CR_IACK);
+}
+
+fn i2c_acked(i2c: &mut Fe310I2c) -> bool {
+    i2c.read(I2C_SR) & SR_RXACK == 0
+}
+
+#[test]
+fn i2c_eeprom() {
+    let mut i2c = Fe310I2c::new();
+    i2c.attach_device(Box::new(Eeprom::new(0x50, 256))).unwrap();
+    assert!(i2c.attach_device(Box::new(Eeprom::new(0x50, 256))).is_err());
+    i2c.write(I2C_CTR, CTR_EN);
+
+    // write 3 bytes at 6, the last wraps to the start of the page.
+    i2c_command(&mut i2c, Some(0x50 << 1), CR_STA | CR_WR);
+    assert!(i2c_acked(&mut i2c));
+    assert_eq!(SR_BUSY | SR_IF, i2c.read(I2C_SR));
+    i2c_command(&mut i2c, Some(0x06), CR_WR);
+    for data in 1..=3 {
+        i2c_command(&mut i2c, Some(data), CR_WR);
+        assert!(i2c_acked(&mut i2c));
+    }
+    i2c_command(&mut i2c, None, CR_STO);
+    assert_eq!(0, i2c.read(I2C_SR) & SR_BUSY);
+
+    // random read: set the address then read after a repeated start.
+    i2c_command(&mut i2c, Some(0x50 << 1), CR_STA | CR_WR);
+    i2c_command(&mut i2c, Some(0x06), CR_WR);
+    i2c_command(&mut i2c, Some(0x50 << 1 | 1), CR_STA | CR_WR);
+    assert!(i2c_acked(&mut i2c));
+    let mut data = vec![];
+    for _ in 0..2 {
+        i2c_command(&mut i2c, None, CR_RD);
+        data.push(i2c.read(I2C_RXR));
+    }
+    i2c_command(&mut i2c, None, CR_RD | CR_ACK | CR_STO);
+    data.push(i2c.read(I2C_RXR));
+    assert_eq!(vec![1, 2, 0xff], data);
+
+    i2c_command(&mut i2c, Some(0x50 << 1), CR_STA | CR_WR);
+    i2c_command(&mut i2c, Some(0x00), CR_WR);
+    i2c_command(&mut i2c, Some(0x50 << 1 | 1), CR_STA | CR_WR);
+    i2c_command(&mut i2c, None, CR_RD | CR_ACK | CR_STO);
+    assert_eq!(3, i2c.read(I2C_RXR));
+
+    // nobody answers at another address.
+    i2c_command(&mut i2c, Some(0x51 << 1), CR_STA | CR_WR);
+    assert!(!i2c_acked(&mut i2c));
+    i2c_command(&mut i2c, None, CR_STO);
+}
+
+/// A device that never acknowledges its address.
+struct NackDevice {
+    writes: std::rc::Rc<std::cell::Cell<usize>>,
+}
+
+impl I2cDevice for NackDevice {
+    fn get_address(&self) -> u8 {
+        0x20
+    }
+
+    fn start(&mut self, _read: bool) -> bool {
+        false
+    }
+
+    fn write(&mut self, _data: u8) -> bool {
+        self.writes.set(self.writes.get() + 1);
+        true
+    }
+
+    fn read(&mut self, _ack: bool) -> u8 {
+        0
+    }
+
+    fn stop(&mut self) {}
+}
+
+#[test]
+fn i2c_address_nack() {
+    let writes = std::rc::Rc::new(std::cell::Cell::new(0));
+    let mut i2c = Fe310I2c::new();
+    i2c.attach_device(Box::new(NackDevice {
+        writes: writes.clone(),
+    }))
+    .unwrap();
+    i2c.write(I2C_CTR, CTR_EN);
+
+    // the device is not selected, the data is not acknowledged.
+    i2c_command(&mut i2c, Some(0x20 << 1), CR_STA | CR_WR);
+    assert!(!i2c_acked(&mut i2c));
+    i2c_command(&mut i2c, Some(0x12), CR_WR);
+    assert!(!i2c_acked(&mut i2c));
+    i2c_command(&mut i2c, None, CR_STO);
+    assert_eq!(0, writes.get());
+}
+
+#[test]
+fn i2c_interrupt() {
+    let mut i2c = Fe310I2c::new();
+    i2c.write(I2C_CTR, CTR_EN | CTR_IEN);
+    i2c.write(I2C_TXR, 0x50 << 1);
+    i2c.write(I2C_CR, CR_STA | CR_WR);
+    assert!(i2c.is_irq());
+    i2c.write(I2C_CR, CR_IACK | CR_STO);
+    assert!(!i2c.is_irq());
+
+    // the commands are ignored while the core is disabled.
+    i2c.write(I2C_CTR, CTR_IEN);
+    i2c.write(I2C_CR, CR_STA | CR_WR);
+    assert_eq!(0, i2c.read(I2C_SR) & (SR_BUSY | SR_IF));
+}
+
+#[test]
+fn eeprom_two_byte_address() {
+    let mut eeprom = Eeprom::new(0x50, 4096);
+    assert!(eeprom.start(false));
+    eeprom.write(0x01);
+    eeprom.write(0x1e);
+    eeprom.write(0xaa);
+    eeprom.write(0xbb);
+    // the page is 32 bytes.
+    eeprom.write(0xcc);
+    eeprom.stop();
+    assert_eq!(&[0xaa, 0xbb][..], &eeprom.get_data()[0x11e..0x120]);
+    assert_eq!(0xcc, eeprom.get_data()[0x100]);
+
+    eeprom.start(false);
+    eeprom.write(0x0f);
+    eeprom.write(0xff);
+    eeprom.start(true);
+    assert_eq!(0xff, eeprom.read(true));
+    // the read wraps at the end of the EEPROM.
+    assert_eq!(0xff, eeprom.read(false));
+    eeprom.stop();
+}
+
+#[test]
+fn temperature_sensor() {
+    let mut sensor = TemperatureSensor::new(0x48);
+    let handle = sensor.get_handle();
+    assert_eq!(25000, handle.get_temperature());
+
+    let read_register = |sensor: &mut TemperatureSensor, pointer: u8| {
+        sensor.start(false);
+        sensor.write(pointer);
+        sensor.start(true);
+        let data = (sensor.read(true) as u16) << 8 | sensor.read(false) as u16;
+        sensor.stop();
+        data
+    };
+    assert_eq!(25 << 8, read_register(&mut sensor, 0));
+    handle.set_temperature(-5500);
+    assert_eq!(0xfa80, read_register(&mut sensor, 0));
+    handle.set_temperature(200_000);
+    assert_eq!(0x7f80, read_register(&mut sensor, 0));
+    assert_eq!(75 << 8, read_register(&mut sensor, 2));
+
+    // overtemperature limit, 90.5 degrees.
+    sensor.start(false);
+    sensor.write(3);
+    sensor.write(90);
+    sensor.write(0x80);
+    sensor.stop();
+    assert_eq!(90 << 8 | 0x80, read_register(&mut sensor, 3));
+    sensor.start(false);
+    sensor.write(1);
+    sensor.write(0x02);
+    sensor.stop();
+    assert_eq!(0x02 << 8, read_register(&mut sensor, 1));
+}
+
+#[test]
+fn pwm_i2c_fe310() {
+    // Read the temperature from the sensor on I2C0 and print its high byte,
+    // then start PWM0.
+    let program = [
+        0x100162b7, // lui t0, 0x10016 (I2C0)
+        0x08000313, // li t1, 0x80
+        0x0062a423, // sw t1, 8(t0) (ctr, EN)
+        0x09100313, // li t1, 0x91
+        0x0062a623, // sw t1, 12(t0) (txr, read 0x48)
+        0x09000313, // li t1, 0x90
+        0x0062a823, // sw t1, 16(t0) (cr, STA | WR)
+        0x06800313, // li t1, 0x68
+        0x0062a823, // sw t1, 16(t0) (cr, RD | ACK | STO)
+        0x00c2a383, // lw t2, 12(t0) (rxr)
+        0x10013eb7, // lui t4, 0x10013 (UART0)
+        0x007ea023, // sw t2, 0(t4)
+        0x100152b7, // lui t0, 0x10015 (PWM0)
+        0x00400313, // li t1, 4
+        0x0262a023, // sw t1, 32(t0) (pwmcmp0)
+        0x00001337, // lui t1, 1
+        0x20030313, // addi t1, t1, 0x200
+        0x0062a023, // sw t1, 0(t0) (pwmcfg, ENALWAYS | ZEROCMP)
+        0x0000006f, // j .
+    ];
+
+    let mut emulator = sifive(Machine::SiFiveE, &program);
+    let sensor = TemperatureSensor::new(0x48);
+    sensor.get_handle().set_temperature(65000);
+    emulator.attach_i2c_device(Box::new(sensor)).unwrap();
+    emulator.run_steps(100);
+    assert_eq!(b'A', emulator.get_console().get_output());
+
+    let edges = emulator.get_pwm(0).unwrap().take_edges();
+    let rising: Vec<u64> = edges
+        .iter()
+        .filter(|edge| edge.channel == 0 && edge.level)
+        .map(|edge| edge.cycle)
+        .collect();
+    assert!(rising.len() > 2);
+    assert!(rising.windows(2).all(|w| w[1] - w[0] == 5));
+    assert!(emulator.get_pwm(3).is_none());
+
+    let mut emulator = Emulator::new(Machine::QemuVirt, Box::new(TtyBuffer::new()), false);
+    assert!(emulator.get_pwm(0).is_none());
+    assert!(emulator
+        .attach_i2c_device(Box::new(Eeprom::new(0x50, 256)))
+        .is_err());
+}
@@ -0,0 +1,267 @@
+extern crate riscv_emu;
+
+use riscv_emu::block::inflate::inflate;
+use riscv_emu::block::qcow2::Qcow2Disk;
+use riscv_emu::block::{open_image, BlockBackend};
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+
+const CLUSTER_SIZE: u64 = 0x10000;
+const L1_TABLE_OFFSET: u64 = 3 * CLUSTER_SIZE;
+
+/// Directory of images, removed when dropped.
+struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("riscv_emu_qcow2_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn read_u64(file: &mut fs::File, offset: u64) -> u64 {
+    let mut data = [0; 8];
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.read_exact(&mut data).unwrap();
+    u64::from_be_bytes(data)
+}
+
+fn write_u64(file: &mut fs::File, offset: u64, value: u64) {
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(&value.to_be_bytes()).unwrap();
+}
+
+/// DEFLATE stream of stored blocks.
+fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        stream.push((i == blocks.len() - 1) as u8);
+        stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream
+}
+
+#[test]
+fn inflate_huffman() {
+    // fixed Huffman codes
+    let fixed = [
+        0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0xb9, 0x00,
+    ];
+    assert_eq!(
+        b"hello hello hello hello\n".to_vec(),
+        inflate(&fixed, 0x1000).unwrap()
+    );
+
+    // dynamic Huffman codes
+    let dynamic = [
+        0xb5, 0xce, 0xbb, 0x11, 0xc2, 0x30, 0x10, 0x84, 0xe1, 0x9c, 0x2a, 0x96, 0x06, 0x5c, 0x07,
+        0x21, 0x81, 0x1b, 0x90, 0xf0, 0x49, 0x3e, 0x90, 0x75, 0x58, 0x4f, 0x4b, 0xd5, 0xa3, 0x61,
+        0x68, 0x80, 0xc0, 0xf1, 0x7e, 0x3b, 0xf3, 0xcf, 0x2b, 0x61, 0xcf, 0xfc, 0x78, 0x41, 0x07,
+        0xa9, 0x1e, 0x46, 0x0e, 0x3c, 0xf3, 0xf6, 0x8e, 0x90, 0x42, 0x01, 0x69, 0xcc, 0x4e, 0xf5,
+        0x86, 0x45, 0xec, 0x84, 0xf9, 0x34, 0x7c, 0x57, 0xc3, 0x6d, 0x0d, 0x7a, 0xa0, 0xca, 0x69,
+        0x85, 0xe1, 0x42, 0x63, 0xea, 0xe4, 0xe1, 0x78, 0xcf, 0x12, 0xc6, 0xd7, 0xc6, 0x3f, 0xe0,
+        0x4d, 0x2a, 0x0a, 0x1d, 0xec, 0xad, 0x6b, 0xbf, 0x8e, 0x45, 0x99, 0x84, 0x4e, 0x3a, 0xa8,
+        0xf8, 0x2d, 0xb9, 0x5e, 0x3e,
+    ];
+    let mut expected = b"The quick brown fox jumps over the lazy dog. ".repeat(3);
+    expected.extend_from_slice(&b"Pack my box with five dozen liquor jugs. ".repeat(2));
+    expected.extend_from_slice(b"How vexingly quick daft zebras jump!\n");
+    assert_eq!(expected, inflate(&dynamic, 0x1000).unwrap());
+
+    // truncated stream
+    assert!(inflate(&dynamic[..50], 0x1000).is_err());
+}
+
+#[test]
+fn qcow2_write_and_reopen() {
+    let dir = TestDir::new("write");
+    let path = dir.path.join("disk.qcow2");
+    let mut disk = Qcow2Disk::create(&path, 0x100_0000, None).unwrap();
+    assert_eq!(0x100_0000, disk.get_size());
+
+    // unallocated clusters read as zeros.
+    let mut data = vec![0xff; 0x200];
+    disk.read_at(0x2000, &mut data).unwrap();
+    assert_eq!(vec![0; 0x200], data);
+
+    // the write crosses a cluster boundary.
+    let written: Vec<u8> = (0..0x3000).map(|i| i as u8).collect();
+    disk.write_at(CLUSTER_SIZE - 0x1000, &written).unwrap();
+    disk.write_at(0xff_f000, &[0xa5; 0x1000]).unwrap();
+    disk.flush().unwrap();
+    assert!(disk.write_at(0xff_f000, &[0; 0x1001]).is_err());
+    drop(disk);
+
+    // the image is detected by the contents.
+    let mut disk = open_image(&path, false).unwrap();
+    let mut data = vec![0; 0x3000];
+    disk.read_at(CLUSTER_SIZE - 0x1000, &mut data).unwrap();
+    assert_eq!(written, data);
+    let mut data = vec![0; 0x1000];
+    disk.read_at(0xff_f000, &mut data).unwrap();
+    assert_eq!(vec![0xa5; 0x1000], data);
+    let mut data = vec![0xff; 0x1000];
+    disk.read_at(CLUSTER_SIZE - 0x2000, &mut data).unwrap();
+    assert_eq!(vec![0; 0x1000], data);
+
+    // metadata and 3 data clusters: an L2 table per 512 MiB.
+    let size = fs::metadata(&path).unwrap().len();
+    assert_eq!(4 * CLUSTER_SIZE + CLUSTER_SIZE + 3 * CLUSTER_SIZE, size);
+
+    let mut file = OpenOptions::new().read(true).open(&path).unwrap();
+    let refcount_block = read_u64(&mut file, CLUSTER_SIZE);
+    let mut refcounts = vec![0; 16];
+    file.seek(SeekFrom::Start(refcount_block)).unwrap();
+    file.read_exact(&mut refcounts).unwrap();
+    assert_eq!(
+        vec![0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1],
+        refcounts
+    );
+
+    let disk = open_image(&path, true).unwrap();
+    assert!(disk.is_read_only());
+}
+
+#[test]
+fn qcow2_invalid_l1_size() {
+    let dir = TestDir::new("l1_size");
+    let path = dir.path.join("disk.qcow2");
+    Qcow2Disk::create(&path, 0x100_0000, None).unwrap();
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(36)).unwrap();
+    file.write_all(&0u32.to_be_bytes()).unwrap();
+    drop(file);
+    assert!(Qcow2Disk::open(&path, false).is_err());
+}
+
+#[test]
+fn qcow2_shared_cluster() {
+    let dir = TestDir::new("shared");
+    let path = dir.path.join("disk.qcow2");
+    let mut disk = Qcow2Disk::create(&path, 0x100_0000, None).unwrap();
+    disk.write_at(0, &[0x5a; 0x200]).unwrap();
+    drop(disk);
+
+    // clear the copied flag as if a snapshot referred to the cluster.
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    let l2_table = read_u64(&mut file, L1_TABLE_OFFSET) & !(1 << 63);
+    let entry = read_u64(&mut file, l2_table);
+    let cluster = entry & !(1 << 63);
+    write_u64(&mut file, l2_table, cluster);
+    drop(file);
+
+    // the write goes to a copy, the shared cluster is untouched.
+    let mut disk = Qcow2Disk::open(&path, false).unwrap();
+    disk.write_at(0x100, &[0xa5; 0x100]).unwrap();
+    let mut data = vec![0; 0x200];
+    disk.read_at(0, &mut data).unwrap();
+    assert_eq!(vec![0x5a; 0x100], data[..0x100].to_vec());
+    assert_eq!(vec![0xa5; 0x100], data[0x100..].to_vec());
+    drop(disk);
+
+    let mut file = OpenOptions::new().read(true).open(&path).unwrap();
+    let entry = read_u64(&mut file, l2_table);
+    assert_ne!(cluster, entry & !(1 << 63));
+    assert_ne!(0, entry & (1 << 63));
+    let mut shared = vec![0; 0x200];
+    file.seek(SeekFrom::Start(cluster)).unwrap();
+    file.read_exact(&mut shared).unwrap();
+    assert_eq!(vec![0x5a; 0x200], shared);
+}
+
+#[test]
+fn qcow2_backing_file() {
+    let dir = TestDir::new("backing");
+    fs::write(dir.path.join("base.img"), vec![0x5a; 0x20000]).unwrap();
+    let path = dir.path.join("overlay.qcow2");
+    let mut disk = Qcow2Disk::create(&path, 0x30000, Some("base.img")).unwrap();
+    assert!(disk.has_backing_file());
+
+    disk.write_at(0x100, &[1; 0x10]).unwrap();
+    let mut data = vec![0; 0x200];
+    disk.read_at(0, &mut data).unwrap();
+    assert_eq!(vec![0x5a; 0x100], data[..0x100].to_vec());
+    assert_eq!(vec![1; 0x10], data[0x100..0x110].to_vec());
+    assert_eq!(vec![0x5a; 0xf0], data[0x110..].to_vec());
+    // beyond the backing file
+    let mut data = vec![0xff; 0x20];
+    disk.read_at(0x1fff0, &mut data).unwrap();
+    assert_eq!(vec![0x5a; 0x10], data[..0x10].to_vec());
+    assert_eq!(vec![0; 0x10], data[0x10..].to_vec());
+
+    // the base image is not modified.
+    assert_eq!(
+        vec![0x5a; 0x20000],
+        fs::read(dir.path.join("base.img")).unwrap()
+    );
+}
+
+#[test]
+fn qcow2_compressed_and_zero_clusters() {
+    let dir = TestDir::new("compressed");
+    let path = dir.path.join("disk.qcow2");
+    let mut disk = Qcow2Disk::create(&path, 4 * CLUSTER_SIZE, None).unwrap();
+    // allocate the L2 table and clusters 1 and 2.
+    disk.write_at(CLUSTER_SIZE, &vec![0x11; 2 * CLUSTER_SIZE as usize])
+        .unwrap();
+    drop(disk);
+
+    // cluster 0 is compressed, cluster 1 is a zero cluster.
+    let cluster: Vec<u8> = (0..CLUSTER_SIZE).map(|i| (i % 251) as u8).collect();
+    let compressed = deflate_stored(&cluster);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    let offset = fs::metadata(&path).unwrap().len() + 0x100;
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(&compressed).unwrap();
+    let sectors = (0x100 + compressed.len() as u64).div_ceil(512) - 1;
+    let l2_table = read_u64(&mut file, L1_TABLE_OFFSET) & 0x00ff_ffff_ffff_fe00;
+    write_u64(&mut file, l2_table, 1 << 62 | sectors << 54 | offset);
+    write_u64(&mut file, l2_table + 8, 1 << 63 | 1);
+    drop(file);
+
+    let mut disk = Qcow2Disk::open(&path, false).unwrap();
+    let mut data = vec![0; CLUSTER_SIZE as usize * 3];
+    disk.read_at(0, &mut data).unwrap();
+    assert_eq!(cluster, data[..CLUSTER_SIZE as usize].to_vec());
+    assert_eq!(
+        vec![0; CLUSTER_SIZE as usize],
+        data[CLUSTER_SIZE as usize..2 * CLUSTER_SIZE as usize].to_vec()
+    );
+    assert_eq!(
+        vec![0x11; CLUSTER_SIZE as usize],
+        data[2 * CLUSTER_SIZE as usize..].to_vec()
+    );
+
+    // writes to a compressed cluster allocate a new one.
+    disk.write_at(0x10, &[0xee; 4]).unwrap();
+    let mut data = vec![0; 0x20];
+    disk.read_at(0, &mut data).unwrap();
+    assert_eq!(cluster[..0x10].to_vec(), data[..0x10].to_vec());
+    assert_eq!(vec![0xee; 4], data[0x10..0x14].to_vec());
+    assert_eq!(cluster[0x14..0x20].to_vec(), data[0x14..].to_vec());
+}
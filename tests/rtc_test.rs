@@ -0,0 +1,180 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::qemu_virt;
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::{ExitStatus, Machine};
+use riscv_emu::peripherals::goldfish_rtc::{GoldfishRtc, HostClock, TimeSource, VirtualClock};
+
+// RTC registers
+const RTC_TIME_LOW: u64 = 0x00;
+const RTC_TIME_HIGH: u64 = 0x04;
+const RTC_ALARM_LOW: u64 = 0x08;
+const RTC_ALARM_HIGH: u64 = 0x0c;
+const RTC_IRQ_ENABLED: u64 = 0x10;
+const RTC_CLEAR_ALARM: u64 = 0x14;
+const RTC_ALARM_STATUS: u64 = 0x18;
+const RTC_CLEAR_INTERRUPT: u64 = 0x1c;
+
+/// 2021-01-01 00:00:00 UTC
+const START: u64 = 1_609_459_200_000_000_000;
+
+/// A clock of one nanosecond per cycle.
+fn virtual_rtc() -> GoldfishRtc {
+    GoldfishRtc::new(Box::new(VirtualClock::new(START, 1_000_000_000)))
+}
+
+fn read_time(rtc: &mut GoldfishRtc) -> u64 {
+    let low = rtc.read(RTC_TIME_LOW) as u64;
+    (rtc.read(RTC_TIME_HIGH) as u64) << 32 | low
+}
+
+fn set_alarm(rtc: &mut GoldfishRtc, alarm: u64) {
+    rtc.write(RTC_ALARM_HIGH, (alarm >> 32) as u32);
+    rtc.write(RTC_ALARM_LOW, alarm as u32);
+}
+
+#[test]
+fn virtual_clock() {
+    let mut clock = VirtualClock::new(START, 100);
+    assert_eq!(START, clock.get_time());
+    for _ in 0..250 {
+        clock.tick();
+    }
+    assert_eq!(START + 2_500_000_000, clock.get_time());
+}
+
+#[test]
+fn host_clock() {
+    // later than the start of 2021.
+    assert!(HostClock::new().get_time() > START);
+}
+
+#[test]
+fn rtc_time() {
+    let mut rtc = virtual_rtc();
+    assert_eq!(START, read_time(&mut rtc));
+    for _ in 0..1000 {
+        rtc.tick();
+    }
+    assert_eq!(START + 1000, read_time(&mut rtc));
+
+    // the guest sets the time, which keeps running from there.
+    let time = 0x1234_5678_0000_0000;
+    rtc.write(RTC_TIME_HIGH, (time >> 32) as u32);
+    rtc.write(RTC_TIME_LOW, time as u32);
+    assert_eq!(time, rtc.get_time());
+    rtc.tick();
+    assert_eq!(time + 1, read_time(&mut rtc));
+
+    // a reset keeps the time, a new source forgets it.
+    rtc.reset();
+    assert_eq!(time + 1, rtc.get_time());
+    rtc.set_time_source(Box::new(VirtualClock::new(START, 1_000_000_000)));
+    assert_eq!(START, rtc.get_time());
+}
+
+#[test]
+fn rtc_alarm() {
+    let mut rtc = virtual_rtc();
+    rtc.write(RTC_IRQ_ENABLED, 1);
+    set_alarm(&mut rtc, START + 0x3000);
+    assert_eq!(
+        START + 0x3000,
+        (rtc.read(RTC_ALARM_HIGH) as u64) << 32 | rtc.read(RTC_ALARM_LOW) as u64
+    );
+    assert_eq!(1, rtc.read(RTC_ALARM_STATUS));
+    for _ in 0..0x2fff {
+        rtc.tick();
+    }
+    assert!(!rtc.is_irq());
+    rtc.tick();
+    assert!(rtc.is_irq());
+    assert_eq!(0, rtc.read(RTC_ALARM_STATUS));
+    rtc.write(RTC_CLEAR_INTERRUPT, 1);
+    assert!(!rtc.is_irq());
+
+    // an alarm in the past fires at once, masked when the interrupt is
+    // disabled.
+    rtc.write(RTC_IRQ_ENABLED, 0);
+    set_alarm(&mut rtc, START);
+    assert!(!rtc.is_irq());
+    rtc.write(RTC_IRQ_ENABLED, 1);
+    assert!(rtc.is_irq());
+    rtc.write(RTC_CLEAR_INTERRUPT, 1);
+
+    // a cleared alarm never fires.
+    set_alarm(&mut rtc, START + 0x4000);
+    rtc.write(RTC_CLEAR_ALARM, 1);
+    assert_eq!(0, rtc.read(RTC_ALARM_STATUS));
+    for _ in 0..0x2000 {
+        rtc.tick();
+    }
+    assert!(!rtc.is_irq());
+}
+
+#[test]
+fn qemu_virt_rtc() {
+    // Set the alarm 100us later, print 'R' in the interrupt handler and power
+    // off.
+    let program = [
+        0x001012b7, // lui t0, 0x101 (RTC)
+        0x0002e303, // lwu t1, 0(t0) (time_low)
+        0x0042e383, // lwu t2, 4(t0) (time_high)
+        0x02039393, // slli t2, t2, 32
+        0x00736333, // or t1, t1, t2
+        0x000183b7, // lui t2, 0x18
+        0x6a038393, // addi t2, t2, 0x6a0 (100000)
+        0x00730333, // add t1, t1, t2
+        0x02035393, // srli t2, t1, 32
+        0x0072a623, // sw t2, 12(t0) (alarm_high)
+        0x0062a423, // sw t1, 8(t0) (alarm_low)
+        0x00100313, // li t1, 1
+        0x0062a823, // sw t1, 16(t0) (irq_enabled)
+        0x0c0003b7, // lui t2, 0xc000 (PLIC)
+        0x0263a623, // sw t1, 44(t2) (priority 11)
+        0x0c002e37, // lui t3, 0xc002
+        0x40000313, // li t1, 0x400
+        0x00131313, // slli t1, t1, 1
+        0x006e2023, // sw t1, 0(t3) (enable 11)
+        0x00000317, // auipc t1, 0
+        0x02430313, // addi t1, t1, 36
+        0x30531073, // csrw mtvec, t1
+        0x00001337, // lui t1, 1
+        0x80030313, // addi t1, t1, -2048
+        0x30431073, // csrw mie, t1
+        0x30046073, // csrsi mstatus, 8
+        0x10500073, // 1: wfi
+        0xffdff06f, // j 1b
+        0x0c200e37, // lui t3, 0xc200
+        0x004e2e83, // lw t4, 4(t3) (claim)
+        0x0002ae23, // sw zero, 28(t0) (clear_interrupt)
+        0x01de2223, // sw t4, 4(t3) (complete)
+        0x100003b7, // lui t2, 0x10000 (UART)
+        0x05200313, // li t1, 'R'
+        0x00638023, // sb t1, 0(t2)
+        0x0053c303, // 2: lbu t1, 5(t2) (LSR)
+        0x02037313, // andi t1, t1, 0x20 (THR empty)
+        0xfe030ce3, // beqz t1, 2b
+        0x001002b7, // lui t0, 0x100 (test)
+        0x00005337, // lui t1, 5
+        0x55530313, // addi t1, t1, 0x555 (pass)
+        0x0062a023, // sw t1, 0(t0)
+        0x0000006f, // j .
+    ];
+
+    let mut emulator = qemu_virt(&program);
+    emulator
+        .set_time_source(Box::new(VirtualClock::new(START, 1_000_000_000)))
+        .unwrap();
+    assert_eq!(ExitStatus::PowerOff(0), emulator.run_until_exit());
+    assert!(emulator.get_cycle() >= 100_000);
+    assert_eq!(b'R', emulator.get_console().get_output());
+
+    let mut emulator = Emulator::new(Machine::SiFiveE, Box::new(TtyBuffer::new()), false);
+    assert!(emulator
+        .set_time_source(Box::new(HostClock::new()))
+        .is_err());
+}
@@ -0,0 +1,408 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::sifive;
+use riscv_emu::block::memory::MemoryDisk;
+use riscv_emu::console::TtyBuffer;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::peripherals::fe310_g002::spi::Fe310Spi;
+use riscv_emu::peripherals::spi::nor_flash::*;
+use riscv_emu::peripherals::spi::sd_card::*;
+use riscv_emu::peripherals::spi::SpiDevice;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Registers
+const CSID: u64 = 0x10;
+const CSDEF: u64 = 0x14;
+const CSMODE: u64 = 0x18;
+const FMT: u64 = 0x40;
+const TXDATA: u64 = 0x48;
+const RXDATA: u64 = 0x4c;
+const TXMARK: u64 = 0x50;
+const RXMARK: u64 = 0x54;
+const FCTRL: u64 = 0x60;
+const FFMT: u64 = 0x64;
+const IE: u64 = 0x70;
+const IP: u64 = 0x74;
+
+const CSMODE_HOLD: u32 = 2;
+const CSMODE_OFF: u32 = 3;
+const RXDATA_EMPTY: u32 = 0x8000_0000;
+
+/// Transaction of bytes with a device, the chip select held.
+fn transaction(device: &mut dyn SpiDevice, data: &[u8]) -> Vec<u8> {
+    device.select(true);
+    let out = data.iter().map(|byte| device.transfer(*byte)).collect();
+    device.select(false);
+    out
+}
+
+#[test]
+fn nor_flash_commands() {
+    let mut flash = NorFlash::new(0x20000);
+    flash.load(&[0x12, 0x34, 0x56]);
+
+    let out = transaction(&mut flash, &[0x9f, 0, 0, 0]);
+    assert_eq!(&NOR_JEDEC_ID[..], &out[1..]);
+    let out = transaction(&mut flash, &[0x03, 0x00, 0x00, 0x01, 0, 0, 0]);
+    assert_eq!(vec![0x34, 0x56, 0xff], out[4..].to_vec());
+    // fast read, a dummy byte after the address.
+    let out = transaction(&mut flash, &[0x0b, 0x00, 0x00, 0x00, 0, 0]);
+    assert_eq!(0x12, out[5]);
+    // the address wraps at the end of the flash.
+    let out = transaction(&mut flash, &[0x03, 0x01, 0xff, 0xff, 0, 0]);
+    assert_eq!(vec![0xff, 0x12], out[4..].to_vec());
+
+    // a program needs the write enable and only clears bits.
+    transaction(&mut flash, &[0x02, 0x00, 0x10, 0x00, 0x00]);
+    assert_eq!(0xff, flash.get_data()[0x1000]);
+    transaction(&mut flash, &[0x06]);
+    assert_eq!(vec![0xff, 0x02], transaction(&mut flash, &[0x05, 0]));
+    transaction(&mut flash, &[0x02, 0x00, 0x10, 0xff, 0xf0, 0x0f, 0xaa]);
+    assert_eq!(vec![0xff, 0x00], transaction(&mut flash, &[0x05, 0]));
+    assert_eq!(0xf0, flash.get_data()[0x10ff]);
+    // wrapping in the page.
+    assert_eq!(0x0f, flash.get_data()[0x1000]);
+    assert_eq!(0xaa, flash.get_data()[0x1001]);
+
+    // sector erase
+    transaction(&mut flash, &[0x06]);
+    transaction(&mut flash, &[0x20, 0x00, 0x10, 0x80]);
+    assert_eq!(0xff, flash.get_data()[0x1000]);
+    assert_eq!(0xff, flash.get_data()[0x10ff]);
+    assert_eq!(0x12, flash.get_data()[0]);
+    // chip erase
+    transaction(&mut flash, &[0x06]);
+    transaction(&mut flash, &[0xc7]);
+    assert!(flash.get_data().iter().all(|byte| *byte == 0xff));
+}
+
+/// Send a command and return the R1 response, after the delay. The card
+/// may still send the data of a read during the command.
+fn sd_command(card: &mut SdCard, index: u8, arg: u32) -> u8 {
+    let mut frame = vec![0x40 | index];
+    frame.extend_from_slice(&arg.to_be_bytes());
+    frame.push(0x95);
+    for byte in frame {
+        card.transfer(byte);
+    }
+    for _ in 0..8 {
+        match card.transfer(0xff) {
+            0xff => {}
+            r1 => return r1,
+        }
+    }
+    panic!("no response");
+}
+
+/// Read a data block after its start token, and check the CRC16.
+fn sd_read_data(card: &mut SdCard, len: usize) -> Vec<u8> {
+    while card.transfer(0xff) != 0xfe {}
+    let data: Vec<u8> = (0..len).map(|_| card.transfer(0xff)).collect();
+    let crc = u16::from_be_bytes([card.transfer(0xff), card.transfer(0xff)]);
+    let expected = data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x1021,
+        })
+    });
+    assert_eq!(expected, crc);
+    data
+}
+
+fn sd_card() -> SdCard {
+    let mut data = vec![0; 1024 * 1024 * 2];
+    for (block, chunk) in data.chunks_mut(SD_BLOCK_SIZE).enumerate() {
+        chunk[0] = block as u8;
+    }
+    let mut card = SdCard::new(Box::new(MemoryDisk::new(data)));
+    card.select(true);
+    assert_eq!(0x01, sd_command(&mut card, 0, 0));
+    assert_eq!(0x01, sd_command(&mut card, 8, 0x1aa));
+    assert_eq!(
+        vec![0x00, 0x00, 0x01, 0xaa],
+        (0..4).map(|_| card.transfer(0xff)).collect::<Vec<u8>>()
+    );
+    assert_eq!(0x01, sd_command(&mut card, 55, 0));
+    assert_eq!(0x00, sd_command(&mut card, 41, 0x4000_0000));
+    assert_eq!(0x00, sd_command(&mut card, 58, 0));
+    let ocr: Vec<u8> = (0..4).map(|_| card.transfer(0xff)).collect();
+    // powered up, high capacity
+    assert_eq!(0xc0, ocr[0]);
+    card
+}
+
+#[test]
+fn sd_card_init() {
+    let mut card = sd_card();
+    // CSD version 2.0 with the size in 512 KiB units, minus 1.
+    assert_eq!(0x00, sd_command(&mut card, 9, 0));
+    let csd = sd_read_data(&mut card, 16);
+    assert_eq!(0x40, csd[0]);
+    assert_eq!(
+        3,
+        (csd[7] as u32) << 16 | (csd[8] as u32) << 8 | csd[9] as u32
+    );
+    assert_eq!(1, csd[15] & 1);
+    assert_eq!(0x00, sd_command(&mut card, 10, 0));
+    assert_eq!(16, sd_read_data(&mut card, 16).len());
+    // the illegal command bit.
+    assert_eq!(0x04, sd_command(&mut card, 5, 0));
+}
+
+#[test]
+fn sd_card_read_write() {
+    let mut card = sd_card();
+    assert_eq!(0x00, sd_command(&mut card, 17, 3));
+    assert_eq!(3, sd_read_data(&mut card, SD_BLOCK_SIZE)[0]);
+
+    // multiple blocks until the stop command.
+    assert_eq!(0x00, sd_command(&mut card, 18, 10));
+    for block in 10..13 {
+        assert_eq!(block, sd_read_data(&mut card, SD_BLOCK_SIZE)[0]);
+    }
+    assert_eq!(0x00, sd_command(&mut card, 12, 0));
+    for _ in 0..600 {
+        assert_eq!(0xff, card.transfer(0xff));
+    }
+
+    // out of the card
+    assert_eq!(0x40, sd_command(&mut card, 17, 4096));
+
+    // single block write
+    assert_eq!(0x00, sd_command(&mut card, 24, 5));
+    card.transfer(0xff);
+    card.transfer(0xfe);
+    for i in 0..SD_BLOCK_SIZE + 2 {
+        card.transfer(i as u8);
+    }
+    assert_eq!(0x05, card.transfer(0xff) & 0x1f);
+
+    // multiple block write
+    assert_eq!(0x00, sd_command(&mut card, 25, 6));
+    for block in 0..2 {
+        card.transfer(0xff);
+        card.transfer(0xfc);
+        for _ in 0..SD_BLOCK_SIZE + 2 {
+            card.transfer(0xa0 + block);
+        }
+        assert_eq!(0x05, card.transfer(0xff) & 0x1f);
+    }
+    card.transfer(0xfd);
+
+    assert_eq!(0x00, sd_command(&mut card, 17, 5));
+    let data = sd_read_data(&mut card, SD_BLOCK_SIZE);
+    assert_eq!(vec![0, 1, 2, 3], data[..4].to_vec());
+    assert_eq!(0xff, data[0xff]);
+    assert_eq!(0x00, sd_command(&mut card, 18, 6));
+    assert_eq!(0xa0, sd_read_data(&mut card, SD_BLOCK_SIZE)[0]);
+    assert_eq!(0xa1, sd_read_data(&mut card, SD_BLOCK_SIZE)[0]);
+    assert_eq!(0x00, sd_command(&mut card, 12, 0));
+}
+
+/// Device recording the chip select changes and answering the complement.
+struct Probe {
+    log: Rc<RefCell<Vec<String>>>,
+}
+
+impl SpiDevice for Probe {
+    fn select(&mut self, selected: bool) {
+        self.log.borrow_mut().push(format!("select {}", selected));
+    }
+
+    fn transfer(&mut self, data: u8) -> u8 {
+        self.log.borrow_mut().push(format!("{:02x}", data));
+        !data
+    }
+}
+
+#[test]
+fn spi_controller_chip_select() {
+    let mut spi = Fe310Spi::new(4, false);
+    let log = Rc::new(RefCell::new(vec![]));
+    spi.attach_device(2, Box::new(Probe { log: log.clone() }))
+        .unwrap();
+    assert!(spi
+        .attach_device(4, Box::new(Probe { log: log.clone() }))
+        .is_err());
+    assert_eq!(0xf, spi.read(CSDEF));
+
+    // nothing on the line 0.
+    spi.write(TXDATA, 0x12);
+    assert_eq!(0xff, spi.read(RXDATA));
+    assert_eq!(RXDATA_EMPTY, spi.read(RXDATA));
+
+    // AUTO deasserts after each frame.
+    spi.write(CSID, 2);
+    spi.write(TXDATA, 0x12);
+    spi.write(TXDATA, 0x34);
+    assert_eq!(0xed, spi.read(RXDATA));
+    assert_eq!(0xcb, spi.read(RXDATA));
+    assert_eq!(
+        vec![
+            "select true",
+            "12",
+            "select false",
+            "select true",
+            "34",
+            "select false"
+        ],
+        *log.borrow()
+    );
+    log.borrow_mut().clear();
+
+    // HOLD keeps the line until the mode changes.
+    spi.write(CSMODE, CSMODE_HOLD);
+    spi.write(TXDATA, 0x01);
+    spi.write(TXDATA, 0x02);
+    spi.write(CSMODE, 0);
+    assert_eq!(
+        vec!["select true", "01", "02", "select false"],
+        *log.borrow()
+    );
+    log.borrow_mut().clear();
+
+    // OFF leaves the lines at their default level.
+    spi.write(CSMODE, CSMODE_OFF);
+    spi.write(TXDATA, 0x03);
+    spi.write(CSDEF, 0xb);
+    spi.write(TXDATA, 0x04);
+    spi.write(CSDEF, 0xf);
+    assert_eq!(vec!["select true", "04", "select false"], *log.borrow());
+
+    spi.reset();
+    assert_eq!(0, spi.read(CSID));
+    assert_eq!(0xf, spi.read(CSDEF));
+}
+
+#[test]
+fn spi_controller_fifo() {
+    let mut spi = Fe310Spi::new(1, false);
+    let log = Rc::new(RefCell::new(vec![]));
+    spi.attach_device(0, Box::new(Probe { log: log.clone() }))
+        .unwrap();
+
+    // the receive FIFO holds 8 frames.
+    for i in 0..10 {
+        spi.write(TXDATA, i);
+    }
+    assert_eq!(0, spi.read(TXDATA));
+    for i in 0..8 {
+        assert_eq!(!(i as u8) as u32, spi.read(RXDATA));
+    }
+    assert_eq!(RXDATA_EMPTY, spi.read(RXDATA));
+
+    // transmit only
+    spi.write(FMT, 0x0008_0008);
+    spi.write(TXDATA, 0x55);
+    assert_eq!(RXDATA_EMPTY, spi.read(RXDATA));
+
+    // LSB first
+    spi.write(FMT, 0x0008_0004);
+    spi.write(TXDATA, 0x01);
+    assert_eq!("80", log.borrow().iter().rev().nth(1).unwrap());
+    assert_eq!(0xfe, spi.read(RXDATA));
+
+    // watermarks
+    spi.write(IE, 0x3);
+    assert_eq!(0, spi.read(IP));
+    assert!(!spi.is_irq());
+    spi.write(TXMARK, 1);
+    assert_eq!(0x1, spi.read(IP));
+    spi.write(TXMARK, 0);
+    spi.write(RXMARK, 1);
+    spi.write(TXDATA, 0x01);
+    assert_eq!(0, spi.read(IP));
+    spi.write(TXDATA, 0x02);
+    assert_eq!(0x2, spi.read(IP));
+    assert!(spi.is_irq());
+    spi.read(RXDATA);
+    assert!(!spi.is_irq());
+}
+
+#[test]
+fn spi_controller_flash_mode() {
+    let mut spi = Fe310Spi::new(1, true);
+    let mut flash = NorFlash::new(0x10000);
+    flash.load(&[0x11, 0x22, 0x33, 0x44, 0x55]);
+    spi.attach_device(0, Box::new(flash)).unwrap();
+
+    assert_eq!(0x1, spi.read(FCTRL));
+    assert_eq!(0x0003_0007, spi.read(FFMT));
+    assert_eq!(Ok(0x4433_2211), spi.read_flash(0, 4));
+    assert_eq!(Ok(0x33), spi.read_flash(2, 1));
+
+    // fast read, 8 dummy cycles
+    spi.write(FFMT, 0x000b_0087);
+    assert_eq!(Ok(0x5544), spi.read_flash(3, 2));
+    // quad I/O read, 6 dummy cycles on 4 lines
+    spi.write(FFMT, 0x00eb_2a67);
+    assert_eq!(Ok(0x55), spi.read_flash(4, 1));
+
+    // loading an image
+    assert_eq!(Ok(()), spi.write_flash(0xfe, &[0x01, 0x02, 0x03, 0x04]));
+    spi.write(FFMT, 0x0003_0007);
+    assert_eq!(Ok(0x0403_0201), spi.read_flash(0xfe, 4));
+
+    // the direct mode: read the JEDEC ID.
+    spi.write(FCTRL, 0);
+    assert_eq!(Err(()), spi.read_flash(0, 4));
+    spi.write(CSMODE, CSMODE_HOLD);
+    for byte in &[0x9f, 0, 0, 0] {
+        spi.write(TXDATA, *byte);
+    }
+    spi.write(CSMODE, 0);
+    spi.read(RXDATA);
+    for byte in &NOR_JEDEC_ID {
+        assert_eq!(*byte as u32, spi.read(RXDATA));
+    }
+}
+
+#[test]
+fn spi_fe310_sd_card() {
+    // Read the first byte of the block 1 of the SD card on SPI1 and print
+    // it.
+    let program = [
+        0x100242b7, // lui t0, 0x10024 (SPI1)
+        0x00200313, // li t1, 2
+        0x0062ac23, // sw t1, 0x18(t0) (csmode, HOLD)
+        0x05100313, // li t1, 0x51 (READ_SINGLE_BLOCK)
+        0x0462a423, // sw t1, 0x48(t0) (txdata)
+        0x0402a423, // sw zero, 0x48(t0)
+        0x0402a423, // sw zero, 0x48(t0)
+        0x0402a423, // sw zero, 0x48(t0)
+        0x00100313, // li t1, 1
+        0x0462a423, // sw t1, 0x48(t0)
+        0x0ff00313, // li t1, 0xff
+        0x0462a423, // sw t1, 0x48(t0)
+        0x04c2a383, // 1: lw t2, 0x4c(t0) (rxdata)
+        0xfe03dee3, // bgez t2, 1b
+        0x0fe00e13, // li t3, 0xfe
+        0x0462a423, // 2: sw t1, 0x48(t0)
+        0x04c2a383, // lw t2, 0x4c(t0)
+        0xffc39ce3, // bne t2, t3, 2b
+        0x0462a423, // sw t1, 0x48(t0)
+        0x04c2a383, // lw t2, 0x4c(t0)
+        0x10013eb7, // lui t4, 0x10013 (UART0)
+        0x007ea023, // sw t2, 0(t4)
+        0x0000006f, // j .
+    ];
+
+    let mut data = vec![0; 1024 * 1024];
+    data[SD_BLOCK_SIZE] = b'S';
+    let mut emulator = sifive(Machine::SiFiveE, &program);
+    emulator
+        .attach_sd_card(Box::new(MemoryDisk::new(data)))
+        .unwrap();
+    emulator.run_steps(1000);
+    assert_eq!(b'S', emulator.get_console().get_output());
+
+    let mut emulator = Emulator::new(Machine::QemuVirt, Box::new(TtyBuffer::new()), false);
+    assert!(emulator
+        .attach_sd_card(Box::new(MemoryDisk::new(vec![0; 1024])))
+        .is_err());
+}
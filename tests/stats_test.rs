@@ -0,0 +1,37 @@
+extern crate riscv_emu;
+
+use std::path::PathBuf;
+
+use riscv_emu::console::TtyDummy;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+
+#[test]
+fn stats_virtual_memory() {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push("tests/bin/rv64ui-v-add");
+
+    let tty = Box::new(TtyDummy::new());
+    let mut emu = Emulator::new(Machine::SiFiveU, tty, true);
+    emu.load_program_from_file(root.as_path());
+    emu.enable_stats();
+    assert_eq!(Ok(1), emu.run());
+
+    let cpu = emu.get_cpu_stats().unwrap();
+    let total: u64 = cpu.mnemonics.values().sum();
+    assert_eq!(cpu.get_instructions(), total);
+    assert!(cpu.mnemonics["add"] > 0);
+    // the test environment enters U-mode and exits with an ecall.
+    assert!(cpu.privilege_ticks[0] > 0);
+    assert_eq!(1, cpu.exceptions[8]);
+
+    let memory = emu.get_memory_stats().unwrap();
+    assert!(memory.page_walks > 0);
+    assert!(memory.pte_reads >= memory.page_walks);
+
+    let mut report = Vec::new();
+    emu.write_stats_report(&mut report).unwrap();
+    assert!(String::from_utf8(report)
+        .unwrap()
+        .contains("instruction mix:"));
+}
@@ -0,0 +1,115 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::qemu_virt;
+use std::path::PathBuf;
+
+use riscv_emu::cache::CacheHierarchyConfig;
+use riscv_emu::console::TtyDummy;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::timing::{PipelineConfig, PipelineModel, RetiredInstruction, TimingModel};
+
+fn run(filename: &'static str, config: Option<PipelineConfig>, cache: bool) -> Emulator {
+    let mut root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    root.push("tests/bin");
+    root.push(filename);
+
+    let tty = Box::new(TtyDummy::new());
+    let mut emu = Emulator::new(Machine::SiFiveU, tty, true);
+    emu.load_program_from_file(root.as_path());
+    if cache {
+        emu.enable_cache(CacheHierarchyConfig::fu540());
+    }
+    if let Some(config) = config {
+        emu.set_timing_model(Some(Box::new(PipelineModel::new(config))));
+    }
+    assert_eq!(Ok(1), emu.run());
+    emu
+}
+
+/// Cycles of the first steps of a known sequence in Qemu_virt.
+fn run_sequence(config: Option<PipelineConfig>, steps: u32) -> u64 {
+    let mut emu = qemu_virt(&[
+        0x00a00293, // li t0, 10
+        0x00300313, // li t1, 3
+        0x0262c3b3, // div t2, t0, t1
+        0x00138e13, // addi t3, t2, 1 (waits for div)
+        0x02628eb3, // mul t4, t0, t1
+        0x00128f13, // addi t5, t0, 1
+        0x001e8f93, // addi t6, t4, 1 (waits for mul)
+        0x00000463, // beqz zero, 1f (mispredicted)
+        0x00000013, // nop
+        0x0000006f, // 1: j 1b
+    ]);
+    if let Some(config) = config {
+        emu.set_timing_model(Some(Box::new(PipelineModel::new(config))));
+    }
+    emu.run_steps(steps);
+    emu.get_cycle()
+}
+
+#[test]
+fn timing_sequence() {
+    assert_eq!(9, run_sequence(None, 9));
+    // div stalls its user for 32 cycles, mul is ready after one instruction
+    // and the branch costs 3 more cycles.
+    assert_eq!(44, run_sequence(Some(PipelineConfig::e31()), 9));
+    // div stalls for 64 cycles, mul for one and the branch for 4.
+    assert_eq!(78, run_sequence(Some(PipelineConfig::u54()), 9));
+    // the loop runs one cycle per jump.
+    assert_eq!(88, run_sequence(Some(PipelineConfig::u54()), 19));
+}
+
+#[test]
+fn timing_pipeline() {
+    // without a timing model every tick is one cycle.
+    let base = run("rv64um-p-div", None, false).get_cycle();
+    let e31 = run("rv64um-p-div", Some(PipelineConfig::e31()), false).get_cycle();
+    let u54 = run("rv64um-p-div", Some(PipelineConfig::u54()), false).get_cycle();
+    assert!(base < e31);
+    assert!(e31 < u54);
+}
+
+#[test]
+fn timing_cache_miss() {
+    let hit = run("rv64ui-p-ld", Some(PipelineConfig::u54()), false).get_cycle();
+    let miss = run("rv64ui-p-ld", Some(PipelineConfig::u54()), true).get_cycle();
+    assert!(hit < miss);
+}
+
+/// Every instruction takes the same number of cycles.
+struct FixedTiming(u64);
+
+impl TimingModel for FixedTiming {
+    fn retire(&mut self, _instruction: &RetiredInstruction) -> u64 {
+        self.0
+    }
+
+    fn trap(&mut self) -> u64 {
+        0
+    }
+}
+
+#[test]
+fn timing_time_csr() {
+    // print the time between two rdtime.
+    let program = [
+        0xc01022f3, // rdtime t0
+        0xc0102373, // rdtime t1
+        0x405303b3, // sub t2, t1, t0
+        0x03038393, // addi t2, t2, '0'
+        0x10000e37, // lui t3, 0x10000 (UART)
+        0x007e0023, // sb t2, 0(t3)
+        0x0000006f, // j .
+    ];
+
+    // time advances with the cycles, and an instruction takes at least one.
+    for (cycles, time) in [(3, b'3'), (0, b'1')] {
+        let mut emu = qemu_virt(&program);
+        emu.set_timing_model(Some(Box::new(FixedTiming(cycles))));
+        emu.run_steps(100);
+        assert_eq!(time, emu.get_console().get_output());
+    }
+}
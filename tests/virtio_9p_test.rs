@@ -0,0 +1,264 @@
+extern crate riscv_emu;
+
+use riscv_emu::peripherals::virtio::mmio::VirtioMmio;
+use riscv_emu::peripherals::virtio::p9::Virtio9p;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TWALK: u8 = 110;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TREADDIR: u8 = 40;
+const TGETATTR: u8 = 24;
+const RLERROR: u8 = 7;
+
+const NOFID: u32 = 0xffff_ffff;
+const EACCES: u32 = 13;
+const EROFS: u32 = 30;
+
+/// A directory with a file and a sub directory, removed when dropped.
+struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("riscv_emu_9p_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join("sub")).unwrap();
+        fs::write(path.join("hello.txt"), b"hello 9p").unwrap();
+        TestDir { path }
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Message payload builder.
+#[derive(Default)]
+struct Payload(Vec<u8>);
+
+impl Payload {
+    fn bytes(mut self, value: &[u8]) -> Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn u16(mut self, value: u16) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u64(mut self, value: u64) -> Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn str(self, value: &str) -> Self {
+        self.u16(value.len() as u16).bytes(value.as_bytes())
+    }
+}
+
+/// Send a T-message and return the type and payload of the R-message.
+fn call(server: &mut Virtio9p, message_type: u8, payload: Payload) -> (u8, Vec<u8>) {
+    let mut request = (7 + payload.0.len() as u32).to_le_bytes().to_vec();
+    request.push(message_type);
+    request.extend_from_slice(&1u16.to_le_bytes());
+    request.extend_from_slice(&payload.0);
+    let response = server.handle_message(&request);
+    let size = u32::from_le_bytes([response[0], response[1], response[2], response[3]]);
+    assert_eq!(size as usize, response.len());
+    assert_eq!(1, u16::from_le_bytes([response[5], response[6]])); // tag
+    (response[4], response[7..].to_vec())
+}
+
+fn get_error(response: (u8, Vec<u8>)) -> u32 {
+    assert_eq!(RLERROR, response.0);
+    u32::from_le_bytes([response.1[0], response.1[1], response.1[2], response.1[3]])
+}
+
+fn attach(server: &mut Virtio9p) {
+    let version = Payload::default().u32(8192).str("9P2000.L");
+    let (rtype, payload) = call(server, TVERSION, version);
+    assert_eq!(TVERSION + 1, rtype);
+    assert_eq!(&b"9P2000.L"[..], &payload[6..]);
+    let attach = Payload::default()
+        .u32(0)
+        .u32(NOFID)
+        .str("root")
+        .str("")
+        .u32(0);
+    assert_eq!(TATTACH + 1, call(server, TATTACH, attach).0);
+}
+
+fn walk(server: &mut Virtio9p, fid: u32, newfid: u32, names: &[&str]) -> (u8, Vec<u8>) {
+    let mut payload = Payload::default()
+        .u32(fid)
+        .u32(newfid)
+        .u16(names.len() as u16);
+    for name in names {
+        payload = payload.str(name);
+    }
+    call(server, TWALK, payload)
+}
+
+/// Names of the entries of the root directory, opened as the fid.
+fn read_root(server: &mut Virtio9p, fid: u32) -> Vec<String> {
+    walk(server, 0, fid, &[]);
+    call(server, TLOPEN, Payload::default().u32(fid).u32(0));
+    let readdir = Payload::default().u32(fid).u64(0).u32(1000);
+    let (rtype, payload) = call(server, TREADDIR, readdir);
+    assert_eq!(TREADDIR + 1, rtype);
+    let mut names = vec![];
+    let mut pos = 4;
+    while pos < payload.len() {
+        let len = u16::from_le_bytes([payload[pos + 22], payload[pos + 23]]) as usize;
+        names.push(String::from_utf8(payload[pos + 24..pos + 24 + len].to_vec()).unwrap());
+        pos += 24 + len;
+    }
+    names
+}
+
+#[test]
+fn virtio_9p_read() {
+    let dir = TestDir::new("read");
+    let mut server = Virtio9p::new(&dir.path, "hostshare", true).unwrap();
+    attach(&mut server);
+
+    let (rtype, payload) = walk(&mut server, 0, 1, &["hello.txt"]);
+    assert_eq!(TWALK + 1, rtype);
+    assert_eq!(1, u16::from_le_bytes([payload[0], payload[1]])); // nwqid
+
+    let getattr = Payload::default().u32(1).u64(0x7ff);
+    let (rtype, payload) = call(&mut server, TGETATTR, getattr);
+    assert_eq!(TGETATTR + 1, rtype);
+    // valid[8] qid[13] mode[4] uid[4] gid[4] nlink[8] rdev[8] size[8]
+    assert_eq!(8u64.to_le_bytes(), payload[49..57]);
+
+    assert_eq!(
+        TLOPEN + 1,
+        call(&mut server, TLOPEN, Payload::default().u32(1).u32(0)).0
+    );
+    let read = Payload::default().u32(1).u64(6).u32(100);
+    let (rtype, payload) = call(&mut server, TREAD, read);
+    assert_eq!(TREAD + 1, rtype);
+    assert_eq!(&b"\x02\x00\x00\x009p"[..], &payload[..]);
+
+    // entries are listed with "." and "..".
+    assert_eq!(
+        vec![".", "..", "hello.txt", "sub"],
+        read_root(&mut server, 2)
+    );
+
+    // nothing can be created or modified.
+    let lcreate = Payload::default()
+        .u32(2)
+        .str("new.txt")
+        .u32(0o101)
+        .u32(0o644)
+        .u32(0);
+    assert_eq!(EROFS, get_error(call(&mut server, TLCREATE, lcreate)));
+    walk(&mut server, 0, 3, &["hello.txt"]);
+    let lopen = Payload::default().u32(3).u32(2); // O_RDWR
+    assert_eq!(EROFS, get_error(call(&mut server, TLOPEN, lopen)));
+    assert!(!dir.path.join("new.txt").exists());
+}
+
+#[test]
+fn virtio_9p_write() {
+    let dir = TestDir::new("write");
+    let mut server = Virtio9p::new(&dir.path, "hostshare", false).unwrap();
+    attach(&mut server);
+
+    walk(&mut server, 0, 1, &["sub"]);
+    let lcreate = Payload::default()
+        .u32(1)
+        .str("new.txt")
+        .u32(0o101)
+        .u32(0o644)
+        .u32(0);
+    assert_eq!(TLCREATE + 1, call(&mut server, TLCREATE, lcreate).0);
+    let write = Payload::default().u32(1).u64(0).u32(5).bytes(b"guest");
+    let (rtype, payload) = call(&mut server, TWRITE, write);
+    assert_eq!(TWRITE + 1, rtype);
+    assert_eq!(vec![5, 0, 0, 0], payload);
+    assert_eq!(
+        b"guest".to_vec(),
+        fs::read(dir.path.join("sub/new.txt")).unwrap()
+    );
+}
+
+#[test]
+fn virtio_9p_path_escape() {
+    let dir = TestDir::new("escape");
+    let mut server = Virtio9p::new(&dir.path, "hostshare", false).unwrap();
+    attach(&mut server);
+
+    // ".." of the root is the root.
+    let root_qid = walk(&mut server, 0, 1, &["sub", ".."]).1[15..28].to_vec();
+    let (rtype, payload) = walk(&mut server, 0, 2, &["..", "..", ".."]);
+    assert_eq!(TWALK + 1, rtype);
+    assert_eq!(root_qid, payload[28..41].to_vec());
+
+    // names can not contain separators.
+    assert_eq!(RLERROR, walk(&mut server, 0, 3, &["sub/../.."]).0);
+
+    // symbolic links can not be followed outside the directory.
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("/", dir.path.join("host")).unwrap();
+        assert_eq!(TWALK + 1, walk(&mut server, 0, 4, &["host"]).0);
+        // the walk stops at the link and newfid is not set up.
+        let (rtype, payload) = walk(&mut server, 0, 5, &["host", "etc"]);
+        assert_eq!(TWALK + 1, rtype);
+        assert_eq!(1, u16::from_le_bytes([payload[0], payload[1]]));
+        let getattr = Payload::default().u32(5).u64(0x7ff);
+        assert_eq!(RLERROR, call(&mut server, TGETATTR, getattr).0);
+        let lopen = Payload::default().u32(4).u32(0);
+        assert_eq!(EACCES, get_error(call(&mut server, TLOPEN, lopen)));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn virtio_9p_readdir_unmappable() {
+    let dir = TestDir::new("unmappable");
+    fs::write(dir.path.join("back\\slash"), b"").unwrap();
+    let mut server = Virtio9p::new(&dir.path, "hostshare", true).unwrap();
+    attach(&mut server);
+
+    // the name can not be walked to, so it is not listed.
+    assert_eq!(
+        vec![".", "..", "hello.txt", "sub"],
+        read_root(&mut server, 1)
+    );
+}
+
+#[test]
+fn virtio_9p_mount_tag() {
+    let dir = TestDir::new("tag");
+    let server = Virtio9p::new(&dir.path, "share", true).unwrap();
+    let mut virtio = VirtioMmio::new(0x8000_0000);
+    virtio.set_device(Box::new(server));
+    assert_eq!(9, virtio.read(0x008)); // 9P transport
+    assert_eq!(1, virtio.read(0x010) & 1); // VIRTIO_9P_MOUNT_TAG
+    assert_eq!(u32::from_le_bytes([5, 0, b's', b'h']), virtio.read(0x100));
+    assert_eq!(
+        u32::from_le_bytes([b'a', b'r', b'e', 0]),
+        virtio.read(0x104)
+    );
+}
@@ -0,0 +1,259 @@
+extern crate riscv_emu;
+
+use riscv_emu::block::file::FileDisk;
+use riscv_emu::block::memory::MemoryDisk;
+use riscv_emu::block::overlay::CowOverlay;
+use riscv_emu::block::BlockBackend;
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::virtio::block::VirtioBlock;
+use riscv_emu::peripherals::virtio::mmio::VirtioMmio;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+const DRAM_BASE: u64 = 0x8000_0000;
+const PAGE_SIZE: u64 = 0x1000;
+const QUEUE_NUM: u64 = 8;
+
+// Request buffers (offsets in the memory)
+const HEADER: u64 = 0x10000;
+const DATA: u64 = 0x11000;
+const STATUS: u64 = 0x12000;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+
+const OK: u8 = 0;
+const IOERR: u8 = 1;
+const UNSUPP: u8 = 2;
+
+const VIRTIO_BLK_F_RO: u32 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u32 = 1 << 9;
+const VIRTIO_BLK_F_DISCARD: u32 = 1 << 13;
+
+/// Disk image in the temporary directory, removed when dropped.
+struct TestImage {
+    path: PathBuf,
+}
+
+impl TestImage {
+    fn new(name: &str, data: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("riscv_emu_blk_{}_{}", name, process::id()));
+        fs::write(&path, data).unwrap();
+        TestImage { path }
+    }
+}
+
+impl Drop for TestImage {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn create_virtio(disk: Box<dyn BlockBackend>) -> VirtioMmio {
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(VirtioBlock::new(disk)));
+    virtio.write(0x028, PAGE_SIZE as u32); // GuestPageSize
+    virtio.write(0x030, 0); // QueueSel
+    virtio.write(0x038, QUEUE_NUM as u32); // QueueNum
+    virtio.write(0x040, ((DRAM_BASE + PAGE_SIZE) / PAGE_SIZE) as u32); // QueuePFN
+    virtio
+}
+
+fn write_descriptor(dram: &mut Memory, index: u64, addr: u64, len: u32, flags: u16) {
+    let desc = PAGE_SIZE + 16 * index;
+    dram.write64(desc, DRAM_BASE + addr);
+    dram.write32(desc + 8, len);
+    dram.write16(desc + 12, flags);
+    dram.write16(desc + 14, index as u16 + 1);
+}
+
+/// Send a request of the header, the data buffer and the status.
+/// Returns the status and the data buffer.
+fn request(
+    virtio: &mut VirtioMmio,
+    dram: &mut Memory,
+    request_type: u32,
+    sector: u64,
+    data: &[u8],
+    device_writable: bool,
+) -> (u8, Vec<u8>) {
+    dram.write32(HEADER, request_type);
+    dram.write32(HEADER + 4, 0);
+    dram.write64(HEADER + 8, sector);
+    dram.mem[DATA as usize..DATA as usize + data.len()].copy_from_slice(data);
+    dram.mem[STATUS as usize] = 0xff;
+
+    write_descriptor(dram, 0, HEADER, 16, 0x1);
+    let flags = if device_writable { 0x1 | 0x2 } else { 0x1 };
+    write_descriptor(dram, 1, DATA, data.len() as u32, flags);
+    write_descriptor(dram, 2, STATUS, 1, 0x2);
+
+    let avail = PAGE_SIZE + 16 * QUEUE_NUM;
+    let idx = dram.read16(avail + 2);
+    dram.write16(avail + 4 + 2 * (idx as u64 % QUEUE_NUM), 0);
+    dram.write16(avail + 2, idx.wrapping_add(1));
+    virtio.write(0x050, 0); // QueueNotify
+    for _ in 0..0x1000 {
+        virtio.tick(dram);
+    }
+
+    let status = dram.mem[STATUS as usize];
+    let data = dram.mem[DATA as usize..DATA as usize + data.len()].to_vec();
+    (status, data)
+}
+
+fn read_capacity(virtio: &mut VirtioMmio) -> u64 {
+    virtio.read(0x100) as u64 | (virtio.read(0x104) as u64) << 32
+}
+
+#[test]
+fn virtio_block_file_backed() {
+    let image = TestImage::new("file", &vec![0x5a; 0x3000]);
+    let disk = FileDisk::open(&image.path, false).unwrap();
+    let mut dram = Memory::new(0x20000);
+    let mut virtio = create_virtio(Box::new(disk));
+    assert_eq!(2, virtio.read(0x008)); // block device
+    assert_eq!(0x3000 / 512, read_capacity(&mut virtio));
+    let features = virtio.read(0x010);
+    assert_ne!(0, features & VIRTIO_BLK_F_FLUSH);
+    assert_ne!(0, features & VIRTIO_BLK_F_DISCARD);
+    assert_eq!(0, features & VIRTIO_BLK_F_RO);
+
+    let written = vec![0xa5; 512];
+    let (status, _) = request(&mut virtio, &mut dram, VIRTIO_BLK_T_OUT, 2, &written, false);
+    assert_eq!(OK, status);
+    let (status, _) = request(&mut virtio, &mut dram, VIRTIO_BLK_T_FLUSH, 0, &[], false);
+    assert_eq!(OK, status);
+    // the write reached the host file.
+    let host = fs::read(&image.path).unwrap();
+    assert_eq!(&written[..], &host[0x400..0x600]);
+    assert_eq!(0x5a, host[0x3ff]);
+
+    let (status, data) = request(&mut virtio, &mut dram, VIRTIO_BLK_T_IN, 1, &[0; 1024], true);
+    assert_eq!(OK, status);
+    assert_eq!(vec![0x5a; 512], data[..512].to_vec());
+    assert_eq!(written, data[512..].to_vec());
+
+    // the discarded sectors 3 and 4 read back as zeros from the host file.
+    let mut segment = 3u64.to_le_bytes().to_vec();
+    segment.extend_from_slice(&2u32.to_le_bytes());
+    segment.extend_from_slice(&0u32.to_le_bytes());
+    let (status, _) = request(
+        &mut virtio,
+        &mut dram,
+        VIRTIO_BLK_T_DISCARD,
+        0,
+        &segment,
+        false,
+    );
+    assert_eq!(OK, status);
+    let host = fs::read(&image.path).unwrap();
+    assert_eq!(written, host[0x400..0x600].to_vec());
+    assert_eq!(vec![0; 0x400], host[0x600..0xa00].to_vec());
+    assert_eq!(0x5a, host[0xa00]);
+
+    // beyond the end of the disk.
+    let (status, _) = request(&mut virtio, &mut dram, VIRTIO_BLK_T_IN, 24, &[0; 512], true);
+    assert_eq!(IOERR, status);
+}
+
+#[test]
+fn virtio_block_read_only() {
+    let image = TestImage::new("ro", &vec![0x5a; 0x1000]);
+    let disk = FileDisk::open(&image.path, true).unwrap();
+    let mut dram = Memory::new(0x20000);
+    let mut virtio = create_virtio(Box::new(disk));
+    assert_ne!(0, virtio.read(0x010) & VIRTIO_BLK_F_RO);
+
+    let (status, _) = request(
+        &mut virtio,
+        &mut dram,
+        VIRTIO_BLK_T_OUT,
+        0,
+        &[0; 512],
+        false,
+    );
+    assert_eq!(IOERR, status);
+    assert_eq!(vec![0x5a; 0x1000], fs::read(&image.path).unwrap());
+}
+
+#[test]
+fn virtio_block_copy_on_write() {
+    let image = TestImage::new("cow", &vec![0x5a; 0x3000]);
+    let base = FileDisk::open(&image.path, true).unwrap();
+    let mut overlay = CowOverlay::new(Box::new(base));
+    // the overlay is writable although the base is not.
+    assert!(!overlay.is_read_only());
+
+    overlay.write_at(0xff0, &[1; 0x20]).unwrap();
+    assert_eq!(0x2000, overlay.get_overlay_size());
+    let mut data = vec![0; 0x40];
+    overlay.read_at(0xfe0, &mut data).unwrap();
+    assert_eq!(vec![0x5a; 0x10], data[..0x10].to_vec());
+    assert_eq!(vec![1; 0x20], data[0x10..0x30].to_vec());
+    assert_eq!(vec![0x5a; 0x10], data[0x30..].to_vec());
+    assert_eq!(vec![0x5a; 0x3000], fs::read(&image.path).unwrap());
+
+    overlay.revert();
+    overlay.read_at(0xfe0, &mut data).unwrap();
+    assert_eq!(vec![0x5a; 0x40], data);
+}
+
+#[test]
+fn virtio_block_get_id_and_discard() {
+    let disk = MemoryDisk::new(vec![0x5a; 0x2000]);
+    let mut dram = Memory::new(0x20000);
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    let mut block = VirtioBlock::new(Box::new(disk));
+    block.set_id("disk0");
+    virtio.set_device(Box::new(block));
+    virtio.write(0x028, PAGE_SIZE as u32);
+    virtio.write(0x038, QUEUE_NUM as u32);
+    virtio.write(0x040, ((DRAM_BASE + PAGE_SIZE) / PAGE_SIZE) as u32);
+
+    let (status, id) = request(
+        &mut virtio,
+        &mut dram,
+        VIRTIO_BLK_T_GET_ID,
+        0,
+        &[0xff; 20],
+        true,
+    );
+    assert_eq!(OK, status);
+    let mut expected = b"disk0".to_vec();
+    expected.resize(20, 0);
+    assert_eq!(expected, id);
+
+    // sector 2, 4 sectors
+    let mut segment = 2u64.to_le_bytes().to_vec();
+    segment.extend_from_slice(&4u32.to_le_bytes());
+    segment.extend_from_slice(&0u32.to_le_bytes());
+    let (status, _) = request(
+        &mut virtio,
+        &mut dram,
+        VIRTIO_BLK_T_DISCARD,
+        0,
+        &segment,
+        false,
+    );
+    assert_eq!(OK, status);
+    let (_, data) = request(
+        &mut virtio,
+        &mut dram,
+        VIRTIO_BLK_T_IN,
+        1,
+        &[0xff; 0xc00],
+        true,
+    );
+    assert_eq!(vec![0x5a; 0x200], data[..0x200].to_vec());
+    assert_eq!(vec![0; 0x800], data[0x200..0xa00].to_vec());
+    assert_eq!(vec![0x5a; 0x200], data[0xa00..].to_vec());
+
+    let (status, _) = request(&mut virtio, &mut dram, 0x1234, 0, &[], false);
+    assert_eq!(UNSUPP, status);
+}
@@ -0,0 +1,94 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{add_buffer, get_used, setup_queue, DRAM_BASE, PAGE_SIZE};
+
+use riscv_emu::console::{Console, SharedConsole, TtyBuffer};
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::virtio::console::VirtioConsole;
+use riscv_emu::peripherals::virtio::mmio::VirtioMmio;
+
+const VIRTIO_CONSOLE_F_MULTIPORT: u32 = 1 << 1;
+
+const PORT1_RX_QUEUE: u64 = 4;
+const PORT1_TX_QUEUE: u64 = 5;
+const CONTROL_RX_QUEUE: u64 = 2;
+const CONTROL_TX_QUEUE: u64 = 3;
+
+fn control(id: u32, event: u16, value: u16) -> Vec<u8> {
+    let mut message = id.to_le_bytes().to_vec();
+    message.extend_from_slice(&event.to_le_bytes());
+    message.extend_from_slice(&value.to_le_bytes());
+    message
+}
+
+fn run(virtio: &mut VirtioMmio, dram: &mut Memory) {
+    for _ in 0..0x1000 {
+        virtio.tick(dram);
+    }
+}
+
+#[test]
+fn virtio_console_multiport() {
+    let mut dram = Memory::new(0x20000);
+    let mut console = SharedConsole::new(Box::new(TtyBuffer::new()));
+    let mut harness = SharedConsole::new(Box::new(TtyBuffer::new()));
+    let mut device = VirtioConsole::new(Box::new(console.clone()));
+    device.add_port("harness", Box::new(harness.clone()));
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(device));
+
+    assert_eq!(3, virtio.read(0x008)); // console
+    assert_ne!(0, virtio.read(0x010) & VIRTIO_CONSOLE_F_MULTIPORT);
+    assert_eq!(2, virtio.read(0x104)); // max_nr_ports
+    virtio.write(0x020, VIRTIO_CONSOLE_F_MULTIPORT); // DriverFeatures
+    virtio.write(0x028, PAGE_SIZE as u32); // GuestPageSize
+    for index in 0..6 {
+        setup_queue(&mut virtio, index);
+    }
+
+    // the device announces its ports once the driver is ready.
+    for _ in 0..4 {
+        add_buffer(&mut dram, CONTROL_RX_QUEUE, &[], true);
+    }
+    add_buffer(&mut dram, CONTROL_TX_QUEUE, &control(0, 0, 1), false); // DEVICE_READY
+    virtio.write(0x050, CONTROL_TX_QUEUE as u32);
+    run(&mut virtio, &mut dram);
+    assert_eq!(
+        vec![control(0, 1, 0), control(1, 1, 0)], // DEVICE_ADD
+        get_used(&dram, CONTROL_RX_QUEUE)
+    );
+
+    add_buffer(&mut dram, CONTROL_TX_QUEUE, &control(1, 3, 1), false); // PORT_READY
+    virtio.write(0x050, CONTROL_TX_QUEUE as u32);
+    run(&mut virtio, &mut dram);
+    let mut port_name = control(1, 7, 1);
+    port_name.extend_from_slice(b"harness");
+    assert_eq!(
+        vec![port_name, control(1, 6, 1)], // PORT_NAME, PORT_OPEN
+        get_used(&dram, CONTROL_RX_QUEUE)[2..].to_vec()
+    );
+
+    // output of the named port does not go to the console.
+    add_buffer(&mut dram, PORT1_TX_QUEUE, b"hello", false);
+    virtio.write(0x050, PORT1_TX_QUEUE as u32);
+    run(&mut virtio, &mut dram);
+    let output: Vec<u8> = (0..5).map(|_| harness.get_output()).collect();
+    assert_eq!(b"hello", &output[..]);
+    assert_eq!(0, console.get_output());
+
+    // input is delivered in bulk when the driver provides a buffer.
+    for c in b"ok\n" {
+        harness.set_input(*c);
+    }
+    add_buffer(&mut dram, PORT1_RX_QUEUE, &[], true);
+    virtio.write(0x050, PORT1_RX_QUEUE as u32);
+    run(&mut virtio, &mut dram);
+    assert_eq!(vec![b"ok\n".to_vec()], get_used(&dram, PORT1_RX_QUEUE));
+    assert!(virtio.is_irq());
+
+    // emergency write goes to the console port.
+    virtio.write8(0x108, b'!');
+    assert_eq!(b'!', console.get_output());
+}
@@ -0,0 +1,182 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{
+    add_buffer, get_queue_offset, get_used, setup_queue, DRAM_BASE, PAGE_SIZE, QUEUE_NUM,
+};
+
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::virtio::balloon::{BalloonStats, VirtioBalloon};
+use riscv_emu::peripherals::virtio::input::{
+    VirtioInput, EV_KEY, EV_SYN, KEY_ENTER, KEY_LEFTSHIFT,
+};
+use riscv_emu::peripherals::virtio::mmio::VirtioMmio;
+use riscv_emu::peripherals::virtio::rng::{EntropySource, SeededEntropy, VirtioRng};
+
+fn setup(virtio: &mut VirtioMmio, queues: u64) {
+    virtio.write(0x028, PAGE_SIZE as u32); // GuestPageSize
+    for index in 0..queues {
+        setup_queue(virtio, index);
+    }
+}
+
+fn notify(virtio: &mut VirtioMmio, dram: &mut Memory, index: u64) {
+    virtio.write(0x050, index as u32); // QueueNotify
+    for _ in 0..0x1000 {
+        virtio.tick(dram);
+    }
+}
+
+#[test]
+fn virtio_rng_seeded() {
+    let mut expected = [0; 64];
+    SeededEntropy::new(42).fill(&mut expected);
+    let mut other = [0; 64];
+    SeededEntropy::new(43).fill(&mut other);
+    assert_ne!(expected, other);
+
+    let mut dram = Memory::new(0x20000);
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(VirtioRng::new(Box::new(SeededEntropy::new(42)))));
+    assert_eq!(4, virtio.read(0x008)); // entropy
+    setup(&mut virtio, 1);
+
+    add_buffer(&mut dram, 0, &[], true);
+    notify(&mut virtio, &mut dram, 0);
+    assert_eq!(vec![expected.to_vec()], get_used(&dram, 0));
+    assert!(virtio.is_irq());
+}
+
+#[test]
+fn virtio_balloon() {
+    let mut dram = Memory::new(0x20000);
+    let device = VirtioBalloon::new();
+    let balloon = device.get_handle();
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(device));
+    assert_eq!(5, virtio.read(0x008)); // balloon
+    setup(&mut virtio, 3);
+
+    // a new target is announced with a configuration change interrupt.
+    balloon.set_target(3);
+    notify(&mut virtio, &mut dram, 0);
+    assert_eq!(0x2, virtio.read(0x060) & 0x2); // InterruptStatus
+    virtio.write(0x064, 0x2); // InterruptACK
+    assert_eq!(3, virtio.read(0x100)); // num_pages
+
+    let pfns: Vec<u8> = [0x80010, 0x80011, 0x80012]
+        .iter()
+        .flat_map(|pfn: &u32| pfn.to_le_bytes().to_vec())
+        .collect();
+    add_buffer(&mut dram, 0, &pfns, false);
+    notify(&mut virtio, &mut dram, 0);
+    virtio.write(0x104, 3); // actual
+    assert_eq!(vec![0x80010, 0x80011, 0x80012], balloon.get_pages());
+    assert_eq!(3, balloon.get_actual());
+
+    add_buffer(&mut dram, 1, &0x80011u32.to_le_bytes(), false);
+    notify(&mut virtio, &mut dram, 1);
+    assert_eq!(vec![0x80010, 0x80012], balloon.get_pages());
+
+    // the driver reports the statistics and the buffer is kept by the device.
+    let mut stats = 4u16.to_le_bytes().to_vec(); // MEMFREE
+    stats.extend_from_slice(&0x100_0000u64.to_le_bytes());
+    stats.extend_from_slice(&5u16.to_le_bytes()); // MEMTOT
+    stats.extend_from_slice(&0x800_0000u64.to_le_bytes());
+    add_buffer(&mut dram, 2, &stats, false);
+    notify(&mut virtio, &mut dram, 2);
+    assert_eq!(0, get_used(&dram, 2).len());
+    assert_eq!(
+        Some(BalloonStats {
+            free_memory: Some(0x100_0000),
+            total_memory: Some(0x800_0000),
+            ..Default::default()
+        }),
+        balloon.get_stats()
+    );
+
+    // the buffer is returned when new statistics are wanted.
+    balloon.request_stats();
+    notify(&mut virtio, &mut dram, 0);
+    assert_eq!(1, get_used(&dram, 2).len());
+}
+
+#[test]
+fn virtio_keyboard() {
+    let mut dram = Memory::new(0x20000);
+    let device = VirtioInput::new_keyboard(None);
+    let keyboard = device.get_handle();
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(device));
+    assert_eq!(18, virtio.read(0x008)); // input
+    setup(&mut virtio, 2);
+
+    virtio.write8(0x100, 0x01); // select ID_NAME
+    let size = (virtio.read(0x100) >> 16) & 0xff;
+    let name: Vec<u8> = (0..size as u64)
+        .map(|i| (virtio.read(0x108 + i / 4 * 4) >> ((i % 4) * 8)) as u8)
+        .collect();
+    assert_eq!(b"riscv-emu virtio keyboard", &name[..]);
+
+    keyboard.type_text("A\n");
+    for _ in 0..QUEUE_NUM {
+        add_buffer(&mut dram, 0, &[], true);
+    }
+    notify(&mut virtio, &mut dram, 0);
+
+    let event = |event_type: u16, code: u16, value: u32| {
+        let mut data = event_type.to_le_bytes().to_vec();
+        data.extend_from_slice(&code.to_le_bytes());
+        data.extend_from_slice(&value.to_le_bytes());
+        data
+    };
+    let syn = event(EV_SYN, 0, 0);
+    assert_eq!(
+        vec![
+            event(EV_KEY, KEY_LEFTSHIFT, 1),
+            syn.clone(),
+            event(EV_KEY, 30, 1), // KEY_A
+            syn.clone(),
+            event(EV_KEY, 30, 0),
+            syn.clone(),
+            event(EV_KEY, KEY_LEFTSHIFT, 0),
+            syn.clone(),
+        ],
+        get_used(&dram, 0)
+    );
+
+    // the rest is delivered when the driver returns the buffers.
+    for _ in 0..QUEUE_NUM {
+        add_buffer(&mut dram, 0, &[], true);
+    }
+    notify(&mut virtio, &mut dram, 0);
+    assert_eq!(
+        vec![
+            event(EV_KEY, KEY_ENTER, 1),
+            syn.clone(),
+            event(EV_KEY, KEY_ENTER, 0),
+            syn,
+        ],
+        get_used(&dram, 0)[8..].to_vec()
+    );
+}
+
+#[test]
+fn virtio_descriptor_out_of_memory() {
+    let mut dram = Memory::new(0x20000);
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(VirtioRng::new(Box::new(SeededEntropy::new(42)))));
+    setup(&mut virtio, 1);
+
+    // the buffer ends past the main memory: the chain fails without data.
+    add_buffer(&mut dram, 0, &[], true);
+    dram.write64(get_queue_offset(0), DRAM_BASE + 0x20000 - 0x20);
+    notify(&mut virtio, &mut dram, 0);
+    assert_eq!(vec![Vec::<u8>::new()], get_used(&dram, 0));
+
+    // the following chain is served.
+    add_buffer(&mut dram, 0, &[], true);
+    notify(&mut virtio, &mut dram, 0);
+    assert_eq!(64, get_used(&dram, 0)[1].len());
+}
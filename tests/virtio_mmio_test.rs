@@ -0,0 +1,147 @@
+extern crate riscv_emu;
+
+use riscv_emu::peripherals::memory::Memory;
+use riscv_emu::peripherals::virtio::mmio::{VirtioMmio, VIRTIO_MMIO_MODERN};
+use riscv_emu::peripherals::virtio::rng::{EntropySource, SeededEntropy, VirtioRng};
+
+const DRAM_BASE: u64 = 0x8000_0000;
+const QUEUE_NUM: u64 = 8;
+
+// Ring addresses (offsets in the memory)
+const DESC: u64 = 0x1000;
+const DRIVER: u64 = 0x2000;
+const DEVICE: u64 = 0x3000;
+const INDIRECT_TABLE: u64 = 0x4000;
+const BUFFER: u64 = 0x5000;
+
+const VIRTIO_F_INDIRECT_DESC: u32 = 1 << 28;
+const VIRTIO_F_EVENT_IDX: u32 = 1 << 29;
+const VIRTIO_F_VERSION_1: u32 = 1; // bit 32
+
+const STATUS_FEATURES_OK: u32 = 0x8;
+
+fn write_descriptor(dram: &mut Memory, table: u64, index: u64, addr: u64, len: u32, flags: u16) {
+    let entry = table + 16 * index;
+    dram.write64(entry, addr);
+    dram.write32(entry + 8, len);
+    dram.write16(entry + 12, flags);
+    dram.write16(entry + 14, index as u16 + 1);
+}
+
+/// Make the chain of the head descriptor available.
+fn make_available(dram: &mut Memory, head: u16) {
+    let idx = dram.read16(DRIVER + 2);
+    dram.write16(DRIVER + 4 + 2 * (idx as u64 % QUEUE_NUM), head);
+    dram.write16(DRIVER + 2, idx.wrapping_add(1));
+}
+
+fn notify(virtio: &mut VirtioMmio, dram: &mut Memory) {
+    virtio.write(0x050, 0); // QueueNotify
+    for _ in 0..0x1000 {
+        virtio.tick(dram);
+    }
+}
+
+fn setup_modern(virtio: &mut VirtioMmio, features: u32, features_high: u32) {
+    virtio.write(0x070, 0x3); // ACKNOWLEDGE | DRIVER
+    virtio.write(0x024, 0); // DriverFeaturesSel
+    virtio.write(0x020, features);
+    virtio.write(0x024, 1);
+    virtio.write(0x020, features_high);
+    virtio.write(0x070, 0x3 | STATUS_FEATURES_OK);
+}
+
+#[test]
+fn virtio_mmio_modern() {
+    let mut dram = Memory::new(0x10000);
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(VirtioRng::new(Box::new(SeededEntropy::new(1)))));
+    virtio.set_version(VIRTIO_MMIO_MODERN);
+    assert_eq!(2, virtio.read(0x004)); // Version
+
+    virtio.write(0x014, 0); // DeviceFeaturesSel
+    let features = virtio.read(0x010);
+    assert_ne!(0, features & VIRTIO_F_INDIRECT_DESC);
+    assert_ne!(0, features & VIRTIO_F_EVENT_IDX);
+    virtio.write(0x014, 1);
+    assert_eq!(VIRTIO_F_VERSION_1, virtio.read(0x010));
+
+    // the features are rejected without VERSION_1.
+    setup_modern(&mut virtio, VIRTIO_F_INDIRECT_DESC, 0);
+    assert_eq!(0, virtio.read(0x070) & STATUS_FEATURES_OK);
+    virtio.write(0x070, 0); // reset
+    let features = VIRTIO_F_INDIRECT_DESC | VIRTIO_F_EVENT_IDX;
+    setup_modern(&mut virtio, features, VIRTIO_F_VERSION_1);
+    assert_ne!(0, virtio.read(0x070) & STATUS_FEATURES_OK);
+
+    // the rings are placed anywhere with 64-bit addresses.
+    virtio.write(0x030, 0); // QueueSel
+    virtio.write(0x038, QUEUE_NUM as u32); // QueueNum
+    virtio.write(0x080, (DRAM_BASE + DESC) as u32); // QueueDescLow
+    virtio.write(0x084, 0); // QueueDescHigh
+    virtio.write(0x090, (DRAM_BASE + DRIVER) as u32); // QueueDriverLow
+    virtio.write(0x094, 0);
+    virtio.write(0x0a0, (DRAM_BASE + DEVICE) as u32); // QueueDeviceLow
+    virtio.write(0x0a4, 0);
+    assert_eq!(0, virtio.read(0x044)); // QueueReady
+    virtio.write(0x044, 1);
+    assert_eq!(1, virtio.read(0x044));
+
+    // an indirect table of two writable buffers.
+    write_descriptor(&mut dram, DESC, 0, DRAM_BASE + INDIRECT_TABLE, 32, 0x4);
+    write_descriptor(
+        &mut dram,
+        INDIRECT_TABLE,
+        0,
+        DRAM_BASE + BUFFER,
+        16,
+        0x2 | 0x1,
+    );
+    write_descriptor(
+        &mut dram,
+        INDIRECT_TABLE,
+        1,
+        DRAM_BASE + BUFFER + 0x100,
+        16,
+        0x2,
+    );
+    make_available(&mut dram, 0);
+    notify(&mut virtio, &mut dram);
+
+    let mut expected = [0; 32];
+    SeededEntropy::new(1).fill(&mut expected);
+    assert_eq!(1, dram.read16(DEVICE + 2)); // used idx
+    assert_eq!(32, dram.read32(DEVICE + 4 + 4)); // used len
+    let buffer = BUFFER as usize;
+    assert_eq!(&expected[..16], &dram.mem[buffer..buffer + 16]);
+    assert_eq!(&expected[16..], &dram.mem[buffer + 0x100..buffer + 0x110]);
+    // avail_event: the driver notifies for the next buffer.
+    assert_eq!(1, dram.read16(DEVICE + 4 + 8 * QUEUE_NUM));
+    // used_event is 0, so the driver wanted an interrupt.
+    assert!(virtio.is_irq());
+    virtio.write(0x064, 0x1); // InterruptACK
+
+    // no interrupt until the used index passes used_event.
+    dram.write16(DRIVER + 4 + 2 * QUEUE_NUM, 2);
+    write_descriptor(&mut dram, DESC, 1, DRAM_BASE + BUFFER + 0x200, 16, 0x2);
+    make_available(&mut dram, 1);
+    notify(&mut virtio, &mut dram);
+    assert_eq!(2, dram.read16(DEVICE + 2));
+    assert!(!virtio.is_irq());
+    make_available(&mut dram, 1);
+    notify(&mut virtio, &mut dram);
+    assert_eq!(3, dram.read16(DEVICE + 2));
+    assert!(virtio.is_irq());
+}
+
+#[test]
+fn virtio_mmio_legacy() {
+    let mut virtio = VirtioMmio::new(DRAM_BASE);
+    virtio.set_device(Box::new(VirtioRng::new(Box::new(SeededEntropy::new(1)))));
+    assert_eq!(1, virtio.read(0x004)); // Version
+    virtio.write(0x014, 1); // DeviceFeaturesSel
+    assert_eq!(0, virtio.read(0x010) & VIRTIO_F_VERSION_1);
+    // modern registers are not available.
+    virtio.write(0x044, 1); // QueueReady
+    assert_eq!(0, virtio.read(0x044));
+}